@@ -21,4 +21,15 @@ pub enum Error {
     InvalidExit(ParseIntError),
     #[error("not an asciicast v3 file")]
     NotAsciicastV3,
+    #[error("invalid redact pattern: {0}")]
+    InvalidPattern(#[from] regex::Error),
+}
+
+impl Error {
+    pub fn code(&self) -> crate::error::ErrorCode {
+        match self {
+            Error::Io(_) => crate::error::ErrorCode::Internal,
+            _ => crate::error::ErrorCode::InvalidInput,
+        }
+    }
 }
@@ -1,24 +1,25 @@
 use async_trait::async_trait;
-use tokio::io::{self, AsyncWrite, AsyncWriteExt};
+use tokio::io;
 
 use crate::asciinema::asciicast;
 use crate::asciinema::encoder::Encoder;
+use crate::asciinema::record_sink::RecordSink;
 use crate::asciinema::session::{self, Metadata};
 
 pub struct FileWriter {
-    writer: Box<dyn AsyncWrite + Send + Unpin>,
+    writer: Box<dyn RecordSink>,
     encoder: Box<dyn Encoder + Send>,
     metadata: Metadata,
 }
 
 pub struct LiveFileWriter {
-    writer: Box<dyn AsyncWrite + Send + Unpin>,
+    writer: Box<dyn RecordSink>,
     encoder: Box<dyn Encoder + Send>,
 }
 
 impl FileWriter {
     pub fn new(
-        writer: Box<dyn AsyncWrite + Send + Unpin>,
+        writer: Box<dyn RecordSink>,
         encoder: Box<dyn Encoder + Send>,
         metadata: Metadata,
     ) -> Self {
@@ -44,7 +45,7 @@ impl FileWriter {
             env: Some(self.metadata.env.clone()),
         };
 
-        self.writer.write_all(&self.encoder.header(&header)).await?;
+        self.writer.write(&self.encoder.header(&header)).await?;
 
         Ok(LiveFileWriter {
             writer: self.writer,
@@ -56,11 +57,7 @@ impl FileWriter {
 #[async_trait]
 impl session::Output for LiveFileWriter {
     async fn event(&mut self, event: session::Event) -> io::Result<()> {
-        match self
-            .writer
-            .write_all(&self.encoder.event(event.into()))
-            .await
-        {
+        match self.writer.write(&self.encoder.event(event.into())).await {
             Ok(_) => Ok(()),
 
             Err(e) => Err(e),
@@ -68,7 +65,8 @@ impl session::Output for LiveFileWriter {
     }
 
     async fn flush(&mut self) -> io::Result<()> {
-        self.writer.write_all(&self.encoder.flush()).await
+        self.writer.write(&self.encoder.flush()).await?;
+        self.writer.finish().await
     }
 }
 
@@ -3,6 +3,9 @@ mod encoder;
 mod error;
 mod file_writer;
 pub mod player;
+pub mod redact;
+mod record_sink;
+mod s3;
 mod session;
 mod tty;
 mod util;
@@ -10,30 +13,27 @@ mod util;
 use encoder::AsciicastV3Encoder;
 pub use error::Error;
 use file_writer::FileWriter;
-pub use session::Session;
+pub use record_sink::{FileSink, RecordSink};
+pub use s3::{S3Sink, S3Store};
+pub use session::{Session, Stats};
 use session::{Metadata, TermInfo};
 use std::collections::HashMap;
-use std::path::Path;
 pub use tty::TtySize;
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 pub async fn new_recorder(
     term_type: Option<String>,
-    file_path: impl AsRef<Path>,
+    sink: Box<dyn RecordSink>,
     size: (u16, u16),
     title: Option<String>,
+    env: HashMap<String, String>,
     record_input: bool,
 ) -> Result<Session> {
     let term = get_term_info(term_type, size).await?;
-    let metadata = get_session_metadata(title, term).await?;
-    let file_writer = get_file_writer(file_path, &metadata).await?;
-
-    let mut outputs: Vec<Box<dyn session::Output>> = Vec::new();
-
-    if let Some(writer) = file_writer {
-        let output = writer.start().await?;
-        outputs.push(Box::new(output));
-    }
+    let metadata = get_session_metadata(title, env, term).await?;
+    let encoder = Box::new(AsciicastV3Encoder::new(false));
+    let output = FileWriter::new(sink, encoder, metadata).start().await?;
+    let outputs: Vec<Box<dyn session::Output>> = vec![Box::new(output)];
     let mut tty = Box::new(tty::FixedSizeTty::new(
         tty::NullTty,
         Some(size.0),
@@ -43,14 +43,18 @@ pub async fn new_recorder(
     session::new(tty.as_mut(), record_input, outputs).await
 }
 
-async fn get_session_metadata(title: Option<String>, term: TermInfo) -> Result<Metadata> {
+async fn get_session_metadata(
+    title: Option<String>,
+    env: HashMap<String, String>,
+    term: TermInfo,
+) -> Result<Metadata> {
     Ok(Metadata {
         time: chrono::Utc::now(),
         term,
         idle_time_limit: None,
         command: None,
         title,
-        env: HashMap::new(),
+        env,
     })
 }
 
@@ -61,24 +65,3 @@ async fn get_term_info(term_type: Option<String>, size: (u16, u16)) -> Result<Te
         size: size.into(),
     })
 }
-
-async fn get_file_writer(
-    path: impl AsRef<Path>,
-    metadata: &Metadata,
-) -> Result<Option<FileWriter>> {
-    if let Some(dir) = path.as_ref().parent() {
-        std::fs::create_dir_all(dir)?;
-    }
-
-    let file = tokio::fs::File::options()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(path)
-        .await?;
-
-    let writer = Box::new(file);
-    let encoder = Box::new(AsciicastV3Encoder::new(false));
-
-    Ok(Some(FileWriter::new(writer, encoder, metadata.clone())))
-}
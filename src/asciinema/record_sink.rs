@@ -0,0 +1,45 @@
+//! Where an encoded recording goes: `FileSink` writes it below
+//! `record_path`, `S3Sink` uploads it to a bucket. `record_sink.type`
+//! picks one for every new recording.
+
+use async_trait::async_trait;
+use std::path::Path;
+use tokio::io::{self, AsyncWriteExt};
+
+#[async_trait]
+pub trait RecordSink: Send {
+    async fn write(&mut self, data: &[u8]) -> io::Result<()>;
+    /// End of the recording, nothing is written after it
+    async fn finish(&mut self) -> io::Result<()>;
+}
+
+pub struct FileSink {
+    file: tokio::fs::File,
+}
+
+impl FileSink {
+    /// Truncates `path`, creating it and its directory when missing
+    pub async fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        if let Some(dir) = path.as_ref().parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = tokio::fs::File::options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .await?;
+        Ok(FileSink { file })
+    }
+}
+
+#[async_trait]
+impl RecordSink for FileSink {
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data).await
+    }
+
+    async fn finish(&mut self) -> io::Result<()> {
+        self.file.flush().await
+    }
+}
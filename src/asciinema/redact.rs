@@ -0,0 +1,172 @@
+use super::Result;
+use super::asciicast::{self, Event, EventData, V3Encoder};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::io::{BufRead, Write};
+
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Longest tail of output kept to look for a password prompt
+const PROMPT_TAIL_LEN: usize = 512;
+
+lazy_static! {
+    static ref PASSWORD_PROMPT: Regex =
+        Regex::new(r"(?i)\b(password|passphrase|passcode|pin)\b[^:\n]*:\s*$").unwrap();
+    static ref CSI: Regex = Regex::new(r"\x1b\[[0-9;?]*[A-Za-z]").unwrap();
+}
+
+#[derive(Debug, Default)]
+pub struct RedactStats {
+    pub events: usize,
+    /// Matches of the configured patterns
+    pub matches: usize,
+    /// Input lines typed at a password prompt
+    pub prompt_inputs: usize,
+}
+
+/// Rewrites a recording so it can be shared. Patterns are matched per event,
+/// a secret split across two output chunks is not caught.
+pub struct Redactor {
+    patterns: Vec<Regex>,
+    prompts: bool,
+}
+
+impl Redactor {
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|p| Regex::new(p))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            patterns,
+            prompts: true,
+        })
+    }
+
+    /// Mask input typed after a visible password prompt, on by default
+    pub fn with_prompts(mut self, val: bool) -> Self {
+        self.prompts = val;
+        self
+    }
+
+    pub fn redact<R, W>(&self, input: R, mut output: W) -> Result<RedactStats>
+    where
+        R: BufRead + Send,
+        W: Write,
+    {
+        let cast = asciicast::open(input)?;
+        let mut encoder = V3Encoder::new();
+        let mut stats = RedactStats::default();
+        let mut tail = String::new();
+        let mut at_prompt = false;
+
+        output.write_all(&encoder.header(&cast.header))?;
+        for event in cast.events {
+            let Event { time, data } = event?;
+            let data = match data {
+                EventData::Output(text) => {
+                    let text = self.apply_patterns(text, &mut stats);
+                    if self.prompts {
+                        push_tail(&mut tail, &text);
+                        at_prompt = PASSWORD_PROMPT.is_match(&CSI.replace_all(&tail, ""));
+                    }
+                    EventData::Output(text)
+                }
+                EventData::Input(text) if at_prompt => {
+                    let (text, done) = mask_input(&text);
+                    if done {
+                        at_prompt = false;
+                        tail.clear();
+                        stats.prompt_inputs += 1;
+                    }
+                    EventData::Input(self.apply_patterns(text, &mut stats))
+                }
+                EventData::Input(text) => EventData::Input(self.apply_patterns(text, &mut stats)),
+                EventData::Marker(text) => EventData::Marker(self.apply_patterns(text, &mut stats)),
+                other => other,
+            };
+            output.write_all(&encoder.event(&Event { time, data }))?;
+            stats.events += 1;
+        }
+        output.flush()?;
+
+        Ok(stats)
+    }
+
+    fn apply_patterns(&self, mut text: String, stats: &mut RedactStats) -> String {
+        for re in &self.patterns {
+            let count = re.find_iter(&text).count();
+            if count > 0 {
+                stats.matches += count;
+                text = re.replace_all(&text, REDACTED).into_owned();
+            }
+        }
+        text
+    }
+}
+
+/// Keeps the current output line only, capped at `PROMPT_TAIL_LEN` bytes
+fn push_tail(tail: &mut String, text: &str) {
+    match text.rfind(['\n', '\r']) {
+        Some(i) => {
+            tail.clear();
+            tail.push_str(&text[i + 1..]);
+        }
+        None => tail.push_str(text),
+    }
+    if tail.len() > PROMPT_TAIL_LEN {
+        let mut start = tail.len() - PROMPT_TAIL_LEN;
+        while !tail.is_char_boundary(start) {
+            start += 1;
+        }
+        tail.drain(..start);
+    }
+}
+
+/// Replaces typed characters with `*` up to the first line break. Returns
+/// whether the line was finished.
+fn mask_input(text: &str) -> (String, bool) {
+    match text.find(['\r', '\n']) {
+        Some(i) => {
+            let masked = "*".repeat(text[..i].chars().count());
+            (masked + &text[i..], true)
+        }
+        None => ("*".repeat(text.chars().count()), false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Redactor;
+
+    #[test]
+    fn redact_prompt_and_patterns() {
+        let cast = concat!(
+            r#"{"version": 3, "term": {"cols": 80, "rows": 24}}"#,
+            "\n",
+            r#"[0.1, "o", "$ sudo ls\r\n[sudo] password for bob: "]"#,
+            "\n",
+            r#"[0.2, "i", "hunter2"]"#,
+            "\n",
+            r#"[0.1, "i", "\r"]"#,
+            "\n",
+            r#"[0.1, "o", "\r\ntoken=abc123\r\n$ "]"#,
+            "\n",
+            r#"[0.1, "i", "ls\r"]"#,
+            "\n",
+        );
+        let redactor = Redactor::new(&["token=\\w+".to_string()]).unwrap();
+        let mut out = Vec::new();
+        let stats = redactor.redact(cast.as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert_eq!(stats.events, 5);
+        assert_eq!(stats.matches, 1);
+        assert_eq!(stats.prompt_inputs, 1);
+        assert!(!out.contains("hunter2"));
+        assert!(out.contains(r#""*******""#));
+        assert!(!out.contains("abc123"));
+        assert!(out.contains("[REDACTED]"));
+        assert!(out.contains(r#""ls\r""#));
+    }
+}
@@ -0,0 +1,494 @@
+//! Recordings in an S3 compatible bucket. Requests are signed with AWS
+//! Signature Version 4 and name the bucket in the path, which AWS, MinIO and
+//! Ceph all take. A recording is held in memory until `part_size` bytes make
+//! a part of a multipart upload, the upload is completed when the recording
+//! ends. One that never fills a part goes up with a single PUT.
+
+use super::record_sink::RecordSink;
+use crate::common::hmac_sha256;
+use crate::config::RecordSinkConfig;
+use crate::config::error::ConfigError;
+use async_trait::async_trait;
+use data_encoding::HEXLOWER;
+use reqwest::header::{AUTHORIZATION, CONTENT_LENGTH, ETAG};
+use reqwest::{Method, Url};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io;
+
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+/// Wait before the first retry, doubled for every further one up to 32s
+const RETRY_PAUSE: Duration = Duration::from_millis(500);
+
+pub struct S3Store {
+    client: reqwest::Client,
+    /// Scheme and `host[:port]` of the endpoint
+    origin: String,
+    /// `host[:port]` as the Host header carries it
+    host: String,
+    /// Path of the bucket, below the endpoint's own
+    bucket_path: String,
+    region: String,
+    prefix: String,
+    access_key: String,
+    secret_key: String,
+    part_size: usize,
+    retries: u32,
+    timeout: Duration,
+}
+
+/// What a request got back
+struct Reply {
+    etag: Option<String>,
+    /// Content-Length, the object's size for a HEAD
+    len: Option<u64>,
+    body: Vec<u8>,
+}
+
+impl S3Store {
+    pub fn new(config: &RecordSinkConfig) -> Result<Self, ConfigError> {
+        let endpoint = config.endpoint.clone().unwrap_or_default();
+        let url = Url::parse(&endpoint)
+            .ok()
+            .filter(|u| u.host_str().is_some())
+            .ok_or_else(|| ConfigError::RecordSinkEndpoint {
+                url: endpoint.clone(),
+            })?;
+        let host = match (url.host_str(), url.port()) {
+            (Some(h), Some(p)) => format!("{}:{}", h, p),
+            (Some(h), None) => h.to_string(),
+            (None, _) => unreachable!("endpoint without host is refused above"),
+        };
+        let bucket = config
+            .bucket
+            .clone()
+            .filter(|b| !b.is_empty())
+            .ok_or(ConfigError::RecordSinkBucketMissing)?;
+        let (access_key, secret_key) = config
+            .credentials()
+            .ok_or(ConfigError::RecordSinkCredentialsMissing)?;
+        Ok(S3Store {
+            client: reqwest::Client::new(),
+            origin: format!("{}://{}", url.scheme(), host),
+            host,
+            bucket_path: format!("{}/{}", url.path().trim_end_matches('/'), bucket),
+            region: config.region.clone(),
+            prefix: config.prefix.clone(),
+            access_key,
+            secret_key,
+            part_size: config.part_size,
+            retries: config.retries,
+            timeout: config.timeout,
+        })
+    }
+
+    /// Sink uploading the recording at `path`, relative as below
+    /// `record_path`
+    pub fn sink(self: &Arc<Self>, path: &str) -> S3Sink {
+        S3Sink {
+            store: self.clone(),
+            key: self.key(path),
+            buf: Vec::new(),
+            upload_id: None,
+            etags: Vec::new(),
+        }
+    }
+
+    /// Contents of the recording at `path`
+    pub async fn get(&self, path: &str) -> io::Result<Vec<u8>> {
+        let reply = self
+            .send(Method::GET, &self.key(path), &[], Vec::new())
+            .await?;
+        Ok(reply.body)
+    }
+
+    /// Bytes of the recording at `path`
+    pub async fn size(&self, path: &str) -> io::Result<u64> {
+        self.send(Method::HEAD, &self.key(path), &[], Vec::new())
+            .await?
+            .len
+            .ok_or_else(|| io::Error::other("no Content-Length for the object"))
+    }
+
+    pub async fn delete(&self, path: &str) -> io::Result<()> {
+        self.send(Method::DELETE, &self.key(path), &[], Vec::new())
+            .await
+            .map(|_| ())
+    }
+
+    fn key(&self, path: &str) -> String {
+        format!("{}{}", self.prefix, path)
+    }
+
+    async fn put(&self, key: &str, body: Vec<u8>) -> io::Result<()> {
+        self.send(Method::PUT, key, &[], body).await.map(|_| ())
+    }
+
+    async fn create_upload(&self, key: &str) -> io::Result<String> {
+        let reply = self
+            .send(Method::POST, key, &[("uploads", "")], Vec::new())
+            .await?;
+        xml_value(&reply.body, "UploadId")
+            .map(str::to_string)
+            .ok_or_else(|| io::Error::other("no UploadId in the answer"))
+    }
+
+    /// ETag of the part, numbered from 1
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        number: usize,
+        body: Vec<u8>,
+    ) -> io::Result<String> {
+        let number = number.to_string();
+        let query = [("partNumber", number.as_str()), ("uploadId", upload_id)];
+        self.send(Method::PUT, key, &query, body)
+            .await?
+            .etag
+            .ok_or_else(|| io::Error::other("no ETag for the part"))
+    }
+
+    async fn complete_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        etags: &[String],
+    ) -> io::Result<()> {
+        let parts: String = etags
+            .iter()
+            .enumerate()
+            .map(|(i, etag)| {
+                format!(
+                    "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                    i + 1,
+                    etag
+                )
+            })
+            .collect();
+        let body = format!(
+            "<CompleteMultipartUpload>{}</CompleteMultipartUpload>",
+            parts
+        );
+        let reply = self
+            .send(
+                Method::POST,
+                key,
+                &[("uploadId", upload_id)],
+                body.into_bytes(),
+            )
+            .await?;
+        // S3 may fail a completion after its 200 has gone out
+        match xml_value(&reply.body, "Code") {
+            Some(code) => Err(io::Error::other(format!("completing upload: {}", code))),
+            None => Ok(()),
+        }
+    }
+
+    async fn abort_upload(&self, key: &str, upload_id: &str) -> io::Result<()> {
+        self.send(Method::DELETE, key, &[("uploadId", upload_id)], Vec::new())
+            .await
+            .map(|_| ())
+    }
+
+    /// Sends the request, again up to `retries` times while it fails
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> io::Result<Reply> {
+        let mut attempt = 0;
+        loop {
+            match self.send_once(&method, key, query, body.clone()).await {
+                Ok(reply) => return Ok(reply),
+                Err(e) if attempt < self.retries => {
+                    log::warn!("S3 {} {} failed, retrying: {}", method, key, e);
+                    tokio::time::sleep(RETRY_PAUSE * 2u32.pow(attempt.min(6))).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn send_once(
+        &self,
+        method: &Method,
+        key: &str,
+        query: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> io::Result<Reply> {
+        let path = uri_encode(&format!("{}/{}", self.bucket_path, key), false);
+        let query = canonical_query(query);
+        let url = if query.is_empty() {
+            format!("{}{}", self.origin, path)
+        } else {
+            format!("{}{}?{}", self.origin, path, query)
+        };
+        let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = HEXLOWER.encode(&Sha256::digest(&body));
+        let authorization =
+            self.authorization(method.as_str(), &path, &query, &payload_hash, &amz_date);
+        let request = self
+            .client
+            .request(method.clone(), url)
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header(AUTHORIZATION, authorization)
+            .body(body);
+        let exchange = async {
+            let response = request.send().await.map_err(io::Error::other)?;
+            let status = response.status();
+            let etag = response
+                .headers()
+                .get(ETAG)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string);
+            let len = response
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok());
+            let body = response.bytes().await.map_err(io::Error::other)?.to_vec();
+            if !status.is_success() {
+                let code = xml_value(&body, "Code").unwrap_or_default();
+                return Err(io::Error::other(format!("{} {}", status, code)));
+            }
+            Ok(Reply { etag, len, body })
+        };
+        tokio::time::timeout(self.timeout, exchange)
+            .await
+            .unwrap_or_else(|_| {
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "no answer within {}",
+                        humantime::format_duration(self.timeout)
+                    ),
+                ))
+            })
+    }
+
+    /// `Authorization` of a request, signing its host, date and payload
+    /// hash. `path` and `query` are as sent, already encoded.
+    fn authorization(
+        &self,
+        method: &str,
+        path: &str,
+        query: &str,
+        payload_hash: &str,
+        amz_date: &str,
+    ) -> String {
+        let date = &amz_date[..8];
+        let canonical = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, path, query, self.host, payload_hash, amz_date, SIGNED_HEADERS, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            HEXLOWER.encode(&Sha256::digest(canonical.as_bytes()))
+        );
+        let key = signing_key(&self.secret_key, date, &self.region, "s3");
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key,
+            scope,
+            SIGNED_HEADERS,
+            HEXLOWER.encode(&hmac_sha256(&key, to_sign.as_bytes()))
+        )
+    }
+}
+
+/// Key of the requests of a day, region and service
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> [u8; 32] {
+    let key = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+/// Percent-encodes all but the unreserved characters, and `/` unless
+/// `slash` is set
+fn uri_encode(s: &str, slash: bool) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if !slash => "/".to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Query string sorted and encoded the way it is signed
+fn canonical_query(query: &[(&str, &str)]) -> String {
+    let mut pairs: Vec<(String, String)> = query
+        .iter()
+        .map(|(k, v)| (uri_encode(k, true), uri_encode(v, true)))
+        .collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Text of the first `<tag>` of an XML answer
+fn xml_value<'a>(body: &'a [u8], tag: &str) -> Option<&'a str> {
+    let body = std::str::from_utf8(body).ok()?;
+    let start = body.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let len = body[start..].find(&format!("</{}>", tag))?;
+    Some(&body[start..start + len])
+}
+
+pub struct S3Sink {
+    store: Arc<S3Store>,
+    key: String,
+    /// Bytes not sent yet
+    buf: Vec<u8>,
+    /// Set by the first part
+    upload_id: Option<String>,
+    /// Of the parts sent, in order
+    etags: Vec<String>,
+}
+
+impl S3Sink {
+    async fn send_part(&mut self) -> io::Result<()> {
+        let upload_id = match &self.upload_id {
+            Some(id) => id.clone(),
+            None => {
+                let id = self.store.create_upload(&self.key).await?;
+                self.upload_id = Some(id.clone());
+                id
+            }
+        };
+        let part = std::mem::take(&mut self.buf);
+        let etag = self
+            .store
+            .upload_part(&self.key, &upload_id, self.etags.len() + 1, part)
+            .await?;
+        self.etags.push(etag);
+        Ok(())
+    }
+
+    /// Drops the parts sent, the recording is lost
+    async fn abort(&mut self) {
+        if let Some(id) = self.upload_id.take()
+            && let Err(e) = self.store.abort_upload(&self.key, &id).await
+        {
+            log::warn!("Failed to abort the upload of {}: {}", self.key, e);
+        }
+    }
+}
+
+#[async_trait]
+impl RecordSink for S3Sink {
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() < self.store.part_size {
+            return Ok(());
+        }
+        if let Err(e) = self.send_part().await {
+            self.abort().await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> io::Result<()> {
+        let Some(upload_id) = self.upload_id.clone() else {
+            return self
+                .store
+                .put(&self.key, std::mem::take(&mut self.buf))
+                .await;
+        };
+        let mut sent = Ok(());
+        if !self.buf.is_empty() {
+            sent = self.send_part().await;
+        }
+        if sent.is_ok() {
+            sent = self
+                .store
+                .complete_upload(&self.key, &upload_id, &self.etags)
+                .await;
+        }
+        if sent.is_err() {
+            self.abort().await;
+        }
+        sent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> S3Store {
+        S3Store::new(&RecordSinkConfig {
+            kind: crate::config::RecordSinkKind::S3,
+            endpoint: Some("https://s3.eu-central-1.amazonaws.com".to_string()),
+            region: "eu-central-1".to_string(),
+            bucket: Some("recordings".to_string()),
+            prefix: "bastion-1/".to_string(),
+            access_key: Some("AKIDEXAMPLE".to_string()),
+            secret_key: Some("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string()),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_signing_key() {
+        // Example of the AWS documentation on deriving a signing key
+        assert_eq!(
+            HEXLOWER.encode(&signing_key(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                "20120215",
+                "us-east-1",
+                "iam"
+            )),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn test_authorization() {
+        let store = store();
+        assert_eq!(store.key("t/s/id.cast"), "bastion-1/t/s/id.cast");
+        let path = uri_encode(
+            &format!("{}/{}", store.bucket_path, "bastion-1/t/s/id.cast"),
+            false,
+        );
+        assert_eq!(path, "/recordings/bastion-1/t/s/id.cast");
+        let query = canonical_query(&[("uploadId", "a b"), ("partNumber", "2")]);
+        assert_eq!(query, "partNumber=2&uploadId=a%20b");
+        // Signature botocore gives the same request
+        let payload_hash = HEXLOWER.encode(&Sha256::digest(b"part"));
+        assert_eq!(
+            store.authorization("PUT", &path, &query, &payload_hash, "20260102T030405Z"),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20260102/eu-central-1/s3/aws4_request, \
+             SignedHeaders=host;x-amz-content-sha256;x-amz-date, \
+             Signature=0388ac756c39348023727980d1523b78505396c7829b5bb0ec959b877c0f0719"
+        );
+    }
+
+    #[test]
+    fn test_xml_value() {
+        let body = b"<?xml version=\"1.0\"?><InitiateMultipartUploadResult><Bucket>b</Bucket>\
+            <UploadId>VXBsb2FkIElE</UploadId></InitiateMultipartUploadResult>";
+        assert_eq!(xml_value(body, "UploadId"), Some("VXBsb2FkIElE"));
+        assert_eq!(xml_value(body, "Code"), None);
+        assert_eq!(
+            xml_value(b"<Error><Code>InternalError</Code></Error>", "Code"),
+            Some("InternalError")
+        );
+    }
+}
@@ -11,6 +11,9 @@ use crate::asciinema::tty::{RawTty, TtySize};
 use crate::asciinema::util::Utf8Decoder;
 use crate::asciinema::Result;
 
+/// Gaps between events longer than this count as idle time
+const ACTIVE_GAP_LIMIT: Duration = Duration::from_secs(2);
+
 #[derive(Clone)]
 pub enum Event {
     Output(Duration, String),
@@ -20,6 +23,29 @@ pub enum Event {
     Exit(Duration, i32),
 }
 
+impl Event {
+    fn time(&self) -> Duration {
+        match self {
+            Event::Output(t, _)
+            | Event::Input(t, _)
+            | Event::Resize(t, _)
+            | Event::Marker(t, _)
+            | Event::Exit(t, _) => *t,
+        }
+    }
+}
+
+/// Timing summary kept in the recording index, so reviewers can tell a long
+/// idle session from a busy one without playing it
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    /// Wall-clock time since the recording started
+    pub duration: Duration,
+    /// Time between events, each gap capped at `ACTIVE_GAP_LIMIT`
+    pub active: Duration,
+    pub events: u64,
+}
+
 #[derive(Clone)]
 pub struct Metadata {
     pub time: chrono::DateTime<chrono::Utc>,
@@ -48,6 +74,9 @@ pub struct Session {
     record_input: bool,
     time_offset: Duration,
     tty_size: TtySize,
+    last_event_time: Option<Duration>,
+    active_time: Duration,
+    event_count: u64,
 }
 
 #[async_trait]
@@ -76,6 +105,9 @@ pub async fn new<T: RawTty + ?Sized>(
         record_input,
         time_offset: Duration::from_micros(0),
         tty_size: winsize.into(),
+        last_event_time: None,
+        active_time: Duration::ZERO,
+        event_count: 0,
     };
     Ok(session)
 }
@@ -181,6 +213,14 @@ impl Session {
         self.send_session_event(event).await;
     }
 
+    pub fn stats(&self) -> Stats {
+        Stats {
+            duration: self.epoch.elapsed(),
+            active: self.active_time,
+            events: self.event_count,
+        }
+    }
+
     fn elapsed_time(&self) -> Duration {
         if let Some(pause_time) = self.pause_time {
             pause_time
@@ -190,6 +230,13 @@ impl Session {
     }
 
     async fn send_session_event(&mut self, event: Event) {
+        let time = event.time();
+        if let Some(last) = self.last_event_time {
+            self.active_time += time.saturating_sub(last).min(ACTIVE_GAP_LIMIT);
+        }
+        self.last_event_time = Some(time);
+        self.event_count += 1;
+
         self.events_tx
             .send(event)
             .await
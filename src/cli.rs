@@ -1,7 +1,23 @@
+use crate::config::error::ConfigError;
 use crate::config::{Config, LogLevel};
+use crate::database::error::DatabaseError;
+use crate::database::models::maintenance_window::{REPEAT_DAILY, REPEAT_NONE, REPEAT_WEEKLY};
+use crate::database::models::{
+    ApiToken, MaintenanceWindow, Secret, TagFilter, Timestamp, TraceRule, User,
+};
+use crate::database::service::{DatabaseService, Dataset};
+use crate::database::{DatabaseRepository, Uuid};
 use crate::error::Error;
-use clap::Parser;
+use crate::server::casbin::{ExtendPolicyReq, GroupType, PolicyCheck};
+use crate::server::error::ServerError;
+use crate::server::policy_lint::{self, Severity};
+use crate::server::role_graph;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
 use log::info;
+use russh::keys::Algorithm;
+use russh::keys::ssh_key::{EcdsaCurve, HashAlg};
+use std::io::{Read, Write};
 
 #[derive(Parser)]
 #[command(name = "rustion")]
@@ -25,6 +41,10 @@ pub struct Cli {
     #[arg(long = "init")]
     pub init_service: bool,
 
+    /// Run with an in-memory database that is discarded on exit
+    #[arg(long = "demo")]
+    pub demo: bool,
+
     /// Listen address (overrides config file)
     #[arg(short = 'l', long = "listen", value_name = "ADDRESS")]
     pub listen: Option<String>,
@@ -40,6 +60,462 @@ pub struct Cli {
         help = "Set log level (error, warn, info, debug, trace)"
     )]
     pub log_level: Option<String>,
+
+    /// Print what a command would change without touching anything
+    #[arg(long = "dry-run", global = true)]
+    pub dry_run: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Export users, targets, secrets and policies to a file
+    Export {
+        /// Output file, `-` for stdout
+        file: String,
+
+        /// Data format, guessed from the file extension by default
+        #[arg(long = "format", value_enum)]
+        format: Option<DataFormat>,
+    },
+    /// Import a file produced by `export` into an empty database
+    Import {
+        /// Input file, `-` for stdin
+        file: String,
+
+        /// Data format, guessed from the file extension by default
+        #[arg(long = "format", value_enum)]
+        format: Option<DataFormat>,
+    },
+    /// Write a copy of a session recording with secrets masked, for sharing
+    Redact {
+        /// Recording to read
+        input: String,
+
+        /// Sanitized copy, `-` for stdout
+        output: String,
+
+        /// Extra regex to mask, added to `redact_patterns` from the config
+        #[arg(short = 'p', long = "pattern", value_name = "REGEX")]
+        patterns: Vec<String>,
+
+        /// Keep input typed at password prompts
+        #[arg(long = "keep-prompts")]
+        keep_prompts: bool,
+    },
+    /// Build the `breached_passwords` filter from the Have I Been Pwned
+    /// SHA-1 dump
+    BreachFilter {
+        /// Dump with one `HASH:count` line per password
+        input: String,
+
+        /// Filter file to write
+        output: String,
+
+        /// Share of good passwords wrongly refused, a smaller rate makes a
+        /// bigger file
+        #[arg(long = "fp-rate", default_value_t = 0.001)]
+        fp_rate: f64,
+    },
+    /// Check the hash chain of the operation logs, fails if an entry was
+    /// changed or removed. The newest hash is printed for keeping elsewhere.
+    VerifyLogs,
+    /// Tell whether a user would be allowed an action on a target, listing
+    /// the policies checked on the way. Fails when nothing grants it or a
+    /// deny rule applies.
+    Enforce {
+        /// User to check, their groups and roles apply
+        username: String,
+
+        /// `account@target`, a target with a single account, or `:login`,
+        /// `:admin` and `:player` for the internal objects
+        target: String,
+
+        #[arg(long = "action", value_enum, default_value = "shell")]
+        action: PolicyAction,
+
+        /// Client address the request comes from, located with
+        /// `geoip_database` for `geo=` policies
+        #[arg(long = "ip", value_name = "ADDRESS")]
+        ip: Option<std::net::IpAddr>,
+
+        /// Time of the request as `2025-01-31 09:00:00 +0800` or RFC 3339,
+        /// now by default
+        #[arg(long = "at", value_name = "TIME", value_parser = parse_time)]
+        at: Option<DateTime<Utc>>,
+
+        /// Ticket given with the login, for policies that require one
+        #[arg(long = "ticket")]
+        ticket: Option<String>,
+    },
+    /// Print the user roles, target groups or action groups with their
+    /// members, as a Graphviz digraph or a tree
+    RoleGraph {
+        #[arg(value_enum)]
+        kind: RoleGraphKind,
+
+        #[arg(long = "format", value_enum, default_value = "dot")]
+        format: GraphFormat,
+    },
+    /// Look for rules naming missing ids or carrying exts that don't parse,
+    /// groups without members or policies, and groups that contain each
+    /// other. Fails when a finding has error severity.
+    ValidatePolicies,
+    /// Issue, list and revoke API tokens used by automation
+    Token {
+        #[command(subcommand)]
+        action: TokenAction,
+    },
+    /// Inspect and restore earlier credentials of a secret
+    #[command(alias = "secrets")]
+    Secret {
+        #[command(subcommand)]
+        action: SecretAction,
+    },
+    /// Write debug traces of the connections of a user or client IP
+    Trace {
+        #[command(subcommand)]
+        action: TraceAction,
+    },
+    /// Schedule maintenance windows keeping non-admins off targets
+    Maintenance {
+        #[command(subcommand)]
+        action: MaintenanceAction,
+    },
+    /// Describe config keys, or compare the loaded config with the defaults
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Write a new host key in place of `server_key` or a `host_keys` file.
+    /// The old key is still served until the grace period ends, both are
+    /// picked up at the next start.
+    RotateHostkey {
+        /// Key file to rotate, `server_key` by default
+        #[arg(long = "key", value_name = "FILE")]
+        key: Option<String>,
+
+        /// Algorithm of the new key, the old key's by default
+        #[arg(long = "algorithm", value_enum)]
+        algorithm: Option<HostKeyAlgorithm>,
+
+        /// How long the old key is still served, such as `30d`
+        #[arg(
+            long = "grace",
+            value_name = "DURATION",
+            value_parser = humantime::parse_duration,
+            default_value = "30d"
+        )]
+        grace: std::time::Duration,
+    },
+    /// Print the public key of `target_ca_key` for sshd's
+    /// `TrustedUserCAKeys` on targets, creating the key when it is missing
+    TargetCa,
+    /// Fetch the host keys of a target and print them with their
+    /// fingerprints, instead of copying them from the target by hand
+    Keyscan {
+        /// Target name, or a hostname when `--port` is given
+        target: String,
+
+        /// Scan `target` as a hostname on this port
+        #[arg(long = "port")]
+        port: Option<u16>,
+
+        /// Store the first key found as the target's `server_public_key`
+        #[arg(long = "save", conflicts_with = "port")]
+        save: bool,
+    },
+    /// Run a throwaway instance on an in-memory database with a dummy target
+    Dev {
+        /// Load the seed file and point its targets at the dummy target
+        #[arg(long = "seed")]
+        seed: bool,
+
+        /// JSON file in the `export` format used by `--seed`
+        #[arg(
+            long = "seed-file",
+            value_name = "FILE",
+            default_value = "mock_data.json"
+        )]
+        seed_file: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TokenAction {
+    /// Issue a token for a user, the token is printed only once
+    Create {
+        /// Owner of the token
+        username: String,
+
+        /// Label telling the tokens of a user apart
+        #[arg(long = "name")]
+        name: String,
+
+        /// Scope granted to the token, repeatable, `*` grants everything
+        #[arg(short = 's', long = "scope", value_name = "SCOPE", required = true)]
+        scopes: Vec<String>,
+
+        /// Lifetime such as `30d` or `12h`, no expiry by default
+        #[arg(
+            long = "expires-in",
+            value_name = "DURATION",
+            value_parser = humantime::parse_duration
+        )]
+        expires_in: Option<std::time::Duration>,
+    },
+    /// List tokens, of one user if given
+    List { username: Option<String> },
+    /// Deactivate a token
+    Revoke { id: Uuid },
+    /// Check a token read from stdin, fails unless it grants `scope`
+    Verify {
+        #[arg(long = "scope")]
+        scope: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SecretAction {
+    /// List the credentials a secret had before its updates, newest first
+    History { name: String },
+    /// Store the credentials of an earlier version as the current ones
+    Rollback {
+        name: String,
+        version: i64,
+
+        /// User recorded as the author of the rollback
+        #[arg(long = "by", value_name = "USERNAME")]
+        by: String,
+    },
+    /// Write every secret in plain text, encrypted to age recovery keys, so
+    /// they survive the loss of `secret_key`
+    Export {
+        /// Armored age file, `-` for stdout
+        file: String,
+
+        /// age public key (`age1...`) able to open the bundle, repeatable
+        #[arg(
+            short = 'r',
+            long = "recipient",
+            value_name = "RECIPIENT",
+            required = true
+        )]
+        recipients: Vec<String>,
+    },
+    /// Store the secrets of an exported bundle under the current `secret_key`,
+    /// replacing secrets with the same id
+    Import {
+        /// Bundle written by `secret export`, `-` for stdin
+        file: String,
+
+        /// age identity file holding the recovery key
+        #[arg(short = 'i', long = "identity", value_name = "FILE")]
+        identity: String,
+
+        /// User recorded as the author of the restored secrets
+        #[arg(long = "by", value_name = "USERNAME")]
+        by: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TraceAction {
+    /// Trace new connections of a user, from an address, or both, into
+    /// `trace_path`
+    Start {
+        /// Login user to trace
+        #[arg(long = "user", value_name = "USERNAME", required_unless_present = "ip")]
+        user: Option<String>,
+
+        /// Client address to trace
+        #[arg(long = "ip", value_name = "ADDRESS")]
+        ip: Option<std::net::IpAddr>,
+
+        /// How long matching connections are traced, such as `15m`, a day at most
+        #[arg(
+            long = "for",
+            value_name = "DURATION",
+            default_value = "15m",
+            value_parser = parse_trace_duration
+        )]
+        duration: std::time::Duration,
+
+        /// User recorded as the author of the rule
+        #[arg(long = "by", value_name = "USERNAME")]
+        by: String,
+    },
+    /// List trace rules
+    List,
+    /// Stop tracing for a rule, traces already running end on their own
+    Stop { id: Uuid },
+}
+
+#[derive(Subcommand)]
+pub enum MaintenanceAction {
+    /// Add a window on a target or on every target carrying a tag
+    Add {
+        /// Target under maintenance
+        #[arg(long = "target", value_name = "NAME", required_unless_present = "tag")]
+        target: Option<String>,
+
+        /// `key=value` tag of the targets under maintenance
+        #[arg(long = "tag", value_name = "TAG", conflicts_with = "target")]
+        tag: Option<TagFilter>,
+
+        /// Start of the first occurrence, as `2025-01-31 22:00:00 +0800` or
+        /// RFC 3339
+        #[arg(long = "start", value_name = "TIME", value_parser = parse_time)]
+        start: DateTime<Utc>,
+
+        /// Length of each occurrence, such as `2h`, a year at most
+        #[arg(
+            long = "for",
+            value_name = "DURATION",
+            value_parser = parse_maintenance_duration
+        )]
+        duration: std::time::Duration,
+
+        #[arg(long = "repeat", value_enum, default_value = "none")]
+        repeat: MaintenanceRepeat,
+
+        /// Told to the users turned away
+        #[arg(long = "reason", default_value = "")]
+        reason: String,
+
+        /// User recorded as the author of the window
+        #[arg(long = "by", value_name = "USERNAME")]
+        by: String,
+    },
+    /// List windows, with the end of the occurrence in progress
+    List,
+    /// Delete a window, sessions are allowed again at once
+    Remove { id: Uuid },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum MaintenanceRepeat {
+    None,
+    Daily,
+    Weekly,
+}
+
+impl MaintenanceRepeat {
+    fn as_str(self) -> &'static str {
+        match self {
+            MaintenanceRepeat::None => REPEAT_NONE,
+            MaintenanceRepeat::Daily => REPEAT_DAILY,
+            MaintenanceRepeat::Weekly => REPEAT_WEEKLY,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Print every key with its default and a short description
+    Schema,
+    /// Print the keys of the config file that differ from their defaults
+    Diff,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum HostKeyAlgorithm {
+    Ed25519,
+    /// ECDSA on NIST P-256
+    Ecdsa,
+    Rsa,
+}
+
+impl HostKeyAlgorithm {
+    fn algorithm(self) -> Algorithm {
+        match self {
+            HostKeyAlgorithm::Ed25519 => Algorithm::Ed25519,
+            HostKeyAlgorithm::Ecdsa => Algorithm::Ecdsa {
+                curve: EcdsaCurve::NistP256,
+            },
+            HostKeyAlgorithm::Rsa => Algorithm::Rsa { hash: None },
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum PolicyAction {
+    Shell,
+    Pty,
+    Exec,
+    Login,
+    DirectTcpip,
+    Sftp,
+    Scp,
+    RemoteForward,
+    AgentForward,
+    Observe,
+}
+
+impl PolicyAction {
+    fn casbin_name(self) -> &'static str {
+        use crate::database::common::*;
+
+        match self {
+            PolicyAction::Shell => ACT_SHELL,
+            PolicyAction::Pty => ACT_PTY,
+            PolicyAction::Exec => ACT_EXEC,
+            PolicyAction::Login => ACT_LOGIN,
+            PolicyAction::DirectTcpip => ACT_DIRECT_TCPIP,
+            PolicyAction::Sftp => ACT_SFTP,
+            PolicyAction::Scp => ACT_SCP,
+            PolicyAction::RemoteForward => ACT_REMOTE_FORWARD,
+            PolicyAction::AgentForward => ACT_AGENT_FORWARD,
+            PolicyAction::Observe => ACT_OBSERVE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum RoleGraphKind {
+    /// User roles and groups (g1)
+    Subject,
+    /// Target groups (g2)
+    Object,
+    /// Action groups (g3)
+    Action,
+}
+
+impl RoleGraphKind {
+    fn group_type(self) -> GroupType {
+        match self {
+            RoleGraphKind::Subject => GroupType::Subject,
+            RoleGraphKind::Object => GroupType::Object,
+            RoleGraphKind::Action => GroupType::Action,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz, e.g. `| dot -Tsvg > roles.svg`
+    Dot,
+    Tree,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum DataFormat {
+    Json,
+    Yaml,
+}
+
+impl DataFormat {
+    fn from_path(path: &str) -> Self {
+        match std::path::Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+        {
+            Some("yaml" | "yml") => DataFormat::Yaml,
+            _ => DataFormat::Json,
+        }
+    }
 }
 
 pub async fn handle_cli_args() -> Result<Option<Config>, Error> {
@@ -54,8 +530,22 @@ pub async fn handle_cli_args() -> Result<Option<Config>, Error> {
     }
 
     // Load configuration from file
+    let ephemeral = cli.demo || matches!(cli.command, Some(Command::Dev { .. }));
+    // Redacting a recording only reads `redact_patterns`, the schema and the
+    // breach filter build read nothing, a config file is optional
+    let offline = matches!(
+        cli.command,
+        Some(Command::Redact { .. })
+            | Some(Command::BreachFilter { .. })
+            | Some(Command::Config {
+                action: ConfigAction::Schema
+            })
+    );
     let mut config = match Config::from_file(&cli.config) {
         Ok(config) => config,
+        Err(_) if (ephemeral || offline) && !std::path::Path::new(&cli.config).exists() => {
+            Config::default().gen_secret_token()
+        }
         Err(e) => {
             panic!("Configuration file load error '{}'", e);
         }
@@ -66,6 +556,76 @@ pub async fn handle_cli_args() -> Result<Option<Config>, Error> {
         return Ok(None);
     }
 
+    match cli.command {
+        Some(Command::Dev { seed, seed_file }) => {
+            config.database = crate::database::DatabaseConfig::Memory {
+                name: "dev".to_string(),
+            };
+            if seed {
+                crate::server::dev::seed(config.clone(), &seed_file).await?;
+            } else {
+                crate::server::init_service::init_service(config.clone()).await;
+                let (addr, public_key) = crate::server::dev::start_dummy_target().await?;
+                eprintln!("Dummy target on {} with host key: {}", addr, public_key);
+            }
+        }
+        Some(Command::Redact {
+            input,
+            output,
+            patterns,
+            keep_prompts,
+        }) => {
+            redact(
+                &config,
+                &input,
+                &output,
+                patterns,
+                keep_prompts,
+                cli.dry_run,
+            )?;
+            return Ok(None);
+        }
+        Some(Command::BreachFilter {
+            input,
+            output,
+            fp_rate,
+        }) => {
+            breach_filter(&input, &output, fp_rate, cli.dry_run)?;
+            return Ok(None);
+        }
+        Some(Command::Config { action }) => {
+            config_action(&config, action)?;
+            return Ok(None);
+        }
+        Some(Command::RotateHostkey {
+            key,
+            algorithm,
+            grace,
+        }) => {
+            if let Some(server_key) = cli.server_key {
+                config.server_key = server_key;
+            }
+            rotate_hostkey(&config, key, algorithm, grace, cli.dry_run)?;
+            return Ok(None);
+        }
+        Some(Command::TargetCa) => {
+            target_ca(&config, cli.dry_run)?;
+            return Ok(None);
+        }
+        Some(command) => {
+            run_command(command, &config, cli.dry_run).await?;
+            return Ok(None);
+        }
+        None => {}
+    }
+
+    if cli.demo {
+        config.database = crate::database::DatabaseConfig::Memory {
+            name: "demo".to_string(),
+        };
+        crate::server::init_service::init_service(config.clone()).await;
+    }
+
     // Override with command line arguments
     if let Some(listen) = cli.listen {
         config.listen = crate::config::ListenConfig::String(listen);
@@ -84,3 +644,1008 @@ pub async fn handle_cli_args() -> Result<Option<Config>, Error> {
 
     Ok(Some(config))
 }
+
+async fn run_command(command: Command, config: &Config, dry_run: bool) -> Result<(), Error> {
+    let db = DatabaseService::new(&config.database).await?;
+    match command {
+        Command::Export { file, format } => {
+            let data = db.export_all().await?;
+            let content = match format.unwrap_or_else(|| DataFormat::from_path(&file)) {
+                DataFormat::Json => serde_json::to_string_pretty(&data)?,
+                DataFormat::Yaml => serde_norway::to_string(&data)?,
+            };
+            if file == "-" {
+                println!("{}", content);
+            } else if dry_run {
+                eprintln!(
+                    "Dry run: would write {} bytes to {}{}",
+                    content.len(),
+                    file,
+                    if std::path::Path::new(&file).exists() {
+                        " (overwriting it)"
+                    } else {
+                        ""
+                    }
+                );
+            } else {
+                std::fs::write(&file, content)?;
+            }
+            eprintln!(
+                "Exported {} users, {} targets, {} secrets, {} target secrets, {} casbin names, {} casbin rules",
+                data.users.len(),
+                data.targets.len(),
+                data.secrets.len(),
+                data.target_secrets.len(),
+                data.casbin_names.len(),
+                data.casbin_rule.len()
+            );
+        }
+        Command::Dev { .. }
+        | Command::Redact { .. }
+        | Command::BreachFilter { .. }
+        | Command::Config { .. }
+        | Command::RotateHostkey { .. }
+        | Command::TargetCa => unreachable!(),
+        Command::VerifyLogs => {
+            let (count, head) = db.verify_logs().await?;
+            eprintln!("Verified {} log entries, newest hash:", count);
+            println!("{}", head);
+        }
+        Command::Enforce {
+            username,
+            target,
+            action,
+            ip,
+            at,
+            ticket,
+        } => {
+            let country = match (config.geoip_database.as_ref(), ip) {
+                (Some(path), Some(ip)) => {
+                    crate::server::geoip::GeoIp::open(std::path::Path::new(path))
+                        .map_err(|e| {
+                            Error::Config(crate::config::error::ConfigError::GeoIpDatabase {
+                                path: path.clone(),
+                                source: e,
+                            })
+                        })?
+                        .country(ip)
+                }
+                _ => None,
+            };
+            let ext = ExtendPolicyReq {
+                ip,
+                now: at.unwrap_or_else(Utc::now),
+                ticket,
+                action: Some(action.casbin_name()),
+                country,
+            };
+            enforce(&db, &username, &target, action, ext).await?
+        }
+        Command::RoleGraph { kind, format } => {
+            let rt = kind.group_type();
+            let graph = crate::server::bastion_server::load_role_manager(db.repository())
+                .await?
+                .get_group(rt);
+            match format {
+                GraphFormat::Dot => print!("{}", role_graph::to_dot(&graph, rt)),
+                GraphFormat::Tree => print!("{}", role_graph::to_tree(&graph)),
+            }
+        }
+        Command::ValidatePolicies => {
+            let findings = policy_lint::lint(&db).await?;
+            for finding in &findings {
+                println!("{}", finding);
+            }
+            let count = findings
+                .iter()
+                .filter(|f| f.severity == Severity::Error)
+                .count();
+            eprintln!("{} findings, {} errors", findings.len(), count);
+            if count > 0 {
+                return Err(ServerError::PolicyLint { count }.into());
+            }
+        }
+        Command::Token { action } => token(&db, action, dry_run).await?,
+        Command::Secret { action } => secret(db.repository(), config, action, dry_run).await?,
+        Command::Trace { action } => trace(db.repository(), action, dry_run).await?,
+        Command::Maintenance { action } => maintenance(db.repository(), action, dry_run).await?,
+        Command::Keyscan { target, port, save } => {
+            keyscan(db.repository(), config, &target, port, save, dry_run).await?
+        }
+        Command::Import { file, format } => {
+            let content = if file == "-" {
+                std::io::read_to_string(std::io::stdin())?
+            } else {
+                std::fs::read_to_string(&file)?
+            };
+            let data: Dataset = match format.unwrap_or_else(|| DataFormat::from_path(&file)) {
+                DataFormat::Json => serde_json::from_str(&content)?,
+                DataFormat::Yaml => serde_norway::from_str(&content)?,
+            };
+            if dry_run {
+                db.check_import().await?;
+                print_import_plan(&data);
+                return Ok(());
+            }
+            db.import_all(&data).await?;
+            eprintln!(
+                "Imported {} users, {} targets, {} secrets, {} target secrets, {} casbin names, {} casbin rules",
+                data.users.len(),
+                data.targets.len(),
+                data.secrets.len(),
+                data.target_secrets.len(),
+                data.casbin_names.len(),
+                data.casbin_rule.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn token(db: &DatabaseService, action: TokenAction, dry_run: bool) -> Result<(), Error> {
+    let repo = db.repository();
+    match action {
+        TokenAction::Create {
+            username,
+            name,
+            scopes,
+            expires_in,
+        } => {
+            let user = user_by_name(repo, &username).await?;
+            let expires_at = expires_in.and_then(|d| Timestamp::now().checked_add(d));
+            let (api_token, plain) = ApiToken::generate(user.id, name, user.id);
+            let api_token = api_token.with_scopes(scopes).with_expires_at(expires_at);
+            api_token.validate().map_err(DatabaseError::from)?;
+            if dry_run {
+                eprintln!(
+                    "Dry run: would issue token '{}' for {} with scopes {}",
+                    api_token.name,
+                    user.username,
+                    api_token.scopes.0.join(",")
+                );
+                return Ok(());
+            }
+            repo.create_api_token(&api_token).await?;
+            eprintln!(
+                "Issued token '{}' ({}) for {}, it is not shown again:",
+                api_token.name, api_token.id, user.username
+            );
+            println!("{}", plain);
+        }
+        TokenAction::List { username } => {
+            let owner = match username {
+                Some(n) => Some(user_by_name(repo, &n).await?.id),
+                None => None,
+            };
+            let now = Timestamp::now();
+            for t in repo.list_api_tokens(owner.as_ref()).await? {
+                let state = if !t.is_active {
+                    "revoked"
+                } else if t.is_expired(now) {
+                    "expired"
+                } else {
+                    "active"
+                };
+                println!(
+                    "{} {} {} [{}] {} expires: {} last used: {}",
+                    t.id,
+                    t.user_id,
+                    t.name,
+                    t.scopes.0.join(","),
+                    state,
+                    format_time(t.expires_at),
+                    format_time(t.last_used_at)
+                );
+            }
+        }
+        TokenAction::Revoke { id } => {
+            let mut api_token =
+                repo.get_api_token_by_id(&id)
+                    .await?
+                    .ok_or_else(|| DatabaseError::NotFound {
+                        table: "api_tokens",
+                        key: id.to_string(),
+                    })?;
+            if dry_run {
+                eprintln!("Dry run: would revoke token '{}' ({})", api_token.name, id);
+                return Ok(());
+            }
+            api_token.is_active = false;
+            repo.update_api_token(&api_token).await?;
+            eprintln!("Revoked token '{}' ({})", api_token.name, id);
+        }
+        TokenAction::Verify { scope } => {
+            let plain = std::io::read_to_string(std::io::stdin())?;
+            match db.authenticate_api_token(plain.trim(), &scope).await? {
+                Some((api_token, user)) => {
+                    eprintln!(
+                        "Token '{}' of {} grants {}",
+                        api_token.name, user.username, scope
+                    )
+                }
+                None => return Err(ServerError::ApiTokenRejected { scope }.into()),
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn secret(
+    repo: &dyn DatabaseRepository,
+    config: &Config,
+    action: SecretAction,
+    dry_run: bool,
+) -> Result<(), Error> {
+    match action {
+        SecretAction::History { name } => {
+            let secret = secret_by_name(repo, &name).await?;
+            println!(
+                "{} (current) {} {}",
+                secret.version,
+                secret.user,
+                secret.print_public_key()
+            );
+            for v in repo.list_secret_versions(&secret.id).await? {
+                println!(
+                    "{} {} {} {} {} by {}",
+                    v.version,
+                    v.updated_at,
+                    v.user,
+                    v.print_password(),
+                    v.print_public_key(),
+                    v.updated_by
+                );
+            }
+        }
+        SecretAction::Rollback { name, version, by } => {
+            let secret = secret_by_name(repo, &name).await?;
+            let user = user_by_name(repo, &by).await?;
+            if dry_run {
+                let versions = repo.list_secret_versions(&secret.id).await?;
+                if !versions.iter().any(|v| v.version == version) {
+                    return Err(DatabaseError::NotFound {
+                        table: "secret_versions",
+                        key: format!("{}@{}", secret.id, version),
+                    }
+                    .into());
+                }
+                eprintln!(
+                    "Dry run: would restore version {} of secret '{}'",
+                    version, secret.name
+                );
+                return Ok(());
+            }
+            let secret = repo.rollback_secret(&secret.id, version, &user.id).await?;
+            eprintln!(
+                "Restored version {} of secret '{}', now at version {}",
+                version, secret.name, secret.version
+            );
+        }
+        SecretAction::Export { file, recipients } => {
+            let cipher = config.secret_cipher()?;
+            let mut secrets = repo.list_secrets(false).await?;
+            for s in secrets.iter_mut() {
+                let password = s.take_password();
+                s.set_password(
+                    password
+                        .map(|p| crate::common::decrypt_with_key(&cipher, &p))
+                        .transpose()?,
+                );
+                let private_key = s.take_private_key();
+                s.set_private_key(
+                    private_key
+                        .map(|k| crate::common::decrypt_with_key(&cipher, &k))
+                        .transpose()?,
+                );
+            }
+            let bundle = seal_bundle(&serde_json::to_vec(&secrets)?, &recipients)?;
+            if dry_run {
+                eprintln!(
+                    "Dry run: would write a bundle of {} secrets to {}",
+                    secrets.len(),
+                    file
+                );
+                return Ok(());
+            }
+            if file == "-" {
+                std::io::stdout().write_all(&bundle)?;
+            } else {
+                std::fs::write(&file, &bundle)?;
+            }
+            eprintln!(
+                "Exported {} secrets for {} recipient(s)",
+                secrets.len(),
+                recipients.len()
+            );
+        }
+        SecretAction::Import { file, identity, by } => {
+            let bundle = if file == "-" {
+                let mut buf = Vec::new();
+                std::io::stdin().read_to_end(&mut buf)?;
+                buf
+            } else {
+                std::fs::read(&file)?
+            };
+            let secrets: Vec<Secret> = serde_json::from_slice(&open_bundle(&bundle, &identity)?)?;
+            let user = user_by_name(repo, &by).await?;
+            let cipher = config.secret_cipher()?;
+            let (mut created, mut replaced) = (0, 0);
+            for mut secret in secrets {
+                let current = repo.get_secret_by_id(&secret.id).await?;
+                if dry_run {
+                    println!(
+                        "  {} {} {}",
+                        if current.is_some() { "~" } else { "+" },
+                        secret.id,
+                        secret.name
+                    );
+                    continue;
+                }
+                let password = secret.take_password();
+                secret.set_password(
+                    password
+                        .map(|p| crate::common::encrypt_with_key(&cipher, &p))
+                        .transpose()?,
+                );
+                let private_key = secret.take_private_key();
+                secret.set_private_key(
+                    private_key
+                        .map(|k| crate::common::encrypt_with_key(&cipher, &k))
+                        .transpose()?,
+                );
+                secret.updated_by = user.id;
+                match current {
+                    Some(c) => {
+                        secret.version = c.version;
+                        repo.update_secret(&secret).await?;
+                        replaced += 1;
+                    }
+                    None => {
+                        repo.create_secret(&secret).await?;
+                        created += 1;
+                    }
+                }
+            }
+            if dry_run {
+                eprintln!("Dry run: nothing was imported");
+                return Ok(());
+            }
+            eprintln!("Imported {} new and {} replaced secrets", created, replaced);
+        }
+    }
+    Ok(())
+}
+
+async fn trace(
+    repo: &dyn DatabaseRepository,
+    action: TraceAction,
+    dry_run: bool,
+) -> Result<(), Error> {
+    match action {
+        TraceAction::Start {
+            user,
+            ip,
+            duration,
+            by,
+        } => {
+            if let Some(name) = user.as_ref() {
+                user_by_name(repo, name).await?;
+            }
+            let author = user_by_name(repo, &by).await?;
+            let expires_at = Timestamp::now()
+                .checked_add(duration)
+                .expect("trace durations are capped");
+            let rule = TraceRule::new(user, ip, expires_at, author.id);
+            if dry_run {
+                eprintln!(
+                    "Dry run: would trace {} until {}",
+                    describe_trace_rule(&rule),
+                    expires_at
+                );
+                return Ok(());
+            }
+            repo.create_trace_rule(&rule).await?;
+            eprintln!(
+                "Tracing {} until {} ({})",
+                describe_trace_rule(&rule),
+                expires_at,
+                rule.id
+            );
+        }
+        TraceAction::List => {
+            let now = Timestamp::now();
+            for r in repo.list_trace_rules().await? {
+                println!(
+                    "{} {} until {} {}",
+                    r.id,
+                    describe_trace_rule(&r),
+                    r.expires_at,
+                    if r.is_expired(now) {
+                        "expired"
+                    } else {
+                        "active"
+                    }
+                );
+            }
+        }
+        TraceAction::Stop { id } => {
+            if dry_run {
+                eprintln!("Dry run: would delete trace rule {}", id);
+                return Ok(());
+            }
+            if !repo.delete_trace_rule(&id).await? {
+                return Err(DatabaseError::NotFound {
+                    table: "trace_rules",
+                    key: id.to_string(),
+                }
+                .into());
+            }
+            eprintln!("Deleted trace rule {}", id);
+        }
+    }
+    Ok(())
+}
+
+async fn maintenance(
+    repo: &dyn DatabaseRepository,
+    action: MaintenanceAction,
+    dry_run: bool,
+) -> Result<(), Error> {
+    match action {
+        MaintenanceAction::Add {
+            target,
+            tag,
+            start,
+            duration,
+            repeat,
+            reason,
+            by,
+        } => {
+            let target_id = match target.as_deref() {
+                Some(name) => Some(
+                    repo.get_target_by_name(name)
+                        .await?
+                        .ok_or_else(|| DatabaseError::NotFound {
+                            table: "targets",
+                            key: name.to_string(),
+                        })?
+                        .id,
+                ),
+                None => None,
+            };
+            let author = user_by_name(repo, &by).await?;
+            let starts_at = Timestamp(start);
+            let ends_at = starts_at
+                .checked_add(duration)
+                .expect("maintenance durations are capped");
+            let window =
+                MaintenanceWindow::new(target_id, tag, starts_at, ends_at, reason, author.id)
+                    .with_repeat(repeat.as_str());
+            window.validate().map_err(DatabaseError::from)?;
+            let scope = target.unwrap_or_else(|| window.print_scope());
+            if dry_run {
+                eprintln!(
+                    "Dry run: would keep non-admins off {} from {} to {} ({})",
+                    scope, starts_at, ends_at, window.repeat
+                );
+                return Ok(());
+            }
+            repo.create_maintenance_window(&window).await?;
+            eprintln!(
+                "Keeping non-admins off {} from {} to {} ({}) ({})",
+                scope, starts_at, ends_at, window.repeat, window.id
+            );
+        }
+        MaintenanceAction::List => {
+            let now = Timestamp::now();
+            for w in repo.list_maintenance_windows().await? {
+                let scope = match w.target_id {
+                    Some(id) => match repo.get_target_by_id(&id, false).await? {
+                        Some(t) => format!("target {}", t.name),
+                        None => w.print_scope(),
+                    },
+                    None => w.print_scope(),
+                };
+                println!(
+                    "{} {} from {} to {} {} {} {}",
+                    w.id,
+                    scope,
+                    w.starts_at,
+                    w.ends_at,
+                    w.repeat,
+                    match w.active_until(now) {
+                        Some(until) => format!("active until {}", until),
+                        None => "inactive".to_string(),
+                    },
+                    w.reason
+                );
+            }
+        }
+        MaintenanceAction::Remove { id } => {
+            if dry_run {
+                eprintln!("Dry run: would delete maintenance window {}", id);
+                return Ok(());
+            }
+            if !repo.delete_maintenance_window(&id).await? {
+                return Err(DatabaseError::NotFound {
+                    table: "maintenance_windows",
+                    key: id.to_string(),
+                }
+                .into());
+            }
+            eprintln!("Deleted maintenance window {}", id);
+        }
+    }
+    Ok(())
+}
+
+async fn enforce(
+    db: &DatabaseService,
+    username: &str,
+    target: &str,
+    action: PolicyAction,
+    ext: ExtendPolicyReq,
+) -> Result<(), Error> {
+    let repo = db.repository();
+    let user = user_by_name(repo, username).await?;
+    let obj = object_by_name(repo, target).await?;
+    let act = casbin_name_id(repo, action.casbin_name()).await?;
+    let names = db.rule_labels().await?;
+    let label = |id: &Uuid| names.label(id);
+
+    // Deny rules first, they override the grants
+    let mut policies = repo.list_casbin_rules_by_ptype("p_deny").await?;
+    policies.extend(repo.list_casbin_rules_by_ptype("p").await?);
+    let role_manager = crate::server::bastion_server::load_role_manager(repo).await?;
+    let checks = role_manager.explain(policies, user.id, obj, act, &ext)?;
+    let active = user.is_active && repo.check_object_active(&obj).await?;
+    let decision = checks
+        .iter()
+        .position(|(_, c)| *c == PolicyCheck::Denied)
+        .or_else(|| checks.iter().position(|(_, c)| *c == PolicyCheck::Granted));
+    let granted = decision.is_some_and(|i| checks[i].1 == PolicyCheck::Granted);
+
+    println!(
+        "{} -> {} ({}) at {}{}",
+        user.username,
+        label(&obj),
+        label(&act),
+        ext.now.format("%Y-%m-%d %H:%M:%S UTC"),
+        ext.ip.map(|ip| format!(" from {}", ip)).unwrap_or_default()
+    );
+    for (i, (pol, check)) in checks.iter().enumerate() {
+        println!(
+            "{} {} {} ext=\"{}\": {}",
+            if Some(i) == decision { "=>" } else { "  " },
+            pol.id,
+            names.rule(pol),
+            pol.v3,
+            check
+        );
+    }
+    if checks.is_empty() {
+        println!("  no policy applies to {} or their roles", user.username);
+    }
+    if !active {
+        println!("  the user or the target is inactive");
+    }
+    if !granted || !active {
+        return Err(ServerError::PolicyDenied {
+            user: user.username,
+            target: target.to_string(),
+            action: label(&act),
+        }
+        .into());
+    }
+    eprintln!("Allowed");
+    Ok(())
+}
+
+/// The id of a target binding or internal object written as in `enforce`
+async fn object_by_name(repo: &dyn DatabaseRepository, name: &str) -> Result<Uuid, Error> {
+    use crate::database::common::*;
+
+    if let Some(internal) = name.strip_prefix(':') {
+        let object = match internal {
+            "login" => OBJ_LOGIN,
+            "admin" => OBJ_ADMIN,
+            "player" => OBJ_PLAYER,
+            _ => internal,
+        };
+        return casbin_name_id(repo, object).await;
+    }
+    let (account, target_name) = match name.rsplit_once('@') {
+        Some((account, target)) => (Some(account), target),
+        None => (None, name),
+    };
+    let not_found = || DatabaseError::NotFound {
+        table: "target_secrets",
+        key: name.to_string(),
+    };
+    let target = repo
+        .get_target_by_name(target_name)
+        .await?
+        .ok_or_else(not_found)?;
+    let mut bindings = Vec::new();
+    for ts in repo.list_target_secrets(false).await? {
+        if ts.target_id != target.id {
+            continue;
+        }
+        let secret = repo.get_secret_by_id(&ts.secret_id).await?;
+        if account.is_none_or(|a| secret.is_some_and(|s| s.user == a)) {
+            bindings.push(ts.id);
+        }
+    }
+    match bindings.as_slice() {
+        [id] => Ok(*id),
+        _ => Err(not_found().into()),
+    }
+}
+
+async fn casbin_name_id(repo: &dyn DatabaseRepository, name: &str) -> Result<Uuid, Error> {
+    repo.get_casbin_name_by_name(name)
+        .await?
+        .map(|n| n.id)
+        .ok_or_else(|| {
+            DatabaseError::NotFound {
+                table: "casbin_names",
+                key: name.to_string(),
+            }
+            .into()
+        })
+}
+
+/// `2025-01-31 09:00:00 +0800` or RFC 3339
+fn parse_time(s: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S %z")
+        .or_else(|_| DateTime::parse_from_rfc3339(s))
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|e| e.to_string())
+}
+
+/// Traces grow with every packet, a forgotten rule must not run for long
+const MAX_TRACE_DURATION: std::time::Duration = std::time::Duration::from_secs(24 * 3600);
+
+fn parse_trace_duration(s: &str) -> Result<std::time::Duration, String> {
+    let d = humantime::parse_duration(s).map_err(|e| e.to_string())?;
+    if d > MAX_TRACE_DURATION {
+        return Err(format!(
+            "at most {} is allowed",
+            humantime::format_duration(MAX_TRACE_DURATION)
+        ));
+    }
+    Ok(d)
+}
+
+const MAX_MAINTENANCE_DURATION: std::time::Duration =
+    std::time::Duration::from_secs(366 * 24 * 3600);
+
+fn parse_maintenance_duration(s: &str) -> Result<std::time::Duration, String> {
+    let d = humantime::parse_duration(s).map_err(|e| e.to_string())?;
+    if d > MAX_MAINTENANCE_DURATION {
+        return Err(format!(
+            "at most {} is allowed",
+            humantime::format_duration(MAX_MAINTENANCE_DURATION)
+        ));
+    }
+    Ok(d)
+}
+
+fn describe_trace_rule(rule: &TraceRule) -> String {
+    match (rule.username.as_deref(), rule.client_ip.as_deref()) {
+        (Some(u), Some(ip)) => format!("user {} from {}", u, ip),
+        (Some(u), None) => format!("user {}", u),
+        (None, Some(ip)) => format!("connections from {}", ip),
+        (None, None) => "every connection".to_string(),
+    }
+}
+
+/// Encrypts `plain` to age `recipients`, armored so the bundle can be pasted
+fn seal_bundle(plain: &[u8], recipients: &[String]) -> Result<Vec<u8>, Error> {
+    use age::armor::{ArmoredWriter, Format};
+
+    let recipients = recipients
+        .iter()
+        .map(|r| {
+            r.parse::<age::x25519::Recipient>()
+                .map_err(|reason| ServerError::InvalidRecipient {
+                    recipient: r.clone(),
+                    reason: reason.to_string(),
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let encryptor =
+        age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))
+            .map_err(|source| ServerError::BundleEncrypt { source })?;
+    let mut out = Vec::new();
+    let armored = ArmoredWriter::wrap_output(&mut out, Format::AsciiArmor)?;
+    let mut writer = encryptor.wrap_output(armored)?;
+    writer.write_all(plain)?;
+    writer.finish()?.finish()?;
+    Ok(out)
+}
+
+fn open_bundle(bundle: &[u8], identity_file: &str) -> Result<Vec<u8>, Error> {
+    use age::armor::ArmoredReader;
+
+    let identities = age::IdentityFile::from_file(identity_file.to_string())?
+        .into_identities()
+        .map_err(|source| ServerError::BundleDecrypt { source })?;
+    let decryptor = age::Decryptor::new(ArmoredReader::new(bundle))
+        .map_err(|source| ServerError::BundleDecrypt { source })?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|i| i.as_ref() as &dyn age::Identity))
+        .map_err(|source| ServerError::BundleDecrypt { source })?;
+    let mut plain = Vec::new();
+    reader.read_to_end(&mut plain)?;
+    Ok(plain)
+}
+
+async fn secret_by_name(repo: &dyn DatabaseRepository, name: &str) -> Result<Secret, Error> {
+    repo.list_secrets(false)
+        .await?
+        .into_iter()
+        .find(|s| s.name == name)
+        .ok_or_else(|| {
+            DatabaseError::NotFound {
+                table: "secrets",
+                key: name.to_string(),
+            }
+            .into()
+        })
+}
+
+async fn user_by_name(repo: &dyn DatabaseRepository, name: &str) -> Result<User, Error> {
+    repo.get_user_by_username(name, false)
+        .await?
+        .ok_or_else(|| {
+            DatabaseError::NotFound {
+                table: "users",
+                key: name.to_string(),
+            }
+            .into()
+        })
+}
+
+fn format_time(ts: Option<Timestamp>) -> String {
+    ts.map(|t| t.to_string()).unwrap_or_else(|| "-".to_string())
+}
+
+fn config_action(config: &Config, action: ConfigAction) -> Result<(), Error> {
+    use crate::config::schema;
+
+    match action {
+        ConfigAction::Schema => {
+            for entry in schema::schema()? {
+                println!("# {}", entry.doc);
+                match entry.default {
+                    Some(default) => println!("{} = {}\n", entry.key, default),
+                    None => println!("# {} =\n", entry.key),
+                }
+            }
+        }
+        ConfigAction::Diff => {
+            let changes = schema::diff(config)?;
+            let unset = || "(unset)".to_string();
+            for c in &changes {
+                println!(
+                    "{}: {} -> {}",
+                    c.key,
+                    c.default.clone().unwrap_or_else(unset),
+                    c.current.clone().unwrap_or_else(unset)
+                );
+            }
+            eprintln!("{} key(s) differ from the defaults", changes.len());
+        }
+    }
+    Ok(())
+}
+
+fn rotate_hostkey(
+    config: &Config,
+    key: Option<String>,
+    algorithm: Option<HostKeyAlgorithm>,
+    grace: std::time::Duration,
+    dry_run: bool,
+) -> Result<(), Error> {
+    use crate::server::host_keys;
+
+    let key = key.unwrap_or_else(|| config.server_key.clone());
+    if key != config.server_key && !config.host_keys.contains(&key) {
+        return Err(ConfigError::UnknownHostKey { path: key }.into());
+    }
+    if dry_run {
+        eprintln!(
+            "Dry run: would retire {} for {} and write a new key in its place",
+            key,
+            humantime::format_duration(grace)
+        );
+        return Ok(());
+    }
+    let (retired, public_key) = host_keys::rotate(
+        std::path::Path::new(&key),
+        algorithm.map(HostKeyAlgorithm::algorithm),
+        grace,
+        Utc::now(),
+    )?;
+    match retired {
+        Some(path) => eprintln!(
+            "Old key moved to {}, served for {} more",
+            path.display(),
+            humantime::format_duration(grace)
+        ),
+        None => eprintln!("No key at {}, nothing retired", key),
+    }
+    eprintln!(
+        "New host key {}, served after a restart:",
+        public_key.fingerprint(HashAlg::Sha256)
+    );
+    println!("{}", public_key.to_openssh()?);
+    Ok(())
+}
+
+async fn keyscan(
+    repo: &dyn DatabaseRepository,
+    config: &Config,
+    name: &str,
+    port: Option<u16>,
+    save: bool,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let mut target = None;
+    let (hostname, port) = match port {
+        Some(port) => (name.to_string(), port),
+        None => {
+            let found = repo.get_target_by_name(name).await?;
+            let t = found.ok_or_else(|| DatabaseError::NotFound {
+                table: "targets",
+                key: name.to_string(),
+            })?;
+            let address = (t.addresses().next().unwrap_or_default().to_string(), t.port);
+            target = Some(t);
+            address
+        }
+    };
+    let keys = crate::server::keyscan::scan(&hostname, port, &config.client_id).await?;
+    for key in &keys {
+        eprintln!("{}", key.fingerprint(HashAlg::Sha256));
+        println!("{}", key.to_openssh()?);
+    }
+
+    let (Some(mut target), true) = (target, save) else {
+        return Ok(());
+    };
+    let fingerprint = keys[0].fingerprint(HashAlg::Sha256);
+    if dry_run {
+        eprintln!(
+            "Dry run: would store {} as the host key of {}",
+            fingerprint, target.name
+        );
+        return Ok(());
+    }
+    target.server_public_key = keys[0].to_openssh()?;
+    target.pending_public_key = None;
+    repo.update_target(&target).await?;
+    eprintln!("Stored {} as the host key of {}", fingerprint, target.name);
+    Ok(())
+}
+
+fn target_ca(config: &Config, dry_run: bool) -> Result<(), Error> {
+    use crate::server::target_ca::TargetCa;
+
+    let Some(path) = config.target_ca_key.as_ref() else {
+        return Err(ConfigError::TargetCaKeyUnset.into());
+    };
+    let public_key = if std::path::Path::new(path).exists() {
+        TargetCa::load(path, config.target_cert_validity)?
+            .public_key()
+            .clone()
+    } else if dry_run {
+        eprintln!("Dry run: would write a new CA key to {}", path);
+        return Ok(());
+    } else {
+        eprintln!("Wrote a new CA key to {}", path);
+        TargetCa::generate(path)?
+    };
+    println!("{}", public_key.to_openssh()?);
+    Ok(())
+}
+
+fn redact(
+    config: &Config,
+    input: &str,
+    output: &str,
+    mut patterns: Vec<String>,
+    keep_prompts: bool,
+    dry_run: bool,
+) -> Result<(), Error> {
+    use crate::asciinema::redact::Redactor;
+    use std::io::{BufReader, BufWriter};
+
+    patterns.extend(config.redact_patterns.iter().cloned());
+    let redactor = Redactor::new(&patterns)?.with_prompts(!keep_prompts);
+    let reader = BufReader::new(std::fs::File::open(input)?);
+    let stats = if dry_run {
+        redactor.redact(reader, std::io::sink())?
+    } else if output == "-" {
+        redactor.redact(reader, std::io::stdout().lock())?
+    } else {
+        redactor.redact(reader, BufWriter::new(std::fs::File::create(output)?))?
+    };
+    eprintln!(
+        "{}Redacted {} pattern match(es) and {} password prompt(s) in {} events",
+        if dry_run { "Dry run: " } else { "" },
+        stats.matches,
+        stats.prompt_inputs,
+        stats.events
+    );
+    Ok(())
+}
+
+fn breach_filter(input: &str, output: &str, fp_rate: f64, dry_run: bool) -> Result<(), Error> {
+    use crate::server::breach::BreachFilter;
+    use std::io::{BufRead, BufReader, BufWriter};
+
+    if fp_rate.is_nan() || fp_rate <= 0.0 || fp_rate >= 1.0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("--fp-rate {} must be between 0 and 1", fp_rate),
+        )
+        .into());
+    }
+    // A first pass sizes the filter
+    let lines = BufReader::new(std::fs::File::open(input)?).lines().count() as u64;
+    let (filter, skipped) =
+        BreachFilter::from_hibp(BufReader::new(std::fs::File::open(input)?), lines, fp_rate)?;
+    if !dry_run {
+        filter.save(BufWriter::new(std::fs::File::create(output)?))?;
+    }
+    eprintln!(
+        "{}Wrote {} hashes into a {} byte filter, skipped {} line(s)",
+        if dry_run { "Dry run: " } else { "" },
+        lines - skipped,
+        filter.size_bytes(),
+        skipped
+    );
+    Ok(())
+}
+
+fn print_import_plan(data: &Dataset) {
+    println!("users: {}", data.users.len());
+    for u in &data.users {
+        println!("  + {} {}", u.id, u.username);
+    }
+    println!("user_groups: {}", data.user_groups.len());
+    for g in &data.user_groups {
+        println!("  + {} {}", g.id, g.name);
+    }
+    println!("user_group_members: {}", data.user_group_members.len());
+    for m in &data.user_group_members {
+        println!("  + {} {} -> {}", m.id, m.user_id, m.group_id);
+    }
+    println!("casbin_names: {}", data.casbin_names.len());
+    for n in &data.casbin_names {
+        println!("  + {} {} {}", n.id, n.ptype, n.name);
+    }
+    println!("targets: {}", data.targets.len());
+    for t in &data.targets {
+        println!("  + {} {} ({}:{})", t.id, t.name, t.hostname, t.port);
+    }
+    println!("target_tags: {}", data.target_tags.len());
+    for t in &data.target_tags {
+        println!("  + {} {} {}", t.id, t.target_id, t.label());
+    }
+    println!("secrets: {}", data.secrets.len());
+    for s in &data.secrets {
+        println!("  + {} {} ({})", s.id, s.name, s.user);
+    }
+    println!("target_secrets: {}", data.target_secrets.len());
+    for ts in &data.target_secrets {
+        println!("  + {} {} -> {}", ts.id, ts.target_id, ts.secret_id);
+    }
+    println!("casbin_rule: {}", data.casbin_rule.len());
+    for r in &data.casbin_rule {
+        println!("  + {} {} {} {} {}", r.id, r.ptype, r.v0, r.v1, r.v2);
+    }
+    eprintln!("Dry run: nothing was imported");
+}
@@ -1,13 +1,20 @@
+use crate::error::Error;
+use crate::server::error::ServerError;
+use aes_gcm::aead::{Aead, OsRng, rand_core::RngCore};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::{Engine as _, engine::general_purpose};
+use hmac::{Hmac, KeyInit, Mac};
 use lazy_static::lazy_static;
 use rand::{prelude::*, seq::SliceRandom};
 use regex::Regex;
+use sha2::Sha256;
 
 lazy_static! {
     pub static ref EMAIL_REGEX: Regex =
         Regex::new(r"^\w+([-+.']\w+)*@\w+([-.]\w+)*\.\w+([-.]\w+)*$").unwrap();
 }
 
-pub type EncryptPlainText = Box<dyn Fn(&str) -> Result<String, crate::error::Error> + Send + Sync>;
+pub type EncryptPlainText = Box<dyn Fn(&str) -> Result<String, Error> + Send + Sync>;
 
 pub fn gen_password(len: usize) -> String {
     let upper = b'A'..=b'Z';
@@ -61,3 +68,55 @@ pub fn shorten_ssh_pubkey(input: &str) -> String {
         None => format!("{key_type} {head}...{tail}"),
     }
 }
+
+/// HMAC-SHA256 of `data`
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    Hmac::<Sha256>::new_from_slice(key)
+        .expect("HMAC takes keys of any size")
+        .chain_update(data)
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+const NONCE_LEN: usize = 12;
+
+/// Encrypts `text` the way secrets are stored: base64 of nonce || ciphertext
+pub fn encrypt_with_key(key: &Aes256Gcm, text: &str) -> Result<String, Error> {
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce_bytes);
+    let nonce = Nonce::from_slice(&nonce_bytes);
+
+    let ciphertext = key.encrypt(nonce, text.as_bytes()).map_err(|e| {
+        Error::Server(ServerError::EncryptionFailed {
+            reason: e.to_string(),
+        })
+    })?;
+
+    let mut blob = Vec::with_capacity(nonce_bytes.len() + ciphertext.len());
+    blob.extend_from_slice(&nonce_bytes);
+    blob.extend_from_slice(&ciphertext);
+
+    Ok(general_purpose::STANDARD.encode(blob))
+}
+
+/// Reverse of `encrypt_with_key`
+pub fn decrypt_with_key(key: &Aes256Gcm, text: &str) -> Result<String, Error> {
+    let blob = general_purpose::STANDARD
+        .decode(text)
+        .map_err(|e| Error::Server(ServerError::Base64Decode { source: e }))?;
+    if blob.len() < NONCE_LEN {
+        return Err(Error::Server(ServerError::DecryptionFailed {
+            reason: "ciphertext is shorter than a nonce".to_string(),
+        }));
+    }
+    let (nonce, ciphertext) = blob.split_at(NONCE_LEN);
+    let nonce = Nonce::from_slice(nonce);
+
+    match key.decrypt(nonce, ciphertext) {
+        Ok(plain) => Ok(String::from_utf8_lossy(&plain).to_string()),
+        Err(e) => Err(Error::Server(ServerError::DecryptionFailed {
+            reason: e.to_string(),
+        })),
+    }
+}
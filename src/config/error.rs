@@ -1,3 +1,4 @@
+use crate::error::ErrorCode;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -17,18 +18,179 @@ pub enum ConfigError {
         source: toml::ser::Error,
     },
 
-    #[error("Failed to resolve address '{addr}': {reason}")]
-    AddressResolutionFailed { addr: String, reason: String },
+    #[error("Failed to resolve address '{addr}': {source}")]
+    AddressResolutionFailed {
+        addr: String,
+        #[source]
+        source: std::io::Error,
+    },
 
     #[error("No address resolved for '{addr}'")]
     NoAddressResolved { addr: String },
 
-    #[error("Invalid listen address '{addr}': {reason}")]
-    InvalidListenAddress { addr: String, reason: String },
+    #[error("Invalid listen address '{addr}': {source}")]
+    InvalidListenAddress {
+        addr: String,
+        #[source]
+        source: Box<crate::error::Error>,
+    },
 
     #[error("max_auth_attempts must be greater than 0")]
     MaxAuthAttemptsZero,
 
+    #[error("Invalid default terminal size {cols}x{rows}")]
+    InvalidTermSize { cols: u32, rows: u32 },
+
+    #[error("limits.{name} must be greater than 0")]
+    LimitZero { name: &'static str },
+
+    #[error("limits.max_packet_size {packet} exceeds limits.window_size {window}")]
+    PacketExceedsWindow { packet: u32, window: u32 },
+
+    #[error("rate_limit.ban_duration must be greater than 0")]
+    BanDurationZero,
+
+    #[error("rate_limit.ban_duration {ban:?} exceeds rate_limit.max_ban_duration {max:?}")]
+    BanDurationExceedsMax {
+        ban: std::time::Duration,
+        max: std::time::Duration,
+    },
+
+    #[error("rate_limit.whitelist entry '{cidr}' is not an address or CIDR: {source}")]
+    InvalidWhitelist {
+        cidr: String,
+        #[source]
+        source: ipnetwork::IpNetworkError,
+    },
+
+    #[error("trusted_proxies entry '{cidr}' is not an address or CIDR: {source}")]
+    InvalidTrustedProxy {
+        cidr: String,
+        #[source]
+        source: ipnetwork::IpNetworkError,
+    },
+
+    #[error("record_files.file_mode {mode:o} is not a permission mode, e.g. 0o640")]
+    InvalidFileMode { mode: u32 },
+
+    #[error("record_files.group '{group}' is not a known group")]
+    UnknownGroup { group: String },
+
+    #[error("record_sink.endpoint '{url}' is not an http or https URL")]
+    RecordSinkEndpoint { url: String },
+
+    #[error("record_sink.bucket is required with type = \"s3\"")]
+    RecordSinkBucketMissing,
+
+    #[error(
+        "record_sink.access_key and secret_key are required with type = \"s3\", or AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY"
+    )]
+    RecordSinkCredentialsMissing,
+
+    #[error("record_sink.part_size {size} is below the 5 MiB S3 requires")]
+    RecordSinkPartSize { size: usize },
+
+    #[error("record_retention.archive_path needs record_sink.type = \"file\"")]
+    ArchiveNeedsFiles,
+
+    #[error("trusted_user_ca_keys[{index}] is not a valid public key: {source}")]
+    InvalidUserCaKey {
+        index: usize,
+        #[source]
+        source: russh::keys::ssh_key::Error,
+    },
+
+    #[error("Failed to load breached_passwords filter '{path}': {source}")]
+    BreachFilter {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to open geoip_database '{path}': {source}")]
+    GeoIpDatabase {
+        path: String,
+        #[source]
+        source: maxminddb::MaxMindDBError,
+    },
+
+    #[error("push_mfa.url '{url}' is not an http or https URL")]
+    PushMfaUrl { url: String },
+
+    #[error("push_mfa.secret is required to sign the requests to push_mfa.url")]
+    PushMfaSecretMissing,
+
+    #[error("push_mfa.timeout must be greater than 0")]
+    PushMfaTimeoutZero,
+
+    #[error("opa.url '{url}' is not an http or https URL")]
+    OpaUrl { url: String },
+
+    #[error("opa.timeout must be greater than 0")]
+    OpaTimeoutZero,
+
+    #[error("{name} must be greater than 0")]
+    DurationZero { name: &'static str },
+
+    #[error("target_connect.backoff {backoff:?} exceeds target_connect.max_backoff {max:?}")]
+    BackoffExceedsMax {
+        backoff: std::time::Duration,
+        max: std::time::Duration,
+    },
+
+    #[error("algorithms.{side}.{kind}: '{name}' is not an algorithm russh knows")]
+    UnknownAlgorithm {
+        side: &'static str,
+        kind: &'static str,
+        name: String,
+    },
+
+    #[error("command_filters '{name}': pattern '{pattern}' doesn't compile: {source}")]
+    CommandFilterPattern {
+        name: String,
+        pattern: String,
+        #[source]
+        source: regex::Error,
+    },
+
+    #[error("command_filters name '{name}' is empty, has a comma or is used twice")]
+    CommandFilterName { name: String },
+
+    #[error("exec_log.redact_patterns: '{pattern}' doesn't compile: {source}")]
+    ExecLogPattern {
+        pattern: String,
+        #[source]
+        source: regex::Error,
+    },
+
+    #[error("subsystems entry '{name}' is empty, repeated, sftp or not a plain name")]
+    InvalidSubsystem { name: String },
+
+    #[error("env_allowlist entry '{pattern}' is empty or has a '*' before its end")]
+    InvalidEnvPattern { pattern: String },
+
+    #[error("Host key file '{path}' not found")]
+    HostKeyNotFound { path: String },
+
+    #[error("'{path}' is neither server_key nor listed in host_keys")]
+    UnknownHostKey { path: String },
+
+    #[error("target_ca_key '{path}' can't be read: {source}")]
+    TargetCaKey {
+        path: String,
+        #[source]
+        source: russh::keys::ssh_key::Error,
+    },
+
+    #[error("target_ca_key is not set")]
+    TargetCaKeyUnset,
+
+    #[error("auth_providers must list at least one provider")]
+    NoAuthProvider,
+
+    #[error("auth_providers token_url '{url}' is not an http or https URL")]
+    OidcTokenUrl { url: String },
+
     #[error("No secret token configured")]
     MissingSecretToken,
 
@@ -46,4 +208,12 @@ pub enum ConfigError {
 
     #[error(transparent)]
     Io(#[from] std::io::Error),
-}
\ No newline at end of file
+}
+impl ConfigError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ConfigError::Io(_) => ErrorCode::Internal,
+            _ => ErrorCode::InvalidInput,
+        }
+    }
+}
@@ -1,11 +1,20 @@
 pub mod error;
+pub mod schema;
 
 use crate::config::error::ConfigError;
 use crate::database::DatabaseConfig;
 use crate::error::Error;
+use crate::server::auth_provider::{AuthProviderConfig, default_auth_providers};
+use crate::server::command_filter::{CommandFilterConfig, CommandFilters};
+use crate::server::exec_log::{ExecLog, ExecLogConfig};
+use crate::server::zmodem::ZmodemPolicy;
 use aes_gcm::KeyInit;
 use base64::{Engine as _, engine::general_purpose};
+use ipnetwork::IpNetwork;
+use russh::keys::ssh_key::{Fingerprint, HashAlg, PublicKey};
+use russh::{Preferred, cipher, kex, mac};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::fs;
 use std::net::SocketAddr;
 use std::path::Path;
@@ -59,10 +68,30 @@ fn default_cache_idle_time() -> Duration {
     Duration::from_secs(1800)
 }
 
+fn default_policy_reload_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
 fn default_record_path() -> String {
     "./record".to_string()
 }
 
+fn default_password_history() -> usize {
+    5
+}
+
+fn default_target_cert_validity() -> Duration {
+    Duration::from_secs(5 * 60)
+}
+
+fn default_trace_path() -> String {
+    "./trace".to_string()
+}
+
+fn default_env_allowlist() -> Vec<String> {
+    vec!["LANG".to_string(), "LC_*".to_string()]
+}
+
 fn default_auth_rejection_time() -> Duration {
     Duration::from_millis(1000)
 }
@@ -79,6 +108,772 @@ fn default_max_user_attempts() -> u32 {
     100
 }
 
+fn default_term_cols() -> u32 {
+    80
+}
+
+fn default_term_rows() -> u32 {
+    24
+}
+
+/// Per-connection caps, so one misbehaving client can't exhaust the process
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Channels (shells, execs, tunnels) open at once on one connection
+    pub max_channels: usize,
+    /// Exec requests running at once on one connection, within
+    /// `max_channels`
+    pub max_execs: usize,
+    /// Direct-tcpip streams (`ssh -L`, `ssh -J`) open at once on one
+    /// connection, within `max_channels`
+    pub max_direct_tcpip: usize,
+    /// Bytes a peer may send on a channel before waiting for an
+    /// acknowledgement, on both the client and the target side
+    pub window_size: u32,
+    /// Largest packet accepted on a channel
+    pub max_packet_size: u32,
+    /// Messages queued per channel before the sender is paused. Together with
+    /// `max_packet_size` it bounds the memory held by one bridge.
+    pub channel_buffer_size: usize,
+    /// Bytes per second a target session may send from the client to the
+    /// target, 0 for no limit. A policy's `rate=` can only lower it.
+    pub upload_rate: u64,
+    /// Bytes per second from the target to the client, 0 for no limit
+    pub download_rate: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_channels: 10,
+            max_execs: 10,
+            max_direct_tcpip: 10,
+            window_size: 2 * 1024 * 1024,
+            max_packet_size: 32 * 1024,
+            channel_buffer_size: 100,
+            upload_rate: 0,
+            download_rate: 0,
+        }
+    }
+}
+
+impl LimitsConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let zero = [
+            ("max_channels", self.max_channels == 0),
+            ("max_execs", self.max_execs == 0),
+            ("max_direct_tcpip", self.max_direct_tcpip == 0),
+            ("window_size", self.window_size == 0),
+            ("max_packet_size", self.max_packet_size == 0),
+            ("channel_buffer_size", self.channel_buffer_size == 0),
+        ];
+        if let Some((name, _)) = zero.into_iter().find(|(_, z)| *z) {
+            return Err(ConfigError::LimitZero { name });
+        }
+        if self.max_packet_size > self.window_size {
+            return Err(ConfigError::PacketExceedsWindow {
+                packet: self.max_packet_size,
+                window: self.window_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for LimitsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "channels={} execs={} direct_tcpip={} window={} packet={} buffer={} upload_rate={} download_rate={}",
+            self.max_channels,
+            self.max_execs,
+            self.max_direct_tcpip,
+            self.window_size,
+            self.max_packet_size,
+            self.channel_buffer_size,
+            self.upload_rate,
+            self.download_rate
+        )
+    }
+}
+
+/// Retries and timeouts of target connections. A round tries the addresses
+/// of a target's `hostname` in order, the rounds after the first wait
+/// `backoff`, doubled each time up to `max_backoff`. An address not answering
+/// within the timeouts counts as failed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TargetConnectConfig {
+    /// Rounds after the first, 0 gives up once every address failed
+    pub retries: u32,
+    #[serde(with = "humantime_serde")]
+    pub backoff: Duration,
+    #[serde(with = "humantime_serde")]
+    pub max_backoff: Duration,
+    /// TCP connect, or the `direct-tcpip` channel of a via target
+    #[serde(with = "humantime_serde")]
+    pub connect_timeout: Duration,
+    /// SSH version and key exchange
+    #[serde(with = "humantime_serde")]
+    pub handshake_timeout: Duration,
+    /// Login with the secret
+    #[serde(with = "humantime_serde")]
+    pub auth_timeout: Duration,
+    /// Keepalives sent over a quiet target connection, 0s turns them off
+    #[serde(with = "humantime_serde")]
+    pub keepalive_interval: Duration,
+    /// Keepalives left unanswered in a row before the target counts as dead
+    /// and its connection, channels and pool entry go away
+    pub keepalive_max: usize,
+    /// How long a shell whose target connection was lost keeps trying to
+    /// log in again and reopen it, 0s closes it at once
+    #[serde(with = "humantime_serde")]
+    pub reconnect_window: Duration,
+}
+
+impl Default for TargetConnectConfig {
+    fn default() -> Self {
+        TargetConnectConfig {
+            retries: 0,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(10),
+            handshake_timeout: Duration::from_secs(10),
+            auth_timeout: Duration::from_secs(30),
+            keepalive_interval: Duration::from_secs(15),
+            keepalive_max: 3,
+            reconnect_window: Duration::ZERO,
+        }
+    }
+}
+
+impl TargetConnectConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.backoff.is_zero() {
+            return Err(ConfigError::DurationZero {
+                name: "target_connect.backoff",
+            });
+        }
+        if self.max_backoff < self.backoff {
+            return Err(ConfigError::BackoffExceedsMax {
+                backoff: self.backoff,
+                max: self.max_backoff,
+            });
+        }
+        for (name, timeout) in [
+            ("target_connect.connect_timeout", self.connect_timeout),
+            ("target_connect.handshake_timeout", self.handshake_timeout),
+            ("target_connect.auth_timeout", self.auth_timeout),
+        ] {
+            if timeout.is_zero() {
+                return Err(ConfigError::DurationZero { name });
+            }
+        }
+        Ok(())
+    }
+
+    /// `None` with keepalives off
+    pub fn keepalive(&self) -> Option<Duration> {
+        Some(self.keepalive_interval).filter(|i| !i.is_zero())
+    }
+
+    /// `None` with shells closed as soon as their target is lost
+    pub fn reconnect(&self) -> Option<Duration> {
+        Some(self.reconnect_window).filter(|w| !w.is_zero())
+    }
+
+    /// Settings for a target, its own timeout replaces all three
+    pub fn for_target(&self, timeout: Option<Duration>) -> Self {
+        match timeout {
+            Some(t) => TargetConnectConfig {
+                connect_timeout: t,
+                handshake_timeout: t,
+                auth_timeout: t,
+                ..self.clone()
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// Waits before each retry round
+    pub fn delays(&self) -> impl Iterator<Item = Duration> {
+        let max = self.max_backoff;
+        std::iter::successors(Some(self.backoff), move |d| Some((*d * 2).min(max)))
+            .take(self.retries as usize)
+    }
+}
+
+impl std::fmt::Display for TargetConnectConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "retries={} backoff={} max_backoff={} connect_timeout={} handshake_timeout={} auth_timeout={} keepalive_interval={} keepalive_max={} reconnect_window={}",
+            self.retries,
+            humantime::format_duration(self.backoff),
+            humantime::format_duration(self.max_backoff),
+            humantime::format_duration(self.connect_timeout),
+            humantime::format_duration(self.handshake_timeout),
+            humantime::format_duration(self.auth_timeout),
+            humantime::format_duration(self.keepalive_interval),
+            self.keepalive_max,
+            humantime::format_duration(self.reconnect_window)
+        )
+    }
+}
+
+/// Key exchange, cipher and MAC algorithms offered on one side, most
+/// preferred first. An empty list keeps russh's defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlgorithmSet {
+    pub kex: Vec<String>,
+    pub cipher: Vec<String>,
+    pub mac: Vec<String>,
+}
+
+impl AlgorithmSet {
+    /// `Preferred` offering these algorithms. The kex list keeps the
+    /// pseudo-algorithms announcing extension negotiation and strict kex.
+    pub fn preferred(&self, side: &'static str) -> Result<Preferred, ConfigError> {
+        let mut preferred = Preferred::default();
+        if !self.kex.is_empty() {
+            let mut kex: Vec<kex::Name> = names(&self.kex, side, "kex")?;
+            let signals: Vec<kex::Name> = preferred
+                .kex
+                .iter()
+                .filter(|n| {
+                    let n: &str = n.as_ref();
+                    n.starts_with("ext-info-") || n.starts_with("kex-strict-")
+                })
+                .filter(|n| !kex.contains(n))
+                .copied()
+                .collect();
+            kex.extend(signals);
+            preferred.kex = Cow::Owned(kex);
+        }
+        if !self.cipher.is_empty() {
+            preferred.cipher = Cow::Owned(names::<cipher::Name>(&self.cipher, side, "cipher")?);
+        }
+        if !self.mac.is_empty() {
+            preferred.mac = Cow::Owned(names::<mac::Name>(&self.mac, side, "mac")?);
+        }
+        Ok(preferred)
+    }
+}
+
+fn names<N>(list: &[String], side: &'static str, kind: &'static str) -> Result<Vec<N>, ConfigError>
+where
+    N: for<'a> TryFrom<&'a str>,
+{
+    list.iter()
+        .map(|name| {
+            N::try_from(name.as_str()).map_err(|_| ConfigError::UnknownAlgorithm {
+                side,
+                kind,
+                name: name.to_string(),
+            })
+        })
+        .collect()
+}
+
+impl std::fmt::Display for AlgorithmSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |l: &[String]| {
+            if l.is_empty() {
+                "default".to_string()
+            } else {
+                l.join(",")
+            }
+        };
+        write!(
+            f,
+            "kex={} cipher={} mac={}",
+            list(&self.kex),
+            list(&self.cipher),
+            list(&self.mac)
+        )
+    }
+}
+
+/// Algorithms of the connections clients make to the bastion (`server`) and
+/// of those it makes to targets (`client`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlgorithmsConfig {
+    pub server: AlgorithmSet,
+    pub client: AlgorithmSet,
+}
+
+impl AlgorithmsConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.server.preferred("server")?;
+        self.client.preferred("client")?;
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for AlgorithmsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "server=[{}] client=[{}]", self.server, self.client)
+    }
+}
+
+/// Token bucket refilled with `attempts_per_minute` authentication attempts
+/// per client IP. An IP that empties it is banned for `ban_duration`, doubled
+/// on every ban in a row up to `max_ban_duration`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Attempts an IP may make per minute, also the burst it may start with.
+    /// 0 turns the limiter off.
+    pub attempts_per_minute: u32,
+    #[serde(with = "humantime_serde")]
+    pub ban_duration: Duration,
+    #[serde(with = "humantime_serde")]
+    pub max_ban_duration: Duration,
+    /// Addresses or CIDRs never rate limited, such as monitoring hosts
+    pub whitelist: Vec<String>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            attempts_per_minute: 30,
+            ban_duration: Duration::from_secs(60),
+            max_ban_duration: Duration::from_secs(3600),
+            whitelist: Vec::new(),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// Parsed `whitelist`
+    pub fn whitelist_networks(&self) -> Result<Vec<IpNetwork>, ConfigError> {
+        self.whitelist
+            .iter()
+            .map(|cidr| {
+                cidr.trim()
+                    .parse::<IpNetwork>()
+                    .map_err(|source| ConfigError::InvalidWhitelist {
+                        cidr: cidr.clone(),
+                        source,
+                    })
+            })
+            .collect()
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.attempts_per_minute > 0 && self.ban_duration.is_zero() {
+            return Err(ConfigError::BanDurationZero);
+        }
+        if self.max_ban_duration < self.ban_duration {
+            return Err(ConfigError::BanDurationExceedsMax {
+                ban: self.ban_duration,
+                max: self.max_ban_duration,
+            });
+        }
+        self.whitelist_networks().map(|_| ())
+    }
+}
+
+impl std::fmt::Display for RateLimitConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "attempts/min={} ban={} max_ban={} whitelist={}",
+            self.attempts_per_minute,
+            humantime::format_duration(self.ban_duration),
+            humantime::format_duration(self.max_ban_duration),
+            self.whitelist.len()
+        )
+    }
+}
+
+/// Layout and rights of new recording files, for deployments where OS
+/// permissions decide which auditors may read which sessions
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordFilesConfig {
+    /// Write into `<target id>/<secret id>/` below `record_path`
+    pub per_target: bool,
+    /// Mode of recording files, e.g. `0o640`. Directories get the same
+    /// bits plus search where reading is allowed.
+    pub file_mode: Option<u32>,
+    /// Group name or gid given to recording files and their directories
+    pub group: Option<String>,
+}
+
+impl RecordFilesConfig {
+    /// `group` as a gid, names are looked up in /etc/group
+    pub fn gid(&self) -> Result<Option<u32>, ConfigError> {
+        let Some(group) = self.group.as_deref() else {
+            return Ok(None);
+        };
+        if let Ok(gid) = group.parse() {
+            return Ok(Some(gid));
+        }
+        std::fs::read_to_string("/etc/group")?
+            .lines()
+            .find_map(|line| {
+                let mut fields = line.split(':');
+                (fields.next() == Some(group))
+                    .then(|| fields.nth(1).and_then(|gid| gid.parse().ok()))
+                    .flatten()
+            })
+            .map(Some)
+            .ok_or_else(|| ConfigError::UnknownGroup {
+                group: group.to_string(),
+            })
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(mode) = self.file_mode.filter(|m| *m > 0o777) {
+            return Err(ConfigError::InvalidFileMode { mode });
+        }
+        self.gid().map(|_| ())
+    }
+}
+
+impl std::fmt::Display for RecordFilesConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "per_target={} mode={} group={}",
+            self.per_target,
+            self.file_mode
+                .map_or("default".to_string(), |m| format!("{:o}", m)),
+            self.group.as_deref().unwrap_or("default")
+        )
+    }
+}
+
+/// Where recordings go
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordSinkKind {
+    /// Files below `record_path`
+    #[default]
+    File,
+    /// Objects in an S3 compatible bucket
+    S3,
+}
+
+impl std::fmt::Display for RecordSinkKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordSinkKind::File => write!(f, "file"),
+            RecordSinkKind::S3 => write!(f, "s3"),
+        }
+    }
+}
+
+/// Smallest part S3 takes in a multipart upload, the last one aside
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// Storage of recordings. With `type = "s3"` they are uploaded to
+/// `<bucket>/<prefix><recording path>` instead of written below
+/// `record_path`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordSinkConfig {
+    #[serde(rename = "type")]
+    pub kind: RecordSinkKind,
+    /// e.g. `https://s3.eu-central-1.amazonaws.com` or a MinIO server,
+    /// buckets are addressed in the path
+    pub endpoint: Option<String>,
+    pub region: String,
+    pub bucket: Option<String>,
+    /// Put before the path of a recording to make its key
+    pub prefix: String,
+    /// Unset falls back to `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+    /// Bytes of a recording held in memory before they are uploaded as a
+    /// part, at least 5 MiB
+    pub part_size: usize,
+    /// Further attempts of a failed request
+    pub retries: u32,
+    /// Limit of each request
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+impl Default for RecordSinkConfig {
+    fn default() -> Self {
+        RecordSinkConfig {
+            kind: RecordSinkKind::File,
+            endpoint: None,
+            region: "us-east-1".to_string(),
+            bucket: None,
+            prefix: String::new(),
+            access_key: None,
+            secret_key: None,
+            part_size: 8 * 1024 * 1024,
+            retries: 3,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RecordSinkConfig {
+    /// Access and secret key, from the environment when not configured
+    pub fn credentials(&self) -> Option<(String, String)> {
+        let access_key = self
+            .access_key
+            .clone()
+            .or_else(|| std::env::var("AWS_ACCESS_KEY_ID").ok())?;
+        let secret_key = self
+            .secret_key
+            .clone()
+            .or_else(|| std::env::var("AWS_SECRET_ACCESS_KEY").ok())?;
+        Some((access_key, secret_key))
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.kind == RecordSinkKind::File {
+            return Ok(());
+        }
+        let endpoint = self.endpoint.as_deref().unwrap_or_default();
+        if !endpoint.starts_with("https://") && !endpoint.starts_with("http://") {
+            return Err(ConfigError::RecordSinkEndpoint {
+                url: endpoint.to_string(),
+            });
+        }
+        if self.bucket.as_deref().is_none_or(str::is_empty) {
+            return Err(ConfigError::RecordSinkBucketMissing);
+        }
+        if self.credentials().is_none() {
+            return Err(ConfigError::RecordSinkCredentialsMissing);
+        }
+        if self.part_size < MIN_PART_SIZE {
+            return Err(ConfigError::RecordSinkPartSize {
+                size: self.part_size,
+            });
+        }
+        if self.timeout.is_zero() {
+            return Err(ConfigError::DurationZero {
+                name: "record_sink.timeout",
+            });
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for RecordSinkConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            RecordSinkKind::File => write!(f, "type=file"),
+            RecordSinkKind::S3 => write!(
+                f,
+                "type=s3 endpoint={} bucket={} prefix={} part_size={} retries={} timeout={}",
+                self.endpoint.as_deref().unwrap_or("None"),
+                self.bucket.as_deref().unwrap_or("None"),
+                self.prefix,
+                self.part_size,
+                self.retries,
+                humantime::format_duration(self.timeout)
+            ),
+        }
+    }
+}
+
+/// Limits of the recordings of one target, in place of the global ones
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetRetentionConfig {
+    /// Target name
+    pub target: String,
+    #[serde(default, with = "humantime_serde")]
+    pub max_age: Option<Duration>,
+    #[serde(default)]
+    pub max_size: Option<u64>,
+}
+
+/// Purging of old recordings, off until a limit is set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordRetentionConfig {
+    /// Recordings ended longer ago are purged
+    #[serde(with = "humantime_serde")]
+    pub max_age: Option<Duration>,
+    /// Bytes the recordings may take, the oldest are purged beyond it
+    pub max_size: Option<u64>,
+    /// Purged recordings are moved below it rather than deleted, files only
+    pub archive_path: Option<String>,
+    /// How often recordings are checked
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    pub targets: Vec<TargetRetentionConfig>,
+}
+
+impl Default for RecordRetentionConfig {
+    fn default() -> Self {
+        RecordRetentionConfig {
+            max_age: None,
+            max_size: None,
+            archive_path: None,
+            interval: Duration::from_secs(3600),
+            targets: Vec::new(),
+        }
+    }
+}
+
+impl RecordRetentionConfig {
+    fn validate(&self, sink: RecordSinkKind) -> Result<(), ConfigError> {
+        if self.interval.is_zero() {
+            return Err(ConfigError::DurationZero {
+                name: "record_retention.interval",
+            });
+        }
+        if self.archive_path.is_some() && sink != RecordSinkKind::File {
+            return Err(ConfigError::ArchiveNeedsFiles);
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for RecordRetentionConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let max_age = self.max_age.map(humantime::format_duration);
+        write!(
+            f,
+            "max_age={} max_size={} archive_path={} interval={} targets={}",
+            max_age.map_or("None".to_string(), |d| d.to_string()),
+            self.max_size.map_or("None".to_string(), |s| s.to_string()),
+            self.archive_path.as_deref().unwrap_or("None"),
+            humantime::format_duration(self.interval),
+            self.targets.len()
+        )
+    }
+}
+
+/// Approval asked from a webhook, which pushes it to the user's device,
+/// once the credentials of a login are accepted. Off without `url`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PushMfaConfig {
+    /// Endpoint receiving the signed approval requests
+    pub url: Option<String>,
+    /// HMAC-SHA256 key signing the requests, shared with the webhook
+    pub secret: Option<String>,
+    /// How long the user has to answer, the login is refused after that
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+impl Default for PushMfaConfig {
+    fn default() -> Self {
+        PushMfaConfig {
+            url: None,
+            secret: None,
+            timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+impl PushMfaConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let Some(url) = self.url.as_deref() else {
+            return Ok(());
+        };
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(ConfigError::PushMfaUrl {
+                url: url.to_string(),
+            });
+        }
+        if self.secret.as_deref().is_none_or(str::is_empty) {
+            return Err(ConfigError::PushMfaSecretMissing);
+        }
+        if self.timeout.is_zero() {
+            return Err(ConfigError::PushMfaTimeoutZero);
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for PushMfaConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "url={} timeout={}",
+            self.url.as_deref().unwrap_or("None"),
+            humantime::format_duration(self.timeout)
+        )
+    }
+}
+
+/// What `enforce` does when the policy decision point can't be asked or
+/// answers something else than a decision
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OpaFallback {
+    /// Decide with the local casbin rules
+    #[default]
+    Local,
+    /// Refuse the request
+    Deny,
+}
+
+impl std::fmt::Display for OpaFallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpaFallback::Local => write!(f, "local"),
+            OpaFallback::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+/// Open Policy Agent asked for every authorization decision in place of the
+/// local rules. Off without `url`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OpaConfig {
+    /// Decision endpoint, e.g. `http://127.0.0.1:8181/v1/data/rustion/allow`
+    pub url: Option<String>,
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+    pub fallback: OpaFallback,
+}
+
+impl Default for OpaConfig {
+    fn default() -> Self {
+        OpaConfig {
+            url: None,
+            timeout: Duration::from_secs(2),
+            fallback: OpaFallback::Local,
+        }
+    }
+}
+
+impl OpaConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        let Some(url) = self.url.as_deref() else {
+            return Ok(());
+        };
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(ConfigError::OpaUrl {
+                url: url.to_string(),
+            });
+        }
+        if self.timeout.is_zero() {
+            return Err(ConfigError::OpaTimeoutZero);
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for OpaConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "url={} timeout={} fallback={}",
+            self.url.as_deref().unwrap_or("None"),
+            humantime::format_duration(self.timeout),
+            self.fallback
+        )
+    }
+}
+
 fn default_server_id() -> String {
     format!("SSH-2.0-rustion_{}", env!("CARGO_PKG_VERSION"))
 }
@@ -91,6 +886,14 @@ fn default_client_id() -> String {
 pub struct Config {
     pub listen: ListenConfig,
     pub server_key: String,
+    /// Further host keys served after `server_key`, e.g. RSA or ECDSA ones
+    /// for clients that don't take the first
+    #[serde(default)]
+    pub host_keys: Vec<String>,
+    /// Load balancers, as addresses or CIDRs, whose connections start with
+    /// a PROXY protocol header naming the real client
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
     secret_key: Option<String>,
     #[serde(default = "default_server_id")]
     pub server_id: String,
@@ -112,9 +915,43 @@ pub struct Config {
     #[serde(default = "default_cache_idle_time")]
     #[serde(with = "humantime_serde")]
     pub target_cache_duration: Duration,
+    /// How long a pooled shell can be picked up again with its reconnection
+    /// token after the client dropped, unset turns tokens off
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub reconnect_token_ttl: Option<Duration>,
     #[serde(default)]
     #[serde(with = "humantime_serde")]
     pub inactivity_timeout: Option<Duration>,
+    /// How often clients that sent nothing are probed with a keepalive,
+    /// unset sends none
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub keepalive_interval: Option<Duration>,
+    /// Target sessions with no input or output for this long are warned,
+    /// then closed a minute later. Unset leaves idle sessions open.
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub idle_session_timeout: Option<Duration>,
+    /// How long the rules behind a policy decision are reused before the
+    /// database is asked again, unset asks every time
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub policy_cache_ttl: Option<Duration>,
+    /// How long after its expire date a policy is deleted by the cleanup
+    /// task, unset keeps expired policies
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub expired_policy_grace: Option<Duration>,
+    /// How often the policy version is polled to reload the role manager
+    /// after a change to rules, groups or targets, `0s` turns it off
+    #[serde(default = "default_policy_reload_interval")]
+    #[serde(with = "humantime_serde")]
+    pub policy_reload_interval: Duration,
+    /// MaxMind DB file (GeoLite2-Country or compatible) locating clients
+    /// for the `geo=` policy flag, unset leaves their country unknown
+    #[serde(default)]
+    pub geoip_database: Option<String>,
     #[serde(default)]
     pub log_level: LogLevel,
     #[serde(default)]
@@ -123,9 +960,99 @@ pub struct Config {
     pub record_input: bool,
     #[serde(default = "default_record_path")]
     pub record_path: String,
+    #[serde(default)]
+    pub record_files: RecordFilesConfig,
+    #[serde(default)]
+    pub record_sink: RecordSinkConfig,
+    #[serde(default)]
+    pub record_retention: RecordRetentionConfig,
+    /// Directory receiving the traces started by `rustion trace start`
+    #[serde(default = "default_trace_path")]
+    pub trace_path: String,
+    /// Regexes masked by `rustion redact` on top of password prompts
+    #[serde(default)]
+    pub redact_patterns: Vec<String>,
+    /// Terminal size used when a client requests a pty without one
+    #[serde(default = "default_term_cols")]
+    pub default_term_cols: u32,
+    #[serde(default = "default_term_rows")]
+    pub default_term_rows: u32,
     #[serde(default = "default_auth_rejection_time")]
     #[serde(with = "humantime_serde")]
     pub auth_rejection_time: Duration,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub target_connect: TargetConnectConfig,
+    /// Key exchange, cipher and MAC algorithms, e.g. to leave out sha1 kex
+    #[serde(default)]
+    pub algorithms: AlgorithmsConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// OpenSSH public keys of CAs whose user certificates are accepted for
+    /// the users named in their principals
+    #[serde(default)]
+    pub trusted_user_ca_keys: Vec<String>,
+    /// Private key of the CA signing the certificates that secrets of kind
+    /// `ca` log into targets with
+    #[serde(default)]
+    pub target_ca_key: Option<String>,
+    /// How long those certificates stay valid, only the login needs them
+    #[serde(default = "default_target_cert_validity")]
+    #[serde(with = "humantime_serde")]
+    pub target_cert_validity: Duration,
+    /// Passwords older than this must be changed at the next login
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub password_max_age: Option<Duration>,
+    /// Earlier passwords a user may not pick again
+    #[serde(default = "default_password_history")]
+    pub password_history: usize,
+    /// Filter written by `rustion breach-filter`, new passwords found in it
+    /// are refused
+    #[serde(default)]
+    pub breached_passwords: Option<String>,
+    /// Legal notice sent to clients before authentication
+    #[serde(default)]
+    pub banner: Option<String>,
+    /// Printed when a shell starts, placeholders are filled by
+    /// `server::motd::render_motd`
+    #[serde(default)]
+    pub motd: Option<String>,
+    /// Lets users ask for servers they can't reach with `request <server>`
+    /// at the target selector, which lists every active server for it
+    #[serde(default)]
+    pub access_requests: bool,
+    /// Subsystems besides `sftp` bridged to targets, each allowed by its own
+    /// `subsystem_<name>` action
+    #[serde(default)]
+    pub subsystems: Vec<String>,
+    /// Names of client `env` requests passed on to targets, a trailing `*`
+    /// matches any suffix. The others are dropped.
+    #[serde(default = "default_env_allowlist")]
+    pub env_allowlist: Vec<String>,
+    /// Puts the keyboard-interactive prompts of a target its stored
+    /// credentials don't satisfy to the user of an interactive shell
+    #[serde(default)]
+    pub relay_target_prompts: bool,
+    /// Identity backends checking credentials, asked in order
+    #[serde(default = "default_auth_providers")]
+    pub auth_providers: Vec<AuthProviderConfig>,
+    #[serde(default)]
+    pub push_mfa: PushMfaConfig,
+    /// External policy decision point, see `server::opa`
+    #[serde(default)]
+    pub opa: OpaConfig,
+    /// Command lists picked by the `cmd=<name>` policy flag
+    #[serde(default)]
+    pub command_filters: Vec<CommandFilterConfig>,
+    /// How exec commands are written to the audit log
+    #[serde(default)]
+    pub exec_log: ExecLogConfig,
+    /// What becomes of `rz`/`sz` transfers in shells whose policy has no
+    /// `zmodem=` flag
+    #[serde(default)]
+    pub zmodem: ZmodemPolicy,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -162,6 +1089,8 @@ impl Config {
         Config {
             listen: ListenConfig::String("0.0.0.0:2222".to_string()),
             server_key: "server_key.pem".to_string(),
+            host_keys: Vec::new(),
+            trusted_proxies: Vec::new(),
             secret_key: None,
             server_id: default_server_id(),
             client_id: default_client_id(),
@@ -171,13 +1100,49 @@ impl Config {
             unban_duration: default_unban_duration(),
             reuse_target_connection: false,
             target_cache_duration: default_cache_idle_time(),
+            reconnect_token_ttl: None,
             inactivity_timeout: None,
+            keepalive_interval: None,
+            idle_session_timeout: None,
+            policy_cache_ttl: None,
+            expired_policy_grace: None,
+            policy_reload_interval: default_policy_reload_interval(),
+            geoip_database: None,
             log_level: LogLevel::default(),
             database: DatabaseConfig::default(),
             enable_record: false,
             record_input: false,
             record_path: default_record_path(),
+            record_files: RecordFilesConfig::default(),
+            record_sink: RecordSinkConfig::default(),
+            record_retention: RecordRetentionConfig::default(),
+            trace_path: default_trace_path(),
+            redact_patterns: Vec::new(),
+            default_term_cols: default_term_cols(),
+            default_term_rows: default_term_rows(),
             auth_rejection_time: default_auth_rejection_time(),
+            limits: LimitsConfig::default(),
+            target_connect: TargetConnectConfig::default(),
+            algorithms: AlgorithmsConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            trusted_user_ca_keys: Vec::new(),
+            target_ca_key: None,
+            target_cert_validity: default_target_cert_validity(),
+            password_max_age: None,
+            password_history: default_password_history(),
+            breached_passwords: None,
+            banner: None,
+            motd: None,
+            access_requests: false,
+            subsystems: Vec::new(),
+            env_allowlist: default_env_allowlist(),
+            relay_target_prompts: false,
+            auth_providers: default_auth_providers(),
+            push_mfa: PushMfaConfig::default(),
+            opa: OpaConfig::default(),
+            command_filters: Vec::new(),
+            exec_log: ExecLogConfig::default(),
+            zmodem: ZmodemPolicy::default(),
         }
     }
 
@@ -230,7 +1195,7 @@ impl Config {
                             .map_err(|e| {
                                 Error::Config(ConfigError::AddressResolutionFailed {
                                     addr: addr_str.clone(),
-                                    reason: e.to_string(),
+                                    source: e,
                                 })
                             })?
                             .next()
@@ -243,23 +1208,132 @@ impl Config {
                     .map_err(|e| {
                         Error::Config(ConfigError::InvalidListenAddress {
                             addr: s.clone(),
-                            reason: e.to_string(),
+                            source: Box::new(e),
                         })
                     })
             }
         }
     }
 
+    /// Fingerprints of `trusted_user_ca_keys`
+    pub fn user_ca_fingerprints(&self) -> Result<Vec<Fingerprint>, ConfigError> {
+        self.trusted_user_ca_keys
+            .iter()
+            .enumerate()
+            .map(|(index, key)| {
+                PublicKey::from_openssh(key.trim())
+                    .map(|k| k.fingerprint(HashAlg::Sha256))
+                    .map_err(|source| ConfigError::InvalidUserCaKey { index, source })
+            })
+            .collect()
+    }
+
+    /// Parsed `trusted_proxies`
+    pub fn trusted_proxy_networks(&self) -> Result<Vec<IpNetwork>, ConfigError> {
+        self.trusted_proxies
+            .iter()
+            .map(|cidr| {
+                cidr.trim().parse::<IpNetwork>().map_err(|source| {
+                    ConfigError::InvalidTrustedProxy {
+                        cidr: cidr.clone(),
+                        source,
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Whether a client's `env` request for `name` is passed on
+    pub fn env_allowed(&self, name: &str) -> bool {
+        self.env_allowlist
+            .iter()
+            .any(|p| match p.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => p == name,
+            })
+    }
+
     /// Validate the configuration
     pub fn validate(&self) -> Result<(), Error> {
         // Validate listen address
         self.parse_listen_addr()?;
+        self.trusted_proxy_networks()?;
 
         // Validate max_auth_attempts
         if self.max_auth_attempts_per_conn == 0 {
             return Err(Error::Config(ConfigError::MaxAuthAttemptsZero));
         }
 
+        if self.default_term_cols == 0 || self.default_term_rows == 0 {
+            return Err(Error::Config(ConfigError::InvalidTermSize {
+                cols: self.default_term_cols,
+                rows: self.default_term_rows,
+            }));
+        }
+
+        self.limits.validate().map_err(Error::Config)?;
+        self.target_connect.validate().map_err(Error::Config)?;
+        self.algorithms.validate().map_err(Error::Config)?;
+        self.rate_limit.validate().map_err(Error::Config)?;
+        self.record_files.validate().map_err(Error::Config)?;
+        self.record_sink.validate().map_err(Error::Config)?;
+        self.record_retention
+            .validate(self.record_sink.kind)
+            .map_err(Error::Config)?;
+        self.push_mfa.validate().map_err(Error::Config)?;
+        self.opa.validate().map_err(Error::Config)?;
+        if self.keepalive_interval == Some(Duration::ZERO) {
+            return Err(Error::Config(ConfigError::DurationZero {
+                name: "keepalive_interval",
+            }));
+        }
+        if self.target_cert_validity.is_zero() {
+            return Err(Error::Config(ConfigError::DurationZero {
+                name: "target_cert_validity",
+            }));
+        }
+        if self.idle_session_timeout == Some(Duration::ZERO) {
+            return Err(Error::Config(ConfigError::DurationZero {
+                name: "idle_session_timeout",
+            }));
+        }
+        CommandFilters::new(&self.command_filters).map_err(Error::Config)?;
+        ExecLog::new(&self.exec_log).map_err(Error::Config)?;
+        for (i, name) in self.subsystems.iter().enumerate() {
+            if name.is_empty()
+                || name == "sftp"
+                || !name.chars().all(|c| c.is_ascii_graphic())
+                || self.subsystems[..i].contains(name)
+            {
+                return Err(Error::Config(ConfigError::InvalidSubsystem {
+                    name: name.to_string(),
+                }));
+            }
+        }
+        if let Some(p) = self
+            .env_allowlist
+            .iter()
+            .find(|p| p.is_empty() || p.trim_end_matches('*').contains('*'))
+        {
+            return Err(Error::Config(ConfigError::InvalidEnvPattern {
+                pattern: p.to_string(),
+            }));
+        }
+        self.user_ca_fingerprints().map_err(Error::Config)?;
+        if self.auth_providers.is_empty() {
+            return Err(Error::Config(ConfigError::NoAuthProvider));
+        }
+        for provider in &self.auth_providers {
+            provider.validate().map_err(Error::Config)?;
+        }
+        self.secret_cipher()?;
+
+        Ok(())
+    }
+
+    /// Cipher of `secret_key`, for tools working on stored secrets without a
+    /// running server
+    pub fn secret_cipher(&self) -> Result<aes_gcm::Aes256Gcm, Error> {
         let sk = match self.secret_key.as_ref() {
             Some(token) => token,
             None => return Err(Error::Config(ConfigError::MissingSecretToken)),
@@ -274,9 +1348,7 @@ impl Config {
             Error::Config(ConfigError::SecretTokenKeyError {
                 reason: e.to_string(),
             })
-        })?;
-
-        Ok(())
+        })
     }
 }
 
@@ -292,6 +1364,8 @@ impl std::fmt::Display for Config {
             f,
             "listen: {}\r
             server_key: {}\r
+            host_keys: {}\r
+            trusted_proxies: {}\r
             server_id: {}\r
             client_id: {}\r
             secret_key: {}...\r
@@ -301,15 +1375,52 @@ impl std::fmt::Display for Config {
             unban_duration: {}\r
             reuse_target_connection: {}\r
             target_cache_duration: {}\r
+            reconnect_token_ttl: {}\r
             inactivity_timeout: {}\r
+            keepalive_interval: {}\r
+            idle_session_timeout: {}\r
+            policy_cache_ttl: {}\r
+            expired_policy_grace: {}\r
+            policy_reload_interval: {}\r
+            geoip_database: {}\r
             log_level: {}\r
             database: {}\r
             enable_record: {}\r
             record_input: {}\r
             record_path: {}\r
-            auth_rejection_time: {}\r",
+            record_files: {}\r
+            record_sink: {}\r
+            record_retention: {}\r
+            trace_path: {}\r
+            redact_patterns: {:?}\r
+            default_term_size: {}x{}\r
+            auth_rejection_time: {}\r
+            limits: {}\r
+            target_connect: {}\r
+            algorithms: {}\r
+            rate_limit: {}\r
+            trusted_user_ca_keys: {}\r
+            target_ca_key: {}\r
+            target_cert_validity: {}\r
+            password_max_age: {}\r
+            password_history: {}\r
+            breached_passwords: {}\r
+            banner: {} lines\r
+            motd: {} lines\r
+            access_requests: {}\r
+            subsystems: {}\r
+            env_allowlist: {}\r
+            relay_target_prompts: {}\r
+            auth_providers: {}\r
+            push_mfa: {}\r
+            opa: {}\r
+            command_filters: {}\r
+            exec_log: {}\r
+            zmodem: {}\r",
             self.listen,
             self.server_key,
+            self.host_keys.join(", "),
+            self.trusted_proxies.join(", "),
             self.server_id,
             self.client_id,
             self.secret_key
@@ -321,15 +1432,71 @@ impl std::fmt::Display for Config {
             humantime::format_duration(self.unban_duration),
             self.reuse_target_connection,
             humantime::format_duration(self.target_cache_duration),
+            self.reconnect_token_ttl
+                .map_or("None".to_string(), |v| humantime::format_duration(v)
+                    .to_string()),
             self.inactivity_timeout
                 .map_or("None".to_string(), |v| humantime::format_duration(v)
                     .to_string()),
+            self.keepalive_interval
+                .map_or("None".to_string(), |v| humantime::format_duration(v)
+                    .to_string()),
+            self.idle_session_timeout
+                .map_or("None".to_string(), |v| humantime::format_duration(v)
+                    .to_string()),
+            self.policy_cache_ttl
+                .map_or("None".to_string(), |v| humantime::format_duration(v)
+                    .to_string()),
+            self.expired_policy_grace
+                .map_or("None".to_string(), |v| humantime::format_duration(v)
+                    .to_string()),
+            humantime::format_duration(self.policy_reload_interval),
+            self.geoip_database.as_deref().unwrap_or("None"),
             self.log_level,
             self.database,
             self.enable_record,
             self.record_input,
             self.record_path,
+            self.record_files,
+            self.record_sink,
+            self.record_retention,
+            self.trace_path,
+            self.redact_patterns,
+            self.default_term_cols,
+            self.default_term_rows,
             humantime::format_duration(self.auth_rejection_time),
+            self.limits,
+            self.target_connect,
+            self.algorithms,
+            self.rate_limit,
+            self.trusted_user_ca_keys.len(),
+            self.target_ca_key.as_deref().unwrap_or("None"),
+            humantime::format_duration(self.target_cert_validity),
+            self.password_max_age
+                .map_or("None".to_string(), |v| humantime::format_duration(v)
+                    .to_string()),
+            self.password_history,
+            self.breached_passwords.as_deref().unwrap_or("None"),
+            self.banner.as_ref().map_or(0, |v| v.lines().count()),
+            self.motd.as_ref().map_or(0, |v| v.lines().count()),
+            self.access_requests,
+            self.subsystems.join(", "),
+            self.env_allowlist.join(", "),
+            self.relay_target_prompts,
+            self.auth_providers
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            self.push_mfa,
+            self.opa,
+            self.command_filters
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            self.exec_log,
+            self.zmodem,
         )
     }
 }
@@ -343,6 +1510,8 @@ mod tests {
         let config = Config {
             listen: ListenConfig::String("localhost:2222".to_string()),
             server_key: "test.pem".to_string(),
+            host_keys: Vec::new(),
+            trusted_proxies: Vec::new(),
             secret_key: None,
             server_id: default_server_id(),
             client_id: default_client_id(),
@@ -352,19 +1521,57 @@ mod tests {
             unban_duration: Duration::from_secs(600),
             reuse_target_connection: false,
             target_cache_duration: Duration::from_secs(600),
+            reconnect_token_ttl: None,
             inactivity_timeout: None,
+            keepalive_interval: None,
+            idle_session_timeout: None,
+            policy_cache_ttl: None,
+            expired_policy_grace: None,
+            policy_reload_interval: default_policy_reload_interval(),
+            geoip_database: None,
             log_level: LogLevel::Info,
             database: DatabaseConfig::default(),
             enable_record: false,
             record_input: false,
             record_path: default_record_path(),
+            record_files: RecordFilesConfig::default(),
+            record_sink: RecordSinkConfig::default(),
+            record_retention: RecordRetentionConfig::default(),
+            trace_path: default_trace_path(),
+            redact_patterns: Vec::new(),
+            default_term_cols: default_term_cols(),
+            default_term_rows: default_term_rows(),
             auth_rejection_time: default_auth_rejection_time(),
+            limits: LimitsConfig::default(),
+            target_connect: TargetConnectConfig::default(),
+            algorithms: AlgorithmsConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            trusted_user_ca_keys: Vec::new(),
+            target_ca_key: None,
+            target_cert_validity: default_target_cert_validity(),
+            password_max_age: None,
+            password_history: default_password_history(),
+            breached_passwords: None,
+            banner: None,
+            motd: None,
+            access_requests: false,
+            subsystems: Vec::new(),
+            env_allowlist: default_env_allowlist(),
+            relay_target_prompts: false,
+            auth_providers: default_auth_providers(),
+            push_mfa: PushMfaConfig::default(),
+            opa: OpaConfig::default(),
+            command_filters: Vec::new(),
+            exec_log: ExecLogConfig::default(),
+            zmodem: ZmodemPolicy::default(),
         };
         assert!(config.parse_listen_addr().is_ok());
 
         let config = Config {
             listen: ListenConfig::String("*:2222".to_string()),
             server_key: "test.pem".to_string(),
+            host_keys: Vec::new(),
+            trusted_proxies: Vec::new(),
             secret_key: None,
             server_id: default_server_id(),
             client_id: default_client_id(),
@@ -374,13 +1581,49 @@ mod tests {
             unban_duration: Duration::from_secs(600),
             reuse_target_connection: false,
             target_cache_duration: Duration::from_secs(600),
+            reconnect_token_ttl: None,
             inactivity_timeout: None,
+            keepalive_interval: None,
+            idle_session_timeout: None,
+            policy_cache_ttl: None,
+            expired_policy_grace: None,
+            policy_reload_interval: default_policy_reload_interval(),
+            geoip_database: None,
             log_level: LogLevel::Info,
             database: DatabaseConfig::default(),
             enable_record: false,
             record_input: false,
             record_path: default_record_path(),
+            record_files: RecordFilesConfig::default(),
+            record_sink: RecordSinkConfig::default(),
+            record_retention: RecordRetentionConfig::default(),
+            trace_path: default_trace_path(),
+            redact_patterns: Vec::new(),
+            default_term_cols: default_term_cols(),
+            default_term_rows: default_term_rows(),
             auth_rejection_time: default_auth_rejection_time(),
+            limits: LimitsConfig::default(),
+            target_connect: TargetConnectConfig::default(),
+            algorithms: AlgorithmsConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            trusted_user_ca_keys: Vec::new(),
+            target_ca_key: None,
+            target_cert_validity: default_target_cert_validity(),
+            password_max_age: None,
+            password_history: default_password_history(),
+            breached_passwords: None,
+            banner: None,
+            motd: None,
+            access_requests: false,
+            subsystems: Vec::new(),
+            env_allowlist: default_env_allowlist(),
+            relay_target_prompts: false,
+            auth_providers: default_auth_providers(),
+            push_mfa: PushMfaConfig::default(),
+            opa: OpaConfig::default(),
+            command_filters: Vec::new(),
+            exec_log: ExecLogConfig::default(),
+            zmodem: ZmodemPolicy::default(),
         };
         let addr = config.parse_listen_addr().unwrap();
         assert_eq!(addr.port(), 2222);
@@ -388,6 +1631,8 @@ mod tests {
         let config = Config {
             listen: ListenConfig::String("2222".to_string()),
             server_key: "test.pem".to_string(),
+            host_keys: Vec::new(),
+            trusted_proxies: Vec::new(),
             secret_key: None,
             server_id: default_server_id(),
             client_id: default_client_id(),
@@ -397,13 +1642,49 @@ mod tests {
             unban_duration: Duration::from_secs(600),
             reuse_target_connection: false,
             target_cache_duration: Duration::from_secs(600),
+            reconnect_token_ttl: None,
             inactivity_timeout: None,
+            keepalive_interval: None,
+            idle_session_timeout: None,
+            policy_cache_ttl: None,
+            expired_policy_grace: None,
+            policy_reload_interval: default_policy_reload_interval(),
+            geoip_database: None,
             log_level: LogLevel::Info,
             database: DatabaseConfig::default(),
             enable_record: false,
             record_input: false,
             record_path: default_record_path(),
+            record_files: RecordFilesConfig::default(),
+            record_sink: RecordSinkConfig::default(),
+            record_retention: RecordRetentionConfig::default(),
+            trace_path: default_trace_path(),
+            redact_patterns: Vec::new(),
+            default_term_cols: default_term_cols(),
+            default_term_rows: default_term_rows(),
             auth_rejection_time: default_auth_rejection_time(),
+            limits: LimitsConfig::default(),
+            target_connect: TargetConnectConfig::default(),
+            algorithms: AlgorithmsConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            trusted_user_ca_keys: Vec::new(),
+            target_ca_key: None,
+            target_cert_validity: default_target_cert_validity(),
+            password_max_age: None,
+            password_history: default_password_history(),
+            breached_passwords: None,
+            banner: None,
+            motd: None,
+            access_requests: false,
+            subsystems: Vec::new(),
+            env_allowlist: default_env_allowlist(),
+            relay_target_prompts: false,
+            auth_providers: default_auth_providers(),
+            push_mfa: PushMfaConfig::default(),
+            opa: OpaConfig::default(),
+            command_filters: Vec::new(),
+            exec_log: ExecLogConfig::default(),
+            zmodem: ZmodemPolicy::default(),
         };
         let addr = config.parse_listen_addr().unwrap();
         assert_eq!(addr.port(), 2222);
@@ -417,6 +1698,8 @@ mod tests {
         let invalid_config = Config {
             listen: ListenConfig::String("invalid".to_string()),
             server_key: "test.pem".to_string(),
+            host_keys: Vec::new(),
+            trusted_proxies: Vec::new(),
             secret_key: None,
             server_id: default_server_id(),
             client_id: default_client_id(),
@@ -426,15 +1709,210 @@ mod tests {
             unban_duration: Duration::from_secs(600),
             reuse_target_connection: false,
             target_cache_duration: Duration::from_secs(600),
+            reconnect_token_ttl: None,
             inactivity_timeout: None,
+            keepalive_interval: None,
+            idle_session_timeout: None,
+            policy_cache_ttl: None,
+            expired_policy_grace: None,
+            policy_reload_interval: default_policy_reload_interval(),
+            geoip_database: None,
             log_level: LogLevel::Info,
             database: DatabaseConfig::default(),
             enable_record: false,
             record_input: false,
             record_path: default_record_path(),
+            record_files: RecordFilesConfig::default(),
+            record_sink: RecordSinkConfig::default(),
+            record_retention: RecordRetentionConfig::default(),
+            trace_path: default_trace_path(),
+            redact_patterns: Vec::new(),
+            default_term_cols: default_term_cols(),
+            default_term_rows: default_term_rows(),
             auth_rejection_time: default_auth_rejection_time(),
+            limits: LimitsConfig::default(),
+            target_connect: TargetConnectConfig::default(),
+            algorithms: AlgorithmsConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            trusted_user_ca_keys: Vec::new(),
+            target_ca_key: None,
+            target_cert_validity: default_target_cert_validity(),
+            password_max_age: None,
+            password_history: default_password_history(),
+            breached_passwords: None,
+            banner: None,
+            motd: None,
+            access_requests: false,
+            subsystems: Vec::new(),
+            env_allowlist: default_env_allowlist(),
+            relay_target_prompts: false,
+            auth_providers: default_auth_providers(),
+            push_mfa: PushMfaConfig::default(),
+            opa: OpaConfig::default(),
+            command_filters: Vec::new(),
+            exec_log: ExecLogConfig::default(),
+            zmodem: ZmodemPolicy::default(),
         };
         assert!(invalid_config.validate().is_err());
+
+        let mut zero_size = Config::default().gen_secret_token();
+        zero_size.default_term_cols = 0;
+        assert!(zero_size.validate().is_err());
+
+        let mut no_channels = Config::default().gen_secret_token();
+        no_channels.limits.max_channels = 0;
+        assert!(no_channels.validate().is_err());
+
+        let mut no_execs = Config::default().gen_secret_token();
+        no_execs.limits.max_execs = 0;
+        assert!(no_execs.validate().is_err());
+
+        let mut big_packet = Config::default().gen_secret_token();
+        big_packet.limits.max_packet_size = big_packet.limits.window_size + 1;
+        assert!(big_packet.validate().is_err());
+
+        let mut bad_ca = Config::default().gen_secret_token();
+        bad_ca.trusted_user_ca_keys = vec!["ssh-ed25519 not-a-key".to_string()];
+        assert!(bad_ca.validate().is_err());
+
+        let mut whitelist = Config::default().gen_secret_token();
+        whitelist.rate_limit.whitelist = vec!["10.0.0.0/8".to_string(), "::1".to_string()];
+        assert!(whitelist.validate().is_ok());
+        whitelist.rate_limit.whitelist = vec!["10.0.0.0/33".to_string()];
+        assert!(whitelist.validate().is_err());
+
+        let mut proxies = Config::default().gen_secret_token();
+        proxies.trusted_proxies = vec!["10.0.0.5".to_string(), "fd00::/8".to_string()];
+        assert!(proxies.validate().is_ok());
+        proxies.trusted_proxies = vec!["lb.internal".to_string()];
+        assert!(proxies.validate().is_err());
+
+        let mut short_max = Config::default().gen_secret_token();
+        short_max.rate_limit.max_ban_duration = Duration::from_secs(1);
+        assert!(short_max.validate().is_err());
+
+        let mut record_files = Config::default().gen_secret_token();
+        record_files.record_files.file_mode = Some(0o640);
+        record_files.record_files.group = Some("4242".to_string());
+        assert!(record_files.validate().is_ok());
+        assert_eq!(record_files.record_files.gid().unwrap(), Some(4242));
+        record_files.record_files.file_mode = Some(0o1640);
+        assert!(record_files.validate().is_err());
+        record_files.record_files.file_mode = None;
+        record_files.record_files.group = Some("no-such-group-here".to_string());
+        assert!(record_files.validate().is_err());
+
+        let mut record_sink = Config::default().gen_secret_token();
+        record_sink.record_sink.kind = RecordSinkKind::S3;
+        assert!(record_sink.validate().is_err());
+        record_sink.record_sink.endpoint = Some("https://s3.example.com".to_string());
+        record_sink.record_sink.bucket = Some("recordings".to_string());
+        record_sink.record_sink.access_key = Some("access".to_string());
+        record_sink.record_sink.secret_key = Some("secret".to_string());
+        assert!(record_sink.validate().is_ok());
+        record_sink.record_sink.part_size = 1024 * 1024;
+        assert!(record_sink.validate().is_err());
+        record_sink.record_sink.part_size = MIN_PART_SIZE;
+        record_sink.record_retention.archive_path = Some("/srv/archive".to_string());
+        assert!(record_sink.validate().is_err());
+        record_sink.record_sink.kind = RecordSinkKind::File;
+        assert!(record_sink.validate().is_ok());
+        let mut push_mfa = Config::default().gen_secret_token();
+        push_mfa.push_mfa.url = Some("https://mfa.example/approve".to_string());
+        assert!(push_mfa.validate().is_err());
+        push_mfa.push_mfa.secret = Some("shared".to_string());
+        assert!(push_mfa.validate().is_ok());
+        push_mfa.push_mfa.url = Some("mfa.example".to_string());
+        assert!(push_mfa.validate().is_err());
+        let mut opa = Config::default().gen_secret_token();
+        opa.opa.url = Some("http://127.0.0.1:8181/v1/data/rustion/allow".to_string());
+        assert!(opa.validate().is_ok());
+        opa.opa.timeout = Duration::ZERO;
+        assert!(opa.validate().is_err());
+        opa.opa.timeout = Duration::from_secs(2);
+        opa.opa.url = Some("127.0.0.1:8181".to_string());
+        assert!(opa.validate().is_err());
+        let mut retry = Config::default().gen_secret_token();
+        retry.target_connect.max_backoff = Duration::from_millis(500);
+        assert!(retry.validate().is_err());
+        let mut algorithms = Config::default().gen_secret_token();
+        algorithms.algorithms.client.kex = vec!["diffie-hellman-group1-sha1x".to_string()];
+        assert!(algorithms.validate().is_err());
+        let mut timeout = Config::default().gen_secret_token();
+        timeout.target_connect.auth_timeout = Duration::ZERO;
+        assert!(timeout.validate().is_err());
+        let mut subsystems = Config::default().gen_secret_token();
+        subsystems.subsystems = vec!["netconf".to_string(), "powershell".to_string()];
+        assert!(subsystems.validate().is_ok());
+        subsystems.subsystems.push("netconf".to_string());
+        assert!(subsystems.validate().is_err());
+        subsystems.subsystems = vec!["sftp".to_string()];
+        assert!(subsystems.validate().is_err());
+        let mut env = Config::default().gen_secret_token();
+        env.env_allowlist = vec!["LC_*".to_string(), "*_PROXY".to_string()];
+        assert!(env.validate().is_err());
+    }
+
+    #[test]
+    fn test_target_connect_delays() {
+        let secs = Duration::from_secs;
+        let retry = TargetConnectConfig {
+            retries: 4,
+            backoff: secs(2),
+            max_backoff: secs(10),
+            ..Default::default()
+        };
+        assert_eq!(
+            retry.delays().collect::<Vec<_>>(),
+            [secs(2), secs(4), secs(8), secs(10)]
+        );
+        assert_eq!(TargetConnectConfig::default().delays().count(), 0);
+        let slow = retry.for_target(Some(secs(60)));
+        assert_eq!(slow.connect_timeout, secs(60));
+        assert_eq!(slow.auth_timeout, secs(60));
+        assert_eq!(slow.retries, 4);
+        assert_eq!(retry.for_target(None).handshake_timeout, secs(10));
+        assert_eq!(retry.keepalive(), Some(secs(15)));
+        let quiet = TargetConnectConfig {
+            keepalive_interval: Duration::ZERO,
+            ..Default::default()
+        };
+        assert_eq!(quiet.keepalive(), None);
+        assert_eq!(quiet.reconnect(), None);
+        let patient = TargetConnectConfig {
+            reconnect_window: secs(120),
+            ..Default::default()
+        };
+        assert_eq!(patient.reconnect(), Some(secs(120)));
+    }
+
+    #[test]
+    fn test_algorithm_preferred() {
+        let set = AlgorithmSet {
+            kex: vec!["curve25519-sha256".to_string()],
+            cipher: vec!["aes256-gcm@openssh.com".to_string()],
+            mac: Vec::new(),
+        };
+        let preferred = set.preferred("server").unwrap();
+        let kex: Vec<&str> = preferred.kex.iter().map(|n| n.as_ref()).collect();
+        assert_eq!(kex[0], "curve25519-sha256");
+        assert!(!kex.contains(&"diffie-hellman-group14-sha1"));
+        assert!(kex.iter().any(|n| n.starts_with("kex-strict-")));
+        assert_eq!(preferred.cipher.len(), 1);
+        assert_eq!(preferred.mac, Preferred::default().mac);
+    }
+
+    #[test]
+    fn test_env_allowed() {
+        let mut config = Config::default();
+        assert!(config.env_allowed("LANG"));
+        assert!(config.env_allowed("LC_ALL"));
+        assert!(!config.env_allowed("LANGUAGE"));
+        assert!(!config.env_allowed("AWS_SECRET_ACCESS_KEY"));
+        config.env_allowlist = vec!["TERM_PROGRAM".to_string(), "*".to_string()];
+        assert!(config.env_allowed("ANYTHING"));
+        config.env_allowlist = Vec::new();
+        assert!(!config.env_allowed("LANG"));
     }
 
     #[test]
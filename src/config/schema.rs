@@ -0,0 +1,523 @@
+use crate::config::Config;
+use crate::config::error::ConfigError;
+use std::collections::{BTreeMap, BTreeSet};
+use toml::Value;
+
+/// Every config key with a short description, in `rustion.toml` order.
+/// Nested tables use dotted keys, which TOML accepts as well.
+pub const KEYS: &[(&str, &str)] = &[
+    (
+        "listen",
+        "Listen address: `host:port`, `*:port` or a bare port",
+    ),
+    (
+        "server_key",
+        "Private host key file, generated when missing",
+    ),
+    (
+        "host_keys",
+        "Further host key files served after server_key, e.g. RSA and ECDSA",
+    ),
+    (
+        "trusted_proxies",
+        "Load balancer addresses or CIDRs sending a PROXY protocol v1/v2 header",
+    ),
+    (
+        "secret_key",
+        "Base64 AES-256 key encrypting stored secrets, required",
+    ),
+    ("server_id", "SSH identification string sent to clients"),
+    ("client_id", "SSH identification string sent to targets"),
+    (
+        "max_auth_attempts_per_conn",
+        "Authentication attempts allowed on one connection",
+    ),
+    (
+        "max_ip_attempts",
+        "Failed logins from one IP before it is banned",
+    ),
+    (
+        "max_user_attempts",
+        "Failed logins for one user before it is banned",
+    ),
+    ("unban_duration", "How long an IP or user ban lasts"),
+    (
+        "reuse_target_connection",
+        "Keep target connections open for later sessions",
+    ),
+    (
+        "target_cache_duration",
+        "Idle time after which a pooled target connection is dropped",
+    ),
+    (
+        "reconnect_token_ttl",
+        "Time a dropped pooled shell can be resumed with its token, off by default",
+    ),
+    (
+        "inactivity_timeout",
+        "Idle time after which a client is disconnected, none by default",
+    ),
+    (
+        "keepalive_interval",
+        "Interval of keepalives sent to clients that went quiet, none by default",
+    ),
+    (
+        "idle_session_timeout",
+        "Time without input or output after which a target session is warned and closed, none by default",
+    ),
+    (
+        "policy_cache_ttl",
+        "Time policy lookups are reused before asking the database again, off by default",
+    ),
+    (
+        "expired_policy_grace",
+        "Time after its expire date a policy is deleted, off by default",
+    ),
+    (
+        "policy_reload_interval",
+        "How often rule changes are looked for to reload the role manager, 0s for never",
+    ),
+    (
+        "geoip_database",
+        "MaxMind country database for the geo= policy flag, none by default",
+    ),
+    ("log_level", "One of error, warn, info, debug, trace"),
+    ("database.type", "Backend: sqlite or memory"),
+    ("database.path", "SQLite database file"),
+    (
+        "database.name",
+        "Memory store name, stores sharing a name share data",
+    ),
+    (
+        "enable_record",
+        "Record interactive sessions as asciinema files",
+    ),
+    ("record_input", "Also record what users type"),
+    ("record_path", "Directory holding recordings"),
+    (
+        "record_files.per_target",
+        "One directory per target secret below record_path",
+    ),
+    (
+        "record_files.file_mode",
+        "Mode of recording files, directories add search bits",
+    ),
+    (
+        "record_files.group",
+        "Group name or gid owning recording files and directories",
+    ),
+    (
+        "record_sink.type",
+        "Where recordings go: file below record_path or s3",
+    ),
+    (
+        "record_sink.endpoint",
+        "S3 endpoint URL, path-style buckets",
+    ),
+    ("record_sink.region", "Region signed into S3 requests"),
+    ("record_sink.bucket", "Bucket receiving recordings"),
+    (
+        "record_sink.prefix",
+        "Put before a recording's path in its key",
+    ),
+    (
+        "record_sink.access_key",
+        "S3 access key, else AWS_ACCESS_KEY_ID",
+    ),
+    (
+        "record_sink.secret_key",
+        "S3 secret key, else AWS_SECRET_ACCESS_KEY",
+    ),
+    (
+        "record_sink.part_size",
+        "Bytes buffered per multipart upload part, at least 5 MiB",
+    ),
+    (
+        "record_sink.retries",
+        "Further attempts of a failed S3 request",
+    ),
+    ("record_sink.timeout", "Limit of each S3 request"),
+    (
+        "record_retention.max_age",
+        "Completed recordings older than this are purged",
+    ),
+    (
+        "record_retention.max_size",
+        "Bytes of recordings kept, the oldest beyond are purged",
+    ),
+    (
+        "record_retention.archive_path",
+        "Directory purged recording files are moved to instead of deleted",
+    ),
+    (
+        "record_retention.interval",
+        "How often recordings are checked for retention",
+    ),
+    (
+        "record_retention.targets",
+        "Per-target max_age and max_size, by target name",
+    ),
+    (
+        "trace_path",
+        "Directory receiving connection traces of `rustion trace`",
+    ),
+    (
+        "redact_patterns",
+        "Regexes masked by `rustion redact` on top of password prompts",
+    ),
+    (
+        "default_term_cols",
+        "Columns used when a pty request has none",
+    ),
+    ("default_term_rows", "Rows used when a pty request has none"),
+    (
+        "auth_rejection_time",
+        "Delay before a failed authentication is answered",
+    ),
+    (
+        "trusted_user_ca_keys",
+        "OpenSSH public keys of CAs trusted to sign user certificates",
+    ),
+    (
+        "target_ca_key",
+        "CA key signing the certificates of `ca` secrets, none by default",
+    ),
+    (
+        "target_cert_validity",
+        "Lifetime of the certificates signed with target_ca_key",
+    ),
+    (
+        "password_max_age",
+        "Age after which a password must be changed, none by default",
+    ),
+    ("password_history", "Earlier passwords a user may not reuse"),
+    (
+        "breached_passwords",
+        "Breached password filter from `rustion breach-filter`, none by default",
+    ),
+    ("banner", "Legal notice sent before authentication"),
+    (
+        "motd",
+        "Shown when a shell starts: {username}, {targets}, {last_login}, {expiry}",
+    ),
+    (
+        "access_requests",
+        "Let users request access to other servers from the target selector",
+    ),
+    (
+        "subsystems",
+        "Subsystems besides sftp bridged to targets, each allowed by its subsystem_<name> action",
+    ),
+    (
+        "env_allowlist",
+        "Client environment variables passed on to targets, a trailing * matches any suffix",
+    ),
+    (
+        "relay_target_prompts",
+        "Ask the user a target's keyboard-interactive prompts, e.g. its own OTP, when its secret isn't enough",
+    ),
+    (
+        "zmodem",
+        "rz/sz transfers in shells without a zmodem= policy flag: allow, log or block",
+    ),
+    (
+        "auth_providers",
+        "Identity backends checking credentials in order: local or oidc",
+    ),
+    (
+        "limits.max_channels",
+        "Channels open at once on one connection",
+    ),
+    (
+        "limits.max_execs",
+        "Exec requests running at once on one connection",
+    ),
+    (
+        "limits.max_direct_tcpip",
+        "Direct-tcpip streams open at once on one connection",
+    ),
+    (
+        "limits.window_size",
+        "Bytes a peer may send on a channel before it is acknowledged",
+    ),
+    (
+        "limits.max_packet_size",
+        "Largest packet accepted on a channel",
+    ),
+    (
+        "limits.channel_buffer_size",
+        "Messages queued per channel before the sender waits",
+    ),
+    (
+        "limits.upload_rate",
+        "Client to target bytes per second of a session, 0 for no limit",
+    ),
+    (
+        "limits.download_rate",
+        "Target to client bytes per second of a session, 0 for no limit",
+    ),
+    (
+        "target_connect.retries",
+        "Rounds over a target's addresses after the first one failed",
+    ),
+    (
+        "target_connect.backoff",
+        "Wait before the first retry round, doubled for each next one",
+    ),
+    (
+        "target_connect.max_backoff",
+        "Longest wait between retry rounds",
+    ),
+    (
+        "target_connect.connect_timeout",
+        "Time an address gets to accept the TCP connection",
+    ),
+    (
+        "target_connect.handshake_timeout",
+        "Time the SSH version and key exchange with a target may take",
+    ),
+    (
+        "target_connect.auth_timeout",
+        "Time the login with the secret may take",
+    ),
+    (
+        "target_connect.keepalive_interval",
+        "Keepalives sent over quiet target connections, 0s for none",
+    ),
+    (
+        "target_connect.keepalive_max",
+        "Unanswered keepalives before a target connection is torn down",
+    ),
+    (
+        "target_connect.reconnect_window",
+        "How long a shell that lost its target tries to reopen it on a new connection, 0s for never",
+    ),
+    (
+        "algorithms.server.kex",
+        "Key exchange algorithms offered to clients, empty for russh's defaults",
+    ),
+    (
+        "algorithms.server.cipher",
+        "Ciphers offered to clients, empty for russh's defaults",
+    ),
+    (
+        "algorithms.server.mac",
+        "MAC algorithms offered to clients, empty for russh's defaults",
+    ),
+    (
+        "algorithms.client.kex",
+        "Key exchange algorithms offered to targets, empty for russh's defaults",
+    ),
+    (
+        "algorithms.client.cipher",
+        "Ciphers offered to targets, empty for russh's defaults",
+    ),
+    (
+        "algorithms.client.mac",
+        "MAC algorithms offered to targets, empty for russh's defaults",
+    ),
+    (
+        "rate_limit.attempts_per_minute",
+        "Authentication attempts allowed per client IP and minute, 0 disables",
+    ),
+    (
+        "rate_limit.ban_duration",
+        "First ban of an IP over the rate, doubled on each ban in a row",
+    ),
+    ("rate_limit.max_ban_duration", "Longest rate limit ban"),
+    (
+        "rate_limit.whitelist",
+        "Addresses or CIDRs exempt from the rate limit",
+    ),
+    (
+        "push_mfa.url",
+        "Webhook asked to approve each login once its credentials are accepted",
+    ),
+    (
+        "push_mfa.secret",
+        "HMAC-SHA256 key signing the approval requests",
+    ),
+    ("push_mfa.timeout", "Time the user has to approve a login"),
+    (
+        "opa.url",
+        "Open Policy Agent endpoint deciding every authorization in place of the local rules",
+    ),
+    ("opa.timeout", "Time OPA has to answer a decision"),
+    (
+        "opa.fallback",
+        "What to do when OPA can't decide: local (use the local rules) or deny",
+    ),
+    (
+        "command_filters",
+        "Named allow and deny lists of commands, picked by the cmd=<name> policy flag",
+    ),
+    (
+        "exec_log.max_len",
+        "Bytes of an exec command kept in the exec log, 0 leaves execs out of it",
+    ),
+    (
+        "exec_log.redact_patterns",
+        "Regexes masked in exec commands before they are logged",
+    ),
+];
+
+/// Keys whose values are never printed
+const HIDDEN: &[&str] = &[
+    "secret_key",
+    "push_mfa.secret",
+    "record_sink.secret_key",
+    "auth_providers",
+];
+
+pub struct Entry {
+    pub key: &'static str,
+    pub doc: &'static str,
+    /// `None` when the key is unset by default
+    pub default: Option<String>,
+}
+
+/// A key whose loaded value differs from the default
+pub struct Change {
+    pub key: String,
+    pub default: Option<String>,
+    pub current: Option<String>,
+}
+
+fn flatten(prefix: &str, value: Value, out: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Table(table) => {
+            for (k, v) in table {
+                let key = if prefix.is_empty() {
+                    k
+                } else {
+                    format!("{}.{}", prefix, k)
+                };
+                flatten(&key, v, out);
+            }
+        }
+        v => {
+            out.insert(prefix.to_string(), v);
+        }
+    }
+}
+
+fn values(config: &Config) -> Result<BTreeMap<String, Value>, ConfigError> {
+    let value = Value::try_from(config).map_err(|e| ConfigError::TomlSerialize { source: e })?;
+    let mut out = BTreeMap::new();
+    flatten("", value, &mut out);
+    Ok(out)
+}
+
+fn show(key: &str, value: &Value) -> String {
+    if HIDDEN.contains(&key) {
+        "<hidden>".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Documented keys with the values `Config::default()` gives them
+pub fn schema() -> Result<Vec<Entry>, ConfigError> {
+    let defaults = values(&Config::default())?;
+    Ok(KEYS
+        .iter()
+        .map(|&(key, doc)| Entry {
+            key,
+            doc,
+            default: defaults.get(key).map(|v| show(key, v)),
+        })
+        .collect())
+}
+
+/// Keys set to something else than their default, in `KEYS` order
+pub fn diff(config: &Config) -> Result<Vec<Change>, ConfigError> {
+    let defaults = values(&Config::default())?;
+    let current = values(config)?;
+    let keys: BTreeSet<&String> = defaults.keys().chain(current.keys()).collect();
+    let mut keys: Vec<&String> = keys.into_iter().collect();
+    keys.sort_by_key(|k| {
+        KEYS.iter()
+            .position(|(key, _)| *key == k.as_str())
+            .unwrap_or(KEYS.len())
+    });
+    Ok(keys
+        .into_iter()
+        .filter(|k| defaults.get(*k) != current.get(*k))
+        .map(|k| Change {
+            key: k.clone(),
+            default: defaults.get(k).map(|v| show(k, v)),
+            current: current.get(k).map(|v| show(k, v)),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LogLevel;
+    use crate::database::DatabaseConfig;
+
+    #[test]
+    fn test_every_key_documented() {
+        let mut config = Config::default().gen_secret_token();
+        config.inactivity_timeout = Some(std::time::Duration::from_secs(60));
+        config.keepalive_interval = Some(std::time::Duration::from_secs(30));
+        config.idle_session_timeout = Some(std::time::Duration::from_secs(900));
+        config.reconnect_token_ttl = Some(std::time::Duration::from_secs(300));
+        config.target_ca_key = Some("target_ca.pem".to_string());
+        config.password_max_age = Some(std::time::Duration::from_secs(86400));
+        config.breached_passwords = Some("breached.bloom".to_string());
+        config.banner = Some("Authorized use only".to_string());
+        config.motd = Some("Welcome {username}".to_string());
+        config.record_files.file_mode = Some(0o640);
+        config.record_files.group = Some("0".to_string());
+        config.push_mfa.url = Some("https://mfa.example/approve".to_string());
+        config.push_mfa.secret = Some("shared".to_string());
+        config.opa.url = Some("http://127.0.0.1:8181/v1/data/rustion/allow".to_string());
+        config.record_sink.endpoint = Some("https://s3.example.com".to_string());
+        config.record_sink.bucket = Some("recordings".to_string());
+        config.record_sink.access_key = Some("access".to_string());
+        config.record_sink.secret_key = Some("secret".to_string());
+        config.record_retention.max_age = Some(std::time::Duration::from_secs(86400));
+        config.record_retention.max_size = Some(1 << 30);
+        config.record_retention.archive_path = Some("/srv/archive".to_string());
+        config
+            .record_retention
+            .targets
+            .push(crate::config::TargetRetentionConfig {
+                target: "prod-db".to_string(),
+                max_age: None,
+                max_size: None,
+            });
+        let databases = [
+            DatabaseConfig::default(),
+            DatabaseConfig::Memory {
+                name: "test".to_string(),
+            },
+        ];
+        for database in databases {
+            config.database = database;
+            for key in values(&config).unwrap().keys() {
+                assert!(
+                    KEYS.iter().any(|(k, _)| k == key),
+                    "{} is not documented",
+                    key
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        assert!(diff(&Config::default()).unwrap().is_empty());
+
+        let mut config = Config::default().gen_secret_token();
+        config.log_level = LogLevel::Debug;
+        let changes = diff(&config).unwrap();
+        let keys: Vec<&str> = changes.iter().map(|c| c.key.as_str()).collect();
+        assert_eq!(keys, ["secret_key", "log_level"]);
+        assert_eq!(changes[0].current.as_deref(), Some("<hidden>"));
+        assert_eq!(changes[1].default.as_deref(), Some("\"info\""));
+        assert_eq!(changes[1].current.as_deref(), Some("\"debug\""));
+    }
+}
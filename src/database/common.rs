@@ -12,13 +12,43 @@ pub const ACT_PTY: &str = "__internal_action_pty";
 pub const ACT_EXEC: &str = "__internal_action_exec";
 pub const ACT_LOGIN: &str = "__internal_action_login";
 pub const ACT_DIRECT_TCPIP: &str = "__internal_action_open_direct_tcpip";
+pub const ACT_SFTP: &str = "__internal_action_sftp";
+pub const ACT_SCP: &str = "__internal_action_scp";
+pub const ACT_REMOTE_FORWARD: &str = "__internal_action_remote_forward";
+pub const ACT_AGENT_FORWARD: &str = "__internal_action_agent_forward";
+/// A shell whose input is dropped, for watching a target without touching it
+pub const ACT_OBSERVE: &str = "__internal_action_observe";
+/// Followed by the name of a subsystem listed in `subsystems`
+pub const ACT_SUBSYSTEM_PREFIX: &str = "__internal_action_subsystem_";
+
+/// Shared by the internal names above, left out when they are displayed
+pub const OBJ_PREFIX: &str = "__internal_object_";
+pub const ACT_PREFIX: &str = "__internal_action_";
 
 pub const INTERNAL_OBJECT_TYPE: &str = "__internal_object_type";
 pub const INTERNAL_ACTION_TYPE: &str = "__internal_action_type";
 
+/// Levels of nested `g1` roles followed when the database resolves the
+/// policies of a user, a cycle in the roles stops there too
+pub const MAX_ROLE_DEPTH: u32 = 8;
+
+/// Object groups generated from target tags are named `tag:key=value`
+pub const TAG_GROUP_PREFIX: &str = "tag:";
+
 pub const INTERNAL_OBJECTS: [&str; 3] = [OBJ_LOGIN, OBJ_ADMIN, OBJ_PLAYER];
 
-pub const INTERNAL_ACTIONS: [&str; 5] = [ACT_SHELL, ACT_DIRECT_TCPIP, ACT_EXEC, ACT_LOGIN, ACT_PTY];
+pub const INTERNAL_ACTIONS: [&str; 10] = [
+    ACT_SHELL,
+    ACT_DIRECT_TCPIP,
+    ACT_EXEC,
+    ACT_LOGIN,
+    ACT_PTY,
+    ACT_SFTP,
+    ACT_SCP,
+    ACT_REMOTE_FORWARD,
+    ACT_AGENT_FORWARD,
+    ACT_OBSERVE,
+];
 
 /// Global UUIDs for internal objects and actions, loaded once at service startup
 /// TODO: use hash map instead of struct
@@ -32,6 +62,11 @@ pub struct InternalUuids {
     pub act_exec: Uuid,
     pub act_login: Uuid,
     pub act_direct_tcpip: Uuid,
+    pub act_sftp: Uuid,
+    pub act_scp: Uuid,
+    pub act_remote_forward: Uuid,
+    pub act_agent_forward: Uuid,
+    pub act_observe: Uuid,
 }
 
 static INTERNAL_UUIDS: OnceLock<InternalUuids> = OnceLock::new();
@@ -64,9 +99,21 @@ impl InternalUuids {
             ACT_EXEC => Some(self.act_exec),
             ACT_LOGIN => Some(self.act_login),
             ACT_DIRECT_TCPIP => Some(self.act_direct_tcpip),
+            ACT_SFTP => Some(self.act_sftp),
+            ACT_SCP => Some(self.act_scp),
+            ACT_REMOTE_FORWARD => Some(self.act_remote_forward),
+            ACT_AGENT_FORWARD => Some(self.act_agent_forward),
+            ACT_OBSERVE => Some(self.act_observe),
             _ => None,
         }
     }
+
+    /// Name of an internal action by its UUID
+    pub fn action_name(&self, id: Uuid) -> Option<&'static str> {
+        INTERNAL_ACTIONS
+            .into_iter()
+            .find(|name| self.action_uuid(name) == Some(id))
+    }
 }
 
 pub const TABLE_CASBIN_RULE: &str = "CASBIN_RULE";
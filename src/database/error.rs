@@ -1,3 +1,4 @@
+use crate::error::ErrorCode;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -19,4 +20,56 @@ pub enum DatabaseError {
 
     #[error(transparent)]
     CasbinNameValidation(#[from] super::models::casbin_rule::ValidateError),
-}
\ No newline at end of file
+
+    #[error(transparent)]
+    ApiTokenValidation(#[from] super::models::api_token::ValidateError),
+
+    #[error(transparent)]
+    BannedIpValidation(#[from] super::models::banned_ip::ValidateError),
+
+    #[error(transparent)]
+    MaintenanceWindowValidation(#[from] super::models::maintenance_window::ValidateError),
+
+    #[error("Log chain broken: {0}")]
+    LogChain(#[from] super::models::log::ChainError),
+
+    #[error("{table} '{key}' not found")]
+    NotFound { table: &'static str, key: String },
+
+    #[error("Still bound by {count} active target secret(s)")]
+    ActiveBindings { count: i64 },
+
+    #[error("Table: {0} is not empty")]
+    NotEmpty(&'static str),
+
+    #[error("{table} row {id} was changed or removed by someone else")]
+    StaleRow { table: &'static str, id: uuid::Uuid },
+
+    #[error("An access request for this binding is already pending")]
+    AccessRequestPending,
+
+    #[error("Access request {id} is already {status}")]
+    AccessRequestDecided { id: uuid::Uuid, status: String },
+}
+impl DatabaseError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            DatabaseError::Sqlx(e) => crate::error::sqlx_code(e),
+            DatabaseError::UserValidation(_)
+            | DatabaseError::TargetValidation(_)
+            | DatabaseError::SecretValidation(_)
+            | DatabaseError::PermissionPolicyValidation(_)
+            | DatabaseError::CasbinNameValidation(_)
+            | DatabaseError::ApiTokenValidation(_)
+            | DatabaseError::BannedIpValidation(_)
+            | DatabaseError::MaintenanceWindowValidation(_) => ErrorCode::InvalidInput,
+            DatabaseError::NotFound { .. } => ErrorCode::NotFound,
+            DatabaseError::ActiveBindings { .. }
+            | DatabaseError::NotEmpty(_)
+            | DatabaseError::StaleRow { .. }
+            | DatabaseError::AccessRequestPending
+            | DatabaseError::AccessRequestDecided { .. }
+            | DatabaseError::LogChain(_) => ErrorCode::Conflict,
+        }
+    }
+}
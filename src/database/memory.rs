@@ -0,0 +1,2610 @@
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use log::{debug, info};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::database::DatabaseRepository;
+use crate::database::common::{
+    INTERNAL_ACTION_TYPE, INTERNAL_OBJECT_TYPE, MAX_ROLE_DEPTH, TAG_GROUP_PREFIX,
+};
+use crate::database::error::DatabaseError;
+use crate::database::models::casbin_rule::ValidateError;
+use crate::database::models::log::GENESIS_HASH;
+use crate::database::models::{
+    AccessRequest, AccessRequestView, ApiToken, BannedIp, CasbinName, CasbinRule, CasbinRuleGroup,
+    Log, MaintenanceWindow, ObjectGroup, PermissionPolicy, PolicyChange, PolicyChangeView,
+    RecordingView, Role, Secret, SecretInfo, SecretVersion, SessionRecording, SshSession,
+    SshSessionView, TagFilter, Target, TargetInfo, TargetSecret, TargetSecretName, TargetTag,
+    Timestamp, TraceRule, User, UserGroup, UserGroupMember, UserWithRole,
+};
+use crate::error::Error;
+
+lazy_static! {
+    /// Named in-memory stores, shared by every repository opened with the same name.
+    /// This mirrors SQLite's shared-cache memory databases, so the admin CLI,
+    /// `--init` and the server can all see the same data within one process.
+    static ref STORES: Mutex<HashMap<String, Arc<RwLock<Tables>>>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Default)]
+struct Tables {
+    users: HashMap<Uuid, User>,
+    targets: HashMap<Uuid, Target>,
+    secrets: HashMap<Uuid, Secret>,
+    secret_versions: Vec<SecretVersion>,
+    target_secrets: HashMap<Uuid, TargetSecret>,
+    target_tags: HashMap<Uuid, TargetTag>,
+    user_groups: HashMap<Uuid, UserGroup>,
+    user_group_members: HashMap<Uuid, UserGroupMember>,
+    api_tokens: HashMap<Uuid, ApiToken>,
+    access_requests: HashMap<Uuid, AccessRequest>,
+    trace_rules: HashMap<Uuid, TraceRule>,
+    banned_ips: HashMap<Uuid, BannedIp>,
+    maintenance_windows: HashMap<Uuid, MaintenanceWindow>,
+    casbin_rules: HashMap<Uuid, CasbinRule>,
+    casbin_names: HashMap<Uuid, CasbinName>,
+    logs: Vec<Log>,
+    policy_changes: Vec<PolicyChange>,
+    session_recordings: HashMap<Uuid, SessionRecording>,
+    sessions: HashMap<Uuid, SshSession>,
+    /// Stands in for SQLite's `policy_version` triggers
+    policy_version: i64,
+}
+
+impl Tables {
+    fn casbin_name(&self, id: &Uuid) -> Option<&CasbinName> {
+        self.casbin_names.get(id)
+    }
+
+    fn casbin_name_label(&self, id: &Uuid) -> Option<String> {
+        self.casbin_name(id).map(|n| n.name.clone())
+    }
+
+    /// Label of a g1 group, either a role or a user group
+    fn subject_group_label(&self, id: &Uuid) -> Option<String> {
+        self.casbin_name_label(id)
+            .or_else(|| self.user_groups.get(id).map(|g| g.name.clone()))
+    }
+
+    /// `user@target:port`, or `user(secret)@target:port` if `with_secret_name`
+    fn target_secret_label(&self, id: &Uuid, with_secret_name: bool) -> Option<String> {
+        let ts = self.target_secrets.get(id)?;
+        let t = self.targets.get(&ts.target_id)?;
+        let s = self.secrets.get(&ts.secret_id)?;
+        if with_secret_name {
+            Some(format!("{}({})@{}:{}", s.user, s.name, t.name, t.port))
+        } else {
+            Some(format!("{}@{}:{}", s.user, t.name, t.port))
+        }
+    }
+
+    /// Bindings of the targets carrying the tag of each active `tag:` group
+    fn tag_rule_groups(&self) -> Vec<CasbinRuleGroup> {
+        let mut rules = Vec::new();
+        for n in self.casbin_names.values() {
+            if n.ptype != "g2" || !n.is_active {
+                continue;
+            }
+            let Some(tag) = n
+                .name
+                .strip_prefix(TAG_GROUP_PREFIX)
+                .and_then(|v| v.parse::<TagFilter>().ok())
+            else {
+                continue;
+            };
+            for tt in self.target_tags.values().filter(|t| tag.matches(t)) {
+                for ts in self
+                    .target_secrets
+                    .values()
+                    .filter(|ts| ts.target_id == tt.target_id)
+                {
+                    let Some(label) = self.target_secret_label(&ts.id, false) else {
+                        continue;
+                    };
+                    rules.push(CasbinRuleGroup {
+                        id: tt.id,
+                        v0: ts.id,
+                        v0_object_label: Some(label),
+                        v0_group_label: None,
+                        v1: n.id,
+                        v1_object_label: None,
+                        v1_group_label: Some(n.name.clone()),
+                    });
+                }
+            }
+        }
+
+        rules
+    }
+
+    fn target_secret_name(
+        &self,
+        pid: Uuid,
+        id: &Uuid,
+        active_only: bool,
+    ) -> Option<TargetSecretName> {
+        let ts = self.target_secrets.get(id)?;
+        let t = self.targets.get(&ts.target_id)?;
+        let s = self.secrets.get(&ts.secret_id)?;
+        if active_only && !(ts.is_active && t.is_active && s.is_active) {
+            return None;
+        }
+        Some(TargetSecretName {
+            pid,
+            id: ts.id,
+            target_id: t.id,
+            target_name: t.name.clone(),
+            target_hostname: t.hostname.clone(),
+            target_port: t.port,
+            secret_id: s.id,
+            secret_user: s.user.clone(),
+        })
+    }
+
+    /// The user, their active user groups and the `g1` roles above them,
+    /// `MAX_ROLE_DEPTH` levels up at most
+    fn user_roles(&self, user_id: &Uuid) -> HashSet<Uuid> {
+        let mut roles: HashSet<Uuid> = HashSet::from([*user_id]);
+        roles.extend(
+            self.user_group_members
+                .values()
+                .filter(|m| m.user_id == *user_id)
+                .filter(|m| {
+                    self.user_groups
+                        .get(&m.group_id)
+                        .is_some_and(|g| g.is_active)
+                })
+                .map(|m| m.group_id),
+        );
+        let mut level = roles.clone();
+        for _ in 0..MAX_ROLE_DEPTH {
+            level = self
+                .casbin_rules
+                .values()
+                .filter(|r| r.ptype == "g1" && level.contains(&r.v1) && !roles.contains(&r.v0))
+                .map(|r| r.v0)
+                .collect();
+            if level.is_empty() {
+                break;
+            }
+            roles.extend(&level);
+        }
+        roles
+    }
+
+    fn policies_for_user(&self, user_id: &Uuid) -> Vec<CasbinRule> {
+        let roles = self.user_roles(user_id);
+        self.casbin_rules
+            .values()
+            .filter(|r| r.ptype == "p" && roles.contains(&r.v0))
+            .cloned()
+            .collect()
+    }
+
+    /// Drops casbin rules referencing any of `ids`
+    fn delete_rules_referencing(&mut self, ids: &HashSet<Uuid>) {
+        self.policy_version += 1;
+        self.casbin_rules
+            .retain(|_, r| !ids.contains(&r.v0) && !ids.contains(&r.v1) && !ids.contains(&r.v2));
+    }
+
+    /// Drops the target_secrets matching `f` and the casbin rules referencing them
+    fn delete_target_secrets_where(&mut self, f: impl Fn(&TargetSecret) -> bool) {
+        let ids: HashSet<Uuid> = self
+            .target_secrets
+            .values()
+            .filter(|ts| f(ts))
+            .map(|ts| ts.id)
+            .collect();
+        self.target_secrets.retain(|id, _| !ids.contains(id));
+        self.delete_rules_referencing(&ids);
+    }
+
+    fn active_bindings(&self, f: impl Fn(&TargetSecret) -> bool) -> i64 {
+        self.target_secrets
+            .values()
+            .filter(|ts| ts.is_active && f(ts))
+            .count() as i64
+    }
+
+    fn check_user_unique(&self, user: &User) -> Result<(), Error> {
+        if self
+            .users
+            .values()
+            .any(|u| u.id != user.id && u.username == user.username)
+        {
+            return Err(unique_violation("users.username"));
+        }
+        Ok(())
+    }
+
+    fn check_target_unique(&self, target: &Target) -> Result<(), Error> {
+        if self
+            .targets
+            .values()
+            .any(|t| t.id != target.id && t.name == target.name)
+        {
+            return Err(unique_violation("targets.name"));
+        }
+        Ok(())
+    }
+
+    fn check_secret_unique(&self, secret: &Secret) -> Result<(), Error> {
+        if self
+            .secrets
+            .values()
+            .any(|s| s.id != secret.id && s.name == secret.name)
+        {
+            return Err(unique_violation("secrets.name"));
+        }
+        Ok(())
+    }
+
+    /// Same as `update_secret_tx` of the sqlite backend
+    fn update_secret(&mut self, secret: &Secret) -> Result<Secret, Error> {
+        let mut updated_secret = secret.clone();
+        updated_secret.updated_at = Timestamp::now();
+
+        self.check_secret_unique(&updated_secret)?;
+        match self.secrets.get_mut(&updated_secret.id) {
+            Some(s) if s.version == updated_secret.version => {
+                if !s.same_credentials(&updated_secret) {
+                    self.secret_versions.push(SecretVersion::of(s));
+                }
+                updated_secret.version += 1;
+                *s = updated_secret.clone();
+            }
+            _ => {
+                return Err(Error::Database(DatabaseError::StaleRow {
+                    table: "secrets",
+                    id: updated_secret.id,
+                }));
+            }
+        }
+        Ok(updated_secret)
+    }
+
+    fn check_target_secret_unique(&self, ts: &TargetSecret) -> Result<(), Error> {
+        if self
+            .target_secrets
+            .values()
+            .any(|v| v.id != ts.id && v.target_id == ts.target_id && v.secret_id == ts.secret_id)
+        {
+            return Err(unique_violation(
+                "target_secrets.target_id, target_secrets.secret_id",
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_target_tag_unique(&self, tag: &TargetTag) -> Result<(), Error> {
+        if self
+            .target_tags
+            .values()
+            .any(|t| t.id != tag.id && t.target_id == tag.target_id && t.key == tag.key)
+        {
+            return Err(unique_violation("target_tags.target_id, target_tags.key"));
+        }
+        Ok(())
+    }
+
+    fn check_user_group_unique(&self, group: &UserGroup) -> Result<(), Error> {
+        if self
+            .user_groups
+            .values()
+            .any(|g| g.id != group.id && g.name == group.name)
+        {
+            return Err(unique_violation("user_groups.name"));
+        }
+        Ok(())
+    }
+
+    fn check_user_group_member_unique(&self, member: &UserGroupMember) -> Result<(), Error> {
+        if self.user_group_members.values().any(|m| {
+            m.id != member.id && m.group_id == member.group_id && m.user_id == member.user_id
+        }) {
+            return Err(unique_violation(
+                "user_group_members.group_id, user_group_members.user_id",
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_casbin_rule_unique(&self, rule: &CasbinRule) -> Result<(), Error> {
+        if self.casbin_rules.values().any(|r| {
+            r.id != rule.id
+                && r.ptype == rule.ptype
+                && r.v0 == rule.v0
+                && r.v1 == rule.v1
+                && r.v2 == rule.v2
+                && r.v3 == rule.v3
+                && r.v4 == rule.v4
+                && r.v5 == rule.v5
+        }) {
+            return Err(unique_violation(
+                "casbin_rule.ptype, casbin_rule.v0, casbin_rule.v1, casbin_rule.v2, casbin_rule.v3, casbin_rule.v4, casbin_rule.v5",
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_casbin_name_unique(&self, name: &CasbinName) -> Result<(), Error> {
+        if self
+            .casbin_names
+            .values()
+            .any(|n| n.id != name.id && n.name == name.name)
+        {
+            return Err(unique_violation("casbin_names.name"));
+        }
+        Ok(())
+    }
+}
+
+/// Mimics the error SQLite reports on a UNIQUE/PRIMARY KEY conflict, so callers
+/// matching on [`sqlx::error::ErrorKind::UniqueViolation`] behave the same.
+#[derive(Debug)]
+struct UniqueViolation {
+    message: String,
+}
+
+impl std::fmt::Display for UniqueViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for UniqueViolation {}
+
+impl sqlx::error::DatabaseError for UniqueViolation {
+    fn message(&self) -> &str {
+        &self.message
+    }
+
+    fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+        self
+    }
+
+    fn kind(&self) -> sqlx::error::ErrorKind {
+        sqlx::error::ErrorKind::UniqueViolation
+    }
+}
+
+fn unique_violation(columns: &str) -> Error {
+    Error::Sqlx(sqlx::Error::Database(Box::new(UniqueViolation {
+        message: format!("UNIQUE constraint failed: {}", columns),
+    })))
+}
+
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+/// Volatile repository backed by hash maps, for tests and demos.
+/// Nothing is written to disk and all data is lost when the process exits.
+pub struct MemoryRepository {
+    tables: Arc<RwLock<Tables>>,
+}
+
+impl MemoryRepository {
+    pub async fn new(name: &str) -> Result<Self, Error> {
+        info!("Opening in-memory database: {}", name);
+
+        let tables = STORES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(name.to_string())
+            .or_default()
+            .clone();
+
+        let repo = Self { tables };
+        repo.initialize().await?;
+
+        Ok(repo)
+    }
+
+    /// Write lock for changes to the tables the role manager and target
+    /// lists are built from, bumping the policy version
+    async fn write_policy(&self) -> tokio::sync::RwLockWriteGuard<'_, Tables> {
+        let mut tables = self.tables.write().await;
+        tables.policy_version += 1;
+        tables
+    }
+}
+
+#[async_trait]
+impl DatabaseRepository for MemoryRepository {
+    async fn initialize(&self) -> Result<(), Error> {
+        debug!("Initializing in-memory database");
+        Ok(())
+    }
+
+    // User operations
+    async fn create_user(&self, user: &User) -> Result<User, Error> {
+        debug!("Creating user: '{}({})'", user.username, user.id);
+        let mut tables = self.tables.write().await;
+        if tables.users.contains_key(&user.id) {
+            return Err(unique_violation("users.id"));
+        }
+        tables.check_user_unique(user)?;
+        tables.users.insert(user.id, user.clone());
+
+        debug!(
+            "User created successfully: '{}({})'",
+            user.username, user.id
+        );
+        Ok(user.clone())
+    }
+
+    async fn get_user_by_id(&self, id: &Uuid) -> Result<Option<User>, Error> {
+        Ok(self.tables.read().await.users.get(id).cloned())
+    }
+
+    async fn get_user_by_username(
+        &self,
+        username: &str,
+        active_only: bool,
+    ) -> Result<Option<User>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .users
+            .values()
+            .find(|u| u.username == username && (!active_only || u.is_active))
+            .cloned())
+    }
+
+    async fn update_user(&self, user: &User) -> Result<User, Error> {
+        debug!("Updating user: '{}({})'", user.username, user.id);
+        let mut updated_user = user.clone();
+        updated_user.updated_at = Timestamp::now();
+
+        let mut tables = self.tables.write().await;
+        tables.check_user_unique(&updated_user)?;
+        match tables.users.get_mut(&updated_user.id) {
+            Some(u) if u.version == updated_user.version => {
+                updated_user.version += 1;
+                *u = updated_user.clone();
+            }
+            _ => {
+                return Err(Error::Database(DatabaseError::StaleRow {
+                    table: "users",
+                    id: updated_user.id,
+                }));
+            }
+        }
+
+        debug!(
+            "User updated successfully: '{}({})'",
+            updated_user.username, updated_user.id
+        );
+        Ok(updated_user)
+    }
+
+    async fn delete_user(&self, id: &Uuid) -> Result<bool, Error> {
+        debug!("Deleting user: id={}", id);
+        let mut tables = self.write_policy().await;
+        // Drop the user's policies and role memberships
+        tables.delete_rules_referencing(&HashSet::from([*id]));
+        tables.user_group_members.retain(|_, m| m.user_id != *id);
+        tables.api_tokens.retain(|_, t| t.user_id != *id);
+        tables.access_requests.retain(|_, r| r.user_id != *id);
+        let deleted = tables.users.remove(id).is_some();
+        if deleted {
+            debug!("User deleted successfully: id={}", id);
+        }
+        Ok(deleted)
+    }
+
+    async fn list_users_with_role(&self, active_only: bool) -> Result<Vec<UserWithRole>, Error> {
+        let tables = self.tables.read().await;
+        let mut users: Vec<UserWithRole> = tables
+            .users
+            .values()
+            .filter(|u| !active_only || u.is_active)
+            .map(|u| {
+                let role = tables
+                    .casbin_rules
+                    .values()
+                    .filter(|r| r.v1 == u.id)
+                    .filter_map(|r| tables.casbin_name_label(&r.v0))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                UserWithRole {
+                    user: u.clone(),
+                    role,
+                }
+            })
+            .collect();
+        users.sort_by(|a, b| a.user.username.cmp(&b.user.username));
+
+        Ok(users)
+    }
+
+    async fn list_users(&self, active_only: bool) -> Result<Vec<User>, Error> {
+        let mut users: Vec<User> = self
+            .tables
+            .read()
+            .await
+            .users
+            .values()
+            .filter(|u| !active_only || u.is_active)
+            .cloned()
+            .collect();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+
+        Ok(users)
+    }
+
+    // UserGroup operations
+    async fn create_user_group(&self, group: &UserGroup) -> Result<UserGroup, Error> {
+        debug!("Creating user group: '{}({})'", group.name, group.id);
+        let mut tables = self.write_policy().await;
+        if tables.user_groups.contains_key(&group.id) {
+            return Err(unique_violation("user_groups.id"));
+        }
+        tables.check_user_group_unique(group)?;
+        tables.user_groups.insert(group.id, group.clone());
+
+        Ok(group.clone())
+    }
+
+    async fn update_user_group(&self, group: &UserGroup) -> Result<UserGroup, Error> {
+        debug!("Updating user group: '{}({})'", group.name, group.id);
+        let mut updated_group = group.clone();
+        updated_group.updated_at = Timestamp::now();
+
+        let mut tables = self.write_policy().await;
+        tables.check_user_group_unique(&updated_group)?;
+        if let Some(g) = tables.user_groups.get_mut(&updated_group.id) {
+            *g = updated_group.clone();
+        }
+
+        Ok(updated_group)
+    }
+
+    async fn delete_user_group(&self, id: &Uuid) -> Result<bool, Error> {
+        debug!("Deleting user group: id={}", id);
+        let mut tables = self.write_policy().await;
+        tables.delete_rules_referencing(&HashSet::from([*id]));
+        tables.user_group_members.retain(|_, m| m.group_id != *id);
+
+        Ok(tables.user_groups.remove(id).is_some())
+    }
+
+    async fn get_user_group_by_id(&self, id: &Uuid) -> Result<Option<UserGroup>, Error> {
+        Ok(self.tables.read().await.user_groups.get(id).cloned())
+    }
+
+    async fn get_user_group_by_name(&self, name: &str) -> Result<Option<UserGroup>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .user_groups
+            .values()
+            .find(|g| g.name == name)
+            .cloned())
+    }
+
+    async fn list_user_groups(&self, active_only: bool) -> Result<Vec<UserGroup>, Error> {
+        let mut groups: Vec<UserGroup> = self
+            .tables
+            .read()
+            .await
+            .user_groups
+            .values()
+            .filter(|g| !active_only || g.is_active)
+            .cloned()
+            .collect();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(groups)
+    }
+
+    async fn add_user_group_member(
+        &self,
+        member: &UserGroupMember,
+    ) -> Result<UserGroupMember, Error> {
+        debug!(
+            "Adding user {} to user group {}",
+            member.user_id, member.group_id
+        );
+        let mut tables = self.write_policy().await;
+        if tables.user_group_members.contains_key(&member.id) {
+            return Err(unique_violation("user_group_members.id"));
+        }
+        tables.check_user_group_member_unique(member)?;
+        tables.user_group_members.insert(member.id, member.clone());
+
+        Ok(member.clone())
+    }
+
+    async fn remove_user_group_member(
+        &self,
+        group_id: &Uuid,
+        user_id: &Uuid,
+    ) -> Result<bool, Error> {
+        debug!("Removing user {} from user group {}", user_id, group_id);
+        let mut tables = self.write_policy().await;
+        let before = tables.user_group_members.len();
+        tables
+            .user_group_members
+            .retain(|_, m| !(m.group_id == *group_id && m.user_id == *user_id));
+
+        Ok(tables.user_group_members.len() < before)
+    }
+
+    async fn list_user_group_members(&self) -> Result<Vec<UserGroupMember>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .user_group_members
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    async fn list_users_in_group(&self, group_id: &Uuid) -> Result<Vec<User>, Error> {
+        let tables = self.tables.read().await;
+        let mut users: Vec<User> = tables
+            .user_group_members
+            .values()
+            .filter(|m| m.group_id == *group_id)
+            .filter_map(|m| tables.users.get(&m.user_id))
+            .cloned()
+            .collect();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+
+        Ok(users)
+    }
+
+    async fn list_user_group_rule_groups(&self) -> Result<Vec<CasbinRuleGroup>, Error> {
+        let tables = self.tables.read().await;
+        Ok(tables
+            .user_group_members
+            .values()
+            .filter_map(|m| {
+                let g = tables
+                    .user_groups
+                    .get(&m.group_id)
+                    .filter(|g| g.is_active)?;
+                let u = tables.users.get(&m.user_id)?;
+                Some(CasbinRuleGroup {
+                    id: m.id,
+                    v0: g.id,
+                    v0_object_label: None,
+                    v0_group_label: Some(g.name.clone()),
+                    v1: u.id,
+                    v1_object_label: Some(u.username.clone()),
+                    v1_group_label: None,
+                })
+            })
+            .collect())
+    }
+
+    // API token operations
+    async fn create_api_token(&self, token: &ApiToken) -> Result<ApiToken, Error> {
+        debug!(
+            "Creating api token '{}' for user {}",
+            token.name, token.user_id
+        );
+        let mut tables = self.tables.write().await;
+        if tables.api_tokens.contains_key(&token.id) {
+            return Err(unique_violation("api_tokens.id"));
+        }
+        if tables
+            .api_tokens
+            .values()
+            .any(|t| t.token_hash == token.token_hash)
+        {
+            return Err(unique_violation("api_tokens.token_hash"));
+        }
+        if tables
+            .api_tokens
+            .values()
+            .any(|t| t.user_id == token.user_id && t.name == token.name)
+        {
+            return Err(unique_violation("api_tokens.user_id, api_tokens.name"));
+        }
+        tables.api_tokens.insert(token.id, token.clone());
+
+        Ok(token.clone())
+    }
+
+    async fn update_api_token(&self, token: &ApiToken) -> Result<ApiToken, Error> {
+        debug!("Updating api token: id={}", token.id);
+        let mut updated_token = token.clone();
+        updated_token.updated_at = Timestamp::now();
+        let mut tables = self.tables.write().await;
+        if tables
+            .api_tokens
+            .values()
+            .any(|t| t.id != token.id && t.user_id == token.user_id && t.name == token.name)
+        {
+            return Err(unique_violation("api_tokens.user_id, api_tokens.name"));
+        }
+        if let Some(t) = tables.api_tokens.get_mut(&token.id) {
+            t.name = updated_token.name.clone();
+            t.scopes = updated_token.scopes.clone();
+            t.expires_at = updated_token.expires_at;
+            t.is_active = updated_token.is_active;
+            t.updated_by = updated_token.updated_by;
+            t.updated_at = updated_token.updated_at;
+        }
+
+        Ok(updated_token)
+    }
+
+    async fn delete_api_token(&self, id: &Uuid) -> Result<bool, Error> {
+        debug!("Deleting api token: id={}", id);
+        Ok(self.tables.write().await.api_tokens.remove(id).is_some())
+    }
+
+    async fn get_api_token_by_id(&self, id: &Uuid) -> Result<Option<ApiToken>, Error> {
+        Ok(self.tables.read().await.api_tokens.get(id).cloned())
+    }
+
+    async fn get_api_token_by_hash(&self, token_hash: &str) -> Result<Option<ApiToken>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .api_tokens
+            .values()
+            .find(|t| t.token_hash == token_hash)
+            .cloned())
+    }
+
+    async fn list_api_tokens(&self, user_id: Option<&Uuid>) -> Result<Vec<ApiToken>, Error> {
+        let mut tokens: Vec<ApiToken> = self
+            .tables
+            .read()
+            .await
+            .api_tokens
+            .values()
+            .filter(|t| user_id.is_none_or(|u| t.user_id == *u))
+            .cloned()
+            .collect();
+        tokens.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(tokens)
+    }
+
+    async fn touch_api_token(&self, id: &Uuid, used_at: Timestamp) -> Result<(), Error> {
+        if let Some(t) = self.tables.write().await.api_tokens.get_mut(id) {
+            t.last_used_at = Some(used_at);
+        }
+
+        Ok(())
+    }
+
+    // Trace rule operations
+    async fn create_trace_rule(&self, rule: &TraceRule) -> Result<TraceRule, Error> {
+        debug!(
+            "Creating trace rule {} for user {:?} from {:?}",
+            rule.id, rule.username, rule.client_ip
+        );
+        let mut tables = self.tables.write().await;
+        if tables.trace_rules.contains_key(&rule.id) {
+            return Err(unique_violation("trace_rules.id"));
+        }
+        tables.trace_rules.insert(rule.id, rule.clone());
+
+        Ok(rule.clone())
+    }
+
+    async fn delete_trace_rule(&self, id: &Uuid) -> Result<bool, Error> {
+        debug!("Deleting trace rule: id={}", id);
+        Ok(self.tables.write().await.trace_rules.remove(id).is_some())
+    }
+
+    async fn list_trace_rules(&self) -> Result<Vec<TraceRule>, Error> {
+        let mut rules: Vec<TraceRule> = self
+            .tables
+            .read()
+            .await
+            .trace_rules
+            .values()
+            .cloned()
+            .collect();
+        rules.sort_by_key(|r| r.expires_at);
+
+        Ok(rules)
+    }
+
+    // Access request operations
+    async fn create_access_request(&self, request: &AccessRequest) -> Result<AccessRequest, Error> {
+        debug!(
+            "Creating access request {} of user {} for {}",
+            request.id, request.user_id, request.target_secret_id
+        );
+        let mut tables = self.tables.write().await;
+        if tables.access_requests.contains_key(&request.id) {
+            return Err(unique_violation("access_requests.id"));
+        }
+        tables.access_requests.insert(request.id, request.clone());
+
+        Ok(request.clone())
+    }
+
+    async fn update_access_request(&self, request: &AccessRequest) -> Result<AccessRequest, Error> {
+        debug!(
+            "Updating access request {}: status={}",
+            request.id, request.status
+        );
+        let mut tables = self.tables.write().await;
+        if let Some(r) = tables.access_requests.get_mut(&request.id) {
+            r.status = request.status.clone();
+            r.decided_by = request.decided_by;
+            r.decided_at = request.decided_at;
+        }
+
+        Ok(request.clone())
+    }
+
+    async fn get_access_request_by_id(&self, id: &Uuid) -> Result<Option<AccessRequest>, Error> {
+        Ok(self.tables.read().await.access_requests.get(id).cloned())
+    }
+
+    async fn list_access_requests(
+        &self,
+        user_id: Option<&Uuid>,
+    ) -> Result<Vec<AccessRequest>, Error> {
+        let mut requests: Vec<AccessRequest> = self
+            .tables
+            .read()
+            .await
+            .access_requests
+            .values()
+            .filter(|r| user_id.is_none_or(|u| r.user_id == *u))
+            .cloned()
+            .collect();
+        requests.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+        Ok(requests)
+    }
+
+    async fn list_access_request_views(&self) -> Result<Vec<AccessRequestView>, Error> {
+        let tables = self.tables.read().await;
+        let mut rows: Vec<(bool, AccessRequestView)> = tables
+            .access_requests
+            .values()
+            .map(|r| {
+                let view = AccessRequestView {
+                    id: r.id,
+                    username: tables
+                        .users
+                        .get(&r.user_id)
+                        .map(|u| u.username.clone())
+                        .unwrap_or_default(),
+                    target_secret: tables
+                        .target_secret_label(&r.target_secret_id, false)
+                        .unwrap_or_default(),
+                    duration: r.duration,
+                    reason: r.reason.clone(),
+                    status: r.status.clone(),
+                    created_at: r.created_at,
+                };
+                (r.is_pending(), view)
+            })
+            .collect();
+        rows.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.created_at.cmp(&a.1.created_at)));
+
+        Ok(rows.into_iter().map(|(_, v)| v).collect())
+    }
+
+    // Banned IP operations
+    async fn create_banned_ip(&self, ban: &BannedIp) -> Result<BannedIp, Error> {
+        debug!(
+            "Creating ban {} for {}: {}",
+            ban.id, ban.network, ban.reason
+        );
+        let mut tables = self.tables.write().await;
+        if tables.banned_ips.contains_key(&ban.id) {
+            return Err(unique_violation("banned_ips.id"));
+        }
+        tables.banned_ips.insert(ban.id, ban.clone());
+
+        Ok(ban.clone())
+    }
+
+    async fn delete_banned_ip(&self, id: &Uuid) -> Result<bool, Error> {
+        debug!("Deleting ban: id={}", id);
+        Ok(self.tables.write().await.banned_ips.remove(id).is_some())
+    }
+
+    async fn list_banned_ips(&self) -> Result<Vec<BannedIp>, Error> {
+        let mut bans: Vec<BannedIp> = self
+            .tables
+            .read()
+            .await
+            .banned_ips
+            .values()
+            .cloned()
+            .collect();
+        bans.sort_by_key(|b| std::cmp::Reverse(b.updated_at));
+
+        Ok(bans)
+    }
+
+    async fn delete_expired_banned_ips(&self, now: Timestamp) -> Result<u64, Error> {
+        let mut tables = self.tables.write().await;
+        let before = tables.banned_ips.len();
+        tables.banned_ips.retain(|_, b| !b.is_expired(now));
+
+        Ok((before - tables.banned_ips.len()) as u64)
+    }
+
+    async fn create_maintenance_window(
+        &self,
+        window: &MaintenanceWindow,
+    ) -> Result<MaintenanceWindow, Error> {
+        debug!(
+            "Creating maintenance window {} for {}",
+            window.id,
+            window.print_scope()
+        );
+        window.validate().map_err(DatabaseError::from)?;
+        let mut tables = self.tables.write().await;
+        if tables.maintenance_windows.contains_key(&window.id) {
+            return Err(unique_violation("maintenance_windows.id"));
+        }
+        tables.maintenance_windows.insert(window.id, window.clone());
+
+        Ok(window.clone())
+    }
+
+    async fn delete_maintenance_window(&self, id: &Uuid) -> Result<bool, Error> {
+        debug!("Deleting maintenance window: id={}", id);
+        Ok(self
+            .tables
+            .write()
+            .await
+            .maintenance_windows
+            .remove(id)
+            .is_some())
+    }
+
+    async fn list_maintenance_windows(&self) -> Result<Vec<MaintenanceWindow>, Error> {
+        let mut windows: Vec<MaintenanceWindow> = self
+            .tables
+            .read()
+            .await
+            .maintenance_windows
+            .values()
+            .cloned()
+            .collect();
+        windows.sort_by_key(|w| w.starts_at);
+
+        Ok(windows)
+    }
+
+    // Target operations
+    async fn create_target(&self, target: &Target) -> Result<Target, Error> {
+        debug!("Creating target: '{}({})'", target.name, target.id);
+        let mut tables = self.write_policy().await;
+        if tables.targets.contains_key(&target.id) {
+            return Err(unique_violation("targets.id"));
+        }
+        tables.check_target_unique(target)?;
+        tables.targets.insert(target.id, target.clone());
+
+        debug!(
+            "Target created successfully: '{}({})'",
+            target.name, target.id
+        );
+        Ok(target.clone())
+    }
+
+    async fn get_target_by_id(
+        &self,
+        id: &Uuid,
+        active_only: bool,
+    ) -> Result<Option<Target>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .targets
+            .get(id)
+            .filter(|t| !active_only || t.is_active)
+            .cloned())
+    }
+
+    async fn get_targets_by_ids(&self, ids: &[&Uuid]) -> Result<Vec<Target>, Error> {
+        let tables = self.tables.read().await;
+        Ok(tables
+            .targets
+            .values()
+            .filter(|t| ids.contains(&&t.id))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_targets_by_target_secret_ids(
+        &self,
+        ids: &[&Uuid],
+        active_only: bool,
+    ) -> Result<Vec<Target>, Error> {
+        let tables = self.tables.read().await;
+        Ok(tables
+            .target_secrets
+            .values()
+            .filter(|ts| ids.contains(&&ts.id))
+            .filter_map(|ts| {
+                let t = tables.targets.get(&ts.target_id)?;
+                if active_only && !(ts.is_active && t.is_active) {
+                    return None;
+                }
+                Some(t.clone())
+            })
+            .collect())
+    }
+
+    async fn get_target_by_name(&self, name: &str) -> Result<Option<Target>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .targets
+            .values()
+            .find(|t| t.name == name)
+            .cloned())
+    }
+
+    async fn get_target_by_hostname(&self, hostname: &str) -> Result<Option<Target>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .targets
+            .values()
+            .find(|t| t.hostname == hostname)
+            .cloned())
+    }
+
+    async fn update_target(&self, target: &Target) -> Result<Target, Error> {
+        debug!("Updating target: '{}({})'", target.name, target.id);
+        let mut updated_target = target.clone();
+        updated_target.updated_at = Timestamp::now();
+
+        let mut tables = self.write_policy().await;
+        tables.check_target_unique(&updated_target)?;
+        match tables.targets.get_mut(&updated_target.id) {
+            Some(t) if t.version == updated_target.version => {
+                updated_target.version += 1;
+                *t = updated_target.clone();
+            }
+            _ => {
+                return Err(Error::Database(DatabaseError::StaleRow {
+                    table: "targets",
+                    id: updated_target.id,
+                }));
+            }
+        }
+
+        debug!(
+            "Target updated successfully: '{}({})'",
+            updated_target.name, updated_target.id
+        );
+        Ok(updated_target)
+    }
+
+    async fn delete_target(&self, id: &Uuid, force: bool) -> Result<bool, Error> {
+        debug!("Deleting target: id={}, force={}", id, force);
+        let mut tables = self.write_policy().await;
+        let count = tables.active_bindings(|ts| ts.target_id == *id);
+        if count > 0 && !force {
+            return Err(Error::Database(DatabaseError::ActiveBindings { count }));
+        }
+        tables.delete_target_secrets_where(|ts| ts.target_id == *id);
+        tables.target_tags.retain(|_, t| t.target_id != *id);
+        tables
+            .maintenance_windows
+            .retain(|_, w| w.target_id != Some(*id));
+        let deleted = tables.targets.remove(id).is_some();
+        if deleted {
+            debug!("Target deleted successfully: id={}", id);
+        }
+        Ok(deleted)
+    }
+
+    async fn list_targets(
+        &self,
+        active_only: bool,
+        tags: &[TagFilter],
+    ) -> Result<Vec<Target>, Error> {
+        let tables = self.tables.read().await;
+        Ok(tables
+            .targets
+            .values()
+            .filter(|t| !active_only || t.is_active)
+            .filter(|t| {
+                tags.iter().all(|f| {
+                    tables
+                        .target_tags
+                        .values()
+                        .any(|tag| tag.target_id == t.id && f.matches(tag))
+                })
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn create_target_tag(&self, tag: &TargetTag) -> Result<TargetTag, Error> {
+        debug!(
+            "Creating target tag: '{}' on {}",
+            tag.label(),
+            tag.target_id
+        );
+        let mut tables = self.write_policy().await;
+        if tables.target_tags.contains_key(&tag.id) {
+            return Err(unique_violation("target_tags.id"));
+        }
+        tables.check_target_tag_unique(tag)?;
+        tables.target_tags.insert(tag.id, tag.clone());
+
+        Ok(tag.clone())
+    }
+
+    async fn delete_target_tag(&self, id: &Uuid) -> Result<bool, Error> {
+        debug!("Deleting target tag: id={}", id);
+        Ok(self.write_policy().await.target_tags.remove(id).is_some())
+    }
+
+    async fn list_target_tags(&self) -> Result<Vec<TargetTag>, Error> {
+        let mut tags: Vec<TargetTag> = self
+            .tables
+            .read()
+            .await
+            .target_tags
+            .values()
+            .cloned()
+            .collect();
+        tags.sort_by(|a, b| (&a.key, &a.value).cmp(&(&b.key, &b.value)));
+        Ok(tags)
+    }
+
+    async fn list_tags_for_target(&self, target_id: &Uuid) -> Result<Vec<TargetTag>, Error> {
+        let mut tags: Vec<TargetTag> = self
+            .tables
+            .read()
+            .await
+            .target_tags
+            .values()
+            .filter(|t| t.target_id == *target_id)
+            .cloned()
+            .collect();
+        tags.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(tags)
+    }
+
+    async fn set_target_tags(
+        &self,
+        target_id: &Uuid,
+        tags: &[TargetTag],
+    ) -> Result<Vec<TargetTag>, Error> {
+        debug!("Setting {} tag(s) on target: id={}", tags.len(), target_id);
+        let mut tables = self.write_policy().await;
+        let mut staged = Tables::default();
+        for t in tags {
+            let mut tag = t.clone();
+            tag.target_id = *target_id;
+            staged.check_target_tag_unique(&tag)?;
+            staged.target_tags.insert(tag.id, tag);
+        }
+        tables.target_tags.retain(|_, t| t.target_id != *target_id);
+        tables.target_tags.extend(staged.target_tags);
+
+        Ok(tags.to_vec())
+    }
+
+    async fn sync_tag_groups(&self, updated_by: &Uuid) -> Result<usize, Error> {
+        let mut tables = self.write_policy().await;
+        let labels: HashSet<(String, String)> = tables
+            .target_tags
+            .values()
+            .map(|t| (t.key.clone(), t.value.clone()))
+            .collect();
+
+        for (key, value) in labels.iter() {
+            let name = format!("{TAG_GROUP_PREFIX}{key}={value}");
+            if tables.casbin_names.values().any(|n| n.name == name) {
+                continue;
+            }
+            let group = CasbinName::new("g2".to_string(), name, true, *updated_by);
+            tables.casbin_names.insert(group.id, group);
+        }
+
+        // Nested groups added by hand are kept, only stored binding members go
+        let groups: HashSet<Uuid> = tables
+            .casbin_names
+            .values()
+            .filter(|n| n.ptype == "g2" && n.name.starts_with(TAG_GROUP_PREFIX))
+            .map(|n| n.id)
+            .collect();
+        let bindings: HashSet<Uuid> = tables.target_secrets.keys().copied().collect();
+        tables.casbin_rules.retain(|_, r| {
+            !(r.ptype == "g2" && groups.contains(&r.v1) && bindings.contains(&r.v0))
+        });
+
+        debug!("Synced {} tag group(s)", labels.len());
+        Ok(labels.len())
+    }
+
+    async fn list_tag_rule_groups(&self) -> Result<Vec<CasbinRuleGroup>, Error> {
+        Ok(self.tables.read().await.tag_rule_groups())
+    }
+
+    async fn list_targets_info(&self) -> Result<Vec<TargetInfo>, Error> {
+        let mut targets: Vec<TargetInfo> = self
+            .tables
+            .read()
+            .await
+            .targets
+            .values()
+            .map(|t| TargetInfo {
+                id: t.id,
+                name: t.name.clone(),
+                hostname: t.hostname.clone(),
+                port: t.port,
+            })
+            .collect();
+        targets.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(targets)
+    }
+
+    async fn list_targets_for_user(
+        &self,
+        user_id: &Uuid,
+        active_only: bool,
+    ) -> Result<Vec<TargetSecretName>, Error> {
+        let tables = self.tables.read().await;
+        let mut g2: Vec<(Uuid, Uuid)> = tables
+            .casbin_rules
+            .values()
+            .filter(|r| r.ptype == "g2")
+            .map(|r| (r.v0, r.v1))
+            .collect();
+        g2.extend(tables.tag_rule_groups().iter().map(|r| (r.v0, r.v1)));
+
+        let mut targets = Vec::new();
+        for p in tables.policies_for_user(user_id) {
+            let members: Vec<Uuid> = g2
+                .iter()
+                .filter(|(_, group)| *group == p.v1)
+                .map(|(member, _)| *member)
+                .collect();
+            // A policy object that is not a group refers to the target_secret directly
+            let ids = if members.is_empty() {
+                vec![p.v1]
+            } else {
+                members
+            };
+            targets.extend(
+                ids.iter()
+                    .filter_map(|id| tables.target_secret_name(p.id, id, active_only)),
+            );
+        }
+
+        Ok(targets)
+    }
+
+    async fn list_targets_by_ids(
+        &self,
+        ids: &[&Uuid],
+        pid: &Uuid,
+        active_only: bool,
+    ) -> Result<Vec<TargetSecretName>, Error> {
+        let tables = self.tables.read().await;
+        Ok(tables
+            .target_secrets
+            .keys()
+            .filter(|id| ids.contains(id))
+            .filter_map(|id| tables.target_secret_name(*pid, id, active_only))
+            .collect())
+    }
+
+    async fn get_actions_for_policy(&self, policy_act: &Uuid) -> Result<Vec<Uuid>, Error> {
+        // Look for action groups (g3) that include this action
+        let actions: Vec<Uuid> = self
+            .tables
+            .read()
+            .await
+            .casbin_rules
+            .values()
+            .filter(|r| r.ptype == "g3" && r.v1 == *policy_act)
+            .map(|r| r.v0)
+            .collect();
+
+        if actions.is_empty() {
+            // Return the action itself if no group membership
+            return Ok(vec![*policy_act]);
+        }
+
+        Ok(actions)
+    }
+
+    async fn get_policies_for_user(&self, user_id: &Uuid) -> Result<Vec<CasbinRule>, Error> {
+        Ok(self.tables.read().await.policies_for_user(user_id))
+    }
+
+    async fn list_casbin_rules(&self) -> Result<Vec<CasbinRule>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .casbin_rules
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    async fn get_casbin_rule_by_id(&self, id: &Uuid) -> Result<Option<CasbinRule>, Error> {
+        Ok(self.tables.read().await.casbin_rules.get(id).cloned())
+    }
+
+    async fn list_roles_by_user_id(&self, user_id: &Uuid) -> Result<Vec<Role>, Error> {
+        let tables = self.tables.read().await;
+        let mut roles = Vec::new();
+        for cn in tables.casbin_names.values().filter(|n| n.ptype == "g1") {
+            let bound: Vec<&CasbinRule> = tables
+                .casbin_rules
+                .values()
+                .filter(|r| r.ptype == "g1" && r.v1 == *user_id && r.v0 == cn.id)
+                .collect();
+            if bound.is_empty() {
+                roles.push(Role {
+                    rid: cn.id,
+                    rule_id: None,
+                    role: cn.name.clone(),
+                    is_bound: false,
+                });
+            }
+            for r in bound {
+                roles.push(Role {
+                    rid: cn.id,
+                    rule_id: Some(r.id),
+                    role: cn.name.clone(),
+                    is_bound: true,
+                });
+            }
+        }
+
+        Ok(roles)
+    }
+
+    async fn list_casbin_rule_group_by_ptype(
+        &self,
+        ptype: &str,
+    ) -> Result<Vec<CasbinRuleGroup>, Error> {
+        let tables = self.tables.read().await;
+        let name_if = |id: &Uuid, internal_action: bool| {
+            tables
+                .casbin_name(id)
+                .filter(|n| (n.ptype == INTERNAL_ACTION_TYPE) == internal_action)
+                .map(|n| n.name.clone())
+        };
+
+        let groups = tables
+            .casbin_rules
+            .values()
+            .filter(|r| r.ptype == ptype)
+            .map(|r| match ptype {
+                "g1" => CasbinRuleGroup {
+                    id: r.id,
+                    v0: r.v0,
+                    v0_object_label: None,
+                    v0_group_label: tables.subject_group_label(&r.v0),
+                    v1: r.v1,
+                    v1_object_label: tables.users.get(&r.v1).map(|u| u.username.clone()),
+                    v1_group_label: tables.subject_group_label(&r.v1),
+                },
+                "g2" => CasbinRuleGroup {
+                    id: r.id,
+                    v0: r.v0,
+                    v0_object_label: tables.target_secret_label(&r.v0, false).or_else(|| {
+                        tables
+                            .casbin_name(&r.v0)
+                            .filter(|n| n.ptype == INTERNAL_OBJECT_TYPE)
+                            .map(|n| n.name.clone())
+                    }),
+                    v0_group_label: tables.casbin_name_label(&r.v0),
+                    v1: r.v1,
+                    v1_object_label: None,
+                    v1_group_label: tables.casbin_name_label(&r.v1),
+                },
+                "g3" => CasbinRuleGroup {
+                    id: r.id,
+                    v0: r.v0,
+                    v0_object_label: name_if(&r.v0, true),
+                    v0_group_label: name_if(&r.v0, false),
+                    v1: r.v1,
+                    v1_object_label: None,
+                    v1_group_label: name_if(&r.v1, false),
+                },
+                _ => unreachable!(),
+            })
+            .collect();
+
+        Ok(groups)
+    }
+
+    async fn list_casbin_rules_by_ptype(&self, ptype: &str) -> Result<Vec<CasbinRule>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .casbin_rules
+            .values()
+            .filter(|r| r.ptype == ptype)
+            .cloned()
+            .collect())
+    }
+
+    async fn create_casbin_rule(&self, rule: &CasbinRule) -> Result<CasbinRule, Error> {
+        debug!("Creating casbin_rule: '({})'", rule.id);
+        let mut tables = self.write_policy().await;
+        if tables.casbin_rules.contains_key(&rule.id) {
+            return Err(unique_violation("casbin_rule.id"));
+        }
+        tables.check_casbin_rule_unique(rule)?;
+        tables.casbin_rules.insert(rule.id, rule.clone());
+
+        debug!("Casbin_rule created successfully: '({})'", rule.id);
+        Ok(rule.clone())
+    }
+
+    async fn update_casbin_rule(&self, rule: &CasbinRule) -> Result<CasbinRule, Error> {
+        debug!("Updating casbin_rule: '({})'", rule.id);
+        let mut updated_rule = rule.clone();
+        updated_rule.updated_at = Timestamp::now();
+
+        let mut tables = self.write_policy().await;
+        tables.check_casbin_rule_unique(&updated_rule)?;
+        if let Some(r) = tables.casbin_rules.get_mut(&updated_rule.id) {
+            *r = updated_rule.clone();
+        }
+
+        debug!("Casbin_rule updated successfully: '({})'", updated_rule.id);
+        Ok(updated_rule)
+    }
+
+    async fn delete_casbin_rule_by_v0_v1(
+        &self,
+        ptype: &str,
+        v0: &Uuid,
+        v1: &Uuid,
+    ) -> Result<bool, Error> {
+        debug!(
+            "Deleting casbin_rule where ptype={} v0={} v1={}",
+            ptype, v0, v1
+        );
+        let mut tables = self.write_policy().await;
+        let before = tables.casbin_rules.len();
+        tables
+            .casbin_rules
+            .retain(|_, r| r.ptype != ptype || r.v0 != *v0 || r.v1 != *v1);
+
+        let deleted = tables.casbin_rules.len() < before;
+        if deleted {
+            debug!(
+                "Casbin_rule deleted successfully: ptype={} v0={} v1={}",
+                ptype, v0, v1
+            );
+        }
+        Ok(deleted)
+    }
+
+    async fn delete_casbin_rule(&self, id: &Uuid) -> Result<bool, Error> {
+        debug!("Deleting casbin_rule: '({})'", id);
+        let deleted = self.write_policy().await.casbin_rules.remove(id).is_some();
+        if deleted {
+            debug!("Casbin_rule deleted successfully: '({})'", id);
+        }
+        Ok(deleted)
+    }
+
+    async fn create_casbin_name(&self, name: &CasbinName) -> Result<CasbinName, Error> {
+        let mut tables = self.write_policy().await;
+        if tables.casbin_names.contains_key(&name.id) {
+            return Err(unique_violation("casbin_names.id"));
+        }
+        tables.check_casbin_name_unique(name)?;
+        tables.casbin_names.insert(name.id, name.clone());
+
+        Ok(name.clone())
+    }
+
+    async fn get_casbin_name_by_name(&self, name: &str) -> Result<Option<CasbinName>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .casbin_names
+            .values()
+            .find(|n| n.name == name)
+            .cloned())
+    }
+
+    async fn get_casbin_name_by_id(&self, id: &Uuid) -> Result<Option<CasbinName>, Error> {
+        Ok(self.tables.read().await.casbin_name(id).cloned())
+    }
+
+    async fn list_user_group(&self) -> Result<Vec<ObjectGroup>, Error> {
+        let tables = self.tables.read().await;
+        let mut rows: Vec<ObjectGroup> = tables
+            .users
+            .values()
+            .map(|u| ObjectGroup {
+                id: u.id,
+                name: u.username.clone(),
+                is_group: false,
+            })
+            .collect();
+        rows.extend(
+            tables
+                .casbin_names
+                .values()
+                .filter(|n| n.ptype == "g1")
+                .map(|n| ObjectGroup {
+                    id: n.id,
+                    name: n.name.clone(),
+                    is_group: true,
+                }),
+        );
+        rows.extend(tables.user_groups.values().map(|g| ObjectGroup {
+            id: g.id,
+            name: g.name.clone(),
+            is_group: true,
+        }));
+
+        Ok(rows)
+    }
+
+    async fn list_target_group(&self) -> Result<Vec<ObjectGroup>, Error> {
+        let tables = self.tables.read().await;
+        let mut rows: Vec<ObjectGroup> = tables
+            .target_secrets
+            .keys()
+            .map(|id| ObjectGroup {
+                id: *id,
+                name: tables.target_secret_label(id, true).unwrap_or_default(),
+                is_group: false,
+            })
+            .collect();
+        rows.extend(
+            tables
+                .casbin_names
+                .values()
+                .filter(|n| n.ptype == "g2" || n.ptype == INTERNAL_OBJECT_TYPE)
+                .map(|n| ObjectGroup {
+                    id: n.id,
+                    name: n.name.clone(),
+                    is_group: n.ptype == "g2",
+                }),
+        );
+
+        Ok(rows)
+    }
+
+    async fn list_action_group(&self) -> Result<Vec<ObjectGroup>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .casbin_names
+            .values()
+            .filter(|n| n.ptype == "g3" || n.ptype == INTERNAL_ACTION_TYPE)
+            .map(|n| ObjectGroup {
+                id: n.id,
+                name: n.name.clone(),
+                is_group: n.ptype == "g3",
+            })
+            .collect())
+    }
+
+    async fn list_casbin_names(&self, active_only: bool) -> Result<Vec<CasbinName>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .casbin_names
+            .values()
+            .filter(|n| !active_only || n.is_active)
+            .cloned()
+            .collect())
+    }
+
+    async fn list_casbin_names_user_visible(
+        &self,
+        active_only: bool,
+    ) -> Result<Vec<CasbinName>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .casbin_names
+            .values()
+            .filter(|n| !n.is_internal() && (!active_only || n.is_active))
+            .cloned()
+            .collect())
+    }
+
+    async fn list_casbin_names_by_ptype(
+        &self,
+        ptype: &str,
+        active_only: bool,
+    ) -> Result<Vec<CasbinName>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .casbin_names
+            .values()
+            .filter(|n| n.ptype == ptype && (!active_only || n.is_active))
+            .cloned()
+            .collect())
+    }
+
+    async fn update_casbin_name(&self, rule: &CasbinName) -> Result<CasbinName, Error> {
+        let mut tables = self.write_policy().await;
+
+        // Check if this is an existing internal type
+        if let Some(existing) = tables.casbin_name(&rule.id)
+            && existing.is_internal()
+            && (existing.ptype != rule.ptype || existing.name != rule.name)
+        {
+            // Prevent changing the ptype of internal types
+            return Err(Error::Database(DatabaseError::CasbinNameValidation(
+                ValidateError::InternalTypeModification,
+            )));
+        }
+
+        let mut updated_rule = rule.clone();
+        updated_rule.updated_at = Timestamp::now();
+
+        tables.check_casbin_name_unique(&updated_rule)?;
+        if let Some(n) = tables.casbin_names.get_mut(&updated_rule.id) {
+            *n = updated_rule.clone();
+        }
+
+        Ok(updated_rule)
+    }
+
+    async fn delete_casbin_name(&self, id: &Uuid) -> Result<bool, Error> {
+        debug!("Deleting casbin_name: id={}", id);
+        let mut tables = self.write_policy().await;
+
+        // Check if this is an internal type
+        if let Some(casbin_name) = tables.casbin_name(id)
+            && casbin_name.is_internal()
+        {
+            return Err(Error::Database(DatabaseError::CasbinNameValidation(
+                ValidateError::InternalTypeModification,
+            )));
+        }
+
+        // Rules using the group as subject, object or action
+        tables.delete_rules_referencing(&HashSet::from([*id]));
+        let deleted = tables.casbin_names.remove(id).is_some();
+        if deleted {
+            debug!("Casbin_name deleted successfully: id={}", id);
+        }
+        Ok(deleted)
+    }
+
+    async fn create_casbin_names_batch(
+        &self,
+        casbin_names: &[CasbinName],
+    ) -> Result<Vec<CasbinName>, Error> {
+        let mut tables = self.write_policy().await;
+        // Validate the whole batch first, the insert is all-or-nothing
+        let mut staged = Tables::default();
+        for n in casbin_names {
+            if tables.casbin_names.contains_key(&n.id) || staged.casbin_names.contains_key(&n.id) {
+                return Err(unique_violation("casbin_names.id"));
+            }
+            tables.check_casbin_name_unique(n)?;
+            staged.check_casbin_name_unique(n)?;
+            staged.casbin_names.insert(n.id, n.clone());
+        }
+        tables.casbin_names.extend(staged.casbin_names);
+
+        Ok(casbin_names.to_vec())
+    }
+
+    async fn list_secrets(&self, active_only: bool) -> Result<Vec<Secret>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .secrets
+            .values()
+            .filter(|s| !active_only || s.is_active)
+            .cloned()
+            .collect())
+    }
+
+    async fn list_secrets_for_target(&self, target_id: &Uuid) -> Result<Vec<SecretInfo>, Error> {
+        let tables = self.tables.read().await;
+        let mut secrets: Vec<SecretInfo> = tables
+            .secrets
+            .values()
+            .map(|s| SecretInfo {
+                id: s.id,
+                name: s.name.clone(),
+                user: s.user.clone(),
+                is_bound: tables
+                    .target_secrets
+                    .values()
+                    .any(|ts| ts.secret_id == s.id && ts.target_id == *target_id && ts.is_active),
+            })
+            .collect();
+        secrets.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(secrets)
+    }
+
+    async fn create_secret(&self, secret: &Secret) -> Result<Secret, Error> {
+        debug!("Creating secret: '{}({})'", secret.name, secret.id);
+        let mut tables = self.tables.write().await;
+        if tables.secrets.contains_key(&secret.id) {
+            return Err(unique_violation("secrets.id"));
+        }
+        tables.check_secret_unique(secret)?;
+        tables.secrets.insert(secret.id, secret.clone());
+
+        debug!(
+            "Secret created successfully: '{}({})'",
+            secret.name, secret.id
+        );
+        Ok(secret.clone())
+    }
+
+    /// Upsert the binding between a target and a secret.
+    ///
+    /// * If the pair `(target_id, secret_id)` does **not** exist yet → insert a new row
+    /// * If it **does** exist → flip `is_active` to the provided value
+    async fn upsert_target_secret(
+        &self,
+        target_id: &Uuid,
+        secret_id: &Uuid,
+        is_active: bool,
+        updated_by: &Uuid,
+    ) -> Result<(), Error> {
+        debug!(
+            "Upserting target_secret binding: target_id={}, secret_id={}, is_active={}",
+            target_id, secret_id, is_active
+        );
+        let exists = self
+            .tables
+            .read()
+            .await
+            .target_secrets
+            .values()
+            .find(|ts| ts.target_id == *target_id && ts.secret_id == *secret_id)
+            .cloned();
+
+        match exists {
+            Some(mut ts) => {
+                ts.is_active = is_active;
+                self.update_target_secret(&ts).await?;
+                debug!(
+                    "Target_secret binding updated: target_id={}, secret_id={}",
+                    target_id, secret_id
+                );
+            }
+            None => {
+                let mut ts = TargetSecret::new(*target_id, *secret_id, *updated_by);
+                ts.is_active = is_active;
+                self.create_target_secret(&ts).await?;
+                debug!(
+                    "Target_secret binding created: target_id={}, secret_id={}",
+                    target_id, secret_id
+                );
+            }
+        };
+
+        Ok(())
+    }
+
+    async fn get_secret_by_target_secret_id(
+        &self,
+        id: &Uuid,
+        active_only: bool,
+    ) -> Result<Option<Secret>, Error> {
+        let tables = self.tables.read().await;
+        let Some(ts) = tables.target_secrets.get(id) else {
+            return Ok(None);
+        };
+        Ok(tables
+            .secrets
+            .get(&ts.secret_id)
+            .filter(|s| !active_only || (ts.is_active && s.is_active))
+            .cloned())
+    }
+
+    async fn get_secret_by_id(&self, id: &Uuid) -> Result<Option<Secret>, Error> {
+        Ok(self.tables.read().await.secrets.get(id).cloned())
+    }
+
+    async fn get_secrets_by_ids(&self, ids: &[&Uuid]) -> Result<Vec<Secret>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .secrets
+            .values()
+            .filter(|s| ids.contains(&&s.id))
+            .cloned()
+            .collect())
+    }
+
+    async fn update_secret(&self, secret: &Secret) -> Result<Secret, Error> {
+        debug!("Updating secret: '{}({})'", secret.name, secret.id);
+        let updated_secret = self.tables.write().await.update_secret(secret)?;
+
+        debug!(
+            "Secret updated successfully: '{}({})'",
+            updated_secret.name, updated_secret.id
+        );
+        Ok(updated_secret)
+    }
+
+    async fn list_secret_versions(&self, secret_id: &Uuid) -> Result<Vec<SecretVersion>, Error> {
+        let tables = self.tables.read().await;
+        let mut versions: Vec<SecretVersion> = tables
+            .secret_versions
+            .iter()
+            .filter(|v| v.secret_id == *secret_id)
+            .cloned()
+            .collect();
+        versions.sort_by(|a, b| b.version.cmp(&a.version));
+        Ok(versions)
+    }
+
+    async fn rollback_secret(
+        &self,
+        secret_id: &Uuid,
+        version: i64,
+        updated_by: &Uuid,
+    ) -> Result<Secret, Error> {
+        debug!(
+            "Rolling back secret: id={} to version={}",
+            secret_id, version
+        );
+        let mut tables = self.tables.write().await;
+        let v = tables
+            .secret_versions
+            .iter()
+            .find(|v| v.secret_id == *secret_id && v.version == version)
+            .cloned()
+            .ok_or_else(|| DatabaseError::NotFound {
+                table: "secret_versions",
+                key: format!("{}@{}", secret_id, version),
+            })?;
+        let mut secret =
+            tables
+                .secrets
+                .get(secret_id)
+                .cloned()
+                .ok_or_else(|| DatabaseError::NotFound {
+                    table: "secrets",
+                    key: secret_id.to_string(),
+                })?;
+
+        secret.restore(&v);
+        secret.updated_by = *updated_by;
+        let secret = tables.update_secret(&secret)?;
+
+        info!(
+            "Secret '{}({})' rolled back to version {}",
+            secret.name, secret.id, version
+        );
+        Ok(secret)
+    }
+
+    async fn delete_secret(&self, id: &Uuid, force: bool) -> Result<bool, Error> {
+        debug!("Deleting secret: id={}, force={}", id, force);
+        let mut tables = self.tables.write().await;
+        let count = tables.active_bindings(|ts| ts.secret_id == *id);
+        if count > 0 && !force {
+            return Err(Error::Database(DatabaseError::ActiveBindings { count }));
+        }
+        tables.delete_target_secrets_where(|ts| ts.secret_id == *id);
+        tables.secret_versions.retain(|v| v.secret_id != *id);
+        let deleted = tables.secrets.remove(id).is_some();
+        if deleted {
+            debug!("Secret deleted successfully: id={}", id);
+        }
+        Ok(deleted)
+    }
+
+    async fn create_casbin_rules_batch(
+        &self,
+        rules: &[CasbinRule],
+    ) -> Result<Vec<CasbinRule>, Error> {
+        let mut tables = self.write_policy().await;
+        let mut staged = Tables::default();
+        for r in rules {
+            if tables.casbin_rules.contains_key(&r.id) || staged.casbin_rules.contains_key(&r.id) {
+                return Err(unique_violation("casbin_rule.id"));
+            }
+            tables.check_casbin_rule_unique(r)?;
+            staged.check_casbin_rule_unique(r)?;
+            staged.casbin_rules.insert(r.id, r.clone());
+        }
+        tables.casbin_rules.extend(staged.casbin_rules);
+
+        Ok(rules.to_vec())
+    }
+
+    async fn create_target_tags_batch(&self, tags: &[TargetTag]) -> Result<Vec<TargetTag>, Error> {
+        let mut tables = self.write_policy().await;
+        let mut staged = Tables::default();
+        for t in tags {
+            if tables.target_tags.contains_key(&t.id) || staged.target_tags.contains_key(&t.id) {
+                return Err(unique_violation("target_tags.id"));
+            }
+            tables.check_target_tag_unique(t)?;
+            staged.check_target_tag_unique(t)?;
+            staged.target_tags.insert(t.id, t.clone());
+        }
+        tables.target_tags.extend(staged.target_tags);
+
+        Ok(tags.to_vec())
+    }
+
+    async fn create_user_groups_batch(
+        &self,
+        groups: &[UserGroup],
+    ) -> Result<Vec<UserGroup>, Error> {
+        let mut tables = self.write_policy().await;
+        let mut staged = Tables::default();
+        for g in groups {
+            if tables.user_groups.contains_key(&g.id) || staged.user_groups.contains_key(&g.id) {
+                return Err(unique_violation("user_groups.id"));
+            }
+            tables.check_user_group_unique(g)?;
+            staged.check_user_group_unique(g)?;
+            staged.user_groups.insert(g.id, g.clone());
+        }
+        tables.user_groups.extend(staged.user_groups);
+
+        Ok(groups.to_vec())
+    }
+
+    async fn create_user_group_members_batch(
+        &self,
+        members: &[UserGroupMember],
+    ) -> Result<Vec<UserGroupMember>, Error> {
+        let mut tables = self.write_policy().await;
+        let mut staged = Tables::default();
+        for m in members {
+            if tables.user_group_members.contains_key(&m.id)
+                || staged.user_group_members.contains_key(&m.id)
+            {
+                return Err(unique_violation("user_group_members.id"));
+            }
+            tables.check_user_group_member_unique(m)?;
+            staged.check_user_group_member_unique(m)?;
+            staged.user_group_members.insert(m.id, m.clone());
+        }
+        tables.user_group_members.extend(staged.user_group_members);
+
+        Ok(members.to_vec())
+    }
+
+    async fn create_users_batch(&self, users: &[User]) -> Result<Vec<User>, Error> {
+        let mut tables = self.tables.write().await;
+        let mut staged = Tables::default();
+        for u in users {
+            if tables.users.contains_key(&u.id) || staged.users.contains_key(&u.id) {
+                return Err(unique_violation("users.id"));
+            }
+            tables.check_user_unique(u)?;
+            staged.check_user_unique(u)?;
+            staged.users.insert(u.id, u.clone());
+        }
+        tables.users.extend(staged.users);
+
+        Ok(users.to_vec())
+    }
+
+    async fn create_targets_batch(&self, targets: &[Target]) -> Result<Vec<Target>, Error> {
+        let mut tables = self.write_policy().await;
+        let mut staged = Tables::default();
+        for t in targets {
+            if tables.targets.contains_key(&t.id) || staged.targets.contains_key(&t.id) {
+                return Err(unique_violation("targets.id"));
+            }
+            tables.check_target_unique(t)?;
+            staged.check_target_unique(t)?;
+            staged.targets.insert(t.id, t.clone());
+        }
+        tables.targets.extend(staged.targets);
+
+        Ok(targets.to_vec())
+    }
+
+    async fn list_target_secrets(&self, active_only: bool) -> Result<Vec<TargetSecret>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .target_secrets
+            .values()
+            .filter(|ts| !active_only || ts.is_active)
+            .cloned()
+            .collect())
+    }
+
+    async fn create_target_secret(
+        &self,
+        target_secret: &TargetSecret,
+    ) -> Result<TargetSecret, Error> {
+        let mut tables = self.write_policy().await;
+        if tables.target_secrets.contains_key(&target_secret.id) {
+            return Err(unique_violation("target_secrets.id"));
+        }
+        tables.check_target_secret_unique(target_secret)?;
+        tables
+            .target_secrets
+            .insert(target_secret.id, target_secret.clone());
+
+        Ok(target_secret.clone())
+    }
+
+    async fn update_target_secret(
+        &self,
+        target_secret: &TargetSecret,
+    ) -> Result<TargetSecret, Error> {
+        let mut updated = target_secret.clone();
+        updated.updated_at = Timestamp::now();
+
+        let mut tables = self.write_policy().await;
+        tables.check_target_secret_unique(&updated)?;
+        if let Some(ts) = tables.target_secrets.get_mut(&updated.id) {
+            *ts = updated.clone();
+        }
+
+        Ok(updated)
+    }
+
+    async fn delete_target_secret(&self, id: &Uuid) -> Result<bool, Error> {
+        let mut tables = self.write_policy().await;
+        tables.delete_rules_referencing(&HashSet::from([*id]));
+        Ok(tables.target_secrets.remove(id).is_some())
+    }
+
+    async fn check_object_active(&self, id: &Uuid) -> Result<bool, Error> {
+        let tables = self.tables.read().await;
+
+        // Check if it's an active target_secret
+        if tables.target_secret_name(Uuid::nil(), id, true).is_some() {
+            return Ok(true);
+        }
+
+        // Check if it's an active internal_object
+        Ok(tables
+            .casbin_name(id)
+            .is_some_and(|n| n.ptype == INTERNAL_OBJECT_TYPE && n.is_active))
+    }
+
+    async fn create_secrets_batch(&self, secrets: &[Secret]) -> Result<Vec<Secret>, Error> {
+        let mut tables = self.tables.write().await;
+        let mut staged = Tables::default();
+        for s in secrets {
+            if tables.secrets.contains_key(&s.id) || staged.secrets.contains_key(&s.id) {
+                return Err(unique_violation("secrets.id"));
+            }
+            tables.check_secret_unique(s)?;
+            staged.check_secret_unique(s)?;
+            staged.secrets.insert(s.id, s.clone());
+        }
+        tables.secrets.extend(staged.secrets);
+
+        Ok(secrets.to_vec())
+    }
+
+    async fn create_target_secrets_batch(
+        &self,
+        secrets: &[TargetSecret],
+    ) -> Result<Vec<TargetSecret>, Error> {
+        let mut tables = self.write_policy().await;
+        let mut staged = Tables::default();
+        for ts in secrets {
+            if tables.target_secrets.contains_key(&ts.id)
+                || staged.target_secrets.contains_key(&ts.id)
+            {
+                return Err(unique_violation("target_secrets.id"));
+            }
+            tables.check_target_secret_unique(ts)?;
+            staged.check_target_secret_unique(ts)?;
+            staged.target_secrets.insert(ts.id, ts.clone());
+        }
+        tables.target_secrets.extend(staged.target_secrets);
+
+        Ok(secrets.to_vec())
+    }
+
+    async fn search_users(&self, query: &str) -> Result<Vec<User>, Error> {
+        let mut users: Vec<User> = self
+            .tables
+            .read()
+            .await
+            .users
+            .values()
+            .filter(|u| {
+                contains_ignore_case(&u.username, query)
+                    || u.email
+                        .as_deref()
+                        .is_some_and(|e| contains_ignore_case(e, query))
+            })
+            .cloned()
+            .collect();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+
+        Ok(users)
+    }
+
+    async fn search_targets(&self, query: &str) -> Result<Vec<Target>, Error> {
+        let mut targets: Vec<Target> = self
+            .tables
+            .read()
+            .await
+            .targets
+            .values()
+            .filter(|t| {
+                contains_ignore_case(&t.name, query)
+                    || contains_ignore_case(&t.hostname, query)
+                    || t.description
+                        .as_deref()
+                        .is_some_and(|d| contains_ignore_case(d, query))
+            })
+            .cloned()
+            .collect();
+        targets.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(targets)
+    }
+
+    async fn count_users(&self) -> Result<i64, Error> {
+        Ok(self.tables.read().await.users.len() as i64)
+    }
+
+    async fn count_targets(&self) -> Result<i64, Error> {
+        Ok(self.tables.read().await.targets.len() as i64)
+    }
+
+    async fn count_active_users(&self) -> Result<i64, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .users
+            .values()
+            .filter(|u| u.is_active)
+            .count() as i64)
+    }
+
+    async fn count_active_targets(&self) -> Result<i64, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .targets
+            .values()
+            .filter(|t| t.is_active)
+            .count() as i64)
+    }
+
+    // log operations
+    async fn insert_log(&self, log: &Log) -> Result<(), Error> {
+        let mut tables = self.tables.write().await;
+        if tables.logs.iter().any(|l| {
+            l.created_at == log.created_at
+                && l.connection_id == log.connection_id
+                && l.detail == log.detail
+        }) {
+            return Err(unique_violation(
+                "logs.created_at, logs.connection_id, logs.detail",
+            ));
+        }
+        let prev = tables.logs.last().map_or(GENESIS_HASH, |l| l.hash.as_str());
+        let mut log = log.clone();
+        log.chain_after(prev);
+        tables.logs.push(log);
+
+        Ok(())
+    }
+
+    async fn list_logs(&self) -> Result<Vec<Log>, Error> {
+        let mut logs = self.tables.read().await.logs.clone();
+        logs.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+        Ok(logs)
+    }
+
+    // policy change operations
+    async fn insert_policy_change(&self, change: &PolicyChange) -> Result<(), Error> {
+        debug!(
+            "Recording policy change {}: rule {} {}",
+            change.id, change.rule_id, change.action
+        );
+        let mut tables = self.tables.write().await;
+        if tables.policy_changes.iter().any(|c| c.id == change.id) {
+            return Err(unique_violation("policy_changes.id"));
+        }
+        tables.policy_changes.push(change.clone());
+
+        Ok(())
+    }
+
+    async fn list_policy_changes(&self) -> Result<Vec<PolicyChange>, Error> {
+        let mut changes: Vec<PolicyChange> = self
+            .tables
+            .read()
+            .await
+            .policy_changes
+            .iter()
+            .rev()
+            .cloned()
+            .collect();
+        // Stable, entries of the same millisecond stay newest first
+        changes.sort_by(|a, b| b.changed_at.cmp(&a.changed_at));
+
+        Ok(changes)
+    }
+
+    async fn list_policy_change_views(&self) -> Result<Vec<PolicyChangeView>, Error> {
+        let tables = self.tables.read().await;
+        let mut views: Vec<PolicyChangeView> = tables
+            .policy_changes
+            .iter()
+            .rev()
+            .map(|c| PolicyChangeView {
+                id: c.id,
+                rule_id: c.rule_id,
+                action: c.action.clone(),
+                diff: c.diff.clone(),
+                changed_by: tables
+                    .users
+                    .get(&c.changed_by)
+                    .map(|u| u.username.clone())
+                    .unwrap_or_default(),
+                changed_at: c.changed_at,
+            })
+            .collect();
+        views.sort_by(|a, b| b.changed_at.cmp(&a.changed_at));
+
+        Ok(views)
+    }
+
+    async fn create_session_recording(
+        &self,
+        recording: &SessionRecording,
+    ) -> Result<SessionRecording, Error> {
+        debug!(
+            "Creating session_recording: user_id={}, target_id={}",
+            recording.user_id, recording.target_id
+        );
+        let mut tables = self.tables.write().await;
+        if tables.session_recordings.contains_key(&recording.id) {
+            return Err(unique_violation("session_recordings.id"));
+        }
+        tables
+            .session_recordings
+            .insert(recording.id, recording.clone());
+
+        Ok(recording.clone())
+    }
+
+    async fn update_session_recording(
+        &self,
+        recording: &SessionRecording,
+    ) -> Result<SessionRecording, Error> {
+        debug!("Updating session_recording: id={}", recording.id);
+        if let Some(r) = self
+            .tables
+            .write()
+            .await
+            .session_recordings
+            .get_mut(&recording.id)
+        {
+            r.file_path = recording.file_path.clone();
+            r.started_at = recording.started_at;
+            r.ended_at = recording.ended_at;
+            r.status = recording.status.clone();
+            r.duration_ms = recording.duration_ms;
+            r.active_ms = recording.active_ms;
+            r.event_count = recording.event_count;
+        }
+
+        Ok(recording.clone())
+    }
+
+    async fn get_session_recording_by_id(
+        &self,
+        id: &Uuid,
+    ) -> Result<Option<SessionRecording>, Error> {
+        Ok(self.tables.read().await.session_recordings.get(id).cloned())
+    }
+
+    async fn list_session_recordings(
+        &self,
+        limit: Option<i64>,
+    ) -> Result<Vec<SessionRecording>, Error> {
+        let mut rows: Vec<SessionRecording> = self
+            .tables
+            .read()
+            .await
+            .session_recordings
+            .values()
+            .cloned()
+            .collect();
+        rows.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+
+        // Negative limit means no limit, as in SQLite
+        if let Some(l) = limit
+            && l >= 0
+        {
+            rows.truncate(l as usize);
+        }
+
+        Ok(rows)
+    }
+
+    async fn list_recording_view_for_user(
+        &self,
+        user_id: &Uuid,
+    ) -> Result<Vec<RecordingView>, Error> {
+        let tables = self.tables.read().await;
+        let mut rows: Vec<RecordingView> = tables
+            .session_recordings
+            .values()
+            .filter(|r| r.user_id == *user_id)
+            .map(|r| {
+                let target_secret = match (
+                    tables.secrets.get(&r.secret_id),
+                    tables.targets.get(&r.target_id),
+                ) {
+                    (Some(s), Some(t)) => format!("{}@{}:{}", s.user, t.name, t.port),
+                    _ => String::new(),
+                };
+                RecordingView {
+                    id: r.id,
+                    target_secret,
+                    file_path: r.file_path.clone(),
+                    started_at: r.started_at,
+                    ended_at: r.ended_at,
+                    status: r.status.clone(),
+                    duration_ms: r.duration_ms,
+                    active_ms: r.active_ms,
+                }
+            })
+            .collect();
+        rows.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+
+        Ok(rows)
+    }
+
+    async fn list_session_recordings_for_user(
+        &self,
+        user_id: &Uuid,
+    ) -> Result<Vec<SessionRecording>, Error> {
+        let mut rows: Vec<SessionRecording> = self
+            .tables
+            .read()
+            .await
+            .session_recordings
+            .values()
+            .filter(|r| r.user_id == *user_id)
+            .cloned()
+            .collect();
+        rows.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+
+        Ok(rows)
+    }
+
+    async fn list_session_recordings_for_target(
+        &self,
+        target_id: &Uuid,
+    ) -> Result<Vec<SessionRecording>, Error> {
+        let mut rows: Vec<SessionRecording> = self
+            .tables
+            .read()
+            .await
+            .session_recordings
+            .values()
+            .filter(|r| r.target_id == *target_id)
+            .cloned()
+            .collect();
+        rows.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+
+        Ok(rows)
+    }
+
+    async fn create_ssh_session(&self, session: &SshSession) -> Result<SshSession, Error> {
+        debug!(
+            "Creating session: user_id={}, target_secret_id={}",
+            session.user_id, session.target_secret_id
+        );
+        let mut tables = self.tables.write().await;
+        if tables.sessions.contains_key(&session.id) {
+            return Err(unique_violation("sessions.id"));
+        }
+        tables.sessions.insert(session.id, session.clone());
+
+        Ok(session.clone())
+    }
+
+    async fn update_ssh_session(&self, session: &SshSession) -> Result<SshSession, Error> {
+        debug!("Updating session: id={}", session.id);
+        if let Some(s) = self.tables.write().await.sessions.get_mut(&session.id) {
+            s.ended_at = session.ended_at;
+            s.bytes_in = session.bytes_in;
+            s.bytes_out = session.bytes_out;
+            s.recording_path = session.recording_path.clone();
+            s.termination_reason = session.termination_reason.clone();
+        }
+
+        Ok(session.clone())
+    }
+
+    async fn get_ssh_session_by_id(&self, id: &Uuid) -> Result<Option<SshSession>, Error> {
+        Ok(self.tables.read().await.sessions.get(id).cloned())
+    }
+
+    async fn list_ssh_sessions(&self, limit: Option<i64>) -> Result<Vec<SshSession>, Error> {
+        let mut rows: Vec<SshSession> = self
+            .tables
+            .read()
+            .await
+            .sessions
+            .values()
+            .cloned()
+            .collect();
+        rows.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+
+        if let Some(l) = limit
+            && l >= 0
+        {
+            rows.truncate(l as usize);
+        }
+
+        Ok(rows)
+    }
+
+    async fn get_last_ssh_session_for_user(
+        &self,
+        user_id: &Uuid,
+    ) -> Result<Option<SshSession>, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .sessions
+            .values()
+            .filter(|s| s.user_id == *user_id)
+            .max_by_key(|s| s.started_at)
+            .cloned())
+    }
+
+    async fn count_ssh_sessions(
+        &self,
+        user_id: &Uuid,
+        target_secret_ids: &[&Uuid],
+        since: Timestamp,
+    ) -> Result<u64, Error> {
+        Ok(self
+            .tables
+            .read()
+            .await
+            .sessions
+            .values()
+            .filter(|s| {
+                s.user_id == *user_id
+                    && s.started_at >= since
+                    && target_secret_ids.contains(&&s.target_secret_id)
+            })
+            .count() as u64)
+    }
+
+    async fn list_active_ssh_sessions(&self) -> Result<Vec<SshSessionView>, Error> {
+        let tables = self.tables.read().await;
+        let mut rows: Vec<SshSessionView> = tables
+            .sessions
+            .values()
+            .filter(|s| s.is_active())
+            .map(|s| SshSessionView {
+                id: s.id,
+                connection_id: s.connection_id,
+                username: tables
+                    .users
+                    .get(&s.user_id)
+                    .map(|u| u.username.clone())
+                    .unwrap_or_default(),
+                target_secret: tables
+                    .target_secret_label(&s.target_secret_id, false)
+                    .map(|label| match (&s.login_as, label.rsplit_once('@')) {
+                        (Some(account), Some((_, rest))) => format!("{}@{}", account, rest),
+                        _ => label,
+                    })
+                    .unwrap_or_default(),
+                request: s.request.clone(),
+                started_at: s.started_at,
+                bytes_in: s.bytes_in,
+                bytes_out: s.bytes_out,
+                ticket: s.ticket.clone(),
+                access_reason: s.access_reason.clone(),
+            })
+            .collect();
+        rows.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+
+        Ok(rows)
+    }
+
+    async fn end_active_ssh_sessions(&self, reason: &str) -> Result<u64, Error> {
+        let now = Timestamp::now();
+        let mut count = 0;
+        for s in self
+            .tables
+            .write()
+            .await
+            .sessions
+            .values_mut()
+            .filter(|s| s.is_active())
+        {
+            s.ended_at = Some(now);
+            s.termination_reason = Some(reason.to_string());
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    async fn health_check(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn get_policy_version(&self) -> Result<i64, Error> {
+        Ok(self.tables.read().await.policy_version)
+    }
+
+    async fn list_permission_polices(&self) -> Result<Vec<PermissionPolicy>, Error> {
+        let tables = self.tables.read().await;
+        Ok(tables
+            .casbin_rules
+            .values()
+            .filter(|r| r.ptype == "p" || r.ptype == "p_deny")
+            .map(|r| PermissionPolicy {
+                rule: r.clone(),
+                user_role: tables
+                    .subject_group_label(&r.v0)
+                    .or_else(|| tables.users.get(&r.v0).map(|u| u.username.clone()))
+                    .unwrap_or_default(),
+                target_group: tables
+                    .target_secret_label(&r.v1, true)
+                    .or_else(|| tables.casbin_name_label(&r.v1))
+                    .unwrap_or_default(),
+                action_group: tables.casbin_name_label(&r.v2).unwrap_or_default(),
+            })
+            .collect())
+    }
+}
@@ -1,15 +1,33 @@
+//! Storage behind `DatabaseRepository`, implemented for SQLite and for an
+//! in-memory store with the same semantics (`test.rs` runs both).
+//!
+//! - Ids are `Uuid` and `*_at` columns are `models::Timestamp`,
+//!   milliseconds in an INTEGER.
+//! - Users, targets and secrets carry a `version`, `update_*` refuses a
+//!   stale copy with `DatabaseError::StaleRow`.
+//! - Updating a secret's credentials first copies the old ones into
+//!   `secret_versions`, `rollback_secret` restores one.
+//! - `logs` is a hash chain (`models/log.rs`); it and `policy_changes` are
+//!   append-only.
+//! - Writes to the policy tables bump `policy_version`, which servers poll
+//!   to reload their role manager.
+
 pub mod common;
 pub mod error;
+pub(crate) mod memory;
 pub(crate) mod models;
 pub(crate) mod service;
 pub(crate) mod sqlite;
+mod test;
 
 use crate::{database::models::UserWithRole, error::Error};
 use async_trait::async_trait;
 use models::{
-    CasbinName, CasbinRule, CasbinRuleGroup, Log, ObjectGroup, PermissionPolicy, RecordingView,
-    Role, Secret, SecretInfo, SessionRecording, Target, TargetInfo, TargetSecret, TargetSecretName,
-    User,
+    AccessRequest, AccessRequestView, ApiToken, BannedIp, CasbinName, CasbinRule, CasbinRuleGroup,
+    Log, MaintenanceWindow, ObjectGroup, PermissionPolicy, PolicyChange, PolicyChangeView,
+    RecordingView, Role, Secret, SecretInfo, SecretVersion, SessionRecording, SshSession,
+    SshSessionView, TagFilter, Target, TargetInfo, TargetSecret, TargetSecretName, TargetTag,
+    Timestamp, TraceRule, User, UserGroup, UserGroupMember,
 };
 pub use uuid::Uuid;
 
@@ -18,6 +36,11 @@ pub use uuid::Uuid;
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum DatabaseConfig {
     Sqlite { path: String },
+    /// Volatile storage for tests and demos, repositories sharing a name share data
+    Memory {
+        #[serde(default = "default_memory_name")]
+        name: String,
+    },
     // Future database support can be added here
     // Mysql { host: String, port: u16, database: String, username: String, password: String },
     // Postgresql { host: String, port: u16, database: String, username: String, password: String },
@@ -29,10 +52,17 @@ impl std::fmt::Display for DatabaseConfig {
             DatabaseConfig::Sqlite { path } => {
                 write!(f, "sqlite({})", path)
             }
+            DatabaseConfig::Memory { name } => {
+                write!(f, "memory({})", name)
+            }
         }
     }
 }
 
+fn default_memory_name() -> String {
+    "rustion".to_string()
+}
+
 impl Default for DatabaseConfig {
     fn default() -> Self {
         DatabaseConfig::Sqlite {
@@ -57,10 +87,80 @@ pub trait DatabaseRepository: Send + Sync {
         active_only: bool,
     ) -> Result<Option<User>, Error>;
     async fn update_user(&self, user: &User) -> Result<User, Error>;
+    /// Also removes the user's policies, role and group memberships, and API tokens
     async fn delete_user(&self, id: &Uuid) -> Result<bool, Error>;
     async fn list_users(&self, active_only: bool) -> Result<Vec<User>, Error>;
     async fn list_users_with_role(&self, active_only: bool) -> Result<Vec<UserWithRole>, Error>;
 
+    /// UserGroup operations
+    async fn create_user_group(&self, group: &UserGroup) -> Result<UserGroup, Error>;
+    async fn update_user_group(&self, group: &UserGroup) -> Result<UserGroup, Error>;
+    /// Also removes the memberships and policies of the group
+    async fn delete_user_group(&self, id: &Uuid) -> Result<bool, Error>;
+    async fn get_user_group_by_id(&self, id: &Uuid) -> Result<Option<UserGroup>, Error>;
+    async fn get_user_group_by_name(&self, name: &str) -> Result<Option<UserGroup>, Error>;
+    async fn list_user_groups(&self, active_only: bool) -> Result<Vec<UserGroup>, Error>;
+    async fn add_user_group_member(
+        &self,
+        member: &UserGroupMember,
+    ) -> Result<UserGroupMember, Error>;
+    async fn remove_user_group_member(
+        &self,
+        group_id: &Uuid,
+        user_id: &Uuid,
+    ) -> Result<bool, Error>;
+    async fn list_user_group_members(&self) -> Result<Vec<UserGroupMember>, Error>;
+    async fn list_users_in_group(&self, group_id: &Uuid) -> Result<Vec<User>, Error>;
+    /// Memberships of active groups, shaped like g1 rules (v0 = group, v1 = user)
+    async fn list_user_group_rule_groups(&self) -> Result<Vec<CasbinRuleGroup>, Error>;
+
+    /// API token operations
+    async fn create_api_token(&self, token: &ApiToken) -> Result<ApiToken, Error>;
+    /// Updates name, scopes, expiry and active flag, the hash never changes
+    async fn update_api_token(&self, token: &ApiToken) -> Result<ApiToken, Error>;
+    async fn delete_api_token(&self, id: &Uuid) -> Result<bool, Error>;
+    async fn get_api_token_by_id(&self, id: &Uuid) -> Result<Option<ApiToken>, Error>;
+    async fn get_api_token_by_hash(&self, token_hash: &str) -> Result<Option<ApiToken>, Error>;
+    /// All tokens, or only those owned by `user_id`
+    async fn list_api_tokens(&self, user_id: Option<&Uuid>) -> Result<Vec<ApiToken>, Error>;
+    async fn touch_api_token(&self, id: &Uuid, used_at: Timestamp) -> Result<(), Error>;
+
+    /// Trace rule operations
+    async fn create_trace_rule(&self, rule: &TraceRule) -> Result<TraceRule, Error>;
+    async fn delete_trace_rule(&self, id: &Uuid) -> Result<bool, Error>;
+    /// Expired rules included, soonest expiry first
+    async fn list_trace_rules(&self) -> Result<Vec<TraceRule>, Error>;
+
+    /// Access request operations
+    async fn create_access_request(&self, request: &AccessRequest) -> Result<AccessRequest, Error>;
+    /// Records the decision, the other fields never change
+    async fn update_access_request(&self, request: &AccessRequest) -> Result<AccessRequest, Error>;
+    async fn get_access_request_by_id(&self, id: &Uuid) -> Result<Option<AccessRequest>, Error>;
+    /// All requests, or only those of `user_id`, newest first
+    async fn list_access_requests(
+        &self,
+        user_id: Option<&Uuid>,
+    ) -> Result<Vec<AccessRequest>, Error>;
+    /// Pending requests first, then the rest, newest first within each
+    async fn list_access_request_views(&self) -> Result<Vec<AccessRequestView>, Error>;
+
+    /// Banned IP operations
+    async fn create_banned_ip(&self, ban: &BannedIp) -> Result<BannedIp, Error>;
+    async fn delete_banned_ip(&self, id: &Uuid) -> Result<bool, Error>;
+    /// Expired bans included, newest first
+    async fn list_banned_ips(&self) -> Result<Vec<BannedIp>, Error>;
+    /// Drops bans expired at `now`, returns how many
+    async fn delete_expired_banned_ips(&self, now: Timestamp) -> Result<u64, Error>;
+
+    /// Maintenance window operations
+    async fn create_maintenance_window(
+        &self,
+        window: &MaintenanceWindow,
+    ) -> Result<MaintenanceWindow, Error>;
+    async fn delete_maintenance_window(&self, id: &Uuid) -> Result<bool, Error>;
+    /// Past windows included, soonest start first
+    async fn list_maintenance_windows(&self) -> Result<Vec<MaintenanceWindow>, Error>;
+
     /// Target operations
     async fn create_target(&self, target: &Target) -> Result<Target, Error>;
     async fn get_target_by_id(&self, id: &Uuid, active_only: bool)
@@ -74,10 +174,36 @@ pub trait DatabaseRepository: Send + Sync {
     async fn get_target_by_name(&self, name: &str) -> Result<Option<Target>, Error>;
     async fn get_target_by_hostname(&self, hostname: &str) -> Result<Option<Target>, Error>;
     async fn update_target(&self, target: &Target) -> Result<Target, Error>;
-    async fn delete_target(&self, id: &Uuid) -> Result<bool, Error>;
-    async fn list_targets(&self, active_only: bool) -> Result<Vec<Target>, Error>;
+    /// Fails with `DatabaseError::ActiveBindings` while active target_secrets exist, unless
+    /// `force` is set. Bindings and casbin rules referencing them are removed with the target.
+    async fn delete_target(&self, id: &Uuid, force: bool) -> Result<bool, Error>;
+    /// Only targets carrying every tag in `tags` are returned
+    async fn list_targets(
+        &self,
+        active_only: bool,
+        tags: &[TagFilter],
+    ) -> Result<Vec<Target>, Error>;
     async fn list_targets_info(&self) -> Result<Vec<TargetInfo>, Error>;
 
+    /// TargetTag operations
+    async fn create_target_tag(&self, tag: &TargetTag) -> Result<TargetTag, Error>;
+    async fn delete_target_tag(&self, id: &Uuid) -> Result<bool, Error>;
+    async fn list_target_tags(&self) -> Result<Vec<TargetTag>, Error>;
+    async fn list_tags_for_target(&self, target_id: &Uuid) -> Result<Vec<TargetTag>, Error>;
+    /// Replaces all tags of the target with `tags`
+    async fn set_target_tags(
+        &self,
+        target_id: &Uuid,
+        tags: &[TargetTag],
+    ) -> Result<Vec<TargetTag>, Error>;
+    /// Names a `g2` group `tag:key=value` for every distinct tag and drops the
+    /// binding members older versions stored under such groups, they are
+    /// resolved from the tags now. Returns the number of tags.
+    async fn sync_tag_groups(&self, updated_by: &Uuid) -> Result<usize, Error>;
+    /// Members of the active `tag:key=value` groups, shaped like g2 rules
+    /// (v0 = binding of a target carrying the tag, v1 = group)
+    async fn list_tag_rule_groups(&self) -> Result<Vec<CasbinRuleGroup>, Error>;
+
     /// Secret operations
     async fn create_secret(&self, secret: &Secret) -> Result<Secret, Error>;
     async fn update_secret(&self, target: &Secret) -> Result<Secret, Error>;
@@ -89,8 +215,19 @@ pub trait DatabaseRepository: Send + Sync {
         active_only: bool,
     ) -> Result<Option<Secret>, Error>;
     async fn get_secrets_by_ids(&self, ids: &[&Uuid]) -> Result<Vec<Secret>, Error>;
-    async fn delete_secret(&self, id: &Uuid) -> Result<bool, Error>;
+    /// Same semantics as `delete_target`
+    async fn delete_secret(&self, id: &Uuid, force: bool) -> Result<bool, Error>;
     async fn list_secrets_for_target(&self, target_id: &Uuid) -> Result<Vec<SecretInfo>, Error>;
+    /// Credentials replaced by earlier updates, newest first
+    async fn list_secret_versions(&self, secret_id: &Uuid) -> Result<Vec<SecretVersion>, Error>;
+    /// Stores the credentials of `version` as a new update. What they replace becomes
+    /// a version too, so a rollback can be rolled back.
+    async fn rollback_secret(
+        &self,
+        secret_id: &Uuid,
+        version: i64,
+        updated_by: &Uuid,
+    ) -> Result<Secret, Error>;
 
     /// TargetSecret operations
     async fn list_target_secrets(&self, active_only: bool) -> Result<Vec<TargetSecret>, Error>;
@@ -99,6 +236,7 @@ pub trait DatabaseRepository: Send + Sync {
         target_secret: &TargetSecret,
     ) -> Result<TargetSecret, Error>;
     async fn update_target_secret(&self, secret: &TargetSecret) -> Result<TargetSecret, Error>;
+    /// Also removes casbin rules referencing the binding
     async fn delete_target_secret(&self, id: &Uuid) -> Result<bool, Error>;
     async fn upsert_target_secret(
         &self,
@@ -110,6 +248,7 @@ pub trait DatabaseRepository: Send + Sync {
 
     /// CasbinRule operations
     async fn list_casbin_rules(&self) -> Result<Vec<CasbinRule>, Error>;
+    async fn get_casbin_rule_by_id(&self, id: &Uuid) -> Result<Option<CasbinRule>, Error>;
     async fn list_casbin_rules_by_ptype(&self, ptype: &str) -> Result<Vec<CasbinRule>, Error>;
     async fn list_casbin_rule_group_by_ptype(
         &self,
@@ -130,6 +269,7 @@ pub trait DatabaseRepository: Send + Sync {
     /// CasbinName operations - maps UUIDs to human-readable names
     async fn create_casbin_name(&self, name: &CasbinName) -> Result<CasbinName, Error>;
     async fn update_casbin_name(&self, rule: &CasbinName) -> Result<CasbinName, Error>;
+    /// Also removes casbin rules referencing the name
     async fn delete_casbin_name(&self, id: &Uuid) -> Result<bool, Error>;
     async fn get_casbin_name_by_name(&self, name: &str) -> Result<Option<CasbinName>, Error>;
     async fn get_casbin_name_by_id(&self, id: &Uuid) -> Result<Option<CasbinName>, Error>;
@@ -152,6 +292,12 @@ pub trait DatabaseRepository: Send + Sync {
     async fn insert_log(&self, log: &Log) -> Result<(), Error>;
     async fn list_logs(&self) -> Result<Vec<Log>, Error>;
 
+    /// Policy change operations, append-only like the logs
+    async fn insert_policy_change(&self, change: &PolicyChange) -> Result<(), Error>;
+    /// Newest first
+    async fn list_policy_changes(&self) -> Result<Vec<PolicyChange>, Error>;
+    async fn list_policy_change_views(&self) -> Result<Vec<PolicyChangeView>, Error>;
+
     /// Session recording operations
     async fn create_session_recording(
         &self,
@@ -188,6 +334,54 @@ pub trait DatabaseRepository: Send + Sync {
         target_id: &Uuid,
     ) -> Result<Vec<SessionRecording>, Error>;
 
+    /// SSH session operations
+    async fn create_ssh_session(
+        &self,
+        session: &SshSession,
+    ) -> Result<SshSession, Error>;
+
+    async fn update_ssh_session(
+        &self,
+        session: &SshSession,
+    ) -> Result<SshSession, Error>;
+
+    async fn get_ssh_session_by_id(
+        &self,
+        id: &Uuid,
+    ) -> Result<Option<SshSession>, Error>;
+
+    async fn list_ssh_sessions(
+        &self,
+        limit: Option<i64>,
+    ) -> Result<Vec<SshSession>, Error>;
+
+    /// Most recently started session of the user, shown in the MOTD
+    async fn get_last_ssh_session_for_user(
+        &self,
+        user_id: &Uuid,
+    ) -> Result<Option<SshSession>, Error>;
+
+    /// Sessions the user started on any of `target_secret_ids` since
+    /// `since`, counted against session quotas
+    async fn count_ssh_sessions(
+        &self,
+        user_id: &Uuid,
+        target_secret_ids: &[&Uuid],
+        since: Timestamp,
+    ) -> Result<u64, Error>;
+
+    async fn list_active_ssh_sessions(&self) -> Result<Vec<SshSessionView>, Error>;
+
+    /// Closes sessions a previous process left open, returns how many
+    async fn end_active_ssh_sessions(&self, reason: &str) -> Result<u64, Error>;
+
+    /// Cheapest round trip proving the backend still answers queries
+    async fn health_check(&self) -> Result<(), Error>;
+
+    /// Counter bumped by every write to the rules, names, user groups,
+    /// targets, bindings and tags, polled to reload the role manager
+    async fn get_policy_version(&self) -> Result<i64, Error>;
+
     /// casbin operations
     async fn get_policies_for_user(&self, user_id: &Uuid) -> Result<Vec<CasbinRule>, Error>;
     async fn get_actions_for_policy(&self, policy_act: &Uuid) -> Result<Vec<Uuid>, Error>;
@@ -204,6 +398,15 @@ pub trait DatabaseRepository: Send + Sync {
         &self,
         rules: &[CasbinRule],
     ) -> Result<Vec<CasbinRule>, Error>;
+    async fn create_target_tags_batch(&self, tags: &[TargetTag]) -> Result<Vec<TargetTag>, Error>;
+    async fn create_user_groups_batch(
+        &self,
+        groups: &[UserGroup],
+    ) -> Result<Vec<UserGroup>, Error>;
+    async fn create_user_group_members_batch(
+        &self,
+        members: &[UserGroupMember],
+    ) -> Result<Vec<UserGroupMember>, Error>;
 
     /// Search operations
     async fn search_users(&self, query: &str) -> Result<Vec<User>, Error>;
@@ -243,6 +446,10 @@ pub async fn create_repository(
         DatabaseConfig::Sqlite { path } => {
             let repo = sqlite::SqliteRepository::new(path).await?;
             Ok(Box::new(repo))
+        }
+        DatabaseConfig::Memory { name } => {
+            let repo = memory::MemoryRepository::new(name).await?;
+            Ok(Box::new(repo))
         } // Future database implementations can be added here
     }
 }
@@ -1,19 +1,41 @@
+pub(crate) mod access_request;
+pub(crate) mod api_token;
+pub(crate) mod banned_ip;
 pub(crate) mod casbin_rule;
 pub mod log;
+pub(crate) mod maintenance_window;
+pub(crate) mod names;
+pub(crate) mod policy_change;
 pub(crate) mod session_recording;
+pub(crate) mod ssh_session;
 pub(crate) mod target;
 pub(crate) mod target_secret;
+pub(crate) mod trace_rule;
 pub(crate) mod user;
+pub(crate) mod user_group;
 
+pub(crate) use access_request::{AccessRequest, AccessRequestView};
+pub(crate) use api_token::ApiToken;
+pub(crate) use banned_ip::BannedIp;
 pub(crate) use casbin_rule::{
     CasbinName, CasbinRule, CasbinRuleGroup, ObjectGroup, PermissionPolicy, Role,
 };
 pub use log::Log;
+pub(crate) use maintenance_window::MaintenanceWindow;
+pub(crate) use names::Names;
+pub(crate) use policy_change::{PolicyChange, PolicyChangeView};
 pub(crate) use session_recording::{RecordingView, SessionRecording};
-pub(crate) use target::{Target, TargetInfo};
-pub(crate) use target_secret::{Secret, SecretInfo, TargetSecret, TargetSecretName};
+pub(crate) use ssh_session::{SshSession, SshSessionView};
+pub(crate) use target::{
+    HOST_KEY_CONFIRM, HOST_KEY_STRICT, HOST_KEY_TOFU, HostKeyCheck, MAX_VIA_HOPS, PresentedKey,
+    TagFilter, Target, TargetInfo, TargetTag,
+};
+pub(crate) use target_secret::{Secret, SecretInfo, SecretVersion, TargetSecret, TargetSecretName};
+pub(crate) use trace_rule::TraceRule;
 pub(crate) use user::{User, UserWithRole};
+pub(crate) use user_group::{UserGroup, UserGroupMember};
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use sqlx::{
@@ -53,3 +75,60 @@ impl<'r> Decode<'r, Sqlite> for StringArray {
         Ok(StringArray(serde_json::from_str(value)?))
     }
 }
+
+/// Point in time, stored as INTEGER milliseconds since the epoch so existing
+/// rows and mock data keep their layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Timestamp(#[serde(with = "chrono::serde::ts_milliseconds")] pub DateTime<Utc>);
+
+impl Timestamp {
+    pub fn now() -> Self {
+        Timestamp(Utc::now())
+    }
+
+    pub fn from_millis(ms: i64) -> Option<Self> {
+        DateTime::from_timestamp_millis(ms).map(Timestamp)
+    }
+
+    pub fn millis(&self) -> i64 {
+        self.0.timestamp_millis()
+    }
+
+    /// `None` when the result doesn't fit
+    pub fn checked_add(&self, d: std::time::Duration) -> Option<Self> {
+        let d = chrono::TimeDelta::from_std(d).ok()?;
+        self.0.checked_add_signed(d).map(Timestamp)
+    }
+}
+
+impl std::fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.format("%Y-%m-%d %H:%M:%S"))
+    }
+}
+
+impl Type<Sqlite> for Timestamp {
+    fn type_info() -> SqliteTypeInfo {
+        <i64 as Type<Sqlite>>::type_info()
+    }
+    fn compatible(ty: &SqliteTypeInfo) -> bool {
+        <i64 as Type<Sqlite>>::compatible(ty)
+    }
+}
+
+impl<'q> Encode<'q, Sqlite> for Timestamp {
+    fn encode_by_ref(
+        &self,
+        buf: &mut Vec<SqliteArgumentValue<'q>>,
+    ) -> Result<IsNull, sqlx::error::BoxDynError> {
+        buf.push(SqliteArgumentValue::Int64(self.millis()));
+        Ok(IsNull::No)
+    }
+}
+
+impl<'r> Decode<'r, Sqlite> for Timestamp {
+    fn decode(value: SqliteValueRef<'r>) -> Result<Self, sqlx::error::BoxDynError> {
+        let ms = <i64 as Decode<Sqlite>>::decode(value)?;
+        Timestamp::from_millis(ms).ok_or_else(|| format!("timestamp {} out of range", ms).into())
+    }
+}
@@ -0,0 +1,85 @@
+use super::{StringArray, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use uuid::Uuid;
+
+pub const STATUS_PENDING: &str = "pending";
+pub const STATUS_APPROVED: &str = "approved";
+pub const STATUS_DENIED: &str = "denied";
+/// Approved, and every rule it granted was removed by the expired policy
+/// cleanup
+pub const STATUS_EXPIRED: &str = "expired";
+
+/// A user asking for a target binding they can't reach yet. The casbin `p`
+/// rules listed in `rule_ids` are written along with the request, carrying
+/// the `pending` flag until an admin decides.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AccessRequest {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub target_secret_id: Uuid,
+    pub rule_ids: StringArray,
+    /// Seconds of access asked for, counted from the approval
+    pub duration: i64,
+    pub reason: String,
+    /// `pending`, `approved`, `denied` or `expired`
+    pub status: String,
+    pub created_at: Timestamp,
+    pub decided_by: Option<Uuid>,
+    pub decided_at: Option<Timestamp>,
+}
+
+impl AccessRequest {
+    pub fn new(user_id: Uuid, target_secret_id: Uuid, duration: Duration, reason: String) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            user_id,
+            target_secret_id,
+            rule_ids: StringArray(Vec::new()),
+            duration: duration.as_secs() as i64,
+            reason,
+            status: STATUS_PENDING.to_string(),
+            created_at: Timestamp::now(),
+            decided_by: None,
+            decided_at: None,
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.status == STATUS_PENDING
+    }
+
+    pub fn rule_ids(&self) -> Vec<Uuid> {
+        self.rule_ids
+            .0
+            .iter()
+            .filter_map(|id| id.parse().ok())
+            .collect()
+    }
+
+    pub fn print_duration(&self) -> String {
+        print_secs(self.duration)
+    }
+}
+
+fn print_secs(secs: i64) -> String {
+    humantime::format_duration(Duration::from_secs(secs.max(0) as u64)).to_string()
+}
+
+/// Request with user and target labels resolved, for the admin table
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AccessRequestView {
+    pub id: Uuid,
+    pub username: String,
+    pub target_secret: String,
+    pub duration: i64,
+    pub reason: String,
+    pub status: String,
+    pub created_at: Timestamp,
+}
+
+impl AccessRequestView {
+    pub fn print_duration(&self) -> String {
+        print_secs(self.duration)
+    }
+}
@@ -0,0 +1,111 @@
+use super::{StringArray, Timestamp};
+use rand::distr::{Alphanumeric, SampleString};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Plain tokens start with this, so they are easy to spot in logs and scanners
+pub const TOKEN_PREFIX: &str = "rtn_";
+const TOKEN_LEN: usize = 40;
+/// Scope granting everything
+pub const SCOPE_ALL: &str = "*";
+
+/// Bearer token for automation. Only the SHA-256 of the token is stored, the
+/// plain value is handed out once by `ApiToken::generate`.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub(in crate::database) token_hash: String,
+    pub scopes: StringArray,
+    pub expires_at: Option<Timestamp>,
+    pub last_used_at: Option<Timestamp>,
+    pub is_active: bool,
+    pub updated_by: Uuid,
+    pub updated_at: Timestamp,
+}
+
+impl ApiToken {
+    /// Returns the new row and the plain token for its owner
+    pub fn generate(user_id: Uuid, name: String, updated_by: Uuid) -> (Self, String) {
+        let token = format!(
+            "{}{}",
+            TOKEN_PREFIX,
+            Alphanumeric.sample_string(&mut rand::rng(), TOKEN_LEN)
+        );
+        let api_token = Self {
+            id: Uuid::new_v4(),
+            user_id,
+            name,
+            token_hash: Self::hash_token(&token),
+            scopes: StringArray(Vec::new()),
+            expires_at: None,
+            last_used_at: None,
+            is_active: true,
+            updated_by,
+            updated_at: Timestamp::now(),
+        };
+        (api_token, token)
+    }
+
+    pub fn with_scopes(mut self, scopes: Vec<String>) -> Self {
+        self.scopes = StringArray(scopes);
+        self
+    }
+
+    pub fn with_expires_at(mut self, val: Option<Timestamp>) -> Self {
+        self.expires_at = val;
+        self
+    }
+
+    pub fn hash_token(token: &str) -> String {
+        Sha256::digest(token.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.0.iter().any(|s| s == scope || s == SCOPE_ALL)
+    }
+
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        self.expires_at.is_some_and(|e| now >= e)
+    }
+
+    /// Active and not expired
+    pub fn is_usable(&self, now: Timestamp) -> bool {
+        self.is_active && !self.is_expired(now)
+    }
+
+    pub fn validate(&self) -> Result<(), ValidateError> {
+        if self.name.trim().is_empty() {
+            return Err(ValidateError::NameEmpty);
+        }
+        if let Some(s) = self
+            .scopes
+            .0
+            .iter()
+            .find(|s| s.is_empty() || s.contains(char::is_whitespace))
+        {
+            return Err(ValidateError::ScopeInvalid(s.clone()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ValidateError {
+    NameEmpty,
+    ScopeInvalid(String),
+}
+
+impl std::fmt::Display for ValidateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidateError::NameEmpty => write!(f, "name cannot be empty"),
+            ValidateError::ScopeInvalid(s) => write!(f, "scope '{}' is invalid", s),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use super::Timestamp;
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use uuid::Uuid;
+
+/// Address or CIDR whose connections are dropped before the SSH handshake.
+/// Rows written by the server after repeated failures have a nil `updated_by`
+/// and always expire, bans added by an admin may be permanent.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct BannedIp {
+    pub id: Uuid,
+    pub network: String,
+    pub reason: String,
+    pub expires_at: Option<Timestamp>,
+    pub updated_by: Uuid,
+    pub updated_at: Timestamp,
+}
+
+impl BannedIp {
+    pub fn new(network: IpNetwork, reason: String, updated_by: Uuid) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            network: network.to_string(),
+            reason,
+            expires_at: None,
+            updated_by,
+            updated_at: Timestamp::now(),
+        }
+    }
+
+    pub fn with_expires_at(mut self, val: Option<Timestamp>) -> Self {
+        self.expires_at = val;
+        self
+    }
+
+    pub fn is_automatic(&self) -> bool {
+        self.updated_by.is_nil()
+    }
+
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        self.expires_at.is_some_and(|e| now >= e)
+    }
+
+    /// In force at `now` and covering `ip`
+    pub fn matches(&self, ip: IpAddr, now: Timestamp) -> bool {
+        !self.is_expired(now)
+            && self
+                .network
+                .parse::<IpNetwork>()
+                .is_ok_and(|n| n.contains(ip))
+    }
+
+    /// Reads an address or a CIDR as typed by an admin
+    pub fn parse_network(s: &str) -> Result<IpNetwork, ValidateError> {
+        s.trim()
+            .parse()
+            .map_err(|_| ValidateError::NetworkInvalid(s.trim().to_string()))
+    }
+
+    pub fn print_expires_at(&self) -> String {
+        self.expires_at
+            .map(|e| e.to_string())
+            .unwrap_or_else(|| "never".to_string())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ValidateError {
+    #[error("'{0}' is not an IP address or CIDR")]
+    NetworkInvalid(String),
+    #[error("'{0}' is not a duration, e.g. 30m or 2days")]
+    DurationInvalid(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_banned_ip_matches() {
+        let now = Timestamp::now();
+        let ban = BannedIp::new("192.0.2.0/24".parse().unwrap(), "scan".into(), Uuid::nil());
+        assert!(ban.is_automatic());
+        assert!(ban.matches("192.0.2.7".parse().unwrap(), now));
+        assert!(!ban.matches("198.51.100.7".parse().unwrap(), now));
+        assert!(!ban.matches("::1".parse().unwrap(), now));
+
+        let single = BannedIp::new(
+            "2001:db8::1".parse().unwrap(),
+            String::new(),
+            Uuid::new_v4(),
+        )
+        .with_expires_at(now.checked_add(Duration::from_secs(60)));
+        assert_eq!(single.network, "2001:db8::1/128");
+        assert!(single.matches("2001:db8::1".parse().unwrap(), now));
+        let later = now.checked_add(Duration::from_secs(61)).unwrap();
+        assert!(single.is_expired(later));
+        assert!(!single.matches("2001:db8::1".parse().unwrap(), later));
+
+        assert!(BannedIp::parse_network(" 10.0.0.0/8 ").is_ok());
+        assert!(BannedIp::parse_network("10.0.0.0/33").is_err());
+        assert!(BannedIp::parse_network("host.example").is_err());
+    }
+}
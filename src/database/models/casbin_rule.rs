@@ -1,4 +1,4 @@
-use chrono::Utc;
+use super::Timestamp;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -19,7 +19,7 @@ pub struct CasbinRule {
     pub v4: String,       // Extended policy data
     pub v5: String,       // Extended policy data
     pub updated_by: Uuid,
-    pub updated_at: i64,
+    pub updated_at: Timestamp,
 }
 
 impl CasbinRule {
@@ -34,7 +34,7 @@ impl CasbinRule {
         v5: String,
         updated_by: Uuid,
     ) -> Self {
-        let now = Utc::now();
+        let now = Timestamp::now();
         Self {
             id: Uuid::new_v4(),
             ptype,
@@ -45,7 +45,7 @@ impl CasbinRule {
             v4,
             v5,
             updated_by,
-            updated_at: now.timestamp_millis(),
+            updated_at: now,
         }
     }
 }
@@ -59,12 +59,12 @@ pub struct CasbinName {
     pub name: String,
     pub is_active: bool,
     pub updated_by: Uuid,
-    pub updated_at: i64,
+    pub updated_at: Timestamp,
 }
 
 impl CasbinName {
     pub fn new(ptype: String, name: String, is_active: bool, updated_by: Uuid) -> Self {
-        let now = Utc::now().timestamp_millis();
+        let now = Timestamp::now();
         Self {
             id: Uuid::new_v4(),
             ptype,
@@ -160,6 +160,15 @@ impl PermissionPolicy {
         }
     }
 
+    /// `deny` for `p_deny` rules, `allow` otherwise
+    pub fn effect(&self) -> &'static str {
+        if self.rule.ptype == "p_deny" {
+            "deny"
+        } else {
+            "allow"
+        }
+    }
+
     pub fn verify(&self) -> Result<(), PermissionPolicyEmptyError> {
         if self.user_role == String::new() {
             return Err(PermissionPolicyEmptyError::UserRole);
@@ -1,13 +1,169 @@
+use super::Timestamp;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use thiserror::Error;
 use uuid::Uuid;
 
+/// `prev_hash` of the first entry of the chain
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
 /// Log model for database storage
 /// Just record user's successful operation
+///
+/// Entries form a hash chain: each one stores the hash of the entry written
+/// before it, so changing or removing a row breaks every later link.
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Log {
     pub connection_id: Uuid,
     pub log_type: String,
     pub user_id: Uuid,
     pub detail: String,
-    pub created_at: i64,
+    pub created_at: Timestamp,
+    /// Set by the repository when the entry is appended
+    #[serde(default)]
+    pub prev_hash: String,
+    #[serde(default)]
+    pub hash: String,
+}
+
+#[derive(Debug, Error)]
+pub enum ChainError {
+    #[error("log of connection {connection_id} at {created_at} doesn't match its hash")]
+    Modified {
+        connection_id: Uuid,
+        created_at: Timestamp,
+    },
+    #[error("{count} log entries follow the same entry {prev_hash}")]
+    Forked { prev_hash: String, count: usize },
+    #[error("{count} log entries are cut off from the chain, an entry before them is missing")]
+    Unlinked { count: usize },
+}
+
+impl Log {
+    /// SHA-256 of `prev_hash` and every field, each prefixed by its length
+    pub fn compute_hash(&self, prev_hash: &str) -> String {
+        let created_at = self.created_at.millis().to_be_bytes();
+        let fields: [&[u8]; 6] = [
+            prev_hash.as_bytes(),
+            self.connection_id.as_bytes(),
+            self.log_type.as_bytes(),
+            self.user_id.as_bytes(),
+            self.detail.as_bytes(),
+            &created_at,
+        ];
+        let mut hasher = Sha256::new();
+        for field in fields {
+            hasher.update((field.len() as u64).to_be_bytes());
+            hasher.update(field);
+        }
+        hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// Links the entry after the one hashed `prev_hash`
+    pub fn chain_after(&mut self, prev_hash: &str) {
+        self.hash = self.compute_hash(prev_hash);
+        self.prev_hash = prev_hash.to_string();
+    }
+}
+
+/// Walks the chain from `GENESIS_HASH` whatever the order of `logs`, returns
+/// the number of entries and the hash of the last one. Removing the newest
+/// entries can't be told from a shorter chain, compare the head with one
+/// kept elsewhere for that.
+pub fn verify_chain(logs: &[Log]) -> Result<(usize, String), ChainError> {
+    let mut next: HashMap<&str, Vec<&Log>> = HashMap::new();
+    for log in logs {
+        next.entry(log.prev_hash.as_str()).or_default().push(log);
+    }
+
+    let mut head = GENESIS_HASH.to_string();
+    let mut count = 0;
+    while let Some(entries) = next.remove(head.as_str()) {
+        if entries.len() > 1 {
+            return Err(ChainError::Forked {
+                prev_hash: head,
+                count: entries.len(),
+            });
+        }
+        let log = entries[0];
+        if log.compute_hash(&head) != log.hash {
+            return Err(ChainError::Modified {
+                connection_id: log.connection_id,
+                created_at: log.created_at,
+            });
+        }
+        head = log.hash.clone();
+        count += 1;
+    }
+
+    if count != logs.len() {
+        return Err(ChainError::Unlinked {
+            count: logs.len() - count,
+        });
+    }
+    Ok((count, head))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(details: &[&str]) -> Vec<Log> {
+        let mut prev = GENESIS_HASH.to_string();
+        details
+            .iter()
+            .map(|d| {
+                let mut log = Log {
+                    connection_id: Uuid::new_v4(),
+                    log_type: "exec".to_string(),
+                    user_id: Uuid::nil(),
+                    detail: d.to_string(),
+                    created_at: Timestamp::now(),
+                    prev_hash: String::new(),
+                    hash: String::new(),
+                };
+                log.chain_after(&prev);
+                prev = log.hash.clone();
+                log
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_verify_chain() {
+        let mut logs = chain(&["ls", "id", "uptime"]);
+        logs.reverse();
+        let (count, head) = verify_chain(&logs).unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(head, logs[0].hash);
+        assert_eq!(verify_chain(&[]).unwrap().1, GENESIS_HASH);
+
+        let mut modified = logs.clone();
+        modified[1].detail = "rm -rf /".to_string();
+        assert!(matches!(
+            verify_chain(&modified),
+            Err(ChainError::Modified { .. })
+        ));
+
+        let mut removed = logs.clone();
+        removed.remove(1);
+        assert!(matches!(
+            verify_chain(&removed),
+            Err(ChainError::Unlinked { count: 1 })
+        ));
+
+        let mut forked = logs.clone();
+        let mut other = chain(&["whoami"]).remove(0);
+        other.chain_after(&logs[2].hash);
+        forked.push(other);
+        assert!(matches!(
+            verify_chain(&forked),
+            Err(ChainError::Forked { count: 2, .. })
+        ));
+    }
 }
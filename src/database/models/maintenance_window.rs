@@ -0,0 +1,212 @@
+use super::{TagFilter, TargetTag, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use uuid::Uuid;
+
+pub const REPEAT_NONE: &str = "none";
+pub const REPEAT_DAILY: &str = "daily";
+pub const REPEAT_WEEKLY: &str = "weekly";
+
+/// Time during which a target, or every target carrying a tag, only takes
+/// admin sessions. The first occurrence runs from `starts_at` to `ends_at`
+/// and `repeat` brings it back every day or week after that.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct MaintenanceWindow {
+    pub id: Uuid,
+    pub target_id: Option<Uuid>,
+    /// `key=value` of the tagged targets covered
+    pub tag: Option<String>,
+    pub starts_at: Timestamp,
+    pub ends_at: Timestamp,
+    /// `none`, `daily` or `weekly`
+    pub repeat: String,
+    pub reason: String,
+    pub updated_by: Uuid,
+    pub updated_at: Timestamp,
+}
+
+impl MaintenanceWindow {
+    pub fn new(
+        target_id: Option<Uuid>,
+        tag: Option<TagFilter>,
+        starts_at: Timestamp,
+        ends_at: Timestamp,
+        reason: String,
+        updated_by: Uuid,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            target_id,
+            tag: tag.map(|t| t.to_string()),
+            starts_at,
+            ends_at,
+            repeat: REPEAT_NONE.to_string(),
+            reason,
+            updated_by,
+            updated_at: Timestamp::now(),
+        }
+    }
+
+    pub fn with_repeat(mut self, val: &str) -> Self {
+        self.repeat = val.to_string();
+        self
+    }
+
+    fn period(&self) -> Result<Option<Duration>, ValidateError> {
+        match self.repeat.as_str() {
+            REPEAT_NONE => Ok(None),
+            REPEAT_DAILY => Ok(Some(Duration::from_secs(86400))),
+            REPEAT_WEEKLY => Ok(Some(Duration::from_secs(7 * 86400))),
+            other => Err(ValidateError::RepeatInvalid(other.to_string())),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ValidateError> {
+        if self.target_id.is_some() == self.tag.is_some() {
+            return Err(ValidateError::ScopeInvalid);
+        }
+        if let Some(tag) = self.tag.as_deref() {
+            tag.parse::<TagFilter>()
+                .map_err(|_| ValidateError::TagInvalid(tag.to_string()))?;
+        }
+        let length = self.ends_at.millis() - self.starts_at.millis();
+        if length <= 0 {
+            return Err(ValidateError::EndBeforeStart);
+        }
+        if let Some(period) = self.period()?
+            && length >= period.as_millis() as i64
+        {
+            return Err(ValidateError::LongerThanPeriod(self.repeat.clone()));
+        }
+        Ok(())
+    }
+
+    /// End of the occurrence `now` falls in, `None` outside the window
+    pub fn active_until(&self, now: Timestamp) -> Option<Timestamp> {
+        let since_start = now.millis() - self.starts_at.millis();
+        if since_start < 0 {
+            return None;
+        }
+        let shift = match self.period().ok()? {
+            Some(period) => {
+                let period = period.as_millis() as i64;
+                since_start / period * period
+            }
+            None => 0,
+        };
+        let ends_at = self
+            .ends_at
+            .checked_add(Duration::from_millis(shift as u64))?;
+        (now < ends_at).then_some(ends_at)
+    }
+
+    /// Whether the window is about `target_id`, which carries `tags`
+    pub fn covers(&self, target_id: Uuid, tags: &[TargetTag]) -> bool {
+        self.target_id == Some(target_id)
+            || self
+                .tag
+                .as_deref()
+                .and_then(|t| t.parse::<TagFilter>().ok())
+                .is_some_and(|f| tags.iter().any(|t| f.matches(t)))
+    }
+
+    /// What a user turned away from `target` is told
+    pub fn notice(&self, target: &str, until: Timestamp) -> String {
+        let mut msg = format!("Target {} is under maintenance until {} UTC", target, until);
+        if !self.reason.is_empty() {
+            msg.push_str(": ");
+            msg.push_str(&self.reason);
+        }
+        msg
+    }
+
+    pub fn print_scope(&self) -> String {
+        match (self.target_id, self.tag.as_deref()) {
+            (_, Some(tag)) => format!("tag {}", tag),
+            (Some(id), None) => format!("target {}", id),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ValidateError {
+    #[error("A maintenance window covers either a target or a tag")]
+    ScopeInvalid,
+    #[error("'{0}' is not a key=value tag")]
+    TagInvalid(String),
+    #[error("A maintenance window must end after it starts")]
+    EndBeforeStart,
+    #[error("A {0} maintenance window must be shorter than its period")]
+    LongerThanPeriod(String),
+    #[error("'{0}' is not one of none, daily or weekly")]
+    RepeatInvalid(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maintenance_window_active_until() {
+        let hour = Duration::from_secs(3600);
+        let start = Timestamp::now();
+        let at = |d: Duration| start.checked_add(d).unwrap();
+        let window = MaintenanceWindow::new(
+            Some(Uuid::new_v4()),
+            None,
+            start,
+            at(2 * hour),
+            "kernel upgrade".to_string(),
+            Uuid::new_v4(),
+        );
+        window.validate().unwrap();
+        assert_eq!(window.active_until(at(hour)), Some(at(2 * hour)));
+        assert_eq!(window.active_until(at(2 * hour)), None);
+        assert_eq!(window.active_until(at(26 * hour)), None);
+
+        let daily = window.clone().with_repeat(REPEAT_DAILY);
+        daily.validate().unwrap();
+        assert_eq!(daily.active_until(at(25 * hour)), Some(at(26 * hour)));
+        assert_eq!(daily.active_until(at(27 * hour)), None);
+        assert!(
+            MaintenanceWindow {
+                ends_at: at(24 * hour),
+                ..daily
+            }
+            .validate()
+            .is_err()
+        );
+        assert!(window.clone().with_repeat("monthly").validate().is_err());
+        assert!(
+            window
+                .notice("web", at(2 * hour))
+                .ends_with(" UTC: kernel upgrade")
+        );
+    }
+
+    #[test]
+    fn test_maintenance_window_covers() {
+        let target_id = Uuid::new_v4();
+        let start = Timestamp::now();
+        let end = start.checked_add(Duration::from_secs(60)).unwrap();
+        let tag = TargetTag::new(target_id, "env=prod".parse().unwrap(), Uuid::nil());
+        let window = MaintenanceWindow::new(
+            None,
+            Some("env=prod".parse().unwrap()),
+            start,
+            end,
+            String::new(),
+            Uuid::nil(),
+        );
+        window.validate().unwrap();
+        assert!(window.covers(target_id, std::slice::from_ref(&tag)));
+        assert!(!window.covers(target_id, &[]));
+
+        let both = MaintenanceWindow {
+            target_id: Some(target_id),
+            ..window
+        };
+        assert!(matches!(both.validate(), Err(ValidateError::ScopeInvalid)));
+    }
+}
@@ -0,0 +1,106 @@
+use super::CasbinRule;
+use crate::database::common::{ACT_PREFIX, OBJ_PREFIX};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Names of the users, groups, bindings, roles and actions rules refer to,
+/// built by `DatabaseService::rule_labels`. Anything unknown is shown as
+/// its raw id.
+#[derive(Debug, Clone, Default)]
+pub struct Names(HashMap<Uuid, String>);
+
+impl Names {
+    pub fn get(&self, id: &Uuid) -> Option<&str> {
+        self.0.get(id).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, id: Uuid, name: String) {
+        self.0.insert(id, name);
+    }
+
+    /// Name of `id` with the prefix of internal objects and actions
+    /// dropped, empty for the nil id and the id itself when unknown
+    pub fn label(&self, id: &Uuid) -> String {
+        match self.get(id) {
+            Some(name) => name
+                .strip_prefix(ACT_PREFIX)
+                .or_else(|| name.strip_prefix(OBJ_PREFIX))
+                .unwrap_or(name)
+                .to_string(),
+            None if id.is_nil() => String::new(),
+            None => id.to_string(),
+        }
+    }
+
+    /// `alice -> root@web (shell)` for a policy, prefixed with `deny` for a
+    /// deny rule, `g2 root@web, prod` for a group rule
+    pub fn rule(&self, rule: &CasbinRule) -> String {
+        match rule.ptype.as_str() {
+            "p" | "p_deny" => format!(
+                "{}{} -> {} ({})",
+                if rule.ptype == "p_deny" { "deny " } else { "" },
+                self.label(&rule.v0),
+                self.label(&rule.v1),
+                self.label(&rule.v2)
+            ),
+            _ => format!(
+                "{} {}, {}",
+                rule.ptype,
+                self.label(&rule.v0),
+                self.label(&rule.v1)
+            ),
+        }
+    }
+}
+
+impl From<HashMap<Uuid, String>> for Names {
+    fn from(names: HashMap<Uuid, String>) -> Self {
+        Names(names)
+    }
+}
+
+impl Extend<(Uuid, String)> for Names {
+    fn extend<I: IntoIterator<Item = (Uuid, String)>>(&mut self, iter: I) {
+        self.0.extend(iter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::common::ACT_SHELL;
+
+    #[test]
+    fn test_names() {
+        let (alice, web, shell, prod) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        let names = Names::from(HashMap::from([
+            (alice, "alice".to_string()),
+            (web, "root@web".to_string()),
+            (shell, ACT_SHELL.to_string()),
+        ]));
+        let mut rule = CasbinRule::new(
+            "p".to_string(),
+            alice,
+            web,
+            shell,
+            String::new(),
+            String::new(),
+            String::new(),
+            Uuid::nil(),
+        );
+        assert_eq!(names.rule(&rule), "alice -> root@web (shell)");
+        rule.ptype = "p_deny".to_string();
+        assert_eq!(names.rule(&rule), "deny alice -> root@web (shell)");
+
+        rule.ptype = "g2".to_string();
+        rule.v0 = web;
+        rule.v1 = prod;
+        assert_eq!(names.rule(&rule), format!("g2 root@web, {}", prod));
+        assert_eq!(names.label(&Uuid::nil()), "");
+    }
+}
@@ -0,0 +1,180 @@
+use super::{CasbinRule, Names, Timestamp};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const CHANGE_CREATED: &str = "created";
+pub const CHANGE_UPDATED: &str = "updated";
+pub const CHANGE_DELETED: &str = "deleted";
+
+/// Audit entry of a casbin rule written, edited or removed. `before` and
+/// `after` hold the rule as JSON, `diff` the same change with ids resolved
+/// to names at the time it was made.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct PolicyChange {
+    pub id: Uuid,
+    pub rule_id: Uuid,
+    /// `created`, `updated` or `deleted`
+    pub action: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub diff: String,
+    pub changed_by: Uuid,
+    pub changed_at: Timestamp,
+}
+
+impl PolicyChange {
+    /// `None` when the rule didn't change, or there is no rule at all
+    pub fn new(
+        before: Option<&CasbinRule>,
+        after: Option<&CasbinRule>,
+        changed_by: Uuid,
+        labels: &Names,
+    ) -> Option<Self> {
+        let (rule_id, action) = match (before, after) {
+            (None, Some(a)) => (a.id, CHANGE_CREATED),
+            (Some(b), None) => (b.id, CHANGE_DELETED),
+            (Some(b), Some(_)) => (b.id, CHANGE_UPDATED),
+            (None, None) => return None,
+        };
+        let diff = rule_diff(before, after, labels)?;
+        let json = |r: Option<&CasbinRule>| r.and_then(|r| serde_json::to_string(r).ok());
+        Some(Self {
+            id: Uuid::new_v4(),
+            rule_id,
+            action: action.to_string(),
+            before: json(before),
+            after: json(after),
+            diff,
+            changed_by,
+            changed_at: Timestamp::now(),
+        })
+    }
+
+    pub fn before_rule(&self) -> Option<CasbinRule> {
+        serde_json::from_str(self.before.as_deref()?).ok()
+    }
+
+    pub fn after_rule(&self) -> Option<CasbinRule> {
+        serde_json::from_str(self.after.as_deref()?).ok()
+    }
+}
+
+/// Change with the admin's name resolved, for the admin table
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct PolicyChangeView {
+    pub id: Uuid,
+    pub rule_id: Uuid,
+    pub action: String,
+    pub diff: String,
+    pub changed_by: String,
+    pub changed_at: Timestamp,
+}
+
+/// Names of the rule columns, by what they hold for the rule's ptype
+fn field_names(ptype: &str) -> [&'static str; 6] {
+    match ptype {
+        "p" | "p_deny" => ["subject", "object", "action", "ext", "v4", "v5"],
+        _ => ["group", "member", "v2", "v3", "v4", "v5"],
+    }
+}
+
+fn fields(rule: &CasbinRule, labels: &Names) -> [String; 6] {
+    [
+        labels.label(&rule.v0),
+        labels.label(&rule.v1),
+        labels.label(&rule.v2),
+        rule.v3.clone(),
+        rule.v4.clone(),
+        rule.v5.clone(),
+    ]
+}
+
+/// One line for a created or deleted rule, `+ p alice, root@web, shell`,
+/// the changed fields of an updated one, `ext: '' -> '10.0.0.0/8,,,,'`.
+/// `None` when nothing changed.
+pub fn rule_diff(
+    before: Option<&CasbinRule>,
+    after: Option<&CasbinRule>,
+    labels: &Names,
+) -> Option<String> {
+    let line = |sign: &str, r: &CasbinRule| {
+        let fields = fields(r, labels);
+        let last = fields.iter().rposition(|f| !f.is_empty()).unwrap_or(0);
+        format!("{} {} {}", sign, r.ptype, fields[..=last].join(", "))
+    };
+    match (before, after) {
+        (None, Some(a)) => Some(line("+", a)),
+        (Some(b), None) => Some(line("-", b)),
+        (Some(b), Some(a)) => {
+            let mut changes = Vec::new();
+            if b.ptype != a.ptype {
+                changes.push(format!("ptype: {} -> {}", b.ptype, a.ptype));
+            }
+            let fields = field_names(&a.ptype)
+                .into_iter()
+                .zip(fields(b, labels))
+                .zip(fields(a, labels));
+            for ((name, old), new) in fields {
+                if old != new {
+                    changes.push(format!("{}: '{}' -> '{}'", name, old, new));
+                }
+            }
+            (!changes.is_empty()).then(|| changes.join("; "))
+        }
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_rule_diff() {
+        let (alice, web, shell) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let labels = Names::from(HashMap::from([
+            (alice, "alice".to_string()),
+            (web, "root@web".to_string()),
+            (shell, "shell".to_string()),
+        ]));
+        let rule = CasbinRule::new(
+            "p".to_string(),
+            alice,
+            web,
+            shell,
+            String::new(),
+            String::new(),
+            String::new(),
+            Uuid::nil(),
+        );
+        assert_eq!(
+            rule_diff(None, Some(&rule), &labels).unwrap(),
+            "+ p alice, root@web, shell"
+        );
+        assert_eq!(
+            rule_diff(Some(&rule), None, &labels).unwrap(),
+            "- p alice, root@web, shell"
+        );
+        assert!(rule_diff(Some(&rule), Some(&rule), &labels).is_none());
+
+        let mut edited = rule.clone();
+        edited.ptype = "p_deny".to_string();
+        edited.v3 = "10.0.0.0/8,,,".to_string();
+        let other = Uuid::new_v4();
+        edited.v1 = other;
+        assert_eq!(
+            rule_diff(Some(&rule), Some(&edited), &labels).unwrap(),
+            format!(
+                "ptype: p -> p_deny; object: 'root@web' -> '{}'; ext: '' -> '10.0.0.0/8,,,'",
+                other
+            )
+        );
+
+        let change = PolicyChange::new(Some(&rule), Some(&edited), alice, &labels).unwrap();
+        assert_eq!(change.action, CHANGE_UPDATED);
+        assert_eq!(change.before_rule().unwrap().v1, web);
+        assert_eq!(change.after_rule().unwrap().v1, other);
+        assert!(PolicyChange::new(Some(&rule), Some(&rule), alice, &labels).is_none());
+    }
+}
@@ -1,4 +1,6 @@
+use super::Timestamp;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use uuid::Uuid;
 
 /// Session recording metadata for database storage
@@ -9,10 +11,20 @@ pub struct SessionRecording {
     pub target_id: Uuid,
     pub secret_id: Uuid,
     pub file_path: String,
-    pub started_at: i64,
-    pub ended_at: Option<i64>,
+    pub started_at: Timestamp,
+    pub ended_at: Option<Timestamp>,
     pub connection_id: Uuid,
     pub status: String,
+    /// Change/ticket reference the session was opened under
+    pub ticket: Option<String>,
+    /// Filled in when the recording completes
+    #[serde(default)]
+    pub duration_ms: Option<i64>,
+    /// Time with terminal activity, long idle gaps left out
+    #[serde(default)]
+    pub active_ms: Option<i64>,
+    #[serde(default)]
+    pub event_count: Option<i64>,
 }
 
 impl SessionRecording {
@@ -24,12 +36,42 @@ impl SessionRecording {
             target_id,
             secret_id,
             file_path: generate_path(id),
-            started_at: chrono::Utc::now().timestamp_millis(),
+            started_at: Timestamp::now(),
             ended_at: None,
             connection_id,
             status: "active".to_string(),
+            ticket: None,
+            duration_ms: None,
+            active_ms: None,
+            event_count: None,
         }
     }
+
+    pub fn with_ticket(mut self, val: Option<String>) -> Self {
+        self.ticket = val;
+        self
+    }
+
+    /// Moves the file under `<target id>/<secret id>/`, where directory
+    /// permissions can restrict who reads the sessions of one target secret
+    pub fn in_target_dir(mut self) -> Self {
+        self.file_path = format!("{}/{}/{}", self.target_id, self.secret_id, self.file_path);
+        self
+    }
+
+    pub fn set_stats(&mut self, duration: Duration, active: Duration, events: u64) {
+        self.duration_ms = Some(duration.as_millis() as i64);
+        self.active_ms = Some(active.as_millis() as i64);
+        self.event_count = Some(events as i64);
+    }
+}
+
+fn format_ms(ms: i64) -> String {
+    if ms < 60_000 {
+        format!("{} s", ms / 1000)
+    } else {
+        format!("{} min", ms / 60_000)
+    }
 }
 
 pub fn generate_path(id: Uuid) -> String {
@@ -40,13 +82,22 @@ pub fn generate_path(id: Uuid) -> String {
 pub struct RecordingView {
     pub id: Uuid,
     pub target_secret: String,
-    pub started_at: i64,
-    pub ended_at: Option<i64>,
+    /// Relative to `record_path`
+    pub file_path: String,
+    pub started_at: Timestamp,
+    pub ended_at: Option<Timestamp>,
     pub status: String,
+    pub duration_ms: Option<i64>,
+    pub active_ms: Option<i64>,
 }
 
 impl RecordingView {
-    pub fn generate_path(&self) -> String {
-        generate_path(self.id)
+    /// `42 min (9 min active)`, empty until the recording completes
+    pub fn print_duration(&self) -> String {
+        match (self.duration_ms, self.active_ms) {
+            (Some(d), Some(a)) => format!("{} ({} active)", format_ms(d), format_ms(a)),
+            (Some(d), None) => format_ms(d),
+            _ => String::new(),
+        }
     }
 }
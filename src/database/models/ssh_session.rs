@@ -0,0 +1,94 @@
+use super::Timestamp;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// One bridged channel to a target, from open to close. `ended_at` stays
+/// empty while the session is live.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SshSession {
+    pub id: Uuid,
+    pub connection_id: Uuid,
+    pub user_id: Uuid,
+    pub target_secret_id: Uuid,
+    /// `shell`, `exec: <command>` or `direct-tcpip: <host>:<port>`
+    pub request: String,
+    pub started_at: Timestamp,
+    pub ended_at: Option<Timestamp>,
+    /// Bytes sent by the client to the target
+    pub bytes_in: i64,
+    /// Bytes sent by the target to the client
+    pub bytes_out: i64,
+    pub recording_path: Option<String>,
+    pub termination_reason: Option<String>,
+    pub ticket: Option<String>,
+    /// Typed by the user when the granting policy has the `reason` flag
+    pub access_reason: Option<String>,
+    /// Account picked by the user in place of the secret's own user, allowed
+    /// by the `anyuser` policy flag
+    pub login_as: Option<String>,
+}
+
+impl SshSession {
+    pub fn new(
+        connection_id: Uuid,
+        user_id: Uuid,
+        target_secret_id: Uuid,
+        request: String,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            connection_id,
+            user_id,
+            target_secret_id,
+            request,
+            started_at: Timestamp::now(),
+            ended_at: None,
+            bytes_in: 0,
+            bytes_out: 0,
+            recording_path: None,
+            termination_reason: None,
+            ticket: None,
+            access_reason: None,
+            login_as: None,
+        }
+    }
+
+    pub fn with_recording_path(mut self, val: Option<String>) -> Self {
+        self.recording_path = val;
+        self
+    }
+
+    pub fn with_ticket(mut self, val: Option<String>) -> Self {
+        self.ticket = val;
+        self
+    }
+
+    pub fn with_access_reason(mut self, val: Option<String>) -> Self {
+        self.access_reason = val;
+        self
+    }
+
+    pub fn with_login_as(mut self, val: Option<String>) -> Self {
+        self.login_as = val;
+        self
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.ended_at.is_none()
+    }
+}
+
+/// Live session with user and target labels resolved
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SshSessionView {
+    pub id: Uuid,
+    pub connection_id: Uuid,
+    pub username: String,
+    pub target_secret: String,
+    pub request: String,
+    pub started_at: Timestamp,
+    pub bytes_in: i64,
+    pub bytes_out: i64,
+    pub ticket: Option<String>,
+    pub access_reason: Option<String>,
+}
@@ -1,16 +1,76 @@
+//! Targets and how to reach them. `hostname` may list fallback addresses,
+//! tried in order with the `[target_connect]` retries and timeouts; a
+//! target with `via_target_id` is reached over a direct-tcpip channel of
+//! another one; `host_key_mode` decides how its host key is trusted
+//! (`strict`, `tofu`, or `confirm` until an admin accepts it).
+
+use super::Timestamp;
+use crate::config::{LimitsConfig, TargetConnectConfig};
 use crate::error::Error;
-use chrono::Utc;
+use crate::server::error::ServerError;
+use crate::server::remote_forward::{ForwardedTcpip, RemoteForwards};
 use log::{debug, warn};
 use russh::client as ru_client;
 use russh::keys::ssh_key::{self, PublicKey};
-use russh::{Preferred, SshId, keys::Algorithm};
+use russh::{Channel, Preferred, SshId, keys::Algorithm};
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
 use uuid::Uuid;
 
 const MAX_NAME_LEN: usize = 50;
+/// Targets a connection may pass through before the one it's for
+pub const MAX_VIA_HOPS: usize = 4;
+
+/// Only `server_public_key` is accepted
+pub const HOST_KEY_STRICT: &str = "strict";
+/// The first key seen is stored and accepted, later ones must match it
+pub const HOST_KEY_TOFU: &str = "tofu";
+/// The first key seen waits in `pending_public_key` for an admin to accept
+/// it in the target editor
+pub const HOST_KEY_CONFIRM: &str = "confirm";
+
+fn default_host_key_mode() -> String {
+    HOST_KEY_STRICT.to_string()
+}
+
+/// `host:port`, with an IPv6 host in brackets
+pub fn endpoint(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// How a host key presented by a target compares to the stored one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostKeyCheck {
+    Match,
+    /// Another key is stored
+    Mismatch,
+    /// No key stored yet, trusted in `tofu` mode
+    Unknown,
+}
+
+/// Key a handshake presented and how it compared, read back by the server
+/// once the connection is built or refused
+pub(crate) type PresentedKey = Arc<Mutex<Option<(PublicKey, HostKeyCheck)>>>;
+
+/// Connection a target is reached through
+#[derive(Clone)]
+pub(crate) struct ViaConnection(Arc<ru_client::Handle<Target>>);
+
+impl std::fmt::Debug for ViaConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ViaConnection")
+    }
+}
 
 /// Target model for database storage
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
@@ -19,26 +79,69 @@ pub struct Target {
     pub name: String,
     pub hostname: String,
     pub port: u16,
+    /// Empty until the first connection in `tofu` or `confirm` mode
     pub server_public_key: String,
+    /// `strict`, `tofu` or `confirm`
+    #[serde(default = "default_host_key_mode")]
+    pub host_key_mode: String,
+    /// Key seen in `confirm` mode, waiting for an admin
+    #[serde(default)]
+    pub pending_public_key: Option<String>,
+    /// Target reached first, the connection to this one is a `direct-tcpip`
+    /// channel of it
+    #[serde(default)]
+    pub via_target_id: Option<Uuid>,
+    /// Replaces the connect, handshake and auth timeouts of
+    /// `target_connect` for this target
+    #[serde(default)]
+    pub timeout_secs: Option<u32>,
     pub description: Option<String>,
     pub is_active: bool,
     pub updated_by: Uuid, // User ID who last updated this target
-    pub updated_at: i64,
+    pub updated_at: Timestamp,
+    #[serde(default)]
+    pub version: i64,
+    /// Where the `forwarded-tcpip` channels of a connection built from this
+    /// target go, set by the server before connecting
+    #[serde(skip)]
+    #[sqlx(skip)]
+    pub(crate) remote_forwards: Option<Arc<RemoteForwards>>,
+    /// Session the agent channels of the target go to, only for
+    /// connections built for a session forwarding its agent
+    #[serde(skip)]
+    #[sqlx(skip)]
+    pub(crate) agent: Option<mpsc::UnboundedSender<Channel<ru_client::Msg>>>,
+    #[serde(skip)]
+    #[sqlx(skip)]
+    pub(crate) presented_key: Option<PresentedKey>,
+    /// Connection to `via_target_id`, kept as long as the one through it
+    #[serde(skip)]
+    #[sqlx(skip)]
+    pub(crate) via: Option<ViaConnection>,
 }
 
 impl Target {
     pub fn new(updated_by: Uuid) -> Self {
-        let now = Utc::now();
+        let now = Timestamp::now();
         Self {
             id: Uuid::new_v4(),
             name: String::default(),
             hostname: String::default(),
             port: 22,
             server_public_key: String::default(),
+            host_key_mode: default_host_key_mode(),
+            pending_public_key: None,
+            via_target_id: None,
+            timeout_secs: None,
             description: None,
             is_active: true,
             updated_by,
-            updated_at: now.timestamp_millis(),
+            updated_at: now,
+            version: 0,
+            remote_forwards: None,
+            agent: None,
+            presented_key: None,
+            via: None,
         }
     }
 
@@ -52,37 +155,184 @@ impl Target {
         self
     }
 
+    pub(crate) fn with_agent(
+        mut self,
+        val: mpsc::UnboundedSender<Channel<ru_client::Msg>>,
+    ) -> Self {
+        self.agent = Some(val);
+        self
+    }
+
+    pub(crate) fn with_presented_key(mut self, val: PresentedKey) -> Self {
+        self.presented_key = Some(val);
+        self
+    }
+
+    pub(crate) fn with_via(mut self, val: Arc<ru_client::Handle<Target>>) -> Self {
+        self.via = Some(ViaConnection(val));
+        self
+    }
+
+    pub(crate) fn with_remote_forwards(mut self, val: Arc<RemoteForwards>) -> Self {
+        self.remote_forwards = Some(val);
+        self
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_secs.map(|s| Duration::from_secs(s.into()))
+    }
+
+    /// Addresses listed in `hostname`, comma separated, in the order they
+    /// are tried
+    pub fn addresses(&self) -> impl Iterator<Item = &str> {
+        self.hostname.split(',').map(str::trim)
+    }
+
+    /// Connects to the first address that answers, the handle comes with the
+    /// `host:port` it reached. `preferred` holds the configured algorithms.
     pub(crate) async fn build_connect(
         self,
         client_id: String,
-    ) -> Result<ru_client::Handle<Self>, Error> {
-        let pub_key = PublicKey::from_openssh(&self.server_public_key)?;
-        let preferred = if let Ok(algo) = Algorithm::new(pub_key.algorithm().as_str()) {
+        limits: &LimitsConfig,
+        retry: &TargetConnectConfig,
+        preferred: Preferred,
+    ) -> Result<(ru_client::Handle<Self>, String), Error> {
+        let retry = retry.for_target(self.timeout());
+        // No key yet in `tofu` and `confirm` modes, any algorithm will do
+        let algo = PublicKey::from_openssh(&self.server_public_key)
+            .ok()
+            .and_then(|k| Algorithm::new(k.algorithm().as_str()).ok());
+        let preferred = if let Some(algo) = algo {
             debug!(
                 "Preferred key: {} from target: {}({})",
                 algo, self.name, self.id
             );
             Preferred {
                 key: Cow::Owned(vec![algo]),
-                ..<_>::default()
+                ..preferred
             }
         } else {
-            Preferred::default()
+            preferred
         };
 
         let config = Arc::new(russh::client::Config {
             client_id: SshId::Standard(Cow::Owned(client_id)),
             preferred,
+            window_size: limits.window_size,
+            maximum_packet_size: limits.max_packet_size,
+            channel_buffer_size: limits.channel_buffer_size,
+            keepalive_interval: retry.keepalive(),
+            keepalive_max: retry.keepalive_max,
             ..Default::default()
         });
 
-        ru_client::connect(config, (self.hostname.clone(), self.port), self).await
+        let addresses: Vec<String> = self.addresses().map(str::to_string).collect();
+        let mut delays = retry.delays();
+        loop {
+            let mut failed = None;
+            for host in &addresses {
+                match self.connect_to(host, config.clone(), &retry).await {
+                    Ok(handle) => return Ok((handle, endpoint(host, self.port))),
+                    // Every address has to present the same key, refused
+                    // once is refused for all
+                    Err(e) if self.key_presented() => return Err(e),
+                    Err(e) => {
+                        warn!(
+                            "Connecting to target {}({}) at {} failed: {}",
+                            self.name,
+                            self.id,
+                            endpoint(host, self.port),
+                            e
+                        );
+                        failed = Some(e);
+                    }
+                }
+            }
+            let Some(delay) = delays.next() else {
+                return Err(match failed {
+                    Some(e) => ServerError::TargetUnreachable {
+                        target: self.name.clone(),
+                        reason: e.to_string(),
+                    }
+                    .into(),
+                    None => {
+                        std::io::Error::new(std::io::ErrorKind::InvalidInput, "no hostname").into()
+                    }
+                });
+            };
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Connects to `host`, directly or over a `direct-tcpip` channel of the
+    /// target in between, and exchanges keys within the timeouts
+    async fn connect_to(
+        &self,
+        host: &str,
+        config: Arc<ru_client::Config>,
+        timeouts: &TargetConnectConfig,
+    ) -> Result<ru_client::Handle<Self>, Error> {
+        let timed_out = |stage: &str, timeout: Duration| -> Error {
+            let msg = format!(
+                "{} timed out after {}",
+                stage,
+                humantime::format_duration(timeout)
+            );
+            std::io::Error::new(std::io::ErrorKind::TimedOut, msg).into()
+        };
+        let (connect, handshake) = (timeouts.connect_timeout, timeouts.handshake_timeout);
+        let handshaken = match self.via.as_ref() {
+            Some(via) => {
+                let open = via
+                    .0
+                    .channel_open_direct_tcpip(host, self.port as u32, "127.0.0.1", 0);
+                let channel = timeout(connect, open)
+                    .await
+                    .map_err(|_| timed_out("connect", connect))??;
+                let stream = channel.into_stream();
+                let ssh = ru_client::connect_stream(config, stream, self.clone());
+                timeout(handshake, ssh).await
+            }
+            None => {
+                let socket = timeout(connect, TcpStream::connect((host, self.port)))
+                    .await
+                    .map_err(|_| timed_out("connect", connect))??;
+                let ssh = ru_client::connect_stream(config, socket, self.clone());
+                timeout(handshake, ssh).await
+            }
+        };
+        handshaken.map_err(|_| timed_out("handshake", handshake))?
+    }
+
+    fn key_presented(&self) -> bool {
+        self.presented_key
+            .as_ref()
+            .is_some_and(|p| p.lock().unwrap().is_some())
     }
 
     pub fn print_server_key(&self) -> String {
         crate::common::shorten_ssh_pubkey(&self.server_public_key)
     }
 
+    pub fn check_host_key(&self, key: &PublicKey) -> HostKeyCheck {
+        if self.server_public_key.trim().is_empty() {
+            return HostKeyCheck::Unknown;
+        }
+        match PublicKey::from_openssh(&self.server_public_key) {
+            Ok(stored) if stored.key_data() == key.key_data() => HostKeyCheck::Match,
+            _ => HostKeyCheck::Mismatch,
+        }
+    }
+
+    /// Whether a handshake presenting a key that checked as `check` goes on
+    pub fn accepts(&self, check: HostKeyCheck) -> bool {
+        match check {
+            HostKeyCheck::Match => true,
+            HostKeyCheck::Mismatch => false,
+            HostKeyCheck::Unknown => self.host_key_mode == HOST_KEY_TOFU,
+        }
+    }
+
     pub fn validate(&self) -> Result<(), ValidateError> {
         let name = self.name.trim();
         if name.is_empty() {
@@ -92,13 +342,26 @@ impl Target {
             return Err(ValidateError::NameTooLong);
         }
         let hostname = self.hostname.trim();
-        if hostname.is_empty() {
+        if self.addresses().any(str::is_empty) {
             return Err(ValidateError::HostnameEmpty);
         }
         if hostname.len() > MAX_NAME_LEN {
             return Err(ValidateError::HostnameTooLong);
         }
-        if PublicKey::from_str(&self.server_public_key).is_err() {
+        if ![HOST_KEY_STRICT, HOST_KEY_TOFU, HOST_KEY_CONFIRM]
+            .contains(&self.host_key_mode.as_str())
+        {
+            return Err(ValidateError::HostKeyMode(self.host_key_mode.clone()));
+        }
+        if self.timeout_secs == Some(0) {
+            return Err(ValidateError::TimeoutInvalid);
+        }
+        if self.via_target_id == Some(self.id) {
+            return Err(ValidateError::ViaTarget);
+        }
+        // Other modes learn the key on the first connection
+        let learns = self.host_key_mode != HOST_KEY_STRICT && self.server_public_key.is_empty();
+        if !learns && PublicKey::from_str(&self.server_public_key).is_err() {
             return Err(ValidateError::ServerPublicKey);
         }
         Ok(())
@@ -111,18 +374,68 @@ impl ru_client::Handler for Target {
         &mut self,
         server_public_key: &ssh_key::PublicKey,
     ) -> Result<bool, Self::Error> {
-        let target_public_key = ssh_key::PublicKey::from_openssh(self.server_public_key.as_str())
-            .map_err(russh::keys::Error::from)?;
-        if target_public_key.key_data() == server_public_key.key_data() {
-            return Ok(true);
-        }
-        warn!(
-            "The public key of target: {}({}) doesn't match: {}",
-            self.name,
-            self.id,
-            server_public_key.to_string()
-        );
-        Ok(false)
+        let check = self.check_host_key(server_public_key);
+        if let Some(presented) = self.presented_key.as_ref() {
+            *presented.lock().unwrap() = Some((server_public_key.clone(), check));
+        }
+        if check == HostKeyCheck::Mismatch {
+            warn!(
+                "The public key of target: {}({}) doesn't match: {}",
+                self.name,
+                self.id,
+                server_public_key.to_string()
+            );
+        }
+        Ok(self.accepts(check))
+    }
+
+    async fn server_channel_open_forwarded_tcpip(
+        &mut self,
+        channel: Channel<ru_client::Msg>,
+        connected_address: &str,
+        connected_port: u32,
+        originator_address: &str,
+        originator_port: u32,
+        _session: &mut ru_client::Session,
+    ) -> Result<(), Self::Error> {
+        let forwarded = ForwardedTcpip {
+            channel,
+            connected_address: connected_address.to_string(),
+            connected_port,
+            originator_address: originator_address.to_string(),
+            originator_port,
+        };
+        let routed = match self.remote_forwards.as_ref() {
+            Some(forwards) => forwards.route(self.id, forwarded),
+            None => Err(forwarded),
+        };
+        if let Err(forwarded) = routed {
+            debug!(
+                "No session listens on {}:{} of target: {}({}), closing the forwarded channel",
+                connected_address, connected_port, self.name, self.id
+            );
+            let _ = forwarded.channel.close().await;
+        }
+        Ok(())
+    }
+
+    async fn server_channel_open_agent_forward(
+        &mut self,
+        channel: Channel<ru_client::Msg>,
+        _session: &mut ru_client::Session,
+    ) -> Result<(), Self::Error> {
+        let routed = match self.agent.as_ref() {
+            Some(agent) => agent.send(channel).map_err(|e| e.0),
+            None => Err(channel),
+        };
+        if let Err(channel) = routed {
+            warn!(
+                "Target: {}({}) opened an agent channel without agent forwarding, closing it",
+                self.name, self.id
+            );
+            let _ = channel.close().await;
+        }
+        Ok(())
     }
 }
 
@@ -134,7 +447,14 @@ pub enum ValidateError {
     HostnameTooLong,
     PortNotNumber,
     PortInvalid,
+    TimeoutInvalid,
     ServerPublicKey,
+    HostKeyMode(String),
+    /// The target is reached through itself, or a chain longer than
+    /// `MAX_VIA_HOPS` or leading back to it
+    ViaTarget,
+    ViaTargetUnknown(String),
+    TagInvalid(String),
 }
 
 impl std::fmt::Display for ValidateError {
@@ -156,12 +476,31 @@ impl std::fmt::Display for ValidateError {
             ServerPublicKey => {
                 write!(f, "server public key is invalid")
             }
+            HostKeyMode(mode) => {
+                write!(f, "host key mode '{}' is not strict, tofu or confirm", mode)
+            }
+            ViaTarget => {
+                write!(
+                    f,
+                    "via target must not lead back to the target or pass more than {} targets",
+                    MAX_VIA_HOPS
+                )
+            }
+            TimeoutInvalid => {
+                write!(f, "timeout is not a positive number of seconds")
+            }
+            ViaTargetUnknown(name) => {
+                write!(f, "via target '{}' doesn't exist", name)
+            }
             PortNotNumber => {
                 write!(f, "port is not a number")
             }
             PortInvalid => {
                 write!(f, "port is not within the range of 1–65536")
             }
+            TagInvalid(tag) => {
+                write!(f, "tag '{}' is not in key=value form", tag)
+            }
         }
     }
 }
@@ -173,3 +512,138 @@ pub struct TargetInfo {
     pub hostname: String,
     pub port: u16,
 }
+
+/// Label attached to a target, e.g. `env=prod`. A target has at most one
+/// value per key.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TargetTag {
+    pub id: Uuid,
+    pub target_id: Uuid,
+    pub key: String,
+    pub value: String,
+    pub updated_by: Uuid,
+    pub updated_at: Timestamp,
+}
+
+impl TargetTag {
+    pub fn new(target_id: Uuid, filter: TagFilter, updated_by: Uuid) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            target_id,
+            key: filter.key,
+            value: filter.value,
+            updated_by,
+            updated_at: Timestamp::now(),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        format!("{}={}", self.key, self.value)
+    }
+}
+
+/// `key=value` pair used to select targets by tag
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TagFilter {
+    pub key: String,
+    pub value: String,
+}
+
+impl TagFilter {
+    pub fn matches(&self, tag: &TargetTag) -> bool {
+        tag.key == self.key && tag.value == self.value
+    }
+
+    /// Parse a comma or whitespace separated list such as `env=prod, team=db`
+    pub fn parse_list(s: &str) -> Result<Vec<Self>, ValidateError> {
+        s.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|v| !v.is_empty())
+            .map(Self::from_str)
+            .collect()
+    }
+}
+
+impl FromStr for TagFilter {
+    type Err = ValidateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ValidateError::TagInvalid(s.to_string());
+        let (key, value) = s.split_once('=').ok_or_else(invalid)?;
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty()
+            || value.is_empty()
+            || key.len() > MAX_NAME_LEN
+            || value.len() > MAX_NAME_LEN
+            || value.contains('=')
+            || s.contains(',')
+        {
+            return Err(invalid());
+        }
+        Ok(Self {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for TagFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh::keys::PrivateKey;
+
+    #[test]
+    fn test_check_host_key() {
+        let key = || {
+            PrivateKey::random(&mut rand::rng(), Algorithm::Ed25519)
+                .unwrap()
+                .public_key()
+                .clone()
+        };
+        let (known, other) = (key(), key());
+        let mut target = Target::new(Uuid::new_v4());
+        target.name = "web1".to_string();
+        target.hostname = "10.0.0.1".to_string();
+        assert!(target.validate().is_err());
+
+        target.host_key_mode = HOST_KEY_TOFU.to_string();
+        target.validate().unwrap();
+        assert_eq!(target.check_host_key(&known), HostKeyCheck::Unknown);
+        assert!(target.accepts(HostKeyCheck::Unknown));
+
+        target.server_public_key = known.to_openssh().unwrap();
+        assert_eq!(target.check_host_key(&known), HostKeyCheck::Match);
+        assert_eq!(target.check_host_key(&other), HostKeyCheck::Mismatch);
+        assert!(!target.accepts(HostKeyCheck::Mismatch));
+
+        target.host_key_mode = HOST_KEY_CONFIRM.to_string();
+        assert!(!target.accepts(HostKeyCheck::Unknown));
+        target.host_key_mode = "trust".to_string();
+        assert!(target.validate().is_err());
+    }
+
+    #[test]
+    fn test_addresses() {
+        let mut target = Target::new(Uuid::new_v4());
+        target.name = "db".to_string();
+        target.host_key_mode = HOST_KEY_TOFU.to_string();
+        target.hostname = "db1.lan, 10.0.0.7,fd00::7".to_string();
+        target.validate().unwrap();
+        assert_eq!(
+            target.addresses().collect::<Vec<_>>(),
+            ["db1.lan", "10.0.0.7", "fd00::7"]
+        );
+        assert_eq!(endpoint("fd00::7", 22), "[fd00::7]:22");
+        assert_eq!(endpoint("db1.lan", 2222), "db1.lan:2222");
+        target.hostname = "db1.lan,".to_string();
+        assert!(target.validate().is_err());
+        target.hostname = "db1.lan".to_string();
+        target.via_target_id = Some(target.id);
+        assert!(target.validate().is_err());
+    }
+}
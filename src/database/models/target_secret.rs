@@ -1,8 +1,18 @@
-use chrono::Utc;
+use super::Timestamp;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use uuid::Uuid;
 
+/// Logs in with the stored password or private key
+pub const KIND_CREDENTIALS: &str = "credentials";
+/// Logs in with a certificate the bastion's `target_ca_key` signs for each
+/// connection, nothing is stored
+pub const KIND_CA: &str = "ca";
+
+fn default_kind() -> String {
+    KIND_CREDENTIALS.to_string()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct TargetSecret {
     pub id: Uuid,
@@ -10,7 +20,7 @@ pub struct TargetSecret {
     pub secret_id: Uuid,
     pub is_active: bool,
     pub updated_by: Uuid,
-    pub updated_at: i64,
+    pub updated_at: Timestamp,
 }
 
 /// For login to remote target
@@ -22,14 +32,33 @@ pub struct Secret {
     pub(in crate::database) password: Option<String>,
     pub(in crate::database) private_key: Option<String>,
     pub(in crate::database) public_key: Option<String>,
+    /// `credentials` or `ca`
+    #[serde(default = "default_kind")]
+    pub kind: String,
     pub is_active: bool,
     pub updated_by: Uuid,
-    pub updated_at: i64,
+    pub updated_at: Timestamp,
+    #[serde(default)]
+    pub version: i64,
+}
+
+/// Credentials a secret carried before an update replaced them
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SecretVersion {
+    pub secret_id: Uuid,
+    /// `Secret::version` while these credentials were current
+    pub version: i64,
+    pub user: String,
+    pub(in crate::database) password: Option<String>,
+    pub(in crate::database) private_key: Option<String>,
+    pub(in crate::database) public_key: Option<String>,
+    pub updated_by: Uuid,
+    pub updated_at: Timestamp,
 }
 
 impl TargetSecret {
     pub fn new(target_id: Uuid, secret_id: Uuid, updated_by: Uuid) -> Self {
-        let now = Utc::now().timestamp_millis();
+        let now = Timestamp::now();
         Self {
             id: Uuid::new_v4(),
             target_id,
@@ -43,7 +72,7 @@ impl TargetSecret {
 
 impl Secret {
     pub fn new(updated_by: Uuid) -> Self {
-        let now = Utc::now().timestamp_millis();
+        let now = Timestamp::now();
         Self {
             id: Uuid::new_v4(),
             name: String::default(),
@@ -51,12 +80,23 @@ impl Secret {
             password: None,
             private_key: None,
             public_key: None,
+            kind: default_kind(),
             is_active: true,
             updated_by,
             updated_at: now,
+            version: 0,
         }
     }
 
+    pub fn with_kind(mut self, kind: &str) -> Self {
+        self.kind = kind.to_string();
+        self
+    }
+
+    pub fn is_ca(&self) -> bool {
+        self.kind == KIND_CA
+    }
+
     pub fn with_password(mut self, password: Option<String>) -> Self {
         self.password = password;
         self
@@ -103,6 +143,8 @@ impl Secret {
     pub fn print_public_key(&self) -> String {
         if let Some(p) = self.public_key.as_ref() {
             crate::common::shorten_ssh_pubkey(p)
+        } else if self.is_ca() {
+            "signed by CA".to_string()
         } else {
             String::new()
         }
@@ -174,6 +216,25 @@ impl Secret {
         Ok(None)
     }
 
+    /// Login user and encrypted credentials are unchanged, the name and the
+    /// active flag don't count
+    pub fn same_credentials(&self, other: &Secret) -> bool {
+        self.user == other.user
+            && self.kind == other.kind
+            && self.password == other.password
+            && self.private_key == other.private_key
+            && self.public_key == other.public_key
+    }
+
+    /// Put the credentials of `v` back, the caller stores the result as a
+    /// regular update
+    pub fn restore(&mut self, v: &SecretVersion) {
+        self.user = v.user.clone();
+        self.password = v.password.clone();
+        self.private_key = v.private_key.clone();
+        self.public_key = v.public_key.clone();
+    }
+
     pub fn validate(&self, verify_key: bool) -> Result<(), ValidateError> {
         let name = self.name.trim();
         if name.is_empty() {
@@ -185,6 +246,16 @@ impl Secret {
             return Err(ValidateError::UserEmpty);
         }
 
+        match self.kind.as_str() {
+            KIND_CREDENTIALS => {}
+            KIND_CA => {
+                if self.password.is_some() || self.private_key.is_some() {
+                    return Err(ValidateError::CaWithCredentials);
+                }
+            }
+            other => return Err(ValidateError::KindInvalid(other.to_string())),
+        }
+
         if verify_key && self.gen_public_key_from_text().is_err() {
             return Err(ValidateError::PrivateKeyInvalid);
         }
@@ -193,11 +264,44 @@ impl Secret {
     }
 }
 
+impl SecretVersion {
+    pub fn of(secret: &Secret) -> Self {
+        Self {
+            secret_id: secret.id,
+            version: secret.version,
+            user: secret.user.clone(),
+            password: secret.password.clone(),
+            private_key: secret.private_key.clone(),
+            public_key: secret.public_key.clone(),
+            updated_by: secret.updated_by,
+            updated_at: secret.updated_at,
+        }
+    }
+
+    pub fn print_password(&self) -> String {
+        if self.password.is_some() {
+            "********".to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    pub fn print_public_key(&self) -> String {
+        if let Some(p) = self.public_key.as_ref() {
+            crate::common::shorten_ssh_pubkey(p)
+        } else {
+            String::new()
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ValidateError {
     NameEmpty,
     UserEmpty,
     PrivateKeyInvalid,
+    KindInvalid(String),
+    CaWithCredentials,
 }
 
 impl std::fmt::Display for ValidateError {
@@ -213,6 +317,12 @@ impl std::fmt::Display for ValidateError {
             PrivateKeyInvalid => {
                 write!(f, "invalid private key")
             }
+            KindInvalid(kind) => {
+                write!(f, "unknown secret kind '{}'", kind)
+            }
+            CaWithCredentials => {
+                write!(f, "a CA signed secret has no password or private key")
+            }
         }
     }
 }
@@ -225,10 +335,27 @@ pub struct TargetSecretName {
     pub id: Uuid,
     pub target_id: Uuid,
     pub target_name: String,
+    pub target_hostname: String,
+    pub target_port: u16,
     pub secret_id: Uuid,
     pub secret_user: String,
 }
 
+impl TargetSecretName {
+    /// `host:port`, IPv6 hosts are bracketed
+    pub fn address(&self) -> String {
+        if self.target_hostname.contains(':') {
+            format!("[{}]:{}", self.target_hostname, self.target_port)
+        } else {
+            format!("{}:{}", self.target_hostname, self.target_port)
+        }
+    }
+
+    pub fn ssh_url(&self) -> String {
+        format!("ssh://{}@{}", self.secret_user, self.address())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct SecretInfo {
     pub id: Uuid,
@@ -0,0 +1,48 @@
+use super::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use uuid::Uuid;
+
+/// Asks the server to write a debug trace of the connections logging in as
+/// `username` or coming from `client_ip` until `expires_at`. A rule without
+/// a user matches every user, one without an address every address.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct TraceRule {
+    pub id: Uuid,
+    pub username: Option<String>,
+    pub client_ip: Option<String>,
+    pub expires_at: Timestamp,
+    pub updated_by: Uuid,
+    pub updated_at: Timestamp,
+}
+
+impl TraceRule {
+    pub fn new(
+        username: Option<String>,
+        client_ip: Option<IpAddr>,
+        expires_at: Timestamp,
+        updated_by: Uuid,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            username,
+            client_ip: client_ip.map(|ip| ip.to_string()),
+            expires_at,
+            updated_by,
+            updated_at: Timestamp::now(),
+        }
+    }
+
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        now >= self.expires_at
+    }
+
+    pub fn matches(&self, username: &str, ip: Option<IpAddr>, now: Timestamp) -> bool {
+        !self.is_expired(now)
+            && self.username.as_deref().is_none_or(|u| u == username)
+            && self
+                .client_ip
+                .as_deref()
+                .is_none_or(|c| c.parse::<IpAddr>().ok().is_some_and(|c| Some(c) == ip))
+    }
+}
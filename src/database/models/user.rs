@@ -1,5 +1,7 @@
-use super::StringArray;
+use super::{StringArray, Timestamp};
+use ipnetwork::IpNetwork;
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 use std::str::FromStr;
 use uuid::Uuid;
 
@@ -7,8 +9,8 @@ use argon2::{
     Argon2,
     password_hash::{PasswordHash, PasswordVerifier},
 };
-use chrono::Utc;
-use russh::keys::ssh_key::PublicKey;
+use russh::keys::ssh_key::authorized_keys::Entry;
+use russh::keys::ssh_key::{HashAlg, PublicKey};
 
 const MAX_USERNAME_LEN: usize = 40;
 
@@ -20,25 +22,47 @@ pub struct User {
     pub email: Option<String>,
     pub(in crate::database) password_hash: Option<String>, // For password authentication
     pub(in crate::database) authorized_keys: Option<StringArray>,
+    /// Base32 TOTP secret encrypted with `secret_key`, set once the user
+    /// enrolled a second factor
+    #[serde(default)]
+    pub(in crate::database) totp_secret: Option<String>,
+    /// When the current password was set, expiry counts from here
+    #[serde(default)]
+    pub password_changed_at: Option<Timestamp>,
+    /// Hashes of the passwords used before the current one, newest first
+    #[serde(default)]
+    pub(in crate::database) password_history: Option<StringArray>,
+    /// Addresses or CIDRs logins must come from, any address when unset.
+    /// Checked before the policies' own IP rules.
+    #[serde(default)]
+    pub(in crate::database) allowed_source_cidrs: Option<StringArray>,
     pub force_init_pass: bool,
     pub is_active: bool,
     pub updated_by: Uuid,
-    pub updated_at: i64,
+    pub updated_at: Timestamp,
+    /// Row version for optimistic locking, bumped by every update
+    #[serde(default)]
+    pub version: i64,
 }
 
 impl User {
     pub fn new(updated_by: Uuid) -> Self {
-        let now = Utc::now().timestamp_millis();
+        let now = Timestamp::now();
         Self {
             id: Uuid::new_v4(),
             username: String::new(),
             email: None,
             password_hash: None,
             authorized_keys: None,
+            totp_secret: None,
+            password_changed_at: None,
+            password_history: None,
+            allowed_source_cidrs: None,
             force_init_pass: true,
             is_active: true,
             updated_by,
             updated_at: now,
+            version: 0,
         }
     }
 
@@ -48,6 +72,7 @@ impl User {
     }
 
     pub fn with_password_hash(mut self, password_hash: Option<String>) -> Self {
+        self.password_changed_at = password_hash.as_ref().map(|_| Timestamp::now());
         self.password_hash = password_hash;
         self
     }
@@ -61,6 +86,29 @@ impl User {
         self.authorized_keys = authorized_keys.map(StringArray)
     }
 
+    pub fn set_allowed_source_cidrs(&mut self, cidrs: Option<Vec<String>>) {
+        self.allowed_source_cidrs = cidrs.map(StringArray)
+    }
+
+    pub fn get_allowed_source_cidrs(&self) -> Option<&[String]> {
+        self.allowed_source_cidrs.as_ref().map(|v| v.0.as_ref())
+    }
+
+    /// A login from `ip` is allowed. With a restriction set, a client whose
+    /// address is unknown is refused.
+    pub fn allows_source(&self, ip: Option<IpAddr>) -> bool {
+        let Some(cidrs) = self.allowed_source_cidrs.as_ref() else {
+            return true;
+        };
+        ip.is_some_and(|ip| {
+            cidrs
+                .0
+                .iter()
+                .filter_map(|c| c.parse::<IpNetwork>().ok())
+                .any(|n| n.contains(ip))
+        })
+    }
+
     pub fn set_active(mut self, active: bool) -> Self {
         self.is_active = active;
         self
@@ -88,39 +136,71 @@ impl User {
         String::new()
     }
 
-    pub(crate) fn set_password_hash(&mut self, password: String) {
-        self.password_hash = Some(password);
+    /// Logins need a TOTP code after the password or key
+    pub fn has_totp(&self) -> bool {
+        self.totp_secret.is_some()
+    }
+
+    pub(crate) fn totp_secret(&self) -> Option<&str> {
+        self.totp_secret.as_deref()
+    }
+
+    pub(crate) fn set_totp_secret(&mut self, encrypted: Option<String>) {
+        self.totp_secret = encrypted;
+    }
+
+    /// Replaces the password, the old hash joins the history which keeps
+    /// at most `history_len` entries
+    pub(crate) fn set_password_hash(&mut self, password: String, history_len: usize) {
+        let mut history = self.password_history.take().map_or_else(Vec::new, |h| h.0);
+        if let Some(old) = self.password_hash.replace(password) {
+            history.insert(0, old);
+        }
+        history.truncate(history_len);
+        self.password_history = (!history.is_empty()).then_some(StringArray(history));
+        self.password_changed_at = Some(Timestamp::now());
+    }
+
+    /// The password is older than `max_age`. Users without a password, or
+    /// whose password predates the tracking, never expire.
+    pub fn password_expired(&self, max_age: Option<std::time::Duration>, now: Timestamp) -> bool {
+        let (Some(max_age), Some(changed)) = (max_age, self.password_changed_at) else {
+            return false;
+        };
+        self.password_hash.is_some()
+            && changed
+                .checked_add(max_age)
+                .is_some_and(|expiry| now >= expiry)
+    }
+
+    /// `password` is the current one or one kept in the history
+    pub(crate) fn password_reused(&self, password: &str) -> bool {
+        self.verify_password(password)
+            || self
+                .password_history
+                .iter()
+                .flat_map(|h| h.0.iter())
+                .any(|hash| verify_hash(hash, password))
     }
 
     /// Verify a password against the stored hash
     pub(crate) fn verify_password(&self, password: &str) -> bool {
-        let hash = match self.password_hash.as_ref() {
-            Some(h) => h,
-            None => return false,
-        };
-        let parsed_hash = match PasswordHash::new(hash) {
-            Ok(h) => h,
-            Err(_) => return false,
-        };
-        Argon2::default()
-            .verify_password(password.as_bytes(), &parsed_hash)
-            .is_ok()
+        match self.password_hash.as_ref() {
+            Some(h) => verify_hash(h, password),
+            None => false,
+        }
     }
 
+    /// Security keys (`sk-ssh-ed25519@openssh.com`,
+    /// `sk-ecdsa-sha2-nistp256@openssh.com`) match like any other key, their
+    /// application string included
     pub(crate) fn verify_authorized_keys(&self, pub_key: &PublicKey) -> bool {
-        if let Some(keys) = self.authorized_keys.as_ref() {
-            for k_str in keys.0.iter() {
-                match PublicKey::from_str(k_str) {
-                    Ok(ref k) => {
-                        if k.key_data() == pub_key.key_data() {
-                            return true;
-                        }
-                    }
-                    Err(_) => return false,
-                };
-            }
-        }
-        false
+        self.authorized_keys
+            .iter()
+            .flat_map(|k| k.0.iter())
+            .any(|line| {
+                parse_authorized_key(line).is_some_and(|k| k.key_data() == pub_key.key_data())
+            })
     }
 
     pub fn validate(&self) -> Result<(), ValidateError> {
@@ -139,7 +219,7 @@ impl User {
         let mut invalid_keys = Vec::new();
         if let Some(keys) = self.authorized_keys.as_ref() {
             for (i, k_str) in keys.0.iter().enumerate() {
-                if PublicKey::from_str(k_str).is_err() {
+                if parse_authorized_key(k_str).is_none() {
                     invalid_keys.push(i);
                 }
             }
@@ -147,16 +227,53 @@ impl User {
         if !invalid_keys.is_empty() {
             return Err(ValidateError::AuthorizedKeyInvalid(invalid_keys));
         }
+        if let Some(c) = self
+            .get_allowed_source_cidrs()
+            .into_iter()
+            .flatten()
+            .find(|c| c.parse::<IpNetwork>().is_err())
+        {
+            return Err(ValidateError::SourceCidrInvalid(c.clone()));
+        }
         Ok(())
     }
 }
 
+/// Key of an authorized_keys line. Options in front of it, such as the
+/// `no-touch-required` of security keys, are accepted and ignored.
+fn parse_authorized_key(line: &str) -> Option<PublicKey> {
+    Entry::from_str(line).ok().map(|e| e.public_key().clone())
+}
+
+/// `<key type> <SHA256 fingerprint>` of an authorized_keys line, as shown
+/// in the admin user form
+pub fn authorized_key_fingerprint(line: &str) -> Option<String> {
+    parse_authorized_key(line).map(|k| {
+        format!(
+            "{} {}",
+            k.algorithm().as_str(),
+            k.fingerprint(HashAlg::Sha256)
+        )
+    })
+}
+
+fn verify_hash(hash: &str, password: &str) -> bool {
+    let parsed_hash = match PasswordHash::new(hash) {
+        Ok(h) => h,
+        Err(_) => return false,
+    };
+    Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .is_ok()
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ValidateError {
     UsernameEmpty,
     UsernameTooLong,
     EmailInvalid,
     AuthorizedKeyInvalid(Vec<usize>),
+    SourceCidrInvalid(String),
 }
 
 impl std::fmt::Display for ValidateError {
@@ -182,6 +299,9 @@ impl std::fmt::Display for ValidateError {
                         .join(", ")
                 )
             }
+            SourceCidrInvalid(c) => {
+                write!(f, "'{}' is not an IP address or CIDR", c)
+            }
         }
     }
 }
@@ -198,3 +318,56 @@ impl UserWithRole {
         self.user.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows_source() {
+        let mut user = User::new(Uuid::nil());
+        user.username = "deploy".to_string();
+        assert!(user.allows_source(None));
+
+        user.set_allowed_source_cidrs(Some(vec![
+            "10.20.0.0/16".to_string(),
+            "2001:db8::/32".to_string(),
+        ]));
+        assert!(user.validate().is_ok());
+        assert!(user.allows_source(Some("10.20.3.4".parse().unwrap())));
+        assert!(user.allows_source(Some("2001:db8::7".parse().unwrap())));
+        assert!(!user.allows_source(Some("10.21.0.1".parse().unwrap())));
+        assert!(!user.allows_source(None));
+
+        user.set_allowed_source_cidrs(Some(vec!["10.20.0.0/33".to_string()]));
+        assert!(user.validate().is_err());
+    }
+
+    #[test]
+    fn test_security_keys() {
+        let sk_ed25519 = "sk-ssh-ed25519@openssh.com AAAAGnNrLXNzaC1lZDI1NTE5QG9wZW5zc2guY29tAAAAIAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8gAAAABHNzaDo= yubikey";
+        let sk_ecdsa = "sk-ecdsa-sha2-nistp256@openssh.com AAAAInNrLWVjZHNhLXNoYTItbmlzdHAyNTZAb3BlbnNzaC5jb20AAAAIbmlzdHAyNTYAAABBBGsX0fLhLEJH+Lzm5WOkQPJ3A32BLeszoPShOUXYmMKWT+NC4v4af5uO5+tKfA+eFivOM1drMV7Oy7ZAaDe/UfUAAAAEc3NoOg== solokey";
+
+        let mut user = User::new(Uuid::nil());
+        user.username = "alice".to_string();
+        user.set_authorized_keys(Some(vec![
+            format!("no-touch-required {}", sk_ed25519),
+            sk_ecdsa.to_string(),
+        ]));
+        assert!(user.validate().is_ok());
+
+        for line in [sk_ed25519, sk_ecdsa] {
+            let key = PublicKey::from_str(line).unwrap();
+            assert!(user.verify_authorized_keys(&key));
+        }
+        let other = PublicKey::from_str(
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8g",
+        )
+        .unwrap();
+        assert!(!user.verify_authorized_keys(&other));
+
+        let fingerprint = authorized_key_fingerprint(sk_ed25519).unwrap();
+        assert!(fingerprint.starts_with("sk-ssh-ed25519@openssh.com SHA256:"));
+        assert!(authorized_key_fingerprint("sk-ssh-ed25519@openssh.com AAAA").is_none());
+    }
+}
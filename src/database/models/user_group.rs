@@ -0,0 +1,55 @@
+use super::Timestamp;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Named set of users, usable as a policy subject like a g1 role
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct UserGroup {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub is_active: bool,
+    pub updated_by: Uuid,
+    pub updated_at: Timestamp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct UserGroupMember {
+    pub id: Uuid,
+    pub group_id: Uuid,
+    pub user_id: Uuid,
+    pub updated_by: Uuid,
+    pub updated_at: Timestamp,
+}
+
+impl UserGroup {
+    pub fn new(name: String, updated_by: Uuid) -> Self {
+        let now = Timestamp::now();
+        Self {
+            id: Uuid::new_v4(),
+            name,
+            description: None,
+            is_active: true,
+            updated_by,
+            updated_at: now,
+        }
+    }
+
+    pub fn with_description(mut self, description: Option<String>) -> Self {
+        self.description = description;
+        self
+    }
+}
+
+impl UserGroupMember {
+    pub fn new(group_id: Uuid, user_id: Uuid, updated_by: Uuid) -> Self {
+        let now = Timestamp::now();
+        Self {
+            id: Uuid::new_v4(),
+            group_id,
+            user_id,
+            updated_by,
+            updated_at: now,
+        }
+    }
+}
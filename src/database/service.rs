@@ -1,9 +1,44 @@
+use chrono::{DateTime, TimeDelta, Utc};
 use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
 
+use crate::database::common::OBJ_LOGIN;
+use crate::database::error::DatabaseError;
+use crate::database::models::access_request::{STATUS_APPROVED, STATUS_DENIED, STATUS_EXPIRED};
+use crate::database::models::log::verify_chain;
+use crate::database::models::{
+    AccessRequest, ApiToken, CasbinName, CasbinRule, Names, PolicyChange, Secret, StringArray,
+    Target, TargetSecret, TargetTag, Timestamp, User, UserGroup, UserGroupMember,
+};
 use crate::database::{create_repository, DatabaseConfig, DatabaseRepository};
 use crate::error::Error;
 
+/// Rows per batch insert when importing, keeps statements under
+/// SQLite's bound parameter limit.
+const IMPORT_BATCH_SIZE: usize = 500;
+
+/// A casbin rule before and after a change, `None` where it doesn't exist
+type RuleChange<'a> = (Option<&'a CasbinRule>, Option<&'a CasbinRule>);
+
+/// Full dataset in the same shape as `mock_data.json`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Dataset {
+    pub users: Vec<User>,
+    pub user_groups: Vec<UserGroup>,
+    pub user_group_members: Vec<UserGroupMember>,
+    pub secrets: Vec<Secret>,
+    pub targets: Vec<Target>,
+    pub target_secrets: Vec<TargetSecret>,
+    pub target_tags: Vec<TargetTag>,
+    pub casbin_names: Vec<CasbinName>,
+    pub casbin_rule: Vec<CasbinRule>,
+}
+
 /// Database service that provides high-level operations
 #[derive(Clone)]
 pub struct DatabaseService {
@@ -24,30 +59,511 @@ impl DatabaseService {
     pub fn repository(&self) -> &dyn DatabaseRepository {
         self.repository.as_ref().as_ref()
     }
+
+    /// Dump users, targets, secrets, bindings and policies, including
+    /// inactive rows, so the result can be imported into another backend.
+    pub async fn export_all(&self) -> Result<Dataset, Error> {
+        let repo = self.repository();
+        Ok(Dataset {
+            users: repo.list_users(false).await?,
+            user_groups: repo.list_user_groups(false).await?,
+            user_group_members: repo.list_user_group_members().await?,
+            secrets: repo.list_secrets(false).await?,
+            targets: repo.list_targets(false, &[]).await?,
+            target_secrets: repo.list_target_secrets(false).await?,
+            target_tags: repo.list_target_tags().await?,
+            casbin_names: repo.list_casbin_names(false).await?,
+            casbin_rule: repo.list_casbin_rules().await?,
+        })
+    }
+
+    /// Fails unless every table touched by `import_all` is empty.
+    pub async fn check_import(&self) -> Result<(), Error> {
+        let repo = self.repository();
+        if repo.count_users().await? > 0 {
+            return Err(DatabaseError::NotEmpty("users").into());
+        }
+        if !repo.list_user_groups(false).await?.is_empty() {
+            return Err(DatabaseError::NotEmpty("user_groups").into());
+        }
+        if repo.count_targets().await? > 0 {
+            return Err(DatabaseError::NotEmpty("targets").into());
+        }
+        if !repo.list_secrets(false).await?.is_empty() {
+            return Err(DatabaseError::NotEmpty("secrets").into());
+        }
+        if !repo.list_casbin_names(false).await?.is_empty() {
+            return Err(DatabaseError::NotEmpty("casbin_names").into());
+        }
+        if !repo.list_casbin_rules().await?.is_empty() {
+            return Err(DatabaseError::NotEmpty("casbin_rule").into());
+        }
+        Ok(())
+    }
+
+    /// Load a dataset produced by `export_all`. The database must be empty,
+    /// ids are kept as-is.
+    pub async fn import_all(&self, data: &Dataset) -> Result<(), Error> {
+        self.check_import().await?;
+        let repo = self.repository();
+        for chunk in data.users.chunks(IMPORT_BATCH_SIZE) {
+            repo.create_users_batch(chunk).await?;
+        }
+        for chunk in data.user_groups.chunks(IMPORT_BATCH_SIZE) {
+            repo.create_user_groups_batch(chunk).await?;
+        }
+        for chunk in data.user_group_members.chunks(IMPORT_BATCH_SIZE) {
+            repo.create_user_group_members_batch(chunk).await?;
+        }
+        for chunk in data.casbin_names.chunks(IMPORT_BATCH_SIZE) {
+            repo.create_casbin_names_batch(chunk).await?;
+        }
+        for chunk in data.targets.chunks(IMPORT_BATCH_SIZE) {
+            repo.create_targets_batch(chunk).await?;
+        }
+        for chunk in data.target_tags.chunks(IMPORT_BATCH_SIZE) {
+            repo.create_target_tags_batch(chunk).await?;
+        }
+        for chunk in data.secrets.chunks(IMPORT_BATCH_SIZE) {
+            repo.create_secrets_batch(chunk).await?;
+        }
+        for chunk in data.target_secrets.chunks(IMPORT_BATCH_SIZE) {
+            repo.create_target_secrets_batch(chunk).await?;
+        }
+        for chunk in data.casbin_rule.chunks(IMPORT_BATCH_SIZE) {
+            repo.create_casbin_rules_batch(chunk).await?;
+        }
+        info!(
+            "Imported {} users, {} targets, {} secrets, {} target secrets, {} casbin names, {} casbin rules",
+            data.users.len(),
+            data.targets.len(),
+            data.secrets.len(),
+            data.target_secrets.len(),
+            data.casbin_names.len(),
+            data.casbin_rule.len()
+        );
+        let named = self.name_unnamed_rule_ids(None).await?;
+        if named > 0 {
+            info!("Named {} ids referenced by imported casbin rules", named);
+        }
+        // Tags of imported targets may have no `tag:` group yet
+        if !data.target_tags.is_empty() {
+            repo.sync_tag_groups(&self.internal_author().await?).await?;
+        }
+        Ok(())
+    }
+
+    /// Author of the rows the bastion writes on its own, the user who created
+    /// the internal login object at `init`. `updated_by` columns reference
+    /// users, so a nil author fails their foreign keys.
+    pub async fn internal_author(&self) -> Result<Uuid, Error> {
+        self.repository()
+            .get_casbin_name_by_name(OBJ_LOGIN)
+            .await?
+            .map(|name| name.updated_by)
+            .ok_or_else(|| {
+                DatabaseError::NotFound {
+                    table: "casbin_names",
+                    key: OBJ_LOGIN.to_string(),
+                }
+                .into()
+            })
+    }
+
+    /// Ids of every user, group, target, binding and casbin name, the
+    /// things a rule may refer to
+    pub async fn known_ids(&self) -> Result<HashSet<Uuid>, Error> {
+        let repo = self.repository();
+        let mut known: HashSet<Uuid> = HashSet::new();
+        known.extend(repo.list_users(false).await?.into_iter().map(|u| u.id));
+        known.extend(repo.list_user_groups(false).await?.iter().map(|g| g.id));
+        known.extend(repo.list_targets(false, &[]).await?.iter().map(|t| t.id));
+        known.extend(repo.list_target_secrets(false).await?.iter().map(|t| t.id));
+        known.extend(repo.list_casbin_names(false).await?.iter().map(|n| n.id));
+        Ok(known)
+    }
+
+    /// Ids referenced by casbin rules that resolve to no user, group, target,
+    /// binding or casbin name. Each comes with the ptype a name for it would
+    /// get and the author of the first rule using it. Such ids show up as raw
+    /// UUIDs in the permission screens.
+    pub async fn unnamed_rule_ids(&self) -> Result<Vec<(Uuid, &'static str, Uuid)>, Error> {
+        let repo = self.repository();
+        let mut known = self.known_ids().await?;
+        let mut unnamed = Vec::new();
+        for rule in repo.list_casbin_rules().await? {
+            // The third field of grouping rules is the nil domain
+            let columns: &[(Uuid, &'static str)] = match rule.ptype.as_str() {
+                "p" | "p_deny" => &[(rule.v0, "g1"), (rule.v1, "g2"), (rule.v2, "g3")],
+                "g1" => &[(rule.v0, "g1"), (rule.v1, "g1")],
+                "g2" => &[(rule.v0, "g2"), (rule.v1, "g2")],
+                "g3" => &[(rule.v0, "g3"), (rule.v1, "g3")],
+                _ => &[],
+            };
+            for &(id, ptype) in columns {
+                if !id.is_nil() && known.insert(id) {
+                    unnamed.push((id, ptype, rule.updated_by));
+                }
+            }
+        }
+        Ok(unnamed)
+    }
+
+    /// Gives every id found by `unnamed_rule_ids` a placeholder name that
+    /// admins can rename later, credited to `updated_by` or else to the rule
+    /// author. Returns how many names were created.
+    pub async fn name_unnamed_rule_ids(&self, updated_by: Option<&Uuid>) -> Result<usize, Error> {
+        let unnamed = self.unnamed_rule_ids().await?;
+        let names: Vec<CasbinName> = unnamed
+            .iter()
+            .map(|&(id, ptype, author)| {
+                let kind = match ptype {
+                    "g1" => "role",
+                    "g2" => "targets",
+                    _ => "actions",
+                };
+                let mut name = CasbinName::new(
+                    ptype.to_string(),
+                    format!("unnamed-{}-{}", kind, &id.simple().to_string()[..8]),
+                    true,
+                    updated_by.copied().unwrap_or(author),
+                );
+                name.id = id;
+                name
+            })
+            .collect();
+        for chunk in names.chunks(IMPORT_BATCH_SIZE) {
+            self.repository().create_casbin_names_batch(chunk).await?;
+        }
+        Ok(names.len())
+    }
+
+    /// Resolve a plain API token to its row and owner. Unknown, revoked or
+    /// expired tokens, tokens lacking `scope` and tokens of inactive users
+    /// all give `None`. A successful lookup bumps `last_used_at`.
+    pub async fn authenticate_api_token(
+        &self,
+        token: &str,
+        scope: &str,
+    ) -> Result<Option<(ApiToken, User)>, Error> {
+        let repo = self.repository();
+        let now = Timestamp::now();
+        let api_token = match repo
+            .get_api_token_by_hash(&ApiToken::hash_token(token))
+            .await?
+        {
+            Some(t) if t.is_usable(now) && t.has_scope(scope) => t,
+            _ => return Ok(None),
+        };
+        let user = match repo.get_user_by_id(&api_token.user_id).await? {
+            Some(u) if u.is_active => u,
+            _ => return Ok(None),
+        };
+        repo.touch_api_token(&api_token.id, now).await?;
+
+        Ok(Some((api_token, user)))
+    }
+
+    /// Check the hash chain of `logs`, returns the number of entries and the
+    /// hash of the newest one
+    pub async fn verify_logs(&self) -> Result<(usize, String), Error> {
+        let logs = self.repository().list_logs().await?;
+        Ok(verify_chain(&logs).map_err(DatabaseError::from)?)
+    }
+
+    /// Names of everything a casbin rule refers to by id: roles, groups and
+    /// actions, user groups, users, and bindings as `user@target`. Targets
+    /// and secrets are named too, for the tables that point at them.
+    pub async fn rule_labels(&self) -> Result<Names, Error> {
+        let repo = self.repository();
+        let mut labels = Names::default();
+        labels.extend(
+            repo.list_casbin_names(false)
+                .await?
+                .into_iter()
+                .map(|n| (n.id, n.name)),
+        );
+        labels.extend(
+            repo.list_user_groups(false)
+                .await?
+                .into_iter()
+                .map(|g| (g.id, g.name)),
+        );
+        labels.extend(
+            repo.list_users(false)
+                .await?
+                .into_iter()
+                .map(|u| (u.id, u.username)),
+        );
+        let targets: HashMap<Uuid, String> = repo
+            .list_targets(false, &[])
+            .await?
+            .into_iter()
+            .map(|t| (t.id, t.name))
+            .collect();
+        let secrets = repo.list_secrets(false).await?;
+        let accounts: HashMap<Uuid, &str> =
+            secrets.iter().map(|s| (s.id, s.user.as_str())).collect();
+        for ts in repo.list_target_secrets(false).await? {
+            if let (Some(t), Some(s)) = (targets.get(&ts.target_id), accounts.get(&ts.secret_id)) {
+                labels.insert(ts.id, format!("{}@{}", s, t));
+            }
+        }
+        labels.extend(secrets.iter().map(|s| (s.id, s.name.clone())));
+        labels.extend(targets);
+        Ok(labels)
+    }
+
+    /// Appends a `policy_changes` entry per rule changed by `changed_by`,
+    /// each pair being the rule before and after
+    async fn record_policy_changes(
+        &self,
+        changes: &[RuleChange<'_>],
+        changed_by: &Uuid,
+    ) -> Result<(), Error> {
+        let labels = self.rule_labels().await?;
+        for (before, after) in changes {
+            if let Some(change) = PolicyChange::new(*before, *after, *changed_by, &labels) {
+                self.repository().insert_policy_change(&change).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the rule and its audit entry, `updated_by` is taken as the
+    /// one who made the change. Rule edits made for an admin or a user go
+    /// through these instead of the repository.
+    pub async fn create_casbin_rule(&self, rule: &CasbinRule) -> Result<CasbinRule, Error> {
+        let created = self.repository().create_casbin_rule(rule).await?;
+        self.record_policy_changes(&[(None, Some(&created))], &rule.updated_by)
+            .await?;
+        Ok(created)
+    }
+
+    pub async fn update_casbin_rule(&self, rule: &CasbinRule) -> Result<CasbinRule, Error> {
+        let repo = self.repository();
+        let before = repo.get_casbin_rule_by_id(&rule.id).await?;
+        let updated = repo.update_casbin_rule(rule).await?;
+        self.record_policy_changes(&[(before.as_ref(), Some(&updated))], &rule.updated_by)
+            .await?;
+        Ok(updated)
+    }
+
+    pub async fn delete_casbin_rule(&self, id: &Uuid, changed_by: &Uuid) -> Result<bool, Error> {
+        let repo = self.repository();
+        let before = repo.get_casbin_rule_by_id(id).await?;
+        let deleted = repo.delete_casbin_rule(id).await?;
+        if deleted {
+            self.record_policy_changes(&[(before.as_ref(), None)], changed_by)
+                .await?;
+        }
+        Ok(deleted)
+    }
+
+    /// Removes the `ptype` rules linking `v0` and `v1`, see the repository
+    /// method of the same name
+    pub async fn delete_casbin_rule_by_v0_v1(
+        &self,
+        ptype: &str,
+        v0: &Uuid,
+        v1: &Uuid,
+        changed_by: &Uuid,
+    ) -> Result<bool, Error> {
+        let repo = self.repository();
+        let rules: Vec<CasbinRule> = repo
+            .list_casbin_rules_by_ptype(ptype)
+            .await?
+            .into_iter()
+            .filter(|r| r.v0 == *v0 && r.v1 == *v1)
+            .collect();
+        let deleted = repo.delete_casbin_rule_by_v0_v1(ptype, v0, v1).await?;
+        if deleted {
+            let changes: Vec<RuleChange> = rules.iter().map(|r| (Some(r), None)).collect();
+            self.record_policy_changes(&changes, changed_by).await?;
+        }
+        Ok(deleted)
+    }
+
+    /// Files a request of `user_id` for the binding `target_secret_id`. A `p`
+    /// rule per action in `actions` is written with it, flagged `pending` so
+    /// it grants nothing until approved.
+    pub async fn request_access(
+        &self,
+        user_id: &Uuid,
+        target_secret_id: &Uuid,
+        actions: &[Uuid],
+        duration: Duration,
+        reason: String,
+    ) -> Result<AccessRequest, Error> {
+        let repo = self.repository();
+        if repo
+            .list_access_requests(Some(user_id))
+            .await?
+            .iter()
+            .any(|r| r.is_pending() && r.target_secret_id == *target_secret_id)
+        {
+            return Err(DatabaseError::AccessRequestPending.into());
+        }
+
+        let mut request = AccessRequest::new(*user_id, *target_secret_id, duration, reason);
+        let ext = access_ext(request.duration, true);
+        let rules: Vec<CasbinRule> = actions
+            .iter()
+            .map(|act| {
+                CasbinRule::new(
+                    "p".to_string(),
+                    *user_id,
+                    *target_secret_id,
+                    *act,
+                    ext.clone(),
+                    String::new(),
+                    String::new(),
+                    *user_id,
+                )
+            })
+            .collect();
+        repo.create_casbin_rules_batch(&rules).await?;
+        let changes: Vec<RuleChange> = rules.iter().map(|r| (None, Some(r))).collect();
+        self.record_policy_changes(&changes, user_id).await?;
+        request.rule_ids = StringArray(rules.iter().map(|r| r.id.to_string()).collect());
+        Ok(repo.create_access_request(&request).await?)
+    }
+
+    /// Clears the `pending` flag of the request's rules, the access lasts the
+    /// requested duration from now on
+    pub async fn approve_access_request(
+        &self,
+        id: &Uuid,
+        decided_by: &Uuid,
+    ) -> Result<AccessRequest, Error> {
+        let repo = self.repository();
+        let request = self.pending_access_request(id).await?;
+        let rule_ids = request.rule_ids();
+        let rules: Vec<CasbinRule> = repo
+            .list_casbin_rules_by_ptype("p")
+            .await?
+            .into_iter()
+            .filter(|r| rule_ids.contains(&r.id))
+            .collect();
+        if rules.is_empty() {
+            return Err(DatabaseError::NotFound {
+                table: "casbin_rule",
+                key: format!("of access request {}", id),
+            }
+            .into());
+        }
+
+        let ext = access_ext(request.duration, false);
+        for mut rule in rules {
+            rule.v3 = ext.clone();
+            rule.updated_by = *decided_by;
+            self.update_casbin_rule(&rule).await?;
+        }
+        self.decide_access_request(request, STATUS_APPROVED, decided_by)
+            .await
+    }
+
+    /// Drops the pending rules of the request
+    pub async fn deny_access_request(
+        &self,
+        id: &Uuid,
+        decided_by: &Uuid,
+    ) -> Result<AccessRequest, Error> {
+        let request = self.pending_access_request(id).await?;
+        for rule_id in request.rule_ids() {
+            self.delete_casbin_rule(&rule_id, decided_by).await?;
+        }
+        self.decide_access_request(request, STATUS_DENIED, decided_by)
+            .await
+    }
+
+    /// Deletes the rules `ids` once they ran out, audited as changed by the
+    /// nil user. Approved requests left without any of their rules are
+    /// marked `expired` and returned.
+    pub async fn delete_expired_casbin_rules(
+        &self,
+        ids: &[Uuid],
+    ) -> Result<Vec<AccessRequest>, Error> {
+        for id in ids {
+            self.delete_casbin_rule(id, &Uuid::nil()).await?;
+        }
+        let repo = self.repository();
+        let mut expired = Vec::new();
+        for mut request in repo.list_access_requests(None).await? {
+            if request.status != STATUS_APPROVED {
+                continue;
+            }
+            let rule_ids = request.rule_ids();
+            if !rule_ids.iter().any(|id| ids.contains(id)) {
+                continue;
+            }
+            let mut remaining = false;
+            for id in &rule_ids {
+                if repo.get_casbin_rule_by_id(id).await?.is_some() {
+                    remaining = true;
+                    break;
+                }
+            }
+            if !remaining {
+                request.status = STATUS_EXPIRED.to_string();
+                expired.push(repo.update_access_request(&request).await?);
+            }
+        }
+        Ok(expired)
+    }
+
+    async fn pending_access_request(&self, id: &Uuid) -> Result<AccessRequest, Error> {
+        match self.repository().get_access_request_by_id(id).await? {
+            Some(r) if r.is_pending() => Ok(r),
+            Some(r) => Err(DatabaseError::AccessRequestDecided {
+                id: r.id,
+                status: r.status,
+            }
+            .into()),
+            None => Err(DatabaseError::NotFound {
+                table: "access_requests",
+                key: id.to_string(),
+            }
+            .into()),
+        }
+    }
+
+    async fn decide_access_request(
+        &self,
+        mut request: AccessRequest,
+        status: &str,
+        decided_by: &Uuid,
+    ) -> Result<AccessRequest, Error> {
+        request.status = status.to_string();
+        request.decided_by = Some(*decided_by);
+        request.decided_at = Some(Timestamp::now());
+        Ok(self.repository().update_access_request(&request).await?)
+    }
+}
+
+/// `p.ext` of an access request, expiring `secs` from now. The format is the
+/// one `ExtendPolicy` parses: ip, start, end, expire date, then flags.
+fn access_ext(secs: i64, pending: bool) -> String {
+    let now = Utc::now();
+    let expire = TimeDelta::try_seconds(secs)
+        .and_then(|d| now.checked_add_signed(d))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC);
+    let mut ext = format!(",,,{}", expire.format("%Y-%m-%d %H:%M:%S %z"));
+    if pending {
+        ext.push_str(",pending");
+    }
+    ext
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::database::{
-        models::{target_secret::TargetSecret, CasbinRule, Secret},
-        CasbinName, Target, User,
-    };
-    use serde::{Deserialize, Serialize};
+    use crate::database::models::policy_change::{CHANGE_CREATED, CHANGE_DELETED, CHANGE_UPDATED};
+    use crate::database::models::{BannedIp, Log, SessionRecording, SshSession};
     use serde_json;
-    use std::{fs::File, io::Read};
+    use std::{fs::File, io::Read, time::Duration};
     use tempfile::tempdir;
 
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    struct RawData {
-        users: Vec<User>,
-        targets: Vec<Target>,
-        secrets: Vec<Secret>,
-        target_secrets: Vec<TargetSecret>,
-        casbin_rule: Vec<CasbinRule>,
-        casbin_names: Vec<CasbinName>,
-    }
-
     async fn create_test_service() -> DatabaseService {
         let temp_dir = tempdir().unwrap();
         let db_path = temp_dir.path().join("test.db");
@@ -56,10 +572,15 @@ mod tests {
             path: db_path.to_string_lossy().to_string(),
         };
         let db = DatabaseService::new(&config).await.unwrap();
+        load_mock_data(&db).await;
+        db
+    }
+
+    async fn load_mock_data(db: &DatabaseService) {
         let mut test_data = File::open("mock_data.json").unwrap();
         let mut buffer = String::new();
         test_data.read_to_string(&mut buffer).unwrap();
-        let mut raw_data: RawData = serde_json::from_str(&buffer).unwrap();
+        let mut raw_data: Dataset = serde_json::from_str(&buffer).unwrap();
         db.repository
             .create_user(&raw_data.users.pop().unwrap())
             .await
@@ -108,17 +629,17 @@ mod tests {
             .create_casbin_names_batch(&raw_data.casbin_names)
             .await
             .unwrap();
-
-        db
     }
 
-    #[tokio::test]
-    async fn test_db_service() {
-        let service = create_test_service().await;
-
+    async fn assert_mock_data_counts(service: &DatabaseService) {
         assert_eq!(service.repository.list_users(false).await.unwrap().len(), 5);
         assert_eq!(
-            service.repository.list_targets(false).await.unwrap().len(),
+            service
+                .repository
+                .list_targets(false, &[])
+                .await
+                .unwrap()
+                .len(),
             30
         );
         assert_eq!(
@@ -148,4 +669,1075 @@ mod tests {
             21
         );
     }
+
+    #[tokio::test]
+    async fn test_db_service() {
+        let service = create_test_service().await;
+        assert_mock_data_counts(&service).await;
+    }
+
+    #[tokio::test]
+    async fn test_memory_db_service() {
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        load_mock_data(&service).await;
+        assert_mock_data_counts(&service).await;
+
+        // Repositories opened with the same name share their data
+        let shared = DatabaseService::new(&config).await.unwrap();
+        assert_mock_data_counts(&shared).await;
+
+        let user = shared.repository().list_users(false).await.unwrap()[0].clone();
+        let err = shared.repository().create_user(&user).await.unwrap_err();
+        assert!(matches!(
+            err,
+            crate::error::Error::Sqlx(sqlx::Error::Database(ref e))
+                if e.kind() == sqlx::error::ErrorKind::UniqueViolation
+        ));
+    }
+
+    async fn assert_delete_integrity(service: &DatabaseService) {
+        let repo = service.repository();
+        let ts = repo.list_target_secrets(true).await.unwrap()[0].clone();
+        let err = repo.delete_target(&ts.target_id, false).await.unwrap_err();
+        assert!(matches!(
+            err,
+            crate::error::Error::Database(crate::database::error::DatabaseError::ActiveBindings {
+                count
+            }) if count > 0
+        ));
+        assert!(repo
+            .get_target_by_id(&ts.target_id, false)
+            .await
+            .unwrap()
+            .is_some());
+
+        let binding_ids: Vec<_> = repo
+            .list_target_secrets(false)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|v| v.target_id == ts.target_id)
+            .map(|v| v.id)
+            .collect();
+        assert!(repo.delete_target(&ts.target_id, true).await.unwrap());
+        assert!(!repo
+            .list_target_secrets(false)
+            .await
+            .unwrap()
+            .iter()
+            .any(|v| v.target_id == ts.target_id));
+        assert!(!repo
+            .list_casbin_rules()
+            .await
+            .unwrap()
+            .iter()
+            .any(|r| binding_ids.contains(&r.v0) || binding_ids.contains(&r.v1)));
+
+        let name = repo.list_casbin_names_by_ptype("g1", false).await.unwrap()[0].clone();
+        assert!(repo.delete_casbin_name(&name.id).await.unwrap());
+        assert!(!repo
+            .list_casbin_rules()
+            .await
+            .unwrap()
+            .iter()
+            .any(|r| r.v0 == name.id || r.v1 == name.id || r.v2 == name.id));
+    }
+
+    #[tokio::test]
+    async fn test_delete_integrity() {
+        let service = create_test_service().await;
+        assert_delete_integrity(&service).await;
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        load_mock_data(&service).await;
+        assert_delete_integrity(&service).await;
+    }
+
+    async fn assert_recording_ticket(service: &DatabaseService) {
+        let repo = service.repository();
+        let ts = repo.list_target_secrets(true).await.unwrap()[0].clone();
+        let with_ticket = SessionRecording::new(
+            uuid::Uuid::new_v4(),
+            ts.target_id,
+            ts.secret_id,
+            uuid::Uuid::new_v4(),
+        )
+        .with_ticket(Some("CHG-1234".to_string()));
+        let without_ticket = SessionRecording::new(
+            uuid::Uuid::new_v4(),
+            ts.target_id,
+            ts.secret_id,
+            uuid::Uuid::new_v4(),
+        );
+        repo.create_session_recording(&with_ticket).await.unwrap();
+        repo.create_session_recording(&without_ticket)
+            .await
+            .unwrap();
+
+        let rec = repo
+            .get_session_recording_by_id(&with_ticket.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(rec.ticket.as_deref(), Some("CHG-1234"));
+        let rec = repo
+            .get_session_recording_by_id(&without_ticket.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(rec.ticket, None);
+        assert_eq!(rec.duration_ms, None);
+
+        let mut rec = rec;
+        rec.set_stats(Duration::from_secs(600), Duration::from_secs(90), 42);
+        repo.update_session_recording(&rec).await.unwrap();
+        let rec = repo
+            .get_session_recording_by_id(&without_ticket.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(rec.duration_ms, Some(600_000));
+        assert_eq!(rec.active_ms, Some(90_000));
+        assert_eq!(rec.event_count, Some(42));
+    }
+
+    #[tokio::test]
+    async fn test_session_recording_ticket() {
+        let service = create_test_service().await;
+        assert_recording_ticket(&service).await;
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        load_mock_data(&service).await;
+        assert_recording_ticket(&service).await;
+    }
+
+    async fn assert_stale_update(service: &DatabaseService) {
+        let repo = service.repository();
+        let target = repo.list_targets(false, &[]).await.unwrap()[0].clone();
+        let mut first = target.clone();
+        first.description = Some("first".to_string());
+        let saved = repo.update_target(&first).await.unwrap();
+        assert_eq!(saved.version, target.version + 1);
+
+        let mut second = target.clone();
+        second.description = Some("second".to_string());
+        let err = repo.update_target(&second).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Database(DatabaseError::StaleRow { table: "targets", id }) if id == target.id
+        ));
+        let current = repo
+            .get_target_by_id(&target.id, false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(current.description.as_deref(), Some("first"));
+        assert_eq!(current.version, saved.version);
+
+        let mut user = repo.list_users(false).await.unwrap()[0].clone();
+        user.set_allowed_source_cidrs(Some(vec!["10.20.0.0/16".to_string()]));
+        repo.update_user(&user).await.unwrap();
+        assert!(repo.update_user(&user).await.is_err());
+        let current = repo.get_user_by_id(&user.id).await.unwrap().unwrap();
+        assert_eq!(
+            current.get_allowed_source_cidrs(),
+            Some(&["10.20.0.0/16".to_string()][..])
+        );
+        let secret = repo.list_secrets(false).await.unwrap()[0].clone();
+        repo.update_secret(&secret).await.unwrap();
+        assert!(repo.update_secret(&secret).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stale_update() {
+        let service = create_test_service().await;
+        assert_stale_update(&service).await;
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        load_mock_data(&service).await;
+        assert_stale_update(&service).await;
+    }
+
+    async fn assert_target_tags(service: &DatabaseService) {
+        use crate::database::models::{CasbinRuleGroup, TagFilter, TargetSecretName, TargetTag};
+
+        let repo = service.repository();
+        let ts = repo.list_target_secrets(true).await.unwrap()[0].clone();
+        let updated_by = uuid::Uuid::new_v4();
+        let tags: Vec<_> = TagFilter::parse_list("env=prod role=db")
+            .unwrap()
+            .into_iter()
+            .map(|f| TargetTag::new(ts.target_id, f, updated_by))
+            .collect();
+        repo.set_target_tags(&ts.target_id, &tags).await.unwrap();
+        assert_eq!(
+            repo.list_tags_for_target(&ts.target_id)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let filter = TagFilter::parse_list("env=prod,role=db").unwrap();
+        let targets = repo.list_targets(false, &filter).await.unwrap();
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].id, ts.target_id);
+        let filter = TagFilter::parse_list("env=prod role=web").unwrap();
+        assert!(repo.list_targets(false, &filter).await.unwrap().is_empty());
+
+        assert_eq!(repo.sync_tag_groups(&updated_by).await.unwrap(), 2);
+        let group = repo
+            .list_casbin_names_by_ptype("g2", false)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|n| n.name == "tag:env=prod")
+            .unwrap();
+        let tag_members = |rules: Vec<CasbinRuleGroup>| -> Vec<Uuid> {
+            rules
+                .into_iter()
+                .filter(|r| r.v1 == group.id)
+                .map(|r| r.v0)
+                .collect()
+        };
+        let members = tag_members(repo.list_tag_rule_groups().await.unwrap());
+        assert!(members.contains(&ts.id));
+
+        // A member stored by an older sync is dropped, the tags resolve it
+        let stored = CasbinRule::new(
+            "g2".to_string(),
+            ts.id,
+            group.id,
+            Uuid::default(),
+            String::new(),
+            String::new(),
+            String::new(),
+            updated_by,
+        );
+        repo.create_casbin_rule(&stored).await.unwrap();
+        assert_eq!(repo.sync_tag_groups(&updated_by).await.unwrap(), 2);
+        let g2 = repo.list_casbin_rule_group_by_ptype("g2").await.unwrap();
+        assert!(tag_members(g2).is_empty());
+
+        // A policy on the group reaches the tagged target's bindings
+        let user = repo.list_users(true).await.unwrap()[0].clone();
+        let policy = CasbinRule::new(
+            "p".to_string(),
+            user.id,
+            group.id,
+            Uuid::new_v4(),
+            String::new(),
+            String::new(),
+            String::new(),
+            updated_by,
+        );
+        repo.create_casbin_rule(&policy).await.unwrap();
+        let reached = |targets: Vec<TargetSecretName>| {
+            targets.iter().any(|t| t.pid == policy.id && t.id == ts.id)
+        };
+        let targets = repo.list_targets_for_user(&user.id, false).await.unwrap();
+        assert!(reached(targets));
+
+        repo.set_target_tags(&ts.target_id, &[]).await.unwrap();
+        assert!(repo
+            .list_tags_for_target(&ts.target_id)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(tag_members(repo.list_tag_rule_groups().await.unwrap()).is_empty());
+        let targets = repo.list_targets_for_user(&user.id, false).await.unwrap();
+        assert!(!reached(targets));
+    }
+
+    #[tokio::test]
+    async fn test_target_tags() {
+        let service = create_test_service().await;
+        assert_target_tags(&service).await;
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        load_mock_data(&service).await;
+        assert_target_tags(&service).await;
+    }
+
+    async fn assert_policy_version(service: &DatabaseService) {
+        use crate::database::models::UserGroup;
+
+        let repo = service.repository();
+        let start = repo.get_policy_version().await.unwrap();
+        let mut user = repo.list_users(false).await.unwrap()[0].clone();
+        user.email = Some("ops@example.com".to_string());
+        repo.update_user(&user).await.unwrap();
+        assert_eq!(repo.get_policy_version().await.unwrap(), start);
+
+        repo.create_user_group(&UserGroup::new("ops".to_string(), user.id))
+            .await
+            .unwrap();
+        let after_group = repo.get_policy_version().await.unwrap();
+        assert!(after_group > start);
+
+        let rule = repo.list_casbin_rules().await.unwrap()[0].clone();
+        assert!(repo.delete_casbin_rule(&rule.id).await.unwrap());
+        assert!(repo.get_policy_version().await.unwrap() > after_group);
+    }
+
+    #[tokio::test]
+    async fn test_policy_version() {
+        let service = create_test_service().await;
+        assert_policy_version(&service).await;
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        load_mock_data(&service).await;
+        assert_policy_version(&service).await;
+    }
+
+    async fn assert_maintenance_windows(service: &DatabaseService) {
+        use crate::database::models::MaintenanceWindow;
+
+        let repo = service.repository();
+        let target = repo.list_targets(false, &[]).await.unwrap()[0].clone();
+        let start = Timestamp::now();
+        let end = start.checked_add(Duration::from_secs(3600)).unwrap();
+        let window = MaintenanceWindow::new(
+            Some(target.id),
+            None,
+            start,
+            end,
+            String::new(),
+            Uuid::nil(),
+        );
+        repo.create_maintenance_window(&window).await.unwrap();
+        let invalid = MaintenanceWindow::new(None, None, start, end, String::new(), Uuid::nil());
+        assert!(repo.create_maintenance_window(&invalid).await.is_err());
+        assert_eq!(repo.list_maintenance_windows().await.unwrap().len(), 1);
+
+        // Goes with its target
+        assert!(repo.delete_target(&target.id, true).await.unwrap());
+        assert!(repo.list_maintenance_windows().await.unwrap().is_empty());
+        assert!(!repo.delete_maintenance_window(&window.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_windows() {
+        let service = create_test_service().await;
+        assert_maintenance_windows(&service).await;
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        load_mock_data(&service).await;
+        assert_maintenance_windows(&service).await;
+    }
+
+    async fn assert_user_groups(service: &DatabaseService) {
+        use crate::database::models::{UserGroup, UserGroupMember};
+
+        let repo = service.repository();
+        let user = repo.list_users(false).await.unwrap()[0].clone();
+        let group = UserGroup::new("ops".to_string(), user.id);
+        repo.create_user_group(&group).await.unwrap();
+        let member = UserGroupMember::new(group.id, user.id, user.id);
+        repo.add_user_group_member(&member).await.unwrap();
+        let err = repo
+            .add_user_group_member(&UserGroupMember::new(group.id, user.id, user.id))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            crate::error::Error::Sqlx(sqlx::Error::Database(ref e))
+                if e.kind() == sqlx::error::ErrorKind::UniqueViolation
+        ));
+
+        let users = repo.list_users_in_group(&group.id).await.unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].id, user.id);
+        let rules = repo.list_user_group_rule_groups().await.unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].v0, group.id);
+        assert_eq!(rules[0].v0_group_label.as_deref(), Some("ops"));
+        assert_eq!(rules[0].v1, user.id);
+        assert_eq!(rules[0].v1_object_label, Some(user.username.clone()));
+
+        let mut inactive = group.clone();
+        inactive.is_active = false;
+        repo.update_user_group(&inactive).await.unwrap();
+        assert!(repo.list_user_group_rule_groups().await.unwrap().is_empty());
+
+        assert!(repo
+            .remove_user_group_member(&group.id, &user.id)
+            .await
+            .unwrap());
+        repo.add_user_group_member(&member).await.unwrap();
+        assert!(repo.delete_user_group(&group.id).await.unwrap());
+        assert!(repo.list_user_group_members().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_user_groups() {
+        let service = create_test_service().await;
+        assert_user_groups(&service).await;
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        load_mock_data(&service).await;
+        assert_user_groups(&service).await;
+    }
+
+    async fn assert_nested_roles(service: &DatabaseService) {
+        let repo = service.repository();
+        let policy_ids = |rules: Vec<CasbinRule>| {
+            let mut ids: Vec<String> = rules.iter().map(|r| r.id.to_string()).collect();
+            ids.sort();
+            ids
+        };
+        let alice = repo
+            .get_user_by_username("alice", false)
+            .await
+            .unwrap()
+            .unwrap();
+        // alice is in dev_dpt, which is in dev_sub1, which is in dev_team1
+        let expected = [
+            "33e60a2b-4b1f-4b5c-8893-ff6f42ef929f",
+            "749bed7e-67a5-4749-9371-ec7df959438e",
+            "8a8a5aba-c0fc-4114-9f98-6ed012d3fb41",
+            "bfcb006c-5caf-4f8b-9691-15520bcc2c2d",
+            "d4522684-9a9f-4098-90df-9d0d9b19e276",
+        ];
+        let got = policy_ids(repo.get_policies_for_user(&alice.id).await.unwrap());
+        assert_eq!(got, expected);
+        let nested: Uuid = "8a8a5aba-c0fc-4114-9f98-6ed012d3fb41".parse().unwrap();
+        let targets = repo.list_targets_for_user(&alice.id, false).await.unwrap();
+        assert!(targets.iter().any(|t| t.pid == nested));
+
+        // A cycle back to dev_dpt ends at the depth bound
+        let dev_team1 = "78cc5445-167b-4edb-a8eb-d76fb335ee3a".parse().unwrap();
+        let dev_dpt = "4c042cc5-4af7-489d-b8bd-846e52b5122a".parse().unwrap();
+        let cycle = CasbinRule::new(
+            "g1".to_string(),
+            dev_dpt,
+            dev_team1,
+            Uuid::nil(),
+            String::new(),
+            String::new(),
+            String::new(),
+            alice.id,
+        );
+        repo.create_casbin_rule(&cycle).await.unwrap();
+        let got = policy_ids(repo.get_policies_for_user(&alice.id).await.unwrap());
+        assert_eq!(got, expected);
+    }
+
+    #[tokio::test]
+    async fn test_nested_roles() {
+        let service = create_test_service().await;
+        assert_nested_roles(&service).await;
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        load_mock_data(&service).await;
+        assert_nested_roles(&service).await;
+    }
+
+    async fn assert_ssh_sessions(service: &DatabaseService) {
+        let repo = service.repository();
+        let user = repo.list_users(true).await.unwrap()[0].clone();
+        let ts = repo.list_target_secrets(true).await.unwrap()[0].clone();
+        let live = SshSession::new(uuid::Uuid::new_v4(), user.id, ts.id, "shell".to_string())
+            .with_ticket(Some("CHG-1234".to_string()))
+            .with_login_as(Some("labuser".to_string()));
+        let mut done = SshSession::new(
+            uuid::Uuid::new_v4(),
+            user.id,
+            ts.id,
+            "exec: uptime".to_string(),
+        );
+        repo.create_ssh_session(&live).await.unwrap();
+        repo.create_ssh_session(&done).await.unwrap();
+        assert!(repo.create_ssh_session(&live).await.is_err());
+
+        done.ended_at = Some(Timestamp::now());
+        done.bytes_in = 7;
+        done.bytes_out = 42;
+        done.termination_reason = Some("exit status 0".to_string());
+        repo.update_ssh_session(&done).await.unwrap();
+        let got = repo.get_ssh_session_by_id(&done.id).await.unwrap().unwrap();
+        assert!(!got.is_active());
+        assert_eq!((got.bytes_in, got.bytes_out), (7, 42));
+        assert_eq!(got.termination_reason.as_deref(), Some("exit status 0"));
+
+        let active = repo.list_active_ssh_sessions().await.unwrap();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].id, live.id);
+        assert_eq!(active[0].username, user.username);
+        assert!(active[0].target_secret.starts_with("labuser@"));
+        assert_eq!(active[0].ticket.as_deref(), Some("CHG-1234"));
+        assert_eq!(repo.list_ssh_sessions(Some(1)).await.unwrap().len(), 1);
+
+        assert_eq!(
+            repo.end_active_ssh_sessions("server restart")
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(repo.list_active_ssh_sessions().await.unwrap().is_empty());
+        let got = repo.get_ssh_session_by_id(&live.id).await.unwrap().unwrap();
+        assert_eq!(got.termination_reason.as_deref(), Some("server restart"));
+        assert_eq!(got.login_as.as_deref(), Some("labuser"));
+
+        let (since, ids) = (live.started_at, [&ts.id]);
+        let counted = repo.count_ssh_sessions(&user.id, &ids, since).await;
+        assert_eq!(counted.unwrap(), 2);
+        let later = since.checked_add(Duration::from_secs(60)).unwrap();
+        let counted = repo.count_ssh_sessions(&user.id, &ids, later).await;
+        assert_eq!(counted.unwrap(), 0);
+        let other = uuid::Uuid::new_v4();
+        let counted = repo.count_ssh_sessions(&user.id, &[&other], since).await;
+        assert_eq!(counted.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_ssh_sessions() {
+        let service = create_test_service().await;
+        assert_ssh_sessions(&service).await;
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        load_mock_data(&service).await;
+        assert_ssh_sessions(&service).await;
+    }
+
+    async fn assert_api_tokens(service: &DatabaseService) {
+        let repo = service.repository();
+        let user = repo.list_users(true).await.unwrap()[0].clone();
+        let (token, plain) = ApiToken::generate(user.id, "ci".to_string(), user.id);
+        let token = token.with_scopes(vec!["read".to_string()]);
+        repo.create_api_token(&token).await.unwrap();
+        let (dup, _) = ApiToken::generate(user.id, "ci".to_string(), user.id);
+        assert!(repo.create_api_token(&dup).await.is_err());
+
+        let (got, owner) = service
+            .authenticate_api_token(&plain, "read")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(got.id, token.id);
+        assert_eq!(owner.id, user.id);
+        let touched = repo.get_api_token_by_id(&token.id).await.unwrap().unwrap();
+        assert!(touched.last_used_at.is_some());
+        assert!(service
+            .authenticate_api_token(&plain, "write")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(service
+            .authenticate_api_token("rtn_unknown", "read")
+            .await
+            .unwrap()
+            .is_none());
+
+        let (expired, expired_plain) = ApiToken::generate(user.id, "old".to_string(), user.id);
+        let expired = expired
+            .with_scopes(vec!["*".to_string()])
+            .with_expires_at(Timestamp::from_millis(1));
+        repo.create_api_token(&expired).await.unwrap();
+        assert!(service
+            .authenticate_api_token(&expired_plain, "read")
+            .await
+            .unwrap()
+            .is_none());
+
+        let mut revoked = touched;
+        revoked.is_active = false;
+        repo.update_api_token(&revoked).await.unwrap();
+        assert!(service
+            .authenticate_api_token(&plain, "read")
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(repo.list_api_tokens(Some(&user.id)).await.unwrap().len(), 2);
+
+        assert!(repo.delete_user(&user.id).await.unwrap());
+        assert!(repo.list_api_tokens(None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_api_tokens() {
+        let service = create_test_service().await;
+        assert_api_tokens(&service).await;
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        load_mock_data(&service).await;
+        assert_api_tokens(&service).await;
+    }
+
+    async fn assert_secret_versions(service: &DatabaseService) {
+        let repo = service.repository();
+        let secret = repo.list_secrets(false).await.unwrap()[0].clone();
+        let original_version = secret.version;
+        let original_password = secret.password.clone();
+
+        let mut renamed = secret.clone();
+        renamed.name = format!("{}-renamed", secret.name);
+        let renamed = repo.update_secret(&renamed).await.unwrap();
+        assert!(repo
+            .list_secret_versions(&secret.id)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let mut rotated = renamed.clone();
+        rotated.set_password(Some("rotated".to_string()));
+        let rotated = repo.update_secret(&rotated).await.unwrap();
+        let versions = repo.list_secret_versions(&secret.id).await.unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].version, original_version + 1);
+        assert_eq!(versions[0].password, original_password);
+
+        let restored = repo
+            .rollback_secret(&secret.id, versions[0].version, &rotated.updated_by)
+            .await
+            .unwrap();
+        assert_eq!(restored.password, original_password);
+        assert_eq!(restored.name, rotated.name);
+        assert_eq!(restored.version, rotated.version + 1);
+        let versions = repo.list_secret_versions(&secret.id).await.unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].password.as_deref(), Some("rotated"));
+
+        let err = repo
+            .rollback_secret(&secret.id, 999, &rotated.updated_by)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Database(DatabaseError::NotFound {
+                table: "secret_versions",
+                ..
+            })
+        ));
+
+        assert!(repo.delete_secret(&secret.id, true).await.unwrap());
+        assert!(repo
+            .list_secret_versions(&secret.id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_secret_versions() {
+        let service = create_test_service().await;
+        assert_secret_versions(&service).await;
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        load_mock_data(&service).await;
+        assert_secret_versions(&service).await;
+    }
+
+    async fn assert_unnamed_rule_ids(service: &DatabaseService) {
+        let repo = service.repository();
+        assert!(service.unnamed_rule_ids().await.unwrap().is_empty());
+
+        let user = repo.list_users(true).await.unwrap()[0].clone();
+        let ts = repo.list_target_secrets(true).await.unwrap()[0].clone();
+        let actions = Uuid::new_v4();
+        let rule = CasbinRule::new(
+            "p".to_string(),
+            user.id,
+            ts.id,
+            actions,
+            String::new(),
+            String::new(),
+            String::new(),
+            user.id,
+        );
+        repo.create_casbin_rule(&rule).await.unwrap();
+
+        let unnamed = service.unnamed_rule_ids().await.unwrap();
+        assert_eq!(unnamed, vec![(actions, "g3", user.id)]);
+
+        let admin = Uuid::new_v4();
+        assert_eq!(
+            service.name_unnamed_rule_ids(Some(&admin)).await.unwrap(),
+            1
+        );
+        let name = repo.get_casbin_name_by_id(&actions).await.unwrap().unwrap();
+        assert_eq!(name.ptype, "g3");
+        assert!(name.name.starts_with("unnamed-actions-"));
+        assert_eq!(name.updated_by, admin);
+        assert!(name.validate().is_ok());
+
+        assert!(service.unnamed_rule_ids().await.unwrap().is_empty());
+        assert_eq!(service.name_unnamed_rule_ids(None).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_unnamed_rule_ids() {
+        let service = create_test_service().await;
+        assert_unnamed_rule_ids(&service).await;
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        load_mock_data(&service).await;
+        assert_unnamed_rule_ids(&service).await;
+    }
+
+    async fn assert_banned_ips(service: &DatabaseService) {
+        let repo = service.repository();
+        assert!(repo.list_banned_ips().await.unwrap().is_empty());
+
+        let now = Timestamp::now();
+        let auto = BannedIp::new(
+            "192.0.2.1".parse().unwrap(),
+            "5 failed logins".into(),
+            Uuid::nil(),
+        )
+        .with_expires_at(Some(now));
+        let manual = BannedIp::new(
+            "198.51.100.0/24".parse().unwrap(),
+            "scanner".into(),
+            Uuid::new_v4(),
+        );
+        repo.create_banned_ip(&auto).await.unwrap();
+        repo.create_banned_ip(&manual).await.unwrap();
+
+        let bans = repo.list_banned_ips().await.unwrap();
+        assert_eq!(bans.len(), 2);
+        let stored = bans.iter().find(|b| b.id == manual.id).unwrap();
+        assert_eq!(stored.network, "198.51.100.0/24");
+        assert_eq!(stored.expires_at, None);
+
+        assert_eq!(repo.delete_expired_banned_ips(now).await.unwrap(), 1);
+        assert!(repo.delete_banned_ip(&manual.id).await.unwrap());
+        assert!(!repo.delete_banned_ip(&manual.id).await.unwrap());
+        assert!(repo.list_banned_ips().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_banned_ips() {
+        let service = create_test_service().await;
+        assert_banned_ips(&service).await;
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        assert_banned_ips(&service).await;
+    }
+
+    #[tokio::test]
+    async fn test_export_import() {
+        let service = create_test_service().await;
+        let data = service.export_all().await.unwrap();
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let memory = DatabaseService::new(&config).await.unwrap();
+        memory.import_all(&data).await.unwrap();
+        assert_mock_data_counts(&memory).await;
+
+        let yaml = serde_norway::to_string(&memory.export_all().await.unwrap()).unwrap();
+        let reloaded: Dataset = serde_norway::from_str(&yaml).unwrap();
+        assert_eq!(reloaded.casbin_rule.len(), data.casbin_rule.len());
+        assert_eq!(reloaded.secrets.len(), data.secrets.len());
+
+        let err = memory.import_all(&data).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Database(DatabaseError::NotEmpty("users"))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_import_tag_groups() {
+        let mut data = create_test_service().await.export_all().await.unwrap();
+        let admin = data
+            .users
+            .iter()
+            .find(|u| u.username == "admin")
+            .unwrap()
+            .id;
+        let tag = TargetTag::new(data.targets[0].id, "env=prod".parse().unwrap(), admin);
+        data.target_tags.push(tag);
+
+        // SQLite checks the author of the groups the import syncs
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("import.db");
+        let _ = File::create(&db_path).unwrap();
+        let config = DatabaseConfig::Sqlite {
+            path: db_path.to_string_lossy().to_string(),
+        };
+        let sqlite = DatabaseService::new(&config).await.unwrap();
+        sqlite.import_all(&data).await.unwrap();
+        let group = sqlite
+            .repository()
+            .get_casbin_name_by_name("tag:env=prod")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(group.updated_by, admin);
+        assert_eq!(sqlite.internal_author().await.unwrap(), admin);
+    }
+
+    async fn assert_log_chain(service: &DatabaseService) {
+        let connection_id = Uuid::new_v4();
+        for detail in ["ls", "id", "uptime"] {
+            let log = Log {
+                connection_id,
+                log_type: "exec".to_string(),
+                user_id: Uuid::nil(),
+                detail: detail.to_string(),
+                created_at: Timestamp::now(),
+                prev_hash: String::new(),
+                hash: String::new(),
+            };
+            service.repository.insert_log(&log).await.unwrap();
+        }
+        let logs = service.repository.list_logs().await.unwrap();
+        let (count, head) = service.verify_logs().await.unwrap();
+        assert_eq!(count, 3);
+        let newest = logs.iter().find(|l| l.detail == "uptime").unwrap();
+        assert_eq!(head, newest.hash);
+    }
+
+    #[tokio::test]
+    async fn test_log_chain() {
+        let service = create_test_service().await;
+        assert_log_chain(&service).await;
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        assert_log_chain(&service).await;
+    }
+
+    async fn rules_of(repo: &dyn DatabaseRepository, request: &AccessRequest) -> Vec<CasbinRule> {
+        let ids = request.rule_ids();
+        repo.list_casbin_rules_by_ptype("p")
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|r| ids.contains(&r.id))
+            .collect()
+    }
+
+    async fn assert_access_requests(service: &DatabaseService) {
+        let repo = service.repository();
+        let user = repo.list_users(true).await.unwrap()[0].clone();
+        let ts = repo.list_target_secrets(true).await.unwrap()[0].clone();
+        let actions = [Uuid::new_v4(), Uuid::new_v4()];
+        let hour = Duration::from_secs(3600);
+
+        let request = service
+            .request_access(&user.id, &ts.id, &actions, hour, "fix".into())
+            .await
+            .unwrap();
+        let rules = rules_of(repo, &request).await;
+        assert_eq!(rules.len(), 2);
+        assert!(rules.iter().all(|r| r.v3.ends_with(",pending")));
+        let err = service
+            .request_access(&user.id, &ts.id, &actions, hour, "again".into())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Database(DatabaseError::AccessRequestPending)
+        ));
+
+        let views = repo.list_access_request_views().await.unwrap();
+        assert_eq!(views[0].id, request.id);
+        assert_eq!(views[0].username, user.username);
+
+        let admin = Uuid::new_v4();
+        let approved = service
+            .approve_access_request(&request.id, &admin)
+            .await
+            .unwrap();
+        assert_eq!(approved.status, STATUS_APPROVED);
+        let rules = rules_of(repo, &request).await;
+        assert!(rules.iter().all(|r| !r.v3.contains("pending")));
+        assert!(rules.iter().all(|r| r.updated_by == admin));
+        assert!(matches!(
+            service.deny_access_request(&request.id, &admin).await,
+            Err(Error::Database(DatabaseError::AccessRequestDecided { .. }))
+        ));
+
+        let request = service
+            .request_access(&user.id, &ts.id, &actions, hour, "more".into())
+            .await
+            .unwrap();
+        service
+            .deny_access_request(&request.id, &admin)
+            .await
+            .unwrap();
+        assert!(rules_of(repo, &request).await.is_empty());
+        let stored = repo
+            .get_access_request_by_id(&request.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.status, STATUS_DENIED);
+        assert_eq!(stored.decided_by, Some(admin));
+
+        // The request expires with the last of its rules
+        let rule_ids = approved.rule_ids();
+        let expired = service
+            .delete_expired_casbin_rules(&rule_ids[..1])
+            .await
+            .unwrap();
+        assert!(expired.is_empty());
+        let expired = service
+            .delete_expired_casbin_rules(&rule_ids[1..])
+            .await
+            .unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, approved.id);
+        assert_eq!(expired[0].status, STATUS_EXPIRED);
+        assert!(rules_of(repo, &approved).await.is_empty());
+        let changes = repo.list_policy_changes().await.unwrap();
+        let removed = changes
+            .iter()
+            .filter(|c| rule_ids.contains(&c.rule_id) && c.action == CHANGE_DELETED);
+        assert!(removed.map(|c| c.changed_by).eq([Uuid::nil(); 2]));
+    }
+
+    async fn assert_policy_changes(service: &DatabaseService) {
+        let repo = service.repository();
+        let admin = repo.list_users(true).await.unwrap()[0].clone();
+        let ts = repo.list_target_secrets(true).await.unwrap()[0].clone();
+        let labels = service.rule_labels().await.unwrap();
+        let binding = labels.label(&ts.id);
+
+        let mut rule = CasbinRule::new(
+            "p".to_string(),
+            admin.id,
+            ts.id,
+            Uuid::new_v4(),
+            String::new(),
+            String::new(),
+            String::new(),
+            admin.id,
+        );
+        service.create_casbin_rule(&rule).await.unwrap();
+        rule.v3 = "10.0.0.0/8,,,".to_string();
+        service.update_casbin_rule(&rule).await.unwrap();
+        // Saving an unchanged rule leaves no entry
+        service.update_casbin_rule(&rule).await.unwrap();
+        let other = Uuid::new_v4();
+        assert!(service.delete_casbin_rule(&rule.id, &other).await.unwrap());
+        assert!(!service.delete_casbin_rule(&rule.id, &other).await.unwrap());
+
+        let changes: Vec<PolicyChange> = repo
+            .list_policy_changes()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|c| c.rule_id == rule.id)
+            .collect();
+        let actions: Vec<&str> = changes.iter().map(|c| c.action.as_str()).collect();
+        assert_eq!(actions, [CHANGE_DELETED, CHANGE_UPDATED, CHANGE_CREATED]);
+        let created = format!("+ p {}, {}", admin.username, binding);
+        assert!(changes[2].diff.starts_with(&created));
+        assert_eq!(changes[1].diff, "ext: '' -> '10.0.0.0/8,,,'");
+        assert_eq!(changes[1].before_rule().unwrap().v3, "");
+        assert_eq!(changes[1].after_rule().unwrap().v3, rule.v3);
+        assert!(changes[0].after.is_none());
+        assert_eq!(changes[0].changed_by, other);
+
+        let views = repo.list_policy_change_views().await.unwrap();
+        let view = views.iter().find(|v| v.id == changes[1].id).unwrap();
+        assert_eq!(view.changed_by, admin.username);
+        assert_eq!(view.diff, changes[1].diff);
+        let view = views.iter().find(|v| v.id == changes[0].id).unwrap();
+        assert_eq!(view.changed_by, "");
+
+        // Access requests record the rules they write and drop
+        let request = service
+            .request_access(
+                &admin.id,
+                &ts.id,
+                &[Uuid::new_v4()],
+                Duration::from_secs(60),
+                "x".into(),
+            )
+            .await
+            .unwrap();
+        service
+            .deny_access_request(&request.id, &other)
+            .await
+            .unwrap();
+        let rule_id = request.rule_ids()[0];
+        let changes: Vec<PolicyChange> = repo
+            .list_policy_changes()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|c| c.rule_id == rule_id)
+            .collect();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[1].action, CHANGE_CREATED);
+        assert_eq!(changes[1].changed_by, admin.id);
+        assert_eq!(changes[0].action, CHANGE_DELETED);
+        assert_eq!(changes[0].changed_by, other);
+    }
+
+    #[tokio::test]
+    async fn test_policy_changes() {
+        let service = create_test_service().await;
+        assert_policy_changes(&service).await;
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        load_mock_data(&service).await;
+        assert_policy_changes(&service).await;
+    }
+
+    #[tokio::test]
+    async fn test_access_requests() {
+        let service = create_test_service().await;
+        assert_access_requests(&service).await;
+
+        let config = DatabaseConfig::Memory {
+            name: uuid::Uuid::new_v4().to_string(),
+        };
+        let service = DatabaseService::new(&config).await.unwrap();
+        load_mock_data(&service).await;
+        assert_access_requests(&service).await;
+    }
 }
@@ -1,21 +1,135 @@
 use async_trait::async_trait;
-use chrono::Utc;
 use log::{debug, info};
 use sqlx::{Pool, Row, Sqlite, sqlite::{SqlitePool, SqliteConnectOptions}};
 use uuid::Uuid;
 
 use crate::database::DatabaseRepository;
+use crate::database::common::{MAX_ROLE_DEPTH, TAG_GROUP_PREFIX};
 use crate::database::error::DatabaseError;
 use crate::database::models::casbin_rule::ValidateError;
+use crate::database::models::log::GENESIS_HASH;
 use crate::database::models::{
-    CasbinName, CasbinRule, CasbinRuleGroup, Log, ObjectGroup, PermissionPolicy, RecordingView,
-    Role, Secret, SecretInfo, SessionRecording, Target, TargetInfo, TargetSecret, TargetSecretName,
-    User, UserWithRole,
+    AccessRequest, AccessRequestView, ApiToken, BannedIp, CasbinName, CasbinRule, CasbinRuleGroup,
+    Log, MaintenanceWindow, ObjectGroup, PermissionPolicy, PolicyChange, PolicyChangeView,
+    RecordingView, Role, Secret, SecretInfo, SecretVersion, SessionRecording, SshSession,
+    SshSessionView, TagFilter, Target, TargetInfo, TargetSecret, TargetSecretName, TargetTag,
+    Timestamp, TraceRule, User, UserGroup, UserGroupMember, UserWithRole,
 };
 use crate::error::Error;
 
+/// Prepared statements kept per connection. Every query below has fixed SQL
+/// text, so the cache holds all of them and `enforce` never re-prepares.
+const STATEMENT_CACHE_CAPACITY: usize = 256;
+
+/// `user_roles(id, depth)`: the user bound to the first two parameters,
+/// their active user groups and every role above them in `g1` (role in v0,
+/// member in v1), up to the depth bound to the third parameter
+macro_rules! user_roles_cte {
+    () => {
+        r#"user_roles(id, depth) AS (
+                SELECT ?, 0
+                UNION SELECT m.group_id, 1 FROM user_group_members m
+                INNER JOIN user_groups g ON g.id = m.group_id
+                WHERE m.user_id = ? AND g.is_active = 1
+                UNION SELECT c.v0, r.depth + 1 FROM casbin_rule c
+                INNER JOIN user_roles r ON c.v1 = r.id
+                WHERE c.ptype = 'g1' AND r.depth < ?
+            )"#
+    };
+}
+
 pub struct SqliteRepository {
     pool: Pool<Sqlite>,
+    /// Reading the head of the log chain and appending after it must not
+    /// interleave
+    log_chain: tokio::sync::Mutex<()>,
+}
+
+/// Encode ids as a JSON array of hex strings for `json_each`, so `IN` lists
+/// bind a single parameter instead of producing a new statement per length.
+fn uuid_list(ids: &[&Uuid]) -> String {
+    let hex = ids
+        .iter()
+        .map(|id| format!("\"{}\"", id.simple()))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("[{hex}]")
+}
+
+/// Writes `secret` over the row at `secret.version`. Credentials it replaces
+/// are kept in `secret_versions`.
+async fn update_secret_tx(
+    conn: &mut sqlx::SqliteConnection,
+    secret: &Secret,
+) -> Result<Secret, Error> {
+    let mut updated_secret = secret.clone();
+    updated_secret.updated_at = Timestamp::now();
+
+    let current = sqlx::query_as::<_, Secret>(
+        r#"SELECT id, name, user, password, private_key, public_key, kind, is_active, updated_by,
+        updated_at, version FROM secrets WHERE id = ? AND version = ?"#,
+    )
+    .bind(secret.id)
+    .bind(secret.version)
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(current) = current else {
+        return Err(Error::Database(DatabaseError::StaleRow {
+            table: "secrets",
+            id: secret.id,
+        }));
+    };
+
+    if !current.same_credentials(&updated_secret) {
+        let v = SecretVersion::of(&current);
+        sqlx::query(
+            r#"
+            INSERT INTO secret_versions
+            (secret_id, version, user, password, private_key, public_key, updated_by, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(v.secret_id)
+        .bind(v.version)
+        .bind(&v.user)
+        .bind(&v.password)
+        .bind(&v.private_key)
+        .bind(&v.public_key)
+        .bind(v.updated_by)
+        .bind(v.updated_at)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    let result = sqlx::query(
+        r#"
+        UPDATE secrets
+        SET name = ?, user = ?, password = ?, private_key = ?, public_key = ?, kind = ?,
+        is_active = ?, updated_by = ?, updated_at = ?, version = version + 1
+        WHERE id = ? AND version = ?
+        "#,
+    )
+    .bind(&updated_secret.name)
+    .bind(&updated_secret.user)
+    .bind(&updated_secret.password)
+    .bind(&updated_secret.private_key)
+    .bind(&updated_secret.public_key)
+    .bind(&updated_secret.kind)
+    .bind(updated_secret.is_active)
+    .bind(updated_secret.updated_by)
+    .bind(updated_secret.updated_at)
+    .bind(updated_secret.id)
+    .bind(updated_secret.version)
+    .execute(&mut *conn)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(Error::Database(DatabaseError::StaleRow {
+            table: "secrets",
+            id: updated_secret.id,
+        }));
+    }
+    updated_secret.version += 1;
+    Ok(updated_secret)
 }
 
 impl SqliteRepository {
@@ -24,11 +138,16 @@ impl SqliteRepository {
 
         let options = SqliteConnectOptions::new()
             .filename(database_path)
-            .create_if_missing(true);
+            .create_if_missing(true)
+            .foreign_keys(true)
+            .statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
 
         let pool = SqlitePool::connect_with(options).await?;
 
-        let repo = Self { pool };
+        let repo = Self {
+            pool,
+            log_chain: tokio::sync::Mutex::new(()),
+        };
         repo.initialize().await?;
 
         Ok(repo)
@@ -44,10 +163,15 @@ impl SqliteRepository {
                 email TEXT,
                 password_hash TEXT,
                 authorized_keys TEXT,  -- Stores JSON array
+                totp_secret TEXT,  -- Encrypted base32 secret
+                password_changed_at INTEGER,
+                password_history TEXT,  -- JSON array of earlier password hashes
+                allowed_source_cidrs TEXT,  -- JSON array, logins from elsewhere are refused
                 force_init_pass BOOLEAN NOT NULL CHECK (force_init_pass IN (0, 1)),
                 is_active BOOLEAN NOT NULL CHECK (is_active IN (0, 1)),
                 updated_by BLOB NOT NULL,
                 updated_at INTEGER NOT NULL,
+                version INTEGER NOT NULL DEFAULT 0,
                 CHECK (json_valid(authorized_keys) OR authorized_keys IS NULL)
             )
             "#,
@@ -64,10 +188,15 @@ impl SqliteRepository {
                 hostname TEXT NOT NULL,
                 port INTEGER NOT NULL,
                 server_public_key TEXT NOT NULL,
+                host_key_mode TEXT NOT NULL DEFAULT 'strict',
+                pending_public_key TEXT,
+                via_target_id BLOB,
+                timeout_secs INTEGER,
                 description TEXT,
                 is_active BOOLEAN NOT NULL CHECK (is_active IN (0, 1)),
                 updated_by BLOB NOT NULL,
                 updated_at INTEGER NOT NULL,
+                version INTEGER NOT NULL DEFAULT 0,
                 FOREIGN KEY (updated_by) REFERENCES users (id)
             )
             "#,
@@ -85,9 +214,11 @@ impl SqliteRepository {
                 password TEXT,
                 private_key TEXT,
                 public_key TEXT,
+                kind TEXT NOT NULL DEFAULT 'credentials',
                 is_active BOOLEAN NOT NULL CHECK (is_active IN (0, 1)),
                 updated_by BLOB NOT NULL,
                 updated_at INTEGER NOT NULL,
+                version INTEGER NOT NULL DEFAULT 0,
                 FOREIGN KEY (updated_by) REFERENCES users (id)
             )
             "#,
@@ -95,6 +226,26 @@ impl SqliteRepository {
         .execute(&self.pool)
         .await?;
 
+        // Create secret_versions table, credentials replaced by updates of a secret
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS secret_versions (
+                secret_id BLOB NOT NULL,
+                version INTEGER NOT NULL,
+                user TEXT NOT NULL,
+                password TEXT,
+                private_key TEXT,
+                public_key TEXT,
+                updated_by BLOB NOT NULL,
+                updated_at INTEGER NOT NULL,
+                PRIMARY KEY (secret_id, version),
+                FOREIGN KEY (secret_id) REFERENCES secrets (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
         // Create target_secrets table
         sqlx::query(
             r#"
@@ -106,8 +257,8 @@ impl SqliteRepository {
                 updated_by BLOB NOT NULL,
                 updated_at INTEGER NOT NULL,
                 FOREIGN KEY (updated_by) REFERENCES users (id)
-                FOREIGN KEY (secret_id) REFERENCES secrets (id)
-                FOREIGN KEY (target_id) REFERENCES targets (id)
+                FOREIGN KEY (secret_id) REFERENCES secrets (id) ON DELETE CASCADE
+                FOREIGN KEY (target_id) REFERENCES targets (id) ON DELETE CASCADE
                 UNIQUE(target_id, secret_id)
             )
             "#,
@@ -115,6 +266,153 @@ impl SqliteRepository {
         .execute(&self.pool)
         .await?;
 
+        // Create target_tags table
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS target_tags (
+                id BLOB PRIMARY KEY,
+                target_id BLOB NOT NULL,
+                key TEXT NOT NULL,
+                value TEXT NOT NULL,
+                updated_by BLOB NOT NULL,
+                updated_at INTEGER NOT NULL,
+                FOREIGN KEY (target_id) REFERENCES targets (id) ON DELETE CASCADE
+                UNIQUE(target_id, key)
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create user_groups table
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS user_groups (
+                id BLOB PRIMARY KEY,
+                name TEXT UNIQUE NOT NULL,
+                description TEXT,
+                is_active BOOLEAN NOT NULL CHECK (is_active IN (0, 1)),
+                updated_by BLOB NOT NULL,
+                updated_at INTEGER NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create user_group_members table
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS user_group_members (
+                id BLOB PRIMARY KEY,
+                group_id BLOB NOT NULL,
+                user_id BLOB NOT NULL,
+                updated_by BLOB NOT NULL,
+                updated_at INTEGER NOT NULL,
+                FOREIGN KEY (group_id) REFERENCES user_groups (id) ON DELETE CASCADE
+                FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+                UNIQUE(group_id, user_id)
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create api_tokens table, only the SHA-256 of a token is stored
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS api_tokens (
+                id BLOB PRIMARY KEY,
+                user_id BLOB NOT NULL,
+                name TEXT NOT NULL,
+                token_hash TEXT NOT NULL UNIQUE,
+                scopes TEXT NOT NULL,
+                expires_at INTEGER,
+                last_used_at INTEGER,
+                is_active BOOLEAN NOT NULL DEFAULT TRUE,
+                updated_by BLOB NOT NULL,
+                updated_at INTEGER NOT NULL,
+                FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+                UNIQUE(user_id, name)
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create trace_rules table, read by the server at each login
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS trace_rules (
+                id BLOB PRIMARY KEY,
+                username TEXT,
+                client_ip TEXT,
+                expires_at INTEGER NOT NULL,
+                updated_by BLOB NOT NULL,
+                updated_at INTEGER NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create access_requests table, filled from the target selector
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS access_requests (
+                id BLOB PRIMARY KEY,
+                user_id BLOB NOT NULL,
+                target_secret_id BLOB NOT NULL,
+                rule_ids TEXT NOT NULL,
+                duration INTEGER NOT NULL,
+                reason TEXT NOT NULL,
+                status TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                decided_by BLOB,
+                decided_at INTEGER,
+                FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create banned_ips table, loaded by the server to drop connections
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS banned_ips (
+                id BLOB PRIMARY KEY,
+                network TEXT NOT NULL,
+                reason TEXT NOT NULL,
+                expires_at INTEGER,
+                updated_by BLOB NOT NULL,
+                updated_at INTEGER NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create maintenance_windows table, a window covers a target or a tag
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS maintenance_windows (
+                id BLOB PRIMARY KEY,
+                target_id BLOB,
+                tag TEXT,
+                starts_at INTEGER NOT NULL,
+                ends_at INTEGER NOT NULL,
+                repeat TEXT NOT NULL DEFAULT 'none',
+                reason TEXT NOT NULL,
+                updated_by BLOB NOT NULL,
+                updated_at INTEGER NOT NULL,
+                FOREIGN KEY (target_id) REFERENCES targets (id) ON DELETE CASCADE
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
         // Create casbin_rule table - v0, v1, v2 are UUIDs stored as BLOB
         sqlx::query(
             r#"
@@ -163,6 +461,8 @@ impl SqliteRepository {
                 user_id BLOB NOT NULL,
                 detail TEXT NOT NULL,
                 created_at INTEGER NOT NULL,
+                prev_hash TEXT NOT NULL DEFAULT '',
+                hash TEXT NOT NULL DEFAULT '',
                 PRIMARY KEY (created_at, connection_id, detail)
             )
             "#,
@@ -170,6 +470,24 @@ impl SqliteRepository {
         .execute(&self.pool)
         .await?;
 
+        // Create policy_changes table, the audit trail of casbin rule edits
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS policy_changes (
+                id BLOB PRIMARY KEY,
+                rule_id BLOB NOT NULL,
+                action TEXT NOT NULL,
+                before TEXT,
+                after TEXT,
+                diff TEXT NOT NULL,
+                changed_by BLOB NOT NULL,
+                changed_at INTEGER NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
         // Create session_recordings table
         sqlx::query(
             r#"
@@ -182,13 +500,242 @@ impl SqliteRepository {
                 started_at INTEGER NOT NULL,
                 ended_at INTEGER,
                 connection_id BLOB NOT NULL,
-                status TEXT NOT NULL
+                status TEXT NOT NULL,
+                ticket TEXT,
+                duration_ms INTEGER,
+                active_ms INTEGER,
+                event_count INTEGER
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Create sessions table
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS sessions (
+                id BLOB PRIMARY KEY,
+                connection_id BLOB NOT NULL,
+                user_id BLOB NOT NULL,
+                target_secret_id BLOB NOT NULL,
+                request TEXT NOT NULL,
+                started_at INTEGER NOT NULL,
+                ended_at INTEGER,
+                bytes_in INTEGER NOT NULL DEFAULT 0,
+                bytes_out INTEGER NOT NULL DEFAULT 0,
+                recording_path TEXT,
+                termination_reason TEXT,
+                ticket TEXT,
+                access_reason TEXT,
+                login_as TEXT
             )
             "#,
         )
         .execute(&self.pool)
         .await?;
 
+        // Databases created before tickets were recorded lack the column
+        let has_ticket: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pragma_table_info('session_recordings') WHERE name = 'ticket'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if has_ticket == 0 {
+            sqlx::query("ALTER TABLE session_recordings ADD COLUMN ticket TEXT")
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Playback stats were added after the recordings table
+        for column in ["duration_ms", "active_ms", "event_count"] {
+            let has_column: i64 = sqlx::query_scalar(
+                "SELECT COUNT(*) FROM pragma_table_info('session_recordings') WHERE name = ?",
+            )
+            .bind(column)
+            .fetch_one(&self.pool)
+            .await?;
+            if has_column == 0 {
+                sqlx::query(&format!(
+                    "ALTER TABLE session_recordings ADD COLUMN {} INTEGER",
+                    column
+                ))
+                .execute(&self.pool)
+                .await?;
+            }
+        }
+
+        // Access reasons were added after the sessions table
+        let has_access_reason: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pragma_table_info('sessions') WHERE name = 'access_reason'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if has_access_reason == 0 {
+            sqlx::query("ALTER TABLE sessions ADD COLUMN access_reason TEXT")
+                .execute(&self.pool)
+                .await?;
+        }
+
+        let has_login_as: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pragma_table_info('sessions') WHERE name = 'login_as'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if has_login_as == 0 {
+            sqlx::query("ALTER TABLE sessions ADD COLUMN login_as TEXT")
+                .execute(&self.pool)
+                .await?;
+        }
+
+        let has_totp_secret: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pragma_table_info('users') WHERE name = 'totp_secret'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if has_totp_secret == 0 {
+            sqlx::query("ALTER TABLE users ADD COLUMN totp_secret TEXT")
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Passwords set before expiry was tracked count from the last update
+        let has_password_changed_at: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pragma_table_info('users') WHERE name = 'password_changed_at'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if has_password_changed_at == 0 {
+            sqlx::query("ALTER TABLE users ADD COLUMN password_changed_at INTEGER")
+                .execute(&self.pool)
+                .await?;
+            sqlx::query("ALTER TABLE users ADD COLUMN password_history TEXT")
+                .execute(&self.pool)
+                .await?;
+            sqlx::query(
+                "UPDATE users SET password_changed_at = updated_at WHERE password_hash IS NOT NULL",
+            )
+            .execute(&self.pool)
+            .await?;
+        }
+
+        let has_allowed_source_cidrs: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pragma_table_info('users') WHERE name = 'allowed_source_cidrs'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if has_allowed_source_cidrs == 0 {
+            sqlx::query("ALTER TABLE users ADD COLUMN allowed_source_cidrs TEXT")
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Logs written before the hash chain are chained in the order they
+        // were inserted
+        let has_log_hash: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pragma_table_info('logs') WHERE name = 'hash'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if has_log_hash == 0 {
+            sqlx::query("ALTER TABLE logs ADD COLUMN prev_hash TEXT NOT NULL DEFAULT ''")
+                .execute(&self.pool)
+                .await?;
+            sqlx::query("ALTER TABLE logs ADD COLUMN hash TEXT NOT NULL DEFAULT ''")
+                .execute(&self.pool)
+                .await?;
+            let rows = sqlx::query(
+                r#"SELECT rowid, connection_id, log_type, user_id, detail, created_at,
+                prev_hash, hash FROM logs ORDER BY rowid"#,
+            )
+            .fetch_all(&self.pool)
+            .await?;
+            let mut tx = self.pool.begin().await?;
+            let mut prev = GENESIS_HASH.to_string();
+            for row in rows {
+                let mut log: Log = sqlx::FromRow::from_row(&row)?;
+                log.chain_after(&prev);
+                sqlx::query("UPDATE logs SET prev_hash = ?, hash = ? WHERE rowid = ?")
+                    .bind(&log.prev_hash)
+                    .bind(&log.hash)
+                    .bind(row.get::<i64, _>("rowid"))
+                    .execute(&mut *tx)
+                    .await?;
+                prev = log.hash;
+            }
+            tx.commit().await?;
+        }
+
+        // Row versions for optimistic locking were added later
+        for table in ["users", "targets", "secrets"] {
+            let has_version: i64 = sqlx::query_scalar(
+                "SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = 'version'",
+            )
+            .bind(table)
+            .fetch_one(&self.pool)
+            .await?;
+            if has_version == 0 {
+                sqlx::query(&format!(
+                    "ALTER TABLE {table} ADD COLUMN version INTEGER NOT NULL DEFAULT 0"
+                ))
+                .execute(&self.pool)
+                .await?;
+            }
+        }
+
+        // Targets verified their host key strictly before modes existed
+        let has_host_key_mode: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pragma_table_info('targets') WHERE name = 'host_key_mode'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if has_host_key_mode == 0 {
+            sqlx::query(
+                "ALTER TABLE targets ADD COLUMN host_key_mode TEXT NOT NULL DEFAULT 'strict'",
+            )
+            .execute(&self.pool)
+            .await?;
+            sqlx::query("ALTER TABLE targets ADD COLUMN pending_public_key TEXT")
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Targets were reached directly before chaining
+        let has_via_target: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pragma_table_info('targets') WHERE name = 'via_target_id'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if has_via_target == 0 {
+            sqlx::query("ALTER TABLE targets ADD COLUMN via_target_id BLOB")
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Targets used the global connect timeouts only
+        let has_timeout: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pragma_table_info('targets') WHERE name = 'timeout_secs'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if has_timeout == 0 {
+            sqlx::query("ALTER TABLE targets ADD COLUMN timeout_secs INTEGER")
+                .execute(&self.pool)
+                .await?;
+        }
+
+        // Secrets signed by the target CA came after stored credentials
+        let has_secret_kind: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pragma_table_info('secrets') WHERE name = 'kind'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if has_secret_kind == 0 {
+            sqlx::query("ALTER TABLE secrets ADD COLUMN kind TEXT NOT NULL DEFAULT 'credentials'")
+                .execute(&self.pool)
+                .await?;
+        }
+
         // Create indexes for better performance
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_users_username ON users (username)")
             .execute(&self.pool)
@@ -198,10 +745,80 @@ impl SqliteRepository {
             .execute(&self.pool)
             .await?;
 
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_target_tags_kv ON target_tags (key, value)")
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_user_group_members_user ON user_group_members (user_id)",
+        )
+        .execute(&self.pool)
+        .await?;
+
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_logs_created_at ON logs (created_at)")
             .execute(&self.pool)
             .await?;
 
+        // Logs are append-only, the hash chain tells when this was bypassed
+        for (name, event) in [("logs_no_update", "UPDATE"), ("logs_no_delete", "DELETE")] {
+            sqlx::query(&format!(
+                "CREATE TRIGGER IF NOT EXISTS {name} BEFORE {event} ON logs \
+                 BEGIN SELECT RAISE(ABORT, 'logs are append-only'); END"
+            ))
+            .execute(&self.pool)
+            .await?;
+        }
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_policy_changes_changed_at ON policy_changes (changed_at)",
+        )
+        .execute(&self.pool)
+        .await?;
+        for (name, event) in [
+            ("policy_changes_no_update", "UPDATE"),
+            ("policy_changes_no_delete", "DELETE"),
+        ] {
+            sqlx::query(&format!(
+                "CREATE TRIGGER IF NOT EXISTS {name} BEFORE {event} ON policy_changes \
+                 BEGIN SELECT RAISE(ABORT, 'policy changes are append-only'); END"
+            ))
+            .execute(&self.pool)
+            .await?;
+        }
+
+        // Bumped on any change the role manager or target lists depend on,
+        // so servers sharing this file notice edits made elsewhere
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS policy_version (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                version INTEGER NOT NULL
+            )",
+        )
+        .execute(&self.pool)
+        .await?;
+        sqlx::query("INSERT OR IGNORE INTO policy_version (id, version) VALUES (1, 0)")
+            .execute(&self.pool)
+            .await?;
+        for table in [
+            "casbin_rule",
+            "casbin_names",
+            "user_groups",
+            "user_group_members",
+            "targets",
+            "target_secrets",
+            "target_tags",
+        ] {
+            for event in ["INSERT", "UPDATE", "DELETE"] {
+                sqlx::query(&format!(
+                    "CREATE TRIGGER IF NOT EXISTS {table}_{}_policy_version AFTER {event} \
+                     ON {table} BEGIN UPDATE policy_version SET version = version + 1; END",
+                    event.to_lowercase()
+                ))
+                .execute(&self.pool)
+                .await?;
+            }
+        }
+
         sqlx::query(
             "CREATE INDEX IF NOT EXISTS idx_session_rec_user ON session_recordings (user_id)",
         )
@@ -221,6 +838,16 @@ impl SqliteRepository {
         .execute(&self.pool)
         .await?;
 
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_sessions_user ON sessions (user_id)")
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_sessions_started ON sessions (started_at)")
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_sessions_ended ON sessions (ended_at)")
+            .execute(&self.pool)
+            .await?;
+
         info!("Database tables and indexes created successfully");
         Ok(())
     }
@@ -238,8 +865,8 @@ impl DatabaseRepository for SqliteRepository {
         debug!("Creating user: '{}({})'", user.username, user.id);
         sqlx::query(
             r#"
-            INSERT INTO users (id, username, email, password_hash, authorized_keys, force_init_pass, is_active, updated_by, updated_at)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            INSERT INTO users (id, username, email, password_hash, authorized_keys, totp_secret, password_changed_at, password_history, allowed_source_cidrs, force_init_pass, is_active, updated_by, updated_at, version)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#,
         )
         .bind(user.id)
@@ -247,10 +874,15 @@ impl DatabaseRepository for SqliteRepository {
         .bind(&user.email)
         .bind(&user.password_hash)
         .bind(&user.authorized_keys)
+        .bind(&user.totp_secret)
+        .bind(user.password_changed_at)
+        .bind(&user.password_history)
+        .bind(&user.allowed_source_cidrs)
         .bind(user.force_init_pass)
         .bind(user.is_active)
         .bind(user.updated_by)
         .bind(user.updated_at)
+        .bind(user.version)
         .execute(&self.pool)
         .await?;
 
@@ -263,8 +895,9 @@ impl DatabaseRepository for SqliteRepository {
 
     async fn get_user_by_id(&self, id: &Uuid) -> Result<Option<User>, Error> {
         let row = sqlx::query_as::<_, User>(
-            r#"SELECT id, username, email, password_hash, authorized_keys, force_init_pass, is_active,
-            updated_by, updated_at
+            r#"SELECT id, username, email, password_hash, authorized_keys, totp_secret, password_changed_at,
+            password_history, allowed_source_cidrs, force_init_pass,
+            is_active, updated_by, updated_at, version
             FROM users WHERE id = ?"#
         )
         .bind(id)
@@ -280,8 +913,8 @@ impl DatabaseRepository for SqliteRepository {
         active_only: bool,
     ) -> Result<Option<User>, Error> {
         let mut query =
-            r#"SELECT id, username, email, password_hash, authorized_keys, force_init_pass,
-        is_active, updated_by, updated_at
+            r#"SELECT id, username, email, password_hash, authorized_keys, totp_secret, password_changed_at, password_history,
+        allowed_source_cidrs, force_init_pass, is_active, updated_by, updated_at, version
             FROM users WHERE username = ?"#
                 .to_string();
         if active_only {
@@ -298,26 +931,40 @@ impl DatabaseRepository for SqliteRepository {
     async fn update_user(&self, user: &User) -> Result<User, Error> {
         debug!("Updating user: '{}({})'", user.username, user.id);
         let mut updated_user = user.clone();
-        updated_user.updated_at = Utc::now().timestamp_millis();
+        updated_user.updated_at = Timestamp::now();
 
-        sqlx::query(
+        let result = sqlx::query(
             r#"
             UPDATE users
-            SET username = ?, email = ?, password_hash = ?, authorized_keys = ?, force_init_pass = ?,
-            is_active = ?, updated_by = ?, updated_at = ? WHERE id = ?
+            SET username = ?, email = ?, password_hash = ?, authorized_keys = ?, totp_secret = ?,
+            password_changed_at = ?, password_history = ?, allowed_source_cidrs = ?,
+            force_init_pass = ?, is_active = ?, updated_by = ?, updated_at = ?, version = version + 1
+            WHERE id = ? AND version = ?
             "#,
         )
         .bind(&updated_user.username)
         .bind(&updated_user.email)
         .bind(&updated_user.password_hash)
         .bind(&updated_user.authorized_keys)
+        .bind(&updated_user.totp_secret)
+        .bind(updated_user.password_changed_at)
+        .bind(&updated_user.password_history)
+        .bind(&updated_user.allowed_source_cidrs)
         .bind(updated_user.force_init_pass)
         .bind(updated_user.is_active)
         .bind(updated_user.updated_by)
         .bind(updated_user.updated_at)
         .bind(updated_user.id)
+        .bind(updated_user.version)
         .execute(&self.pool)
         .await?;
+        if result.rows_affected() == 0 {
+            return Err(Error::Database(DatabaseError::StaleRow {
+                table: "users",
+                id: updated_user.id,
+            }));
+        }
+        updated_user.version += 1;
 
         debug!(
             "User updated successfully: '{}({})'",
@@ -328,10 +975,20 @@ impl DatabaseRepository for SqliteRepository {
 
     async fn delete_user(&self, id: &Uuid) -> Result<bool, Error> {
         debug!("Deleting user: id={}", id);
+        let mut tx = self.pool.begin().await?;
+
+        // Drop the user's policies and role memberships
+        sqlx::query("DELETE FROM casbin_rule WHERE v0 = ? OR v1 = ?")
+            .bind(id)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
         let result = sqlx::query("DELETE FROM users WHERE id = ?")
             .bind(id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
+        tx.commit().await?;
 
         let deleted = result.rows_affected() > 0;
         if deleted {
@@ -348,11 +1005,16 @@ impl DatabaseRepository for SqliteRepository {
     u.email,
     u.password_hash,
     u.authorized_keys,
+    u.totp_secret,
+    u.password_changed_at,
+    u.password_history,
+    u.allowed_source_cidrs,
     u.force_init_pass,
     u.is_active,
     r.role,
     u.updated_by,
-    u.updated_at
+    u.updated_at,
+    u.version
 FROM users u
 LEFT JOIN (
     SELECT
@@ -377,8 +1039,8 @@ LEFT JOIN (
 
     async fn list_users(&self, active_only: bool) -> Result<Vec<User>, Error> {
         let mut query = String::from(
-            r#"SELECT id, username, email, password_hash, authorized_keys,
-                 force_init_pass, is_active, updated_by, updated_at
+            r#"SELECT id, username, email, password_hash, authorized_keys, totp_secret, password_changed_at, password_history,
+                 allowed_source_cidrs, force_init_pass, is_active, updated_by, updated_at, version
           FROM users"#,
         );
 
@@ -393,14 +1055,555 @@ LEFT JOIN (
             .map_err(Error::Sqlx)
     }
 
-    // Target operations
-    async fn create_target(&self, target: &Target) -> Result<Target, Error> {
+    // UserGroup operations
+    async fn create_user_group(&self, group: &UserGroup) -> Result<UserGroup, Error> {
+        debug!("Creating user group: '{}({})'", group.name, group.id);
+        sqlx::query(
+            r#"
+            INSERT INTO user_groups (id, name, description, is_active, updated_by, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(group.id)
+        .bind(&group.name)
+        .bind(&group.description)
+        .bind(group.is_active)
+        .bind(group.updated_by)
+        .bind(group.updated_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(group.clone())
+    }
+
+    async fn update_user_group(&self, group: &UserGroup) -> Result<UserGroup, Error> {
+        debug!("Updating user group: '{}({})'", group.name, group.id);
+        let mut updated_group = group.clone();
+        updated_group.updated_at = Timestamp::now();
+
+        sqlx::query(
+            r#"
+            UPDATE user_groups
+            SET name = ?, description = ?, is_active = ?, updated_by = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(&updated_group.name)
+        .bind(&updated_group.description)
+        .bind(updated_group.is_active)
+        .bind(updated_group.updated_by)
+        .bind(updated_group.updated_at)
+        .bind(updated_group.id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(updated_group)
+    }
+
+    async fn delete_user_group(&self, id: &Uuid) -> Result<bool, Error> {
+        debug!("Deleting user group: id={}", id);
+        let mut tx = self.pool.begin().await?;
+
+        // Policies naming the group as subject, and its place in the role hierarchy
+        sqlx::query("DELETE FROM casbin_rule WHERE v0 = ? OR v1 = ?")
+            .bind(id)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        // Members are removed by ON DELETE CASCADE
+        let result = sqlx::query("DELETE FROM user_groups WHERE id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_user_group_by_id(&self, id: &Uuid) -> Result<Option<UserGroup>, Error> {
+        let row = sqlx::query_as::<_, UserGroup>(
+            r#"SELECT id, name, description, is_active, updated_by, updated_at
+            FROM user_groups WHERE id = ?"#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row)
+    }
+
+    async fn get_user_group_by_name(&self, name: &str) -> Result<Option<UserGroup>, Error> {
+        let row = sqlx::query_as::<_, UserGroup>(
+            r#"SELECT id, name, description, is_active, updated_by, updated_at
+            FROM user_groups WHERE name = ?"#,
+        )
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row)
+    }
+
+    async fn list_user_groups(&self, active_only: bool) -> Result<Vec<UserGroup>, Error> {
+        sqlx::query_as::<_, UserGroup>(
+            r#"SELECT id, name, description, is_active, updated_by, updated_at
+            FROM user_groups WHERE (? = 0 OR is_active = 1) ORDER BY name"#,
+        )
+        .bind(active_only)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
+    async fn add_user_group_member(
+        &self,
+        member: &UserGroupMember,
+    ) -> Result<UserGroupMember, Error> {
+        debug!(
+            "Adding user {} to user group {}",
+            member.user_id, member.group_id
+        );
+        sqlx::query(
+            r#"
+            INSERT INTO user_group_members (id, group_id, user_id, updated_by, updated_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(member.id)
+        .bind(member.group_id)
+        .bind(member.user_id)
+        .bind(member.updated_by)
+        .bind(member.updated_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(member.clone())
+    }
+
+    async fn remove_user_group_member(
+        &self,
+        group_id: &Uuid,
+        user_id: &Uuid,
+    ) -> Result<bool, Error> {
+        debug!("Removing user {} from user group {}", user_id, group_id);
+        let result =
+            sqlx::query("DELETE FROM user_group_members WHERE group_id = ? AND user_id = ?")
+                .bind(group_id)
+                .bind(user_id)
+                .execute(&self.pool)
+                .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_user_group_members(&self) -> Result<Vec<UserGroupMember>, Error> {
+        sqlx::query_as::<_, UserGroupMember>(
+            "SELECT id, group_id, user_id, updated_by, updated_at FROM user_group_members",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
+    async fn list_users_in_group(&self, group_id: &Uuid) -> Result<Vec<User>, Error> {
+        sqlx::query_as::<_, User>(
+            r#"SELECT u.id, u.username, u.email, u.password_hash, u.authorized_keys, u.totp_secret,
+                 u.password_changed_at, u.password_history, u.allowed_source_cidrs,
+                 u.force_init_pass, u.is_active, u.updated_by, u.updated_at, u.version
+          FROM users u INNER JOIN user_group_members m ON m.user_id = u.id
+          WHERE m.group_id = ? ORDER BY u.username"#,
+        )
+        .bind(group_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
+    async fn list_user_group_rule_groups(&self) -> Result<Vec<CasbinRuleGroup>, Error> {
+        sqlx::query_as::<_, CasbinRuleGroup>(
+            r#"SELECT
+    m.id,
+    g.id AS v0,
+    NULL AS v0_object_label,
+    g.name AS v0_group_label,
+    u.id AS v1,
+    u.username AS v1_object_label,
+    NULL AS v1_group_label
+FROM user_group_members AS m
+INNER JOIN user_groups AS g ON m.group_id = g.id
+INNER JOIN users AS u ON m.user_id = u.id
+WHERE g.is_active = 1;"#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
+    // API token operations
+    async fn create_api_token(&self, token: &ApiToken) -> Result<ApiToken, Error> {
+        debug!(
+            "Creating api token '{}' for user {}",
+            token.name, token.user_id
+        );
+        sqlx::query(
+            r#"
+            INSERT INTO api_tokens
+            (id, user_id, name, token_hash, scopes, expires_at, last_used_at, is_active, updated_by, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(token.id)
+        .bind(token.user_id)
+        .bind(&token.name)
+        .bind(&token.token_hash)
+        .bind(&token.scopes)
+        .bind(token.expires_at)
+        .bind(token.last_used_at)
+        .bind(token.is_active)
+        .bind(token.updated_by)
+        .bind(token.updated_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(token.clone())
+    }
+
+    async fn update_api_token(&self, token: &ApiToken) -> Result<ApiToken, Error> {
+        debug!("Updating api token: id={}", token.id);
+        let mut updated_token = token.clone();
+        updated_token.updated_at = Timestamp::now();
+        sqlx::query(
+            r#"
+            UPDATE api_tokens
+            SET name = ?, scopes = ?, expires_at = ?, is_active = ?, updated_by = ?, updated_at = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(&updated_token.name)
+        .bind(&updated_token.scopes)
+        .bind(updated_token.expires_at)
+        .bind(updated_token.is_active)
+        .bind(updated_token.updated_by)
+        .bind(updated_token.updated_at)
+        .bind(updated_token.id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(updated_token)
+    }
+
+    async fn delete_api_token(&self, id: &Uuid) -> Result<bool, Error> {
+        debug!("Deleting api token: id={}", id);
+        let result = sqlx::query("DELETE FROM api_tokens WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_api_token_by_id(&self, id: &Uuid) -> Result<Option<ApiToken>, Error> {
+        let row = sqlx::query_as::<_, ApiToken>(
+            r#"SELECT id, user_id, name, token_hash, scopes, expires_at, last_used_at, is_active,
+            updated_by, updated_at FROM api_tokens WHERE id = ?"#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row)
+    }
+
+    async fn get_api_token_by_hash(&self, token_hash: &str) -> Result<Option<ApiToken>, Error> {
+        let row = sqlx::query_as::<_, ApiToken>(
+            r#"SELECT id, user_id, name, token_hash, scopes, expires_at, last_used_at, is_active,
+            updated_by, updated_at FROM api_tokens WHERE token_hash = ?"#,
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row)
+    }
+
+    async fn list_api_tokens(&self, user_id: Option<&Uuid>) -> Result<Vec<ApiToken>, Error> {
+        sqlx::query_as::<_, ApiToken>(
+            r#"SELECT id, user_id, name, token_hash, scopes, expires_at, last_used_at, is_active,
+            updated_by, updated_at FROM api_tokens
+            WHERE (? IS NULL OR user_id = ?) ORDER BY name"#,
+        )
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
+    async fn touch_api_token(&self, id: &Uuid, used_at: Timestamp) -> Result<(), Error> {
+        sqlx::query("UPDATE api_tokens SET last_used_at = ? WHERE id = ?")
+            .bind(used_at)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    // Trace rule operations
+    async fn create_trace_rule(&self, rule: &TraceRule) -> Result<TraceRule, Error> {
+        debug!(
+            "Creating trace rule {} for user {:?} from {:?}",
+            rule.id, rule.username, rule.client_ip
+        );
+        sqlx::query(
+            r#"
+            INSERT INTO trace_rules
+            (id, username, client_ip, expires_at, updated_by, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(rule.id)
+        .bind(&rule.username)
+        .bind(&rule.client_ip)
+        .bind(rule.expires_at)
+        .bind(rule.updated_by)
+        .bind(rule.updated_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(rule.clone())
+    }
+
+    async fn delete_trace_rule(&self, id: &Uuid) -> Result<bool, Error> {
+        debug!("Deleting trace rule: id={}", id);
+        let result = sqlx::query("DELETE FROM trace_rules WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_trace_rules(&self) -> Result<Vec<TraceRule>, Error> {
+        sqlx::query_as::<_, TraceRule>(
+            r#"SELECT id, username, client_ip, expires_at, updated_by, updated_at
+            FROM trace_rules ORDER BY expires_at"#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
+    // Access request operations
+    async fn create_access_request(&self, request: &AccessRequest) -> Result<AccessRequest, Error> {
+        debug!(
+            "Creating access request {} of user {} for {}",
+            request.id, request.user_id, request.target_secret_id
+        );
+        sqlx::query(
+            r#"
+            INSERT INTO access_requests
+            (id, user_id, target_secret_id, rule_ids, duration, reason, status, created_at,
+            decided_by, decided_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(request.id)
+        .bind(request.user_id)
+        .bind(request.target_secret_id)
+        .bind(&request.rule_ids)
+        .bind(request.duration)
+        .bind(&request.reason)
+        .bind(&request.status)
+        .bind(request.created_at)
+        .bind(request.decided_by)
+        .bind(request.decided_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(request.clone())
+    }
+
+    async fn update_access_request(&self, request: &AccessRequest) -> Result<AccessRequest, Error> {
+        debug!(
+            "Updating access request {}: status={}",
+            request.id, request.status
+        );
+        sqlx::query(
+            "UPDATE access_requests SET status = ?, decided_by = ?, decided_at = ? WHERE id = ?",
+        )
+        .bind(&request.status)
+        .bind(request.decided_by)
+        .bind(request.decided_at)
+        .bind(request.id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(request.clone())
+    }
+
+    async fn get_access_request_by_id(&self, id: &Uuid) -> Result<Option<AccessRequest>, Error> {
+        sqlx::query_as::<_, AccessRequest>(
+            r#"SELECT id, user_id, target_secret_id, rule_ids, duration, reason, status,
+            created_at, decided_by, decided_at
+            FROM access_requests WHERE id = ?"#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
+    async fn list_access_requests(
+        &self,
+        user_id: Option<&Uuid>,
+    ) -> Result<Vec<AccessRequest>, Error> {
+        sqlx::query_as::<_, AccessRequest>(
+            r#"SELECT id, user_id, target_secret_id, rule_ids, duration, reason, status,
+            created_at, decided_by, decided_at
+            FROM access_requests WHERE (? IS NULL OR user_id = ?)
+            ORDER BY created_at DESC"#,
+        )
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
+    async fn list_access_request_views(&self) -> Result<Vec<AccessRequestView>, Error> {
+        sqlx::query_as::<_, AccessRequestView>(
+            r#"SELECT ar.id, COALESCE(u.username, '') AS username,
+            COALESCE(s.user || '@' || t.name || ':' || t.port, '') AS target_secret,
+            ar.duration, ar.reason, ar.status, ar.created_at
+            FROM access_requests ar
+            LEFT JOIN users u ON ar.user_id = u.id
+            LEFT JOIN target_secrets ts ON ar.target_secret_id = ts.id
+            LEFT JOIN targets t ON ts.target_id = t.id
+            LEFT JOIN secrets s ON ts.secret_id = s.id
+            ORDER BY ar.status != 'pending', ar.created_at DESC"#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
+    // Banned IP operations
+    async fn create_banned_ip(&self, ban: &BannedIp) -> Result<BannedIp, Error> {
+        debug!(
+            "Creating ban {} for {}: {}",
+            ban.id, ban.network, ban.reason
+        );
+        sqlx::query(
+            r#"
+            INSERT INTO banned_ips
+            (id, network, reason, expires_at, updated_by, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(ban.id)
+        .bind(&ban.network)
+        .bind(&ban.reason)
+        .bind(ban.expires_at)
+        .bind(ban.updated_by)
+        .bind(ban.updated_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(ban.clone())
+    }
+
+    async fn delete_banned_ip(&self, id: &Uuid) -> Result<bool, Error> {
+        debug!("Deleting ban: id={}", id);
+        let result = sqlx::query("DELETE FROM banned_ips WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_banned_ips(&self) -> Result<Vec<BannedIp>, Error> {
+        sqlx::query_as::<_, BannedIp>(
+            r#"SELECT id, network, reason, expires_at, updated_by, updated_at
+            FROM banned_ips ORDER BY updated_at DESC"#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
+    async fn delete_expired_banned_ips(&self, now: Timestamp) -> Result<u64, Error> {
+        let result =
+            sqlx::query("DELETE FROM banned_ips WHERE expires_at IS NOT NULL AND expires_at <= ?")
+                .bind(now)
+                .execute(&self.pool)
+                .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    // Maintenance window operations
+    async fn create_maintenance_window(
+        &self,
+        window: &MaintenanceWindow,
+    ) -> Result<MaintenanceWindow, Error> {
+        debug!(
+            "Creating maintenance window {} for {}",
+            window.id,
+            window.print_scope()
+        );
+        window.validate().map_err(DatabaseError::from)?;
+        sqlx::query(
+            r#"
+            INSERT INTO maintenance_windows
+            (id, target_id, tag, starts_at, ends_at, repeat, reason, updated_by, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(window.id)
+        .bind(window.target_id)
+        .bind(&window.tag)
+        .bind(window.starts_at)
+        .bind(window.ends_at)
+        .bind(&window.repeat)
+        .bind(&window.reason)
+        .bind(window.updated_by)
+        .bind(window.updated_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(window.clone())
+    }
+
+    async fn delete_maintenance_window(&self, id: &Uuid) -> Result<bool, Error> {
+        debug!("Deleting maintenance window: id={}", id);
+        let result = sqlx::query("DELETE FROM maintenance_windows WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_maintenance_windows(&self) -> Result<Vec<MaintenanceWindow>, Error> {
+        sqlx::query_as::<_, MaintenanceWindow>(
+            r#"SELECT id, target_id, tag, starts_at, ends_at, repeat, reason, updated_by, updated_at
+            FROM maintenance_windows ORDER BY starts_at"#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
+    // Target operations
+    async fn create_target(&self, target: &Target) -> Result<Target, Error> {
         debug!("Creating target: '{}({})'", target.name, target.id);
         sqlx::query(
             r#"
             INSERT INTO targets
-            (id, name, hostname, port, server_public_key, description, is_active, updated_by, updated_at)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            (id, name, hostname, port, server_public_key, host_key_mode, pending_public_key, via_target_id, timeout_secs, description, is_active, updated_by, updated_at, version)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#,
         )
         .bind(target.id)
@@ -408,10 +1611,15 @@ LEFT JOIN (
         .bind(&target.hostname)
         .bind(target.port as i64)
         .bind(&target.server_public_key)
+        .bind(&target.host_key_mode)
+        .bind(&target.pending_public_key)
+        .bind(target.via_target_id)
+        .bind(target.timeout_secs)
         .bind(&target.description)
         .bind(target.is_active)
         .bind(target.updated_by)
         .bind(target.updated_at)
+        .bind(target.version)
         .execute(&self.pool)
         .await?;
 
@@ -427,8 +1635,8 @@ LEFT JOIN (
         id: &Uuid,
         active_only: bool,
     ) -> Result<Option<Target>, Error> {
-        let mut query = r#"SELECT id, name, hostname, port, server_public_key, description,
-            is_active, updated_by, updated_at FROM targets WHERE id = ?"#
+        let mut query = r#"SELECT id, name, hostname, port, server_public_key, host_key_mode, pending_public_key, via_target_id, timeout_secs, description,
+            is_active, updated_by, updated_at, version FROM targets WHERE id = ?"#
             .to_string();
         if active_only {
             query.push_str(" AND is_active = 1");
@@ -445,18 +1653,14 @@ LEFT JOIN (
         if ids.is_empty() {
             return Ok(Vec::new());
         }
-        let placeholders = ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
-        let sql = format!(
-            r#"SELECT id, name, hostname, port, server_public_key, description,
-            is_active, updated_by, updated_at FROM targets WHERE id IN ({placeholders})"#
-        );
-
-        let mut query = sqlx::query_as::<_, Target>(&sql);
-
-        for id in ids {
-            query = query.bind(id);
-        }
-        let rows = query.fetch_all(&self.pool).await?;
+        let rows = sqlx::query_as::<_, Target>(
+            r#"SELECT id, name, hostname, port, server_public_key, host_key_mode, pending_public_key, via_target_id, timeout_secs, description,
+            is_active, updated_by, updated_at, version FROM targets
+            WHERE id IN (SELECT unhex(value) FROM json_each(?))"#,
+        )
+        .bind(uuid_list(ids))
+        .fetch_all(&self.pool)
+        .await?;
 
         Ok(rows)
     }
@@ -469,32 +1673,25 @@ LEFT JOIN (
         if ids.is_empty() {
             return Ok(Vec::new());
         }
-        let placeholders = ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
-        let mut sql = format!(
-            r#"SELECT t.id, t.name, t.hostname, t.port, t.server_public_key, t.description,
-            t.is_active, t.updated_by, t.updated_at FROM target_secrets ts
+        let rows = sqlx::query_as::<_, Target>(
+            r#"SELECT t.id, t.name, t.hostname, t.port, t.server_public_key, t.host_key_mode, t.pending_public_key, t.via_target_id, t.timeout_secs, t.description,
+            t.is_active, t.updated_by, t.updated_at, t.version FROM target_secrets ts
             INNER JOIN targets t ON ts.target_id = t.id
-            WHERE ts.id IN ({placeholders})"#
-        );
-
-        if active_only {
-            sql.push_str(" AND ts.is_active = 1 AND t.is_active = 1");
-        }
-
-        let mut query = sqlx::query_as::<_, Target>(&sql);
-
-        for id in ids {
-            query = query.bind(id);
-        }
-        let rows = query.fetch_all(&self.pool).await?;
+            WHERE ts.id IN (SELECT unhex(value) FROM json_each(?))
+            AND (? = 0 OR (ts.is_active = 1 AND t.is_active = 1))"#,
+        )
+        .bind(uuid_list(ids))
+        .bind(active_only)
+        .fetch_all(&self.pool)
+        .await?;
 
         Ok(rows)
     }
 
     async fn get_target_by_name(&self, name: &str) -> Result<Option<Target>, Error> {
         let row = sqlx::query_as::<_, Target>(
-            r#"SELECT id, name, hostname, port, server_public_key, description,
-            is_active, updated_by, updated_at FROM targets WHERE name = ?"#,
+            r#"SELECT id, name, hostname, port, server_public_key, host_key_mode, pending_public_key, via_target_id, timeout_secs, description,
+            is_active, updated_by, updated_at, version FROM targets WHERE name = ?"#,
         )
         .bind(name)
         .fetch_optional(&self.pool)
@@ -505,8 +1702,8 @@ LEFT JOIN (
 
     async fn get_target_by_hostname(&self, hostname: &str) -> Result<Option<Target>, Error> {
         let row = sqlx::query_as::<_, Target>(
-            r#"SELECT id, name, hostname, port, server_public_key, description,
-            is_active, updated_by, updated_at FROM targets WHERE hostname = ?"#,
+            r#"SELECT id, name, hostname, port, server_public_key, host_key_mode, pending_public_key, via_target_id, timeout_secs, description,
+            is_active, updated_by, updated_at, version FROM targets WHERE hostname = ?"#,
         )
         .bind(hostname)
         .fetch_optional(&self.pool)
@@ -518,27 +1715,40 @@ LEFT JOIN (
     async fn update_target(&self, target: &Target) -> Result<Target, Error> {
         debug!("Updating target: '{}({})'", target.name, target.id);
         let mut updated_target = target.clone();
-        updated_target.updated_at = Utc::now().timestamp_millis();
+        updated_target.updated_at = Timestamp::now();
 
-        sqlx::query(
+        let result = sqlx::query(
             r#"
             UPDATE targets
-            SET name = ?, hostname = ?, port = ?, server_public_key = ?, description = ?,
-            is_active = ?, updated_by = ?, updated_at = ?
-            WHERE id = ?
+            SET name = ?, hostname = ?, port = ?, server_public_key = ?, host_key_mode = ?,
+            pending_public_key = ?, via_target_id = ?, timeout_secs = ?, description = ?, is_active = ?, updated_by = ?, updated_at = ?,
+            version = version + 1
+            WHERE id = ? AND version = ?
             "#,
         )
         .bind(&updated_target.name)
         .bind(&updated_target.hostname)
         .bind(updated_target.port as i64)
         .bind(&updated_target.server_public_key)
+        .bind(&updated_target.host_key_mode)
+        .bind(&updated_target.pending_public_key)
+        .bind(updated_target.via_target_id)
+        .bind(updated_target.timeout_secs)
         .bind(&updated_target.description)
         .bind(updated_target.is_active)
         .bind(updated_target.updated_by)
         .bind(updated_target.updated_at)
         .bind(updated_target.id)
+        .bind(updated_target.version)
         .execute(&self.pool)
         .await?;
+        if result.rows_affected() == 0 {
+            return Err(Error::Database(DatabaseError::StaleRow {
+                table: "targets",
+                id: updated_target.id,
+            }));
+        }
+        updated_target.version += 1;
 
         debug!(
             "Target updated successfully: '{}({})'",
@@ -547,12 +1757,39 @@ LEFT JOIN (
         Ok(updated_target)
     }
 
-    async fn delete_target(&self, id: &Uuid) -> Result<bool, Error> {
-        debug!("Deleting target: id={}", id);
+    async fn delete_target(&self, id: &Uuid, force: bool) -> Result<bool, Error> {
+        debug!("Deleting target: id={}, force={}", id, force);
+        let mut tx = self.pool.begin().await?;
+
+        let count: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM target_secrets WHERE target_id = ? AND is_active = 1",
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if count > 0 && !force {
+            return Err(Error::Database(DatabaseError::ActiveBindings { count }));
+        }
+
+        // Policies and group members pointing at the bindings would be left dangling
+        sqlx::query(
+            r#"DELETE FROM casbin_rule WHERE v0 IN (SELECT id FROM target_secrets WHERE target_id = ?)
+            OR v1 IN (SELECT id FROM target_secrets WHERE target_id = ?)"#,
+        )
+        .bind(id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM target_secrets WHERE target_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
         let result = sqlx::query("DELETE FROM targets WHERE id = ?")
             .bind(id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
+        tx.commit().await?;
 
         let deleted = result.rows_affected() > 0;
         if deleted {
@@ -561,21 +1798,36 @@ LEFT JOIN (
         Ok(deleted)
     }
 
-    async fn list_targets(&self, active_only: bool) -> Result<Vec<Target>, Error> {
-        let mut query = String::from(
-            r#"SELECT id, name, hostname, port, server_public_key, description,
-                  is_active, updated_by, updated_at
-           FROM targets"#,
-        );
-
-        if active_only {
-            query.push_str(" WHERE is_active = 1");
-        }
-
-        sqlx::query_as::<_, Target>(&query)
-            .fetch_all(&self.pool)
-            .await
-            .map_err(Error::Sqlx)
+    async fn list_targets(
+        &self,
+        active_only: bool,
+        tags: &[TagFilter],
+    ) -> Result<Vec<Target>, Error> {
+        let mut tags = tags.to_vec();
+        tags.sort_by(|a, b| (&a.key, &a.value).cmp(&(&b.key, &b.value)));
+        tags.dedup();
+        let tags = serde_json::to_string(&tags)?;
+
+        // A target has one value per key, so matching every filter means
+        // the number of matched tags equals the number of filters
+        sqlx::query_as::<_, Target>(
+            r#"SELECT id, name, hostname, port, server_public_key, host_key_mode, pending_public_key, via_target_id, timeout_secs, description,
+                  is_active, updated_by, updated_at, version
+           FROM targets t
+           WHERE (? = 0 OR t.is_active = 1)
+           AND json_array_length(?) = (
+               SELECT COUNT(*) FROM json_each(?) f
+               INNER JOIN target_tags tt ON tt.target_id = t.id
+               AND tt.key = json_extract(f.value, '$.key')
+               AND tt.value = json_extract(f.value, '$.value')
+           )"#,
+        )
+        .bind(active_only)
+        .bind(&tags)
+        .bind(&tags)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
     }
 
     async fn list_targets_info(&self) -> Result<Vec<TargetInfo>, Error> {
@@ -586,28 +1838,202 @@ LEFT JOIN (
             .map_err(Error::Sqlx)
     }
 
+    async fn create_target_tag(&self, tag: &TargetTag) -> Result<TargetTag, Error> {
+        debug!(
+            "Creating target tag: '{}' on {}",
+            tag.label(),
+            tag.target_id
+        );
+        sqlx::query(
+            r#"
+            INSERT INTO target_tags (id, target_id, key, value, updated_by, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(tag.id)
+        .bind(tag.target_id)
+        .bind(&tag.key)
+        .bind(&tag.value)
+        .bind(tag.updated_by)
+        .bind(tag.updated_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(tag.clone())
+    }
+
+    async fn delete_target_tag(&self, id: &Uuid) -> Result<bool, Error> {
+        debug!("Deleting target tag: id={}", id);
+        let result = sqlx::query("DELETE FROM target_tags WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_target_tags(&self) -> Result<Vec<TargetTag>, Error> {
+        sqlx::query_as::<_, TargetTag>(
+            r#"SELECT id, target_id, key, value, updated_by, updated_at
+            FROM target_tags ORDER BY key, value"#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
+    async fn list_tags_for_target(&self, target_id: &Uuid) -> Result<Vec<TargetTag>, Error> {
+        sqlx::query_as::<_, TargetTag>(
+            r#"SELECT id, target_id, key, value, updated_by, updated_at
+            FROM target_tags WHERE target_id = ? ORDER BY key"#,
+        )
+        .bind(target_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
+    async fn set_target_tags(
+        &self,
+        target_id: &Uuid,
+        tags: &[TargetTag],
+    ) -> Result<Vec<TargetTag>, Error> {
+        debug!("Setting {} tag(s) on target: id={}", tags.len(), target_id);
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM target_tags WHERE target_id = ?")
+            .bind(target_id)
+            .execute(&mut *tx)
+            .await?;
+        for tag in tags {
+            sqlx::query(
+                r#"
+                INSERT INTO target_tags (id, target_id, key, value, updated_by, updated_at)
+                VALUES (?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(tag.id)
+            .bind(target_id)
+            .bind(&tag.key)
+            .bind(&tag.value)
+            .bind(tag.updated_by)
+            .bind(tag.updated_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(tags.to_vec())
+    }
+
+    async fn sync_tag_groups(&self, updated_by: &Uuid) -> Result<usize, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let labels: Vec<(String, String)> =
+            sqlx::query_as("SELECT DISTINCT key, value FROM target_tags")
+                .fetch_all(&mut *tx)
+                .await?;
+        let groups: Vec<(Uuid, String)> =
+            sqlx::query_as("SELECT id, name FROM casbin_names WHERE ptype = 'g2' AND name LIKE ?")
+                .bind(format!("{TAG_GROUP_PREFIX}%"))
+                .fetch_all(&mut *tx)
+                .await?;
+
+        for (key, value) in labels.iter() {
+            let name = format!("{TAG_GROUP_PREFIX}{key}={value}");
+            if groups.iter().any(|(_, n)| n == &name) {
+                continue;
+            }
+            let group = CasbinName::new("g2".to_string(), name, true, *updated_by);
+            sqlx::query(
+                r#"
+                INSERT INTO casbin_names (id, ptype, name, is_active, updated_by, updated_at)
+                VALUES (?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(group.id)
+            .bind(&group.ptype)
+            .bind(&group.name)
+            .bind(group.is_active)
+            .bind(group.updated_by)
+            .bind(group.updated_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        // Nested groups added by hand are kept, only stored binding members go
+        for (group_id, _) in groups.iter() {
+            sqlx::query(
+                r#"DELETE FROM casbin_rule WHERE ptype = 'g2' AND v1 = ?
+                AND v0 IN (SELECT id FROM target_secrets)"#,
+            )
+            .bind(group_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        debug!("Synced {} tag group(s)", labels.len());
+        Ok(labels.len())
+    }
+
+    async fn list_tag_rule_groups(&self) -> Result<Vec<CasbinRuleGroup>, Error> {
+        sqlx::query_as::<_, CasbinRuleGroup>(
+            r#"SELECT
+    tt.id,
+    ts.id AS v0,
+    s.user || '@' || t.name || ':' || t.port AS v0_object_label,
+    NULL AS v0_group_label,
+    n.id AS v1,
+    NULL AS v1_object_label,
+    n.name AS v1_group_label
+FROM casbin_names AS n
+INNER JOIN target_tags AS tt ON n.name = ? || tt.key || '=' || tt.value
+INNER JOIN target_secrets AS ts ON ts.target_id = tt.target_id
+INNER JOIN targets AS t ON ts.target_id = t.id
+INNER JOIN secrets AS s ON ts.secret_id = s.id
+WHERE n.ptype = 'g2' AND n.is_active = 1;"#,
+        )
+        .bind(TAG_GROUP_PREFIX)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
     async fn list_targets_for_user(
         &self,
         user_id: &Uuid,
         active_only: bool,
     ) -> Result<Vec<TargetSecretName>, Error> {
-        let mut query = r#"
-            SELECT l.pid, ts.id, t.id AS target_id, t.name AS target_name, s.id AS secret_id, s.user AS secret_user
-            FROM (WITH all_policy AS (SELECT id, v1 FROM casbin_rule WHERE v0 = ? AND ptype = 'p'
-            UNION ALL SELECT id, v1 FROM casbin_rule WHERE ptype = 'p' AND v0 IN
-            (SELECT v1 FROM casbin_rule WHERE v0 = ? AND ptype = 'g1'))
-            SELECT p.id AS pid, c.v0 AS id FROM (SELECT * FROM casbin_rule WHERE ptype = 'g2') c INNER JOIN all_policy p ON p.v1 = c.v1
-            UNION ALL SELECT p.id AS pid, p.v1 AS id FROM all_policy p LEFT JOIN (SELECT * FROM casbin_rule WHERE ptype = 'g2') c
+        let mut query = concat!(
+            r#"
+            SELECT l.pid, ts.id, t.id AS target_id, t.name AS target_name, t.hostname AS target_hostname,
+            t.port AS target_port, s.id AS secret_id, s.user AS secret_user
+            FROM (WITH RECURSIVE "#,
+            user_roles_cte!(),
+            r#",
+            all_policy AS (SELECT id, v1 FROM casbin_rule WHERE ptype = 'p'
+            AND v0 IN (SELECT id FROM user_roles)),
+            object_group(v0, v1) AS (SELECT v0, v1 FROM casbin_rule WHERE ptype = 'g2'
+                UNION SELECT ts.id, n.id FROM casbin_names n
+                INNER JOIN target_tags tt ON n.name = ? || tt.key || '=' || tt.value
+                INNER JOIN target_secrets ts ON ts.target_id = tt.target_id
+                WHERE n.ptype = 'g2' AND n.is_active = 1)
+            SELECT p.id AS pid, c.v0 AS id FROM object_group c INNER JOIN all_policy p ON p.v1 = c.v1
+            UNION ALL SELECT p.id AS pid, p.v1 AS id FROM all_policy p LEFT JOIN object_group c
             ON p.v1 = c.v1 WHERE c.v1 IS NULL) l INNER JOIN target_secrets ts ON ts.id = l.id
             INNER JOIN targets t ON ts.target_id = t.id INNER JOIN secrets s ON ts.secret_id = s.id
             "#
-            .to_string();
+        )
+        .to_string();
         if active_only {
             query.push_str(" WHERE ts.is_active = 1 AND t.is_active = 1 AND s.is_active = 1");
         }
         let targets = sqlx::query_as::<_, TargetSecretName>(&query)
             .bind(user_id)
             .bind(user_id)
+            .bind(MAX_ROLE_DEPTH)
+            .bind(TAG_GROUP_PREFIX)
             .fetch_all(&self.pool)
             .await?;
 
@@ -623,25 +2049,20 @@ LEFT JOIN (
         if ids.is_empty() {
             return Ok(Vec::new());
         }
-        let placeholders = ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
-        let mut sql = format!(
+        let targets = sqlx::query_as::<_, TargetSecretName>(
             r#"
-            SELECT ? AS pid, ts.id, t.id AS target_id, t.name AS target_name, s.id AS secret_id, s.user AS secret_user
+            SELECT ? AS pid, ts.id, t.id AS target_id, t.name AS target_name, t.hostname AS target_hostname,
+            t.port AS target_port, s.id AS secret_id, s.user AS secret_user
             FROM target_secrets ts INNER JOIN targets t ON ts.target_id = t.id
             INNER JOIN secrets s ON ts.secret_id = s.id
-            WHERE ts.id IN ({placeholders})"#
-        );
-
-        if active_only {
-            sql.push_str(" AND ts.is_active = 1 AND t.is_active = 1 AND s.is_active = 1");
-        }
-
-        let mut query = sqlx::query_as::<_, TargetSecretName>(&sql).bind(pid);
-        for id in ids {
-            query = query.bind(id);
-        }
-
-        let targets = query.fetch_all(&self.pool).await?;
+            WHERE ts.id IN (SELECT unhex(value) FROM json_each(?))
+            AND (? = 0 OR (ts.is_active = 1 AND t.is_active = 1 AND s.is_active = 1))"#,
+        )
+        .bind(pid)
+        .bind(uuid_list(ids))
+        .bind(active_only)
+        .fetch_all(&self.pool)
+        .await?;
 
         Ok(targets)
     }
@@ -650,7 +2071,8 @@ LEFT JOIN (
         // Look for action groups (g3) that include this action
         let rules = sqlx::query_as::<_, CasbinRule>(
             r#"
-            SELECT * FROM casbin_rule WHERE v1 = ? AND ptype = 'g3'
+            SELECT id, ptype, v0, v1, v2, v3, v4, v5, updated_by, updated_at
+            FROM casbin_rule WHERE v1 = ? AND ptype = 'g3'
             "#,
         )
         .bind(policy_act)
@@ -671,15 +2093,17 @@ LEFT JOIN (
     }
 
     async fn get_policies_for_user(&self, user_id: &Uuid) -> Result<Vec<CasbinRule>, Error> {
-        let policies = sqlx::query_as::<_, CasbinRule>(
+        let policies = sqlx::query_as::<_, CasbinRule>(concat!(
+            "WITH RECURSIVE ",
+            user_roles_cte!(),
             r#"
-            SELECT * FROM casbin_rule WHERE v0 = ? AND ptype = 'p'
-            UNION ALL SELECT * FROM casbin_rule WHERE ptype = 'p' AND v0 IN
-            (SELECT v1 FROM casbin_rule WHERE v0 = ? AND ptype = 'g1');
+            SELECT id, ptype, v0, v1, v2, v3, v4, v5, updated_by, updated_at
+            FROM casbin_rule WHERE ptype = 'p' AND v0 IN (SELECT id FROM user_roles);
             "#,
-        )
+        ))
         .bind(user_id)
         .bind(user_id)
+        .bind(MAX_ROLE_DEPTH)
         .fetch_all(&self.pool)
         .await?;
 
@@ -698,6 +2122,17 @@ LEFT JOIN (
             .map_err(Error::Sqlx)
     }
 
+    async fn get_casbin_rule_by_id(&self, id: &Uuid) -> Result<Option<CasbinRule>, Error> {
+        sqlx::query_as::<_, CasbinRule>(
+            r#"SELECT id, ptype, v0, v1, v2, v3, v4, v5, updated_by, updated_at
+            FROM casbin_rule WHERE id = ?"#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
     async fn list_roles_by_user_id(&self, user_id: &Uuid) -> Result<Vec<Role>, Error> {
         let query = r#"
         SELECT 
@@ -735,14 +2170,16 @@ WHERE cn.ptype = 'g1';
     c.id,
     c.v0,
     NULL AS v0_object_label,
-    cn0.name AS v0_group_label,
+    COALESCE(cn0.name, ug0.name) AS v0_group_label,
     c.v1,
     u1.username AS v1_object_label,
-    cn1.name AS v1_group_label
+    COALESCE(cn1.name, ug1.name) AS v1_group_label
 FROM casbin_rule AS c
 LEFT JOIN users AS u1 ON c.v1 = u1.id
 LEFT JOIN casbin_names AS cn0 ON c.v0 = cn0.id
 LEFT JOIN casbin_names AS cn1 ON c.v1 = cn1.id
+LEFT JOIN user_groups AS ug0 ON c.v0 = ug0.id
+LEFT JOIN user_groups AS ug1 ON c.v1 = ug1.id
 WHERE c.ptype = 'g1';"#
             }
             "g2" => {
@@ -839,7 +2276,7 @@ WHERE c.ptype = 'g3';"#
     async fn update_casbin_rule(&self, rule: &CasbinRule) -> Result<CasbinRule, Error> {
         debug!("Updating casbin_rule: '({})'", rule.id);
         let mut updated_rule = rule.clone();
-        updated_rule.updated_at = Utc::now().timestamp_millis();
+        updated_rule.updated_at = Timestamp::now();
 
         sqlx::query(
             r#"
@@ -963,7 +2400,15 @@ SELECT
     name, 
     1 AS is_group 
 FROM casbin_names 
-WHERE ptype = 'g1';"#,
+WHERE ptype = 'g1'
+
+UNION ALL
+
+SELECT 
+    id, 
+    name, 
+    1 AS is_group 
+FROM user_groups;"#,
         );
 
         let rows = sqlx::query_as::<_, ObjectGroup>(&query)
@@ -1097,7 +2542,7 @@ WHERE ptype = 'g3'
         }
 
         let mut updated_rule = rule.clone();
-        updated_rule.updated_at = Utc::now().timestamp_millis();
+        updated_rule.updated_at = Timestamp::now();
 
         sqlx::query(
             r#"
@@ -1130,10 +2575,21 @@ WHERE ptype = 'g3'
             )));
         }
 
+        let mut tx = self.pool.begin().await?;
+
+        // Rules using the group as subject, object or action
+        sqlx::query("DELETE FROM casbin_rule WHERE v0 = ? OR v1 = ? OR v2 = ?")
+            .bind(id)
+            .bind(id)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
         let result = sqlx::query("DELETE FROM casbin_names WHERE id = ?")
             .bind(id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
+        tx.commit().await?;
 
         let deleted = result.rows_affected() > 0;
         if deleted {
@@ -1181,8 +2637,8 @@ WHERE ptype = 'g3'
 
     async fn list_secrets(&self, active_only: bool) -> Result<Vec<Secret>, Error> {
         let mut query = String::from(
-            r#"SELECT id, name, user, password, private_key, public_key,
-            is_active, updated_by, updated_at
+            r#"SELECT id, name, user, password, private_key, public_key, kind,
+            is_active, updated_by, updated_at, version
             FROM secrets"#,
         );
 
@@ -1216,8 +2672,8 @@ WHERE ptype = 'g3'
         sqlx::query(
             r#"
             INSERT INTO secrets
-            (id, name, user, password, private_key, public_key, is_active, updated_by, updated_at)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            (id, name, user, password, private_key, public_key, kind, is_active, updated_by, updated_at, version)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#,
         )
         .bind(secret.id)
@@ -1226,9 +2682,11 @@ WHERE ptype = 'g3'
         .bind(&secret.password)
         .bind(&secret.private_key)
         .bind(&secret.public_key)
+        .bind(&secret.kind)
         .bind(secret.is_active)
         .bind(secret.updated_by)
         .bind(secret.updated_at)
+        .bind(secret.version)
         .execute(&self.pool)
         .await?;
 
@@ -1293,8 +2751,8 @@ WHERE ptype = 'g3'
         id: &Uuid,
         active_only: bool,
     ) -> Result<Option<Secret>, Error> {
-        let mut query = r#"SELECT s.id, s.name, s.user, s.password, s.private_key, s.public_key, s.is_active, s.updated_by,
-            s.updated_at FROM target_secrets ts
+        let mut query = r#"SELECT s.id, s.name, s.user, s.password, s.private_key, s.public_key, s.kind, s.is_active, s.updated_by,
+            s.updated_at, s.version FROM target_secrets ts
             INNER JOIN secrets s ON ts.secret_id = s.id
             WHERE ts.id = ?"#
             .to_string();
@@ -1311,8 +2769,8 @@ WHERE ptype = 'g3'
 
     async fn get_secret_by_id(&self, id: &Uuid) -> Result<Option<Secret>, Error> {
         let row = sqlx::query_as::<_, Secret>(
-            r#"SELECT id, name, user, password, private_key, public_key, is_active, updated_by,
-            updated_at FROM secrets WHERE id = ?"#,
+            r#"SELECT id, name, user, password, private_key, public_key, kind, is_active, updated_by,
+            updated_at, version FROM secrets WHERE id = ?"#,
         )
         .bind(id)
         .fetch_optional(&self.pool)
@@ -1325,46 +2783,22 @@ WHERE ptype = 'g3'
         if ids.is_empty() {
             return Ok(Vec::new());
         }
-        let placeholders = ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
-        let sql = format!(
-            r#"SELECT id, name, user, password, private_key, public_key, is_active, updated_by,
-            updated_at FROM secrets WHERE id IN ({placeholders})"#,
-        );
-
-        let mut query = sqlx::query_as::<_, Secret>(&sql);
-
-        for id in ids {
-            query = query.bind(id);
-        }
-        let rows = query.fetch_all(&self.pool).await?;
+        let rows = sqlx::query_as::<_, Secret>(
+            r#"SELECT id, name, user, password, private_key, public_key, kind, is_active, updated_by,
+            updated_at, version FROM secrets WHERE id IN (SELECT unhex(value) FROM json_each(?))"#,
+        )
+        .bind(uuid_list(ids))
+        .fetch_all(&self.pool)
+        .await?;
 
         Ok(rows)
     }
 
     async fn update_secret(&self, secret: &Secret) -> Result<Secret, Error> {
         debug!("Updating secret: '{}({})'", secret.name, secret.id);
-        let mut updated_secret = secret.clone();
-        updated_secret.updated_at = Utc::now().timestamp_millis();
-
-        sqlx::query(
-            r#"
-            UPDATE secrets
-            SET name = ?, user = ?, password = ?, private_key = ?, public_key = ?,
-            is_active = ?, updated_by = ?, updated_at = ?
-            WHERE id = ?
-            "#,
-        )
-        .bind(&updated_secret.name)
-        .bind(&updated_secret.user)
-        .bind(&updated_secret.password)
-        .bind(&updated_secret.private_key)
-        .bind(&updated_secret.public_key)
-        .bind(updated_secret.is_active)
-        .bind(updated_secret.updated_by)
-        .bind(updated_secret.updated_at)
-        .bind(updated_secret.id)
-        .execute(&self.pool)
-        .await?;
+        let mut tx = self.pool.begin().await?;
+        let updated_secret = update_secret_tx(&mut tx, secret).await?;
+        tx.commit().await?;
 
         debug!(
             "Secret updated successfully: '{}({})'",
@@ -1373,12 +2807,99 @@ WHERE ptype = 'g3'
         Ok(updated_secret)
     }
 
-    async fn delete_secret(&self, id: &Uuid) -> Result<bool, Error> {
-        debug!("Deleting secret: id={}", id);
+    async fn list_secret_versions(&self, secret_id: &Uuid) -> Result<Vec<SecretVersion>, Error> {
+        let rows = sqlx::query_as::<_, SecretVersion>(
+            r#"SELECT secret_id, version, user, password, private_key, public_key, updated_by,
+            updated_at FROM secret_versions WHERE secret_id = ? ORDER BY version DESC"#,
+        )
+        .bind(secret_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    async fn rollback_secret(
+        &self,
+        secret_id: &Uuid,
+        version: i64,
+        updated_by: &Uuid,
+    ) -> Result<Secret, Error> {
+        debug!(
+            "Rolling back secret: id={} to version={}",
+            secret_id, version
+        );
+        let mut tx = self.pool.begin().await?;
+
+        let v = sqlx::query_as::<_, SecretVersion>(
+            r#"SELECT secret_id, version, user, password, private_key, public_key, updated_by,
+            updated_at FROM secret_versions WHERE secret_id = ? AND version = ?"#,
+        )
+        .bind(secret_id)
+        .bind(version)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| DatabaseError::NotFound {
+            table: "secret_versions",
+            key: format!("{}@{}", secret_id, version),
+        })?;
+        let mut secret = sqlx::query_as::<_, Secret>(
+            r#"SELECT id, name, user, password, private_key, public_key, kind, is_active, updated_by,
+            updated_at, version FROM secrets WHERE id = ?"#,
+        )
+        .bind(secret_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| DatabaseError::NotFound {
+            table: "secrets",
+            key: secret_id.to_string(),
+        })?;
+
+        secret.restore(&v);
+        secret.updated_by = *updated_by;
+        let secret = update_secret_tx(&mut tx, &secret).await?;
+        tx.commit().await?;
+
+        info!(
+            "Secret '{}({})' rolled back to version {}",
+            secret.name, secret.id, version
+        );
+        Ok(secret)
+    }
+
+    async fn delete_secret(&self, id: &Uuid, force: bool) -> Result<bool, Error> {
+        debug!("Deleting secret: id={}, force={}", id, force);
+        let mut tx = self.pool.begin().await?;
+
+        let count: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM target_secrets WHERE secret_id = ? AND is_active = 1",
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if count > 0 && !force {
+            return Err(Error::Database(DatabaseError::ActiveBindings { count }));
+        }
+
+        // Policies and group members pointing at the bindings would be left dangling
+        sqlx::query(
+            r#"DELETE FROM casbin_rule WHERE v0 IN (SELECT id FROM target_secrets WHERE secret_id = ?)
+            OR v1 IN (SELECT id FROM target_secrets WHERE secret_id = ?)"#,
+        )
+        .bind(id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM target_secrets WHERE secret_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
         let result = sqlx::query("DELETE FROM secrets WHERE id = ?")
             .bind(id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
+        tx.commit().await?;
 
         let deleted = result.rows_affected() > 0;
         if deleted {
@@ -1428,20 +2949,118 @@ WHERE ptype = 'g3'
         Ok(rules.to_vec())
     }
 
+    async fn create_target_tags_batch(&self, tags: &[TargetTag]) -> Result<Vec<TargetTag>, Error> {
+        if tags.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let rows = (0..tags.len())
+            .map(|_| "(?,?,?,?,?,?)")
+            .collect::<Vec<_>>()
+            .join(",");
+        let query = format!(
+            r"INSERT INTO target_tags
+          (id, target_id, key, value, updated_by, updated_at)
+          VALUES {rows}"
+        );
+        let mut q = sqlx::query(&query);
+
+        for t in tags {
+            q = q
+                .bind(t.id)
+                .bind(t.target_id)
+                .bind(&t.key)
+                .bind(&t.value)
+                .bind(t.updated_by)
+                .bind(t.updated_at);
+        }
+
+        q.execute(&self.pool).await?;
+
+        Ok(tags.to_vec())
+    }
+
+    async fn create_user_groups_batch(
+        &self,
+        groups: &[UserGroup],
+    ) -> Result<Vec<UserGroup>, Error> {
+        if groups.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let rows = (0..groups.len())
+            .map(|_| "(?,?,?,?,?,?)")
+            .collect::<Vec<_>>()
+            .join(",");
+        let query = format!(
+            r"INSERT INTO user_groups
+          (id, name, description, is_active, updated_by, updated_at)
+          VALUES {rows}"
+        );
+        let mut q = sqlx::query(&query);
+
+        for g in groups {
+            q = q
+                .bind(g.id)
+                .bind(&g.name)
+                .bind(&g.description)
+                .bind(g.is_active)
+                .bind(g.updated_by)
+                .bind(g.updated_at);
+        }
+
+        q.execute(&self.pool).await?;
+
+        Ok(groups.to_vec())
+    }
+
+    async fn create_user_group_members_batch(
+        &self,
+        members: &[UserGroupMember],
+    ) -> Result<Vec<UserGroupMember>, Error> {
+        if members.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let rows = (0..members.len())
+            .map(|_| "(?,?,?,?,?)")
+            .collect::<Vec<_>>()
+            .join(",");
+        let query = format!(
+            r"INSERT INTO user_group_members
+          (id, group_id, user_id, updated_by, updated_at)
+          VALUES {rows}"
+        );
+        let mut q = sqlx::query(&query);
+
+        for m in members {
+            q = q
+                .bind(m.id)
+                .bind(m.group_id)
+                .bind(m.user_id)
+                .bind(m.updated_by)
+                .bind(m.updated_at);
+        }
+
+        q.execute(&self.pool).await?;
+
+        Ok(members.to_vec())
+    }
+
     async fn create_users_batch(&self, users: &[User]) -> Result<Vec<User>, Error> {
         if users.is_empty() {
             return Ok(vec![]);
         }
 
         let rows = (0..users.len())
-            .map(|_| "(?,?,?,?,?,?,?,?,?)")
+            .map(|_| "(?,?,?,?,?,?,?,?,?,?,?,?,?,?)")
             .collect::<Vec<_>>()
             .join(",");
 
         let query = format!(
             r"INSERT INTO users
-          (id, username, email, password_hash, authorized_keys,
-           force_init_pass, is_active, updated_by, updated_at)
+          (id, username, email, password_hash, authorized_keys, totp_secret, password_changed_at, password_history,
+           allowed_source_cidrs, force_init_pass, is_active, updated_by, updated_at, version)
           VALUES {rows}"
         );
         let mut q = sqlx::query(&query);
@@ -1453,10 +3072,15 @@ WHERE ptype = 'g3'
                 .bind(&u.email)
                 .bind(&u.password_hash)
                 .bind(&u.authorized_keys)
+                .bind(&u.totp_secret)
+                .bind(u.password_changed_at)
+                .bind(&u.password_history)
+                .bind(&u.allowed_source_cidrs)
                 .bind(u.force_init_pass)
                 .bind(u.is_active)
                 .bind(u.updated_by)
-                .bind(u.updated_at);
+                .bind(u.updated_at)
+                .bind(u.version);
         }
 
         q.execute(&self.pool).await?;
@@ -1470,13 +3094,13 @@ WHERE ptype = 'g3'
         }
 
         let rows = (0..targets.len())
-            .map(|_| "(?,?,?,?,?,?,?,?,?)")
+            .map(|_| "(?,?,?,?,?,?,?,?,?,?,?,?,?,?)")
             .collect::<Vec<_>>()
             .join(",");
         let query = format!(
             r"INSERT INTO targets
-          (id, name, hostname, port, server_public_key, description,
-           is_active, updated_by, updated_at)
+          (id, name, hostname, port, server_public_key, host_key_mode, pending_public_key, via_target_id, timeout_secs, description,
+           is_active, updated_by, updated_at, version)
           VALUES {rows}"
         );
         let mut q = sqlx::query(&query);
@@ -1488,10 +3112,15 @@ WHERE ptype = 'g3'
                 .bind(&t.hostname)
                 .bind(t.port as i64)
                 .bind(&t.server_public_key)
+                .bind(&t.host_key_mode)
+                .bind(&t.pending_public_key)
+                .bind(t.via_target_id)
+                .bind(t.timeout_secs)
                 .bind(&t.description)
                 .bind(t.is_active)
                 .bind(t.updated_by)
-                .bind(t.updated_at);
+                .bind(t.updated_at)
+                .bind(t.version);
         }
 
         q.execute(&self.pool).await?;
@@ -1543,7 +3172,7 @@ WHERE ptype = 'g3'
         target_secret: &TargetSecret,
     ) -> Result<TargetSecret, Error> {
         let mut updated = target_secret.clone();
-        updated.updated_at = Utc::now().timestamp_millis();
+        updated.updated_at = Timestamp::now();
 
         sqlx::query(
             r#"
@@ -1569,10 +3198,19 @@ WHERE ptype = 'g3'
     }
 
     async fn delete_target_secret(&self, id: &Uuid) -> Result<bool, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM casbin_rule WHERE v0 = ? OR v1 = ?")
+            .bind(id)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
         let result = sqlx::query("DELETE FROM target_secrets WHERE id = ?")
             .bind(id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
+        tx.commit().await?;
 
         Ok(result.rows_affected() > 0)
     }
@@ -1580,7 +3218,8 @@ WHERE ptype = 'g3'
     async fn check_object_active(&self, id: &Uuid) -> Result<bool, Error> {
         // Check if it's an active target_secret
         let row = sqlx::query_as::<_, TargetSecret>(
-            r#"SELECT ts.* FROM target_secrets ts INNER JOIN targets t ON ts.target_id = t.id
+            r#"SELECT ts.id, ts.target_id, ts.secret_id, ts.is_active, ts.updated_by, ts.updated_at
+               FROM target_secrets ts INNER JOIN targets t ON ts.target_id = t.id
                INNER JOIN secrets s ON ts.secret_id = s.id WHERE ts.is_active = 1
                AND t.is_active = 1 AND s.is_active = 1 AND ts.id = ?
             "#,
@@ -1595,7 +3234,7 @@ WHERE ptype = 'g3'
 
         // Check if it's an active internal_object
         let row = sqlx::query_as::<_, CasbinName>(
-            "SELECT * FROM casbin_names WHERE ptype = '__internal_object_type' AND is_active = 1 AND id = ?",
+            "SELECT id, ptype, name, is_active, updated_by, updated_at FROM casbin_names WHERE ptype = '__internal_object_type' AND is_active = 1 AND id = ?",
         )
         .bind(id)
         .fetch_all(&self.pool)
@@ -1614,13 +3253,13 @@ WHERE ptype = 'g3'
         }
 
         let rows = (0..secrets.len())
-            .map(|_| "(?,?,?,?,?,?,?,?,?)")
+            .map(|_| "(?,?,?,?,?,?,?,?,?,?,?)")
             .collect::<Vec<_>>()
             .join(",");
 
         let query = format!(
             r"INSERT INTO secrets
-              (id, name, user, password, private_key, public_key, is_active, updated_by, updated_at)
+              (id, name, user, password, private_key, public_key, kind, is_active, updated_by, updated_at, version)
               VALUES {rows}"
         );
         let mut q = sqlx::query(&query);
@@ -1633,9 +3272,11 @@ WHERE ptype = 'g3'
                 .bind(&s.password)
                 .bind(&s.private_key)
                 .bind(&s.public_key)
+                .bind(&s.kind)
                 .bind(s.is_active)
                 .bind(s.updated_by)
-                .bind(s.updated_at);
+                .bind(s.updated_at)
+                .bind(s.version);
         }
 
         q.execute(&self.pool).await?;
@@ -1682,7 +3323,9 @@ WHERE ptype = 'g3'
         let search_pattern = format!("%{}%", query);
         let users = sqlx::query_as::<_, User>(
             r#"
-            SELECT id, username, email, password_hash, force_init_pass, is_active, updated_by, updated_at
+            SELECT id, username, email, password_hash, authorized_keys, totp_secret, password_changed_at,
+            password_history, allowed_source_cidrs, force_init_pass,
+            is_active, updated_by, updated_at, version
             FROM users 
             WHERE username LIKE ? OR email LIKE ?
             ORDER BY username
@@ -1700,8 +3343,8 @@ WHERE ptype = 'g3'
         let search_pattern = format!("%{}%", query);
         let targets = sqlx::query_as::<_, Target>(
             r#"
-            SELECT id, name, hostname, port, server_public_key, description,
-            is_active, updated_by, updated_at
+            SELECT id, name, hostname, port, server_public_key, host_key_mode, pending_public_key, via_target_id, timeout_secs, description,
+            is_active, updated_by, updated_at, version
             FROM targets 
             WHERE name LIKE ? OR hostname LIKE ? OR description LIKE ?
             ORDER BY name
@@ -1750,11 +3393,19 @@ WHERE ptype = 'g3'
 
     // log operations
     async fn insert_log(&self, log: &Log) -> Result<(), Error> {
+        let _chain = self.log_chain.lock().await;
+        let prev: Option<String> =
+            sqlx::query_scalar("SELECT hash FROM logs ORDER BY rowid DESC LIMIT 1")
+                .fetch_optional(&self.pool)
+                .await?;
+        let mut log = log.clone();
+        log.chain_after(prev.as_deref().unwrap_or(GENESIS_HASH));
+
         sqlx::query(
             r#"
             INSERT INTO logs
-            (connection_id, log_type, user_id, detail, created_at)
-            VALUES (?, ?, ?, ?, ?)
+            (connection_id, log_type, user_id, detail, created_at, prev_hash, hash)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
             "#,
         )
         .bind(log.connection_id)
@@ -1762,6 +3413,8 @@ WHERE ptype = 'g3'
         .bind(log.user_id)
         .bind(&log.detail)
         .bind(log.created_at)
+        .bind(&log.prev_hash)
+        .bind(&log.hash)
         .execute(&self.pool)
         .await?;
 
@@ -1770,7 +3423,7 @@ WHERE ptype = 'g3'
 
     async fn list_logs(&self) -> Result<Vec<Log>, Error> {
         let logs = sqlx::query_as::<_, Log>(
-            r#"SELECT connection_id, log_type, user_id, detail, created_at
+            r#"SELECT connection_id, log_type, user_id, detail, created_at, prev_hash, hash
             FROM logs ORDER BY created_at desc"#,
         )
         .fetch_all(&self.pool)
@@ -1779,6 +3432,56 @@ WHERE ptype = 'g3'
         Ok(logs)
     }
 
+    // policy change operations
+    async fn insert_policy_change(&self, change: &PolicyChange) -> Result<(), Error> {
+        debug!(
+            "Recording policy change {}: rule {} {}",
+            change.id, change.rule_id, change.action
+        );
+        sqlx::query(
+            r#"
+            INSERT INTO policy_changes
+            (id, rule_id, action, before, after, diff, changed_by, changed_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(change.id)
+        .bind(change.rule_id)
+        .bind(&change.action)
+        .bind(&change.before)
+        .bind(&change.after)
+        .bind(&change.diff)
+        .bind(change.changed_by)
+        .bind(change.changed_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_policy_changes(&self) -> Result<Vec<PolicyChange>, Error> {
+        sqlx::query_as::<_, PolicyChange>(
+            r#"SELECT id, rule_id, action, before, after, diff, changed_by, changed_at
+            FROM policy_changes ORDER BY changed_at DESC, rowid DESC"#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
+    async fn list_policy_change_views(&self) -> Result<Vec<PolicyChangeView>, Error> {
+        sqlx::query_as::<_, PolicyChangeView>(
+            r#"SELECT pc.id, pc.rule_id, pc.action, pc.diff,
+            COALESCE(u.username, '') AS changed_by, pc.changed_at
+            FROM policy_changes pc
+            LEFT JOIN users u ON pc.changed_by = u.id
+            ORDER BY pc.changed_at DESC, pc.rowid DESC"#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)
+    }
+
     async fn create_session_recording(
         &self,
         recording: &SessionRecording,
@@ -1791,8 +3494,9 @@ WHERE ptype = 'g3'
         sqlx::query(
             r#"
             INSERT INTO session_recordings
-            (id, user_id, target_id, secret_id, file_path, started_at, ended_at, connection_id, status)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            (id, user_id, target_id, secret_id, file_path, started_at, ended_at, connection_id, status, ticket,
+            duration_ms, active_ms, event_count)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#,
         )
         .bind(recording.id)
@@ -1804,6 +3508,10 @@ WHERE ptype = 'g3'
         .bind(recording.ended_at)
         .bind(recording.connection_id)
         .bind(&recording.status)
+        .bind(&recording.ticket)
+        .bind(recording.duration_ms)
+        .bind(recording.active_ms)
+        .bind(recording.event_count)
         .execute(&self.pool)
         .await?;
 
@@ -1819,7 +3527,8 @@ WHERE ptype = 'g3'
         sqlx::query(
             r#"
             UPDATE session_recordings
-            SET file_path = ?, started_at = ?, ended_at = ?, status = ?
+            SET file_path = ?, started_at = ?, ended_at = ?, status = ?, duration_ms = ?,
+            active_ms = ?, event_count = ?
             WHERE id = ?
             "#,
         )
@@ -1827,6 +3536,9 @@ WHERE ptype = 'g3'
         .bind(recording.started_at)
         .bind(recording.ended_at)
         .bind(&recording.status)
+        .bind(recording.duration_ms)
+        .bind(recording.active_ms)
+        .bind(recording.event_count)
         .bind(recording.id)
         .execute(&self.pool)
         .await?;
@@ -1839,7 +3551,7 @@ WHERE ptype = 'g3'
         id: &Uuid,
     ) -> Result<Option<SessionRecording>, Error> {
         let row = sqlx::query_as::<_, SessionRecording>(
-            "SELECT id, user_id, target_id, secret_id, file_path, started_at, ended_at, connection_id, status FROM session_recordings WHERE id = ?",
+            "SELECT id, user_id, target_id, secret_id, file_path, started_at, ended_at, connection_id, status, ticket, duration_ms, active_ms, event_count FROM session_recordings WHERE id = ?",
         )
         .bind(id)
         .fetch_optional(&self.pool)
@@ -1852,18 +3564,14 @@ WHERE ptype = 'g3'
         &self,
         limit: Option<i64>,
     ) -> Result<Vec<SessionRecording>, Error> {
-        let mut query = String::from(
-            "SELECT id, user_id, target_id, secret_id, file_path, started_at, ended_at, connection_id, status FROM session_recordings ORDER BY started_at DESC",
-        );
-
-        if let Some(l) = limit {
-            query.push_str(&format!(" LIMIT {}", l));
-        }
-
-        let rows = sqlx::query_as::<_, SessionRecording>(&query)
-            .fetch_all(&self.pool)
-            .await
-            .map_err(Error::Sqlx)?;
+        // A negative LIMIT means no limit in SQLite
+        let rows = sqlx::query_as::<_, SessionRecording>(
+            "SELECT id, user_id, target_id, secret_id, file_path, started_at, ended_at, connection_id, status, ticket, duration_ms, active_ms, event_count FROM session_recordings ORDER BY started_at DESC LIMIT ?",
+        )
+        .bind(limit.unwrap_or(-1))
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::Sqlx)?;
 
         Ok(rows)
     }
@@ -1874,7 +3582,7 @@ WHERE ptype = 'g3'
     ) -> Result<Vec<RecordingView>, Error> {
         let rows = sqlx::query_as::<_, RecordingView>(
             r#"SELECT r.id, s.user || '@' || t.name || ':' || t.port AS target_secret,
-            r.started_at, r.ended_at, r.status FROM session_recordings r
+            r.file_path, r.started_at, r.ended_at, r.status, r.duration_ms, r.active_ms FROM session_recordings r
             LEFT JOIN secrets s ON r.secret_id = s.id
             LEFT JOIN targets t ON r.target_id = t.id
             WHERE r.user_id = ? ORDER BY r.started_at DESC"#,
@@ -1892,7 +3600,7 @@ WHERE ptype = 'g3'
         user_id: &Uuid,
     ) -> Result<Vec<SessionRecording>, Error> {
         let rows = sqlx::query_as::<_, SessionRecording>(
-            "SELECT id, user_id, target_id, secret_id, file_path, started_at, ended_at, connection_id, status FROM session_recordings WHERE user_id = ? ORDER BY started_at DESC",
+            "SELECT id, user_id, target_id, secret_id, file_path, started_at, ended_at, connection_id, status, ticket, duration_ms, active_ms, event_count FROM session_recordings WHERE user_id = ? ORDER BY started_at DESC",
         )
         .bind(user_id)
         .fetch_all(&self.pool)
@@ -1907,7 +3615,7 @@ WHERE ptype = 'g3'
         target_id: &Uuid,
     ) -> Result<Vec<SessionRecording>, Error> {
         let rows = sqlx::query_as::<_, SessionRecording>(
-            "SELECT id, user_id, target_id, secret_id, file_path, started_at, ended_at, connection_id, status FROM session_recordings WHERE target_id = ? ORDER BY started_at DESC",
+            "SELECT id, user_id, target_id, secret_id, file_path, started_at, ended_at, connection_id, status, ticket, duration_ms, active_ms, event_count FROM session_recordings WHERE target_id = ? ORDER BY started_at DESC",
         )
         .bind(target_id)
         .fetch_all(&self.pool)
@@ -1917,6 +3625,175 @@ WHERE ptype = 'g3'
         Ok(rows)
     }
 
+    async fn create_ssh_session(&self, session: &SshSession) -> Result<SshSession, Error> {
+        debug!(
+            "Creating session: user_id={}, target_secret_id={}",
+            session.user_id, session.target_secret_id
+        );
+
+        sqlx::query(
+            r#"
+            INSERT INTO sessions
+            (id, connection_id, user_id, target_secret_id, request, started_at, ended_at,
+            bytes_in, bytes_out, recording_path, termination_reason, ticket, access_reason,
+            login_as)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(session.id)
+        .bind(session.connection_id)
+        .bind(session.user_id)
+        .bind(session.target_secret_id)
+        .bind(&session.request)
+        .bind(session.started_at)
+        .bind(session.ended_at)
+        .bind(session.bytes_in)
+        .bind(session.bytes_out)
+        .bind(&session.recording_path)
+        .bind(&session.termination_reason)
+        .bind(&session.ticket)
+        .bind(&session.access_reason)
+        .bind(&session.login_as)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(session.clone())
+    }
+
+    async fn update_ssh_session(&self, session: &SshSession) -> Result<SshSession, Error> {
+        debug!("Updating session: id={}", session.id);
+
+        sqlx::query(
+            r#"
+            UPDATE sessions
+            SET ended_at = ?, bytes_in = ?, bytes_out = ?, recording_path = ?, termination_reason = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(session.ended_at)
+        .bind(session.bytes_in)
+        .bind(session.bytes_out)
+        .bind(&session.recording_path)
+        .bind(&session.termination_reason)
+        .bind(session.id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(session.clone())
+    }
+
+    async fn get_ssh_session_by_id(&self, id: &Uuid) -> Result<Option<SshSession>, Error> {
+        let row = sqlx::query_as::<_, SshSession>(
+            r#"SELECT id, connection_id, user_id, target_secret_id, request, started_at, ended_at,
+            bytes_in, bytes_out, recording_path, termination_reason, ticket, access_reason,
+            login_as
+            FROM sessions WHERE id = ?"#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row)
+    }
+
+    async fn list_ssh_sessions(&self, limit: Option<i64>) -> Result<Vec<SshSession>, Error> {
+        let rows = sqlx::query_as::<_, SshSession>(
+            r#"SELECT id, connection_id, user_id, target_secret_id, request, started_at, ended_at,
+            bytes_in, bytes_out, recording_path, termination_reason, ticket, access_reason,
+            login_as
+            FROM sessions ORDER BY started_at DESC LIMIT ?"#,
+        )
+        .bind(limit.unwrap_or(-1))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    async fn get_last_ssh_session_for_user(
+        &self,
+        user_id: &Uuid,
+    ) -> Result<Option<SshSession>, Error> {
+        let row = sqlx::query_as::<_, SshSession>(
+            r#"SELECT id, connection_id, user_id, target_secret_id, request, started_at, ended_at,
+            bytes_in, bytes_out, recording_path, termination_reason, ticket, access_reason,
+            login_as
+            FROM sessions WHERE user_id = ? ORDER BY started_at DESC LIMIT 1"#,
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row)
+    }
+
+    async fn count_ssh_sessions(
+        &self,
+        user_id: &Uuid,
+        target_secret_ids: &[&Uuid],
+        since: Timestamp,
+    ) -> Result<u64, Error> {
+        if target_secret_ids.is_empty() {
+            return Ok(0);
+        }
+        let count: i64 = sqlx::query_scalar(
+            r#"SELECT COUNT(*) FROM sessions WHERE user_id = ? AND started_at >= ?
+            AND target_secret_id IN (SELECT unhex(value) FROM json_each(?))"#,
+        )
+        .bind(user_id)
+        .bind(since)
+        .bind(uuid_list(target_secret_ids))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count as u64)
+    }
+
+    async fn list_active_ssh_sessions(&self) -> Result<Vec<SshSessionView>, Error> {
+        let rows = sqlx::query_as::<_, SshSessionView>(
+            r#"SELECT ss.id, ss.connection_id, COALESCE(u.username, '') AS username,
+            COALESCE(COALESCE(ss.login_as, s.user) || '@' || t.name || ':' || t.port, '')
+            AS target_secret,
+            ss.request, ss.started_at, ss.bytes_in, ss.bytes_out, ss.ticket, ss.access_reason
+            FROM sessions ss
+            LEFT JOIN users u ON ss.user_id = u.id
+            LEFT JOIN target_secrets ts ON ss.target_secret_id = ts.id
+            LEFT JOIN targets t ON ts.target_id = t.id
+            LEFT JOIN secrets s ON ts.secret_id = s.id
+            WHERE ss.ended_at IS NULL ORDER BY ss.started_at DESC"#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    async fn end_active_ssh_sessions(&self, reason: &str) -> Result<u64, Error> {
+        let result = sqlx::query(
+            "UPDATE sessions SET ended_at = ?, termination_reason = ? WHERE ended_at IS NULL",
+        )
+        .bind(Timestamp::now())
+        .bind(reason)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn health_check(&self) -> Result<(), Error> {
+        // A broken pooled connection is dropped here and a fresh one opened
+        // on the next acquire, which is how the pool reconnects
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn get_policy_version(&self) -> Result<i64, Error> {
+        let version: i64 = sqlx::query_scalar("SELECT version FROM policy_version WHERE id = 1")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(version)
+    }
+
     async fn list_permission_polices(&self) -> Result<Vec<PermissionPolicy>, Error> {
         let pols = sqlx::query_as::<_, PermissionPolicy>(
             r#"SELECT 
@@ -1930,6 +3807,8 @@ LEFT JOIN (
     SELECT id, name FROM casbin_names
     UNION ALL
     SELECT id, username FROM users
+    UNION ALL
+    SELECT id, name FROM user_groups
 ) n1 ON n1.id = cr.v0
 LEFT JOIN (
     SELECT 
@@ -1945,7 +3824,7 @@ LEFT JOIN (
     SELECT id, name FROM casbin_names
 ) n3 ON n3.id = cr.v2
 WHERE 
-    cr.ptype = 'p'"#,
+    cr.ptype IN ('p', 'p_deny')"#,
         )
         .fetch_all(&self.pool)
         .await?;
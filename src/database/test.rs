@@ -0,0 +1,275 @@
+#[cfg(test)]
+mod tests {
+    use crate::database::models::{CasbinName, CasbinRule, Role, User};
+    use crate::database::{DatabaseConfig, service::DatabaseService};
+    use crate::error::Error;
+    use proptest::prelude::*;
+    use std::collections::{BTreeSet, HashSet};
+    use std::fs::File;
+    use tempfile::tempdir;
+    use uuid::Uuid;
+
+    /// Small name pools so generated sequences keep running into the
+    /// unique constraints
+    const NAMES: u8 = 6;
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        CreateUser(u8),
+        DeleteUser(usize),
+        CreateRole(u8),
+        DeleteRole(usize),
+        Bind(usize, usize),
+        Unbind(usize),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            3 => (0..NAMES).prop_map(Op::CreateUser),
+            1 => any::<usize>().prop_map(Op::DeleteUser),
+            3 => (0..NAMES).prop_map(Op::CreateRole),
+            1 => any::<usize>().prop_map(Op::DeleteRole),
+            4 => (any::<usize>(), any::<usize>()).prop_map(|(u, r)| Op::Bind(u, r)),
+            2 => any::<usize>().prop_map(Op::Unbind),
+        ]
+    }
+
+    /// What both backends must agree on: success, a unique violation or
+    /// whether a delete found its row
+    #[derive(Debug, PartialEq)]
+    enum Outcome {
+        Created,
+        Unique,
+        Deleted(bool),
+    }
+
+    fn outcome<T>(res: Result<T, Error>) -> Outcome {
+        match res {
+            Ok(_) => Outcome::Created,
+            Err(Error::Sqlx(sqlx::Error::Database(e)))
+                if e.kind() == sqlx::error::ErrorKind::UniqueViolation =>
+            {
+                Outcome::Unique
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    /// Every id handed out so far, deleted ones included so deletes and
+    /// binds also hit missing rows. Picks are the same for both backends.
+    #[derive(Default)]
+    struct Ids {
+        users: Vec<Uuid>,
+        roles: Vec<Uuid>,
+        rules: Vec<Uuid>,
+    }
+
+    fn user(username: &str, updated_by: Uuid) -> User {
+        let mut user = User::new(updated_by);
+        user.username = username.to_string();
+        user
+    }
+
+    fn pick(ids: &[Uuid], i: usize) -> Option<Uuid> {
+        (!ids.is_empty()).then(|| ids[i % ids.len()])
+    }
+
+    async fn backends() -> (tempfile::TempDir, [DatabaseService; 2]) {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let _ = File::create(&db_path).unwrap();
+        let sqlite = DatabaseService::new(&DatabaseConfig::Sqlite {
+            path: db_path.to_string_lossy().to_string(),
+        })
+        .await
+        .unwrap();
+        let memory = DatabaseService::new(&DatabaseConfig::Memory {
+            name: Uuid::new_v4().to_string(),
+        })
+        .await
+        .unwrap();
+        (temp_dir, [sqlite, memory])
+    }
+
+    async fn apply_delete(db: &DatabaseService, op: &Op, ids: &Ids) -> Outcome {
+        let repo = db.repository();
+        match *op {
+            Op::CreateUser(_) | Op::CreateRole(_) | Op::Bind(..) => {
+                unreachable!("rows are built once and inserted by apply_create")
+            }
+            Op::DeleteUser(i) => match pick(&ids.users, i) {
+                Some(id) => Outcome::Deleted(repo.delete_user(&id).await.unwrap()),
+                None => Outcome::Deleted(false),
+            },
+            Op::DeleteRole(i) => match pick(&ids.roles, i) {
+                Some(id) => Outcome::Deleted(repo.delete_casbin_name(&id).await.unwrap()),
+                None => Outcome::Deleted(false),
+            },
+            Op::Unbind(i) => match pick(&ids.rules, i) {
+                Some(id) => Outcome::Deleted(repo.delete_casbin_rule(&id).await.unwrap()),
+                None => Outcome::Deleted(false),
+            },
+        }
+    }
+
+    enum Create {
+        User(User),
+        Role(CasbinName),
+        Rule(CasbinRule),
+    }
+
+    async fn apply_create(db: &DatabaseService, create: &Create) -> Outcome {
+        let repo = db.repository();
+        match create {
+            Create::User(u) => outcome(repo.create_user(u).await),
+            Create::Role(n) => outcome(repo.create_casbin_name(n).await),
+            Create::Rule(r) => outcome(repo.create_casbin_rule(r).await),
+        }
+    }
+
+    /// The role list of the user screens, rebuilt from the raw tables
+    async fn roles_from_rules(
+        db: &DatabaseService,
+        user_id: Uuid,
+    ) -> BTreeSet<(Uuid, Option<Uuid>)> {
+        let repo = db.repository();
+        let rules = repo.list_casbin_rules_by_ptype("g1").await.unwrap();
+        let mut roles = BTreeSet::new();
+        for name in repo.list_casbin_names_by_ptype("g1", false).await.unwrap() {
+            let bound: Vec<Uuid> = rules
+                .iter()
+                .filter(|r| r.v0 == name.id && r.v1 == user_id)
+                .map(|r| r.id)
+                .collect();
+            if bound.is_empty() {
+                roles.insert((name.id, None));
+            }
+            roles.extend(bound.into_iter().map(|id| (name.id, Some(id))));
+        }
+        roles
+    }
+
+    fn role_set(roles: &[Role]) -> BTreeSet<(Uuid, Option<Uuid>)> {
+        roles
+            .iter()
+            .inspect(|r| assert_eq!(r.is_bound, r.rule_id.is_some()))
+            .map(|r| (r.rid, r.rule_id))
+            .collect()
+    }
+
+    /// Invariants of one backend, returning its rows for the comparison
+    /// with the other one
+    async fn check(db: &DatabaseService) -> (BTreeSet<Uuid>, BTreeSet<Uuid>, BTreeSet<Uuid>) {
+        let repo = db.repository();
+        let users = repo.list_users(false).await.unwrap();
+        let names = repo.list_casbin_names(false).await.unwrap();
+        let rules = repo.list_casbin_rules().await.unwrap();
+
+        let usernames: HashSet<&str> = users.iter().map(|u| u.username.as_str()).collect();
+        assert_eq!(usernames.len(), users.len(), "duplicate username");
+        let role_names: HashSet<&str> = names.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(role_names.len(), names.len(), "duplicate casbin name");
+
+        let user_ids: BTreeSet<Uuid> = users.iter().map(|u| u.id).collect();
+        let name_ids: BTreeSet<Uuid> = names.iter().map(|n| n.id).collect();
+        for rule in &rules {
+            assert!(
+                name_ids.contains(&rule.v0),
+                "rule {} outlived its role",
+                rule.id
+            );
+            assert!(
+                user_ids.contains(&rule.v1),
+                "rule {} outlived its user",
+                rule.id
+            );
+        }
+
+        for user in &users {
+            let light = role_set(&repo.list_roles_by_user_id(&user.id).await.unwrap());
+            assert_eq!(light, roles_from_rules(db, user.id).await);
+        }
+
+        (user_ids, name_ids, rules.iter().map(|r| r.id).collect())
+    }
+
+    async fn run(ops: Vec<Op>) {
+        let (_dir, dbs) = backends().await;
+        let admin = user("admin", Uuid::nil());
+        for db in &dbs {
+            db.repository().create_user(&admin).await.unwrap();
+        }
+        let mut ids = Ids::default();
+
+        for op in &ops {
+            let create = match *op {
+                Op::CreateUser(n) => {
+                    let user = user(&format!("user{}", n), admin.id);
+                    ids.users.push(user.id);
+                    Some(Create::User(user))
+                }
+                Op::CreateRole(n) => {
+                    let name =
+                        CasbinName::new("g1".to_string(), format!("role{}", n), true, admin.id);
+                    ids.roles.push(name.id);
+                    Some(Create::Role(name))
+                }
+                Op::Bind(u, r) => match (pick(&ids.users, u), pick(&ids.roles, r)) {
+                    (Some(user), Some(role)) => {
+                        let rule = CasbinRule::new(
+                            "g1".to_string(),
+                            role,
+                            user,
+                            Uuid::nil(),
+                            String::new(),
+                            String::new(),
+                            String::new(),
+                            admin.id,
+                        );
+                        ids.rules.push(rule.id);
+                        Some(Create::Rule(rule))
+                    }
+                    _ => continue,
+                },
+                _ => None,
+            };
+            // Rules may only point at rows that exist, like the admin
+            // screens guarantee
+            if let Some(Create::Rule(rule)) = &create {
+                let repo = dbs[1].repository();
+                if repo.get_user_by_id(&rule.v1).await.unwrap().is_none()
+                    || repo
+                        .get_casbin_name_by_id(&rule.v0)
+                        .await
+                        .unwrap()
+                        .is_none()
+                {
+                    continue;
+                }
+            }
+
+            let mut outcomes = Vec::new();
+            for db in &dbs {
+                outcomes.push(match &create {
+                    Some(c) => apply_create(db, c).await,
+                    None => apply_delete(db, op, &ids).await,
+                });
+            }
+            assert_eq!(outcomes[0], outcomes[1], "backends diverged on {:?}", op);
+            assert_eq!(check(&dbs[0]).await, check(&dbs[1]).await, "after {:?}", op);
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn test_backends_agree(ops in prop::collection::vec(op(), 1..40)) {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(run(ops));
+        }
+    }
+}
@@ -21,6 +21,9 @@ pub enum Error {
     #[error(transparent)]
     Json(#[from] serde_json::Error),
 
+    #[error(transparent)]
+    Yaml(#[from] serde_norway::Error),
+
     // Module-level errors
     #[error(transparent)]
     Config(#[from] crate::config::error::ConfigError),
@@ -36,4 +39,96 @@ pub enum Error {
 
     #[error(transparent)]
     Record(#[from] crate::asciinema::Error),
-}
\ No newline at end of file
+}
+
+/// Stable class of an error. Messages may be reworded, codes may not: they
+/// are printed by the CLI and meant to be matched on by scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    InvalidInput,
+    NotFound,
+    Conflict,
+    Unauthorized,
+    Unavailable,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidInput => "invalid_input",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::Unavailable => "unavailable",
+            ErrorCode::Internal => "internal",
+        }
+    }
+
+    /// Failures of the bastion itself rather than of the request
+    pub fn is_internal(&self) -> bool {
+        matches!(self, ErrorCode::Unavailable | ErrorCode::Internal)
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub(crate) fn sqlx_code(e: &sqlx::Error) -> ErrorCode {
+    match e {
+        sqlx::Error::RowNotFound => ErrorCode::NotFound,
+        sqlx::Error::Database(db_err) => match db_err.kind() {
+            sqlx::error::ErrorKind::UniqueViolation => ErrorCode::Conflict,
+            sqlx::error::ErrorKind::ForeignKeyViolation
+            | sqlx::error::ErrorKind::NotNullViolation
+            | sqlx::error::ErrorKind::CheckViolation => ErrorCode::InvalidInput,
+            _ => ErrorCode::Internal,
+        },
+        sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_) => {
+            ErrorCode::Unavailable
+        }
+        _ => ErrorCode::Internal,
+    }
+}
+
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::Russh(_) => ErrorCode::Unavailable,
+            Error::RusshKey(_) | Error::RusshForkedKey(_) => ErrorCode::InvalidInput,
+            Error::IO(_) => ErrorCode::Internal,
+            Error::Sqlx(e) => sqlx_code(e),
+            Error::Json(_) | Error::Yaml(_) => ErrorCode::InvalidInput,
+            Error::Config(e) => e.code(),
+            Error::Database(e) => e.code(),
+            Error::Server(e) => e.code(),
+            Error::App(e) => e.code(),
+            Error::Record(e) => e.code(),
+        }
+    }
+
+    /// Text that is safe to show to a user. Internal failures collapse into a
+    /// generic message, their details only go to the log.
+    pub fn user_message(&self) -> String {
+        match self.code() {
+            ErrorCode::Internal => "Internal error".into(),
+            ErrorCode::Unavailable => "Service temporarily unavailable".into(),
+            ErrorCode::Conflict if matches!(self, Error::Sqlx(_)) => "Already exists".into(),
+            ErrorCode::NotFound if matches!(self, Error::Sqlx(_)) => "Not found".into(),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Internal failures are errors in the log, everything else was caused by
+    /// the request and only warrants a warning
+    pub fn log_level(&self) -> log::Level {
+        if self.code().is_internal() {
+            log::Level::Error
+        } else {
+            log::Level::Warn
+        }
+    }
+}
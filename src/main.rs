@@ -31,7 +31,7 @@ async fn main() {
         Err(e) => {
             // Initialize basic logger for error reporting
             env_logger::init();
-            error!("{}", e);
+            error!("[{}] {}", e.code(), e);
             std::process::exit(1);
         }
     };
@@ -48,13 +48,13 @@ async fn main() {
     let mut server = match server::BastionServer::with_config(config).await {
         Ok(server) => server,
         Err(e) => {
-            error!("Server error: {}", e);
+            error!("Server error [{}]: {}", e.code(), e);
             std::process::exit(1);
         }
     };
 
     if let Err(e) = server.run().await {
-        error!("Server error: {}", e);
+        error!("Server error [{}]: {}", e.code(), e);
         std::process::exit(1);
     }
 }
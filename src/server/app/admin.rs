@@ -149,10 +149,10 @@ impl Admin {
                 sender.send(win_raw).map_err(std::io::Error::other)?;
             }
             session.channel_success(channel)?;
+        } else {
+            session.channel_failure(channel)?;
         }
 
-        session.channel_failure(channel)?;
-
         Ok(())
     }
 
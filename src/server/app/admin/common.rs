@@ -6,12 +6,17 @@ pub const CMD_DATABASE: &str = "database";
 pub const CMD_MANAGE: &str = "manage";
 pub const CMD_HELP: &str = "help";
 pub const CMD_FLUSH_PRIVILEGES: &str = "flush_privileges";
+pub const CMD_SYNC_TAG_GROUPS: &str = "sync_tag_groups";
+/// `role_graph <subject|object|action> [dot|tree]`
+pub const CMD_ROLE_GRAPH: &str = "role_graph";
 pub const CMD_QUIT: &str = "quit";
 pub const CMD_EXIT: &str = "exit";
-pub const COMMAND_LIST: [&str; 5] = [
+pub const COMMAND_LIST: [&str; 7] = [
     CMD_DATABASE,
     CMD_MANAGE,
     CMD_FLUSH_PRIVILEGES,
+    CMD_SYNC_TAG_GROUPS,
+    CMD_ROLE_GRAPH,
     CMD_HELP,
     CMD_EXIT,
 ];
@@ -25,7 +30,12 @@ pub const MANAGE_CASBIN_NAMES: &str = "Groups";
 pub const MANAGE_ROLE_HIERARCHY: &str = "Role Hierarchy";
 pub const MANAGE_TARGET_GROUP: &str = "Target Group";
 pub const MANAGE_ACTION_GROUP: &str = "Action Group";
-pub const MANAGE_LIST: [&str; 9] = [
+pub const MANAGE_BANS: &str = "Bans";
+pub const MANAGE_POOLED: &str = "Pooled";
+pub const MANAGE_LIVE: &str = "Live";
+pub const MANAGE_REQUESTS: &str = "Requests";
+pub const MANAGE_POLICY_CHANGES: &str = "Policy Changes";
+pub const MANAGE_LIST: [&str; 14] = [
     MANAGE_USERS,
     MANAGE_TARGETS,
     MANAGE_SECRETS,
@@ -35,4 +45,9 @@ pub const MANAGE_LIST: [&str; 9] = [
     MANAGE_ROLE_HIERARCHY,
     MANAGE_TARGET_GROUP,
     MANAGE_ACTION_GROUP,
+    MANAGE_BANS,
+    MANAGE_POOLED,
+    MANAGE_LIVE,
+    MANAGE_REQUESTS,
+    MANAGE_POLICY_CHANGES,
 ];
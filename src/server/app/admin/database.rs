@@ -4,7 +4,9 @@ use crate::database::common::{
 };
 use crate::database::models::*;
 use crate::error::Error;
-use crate::server::widgets::{AdminTable, DisplayMode, FieldsToArray, TableData as TD};
+use crate::server::widgets::{
+    AdminTable, DisplayMode, FieldsToArray, Named, TableData as TD, common::DATETIME_LENGTH,
+};
 use crossterm::event::{self, KeyCode, KeyModifiers, NoTtyEvent};
 use ratatui::backend::NottyBackend;
 use ratatui::layout::{Constraint, Layout, Rect};
@@ -24,7 +26,6 @@ const INFO_TEXT: [&str; 2] = [
 ];
 
 const LENGTH_UUID: u16 = 36;
-const LENGTH_TIMSTAMP: u16 = 14;
 
 pub(super) fn query_table<B, W: Write>(
     tty: NoTtyEvent,
@@ -141,6 +142,15 @@ where
         self.render_footer(frame, footer_area);
     }
 
+    /// Names shown in place of the ids a row refers to
+    fn names(&self) -> Arc<Names> {
+        Arc::new(
+            self.t_handle
+                .block_on(self.backend.db_service().rule_labels())
+                .unwrap_or_default(),
+        )
+    }
+
     fn refresh_data(&mut self) {
         match TABLE_LIST[self.selected_tab] {
             TABLE_USERS => {
@@ -153,16 +163,16 @@ where
             TABLE_TARGETS => {
                 self.items = TableData::Targets(
                     self.t_handle
-                        .block_on(self.backend.db_repository().list_targets(false))
+                        .block_on(self.backend.db_repository().list_targets(false, &[]))
                         .unwrap_or_default(),
                 );
             }
             TABLE_TARGET_SECRETS => {
-                self.items = TableData::TargetSecrets(
-                    self.t_handle
-                        .block_on(self.backend.db_repository().list_target_secrets(false))
-                        .unwrap_or_default(),
-                );
+                let rows = self
+                    .t_handle
+                    .block_on(self.backend.db_repository().list_target_secrets(false))
+                    .unwrap_or_default();
+                self.items = TableData::TargetSecrets(Named::wrap(rows, &self.names()));
             }
             TABLE_SECRETS => {
                 self.items = TableData::Secrets(
@@ -179,25 +189,25 @@ where
                 );
             }
             TABLE_CASBIN_RULE => {
-                self.items = TableData::CasbinRule(
-                    self.t_handle
-                        .block_on(self.backend.db_repository().list_casbin_rules())
-                        .unwrap_or_default(),
-                );
+                let rows = self
+                    .t_handle
+                    .block_on(self.backend.db_repository().list_casbin_rules())
+                    .unwrap_or_default();
+                self.items = TableData::CasbinRule(Named::wrap(rows, &self.names()));
             }
             TABLE_LOGS => {
-                self.items = TableData::Logs(
-                    self.t_handle
-                        .block_on(self.backend.db_repository().list_logs())
-                        .unwrap_or_default(),
-                );
+                let rows = self
+                    .t_handle
+                    .block_on(self.backend.db_repository().list_logs())
+                    .unwrap_or_default();
+                self.items = TableData::Logs(Named::wrap(rows, &self.names()));
             }
             TABLE_SESSION_RECORDINGS => {
-                self.items = TableData::SessionRecordings(
-                    self.t_handle
-                        .block_on(self.backend.db_repository().list_session_recordings(None))
-                        .unwrap_or_default(),
-                );
+                let rows = self
+                    .t_handle
+                    .block_on(self.backend.db_repository().list_session_recordings(None))
+                    .unwrap_or_default();
+                self.items = TableData::SessionRecordings(Named::wrap(rows, &self.names()));
             }
             _ => {
                 unreachable!()
@@ -254,11 +264,11 @@ enum TableData {
     Users(Vec<User>),
     Targets(Vec<Target>),
     Secrets(Vec<Secret>),
-    TargetSecrets(Vec<TargetSecret>),
+    TargetSecrets(Vec<Named<TargetSecret>>),
     CasbinNames(Vec<CasbinName>),
-    CasbinRule(Vec<CasbinRule>),
-    Logs(Vec<Log>),
-    SessionRecordings(Vec<SessionRecording>),
+    CasbinRule(Vec<Named<CasbinRule>>),
+    Logs(Vec<Named<Log>>),
+    SessionRecordings(Vec<Named<SessionRecording>>),
 }
 
 impl TableData {
@@ -289,7 +299,7 @@ impl TableData {
                     Constraint::Length(15),
                     Constraint::Length(9),
                     Constraint::Length(LENGTH_UUID),
-                    Constraint::Length(LENGTH_TIMSTAMP),
+                    Constraint::Length(DATETIME_LENGTH),
                 ]
             }
             Self::Targets(data) => {
@@ -330,7 +340,7 @@ impl TableData {
                     Constraint::Length(desc_len as u16),
                     Constraint::Length(9), // is_active
                     Constraint::Length(LENGTH_UUID),
-                    Constraint::Length(LENGTH_TIMSTAMP),
+                    Constraint::Length(DATETIME_LENGTH),
                 ]
             }
 
@@ -341,7 +351,7 @@ impl TableData {
                     Constraint::Length(LENGTH_UUID), // secret_id
                     Constraint::Length(9),           // is_active
                     Constraint::Length(LENGTH_UUID), // created_by
-                    Constraint::Length(LENGTH_TIMSTAMP),
+                    Constraint::Length(DATETIME_LENGTH),
                 ]
             }
             Self::Secrets(data) => {
@@ -377,7 +387,7 @@ impl TableData {
                     Constraint::Length(public_key_len as u16),
                     Constraint::Length(9),           // is_active
                     Constraint::Length(LENGTH_UUID), // created_by
-                    Constraint::Length(LENGTH_TIMSTAMP),
+                    Constraint::Length(DATETIME_LENGTH),
                 ]
             }
             Self::CasbinNames(data) => {
@@ -397,7 +407,7 @@ impl TableData {
                     Constraint::Length(name_len as u16),
                     Constraint::Length(9), // is_active
                     Constraint::Length(LENGTH_UUID),
-                    Constraint::Length(LENGTH_TIMSTAMP),
+                    Constraint::Length(DATETIME_LENGTH),
                 ]
             }
             Self::CasbinRule(data) => {
@@ -433,7 +443,7 @@ impl TableData {
                     Constraint::Length(v4_len as u16),
                     Constraint::Length(v5_len as u16),
                     Constraint::Length(LENGTH_UUID),
-                    Constraint::Length(LENGTH_TIMSTAMP),
+                    Constraint::Length(DATETIME_LENGTH),
                 ]
             }
             Self::Logs(data) => {
@@ -451,12 +461,19 @@ impl TableData {
                     .max()
                     .unwrap_or(0)
                     .max(6);
+                let ticket_len = data
+                    .iter()
+                    .filter_map(|v| v.ticket.as_deref())
+                    .map(UnicodeWidthStr::width)
+                    .max()
+                    .unwrap_or(0)
+                    .max(6);
                 vec![
                     Constraint::Length(LENGTH_UUID),
                     Constraint::Length(log_type_len as u16),
                     Constraint::Length(LENGTH_UUID),
                     Constraint::Length(detail_len as u16),
-                    Constraint::Length(LENGTH_TIMSTAMP),
+                    Constraint::Length(DATETIME_LENGTH),
                 ]
             }
             Self::SessionRecordings(data) => {
@@ -480,10 +497,11 @@ impl TableData {
                     Constraint::Length(LENGTH_UUID), // target_id
                     Constraint::Length(LENGTH_UUID), // secret_id
                     Constraint::Length(file_path_len as u16),
-                    Constraint::Length(LENGTH_TIMSTAMP), // started_at
-                    Constraint::Length(LENGTH_TIMSTAMP), // ended_at
+                    Constraint::Length(DATETIME_LENGTH), // started_at
+                    Constraint::Length(DATETIME_LENGTH), // ended_at
                     Constraint::Length(LENGTH_UUID),     // connection_id
                     Constraint::Length(status_len as u16),
+                    Constraint::Length(ticket_len as u16),
                 ]
             }
         }
@@ -636,6 +654,7 @@ impl crate::server::widgets::TableData for TableData {
                     "ended_at",
                     "connection_id",
                     "status",
+                    "ticket",
                 ]
             }
         }
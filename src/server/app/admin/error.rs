@@ -1,3 +1,4 @@
+use crate::error::ErrorCode;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -5,3 +6,11 @@ pub enum AdminError {
     #[error("Target '{target}' already has a bound secret with user '{user}'")]
     DuplicateTargetUser { target: String, user: String },
 }
+
+impl AdminError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AdminError::DuplicateTargetUser { .. } => ErrorCode::Conflict,
+        }
+    }
+}
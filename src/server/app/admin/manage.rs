@@ -1,14 +1,18 @@
 use super::common::*;
 use crate::database::Uuid;
+use crate::database::error::DatabaseError;
+use crate::database::models::access_request::STATUS_PENDING;
 use crate::database::models::*;
 use crate::error::Error;
 use crate::server::HandlerLog;
 use crate::server::casbin::GroupType;
+use crate::server::connection_pool::PooledChannels;
+use crate::server::live_sessions::LiveChannel;
 use crate::server::widgets::{
     AdminTable, Colors, DisplayMode, FieldsToArray, Message, TableData as TD, centered_area,
     common::*, render_confirm_dialog, render_message_popup,
 };
-use ::log::{error, info, warn};
+use ::log::{error, info, log, warn};
 use crossterm::event::{self, KeyCode, KeyEvent, KeyModifiers, NoTtyEvent};
 use ratatui::backend::NottyBackend;
 use ratatui::layout::{Constraint, Layout, Rect};
@@ -23,10 +27,12 @@ use style::palette::tailwind;
 use tokio::runtime::Handle;
 use unicode_width::UnicodeWidthStr;
 
+mod ban;
 mod bind;
 mod casbin_group;
 mod casbin_name;
 mod grant_role;
+mod live;
 mod permission;
 mod secret;
 mod target;
@@ -38,6 +44,36 @@ const HELP_TEXT: [&str; 2] = [
     "(Tab) next tab | (Shift Tab) previous tab | (+/-) zoom in/out | (PgUp/PgDn) page up/down",
 ];
 
+const CASBIN_NAME_HELP_TEXT: [&str; 2] = [
+    "(a) add | (e) edit | (d) delete | (u) name unnamed ids | (Esc) quit | (↑↓←→) move around",
+    "(Tab) next tab | (Shift Tab) previous tab | (+/-) zoom in/out | (PgUp/PgDn) page up/down",
+];
+
+const BAN_HELP_TEXT: [&str; 2] = [
+    "(a) add | (d) lift ban | (Esc) quit | (↑↓←→) move around",
+    "(Tab) next tab | (Shift Tab) previous tab | (+/-) zoom in/out | (PgUp/PgDn) page up/down",
+];
+
+const REQUEST_HELP_TEXT: [&str; 2] = [
+    "(y) approve | (d) deny | (Esc) quit | (↑↓←→) move around",
+    "(Tab) next tab | (Shift Tab) previous tab | (+/-) zoom in/out | (PgUp/PgDn) page up/down",
+];
+
+const POLICY_CHANGE_HELP_TEXT: [&str; 2] = [
+    "(Esc) quit | (↑↓←→) move around",
+    "(Tab) next tab | (Shift Tab) previous tab | (+/-) zoom in/out | (PgUp/PgDn) page up/down",
+];
+
+const POOLED_HELP_TEXT: [&str; 2] = [
+    "(d) revoke the user's channels on this handle | (Esc) quit | (↑↓←→) move around",
+    "(Tab) next tab | (Shift Tab) previous tab | (+/-) zoom in/out | (PgUp/PgDn) page up/down",
+];
+
+const LIVE_HELP_TEXT: [&str; 2] = [
+    "(w) watch | (W) watch and tell the user | (x) kill connection | (Esc) quit | (↑↓←→) move around",
+    "(Tab) next tab | (Shift Tab) previous tab | (+/-) zoom in/out | (PgUp/PgDn) page up/down",
+];
+
 const USER_HELP_TEXT: [&str; 2] = [
     "(a) add | (e) edit | (d) delete | (r) grant role | (Esc) quit | (↑↓←→) move around",
     "(Tab) next tab | (Shift Tab) previous tab | (+/-) zoom in/out | (PgUp/PgDn) page up/down",
@@ -89,6 +125,10 @@ enum Popup {
     Add,
     Edit,
     Delete(usize),
+    /// Row index and the number of active bindings blocking a plain delete
+    ForceDelete(usize, i64),
+    /// Id of a row that someone else changed while it was being edited
+    StaleRow(Uuid),
 }
 
 #[repr(usize)]
@@ -103,6 +143,11 @@ enum SelectedTab {
     RoleHierarchy = 6,
     TargetGroup = 7,
     ActionGroup = 8,
+    Bans = 9,
+    Pooled = 10,
+    Live = 11,
+    AccessRequests = 12,
+    PolicyChanges = 13,
 }
 
 impl fmt::Display for SelectedTab {
@@ -117,6 +162,11 @@ impl fmt::Display for SelectedTab {
             SelectedTab::RoleHierarchy => write!(f, "{}", MANAGE_ROLE_HIERARCHY),
             SelectedTab::TargetGroup => write!(f, "{}", MANAGE_TARGET_GROUP),
             SelectedTab::ActionGroup => write!(f, "{}", MANAGE_ACTION_GROUP),
+            SelectedTab::Bans => write!(f, "{}", MANAGE_BANS),
+            SelectedTab::Pooled => write!(f, "{}", MANAGE_POOLED),
+            SelectedTab::Live => write!(f, "{}", MANAGE_LIVE),
+            SelectedTab::AccessRequests => write!(f, "{}", MANAGE_REQUESTS),
+            SelectedTab::PolicyChanges => write!(f, "{}", MANAGE_POLICY_CHANGES),
         }
     }
 }
@@ -132,13 +182,18 @@ impl SelectedTab {
             SelectedTab::CasbinNames => SelectedTab::RoleHierarchy,
             SelectedTab::RoleHierarchy => SelectedTab::TargetGroup,
             SelectedTab::TargetGroup => SelectedTab::ActionGroup,
-            SelectedTab::ActionGroup => SelectedTab::Users,
+            SelectedTab::ActionGroup => SelectedTab::Bans,
+            SelectedTab::Bans => SelectedTab::Pooled,
+            SelectedTab::Pooled => SelectedTab::Live,
+            SelectedTab::Live => SelectedTab::AccessRequests,
+            SelectedTab::AccessRequests => SelectedTab::PolicyChanges,
+            SelectedTab::PolicyChanges => SelectedTab::Users,
         }
     }
 
     fn previous(&self) -> Self {
         match self {
-            SelectedTab::Users => SelectedTab::ActionGroup,
+            SelectedTab::Users => SelectedTab::PolicyChanges,
             SelectedTab::Targets => SelectedTab::Users,
             SelectedTab::Secrets => SelectedTab::Targets,
             SelectedTab::Bind => SelectedTab::Secrets,
@@ -147,6 +202,11 @@ impl SelectedTab {
             SelectedTab::RoleHierarchy => SelectedTab::CasbinNames,
             SelectedTab::TargetGroup => SelectedTab::RoleHierarchy,
             SelectedTab::ActionGroup => SelectedTab::TargetGroup,
+            SelectedTab::Bans => SelectedTab::ActionGroup,
+            SelectedTab::Pooled => SelectedTab::Bans,
+            SelectedTab::Live => SelectedTab::Pooled,
+            SelectedTab::AccessRequests => SelectedTab::Live,
+            SelectedTab::PolicyChanges => SelectedTab::AccessRequests,
         }
     }
 }
@@ -229,9 +289,11 @@ where
                     Editor::User(Box::new(user::UserEditor::new(User::new(self.admin_id))))
             }
             SelectedTab::Targets => {
-                self.editor = Editor::Target(Box::new(target::TargetEditor::new(Target::new(
-                    self.admin_id,
-                ))))
+                self.editor = Editor::Target(Box::new(target::TargetEditor::new(
+                    Target::new(self.admin_id),
+                    &[],
+                    String::new(),
+                )))
             }
             SelectedTab::Secrets => {
                 self.editor = Editor::Secret(Box::new(secret::SecretEditor::new(Secret::new(
@@ -252,6 +314,13 @@ where
                     CasbinName::new(String::new(), String::new(), true, self.admin_id),
                 )))
             }
+            SelectedTab::Bans => {
+                self.editor = Editor::Ban(Box::new(ban::BanEditor::new(self.admin_id)))
+            }
+            SelectedTab::Pooled => unreachable!(),
+            SelectedTab::Live => unreachable!(),
+            SelectedTab::AccessRequests => unreachable!(),
+            SelectedTab::PolicyChanges => unreachable!(),
             SelectedTab::Bind => unreachable!(),
             SelectedTab::RoleHierarchy => unreachable!(),
             SelectedTab::TargetGroup => unreachable!(),
@@ -279,6 +348,137 @@ where
         true
     }
 
+    /// Gives a placeholder name to every id used in rules that nothing
+    /// names yet, so policies read as names instead of bare UUIDs
+    fn name_unnamed_ids(&mut self) {
+        let result = self.t_handle.block_on(
+            self.backend
+                .db_service()
+                .name_unnamed_rule_ids(Some(&self.admin_id)),
+        );
+        match result {
+            Ok(0) => {
+                self.message = Some(Message::Info(vec![
+                    "Every id used in rules already has a name".into(),
+                ]));
+            }
+            Ok(n) => {
+                info!(
+                    "[{}] Named {} unnamed rule ids by admin_id={}",
+                    self.handler_id, n, self.admin_id
+                );
+                self.t_handle.block_on((self.log)(
+                    LOG_TYPE.into(),
+                    format!("Named {} ids referenced by rules", n),
+                ));
+                self.message = Some(Message::Success(vec![format!(
+                    "Named {} ids, rename them with (e)",
+                    n
+                )]));
+                self.refresh_data();
+            }
+            Err(e) => {
+                self.message = Some(Message::Error(vec![e.user_message()]));
+                warn!(
+                    "[{}] Naming unnamed rule ids failed by admin_id={}: {}",
+                    self.handler_id, self.admin_id, e
+                );
+            }
+        }
+    }
+
+    /// Starts the access asked for by the selected request
+    fn approve_request(&mut self) {
+        let idx = self.table.state.selected().unwrap();
+        let Some(r) = self.items.get_request(idx) else {
+            return;
+        };
+        let result = self.t_handle.block_on(
+            self.backend
+                .db_service()
+                .approve_access_request(&r.id, &self.admin_id),
+        );
+        if let Err(e) = result {
+            self.message = Some(Message::Error(vec![e.user_message()]));
+            warn!(
+                "[{}] Approve access request '{}({})' failed by admin_id={}: {}",
+                self.handler_id, r.target_secret, r.id, self.admin_id, e
+            );
+            return;
+        }
+
+        info!(
+            "[{}] Access of '{}' to '{}({})' approved by admin_id={}",
+            self.handler_id, r.username, r.target_secret, r.id, self.admin_id
+        );
+        self.t_handle.block_on((self.log)(
+            LOG_TYPE.into(),
+            format!(
+                "Access of '{}' to '{}({})' approved for {}",
+                r.username,
+                r.target_secret,
+                r.id,
+                r.print_duration()
+            ),
+        ));
+        self.message = Some(Message::Success(vec!["Request approved".into()]));
+        self.refresh_data();
+    }
+
+    /// Follows the output of the selected live channel, read-only. With
+    /// `tell`, its user is shown that an admin is watching.
+    fn watch_live<W: Write>(
+        &mut self,
+        idx: usize,
+        tell: bool,
+        tty: &NoTtyEvent,
+        terminal: &mut Terminal<NottyBackend<W>>,
+    ) -> Result<(), Error> {
+        let Some(l) = self.items.get_live(idx) else {
+            return Ok(());
+        };
+        let notice = tell.then(|| "an admin is watching this session".to_string());
+        let Some(watch) = self.backend.live_sessions().watch(l.id, notice) else {
+            self.message = Some(Message::Error(vec!["The session has ended".into()]));
+            self.refresh_data();
+            return Ok(());
+        };
+
+        info!(
+            "[{}] Session '{}' of '{}({})' on {} watched by admin_id={}, told: {}",
+            self.handler_id, l.session_id, l.username, l.user_id, l.target, self.admin_id, tell
+        );
+        self.t_handle.block_on((self.log)(
+            LOG_TYPE.into(),
+            format!(
+                "Watched session '{}' of '{}({})' on {}",
+                l.session_id, l.username, l.user_id, l.target
+            ),
+        ));
+        let title = format!(" {} on {} ({}) ", l.username, l.target, l.request);
+        live::watch(tty, terminal, watch, &title, &self.table.colors)?;
+        self.refresh_data();
+        Ok(())
+    }
+
+    /// Asks why the connection of the selected live channel is terminated
+    fn kill_form(&mut self) -> bool {
+        self.popup = Popup::Edit;
+        let idx = self.table.state.selected().unwrap();
+        let Some(l) = self.items.get_live(idx) else {
+            return false;
+        };
+        self.editor = Editor::Kill(Box::new(live::KillEditor::new(l)));
+        true
+    }
+
+    /// Hands ban changes to the server, which only rereads them once a minute
+    fn reload_bans(&self) {
+        if let Err(e) = self.t_handle.block_on(self.backend.load_bans()) {
+            error!("[{}] Failed to reload bans: {}", self.handler_id, e);
+        }
+    }
+
     fn edit_form(&mut self) -> bool {
         self.popup = Popup::Edit;
 
@@ -301,7 +501,27 @@ where
                         return false;
                     }
                 };
-                self.editor = Editor::Target(Box::new(target::TargetEditor::new(target)));
+                let tags = self
+                    .t_handle
+                    .block_on(
+                        self.backend
+                            .db_repository()
+                            .list_tags_for_target(&target.id),
+                    )
+                    .unwrap_or_default();
+                let via_name = target
+                    .via_target_id
+                    .and_then(|id| {
+                        self.t_handle
+                            .block_on(self.backend.db_repository().get_target_by_id(&id, false))
+                            .ok()
+                            .flatten()
+                    })
+                    .map(|t| t.name)
+                    .unwrap_or_default();
+                self.editor = Editor::Target(Box::new(target::TargetEditor::new(
+                    target, &tags, via_name,
+                )));
             }
             SelectedTab::Secrets => {
                 let idx = self.table.state.selected().unwrap();
@@ -338,6 +558,11 @@ where
                 self.editor =
                     Editor::CasbinName(Box::new(casbin_name::CasbinNameEditor::new(casbin_name)));
             }
+            SelectedTab::Bans => unreachable!(),
+            SelectedTab::Pooled => unreachable!(),
+            SelectedTab::Live => unreachable!(),
+            SelectedTab::AccessRequests => unreachable!(),
+            SelectedTab::PolicyChanges => unreachable!(),
             SelectedTab::Bind => unreachable!(),
             SelectedTab::RoleHierarchy => unreachable!(),
             SelectedTab::TargetGroup => unreachable!(),
@@ -347,7 +572,25 @@ where
         true
     }
 
-    fn do_delete(&mut self, idx: usize) {
+    /// Reopen the editor on the current copy of a row whose update was rejected
+    fn reload_form(&mut self, id: Uuid) {
+        self.clear_form();
+        self.refresh_data();
+        match self.items.position(&id) {
+            Some(idx) => {
+                self.table.state.select(Some(idx));
+                if !self.edit_form() {
+                    self.clear_form();
+                    self.restore_color();
+                }
+            }
+            None => {
+                self.message = Some(Message::Error(vec!["Row no longer exists".into()]));
+            }
+        }
+    }
+
+    fn do_delete(&mut self, idx: usize, force: bool) {
         self.popup = Popup::None;
         match self.selected_tab {
             SelectedTab::Users => {
@@ -357,7 +600,7 @@ where
                         .block_on(self.backend.db_repository().delete_user(&u.id));
 
                     if let Err(e) = result {
-                        self.message = Some(Message::Error(vec!["Internal error".into()]));
+                        self.message = Some(Message::Error(vec![e.user_message()]));
                         warn!(
                             "[{}] Delete user '{}({})' failed by admin_id={}: {}",
                             self.handler_id, u.username, u.id, self.admin_id, e
@@ -381,10 +624,14 @@ where
                 if let Some(t) = self.items.get_target(idx) {
                     let result = self
                         .t_handle
-                        .block_on(self.backend.db_repository().delete_target(&t.id));
+                        .block_on(self.backend.db_repository().delete_target(&t.id, force));
 
+                    if let Err(Error::Database(DatabaseError::ActiveBindings { count })) = result {
+                        self.popup = Popup::ForceDelete(idx, count);
+                        return;
+                    }
                     if let Err(e) = result {
-                        self.message = Some(Message::Error(vec!["Internal error".into()]));
+                        self.message = Some(Message::Error(vec![e.user_message()]));
                         warn!(
                             "[{}] Delete target '{}({})' failed by admin_id={}: {}",
                             self.handler_id, t.name, t.id, self.admin_id, e
@@ -408,10 +655,14 @@ where
                 if let Some(s) = self.items.get_secret(idx) {
                     let result = self
                         .t_handle
-                        .block_on(self.backend.db_repository().delete_secret(&s.id));
+                        .block_on(self.backend.db_repository().delete_secret(&s.id, force));
 
+                    if let Err(Error::Database(DatabaseError::ActiveBindings { count })) = result {
+                        self.popup = Popup::ForceDelete(idx, count);
+                        return;
+                    }
                     if let Err(e) = result {
-                        self.message = Some(Message::Error(vec!["Internal error".into()]));
+                        self.message = Some(Message::Error(vec![e.user_message()]));
                         warn!(
                             "[{}] Delete secret '{}({})' failed by admin_id={}: {}",
                             self.handler_id, s.name, s.id, self.admin_id, e
@@ -433,12 +684,14 @@ where
             }
             SelectedTab::Permissions => {
                 if let Some(p) = self.items.get_permission(idx) {
-                    let result = self
-                        .t_handle
-                        .block_on(self.backend.db_repository().delete_casbin_rule(&p.rule.id));
+                    let result = self.t_handle.block_on(
+                        self.backend
+                            .db_service()
+                            .delete_casbin_rule(&p.rule.id, &self.admin_id),
+                    );
 
                     if let Err(e) = result {
-                        self.message = Some(Message::Error(vec!["Internal error".into()]));
+                        self.message = Some(Message::Error(vec![e.user_message()]));
                         warn!(
                             "[{}] Delete permission '({})' failed by admin_id={}: {}",
                             self.handler_id, p.rule.id, self.admin_id, e
@@ -465,7 +718,7 @@ where
                         .block_on(self.backend.db_repository().delete_casbin_name(&c.id));
 
                     if let Err(e) = result {
-                        self.message = Some(Message::Error(vec!["Internal error".into()]));
+                        self.message = Some(Message::Error(vec![e.user_message()]));
                         warn!(
                             "[{}] Delete casbin name '{}({})' failed by admin_id={}: {}",
                             self.handler_id, c.name, c.id, self.admin_id, e
@@ -485,6 +738,93 @@ where
                     self.refresh_data();
                 }
             }
+            SelectedTab::Bans => {
+                if let Some(b) = self.items.get_ban(idx) {
+                    let result = self
+                        .t_handle
+                        .block_on(self.backend.db_repository().delete_banned_ip(&b.id));
+
+                    if let Err(e) = result {
+                        self.message = Some(Message::Error(vec![e.user_message()]));
+                        warn!(
+                            "[{}] Lift ban of '{}({})' failed by admin_id={}: {}",
+                            self.handler_id, b.network, b.id, self.admin_id, e
+                        );
+                        return;
+                    }
+
+                    info!(
+                        "[{}] Ban of '{}({})' lifted by admin_id={}",
+                        self.handler_id, b.network, b.id, self.admin_id
+                    );
+                    self.t_handle.block_on((self.log)(
+                        LOG_TYPE.into(),
+                        format!("Ban of '{}({})' lifted", b.network, b.id),
+                    ));
+                    self.reload_bans();
+                    self.message = Some(Message::Success(vec!["Ban lifted".into()]));
+                    self.refresh_data();
+                }
+            }
+            SelectedTab::Pooled => {
+                if let Some(p) = self.items.get_pooled(idx) {
+                    let revoked = self
+                        .backend
+                        .pool_leases()
+                        .map_or(0, |l| l.revoke(&p.pool_key, &p.user_id));
+
+                    info!(
+                        "[{}] {} channel(s) of '{}({})' on {} revoked by admin_id={}",
+                        self.handler_id, revoked, p.username, p.user_id, p.target, self.admin_id
+                    );
+                    self.t_handle.block_on((self.log)(
+                        LOG_TYPE.into(),
+                        format!(
+                            "{} channel(s) of '{}({})' on {} revoked",
+                            revoked, p.username, p.user_id, p.target
+                        ),
+                    ));
+                    self.message = Some(Message::Success(vec![format!(
+                        "{} channel(s) revoked",
+                        revoked
+                    )]));
+                    self.refresh_data();
+                }
+            }
+            SelectedTab::Live => unreachable!(),
+            SelectedTab::AccessRequests => {
+                if let Some(r) = self.items.get_request(idx) {
+                    let result = self.t_handle.block_on(
+                        self.backend
+                            .db_service()
+                            .deny_access_request(&r.id, &self.admin_id),
+                    );
+
+                    if let Err(e) = result {
+                        self.message = Some(Message::Error(vec![e.user_message()]));
+                        warn!(
+                            "[{}] Deny access request '{}({})' failed by admin_id={}: {}",
+                            self.handler_id, r.target_secret, r.id, self.admin_id, e
+                        );
+                        return;
+                    }
+
+                    info!(
+                        "[{}] Access of '{}' to '{}({})' denied by admin_id={}",
+                        self.handler_id, r.username, r.target_secret, r.id, self.admin_id
+                    );
+                    self.t_handle.block_on((self.log)(
+                        LOG_TYPE.into(),
+                        format!(
+                            "Access of '{}' to '{}({})' denied",
+                            r.username, r.target_secret, r.id
+                        ),
+                    ));
+                    self.message = Some(Message::Success(vec!["Request denied".into()]));
+                    self.refresh_data();
+                }
+            }
+            SelectedTab::PolicyChanges => unreachable!(),
             SelectedTab::Bind => unreachable!(),
             SelectedTab::RoleHierarchy => unreachable!(),
             SelectedTab::TargetGroup => unreachable!(),
@@ -519,6 +859,28 @@ where
                     return true;
                 }
             }
+            SelectedTab::Bans => {
+                if self.items.get_ban(idx).is_some() {
+                    return true;
+                }
+            }
+            SelectedTab::Pooled => {
+                if self.items.get_pooled(idx).is_some() {
+                    return true;
+                }
+            }
+            SelectedTab::Live => {}
+            SelectedTab::AccessRequests => {
+                if self
+                    .items
+                    .get_request(idx)
+                    .is_some_and(|r| r.status == STATUS_PENDING)
+                {
+                    return true;
+                }
+            }
+            // The audit trail can't be edited
+            SelectedTab::PolicyChanges => {}
             SelectedTab::Bind => unreachable!(),
             SelectedTab::RoleHierarchy => unreachable!(),
             SelectedTab::TargetGroup => unreachable!(),
@@ -607,11 +969,28 @@ where
                                     self.clear_form();
                                 }
                             }
-                            KeyCode::Char('a') => {
+                            KeyCode::Char('a')
+                                if !matches!(
+                                    self.selected_tab,
+                                    SelectedTab::Pooled
+                                        | SelectedTab::Live
+                                        | SelectedTab::AccessRequests
+                                        | SelectedTab::PolicyChanges
+                                ) =>
+                            {
                                 self.table.colors.gray();
                                 self.add_form()
                             }
-                            KeyCode::Char('e') => {
+                            KeyCode::Char('e')
+                                if !matches!(
+                                    self.selected_tab,
+                                    SelectedTab::Bans
+                                        | SelectedTab::Pooled
+                                        | SelectedTab::Live
+                                        | SelectedTab::AccessRequests
+                                        | SelectedTab::PolicyChanges
+                                ) =>
+                            {
                                 self.table.colors.gray();
                                 if !self.edit_form() {
                                     self.clear_form();
@@ -623,18 +1002,49 @@ where
                                     self.clear_form();
                                 }
                             }
+                            KeyCode::Char('u') if self.selected_tab == SelectedTab::CasbinNames => {
+                                self.name_unnamed_ids();
+                            }
+                            KeyCode::Char('y')
+                                if self.selected_tab == SelectedTab::AccessRequests =>
+                            {
+                                self.approve_request();
+                            }
+                            KeyCode::Char(c @ ('w' | 'W'))
+                                if self.selected_tab == SelectedTab::Live =>
+                            {
+                                let idx = self.table.state.selected().unwrap();
+                                self.watch_live(idx, c == 'W', &tty, terminal)?;
+                            }
+                            KeyCode::Char('x') if self.selected_tab == SelectedTab::Live => {
+                                self.table.colors.gray();
+                                if !self.kill_form() {
+                                    self.clear_form();
+                                }
+                            }
                             _ => {}
                         }
                     }
                     Popup::Add | Popup::Edit => {
                         if let Err(e) = self.do_edit(key) {
-                            self.message = Some(Message::Error(vec!["Internal error".into()]));
-                            warn!("[{}] Failed to edit: {}", self.handler_id, e);
+                            self.message = Some(Message::Error(vec![e.user_message()]));
+                            log!(e.log_level(), "[{}] Failed to edit: {}", self.handler_id, e);
                         }
                     }
                     Popup::Delete(i) => match key.code {
                         KeyCode::Char('y') | KeyCode::Char('Y') => {
-                            self.do_delete(i);
+                            self.do_delete(i, false);
+                        }
+                        KeyCode::Char('n') | KeyCode::Char('N') => {
+                            self.popup = Popup::None;
+                            self.clear_form();
+                            self.restore_color();
+                        }
+                        _ => {}
+                    },
+                    Popup::ForceDelete(i, _) => match key.code {
+                        KeyCode::Char('y') | KeyCode::Char('Y') => {
+                            self.do_delete(i, true);
                         }
                         KeyCode::Char('n') | KeyCode::Char('N') => {
                             self.popup = Popup::None;
@@ -643,6 +1053,18 @@ where
                         }
                         _ => {}
                     },
+                    Popup::StaleRow(id) => match key.code {
+                        KeyCode::Char('y') | KeyCode::Char('Y') => {
+                            self.reload_form(id);
+                        }
+                        KeyCode::Char('n') | KeyCode::Char('N') => {
+                            self.popup = Popup::None;
+                            self.clear_form();
+                            self.refresh_data();
+                            self.restore_color();
+                        }
+                        _ => {}
+                    },
                 }
             }
             if let Some(paste) = event.as_paste_event() {
@@ -659,6 +1081,12 @@ where
                     Editor::CasbinName(ref mut e) => {
                         let _ = e.as_mut().handle_paste_event(paste);
                     }
+                    Editor::Ban(ref mut e) => {
+                        let _ = e.as_mut().handle_paste_event(paste);
+                    }
+                    Editor::Kill(ref mut e) => {
+                        let _ = e.as_mut().handle_paste_event(paste);
+                    }
                     Editor::GrantRole(_) => {}
                     Editor::Permission(_) => {}
                     Editor::Bind(_) => unreachable!(),
@@ -681,6 +1109,14 @@ where
                             password = crate::common::gen_password(12);
                             self.backend.set_password(&mut user, &password)?;
                         }
+                        let mut totp_secret = None;
+                        if e.totp && !user.has_totp() {
+                            let secret = crate::server::totp::generate_secret();
+                            self.backend.set_totp_secret(&mut user, Some(&secret))?;
+                            totp_secret = Some(secret);
+                        } else if !e.totp && user.has_totp() {
+                            self.backend.set_totp_secret(&mut user, None)?;
+                        }
 
                         let (action, result) = match self.popup {
                             Popup::Add => (
@@ -696,14 +1132,22 @@ where
                             _ => unreachable!(),
                         };
 
+                        if let Err(Error::Database(DatabaseError::StaleRow { .. })) = result {
+                            warn!(
+                                "[{}] User '{}({})' changed before admin_id={} saved it",
+                                self.handler_id, user.username, user.id, self.admin_id
+                            );
+                            self.popup = Popup::StaleRow(user.id);
+                            return Ok(());
+                        }
                         if let Err(ref err) = result {
                             let msg = match err {
                                 Error::Sqlx(sqlx::Error::Database(db_err))
                                     if db_err.kind() == sqlx::error::ErrorKind::UniqueViolation =>
                                 {
-                                    "Username already exists"
+                                    "Username already exists".to_string()
                                 }
-                                _ => "Internal error",
+                                _ => err.user_message(),
                             };
                             warn!(
                                 "[{}] Failed to {} user '{}({})': {}",
@@ -725,6 +1169,9 @@ where
                         if !password.is_empty() {
                             msg.push(format!("New password: {}", password));
                         }
+                        if let Some(secret) = totp_secret {
+                            msg.push(format!("TOTP key: {}", secret));
+                        }
                         self.message = Some(Message::Success(msg));
                     }
 
@@ -736,7 +1183,18 @@ where
             Editor::Target(ref mut e) => {
                 if e.as_mut().handle_key_event(key.code, key.modifiers) {
                     if !e.form.show_cancel_confirmation {
-                        let target = e.target.to_owned();
+                        let mut target = e.target.to_owned();
+                        match self.t_handle.block_on(target::via_target_id(
+                            self.backend.db_repository(),
+                            &target,
+                            &e.via_name,
+                        )) {
+                            Ok(via) => target.via_target_id = via,
+                            Err(err) => {
+                                self.message = Some(Message::Error(vec![err.user_message()]));
+                                return Ok(());
+                            }
+                        }
 
                         let (action, result) = match self.popup {
                             Popup::Add => (
@@ -752,14 +1210,22 @@ where
                             _ => unreachable!(),
                         };
 
+                        if let Err(Error::Database(DatabaseError::StaleRow { .. })) = result {
+                            warn!(
+                                "[{}] Target '{}({})' changed before admin_id={} saved it",
+                                self.handler_id, target.name, target.id, self.admin_id
+                            );
+                            self.popup = Popup::StaleRow(target.id);
+                            return Ok(());
+                        }
                         if let Err(ref err) = result {
                             let msg = match err {
                                 Error::Sqlx(sqlx::Error::Database(db_err))
                                     if db_err.kind() == sqlx::error::ErrorKind::UniqueViolation =>
                                 {
-                                    "Target already exists"
+                                    "Target already exists".to_string()
                                 }
-                                _ => "Internal error",
+                                _ => err.user_message(),
                             };
                             warn!(
                                 "[{}] Failed to {} target '{}({})': {}",
@@ -769,6 +1235,32 @@ where
                             return Ok(());
                         }
 
+                        let tags: Vec<TargetTag> = e
+                            .tags
+                            .iter()
+                            .map(|f| TargetTag::new(target.id, f.clone(), self.admin_id))
+                            .collect();
+                        let repo = self.backend.db_repository();
+                        if let Err(err) = self.t_handle.block_on(async {
+                            repo.set_target_tags(&target.id, &tags).await?;
+                            // A new tag gets its group right away
+                            repo.sync_tag_groups(&self.admin_id).await
+                        }) {
+                            warn!(
+                                "[{}] Failed to set tags of target '{}({})': {}",
+                                self.handler_id, target.name, target.id, err
+                            );
+                            self.message = Some(Message::Error(vec![err.user_message()]));
+                            return Ok(());
+                        }
+                        // Tag groups take their members from the tags
+                        if let Err(err) = self.t_handle.block_on(self.backend.load_role_manager()) {
+                            warn!(
+                                "[{}] Failed to reload role manager after tagging '{}({})': {}",
+                                self.handler_id, target.name, target.id, err
+                            );
+                        }
+
                         info!(
                             "[{}] Target '{}({})' {} by admin_id={}",
                             self.handler_id, target.name, target.id, action, self.admin_id
@@ -785,6 +1277,24 @@ where
                     self.refresh_data();
                     self.restore_color();
                 }
+                if let Some(address) = e.take_scan_request() {
+                    let scanned = match address {
+                        Ok((hostname, port)) => self
+                            .t_handle
+                            .block_on(self.backend.scan_host_keys(&hostname, port))
+                            .inspect_err(|err| {
+                                warn!(
+                                    "[{}] Host key scan of {}:{} failed: {}",
+                                    self.handler_id, hostname, port, err
+                                )
+                            }),
+                        Err(err) => Err(err),
+                    };
+                    match scanned {
+                        Ok(keys) => e.set_scanned_keys(keys),
+                        Err(err) => e.form.set_save_error(vec![err.to_string()]),
+                    }
+                }
             }
             Editor::Secret(ref mut e) => {
                 if e.as_mut().handle_key_event(key.code, key.modifiers) {
@@ -809,14 +1319,22 @@ where
                             ),
                             _ => unreachable!(),
                         };
+                        if let Err(Error::Database(DatabaseError::StaleRow { .. })) = result {
+                            warn!(
+                                "[{}] Secret '{}({})' changed before admin_id={} saved it",
+                                self.handler_id, secret.name, secret.id, self.admin_id
+                            );
+                            self.popup = Popup::StaleRow(secret.id);
+                            return Ok(());
+                        }
                         if let Err(ref err) = result {
                             let msg = match err {
                                 Error::Sqlx(sqlx::Error::Database(db_err))
                                     if db_err.kind() == sqlx::error::ErrorKind::UniqueViolation =>
                                 {
-                                    "Secret already exists"
+                                    "Secret already exists".to_string()
                                 }
-                                _ => "Internal error",
+                                _ => err.user_message(),
                             };
                             warn!(
                                 "[{}] Failed to {} secret '{}({})': {}",
@@ -850,13 +1368,13 @@ where
                             Popup::Add => (
                                 "added",
                                 self.t_handle.block_on(
-                                    self.backend.db_repository().create_casbin_rule(&perm.rule),
+                                    self.backend.db_service().create_casbin_rule(&perm.rule),
                                 ),
                             ),
                             Popup::Edit => (
                                 "updated",
                                 self.t_handle.block_on(
-                                    self.backend.db_repository().update_casbin_rule(&perm.rule),
+                                    self.backend.db_service().update_casbin_rule(&perm.rule),
                                 ),
                             ),
                             _ => unreachable!(),
@@ -866,9 +1384,9 @@ where
                                 Error::Sqlx(sqlx::Error::Database(db_err))
                                     if db_err.kind() == sqlx::error::ErrorKind::UniqueViolation =>
                                 {
-                                    "Permission already exists"
+                                    "Permission already exists".to_string()
                                 }
-                                _ => "Internal error",
+                                _ => err.user_message(),
                             };
                             warn!(
                                 "[{}] Failed to {} permission '({})': {}",
@@ -930,9 +1448,9 @@ where
                                 Error::Sqlx(sqlx::Error::Database(db_err))
                                     if db_err.kind() == sqlx::error::ErrorKind::UniqueViolation =>
                                 {
-                                    "Group already exists"
+                                    "Group already exists".to_string()
                                 }
-                                _ => "Internal error",
+                                _ => err.user_message(),
                             };
                             warn!(
                                 "[{}] Failed to {} casbin name '{}({})': {}",
@@ -965,6 +1483,86 @@ where
                     self.restore_color();
                 }
             }
+            Editor::Ban(ref mut e) => {
+                if e.as_mut().handle_key_event(key.code, key.modifiers) {
+                    if !e.form.show_cancel_confirmation
+                        && let Some(ban) = e.ban.take()
+                    {
+                        if let Err(err) = self
+                            .t_handle
+                            .block_on(self.backend.db_repository().create_banned_ip(&ban))
+                        {
+                            warn!(
+                                "[{}] Failed to ban '{}({})': {}",
+                                self.handler_id, ban.network, ban.id, err
+                            );
+                            self.message = Some(Message::Error(vec![err.user_message()]));
+                            return Ok(());
+                        }
+
+                        info!(
+                            "[{}] '{}({})' banned until {} by admin_id={}",
+                            self.handler_id,
+                            ban.network,
+                            ban.id,
+                            ban.print_expires_at(),
+                            self.admin_id
+                        );
+                        self.t_handle.block_on((self.log)(
+                            LOG_TYPE.into(),
+                            format!(
+                                "'{}({})' banned until {}",
+                                ban.network,
+                                ban.id,
+                                ban.print_expires_at()
+                            ),
+                        ));
+                        self.reload_bans();
+                        self.message = Some(Message::Success(vec!["Ban added".into()]));
+                    }
+                    self.clear_form();
+                    self.refresh_data();
+                    self.restore_color();
+                }
+            }
+            Editor::Kill(ref mut e) => {
+                if e.as_mut().handle_key_event(key.code, key.modifiers) {
+                    if !e.form.show_cancel_confirmation
+                        && let Some(reason) = e.reason.take()
+                    {
+                        let l = &e.live;
+                        let killed = self
+                            .backend
+                            .live_sessions()
+                            .kill(&l.connection_id, &reason);
+
+                        info!(
+                            "[{}] {} channel(s) of connection '{}' of '{}({})' terminated by admin_id={}: {}",
+                            self.handler_id,
+                            killed,
+                            l.connection_id,
+                            l.username,
+                            l.user_id,
+                            self.admin_id,
+                            reason
+                        );
+                        self.t_handle.block_on((self.log)(
+                            LOG_TYPE.into(),
+                            format!(
+                                "{} channel(s) of connection '{}' of '{}({})' terminated: {}",
+                                killed, l.connection_id, l.username, l.user_id, reason
+                            ),
+                        ));
+                        self.message = Some(Message::Success(vec![format!(
+                            "{} channel(s) terminated",
+                            killed
+                        )]));
+                    }
+                    self.clear_form();
+                    self.refresh_data();
+                    self.restore_color();
+                }
+            }
             Editor::Bind(_) => unreachable!(),
             Editor::CasbinGroup(_) => unreachable!(),
             Editor::None => unreachable!(),
@@ -1023,7 +1621,12 @@ where
             | SelectedTab::Targets
             | SelectedTab::Secrets
             | SelectedTab::Permissions
-            | SelectedTab::CasbinNames => {
+            | SelectedTab::CasbinNames
+            | SelectedTab::Bans
+            | SelectedTab::Pooled
+            | SelectedTab::Live
+            | SelectedTab::AccessRequests
+            | SelectedTab::PolicyChanges => {
                 self.table.render(
                     frame.buffer_mut(),
                     table_area,
@@ -1041,6 +1644,8 @@ where
     }
 
     fn refresh_data(&mut self) {
+        // Called after every change, which may move a policy decision
+        self.backend.invalidate_policy_cache();
         match self.selected_tab {
             SelectedTab::Users => {
                 self.items = TableData::Users(
@@ -1052,7 +1657,7 @@ where
             SelectedTab::Targets => {
                 self.items = TableData::Targets(
                     self.t_handle
-                        .block_on(self.backend.db_repository().list_targets(false))
+                        .block_on(self.backend.db_repository().list_targets(false, &[]))
                         .unwrap_or_default(),
                 );
             }
@@ -1108,6 +1713,50 @@ where
                         )
                         .unwrap_or_default(),
                 );
+                let unnamed = self
+                    .t_handle
+                    .block_on(self.backend.db_service().unnamed_rule_ids())
+                    .map(|ids| ids.len())
+                    .unwrap_or_default();
+                if unnamed > 0 && self.message.is_none() {
+                    self.message = Some(Message::Warning(vec![format!(
+                        "{} ids used in rules have no name, press (u) to name them",
+                        unnamed
+                    )]));
+                }
+            }
+            SelectedTab::Bans => {
+                self.items = TableData::Bans(
+                    self.t_handle
+                        .block_on(self.backend.db_repository().list_banned_ips())
+                        .unwrap_or_default(),
+                );
+            }
+            SelectedTab::AccessRequests => {
+                self.items = TableData::AccessRequests(
+                    self.t_handle
+                        .block_on(self.backend.db_repository().list_access_request_views())
+                        .unwrap_or_default(),
+                );
+            }
+            SelectedTab::PolicyChanges => {
+                self.items = TableData::PolicyChanges(
+                    self.t_handle
+                        .block_on(self.backend.db_repository().list_policy_change_views())
+                        .unwrap_or_default(),
+                );
+            }
+            SelectedTab::Pooled => {
+                let leases = self.backend.pool_leases();
+                self.items = TableData::Pooled(leases.map(|l| l.list()).unwrap_or_default());
+                if leases.is_none() && self.message.is_none() {
+                    self.message = Some(Message::Info(vec![
+                        "reuse_target_connection is off, no handle is shared".into(),
+                    ]));
+                }
+            }
+            SelectedTab::Live => {
+                self.items = TableData::Live(self.backend.live_sessions().list());
             }
             SelectedTab::RoleHierarchy => {
                 self.editor = Editor::CasbinGroup(Box::new(casbin_group::CasbinGroupEditor::new(
@@ -1296,6 +1945,8 @@ where
                     Line::styled("Add New Permission", Style::default().bold())
                 }
                 Editor::CasbinName(_) => Line::styled("Add New Group", Style::default().bold()),
+                Editor::Ban(_) => Line::styled("Add New Ban", Style::default().bold()),
+                Editor::Kill(_) => unreachable!(),
                 Editor::GrantRole(_) => unreachable!(),
                 Editor::Bind(_) => unreachable!(),
                 Editor::CasbinGroup(_) => unreachable!(),
@@ -1308,6 +1959,8 @@ where
                 Editor::Permission(_) => Line::styled("Edit Permission", Style::default().bold()),
                 Editor::GrantRole(_) => Line::styled("Grant Role", Style::default().bold()),
                 Editor::CasbinName(_) => Line::styled("Edit Group", Style::default().bold()),
+                Editor::Kill(_) => Line::styled("Terminate Connection", Style::default().bold()),
+                Editor::Ban(_) => unreachable!(),
                 Editor::Bind(_) => unreachable!(),
                 Editor::CasbinGroup(_) => unreachable!(),
                 Editor::None => unreachable!(),
@@ -1349,6 +2002,29 @@ where
                             &["Delete selected group?".to_string()],
                         );
                     }
+                    SelectedTab::Bans => {
+                        render_confirm_dialog(
+                            popup_area,
+                            frame.buffer_mut(),
+                            &["Lift selected ban?".to_string()],
+                        );
+                    }
+                    SelectedTab::Pooled => {
+                        render_confirm_dialog(
+                            popup_area,
+                            frame.buffer_mut(),
+                            &["Revoke the user's channels on this handle?".to_string()],
+                        );
+                    }
+                    SelectedTab::Live => unreachable!(),
+                    SelectedTab::AccessRequests => {
+                        render_confirm_dialog(
+                            popup_area,
+                            frame.buffer_mut(),
+                            &["Deny selected request?".to_string()],
+                        );
+                    }
+                    SelectedTab::PolicyChanges => unreachable!(),
                     SelectedTab::Bind => unreachable!(),
                     SelectedTab::RoleHierarchy => unreachable!(),
                     SelectedTab::TargetGroup => unreachable!(),
@@ -1356,6 +2032,33 @@ where
                 }
                 return;
             }
+            Popup::StaleRow(_) => {
+                render_confirm_dialog(
+                    popup_area,
+                    frame.buffer_mut(),
+                    &[
+                        "Row changed since you opened it.".to_string(),
+                        "Reload it and discard your edits?".to_string(),
+                    ],
+                );
+                return;
+            }
+            Popup::ForceDelete(_, count) => {
+                let object = match self.selected_tab {
+                    SelectedTab::Targets => "Target",
+                    SelectedTab::Secrets => "Secret",
+                    _ => unreachable!(),
+                };
+                render_confirm_dialog(
+                    popup_area,
+                    frame.buffer_mut(),
+                    &[
+                        format!("{} has {} active binding(s).", object, count),
+                        "Delete it with its bindings and their policies?".to_string(),
+                    ],
+                );
+                return;
+            }
             _ => unreachable!(),
         };
         let popup = Block::bordered()
@@ -1378,13 +2081,18 @@ where
             Editor::Permission(ref e) => e.as_ref().help_text,
             Editor::GrantRole(ref e) => e.as_ref().help_text,
             Editor::CasbinName(ref e) => e.as_ref().form.help_text,
-            Editor::None => {
-                if self.selected_tab == SelectedTab::Users {
-                    USER_HELP_TEXT
-                } else {
-                    HELP_TEXT
-                }
-            }
+            Editor::Ban(ref e) => e.as_ref().form.help_text,
+            Editor::Kill(ref e) => e.as_ref().form.help_text,
+            Editor::None => match self.selected_tab {
+                SelectedTab::Users => USER_HELP_TEXT,
+                SelectedTab::CasbinNames => CASBIN_NAME_HELP_TEXT,
+                SelectedTab::Bans => BAN_HELP_TEXT,
+                SelectedTab::Pooled => POOLED_HELP_TEXT,
+                SelectedTab::Live => LIVE_HELP_TEXT,
+                SelectedTab::AccessRequests => REQUEST_HELP_TEXT,
+                SelectedTab::PolicyChanges => POLICY_CHANGE_HELP_TEXT,
+                _ => HELP_TEXT,
+            },
         };
 
         let info_footer = Paragraph::new(Text::from_iter(text))
@@ -1410,6 +2118,11 @@ enum TableData {
     Secrets(Vec<Secret>),
     CasbinNames(Vec<CasbinName>),
     Permissions(Vec<PermissionPolicy>),
+    Bans(Vec<BannedIp>),
+    Pooled(Vec<PooledChannels>),
+    Live(Vec<LiveChannel>),
+    AccessRequests(Vec<AccessRequestView>),
+    PolicyChanges(Vec<PolicyChangeView>),
 }
 
 impl TableData {
@@ -1429,6 +2142,15 @@ impl TableData {
         }
     }
 
+    fn position(&self, id: &Uuid) -> Option<usize> {
+        match self {
+            TableData::Users(data) => data.iter().position(|u| u.user.id == *id),
+            TableData::Targets(data) => data.iter().position(|t| t.id == *id),
+            TableData::Secrets(data) => data.iter().position(|s| s.id == *id),
+            _ => None,
+        }
+    }
+
     fn get_secret(&self, i: usize) -> Option<Secret> {
         if let TableData::Secrets(data) = self {
             data.get(i).cloned()
@@ -1453,6 +2175,38 @@ impl TableData {
         }
     }
 
+    fn get_ban(&self, i: usize) -> Option<BannedIp> {
+        if let TableData::Bans(data) = self {
+            data.get(i).cloned()
+        } else {
+            None
+        }
+    }
+
+    fn get_pooled(&self, i: usize) -> Option<PooledChannels> {
+        if let TableData::Pooled(data) = self {
+            data.get(i).cloned()
+        } else {
+            None
+        }
+    }
+
+    fn get_live(&self, i: usize) -> Option<LiveChannel> {
+        if let TableData::Live(data) = self {
+            data.get(i).cloned()
+        } else {
+            None
+        }
+    }
+
+    fn get_request(&self, i: usize) -> Option<AccessRequestView> {
+        if let TableData::AccessRequests(data) = self {
+            data.get(i).cloned()
+        } else {
+            None
+        }
+    }
+
     fn constraint_len_calculator(&self) -> Vec<Constraint> {
         match self {
             Self::Users(data) => {
@@ -1616,9 +2370,157 @@ impl TableData {
                     Constraint::Length(user_role_len as u16),
                     Constraint::Length(target_group_len as u16),
                     Constraint::Length(action_group_len as u16),
+                    Constraint::Length(6), // effect
                     Constraint::Length(ext_len as u16),
                 ]
             }
+            Self::Bans(data) => {
+                let network_len = data
+                    .iter()
+                    .map(|v| v.network.len())
+                    .max()
+                    .unwrap_or(0)
+                    .max(7);
+
+                let reason_len = data
+                    .iter()
+                    .map(|v| v.reason.as_str())
+                    .map(UnicodeWidthStr::width)
+                    .max()
+                    .unwrap_or(0)
+                    .max(6);
+
+                vec![
+                    Constraint::Length(network_len as u16),
+                    Constraint::Length(reason_len as u16),
+                    Constraint::Length(19), // expires_at
+                    Constraint::Length(9),  // banned_by
+                ]
+            }
+            Self::Pooled(data) => {
+                let target_len = data
+                    .iter()
+                    .map(|v| v.target.as_str())
+                    .map(UnicodeWidthStr::width)
+                    .max()
+                    .unwrap_or(0)
+                    .max(6);
+
+                let username_len = data
+                    .iter()
+                    .map(|v| v.username.as_str())
+                    .map(UnicodeWidthStr::width)
+                    .max()
+                    .unwrap_or(0)
+                    .max(8);
+
+                vec![
+                    Constraint::Length(target_len as u16),
+                    Constraint::Length(username_len as u16),
+                    Constraint::Length(8),  // channels
+                    Constraint::Length(19), // since
+                ]
+            }
+            Self::Live(data) => {
+                let username_len = data
+                    .iter()
+                    .map(|v| v.username.as_str())
+                    .map(UnicodeWidthStr::width)
+                    .max()
+                    .unwrap_or(0)
+                    .max(8);
+
+                let target_len = data
+                    .iter()
+                    .map(|v| v.target.as_str())
+                    .map(UnicodeWidthStr::width)
+                    .max()
+                    .unwrap_or(0)
+                    .max(6);
+
+                let request_len = data
+                    .iter()
+                    .map(|v| v.request.as_str())
+                    .map(UnicodeWidthStr::width)
+                    .max()
+                    .unwrap_or(0)
+                    .max(7);
+
+                vec![
+                    Constraint::Length(username_len as u16),
+                    Constraint::Length(target_len as u16),
+                    Constraint::Length(request_len as u16),
+                    Constraint::Length(36), // connection
+                    Constraint::Length(8),  // watchers
+                    Constraint::Length(19), // since
+                ]
+            }
+            Self::AccessRequests(data) => {
+                let username_len = data
+                    .iter()
+                    .map(|v| v.username.as_str())
+                    .map(UnicodeWidthStr::width)
+                    .max()
+                    .unwrap_or(0)
+                    .max(8);
+
+                let target_len = data
+                    .iter()
+                    .map(|v| v.target_secret.as_str())
+                    .map(UnicodeWidthStr::width)
+                    .max()
+                    .unwrap_or(0)
+                    .max(6);
+
+                let duration_len = data
+                    .iter()
+                    .map(|v| v.print_duration().len())
+                    .max()
+                    .unwrap_or(0)
+                    .max(8);
+
+                let reason_len = data
+                    .iter()
+                    .map(|v| v.reason.as_str())
+                    .map(UnicodeWidthStr::width)
+                    .max()
+                    .unwrap_or(0)
+                    .max(6);
+
+                vec![
+                    Constraint::Length(username_len as u16),
+                    Constraint::Length(target_len as u16),
+                    Constraint::Length(duration_len as u16),
+                    Constraint::Length(reason_len as u16),
+                    Constraint::Length(8),  // status
+                    Constraint::Length(19), // created_at
+                ]
+            }
+            Self::PolicyChanges(data) => {
+                let changed_by_len = data
+                    .iter()
+                    .map(|v| v.changed_by.as_str())
+                    .map(UnicodeWidthStr::width)
+                    .max()
+                    .unwrap_or(0)
+                    .max(10);
+
+                let diff_len = data
+                    .iter()
+                    .map(|v| v.diff.as_str())
+                    .map(UnicodeWidthStr::width)
+                    .max()
+                    .unwrap_or(0)
+                    .max(4);
+
+                vec![
+                    Constraint::Length(19), // changed_at
+                    Constraint::Length(changed_by_len as u16),
+                    Constraint::Length(7),  // action
+                    Constraint::Length(36), // rule
+                    Constraint::Length(diff_len as u16),
+                ]
+            }
         }
     }
 }
@@ -1646,6 +2548,26 @@ impl crate::server::widgets::TableData for TableData {
                 .iter()
                 .map(|v| v as &dyn FieldsToArray)
                 .collect::<Vec<_>>(),
+            Self::Bans(data) => data
+                .iter()
+                .map(|v| v as &dyn FieldsToArray)
+                .collect::<Vec<_>>(),
+            Self::Pooled(data) => data
+                .iter()
+                .map(|v| v as &dyn FieldsToArray)
+                .collect::<Vec<_>>(),
+            Self::Live(data) => data
+                .iter()
+                .map(|v| v as &dyn FieldsToArray)
+                .collect::<Vec<_>>(),
+            Self::AccessRequests(data) => data
+                .iter()
+                .map(|v| v as &dyn FieldsToArray)
+                .collect::<Vec<_>>(),
+            Self::PolicyChanges(data) => data
+                .iter()
+                .map(|v| v as &dyn FieldsToArray)
+                .collect::<Vec<_>>(),
         }
     }
 
@@ -1656,6 +2578,11 @@ impl crate::server::widgets::TableData for TableData {
             Self::Secrets(data) => data.len(),
             Self::CasbinNames(data) => data.len(),
             Self::Permissions(data) => data.len(),
+            Self::Bans(data) => data.len(),
+            Self::Pooled(data) => data.len(),
+            Self::Live(data) => data.len(),
+            Self::AccessRequests(data) => data.len(),
+            Self::PolicyChanges(data) => data.len(),
         }
     }
 
@@ -1688,8 +2615,33 @@ impl crate::server::widgets::TableData for TableData {
             ],
             Self::CasbinNames(_) => vec!["Type", "name", "is_active"],
             Self::Permissions(_) => {
-                vec!["user/role", "target/group", "action/group", "extend policy"]
+                vec![
+                    "user/role",
+                    "target/group",
+                    "action/group",
+                    "effect",
+                    "extend policy",
+                ]
             }
+            Self::Bans(_) => vec!["network", "reason", "expires_at", "banned_by"],
+            Self::Pooled(_) => vec!["target", "username", "channels", "since"],
+            Self::Live(_) => vec![
+                "username",
+                "target",
+                "request",
+                "connection",
+                "watchers",
+                "since",
+            ],
+            Self::AccessRequests(_) => vec![
+                "username",
+                "target",
+                "duration",
+                "reason",
+                "status",
+                "requested_at",
+            ],
+            Self::PolicyChanges(_) => vec!["changed_at", "changed_by", "action", "rule", "diff"],
         }
     }
 }
@@ -1706,6 +2658,8 @@ where
     CasbinGroup(Box<casbin_group::CasbinGroupEditor<B>>),
     GrantRole(Box<grant_role::GrantRoleEditor<B>>),
     CasbinName(Box<casbin_name::CasbinNameEditor>),
+    Ban(Box<ban::BanEditor>),
+    Kill(Box<live::KillEditor>),
     None,
 }
 
@@ -1739,6 +2693,12 @@ where
             Editor::CasbinName(e) => {
                 e.render(area, buf);
             }
+            Editor::Ban(e) => {
+                e.render(area, buf);
+            }
+            Editor::Kill(e) => {
+                e.render(area, buf);
+            }
             Editor::CasbinGroup(_) => {
                 unreachable!();
             }
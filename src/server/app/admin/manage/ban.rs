@@ -0,0 +1,87 @@
+use crate::database::Uuid;
+use crate::database::error::DatabaseError;
+use crate::database::models::banned_ip::ValidateError;
+use crate::database::models::{BannedIp, Timestamp};
+use crate::error::Error;
+use crate::server::widgets::*;
+use crossterm::event::{KeyCode, KeyModifiers};
+use ratatui::{buffer::Buffer, layout::Rect, widgets::Widget};
+
+// Field indices
+const F_NETWORK: usize = 0;
+const F_DURATION: usize = 1;
+const F_REASON: usize = 2;
+
+/// Adds a ban, bans are lifted rather than edited
+#[derive(Debug)]
+pub struct BanEditor {
+    updated_by: Uuid,
+    /// Set once the form is saved
+    pub ban: Option<BannedIp>,
+    pub form: FormEditor,
+}
+
+impl BanEditor {
+    pub fn new(updated_by: Uuid) -> Self {
+        let form = FormEditor::new(vec![
+            FormField::text("*IP/CIDR*", None),
+            // Empty keeps the ban until it is lifted
+            FormField::text("Duration", None),
+            FormField::text("Reason", None),
+        ]);
+        Self {
+            updated_by,
+            ban: None,
+            form,
+        }
+    }
+
+    pub fn handle_paste_event(&mut self, paste: &str) -> bool {
+        self.form.handle_paste_event(paste)
+    }
+
+    pub fn handle_key_event(&mut self, key: KeyCode, modifiers: KeyModifiers) -> bool {
+        match self.form.handle_key_event(key, modifiers) {
+            FormEvent::Save => {
+                if let Err(e) = self.save_ban() {
+                    self.form.set_save_error(vec![e.to_string()]);
+                    return false;
+                }
+                true
+            }
+            FormEvent::Cancel => {
+                self.form.show_cancel_confirmation = true;
+                true
+            }
+            FormEvent::None => false,
+        }
+    }
+
+    fn save_ban(&mut self) -> Result<(), Error> {
+        let network = BannedIp::parse_network(&self.form.get_text(F_NETWORK))
+            .map_err(|e| Error::Database(DatabaseError::BannedIpValidation(e)))?;
+
+        let duration = self.form.get_text(F_DURATION);
+        let duration = duration.trim();
+        let expires_at = if duration.is_empty() {
+            None
+        } else {
+            let d = humantime::parse_duration(duration).map_err(|_| {
+                Error::Database(DatabaseError::BannedIpValidation(
+                    ValidateError::DurationInvalid(duration.to_string()),
+                ))
+            })?;
+            Timestamp::now().checked_add(d)
+        };
+        let reason = self.form.get_text(F_REASON).trim().to_string();
+        self.ban =
+            Some(BannedIp::new(network, reason, self.updated_by).with_expires_at(expires_at));
+        Ok(())
+    }
+}
+
+impl Widget for &mut BanEditor {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        self.form.render_ui(area, buf);
+    }
+}
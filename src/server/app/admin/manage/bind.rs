@@ -211,6 +211,7 @@ where
                 !s.is_bound,
                 &self.admin_id,
             ))?;
+        self.backend.invalidate_policy_cache();
         info!(
             "[{}] Secret '{}({})' {} target '{}({})' by admin_id={}",
             self.handler_id, s.name, s.id, action, t.name, t.id, self.admin_id
@@ -1,6 +1,6 @@
 use super::LOG_TYPE;
 use crate::database::Uuid;
-use crate::database::models::{CasbinRule, ObjectGroup};
+use crate::database::models::{CasbinRule, ObjectGroup, UserGroupMember};
 use crate::error::Error;
 use crate::server::HandlerLog;
 use crate::server::casbin::GroupType;
@@ -340,7 +340,7 @@ where
     fn refreash_data(&mut self) {
         if let Err(e) = self.t_handle.block_on(self.backend.load_role_manager()) {
             error!("[{}] Load role manager error: {}", self.handler_id, e);
-            self.message = Some(Message::Error(vec!["Internal error".into()]));
+            self.message = Some(Message::Error(vec![e.user_message()]));
         }
         let (state, items, selector_items) = match CasbinGroupEditor::build_tree(
             self.handler_id,
@@ -365,13 +365,22 @@ where
         if iden_list.len() > 1 {
             let group_iden = self.state.selected().first().unwrap();
             let item_iden = self.state.selected().get(1).unwrap();
-            match self
-                .t_handle
-                .block_on(self.backend.db_repository().delete_casbin_rule_by_v0_v1(
-                    &self.group_type.to_string(),
-                    &item_iden.rid,
-                    &group_iden.rid,
-                )) {
+            let res = if self.is_user_group_membership(&item_iden.rid, &group_iden.rid) {
+                self.t_handle.block_on(
+                    self.backend
+                        .db_repository()
+                        .remove_user_group_member(&item_iden.rid, &group_iden.rid),
+                )
+            } else {
+                self.t_handle
+                    .block_on(self.backend.db_service().delete_casbin_rule_by_v0_v1(
+                        &self.group_type.to_string(),
+                        &item_iden.rid,
+                        &group_iden.rid,
+                        &self.admin_id,
+                    ))
+            };
+            match res {
                 Ok(res) => {
                     if res {
                         self.t_handle.block_on((self.log)(
@@ -405,12 +414,27 @@ where
                         "[{}] Failed to delete casbin_rule, ptype={}, v0={}, v1={}, error: {}",
                         self.handler_id, self.group_type, item_iden.rid, group_iden.rid, e
                     );
-                    self.message = Some(Message::Error(vec!["Internal error".into()]));
+                    self.message = Some(Message::Error(vec![e.user_message()]));
                 }
             }
         }
     }
 
+    /// A user placed directly under a user group is a row of user_group_members,
+    /// not a g1 rule
+    fn is_user_group_membership(&self, group: &Uuid, member: &Uuid) -> bool {
+        self.group_type == GroupType::Subject
+            && self
+                .selector_items
+                .iter()
+                .any(|v| v.id == *member && !v.is_group)
+            && matches!(
+                self.t_handle
+                    .block_on(self.backend.db_repository().get_user_group_by_id(group)),
+                Ok(Some(_))
+            )
+    }
+
     fn insert_group(&mut self) {
         let idx = self.selector_table.state.selected().unwrap();
         let obj = self.selector_items.get(idx).unwrap();
@@ -493,10 +517,17 @@ where
             }
         }
 
-        match self
-            .t_handle
-            .block_on(self.backend.db_repository().create_casbin_rule(&cr))
-        {
+        let res = if self.is_user_group_membership(&obj.id, &iden.rid) {
+            let member = UserGroupMember::new(obj.id, iden.rid, self.admin_id);
+            self.t_handle
+                .block_on(self.backend.db_repository().add_user_group_member(&member))
+                .map(|_| ())
+        } else {
+            self.t_handle
+                .block_on(self.backend.db_service().create_casbin_rule(&cr))
+                .map(|_| ())
+        };
+        match res {
             Ok(_) => {
                 self.t_handle.block_on((self.log)(
                     LOG_TYPE.into(),
@@ -531,7 +562,7 @@ where
                             "[{}] Failed to add {} '{}' to group '{}': {}",
                             self.handler_id, t_type, obj.name, g_name, err
                         );
-                        "Internal error".to_string()
+                        err.user_message()
                     }
                 };
                 self.message = Some(Message::Error(vec![msg]));
@@ -136,8 +136,11 @@ where
                 .rule_id
                 .as_ref()
                 .ok_or(Error::Server(ServerError::MissingRuleId))?;
-            self.t_handle
-                .block_on(self.backend.db_repository().delete_casbin_rule(id))?;
+            self.t_handle.block_on(
+                self.backend
+                    .db_service()
+                    .delete_casbin_rule(id, &self.admin_id),
+            )?;
             info!(
                 "[{}] Role '{}({})' revoked from user_id={} by admin_id={}",
                 self.handler_id, t.role, t.rid, self.selected_user_id, self.admin_id
@@ -158,7 +161,7 @@ where
                 self.admin_id,
             );
             self.t_handle
-                .block_on(self.backend.db_repository().create_casbin_rule(&cr))?;
+                .block_on(self.backend.db_service().create_casbin_rule(&cr))?;
             info!(
                 "[{}] Role '{}({})' granted to user_id={} by admin_id={}",
                 self.handler_id, t.role, t.rid, self.selected_user_id, self.admin_id
@@ -0,0 +1,141 @@
+use crate::error::Error;
+use crate::server::live_sessions::{LiveChannel, LiveEvent, Watch};
+use crate::server::widgets::*;
+use crossterm::event::{self, KeyCode, KeyModifiers, NoTtyEvent};
+use ratatui::backend::NottyBackend;
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::Style;
+use ratatui::widgets::{Block, BorderType, Paragraph, Widget};
+use ratatui::{Frame, Terminal};
+use std::io::Write;
+use std::time::Duration;
+use tokio::sync::broadcast::error::TryRecvError;
+use tui_term::widget::PseudoTerminal;
+
+/// How often the screen is redrawn with what arrived meanwhile
+const REFRESH: Duration = Duration::from_millis(50);
+const WATCH_HELP_TEXT: &str = "(q/Esc) stop watching";
+const ENDED_HELP_TEXT: &str = "session ended | (q/Esc) back";
+
+// Field indices
+const F_REASON: usize = 0;
+
+/// Asks why the connection of a live channel is being terminated
+#[derive(Debug)]
+pub struct KillEditor {
+    pub live: LiveChannel,
+    /// Set once the form is saved
+    pub reason: Option<String>,
+    pub form: FormEditor,
+}
+
+impl KillEditor {
+    pub fn new(live: LiveChannel) -> Self {
+        let form = FormEditor::new(vec![FormField::text("*Reason*", None)]);
+        Self {
+            live,
+            reason: None,
+            form,
+        }
+    }
+
+    pub fn handle_paste_event(&mut self, paste: &str) -> bool {
+        self.form.handle_paste_event(paste)
+    }
+
+    pub fn handle_key_event(&mut self, key: KeyCode, modifiers: KeyModifiers) -> bool {
+        match self.form.handle_key_event(key, modifiers) {
+            FormEvent::Save => {
+                let reason = self.form.get_text(F_REASON).trim().to_string();
+                if reason.is_empty() {
+                    self.form
+                        .set_save_error(vec!["A reason is required".to_string()]);
+                    return false;
+                }
+                self.reason = Some(reason);
+                true
+            }
+            FormEvent::Cancel => {
+                self.form.show_cancel_confirmation = true;
+                true
+            }
+            FormEvent::None => false,
+        }
+    }
+}
+
+impl Widget for &mut KillEditor {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        self.form.render_ui(area, buf);
+    }
+}
+
+/// Shows a channel's output as its user sees it until the admin leaves. Keys
+/// never reach the session.
+pub(super) fn watch<W: Write>(
+    tty: &NoTtyEvent,
+    terminal: &mut Terminal<NottyBackend<W>>,
+    mut watch: Watch,
+    title: &str,
+    colors: &Colors,
+) -> Result<(), Error> {
+    let (cols, rows) = watch.size;
+    let mut parser = vt100::Parser::new(rows, cols, 0);
+    let mut ended = false;
+    loop {
+        while !ended {
+            match watch.events.try_recv() {
+                Ok(LiveEvent::Output(data)) => parser.process(&data),
+                Ok(LiveEvent::Resize(cols, rows)) => parser.screen_mut().set_size(rows, cols),
+                // What was skipped is repaired by the session's next redraw
+                Err(TryRecvError::Lagged(_)) => {}
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Closed) => ended = true,
+            }
+        }
+        terminal.draw(|frame| render(frame, parser.screen(), title, ended, colors))?;
+
+        if !event::poll(tty, REFRESH)? {
+            continue;
+        }
+        if let Some(key) = event::read(tty)?.as_key_press_event() {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn render(frame: &mut Frame, screen: &vt100::Screen, title: &str, ended: bool, colors: &Colors) {
+    let [term_area, footer_area] =
+        Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+    let (rows, cols) = screen.size();
+    let area = Rect::new(
+        term_area.x,
+        term_area.y,
+        (cols + 2).min(term_area.width),
+        (rows + 2).min(term_area.height),
+    );
+    let block = Block::bordered()
+        .title(title)
+        .border_type(BorderType::Double)
+        .border_style(Style::new().fg(colors.footer_border_color));
+    frame.render_widget(PseudoTerminal::new(screen).block(block), area);
+
+    let help = if ended {
+        ENDED_HELP_TEXT
+    } else {
+        WATCH_HELP_TEXT
+    };
+    frame.render_widget(
+        Paragraph::new(help)
+            .style(Style::new().fg(colors.row_fg).bg(colors.buffer_bg))
+            .centered(),
+        footer_area,
+    );
+}
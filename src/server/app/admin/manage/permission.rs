@@ -29,6 +29,7 @@ enum InputField {
     Target,
     Action,
     ExtendPolicy,
+    Effect,
 }
 
 impl InputField {
@@ -37,16 +38,18 @@ impl InputField {
             Self::User => Self::Target,
             Self::Target => Self::Action,
             Self::Action => Self::ExtendPolicy,
-            Self::ExtendPolicy => Self::User,
+            Self::ExtendPolicy => Self::Effect,
+            Self::Effect => Self::User,
         }
     }
 
     fn previous(&self) -> Self {
         match self {
-            Self::User => Self::ExtendPolicy,
+            Self::User => Self::Effect,
             Self::Target => Self::User,
             Self::Action => Self::Target,
             Self::ExtendPolicy => Self::Action,
+            Self::Effect => Self::ExtendPolicy,
         }
     }
 }
@@ -187,6 +190,7 @@ impl PermissionEditor {
                         self.extend_policy_text.clear_style();
                     }
                 }
+                InputField::Effect => unreachable!(),
             }
             if self.focused_field != InputField::ExtendPolicy {
                 match key {
@@ -240,7 +244,7 @@ impl PermissionEditor {
                                 self.perm.action_group = t.name.clone();
                                 self.perm.rule.v2 = t.id;
                             }
-                            InputField::ExtendPolicy => {
+                            InputField::ExtendPolicy | InputField::Effect => {
                                 unreachable!()
                             }
                         }
@@ -293,8 +297,19 @@ impl PermissionEditor {
                     );
                     text_input_position(key, &mut self.extend_policy_text.textarea);
                 }
+                // Deny rules win over any policy granting the same request
+                KeyCode::Enter | KeyCode::Char(' ') | KeyCode::Char('e')
+                    if self.focused_field == InputField::Effect =>
+                {
+                    self.perm.rule.ptype = if self.perm.rule.ptype == "p_deny" {
+                        "p".to_string()
+                    } else {
+                        "p_deny".to_string()
+                    };
+                }
                 KeyCode::Enter | KeyCode::Char('e') | KeyCode::Char('i') | KeyCode::Char('a')
-                    if self.focused_field != InputField::ExtendPolicy =>
+                    if self.focused_field != InputField::ExtendPolicy
+                        && self.focused_field != InputField::Effect =>
                 {
                     self.editing_mode = true;
                     self.help_text = HELP_TABLE
@@ -336,11 +351,11 @@ impl PermissionEditor {
     }
 
     fn max_scroll_offset(&self) -> usize {
-        5
+        6
     }
 
     fn window_height(&self) -> u16 {
-        12
+        15
     }
 
     fn render_textarea(&mut self, area: Rect, buf: &mut Buffer) {
@@ -366,6 +381,7 @@ impl PermissionEditor {
                 Constraint::Length(3),
                 Constraint::Length(3),
                 Constraint::Length(3),
+                Constraint::Length(3),
             ])
             .split(content_area);
 
@@ -413,6 +429,17 @@ impl PermissionEditor {
             self.focused_field == InputField::ExtendPolicy,
         );
 
+        // Effect field, toggled between allow and deny
+        render_textarea(
+            chunks[4],
+            &mut editor_buf,
+            "Effect",
+            &SingleLineText::new(Some(self.perm.effect().to_string())),
+            false,
+            &self.colors,
+            self.focused_field == InputField::Effect,
+        );
+
         if scrollbar_needed {
             let visible_content = editor_buf
                 .content
@@ -441,7 +468,10 @@ impl PermissionEditor {
     }
 
     fn render_ui(&mut self, area: Rect, buf: &mut Buffer) {
-        if self.editing_mode && self.focused_field != InputField::ExtendPolicy {
+        if self.editing_mode
+            && self.focused_field != InputField::ExtendPolicy
+            && self.focused_field != InputField::Effect
+        {
             let area = centered_area(area, area.width - 2, area.height - 2);
             match self.focused_field {
                 InputField::User => {
@@ -474,7 +504,7 @@ impl PermissionEditor {
                         DisplayMode::Manage,
                     );
                 }
-                InputField::ExtendPolicy => unreachable!(),
+                InputField::ExtendPolicy | InputField::Effect => unreachable!(),
             }
         } else {
             self.render_textarea(area, buf);
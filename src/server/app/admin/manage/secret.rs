@@ -1,5 +1,5 @@
 use crate::database::error::DatabaseError;
-use crate::database::models::target_secret::Secret;
+use crate::database::models::target_secret::{KIND_CA, KIND_CREDENTIALS, Secret};
 use crate::error::Error;
 use crate::server::widgets::*;
 use crossterm::event::{KeyCode, KeyModifiers};
@@ -15,6 +15,7 @@ const F_USER: usize = 1;
 const F_PASSWORD: usize = 2;
 const F_IS_ACTIVE: usize = 3;
 const F_PRIVATE_KEY: usize = 4;
+const F_CA: usize = 5;
 
 #[derive(Debug)]
 pub struct SecretEditor {
@@ -32,6 +33,7 @@ impl SecretEditor {
             FormField::text_masked("Password", Some(secret.print_password()), '*'),
             FormField::checkbox("Is Active", secret.is_active),
             FormField::multiline("Private Key", Some(&[secret.print_private_key()]), 8),
+            FormField::checkbox("Signed By CA", secret.is_ca()),
         ]);
         Self {
             secret,
@@ -79,6 +81,12 @@ impl SecretEditor {
         }
 
         self.secret.is_active = self.form.get_checkbox(F_IS_ACTIVE);
+        self.secret.kind = if self.form.get_checkbox(F_CA) {
+            KIND_CA
+        } else {
+            KIND_CREDENTIALS
+        }
+        .to_string();
 
         let private_key = self
             .form
@@ -1,6 +1,9 @@
 use crate::database::error::DatabaseError;
 use crate::database::models::target::ValidateError;
-use crate::database::models::Target;
+use crate::database::models::{
+    HOST_KEY_CONFIRM, HOST_KEY_STRICT, HOST_KEY_TOFU, MAX_VIA_HOPS, TagFilter, Target, TargetTag,
+};
+use crate::database::{DatabaseRepository, Uuid};
 use crate::error::Error;
 use crate::server::widgets::*;
 use crossterm::event::{KeyCode, KeyModifiers};
@@ -9,32 +12,134 @@ use ratatui::{
     layout::Rect,
     widgets::Widget,
 };
+use russh::keys::ssh_key::{HashAlg, PublicKey};
+
+const HOST_KEY_MODE_OPTIONS: [RadioOption; 3] = [
+    RadioOption::new("Strict", HOST_KEY_STRICT),
+    RadioOption::new("Trust On First Use", HOST_KEY_TOFU),
+    RadioOption::new("Confirm", HOST_KEY_CONFIRM),
+];
 
 // Field indices
 const F_NAME: usize = 0;
 const F_HOSTNAME: usize = 1;
 const F_PORT: usize = 2;
-const F_SERVER_PUBLIC_KEY: usize = 3;
-const F_DESCRIPTION: usize = 4;
-const F_IS_ACTIVE: usize = 5;
+const F_VIA_TARGET: usize = 3;
+const F_TIMEOUT: usize = 4;
+const F_SERVER_PUBLIC_KEY: usize = 5;
+const F_SCANNED_KEYS: usize = 6;
+const F_USE_SCANNED_KEY: usize = 7;
+const F_HOST_KEY_MODE: usize = 8;
+const F_ACCEPT_PENDING_KEY: usize = 10;
+const F_DESCRIPTION: usize = 11;
+const F_TAGS: usize = 12;
+const F_IS_ACTIVE: usize = 13;
 
 #[derive(Debug)]
 pub struct TargetEditor {
     pub target: Target,
+    pub tags: Vec<TagFilter>,
+    /// Name of the target to go through, resolved when saving
+    pub via_name: String,
     pub form: FormEditor,
+    /// Keys found by the last scan, the first one is stored on request
+    scanned_keys: Vec<PublicKey>,
+    scan_requested: bool,
 }
 
 impl TargetEditor {
-    pub fn new(target: Target) -> Self {
+    pub fn new(target: Target, tags: &[TargetTag], via_name: String) -> Self {
+        let tag_text = tags
+            .iter()
+            .map(|t| t.label())
+            .collect::<Vec<_>>()
+            .join(", ");
         let form = FormEditor::new(vec![
             FormField::text("*Name*", Some(target.name.clone())),
             FormField::text("*Hostname*", Some(target.hostname.clone())),
             FormField::text("*Port*", Some(target.port.to_string())),
-            FormField::text("*Server Public Key*", Some(target.server_public_key.clone())),
+            // Hostname and port as the via target sees them
+            FormField::text("Via Target (name)", Some(via_name.clone())),
+            // Empty for the timeouts of `target_connect`
+            FormField::text(
+                "Timeout (seconds)",
+                target.timeout_secs.map(|t| t.to_string()),
+            ),
+            // Learned on the first connection unless the mode is strict
+            FormField::text("Server Public Key", Some(target.server_public_key.clone())),
+            FormField::info(
+                "Scanned Host Keys",
+                vec!["(Ctrl+f) fetch from hostname and port".to_string()],
+                4,
+            ),
+            FormField::checkbox("Use First Scanned Key", false),
+            FormField::radio(
+                "*Host Key Mode*",
+                &HOST_KEY_MODE_OPTIONS,
+                &target.host_key_mode,
+                5,
+            ),
+            FormField::info(
+                "Pending Public Key",
+                vec![
+                    target
+                        .pending_public_key
+                        .as_deref()
+                        .map(crate::common::shorten_ssh_pubkey)
+                        .unwrap_or_else(|| "none".to_string()),
+                ],
+                3,
+            ),
+            FormField::checkbox("Accept Pending Key", false),
             FormField::text("Description", target.description.clone()),
+            FormField::text("Tags (key=value, ...)", Some(tag_text)),
             FormField::checkbox("Is Active", target.is_active),
         ]);
-        Self { target, form }
+        let tags = tags
+            .iter()
+            .map(|t| TagFilter {
+                key: t.key.clone(),
+                value: t.value.clone(),
+            })
+            .collect();
+        Self {
+            target,
+            tags,
+            via_name,
+            form,
+            scanned_keys: Vec::new(),
+            scan_requested: false,
+        }
+    }
+
+    /// Hostname and port to scan once Ctrl+f was pressed
+    pub fn take_scan_request(&mut self) -> Option<Result<(String, u16), Error>> {
+        if !std::mem::take(&mut self.scan_requested) {
+            return None;
+        }
+        // Fallback addresses serve the same keys, the first one is asked
+        let hostname = self.form.get_text(F_HOSTNAME);
+        let hostname = hostname
+            .split(',')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        if hostname.is_empty() {
+            return Some(Err(Error::Database(DatabaseError::TargetValidation(
+                ValidateError::HostnameEmpty,
+            ))));
+        }
+        Some(parse_port(&self.form.get_text(F_PORT)).map(|port| (hostname, port)))
+    }
+
+    pub fn set_scanned_keys(&mut self, keys: Vec<PublicKey>) {
+        let lines = keys
+            .iter()
+            .map(|k| format!("{} {}", k.algorithm(), k.fingerprint(HashAlg::Sha256)))
+            .collect();
+        self.form.set_info(F_SCANNED_KEYS, lines);
+        self.scanned_keys = keys;
     }
 
     pub fn handle_paste_event(&mut self, paste: &str) -> bool {
@@ -42,6 +147,10 @@ impl TargetEditor {
     }
 
     pub fn handle_key_event(&mut self, key: KeyCode, modifiers: KeyModifiers) -> bool {
+        if modifiers.contains(KeyModifiers::CONTROL) && key == KeyCode::Char('f') {
+            self.scan_requested = true;
+            return false;
+        }
         match self.form.handle_key_event(key, modifiers) {
             FormEvent::Save => {
                 if let Err(e) = self.save_target() {
@@ -62,39 +171,110 @@ impl TargetEditor {
         self.target.name = self.form.get_text(F_NAME).trim().into();
         self.target.hostname = self.form.get_text(F_HOSTNAME).trim().into();
 
-        let port_str = self.form.get_text(F_PORT).trim().to_string();
-        let port: u64 = match port_str.parse() {
-            Ok(p) => {
-                if (1..=65535).contains(&p) {
-                    p
-                } else {
-                    return Err(Error::Database(DatabaseError::TargetValidation(
-                        ValidateError::PortInvalid,
-                    )));
-                }
-            }
-            Err(_) => {
-                return Err(Error::Database(DatabaseError::TargetValidation(
-                    ValidateError::PortNotNumber,
-                )))
-            }
-        };
-        self.target.port = port as u16;
+        self.target.port = parse_port(&self.form.get_text(F_PORT))?;
+        self.via_name = self.form.get_text(F_VIA_TARGET).trim().to_string();
+        self.target.timeout_secs = parse_timeout(&self.form.get_text(F_TIMEOUT))?;
 
         self.target.server_public_key =
             self.form.get_text(F_SERVER_PUBLIC_KEY).trim().to_string();
+        if self.form.get_checkbox(F_USE_SCANNED_KEY)
+            && let Some(key) = self.scanned_keys.first()
+        {
+            self.target.server_public_key = key.to_openssh()?;
+            self.target.pending_public_key = None;
+        }
+        self.target.host_key_mode = self.form.get_radio(F_HOST_KEY_MODE).to_string();
+        if self.form.get_checkbox(F_ACCEPT_PENDING_KEY)
+            && let Some(key) = self.target.pending_public_key.take()
+        {
+            self.target.server_public_key = key;
+        }
 
         let desc = self.form.get_text(F_DESCRIPTION).trim().to_string();
         self.target.description = (!desc.is_empty()).then_some(desc);
 
         self.target.is_active = self.form.get_checkbox(F_IS_ACTIVE);
 
+        let tags = TagFilter::parse_list(&self.form.get_text(F_TAGS))
+            .map_err(|e| Error::Database(DatabaseError::TargetValidation(e)))?;
+        for (i, tag) in tags.iter().enumerate() {
+            if tags[..i].iter().any(|t| t.key == tag.key) {
+                return Err(Error::Database(DatabaseError::TargetValidation(
+                    ValidateError::TagInvalid(tag.to_string()),
+                )));
+            }
+        }
+        self.tags = tags;
+
         self.target
             .validate()
             .map_err(|e| Error::Database(DatabaseError::TargetValidation(e)))
     }
 }
 
+/// Id of the target named `via_name` for `target` to go through, checking
+/// the chain from it doesn't lead back to `target` or grow too long
+pub async fn via_target_id(
+    repo: &dyn DatabaseRepository,
+    target: &Target,
+    via_name: &str,
+) -> Result<Option<Uuid>, Error> {
+    if via_name.is_empty() {
+        return Ok(None);
+    }
+    let invalid = |e| Error::Database(DatabaseError::TargetValidation(e));
+    let Some(via) = repo.get_target_by_name(via_name).await? else {
+        return Err(invalid(ValidateError::ViaTargetUnknown(
+            via_name.to_string(),
+        )));
+    };
+    let (mut next, mut hops) = (Some(via.id), 0);
+    while let Some(id) = next {
+        hops += 1;
+        if id == target.id || hops > MAX_VIA_HOPS {
+            return Err(invalid(ValidateError::ViaTarget));
+        }
+        next = repo
+            .get_target_by_id(&id, false)
+            .await?
+            .and_then(|t| t.via_target_id);
+    }
+    Ok(Some(via.id))
+}
+
+fn parse_timeout(text: &str) -> Result<Option<u32>, Error> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    match text.parse() {
+        Ok(secs) if secs > 0 => Ok(Some(secs)),
+        _ => Err(Error::Database(DatabaseError::TargetValidation(
+            ValidateError::TimeoutInvalid,
+        ))),
+    }
+}
+
+fn parse_port(text: &str) -> Result<u16, Error> {
+    let port: u64 = match text.trim().parse() {
+        Ok(p) => {
+            if (1..=65535).contains(&p) {
+                p
+            } else {
+                return Err(Error::Database(DatabaseError::TargetValidation(
+                    ValidateError::PortInvalid,
+                )));
+            }
+        }
+        Err(_) => {
+            return Err(Error::Database(DatabaseError::TargetValidation(
+                ValidateError::PortNotNumber,
+            )));
+        }
+    };
+    Ok(port as u16)
+}
+
 impl Widget for &mut TargetEditor {
     fn render(self, area: Rect, buf: &mut Buffer) {
         self.form.render_ui(area, buf);
@@ -1,6 +1,7 @@
 use crate::database::error::DatabaseError;
 use crate::database::models::user::ValidateError;
 use crate::database::models::User;
+use crate::database::models::user::authorized_key_fingerprint;
 use crate::error::Error;
 use crate::server::widgets::*;
 use crossterm::event::{KeyCode, KeyModifiers};
@@ -16,13 +17,18 @@ const F_EMAIL: usize = 1;
 const F_PASSWORD: usize = 2;
 const F_FORCE_INIT_PASS: usize = 3;
 const F_IS_ACTIVE: usize = 4;
-const F_AUTHORIZED_KEYS: usize = 5;
+const F_TOTP: usize = 5;
+const F_SOURCE_CIDRS: usize = 6;
+const F_AUTHORIZED_KEYS: usize = 7;
+const F_KEY_FINGERPRINTS: usize = 8;
 
 #[derive(Debug)]
 pub struct UserEditor {
     pub user: User,
     pub form: FormEditor,
     pub generate_password: bool,
+    /// Checkbox state, a new key is generated when it turns on
+    pub totp: bool,
 }
 
 impl UserEditor {
@@ -33,25 +39,47 @@ impl UserEditor {
             FormField::checkbox("Generate New Password", false),
             FormField::checkbox("Force Init Password", user.force_init_pass),
             FormField::checkbox("Is Active", user.is_active),
+            FormField::checkbox("TOTP Enabled", user.has_totp()),
+            // Empty lets the user log in from anywhere
+            FormField::text(
+                "Allowed Source CIDRs (comma separated)",
+                user.get_allowed_source_cidrs().map(|c| c.join(", ")),
+            ),
             FormField::multiline(
                 "Authorized Keys (one per line)",
                 user.get_authorized_keys(),
                 8,
             ),
+            FormField::info(
+                "Key Fingerprints",
+                key_fingerprints(user.get_authorized_keys().unwrap_or_default()),
+                8,
+            ),
         ]);
+        let totp = user.has_totp();
         Self {
             user,
             form,
             generate_password: false,
+            totp,
         }
     }
 
     pub fn handle_paste_event(&mut self, paste: &str) -> bool {
-        self.form.handle_paste_event(paste)
+        let handled = self.form.handle_paste_event(paste);
+        self.refresh_fingerprints();
+        handled
+    }
+
+    fn refresh_fingerprints(&mut self) {
+        let lines = key_fingerprints(self.form.get_multiline(F_AUTHORIZED_KEYS));
+        self.form.set_info(F_KEY_FINGERPRINTS, lines);
     }
 
     pub fn handle_key_event(&mut self, key: KeyCode, modifiers: KeyModifiers) -> bool {
-        match self.form.handle_key_event(key, modifiers) {
+        let event = self.form.handle_key_event(key, modifiers);
+        self.refresh_fingerprints();
+        match event {
             FormEvent::Save => {
                 if let Err(e) = self.save_user() {
                     let lines = if let Error::Database(DatabaseError::UserValidation(
@@ -93,6 +121,17 @@ impl UserEditor {
         self.generate_password = self.form.get_checkbox(F_PASSWORD);
         self.user.force_init_pass = self.form.get_checkbox(F_FORCE_INIT_PASS);
         self.user.is_active = self.form.get_checkbox(F_IS_ACTIVE);
+        self.totp = self.form.get_checkbox(F_TOTP);
+
+        let source_cidrs = self
+            .form
+            .get_text(F_SOURCE_CIDRS)
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<String>>();
+        self.user
+            .set_allowed_source_cidrs((!source_cidrs.is_empty()).then_some(source_cidrs));
 
         let authorized_keys = self
             .form
@@ -114,6 +153,19 @@ impl UserEditor {
     }
 }
 
+/// One line per authorized key, security keys (sk-*) included, so admins
+/// can match them with `ssh-keygen -lf`
+fn key_fingerprints(keys: &[String]) -> Vec<String> {
+    keys.iter()
+        .enumerate()
+        .filter(|(_, k)| !k.trim().is_empty())
+        .map(|(i, k)| {
+            authorized_key_fingerprint(k.trim())
+                .unwrap_or_else(|| format!("line {}: invalid key", i + 1))
+        })
+        .collect()
+}
+
 impl Widget for &mut UserEditor {
     fn render(self, area: Rect, buf: &mut Buffer) {
         self.form.render_ui(area, buf);
@@ -1,5 +1,6 @@
 use crate::database::Uuid;
 use crate::server::HandlerLog;
+use crate::server::role_graph;
 use log::warn;
 use reedline::{
     ColumnarMenu, DefaultPrompt, DefaultPromptSegment, Emacs, ExampleHighlighter, KeyCode,
@@ -100,6 +101,31 @@ pub(super) fn shell<B>(
                             let _ = send_to_session.blocking_send("flushed successfully".into());
                         }
                     }
+                    CMD_SYNC_TAG_GROUPS => {
+                        let res = t_handle.block_on(async {
+                            let count = backend.db_repository().sync_tag_groups(&user_id).await?;
+                            backend.load_role_manager().await?;
+                            Ok::<_, crate::error::Error>(count)
+                        });
+                        match res {
+                            Ok(count) => {
+                                t_handle.block_on((log)(
+                                    "manage".into(),
+                                    format!("Synced {} tag group(s)", count),
+                                ));
+                                let _ = send_to_session
+                                    .blocking_send(format!("synced {} tag group(s)", count).into());
+                            }
+                            Err(e) => {
+                                let _ = send_to_session
+                                    .blocking_send(format!("sync tag groups error: {}", e).into());
+                            }
+                        }
+                    }
+                    cmd if cmd.split_whitespace().next() == Some(CMD_ROLE_GRAPH) => {
+                        let out = role_graph(cmd, backend.as_ref(), &t_handle);
+                        let _ = send_to_session.blocking_send(out.replace('\n', "\r\n").into());
+                    }
                     _ => {
                         let _ =
                             send_to_session.blocking_send(format!("Unknown command: {}", p).into());
@@ -123,6 +149,31 @@ pub(super) fn shell<B>(
     }
 }
 
+/// Prints a role graph as DOT, or as a tree with `tree`
+fn role_graph<B>(cmd: &str, backend: &B, t_handle: &tokio::runtime::Handle) -> String
+where
+    B: crate::server::HandlerBackend,
+{
+    let usage = format!(
+        "usage: {} <subject|object|action> [dot|tree]",
+        CMD_ROLE_GRAPH
+    );
+    let mut args = cmd.split_whitespace().skip(1);
+    let Some(rt) = args
+        .next()
+        .and_then(|kind| role_graph::KINDS.iter().find(|(k, _)| *k == kind))
+        .map(|(_, rt)| *rt)
+    else {
+        return usage;
+    };
+    let graph = t_handle.block_on(backend.get_graph(rt));
+    match (args.next(), args.next()) {
+        (None | Some("dot"), None) => role_graph::to_dot(&graph, rt),
+        (Some("tree"), None) => role_graph::to_tree(&graph),
+        _ => usage,
+    }
+}
+
 fn add_menu_keybindings(keybindings: &mut Keybindings) {
     keybindings.add_binding(
         KeyModifiers::NONE,
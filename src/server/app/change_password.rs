@@ -2,10 +2,12 @@ use crate::database::Uuid;
 use crate::database::models::User;
 use crate::error::Error;
 use crate::server::HandlerLog;
+use crate::server::breach::BreachFilter;
+use crate::server::totp;
 use crossbeam_channel::{Receiver, Sender, unbounded};
 use crossterm::event::{NoTtyEvent, SenderWriter};
 use inquire::{
-    Password, PasswordDisplayMode, min_length,
+    Password, PasswordDisplayMode, Text, min_length,
     validator::{StringValidator, Validation},
 };
 use log::{debug, warn};
@@ -35,11 +37,27 @@ struct OldPasswordValidator(User);
 
 impl StringValidator for OldPasswordValidator {
     fn validate(&self, input: &str) -> Result<Validation, inquire::error::CustomUserError> {
-        Ok(if !self.0.verify_password(input) {
+        Ok(if !self.0.password_reused(input) {
             Validation::Valid
         } else {
             Validation::Invalid(
-                "The new password cannot be the same as the original password".into(),
+                "The new password cannot be the current or a recently used password".into(),
+            )
+        })
+    }
+}
+
+/// Refuses passwords listed in the `breached_passwords` filter
+#[derive(Clone)]
+struct BreachedPasswordValidator(Arc<BreachFilter>);
+
+impl StringValidator for BreachedPasswordValidator {
+    fn validate(&self, input: &str) -> Result<Validation, inquire::error::CustomUserError> {
+        Ok(if !self.0.contains_password(input) {
+            Validation::Valid
+        } else {
+            Validation::Invalid(
+                "This password appeared in a known data breach, choose another one".into(),
             )
         })
     }
@@ -86,6 +104,22 @@ impl StringValidator for HasSpecialCharValidator {
     }
 }
 
+/// Accepts only a code of the secret being enrolled, so a mistyped key never
+/// gets stored
+#[derive(Clone)]
+struct TotpCodeValidator(String);
+
+impl StringValidator for TotpCodeValidator {
+    fn validate(&self, input: &str) -> Result<Validation, inquire::error::CustomUserError> {
+        let now = chrono::Utc::now().timestamp() as u64;
+        Ok(if totp::verify(&self.0, input, now).is_some() {
+            Validation::Valid
+        } else {
+            Validation::Invalid("Wrong code, check the clock of the device".into())
+        })
+    }
+}
+
 pub(crate) struct ChangePassword {
     handler_id: Uuid,
     tty: NoTtyEvent,
@@ -93,6 +127,8 @@ pub(crate) struct ChangePassword {
     recv_from_tty: Receiver<Vec<u8>>,
     user: Option<User>,
     log: HandlerLog,
+    // enroll a TOTP secret instead of changing the password
+    totp: bool,
 }
 
 enum Status {
@@ -111,17 +147,23 @@ impl ChangePassword {
             recv_from_tty,
             user,
             log,
+            totp: false,
         }
     }
 
+    pub(crate) fn with_totp(mut self, totp: bool) -> Self {
+        self.totp = totp;
+        self
+    }
+
     pub(crate) async fn window_change_request(
         &mut self,
-        _channel: ChannelId,
+        channel: ChannelId,
         col_width: u32,
         row_height: u32,
         pix_width: u32,
         pix_height: u32,
-        _session: &mut ru_server::Session,
+        session: &mut ru_server::Session,
     ) -> Result<(), Error> {
         let win_raw = crate::terminal::window_change(
             &mut self.tty,
@@ -134,6 +176,7 @@ impl ChangePassword {
         self.send_to_tty
             .send(win_raw)
             .map_err(std::io::Error::other)?;
+        session.channel_success(channel)?;
         Ok(())
     }
 
@@ -184,6 +227,14 @@ impl ChangePassword {
         let username = user.username.clone();
         let user_id = user.id;
         let log = self.log.clone();
+        let totp_secret = self.totp.then(totp::generate_secret);
+        let totp_secret_for_prompt = totp_secret.clone();
+        let breach_filter = backend.breach_filter();
+        let (done, failed) = if self.totp {
+            ("TOTP enrolled successfully", "TOTP enrollment failed")
+        } else {
+            ("password updated successfully", "password updated failed")
+        };
 
         tokio::spawn(async move {
             loop {
@@ -211,19 +262,28 @@ impl ChangePassword {
                             Some(s) => {
                                 match s {
                                     Status::Finish(password) => {
-                                        user.force_init_pass=false;
                                         let mut exit_status = 0;
-                                        if backend.update_user_password(password.clone(),user).await.is_err() {
+                                        let result = match totp_secret.as_deref() {
+                                            Some(secret) => match backend.set_totp_secret(&mut user, Some(secret)) {
+                                                Ok(()) => backend.db_repository().update_user(&user).await.map(|_| ()),
+                                                Err(e) => Err(e),
+                                            },
+                                            None => {
+                                                user.force_init_pass=false;
+                                                backend.update_user_password(password.clone(),user).await.map(|_| ())
+                                            }
+                                        };
+                                        if let Err(e) = result {
                                             exit_status = 1;
-                                            warn!("[{}] Password update failed for user '{}({})'", handler_id, username, user_id);
-                                            handle_prompt.data(channel, "\r\npassword updated failed.\r\n"
+                                            warn!("[{}] {} for user '{}({})': {}", handler_id, failed, username, user_id, e);
+                                            handle_prompt.data(channel, format!("\r\n{}.\r\n", failed).into_bytes()
                                                 ).await.is_err().then(|| warn!("[{}] Fail to send password prompt to session from prompt", handler_id));
 
                                         } else {
-                                            debug!("[{}] Password updated successfully for user '{}({})'", handler_id, username, user_id);
-                                            handle_prompt.data(channel, "\r\npassword updated successfully.\r\n"
+                                            debug!("[{}] {} for user '{}({})'", handler_id, done, username, user_id);
+                                            handle_prompt.data(channel, format!("\r\n{}.\r\n", done).into_bytes()
                                                 ).await.is_err().then(|| warn!("[{}] Fail to send password prompt to session from prompt", handler_id));
-                                            log(LOG_TYPE.into(),"password updated successfully".into()).await;
+                                            log(LOG_TYPE.into(),done.into()).await;
                                         }
                                         if handle_prompt.exit_status_request(channel,exit_status).await.is_err() {
                                             warn!("[{}] Fail to send exit status", handler_id);
@@ -256,9 +316,10 @@ impl ChangePassword {
             }
         });
         let handler_id = self.handler_id;
+        let username_for_prompt = user_for_prompt.username.clone();
 
         tokio::task::spawn_blocking(move || {
-            let validators: &[Box<dyn StringValidator>] = &[
+            let mut validators: Vec<Box<dyn StringValidator>> = vec![
                 Box::new(min_length!(8)),
                 Box::new(HasDigitValidator),
                 Box::new(HasUppercaseValidator),
@@ -266,15 +327,34 @@ impl ChangePassword {
                 Box::new(HasSpecialCharValidator),
                 Box::new(OldPasswordValidator(user_for_prompt)),
             ];
+            if let Some(filter) = breach_filter {
+                validators.push(Box::new(BreachedPasswordValidator(filter)));
+            }
 
-            let res = Password::new("New Password: ")
-                .with_display_toggle_enabled()
-                .with_display_mode(PasswordDisplayMode::Hidden)
-                .with_validators(validators)
-                .with_formatter(&|_| String::new())
-                .with_help_message("Please change your password")
-                .with_custom_confirmation_error_message("Passwords don't match.")
-                .prompt(tty, SenderWriter::new(send_to_session));
+            let res = match totp_secret_for_prompt {
+                Some(secret) => {
+                    let intro = format!(
+                        "Add this account to your authenticator app:\r\n\r\n  {}\r\n\r\nor enter the key by hand: {}\r\n\r\n",
+                        totp::otpauth_url(&username_for_prompt, &secret),
+                        secret
+                    );
+                    if send_to_session.blocking_send(intro.into_bytes()).is_err() {
+                        debug!("[{}] Fail to send TOTP key to session", handler_id);
+                    }
+                    Text::new("Verification code: ")
+                        .with_validator(TotpCodeValidator(secret))
+                        .with_help_message("Type the code shown by the app to turn TOTP on")
+                        .prompt(tty, SenderWriter::new(send_to_session))
+                }
+                None => Password::new("New Password: ")
+                    .with_display_toggle_enabled()
+                    .with_display_mode(PasswordDisplayMode::Hidden)
+                    .with_validators(&validators)
+                    .with_formatter(&|_| String::new())
+                    .with_help_message("Please change your password")
+                    .with_custom_confirmation_error_message("Passwords don't match.")
+                    .prompt(tty, SenderWriter::new(send_to_session)),
+            };
 
             let status = match res {
                 Ok(password) => Status::Finish(password),
@@ -396,4 +476,21 @@ mod tests {
             Ok(Validation::Invalid(_))
         ));
     }
+
+    #[test]
+    fn breached_passwords() {
+        // SHA-1 of "P@ssw0rd1", passes every rule above
+        let dump = "F2A12F187EBB7080BD75AAC9160214E6B1E49F7D:12\n";
+        let (filter, _) = BreachFilter::from_hibp(dump.as_bytes(), 1, 0.001).unwrap();
+        let breached = BreachedPasswordValidator(Arc::new(filter));
+        assert!(validate_all("P@ssw0rd1"));
+        assert!(matches!(
+            breached.validate("P@ssw0rd1"),
+            Ok(Validation::Invalid(_))
+        ));
+        assert!(matches!(
+            breached.validate("Str0ng&P@ssw0rd"),
+            Ok(Validation::Valid)
+        ));
+    }
 }
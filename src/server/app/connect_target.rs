@@ -1,31 +1,307 @@
+//! Sessions on a target. `ConnectTarget` checks each channel request
+//! against the policies (maintenance windows, quotas, command filters,
+//! observe-only shells, agent forwarding, allowed env) and bridges it to a
+//! channel on the target. The bridge enforces the policy deadline,
+//! `max_session` and idle limits, throttles to `rate=`, audits SCP, SFTP
+//! and ZMODEM transfers, watches the target handle and reopens a lost
+//! shell within `reconnect_window`.
+
 use crate::asciinema;
 use crate::database::Uuid;
-use crate::database::models::{SessionRecording, Target, TargetSecretName, User};
+use crate::database::models::target::endpoint;
+use crate::database::models::{
+    SessionRecording, SshSession, Target, TargetSecretName, Timestamp, User,
+};
 use crate::error::Error;
 use crate::server::app::error::AppError;
-use crate::server::{HandlerLog, casbin};
-use log::{debug, trace};
+use crate::server::casbin;
+use crate::server::command_filter::{CommandFilter, LineTracker};
+use crate::server::connection_pool::pool_key;
+use crate::server::error::ServerError;
+use crate::server::live_sessions::{Control, LiveEvent};
+use crate::server::motd::motd_for;
+use crate::server::remote_forward::{ForwardedTcpip, RemoteForwards};
+use crate::server::scp::{ScpAudit, ScpCommand};
+use crate::server::sftp_audit::{SFTP_SUBSYSTEM, SftpAudit};
+use crate::server::target_prompts::{PromptLogin, TargetLogin};
+use crate::server::throttle::{Rate, Throttle};
+use crate::server::zmodem::{self, ZmodemPolicy, ZmodemWatch};
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{debug, info, trace, warn};
 use russh::client as ru_client;
 use russh::server as ru_server;
 use russh::{Channel, ChannelId, ChannelMsg, ChannelReadHalf, ChannelWriteHalf, Pty};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::path::Path;
 use std::sync::Arc;
-use tokio::sync::{Mutex, mpsc};
-
-static LOG_TYPE: &str = "target";
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::{Mutex, broadcast, mpsc};
 
 /// Wrapper for session recording that includes the database metadata ID
 #[derive(Clone)]
 struct RecordingSession {
     session: asciinema::Session,
     recording_id: Uuid,
+    file_path: String,
+}
+
+type SharedThrottle = Arc<std::sync::Mutex<Throttle>>;
+
+/// A listener bound on the target for `tcpip-forward`, with the connection
+/// it lives on. Keeping the handle keeps the listener up when later
+/// channels get a connection of their own.
+struct RemoteForward {
+    address: String,
+    port: u32,
+    handle: Arc<ru_client::Handle<Target>>,
+}
+
+/// Follower of a bridged channel that copies files, each entry it returns
+/// becomes a log line of the channel's protocol
+enum TransferAudit {
+    Sftp(SftpAudit),
+    Scp(ScpAudit),
+}
+
+impl TransferAudit {
+    fn log_type(&self) -> &'static str {
+        match self {
+            TransferAudit::Sftp(_) => "sftp",
+            TransferAudit::Scp(_) => "scp",
+        }
+    }
+
+    fn is_following(&self) -> bool {
+        match self {
+            TransferAudit::Sftp(a) => a.is_following(),
+            TransferAudit::Scp(a) => a.is_following(),
+        }
+    }
+
+    fn client_data(&mut self, data: &[u8]) -> Vec<String> {
+        match self {
+            TransferAudit::Sftp(a) => a.client_data(data).iter().map(|e| e.to_string()).collect(),
+            TransferAudit::Scp(a) => a.client_data(data).iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    fn target_data(&mut self, data: &[u8]) -> Vec<String> {
+        match self {
+            TransferAudit::Sftp(a) => {
+                a.target_data(data);
+                Vec::new()
+            }
+            TransferAudit::Scp(a) => a.target_data(data).iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    /// What was still in progress when the channel ended
+    fn finish(&mut self) -> Vec<String> {
+        match self {
+            TransferAudit::Sftp(a) => a.finish().iter().map(|e| e.to_string()).collect(),
+            TransferAudit::Scp(_) => Vec::new(),
+        }
+    }
+}
+
+/// Longest access reason kept, in bytes
+const MAX_REASON_LEN: usize = 200;
+/// Longest answer to a target's prompt, in bytes
+const MAX_ANSWER_LEN: usize = 1024;
+/// Longest account name accepted under the `anyuser` policy flag
+const MAX_ACCOUNT_LEN: usize = 32;
+
+/// Account names picked by users go to the target as they are, so only
+/// portable user names are let through
+fn is_account_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_ACCOUNT_LEN
+        && !name.starts_with('-')
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+const REASON_PROMPT: &str = "Reason for access: ";
+const TARGET_PROMPTS_REFUSED: &str = "rustion: the target refused the answers\r\n";
+/// Every address failed or a timeout of `target_connect` ran out
+const TARGET_UNREACHABLE: &str = "target unreachable";
+/// Told to a shell user whose `rz`/`sz` transfer was kept from the other side
+const ZMODEM_BLOCKED: &str = "\r\nrustion: zmodem file transfer blocked by policy\r\n";
+const LOG_TYPE: &str = "command";
+/// Request of a session opened under `observe`
+const OBSERVE_REQUEST: &str = "observe";
+/// Told to an observer when the shell starts
+const OBSERVE_NOTICE: &str = "rustion: read-only session, your input is not sent to the target\r\n";
+/// Log type of the connections bridged through remote forwards
+const FORWARD_LOG_TYPE: &str = "forward";
+/// Log type of agent forwarding and each use the target makes of it
+const AGENT_LOG_TYPE: &str = "agent";
+/// Log type of the exec commands sent to targets
+const EXEC_LOG_TYPE: &str = "exec";
+/// Log type of the address each new target connection reached
+const CONNECT_LOG_TYPE: &str = "connect";
+/// Log type of the `rz`/`sz` transfers seen in shells
+const ZMODEM_LOG_TYPE: &str = "zmodem";
+/// Time left at which a session running into its policy deadline is warned
+const EXPIRY_WARNINGS: [Duration; 2] = [Duration::from_secs(10 * 60), Duration::from_secs(60)];
+/// Termination reason of a session closed at the end of its policy
+const ACCESS_EXPIRED: &str = "access expired";
+/// Termination reason of a session closed at the policy's `max_session`
+const POLICY_EXPIRED: &str = "policy_expired";
+/// Longest notice an idle session gets before it is closed
+const IDLE_WARNING: Duration = Duration::from_secs(60);
+/// Termination reason of a session closed by `idle_session_timeout`
+const IDLE_TIMEOUT: &str = "idle timeout";
+/// Termination reason of a session whose target stopped answering keepalives
+const TARGET_LOST: &str = "target connection lost";
+/// Wait between attempts to reopen a shell whose target was lost
+const RECONNECT_PAUSE: Duration = Duration::from_secs(5);
+/// Termination reason of a session closed from the admin Live tab, followed
+/// by the admin's reason
+const KILLED_BY_ADMIN: &str = "terminated by admin";
+
+/// Warnings due for a session with `left` until its deadline, as (wait from
+/// now, time left then). A session starting inside the first warning period
+/// is told at once.
+fn expiry_warnings(left: Duration) -> Vec<(Duration, Duration)> {
+    let mut warnings = Vec::new();
+    if left <= EXPIRY_WARNINGS[0] {
+        warnings.push((Duration::ZERO, left));
+    }
+    for w in EXPIRY_WARNINGS {
+        if left > w {
+            warnings.push((left - w, w));
+        }
+    }
+    warnings
+}
+
+/// How long before `timeout` an idle session is warned, halfway through
+/// when the timeout is shorter than twice `IDLE_WARNING`
+fn idle_warning(timeout: Duration) -> Duration {
+    IDLE_WARNING.min(timeout / 2)
+}
+
+/// A channel without activity for `after` is warned, and closed `grace`
+/// later unless it wakes up
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct IdleLimit {
+    after: Duration,
+    grace: Duration,
+}
+
+impl IdleLimit {
+    /// The policy's `idle=` and `idle_grace=` take the place of what
+    /// `idle_session_timeout` implies. `None` when neither sets a limit.
+    fn new(
+        timeout: Option<Duration>,
+        idle: Option<Duration>,
+        idle_grace: Option<Duration>,
+    ) -> Option<Self> {
+        let after = idle.or_else(|| timeout.map(|t| t - idle_warning(t)))?;
+        let grace = idle_grace
+            .or_else(|| timeout.map(idle_warning))
+            .unwrap_or(IDLE_WARNING);
+        Some(Self { after, grace })
+    }
+}
+
+/// Shell request parked until the user has typed an access reason
+struct PendingShell {
+    term: String,
+    window_size: (u32, u32, u32, u32),
+    modes: Vec<(Pty, u32)>,
+    input: Vec<u8>,
+}
+
+/// Shell request parked while the user answers the target's prompts
+struct PendingPrompts {
+    shell: PendingShell,
+    login: PromptLogin,
+    /// Whether the answer being typed is echoed
+    echo: bool,
+    /// Shown once the target has let the user in
+    motd: Option<String>,
+}
+
+/// What a channel's pty was asked for with, to open a shell again on a new
+/// target connection. The size follows window changes.
+#[derive(Clone)]
+struct PtyRequest {
+    term: String,
+    modes: Vec<(Pty, u32)>,
+    size: Arc<std::sync::Mutex<(u32, u32, u32, u32)>>,
+}
+
+/// Hand-over between a bridge reopening its shell on a new target
+/// connection and the handler writing to the shell
+#[derive(Default)]
+struct Reconnect {
+    /// Set while the target is away, client input is dropped meanwhile
+    pending: AtomicBool,
+    /// Target side of the reopened shell, taken by the next write
+    channel: std::sync::Mutex<Option<TargetChannel>>,
+}
+
+/// Everything a bridge needs to reopen its shell on a new target connection
+struct Reopen {
+    pty: PtyRequest,
+    env: Vec<(String, String)>,
+    window: Duration,
+    target_secret_id: Uuid,
+    login_as: Option<String>,
+    reconnect: Arc<Reconnect>,
+}
+
+/// Where a line typed at one of our prompts got
+enum LineInput {
+    Partial,
+    Entered,
+    /// Ctrl-C or Ctrl-D
+    Aborted,
+}
+
+/// Line editing for our prompts: adds `data` to `input`, up to `max` bytes,
+/// and returns what to echo back, nothing unless `echo`
+fn edit_line(input: &mut Vec<u8>, data: &[u8], max: usize, echo: bool) -> (LineInput, Vec<u8>) {
+    let mut out = Vec::new();
+    for &b in data {
+        match b {
+            b'\r' | b'\n' => return (LineInput::Entered, out),
+            0x03 | 0x04 => return (LineInput::Aborted, out),
+            0x7f | 0x08 => {
+                if input.is_empty() {
+                    continue;
+                }
+                // Drop a whole UTF-8 character, not just its last byte
+                while let Some(c) = input.pop() {
+                    if c & 0xc0 != 0x80 {
+                        break;
+                    }
+                }
+                if echo {
+                    out.extend_from_slice(b"\x08 \x08");
+                }
+            }
+            b if b >= 0x20 && input.len() < max => {
+                input.push(b);
+                if echo {
+                    out.push(b);
+                }
+            }
+            _ => {}
+        }
+    }
+    (LineInput::Partial, out)
 }
 
 #[derive(Clone, Copy)]
 pub enum Request<'a> {
     Shell,
     Exec(&'a [u8]),
+    Subsystem(&'a str),
     OpenDirectTcpip((&'a str, u32, &'a str, u32)),
 }
 
@@ -39,14 +315,81 @@ pub(crate) struct ConnectTarget {
     target_channel: HashMap<ChannelId, TargetChannel>,
     target_handle: Option<Arc<ru_client::Handle<Target>>>,
     target_sec_name: Option<TargetSecretName>,
+    // change/ticket reference attached at login
+    ticket: Option<String>,
+    // granting policy asked for no bastion notices on exec and tunnels
+    quiet: bool,
+    // granting policy wants an access reason before the shell starts
+    require_reason: bool,
+    reason: Option<String>,
+    // account picked with `user@account@target` under an `anyuser` policy
+    login_as: Option<String>,
+    // granting policy runs out, bridged channels are closed then
+    deadline: Option<DateTime<Utc>>,
+    // the session reaches the granting policy's `max_session`
+    session_limit: Option<DateTime<Utc>>,
+    // when bridged channels without activity are warned and closed
+    idle: Option<IdleLimit>,
+    started_at: DateTime<Utc>,
+    // the target selector printed the MOTD before handing over
+    motd_shown: bool,
+    // why `check_permission` last turned the user away, when they should
+    // be told
+    refusal: Option<String>,
+    // granting policy's `cmd=` filter, with the line typed on each shell
+    command_filter: Option<Arc<CommandFilter>>,
+    // granting policy's `ports=` range
+    ports: Option<(u16, u16)>,
+    // the last shell was granted by `observe` rather than `shell`, and the
+    // shells whose input is dropped for it
+    observe: bool,
+    observers: HashSet<ChannelId>,
+    // granting policy's `zmodem=`, else the `zmodem` setting
+    zmodem: ZmodemPolicy,
+    // client side of each shell whose ZMODEM transfers are blocked
+    zmodem_input: HashMap<ChannelId, ZmodemWatch>,
+    // bytes per second each way, the lower of `limits` and the granting
+    // policy's `rate=`, shared by all channels of the session
+    rate: Rate,
+    throttle_up: Option<SharedThrottle>,
+    throttle_down: Option<SharedThrottle>,
+    // listeners bound on the target for `tcpip-forward`, and where the
+    // connections they accept are sent
+    remote_forwards: Vec<RemoteForward>,
+    forward_route: Option<(Arc<RemoteForwards>, mpsc::UnboundedSender<ForwardedTcpip>)>,
+    // where target connections send their agent channels once the client
+    // forwarded its agent, and the channels to ask the target for it on
+    agent: Option<mpsc::UnboundedSender<Channel<ru_client::Msg>>>,
+    agent_channels: HashSet<ChannelId>,
+    shell_input: HashMap<ChannelId, LineTracker>,
+    // allowed `env` requests of each channel, set on the target channel
+    // before its shell, exec or subsystem
+    env: HashMap<ChannelId, Vec<(String, String)>>,
+    pending_shell: HashMap<ChannelId, PendingShell>,
+    // keyboard-interactive login a target handed over, until the shell that
+    // asked for it is parked, and the handle it gave once answered
+    prompt_login: Option<PromptLogin>,
+    prompted: Option<Arc<ru_client::Handle<Target>>>,
+    pending_prompts: HashMap<ChannelId, PendingPrompts>,
     notify: HashMap<ChannelId, mpsc::Sender<()>>,
 
     record_session: HashMap<ChannelId, Arc<Mutex<RecordingSession>>>,
-    log: HandlerLog,
+    // protocol follower of each bridged sftp or scp channel
+    transfer_audit: HashMap<ChannelId, Arc<Mutex<TransferAudit>>>,
+    // client to target byte count of each bridged channel
+    bytes_in: HashMap<ChannelId, Arc<AtomicU64>>,
+    // `(cols, rows)` of each channel's pty, and its id in the live sessions
+    // admins watch
+    pty_size: HashMap<ChannelId, (u16, u16)>,
+    // pty each channel asked for, and the hand-over of the shells reopened
+    // after their target was lost
+    pty_requests: HashMap<ChannelId, PtyRequest>,
+    reconnects: HashMap<ChannelId, Arc<Reconnect>>,
+    live: HashMap<ChannelId, u64>,
 }
 
 impl ConnectTarget {
-    pub(crate) fn new(id: Uuid, user: Option<User>, log: HandlerLog) -> Self {
+    pub(crate) fn new(id: Uuid, user: Option<User>) -> Self {
         Self {
             handler_id: id,
             user,
@@ -54,9 +397,44 @@ impl ConnectTarget {
             target_channel: HashMap::with_capacity(3),
             target_handle: None,
             target_sec_name: None,
+            ticket: None,
+            quiet: false,
+            require_reason: false,
+            reason: None,
+            login_as: None,
+            deadline: None,
+            session_limit: None,
+            started_at: Utc::now(),
+            motd_shown: false,
+            refusal: None,
+            command_filter: None,
+            ports: None,
+            observe: false,
+            observers: HashSet::new(),
+            zmodem: ZmodemPolicy::default(),
+            zmodem_input: HashMap::new(),
+            idle: None,
+            rate: Rate::default(),
+            throttle_up: None,
+            throttle_down: None,
+            remote_forwards: Vec::new(),
+            forward_route: None,
+            agent: None,
+            agent_channels: HashSet::new(),
+            shell_input: HashMap::new(),
+            env: HashMap::new(),
+            pending_shell: HashMap::new(),
+            prompt_login: None,
+            prompted: None,
+            pending_prompts: HashMap::new(),
             notify: HashMap::with_capacity(3),
             record_session: HashMap::with_capacity(3),
-            log,
+            transfer_audit: HashMap::new(),
+            bytes_in: HashMap::with_capacity(3),
+            pty_size: HashMap::new(),
+            pty_requests: HashMap::new(),
+            reconnects: HashMap::new(),
+            live: HashMap::with_capacity(3),
         }
     }
 
@@ -70,28 +448,291 @@ impl ConnectTarget {
         self
     }
 
-    pub(crate) async fn data(
+    pub(crate) fn with_ticket(mut self, val: Option<String>) -> Self {
+        self.ticket = val;
+        self
+    }
+
+    pub(crate) fn with_motd_shown(mut self, val: bool) -> Self {
+        self.motd_shown = val;
+        self
+    }
+
+    /// Keeps the buckets of a rate already in force, so opening another
+    /// channel doesn't refill them
+    fn set_rate(&mut self, rate: Rate) {
+        if rate == self.rate {
+            return;
+        }
+        let now = std::time::Instant::now();
+        let shared = |r| Throttle::new(r, now).map(|t| Arc::new(std::sync::Mutex::new(t)));
+        self.throttle_up = shared(rate.up);
+        self.throttle_down = shared(rate.down);
+        self.rate = rate;
+    }
+
+    pub(crate) async fn data<B>(
         &mut self,
+        backend: Arc<B>,
         channel: ChannelId,
         data: &[u8],
-        _session: &mut ru_server::Session,
-    ) -> Result<(), Error> {
-        if let Some(w) = self.target_channel.get(&channel) {
-            w.data(data).await?
+        session: &mut ru_server::Session,
+    ) -> Result<(), Error>
+    where
+        B: 'static + crate::server::HandlerBackend + Send + Sync,
+    {
+        if let Some(pending) = self.pending_shell.remove(&channel) {
+            return self
+                .read_reason(backend, channel, pending, data, session)
+                .await;
+        }
+        if let Some(pending) = self.pending_prompts.remove(&channel) {
+            return self
+                .read_prompt(backend, channel, pending, data, session)
+                .await;
+        }
+        // Whatever an observer types goes nowhere
+        if self.observers.contains(&channel) {
+            return Ok(());
+        }
+        let data = self
+            .block_zmodem_input(backend.as_ref(), channel, data, session)
+            .await?;
+        let input = self
+            .filter_shell_input(backend.as_ref(), channel, &data, session)
+            .await?;
+        if let Some(w) = self.target_writer(channel) {
+            throttle(&self.throttle_up, input.len()).await;
+            w.data(input.as_ref()).await?
+        }
+        if let Some(c) = self.bytes_in.get(&channel) {
+            c.fetch_add(input.len() as u64, Ordering::Relaxed);
         }
         if let Some(r) = self.record_session.get(&channel) {
-            r.lock().await.session.handle_input(data).await;
+            r.lock().await.session.handle_input(&data).await;
+        }
+        if let Some(a) = self.transfer_audit.get(&channel) {
+            let mut audit = a.lock().await;
+            let events = audit.client_data(&data);
+            if let (Some(user), Some(target)) = (self.user.as_ref(), self.target.as_ref()) {
+                for event in events {
+                    log_transfer(
+                        backend.as_ref(),
+                        self.handler_id,
+                        user.id,
+                        audit.log_type(),
+                        &target.name,
+                        &event,
+                    )
+                    .await;
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// Input of a shell under `zmodem=block`, cut at a ZMODEM header and led
+    /// by `zmodem::CANCEL` once a transfer was kept from either side
+    async fn block_zmodem_input<'a, B>(
+        &mut self,
+        backend: &B,
+        channel: ChannelId,
+        data: &'a [u8],
+        session: &mut ru_server::Session,
+    ) -> Result<std::borrow::Cow<'a, [u8]>, Error>
+    where
+        B: crate::server::HandlerBackend,
+    {
+        let Some(watch) = self.zmodem_input.get_mut(&channel) else {
+            return Ok(data.into());
+        };
+        let checked = watch.check(data);
+        let cancel = watch.take_cancel();
+        let event = checked.started.map(|d| watch.event(d));
+        if let (Some(event), Some(user), Some(target)) =
+            (event, self.user.as_ref(), self.target.as_ref())
+        {
+            session.extended_data(channel, 1, ZMODEM_BLOCKED.as_bytes().to_vec())?;
+            log_transfer(
+                backend,
+                self.handler_id,
+                user.id,
+                ZMODEM_LOG_TYPE,
+                &target.name,
+                event,
+            )
+            .await;
+        }
+        let data = &data[..checked.pass];
+        if !cancel {
+            return Ok(data.into());
+        }
+        Ok([zmodem::CANCEL, data].concat().into())
+    }
+
+    /// Input of a filtered shell with each refused line ended by Ctrl-C in
+    /// place of Enter, so the target's shell drops it
+    async fn filter_shell_input<'a, B>(
+        &mut self,
+        backend: &B,
+        channel: ChannelId,
+        data: &'a [u8],
+        session: &mut ru_server::Session,
+    ) -> Result<std::borrow::Cow<'a, [u8]>, Error>
+    where
+        B: crate::server::HandlerBackend,
+    {
+        let (Some(tracker), Some(filter)) = (
+            self.shell_input.get_mut(&channel),
+            self.command_filter.as_ref(),
+        ) else {
+            return Ok(data.into());
+        };
+        let mut input = Vec::with_capacity(data.len());
+        let mut refused = Vec::new();
+        for &b in data {
+            if let Some(line) = tracker.push(b)
+                && let Some(why) = filter.check(&line)
+            {
+                input.push(0x03);
+                refused.push((line, why));
+                continue;
+            }
+            input.push(b);
+        }
+        for (line, why) in refused {
+            session.extended_data(
+                channel,
+                1,
+                b"\r\nrustion: command refused by policy\r\n".to_vec(),
+            )?;
+            self.log_refused(backend, &line, &why).await;
+        }
+        Ok(input.into())
+    }
+
+    /// Operation log entry of a command the policy's filter refused
+    async fn log_refused<B>(&self, backend: &B, command: &str, why: &str)
+    where
+        B: crate::server::HandlerBackend,
+    {
+        let Some(user) = self.user.as_ref() else {
+            return;
+        };
+        let target = self.target.as_ref().map_or("", |t| t.name.as_str());
+        debug!(
+            "[{}] Refused command of '{}' on '{}': {}",
+            self.handler_id, user.username, target, why
+        );
+        backend
+            .insert_log(
+                self.handler_id,
+                user.id,
+                LOG_TYPE.into(),
+                format!("refused on {}: {} ({})", target, command, why),
+            )
+            .await;
+    }
+
+    /// Writes the command of an exec about to be bridged to the `exec` log
+    async fn log_exec<B>(&self, backend: &B, command: &str)
+    where
+        B: crate::server::HandlerBackend,
+    {
+        let (Some(user), Some(entry)) = (self.user.as_ref(), backend.exec_log().entry(command))
+        else {
+            return;
+        };
+        let target = self.target.as_ref().map_or("", |t| t.name.as_str());
+        let account = match &self.login_as {
+            Some(a) => a.as_str(),
+            None => self
+                .target_sec_name
+                .as_ref()
+                .map_or("", |t| t.secret_user.as_str()),
+        };
+        backend
+            .insert_log(
+                self.handler_id,
+                user.id,
+                EXEC_LOG_TYPE.into(),
+                format!("{}@{}: {}", account, target, entry),
+            )
+            .await;
+    }
+
+    /// Target side of `channel`, the reopened one once a bridge has
+    /// reconnected it. `None` while it reconnects.
+    fn target_writer(&mut self, channel: ChannelId) -> Option<&TargetChannel> {
+        if let Some(r) = self.reconnects.get(&channel) {
+            if let Some(w) = r.channel.lock().unwrap().take() {
+                self.target_channel.insert(channel, w);
+            } else if r.pending.load(Ordering::Relaxed) {
+                return None;
+            }
+        }
+        self.target_channel.get(&channel)
+    }
+
+    /// Closes the target side of a channel whose bridge has ended, as when
+    /// an admin terminated it or its access expired. The target keeps a
+    /// channel the client closed mid-session until the connection drops.
+    pub(crate) async fn channel_close(&mut self, channel: ChannelId) -> Result<(), Error> {
+        self.env.remove(&channel);
+        self.pending_prompts.remove(&channel);
+        self.zmodem_input.remove(&channel);
+        self.pty_requests.remove(&channel);
+        self.observers.remove(&channel);
+        if !self.notify.get(&channel).is_some_and(|n| n.is_closed()) {
+            return Ok(());
+        }
+        self.notify.remove(&channel);
+        self.target_writer(channel);
+        self.reconnects.remove(&channel);
+        if let Some(w) = self.target_channel.remove(&channel) {
+            w.close().await?
+        }
+
+        Ok(())
+    }
+
+    /// Keeps `name=value` for the target channel when `env_allowlist` lets
+    /// it through, logs and drops it otherwise
+    pub(crate) fn env_request<B>(
+        &mut self,
+        backend: Arc<B>,
+        channel: ChannelId,
+        name: &str,
+        value: &str,
+    ) -> bool
+    where
+        B: 'static + crate::server::HandlerBackend + Send + Sync,
+    {
+        if !backend.env_allowed(name) {
+            info!(
+                "[{}] channel {:?}: env {} dropped, not in env_allowlist",
+                self.handler_id, channel, name
+            );
+            return false;
+        }
+        self.env
+            .entry(channel)
+            .or_default()
+            .push((name.to_string(), value.to_string()));
+        true
+    }
+
     pub(crate) async fn channel_eof(
         &mut self,
         channel: ChannelId,
         _session: &mut ru_server::Session,
     ) -> Result<(), Error> {
-        if let Some(w) = self.target_channel.get(&channel) {
+        // It would end the shell being watched
+        if self.observers.contains(&channel) {
+            return Ok(());
+        }
+        if let Some(w) = self.target_writer(channel) {
             w.eof().await?
         }
 
@@ -110,14 +751,35 @@ impl ConnectTarget {
             return Ok(false);
         };
 
-        let target_secret_name = match backend
-            .list_targets_for_user(&user.id, true)
-            .await?
-            .into_iter()
+        let targets = backend.list_targets_for_user(&user.id, true).await?;
+        let exact = targets
+            .iter()
             .find(|t| t.target_name == target_name && t.secret_user == target_user)
-        {
-            Some(t) => t,
-            None => {
+            .cloned();
+        let any_user = match exact {
+            Some(_) => None,
+            None if is_account_name(target_user) => {
+                any_user_binding(backend.clone(), &targets, target_name).await?
+            }
+            None => None,
+        };
+        let target_secret_name = match (exact, any_user) {
+            (Some(t), _) => t,
+            (None, Some(mut t)) => {
+                debug!(
+                    "[{}] User '{}({})' logs into '{}' as '{}' in place of '{}'",
+                    self.handler_id,
+                    user.username,
+                    user.id,
+                    target_name,
+                    target_user,
+                    t.secret_user
+                );
+                t.secret_user = target_user.to_string();
+                self.login_as = Some(target_user.to_string());
+                t
+            }
+            (None, None) => {
                 debug!(
                     "[{}] No target with secret user found for user: '{}({})', target: '{}@{}'",
                     self.handler_id, &user.username, user.id, target_user, target_name
@@ -135,62 +797,374 @@ impl ConnectTarget {
             return Ok(false);
         };
 
-        self.target_sec_name = Some(target_secret_name);
         debug!(
-            "[{}] Target initialized: user='{}({})' target: '{}@{}'",
-            self.handler_id, user.username, user.id, target_user, target_name,
+            "[{}] Target initialized: user='{}({})' target: '{}' {}",
+            self.handler_id,
+            user.username,
+            user.id,
+            target_name,
+            target_secret_name.ssh_url(),
         );
+        self.target_sec_name = Some(target_secret_name);
+
+        Ok(true)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn channel_open_direct_tcpip<B>(
+        &mut self,
+        backend: Arc<B>,
+        channel: Channel<ru_server::Msg>,
+        host_to_connect: &str,
+        port_to_connect: u32,
+        originator_address: &str,
+        originator_port: u32,
+        session: &mut ru_server::Session,
+    ) -> Result<bool, Error>
+    where
+        B: 'static + crate::server::HandlerBackend + Send + Sync,
+    {
+        if self.reason_missing() {
+            debug!(
+                "[{}] Refused direct-tcpip to {}:{}, an access reason is required",
+                self.handler_id, host_to_connect, port_to_connect
+            );
+            return Ok(false);
+        }
+        if !casbin::is_port_allowed(port_to_connect, self.ports) {
+            debug!(
+                "[{}] Refused direct-tcpip to {}:{}, the port is outside the policy's ports",
+                self.handler_id, host_to_connect, port_to_connect
+            );
+            return Ok(false);
+        }
+        match self
+            .do_channel_open_direct_tcpip(
+                backend,
+                channel,
+                host_to_connect,
+                port_to_connect,
+                originator_address,
+                originator_port,
+                session,
+            )
+            .await
+        {
+            Err(Error::Russh(russh::Error::ChannelOpenFailure(_))) => Ok(false),
+            res => res,
+        }
+    }
+
+    /// `ssh -J`: the client talks to the sshd of the target itself and logs
+    /// in there, the bastion only carries the bytes until the granting
+    /// policy runs out. The target's own port is used whatever port the
+    /// client asked for.
+    pub(crate) async fn open_jump<B>(
+        &mut self,
+        backend: Arc<B>,
+        channel: Channel<ru_server::Msg>,
+    ) -> Result<bool, Error>
+    where
+        B: 'static + crate::server::HandlerBackend + Send + Sync,
+    {
+        let (Some(user), Some(target)) = (self.user.as_ref(), self.target.as_ref()) else {
+            return Ok(false);
+        };
+        if self.reason_missing() {
+            debug!(
+                "[{}] Refused jump to {}, an access reason is required",
+                self.handler_id, target.name
+            );
+            return Ok(false);
+        }
+        if !casbin::is_port_allowed(target.port as u32, self.ports) {
+            debug!(
+                "[{}] Refused jump to {}, port {} is outside the policy's ports",
+                self.handler_id, target.name, target.port
+            );
+            return Ok(false);
+        }
+        // The target's addresses in order, the first that answers is used
+        let mut connected = None;
+        for host in target.addresses() {
+            let address = endpoint(host, target.port);
+            match tokio::net::TcpStream::connect((host, target.port)).await {
+                Ok(s) => {
+                    connected = Some((s, address));
+                    break;
+                }
+                Err(e) => warn!(
+                    "[{}] Jump to {} ({}) failed: {}",
+                    self.handler_id, target.name, address, e
+                ),
+            }
+        }
+        let Some((mut stream, address)) = connected else {
+            return Ok(false);
+        };
+        backend
+            .insert_log(
+                self.handler_id,
+                user.id,
+                FORWARD_LOG_TYPE.into(),
+                format!("jump to {} ({})", target.name, address),
+            )
+            .await;
+
+        let left = [self.deadline, self.session_limit]
+            .into_iter()
+            .flatten()
+            .min()
+            .map(|end| (end - Utc::now()).to_std().unwrap_or_default());
+        let (handler_id, name) = (self.handler_id, target.name.clone());
+        tokio::spawn(async move {
+            let mut client = channel.into_stream();
+            let copy = tokio::io::copy_bidirectional(&mut client, &mut stream);
+            let res = match left {
+                Some(left) => match tokio::time::timeout(left, copy).await {
+                    Ok(res) => res,
+                    Err(_) => {
+                        debug!(
+                            "[{}] Jump to {} closed, {}",
+                            handler_id, name, ACCESS_EXPIRED
+                        );
+                        return;
+                    }
+                },
+                None => copy.await,
+            };
+            match res {
+                Ok((up, down)) => debug!(
+                    "[{}] Jump to {} closed after {} bytes up, {} bytes down",
+                    handler_id, name, up, down
+                ),
+                Err(e) => debug!("[{}] Jump to {} failed: {}", handler_id, name, e),
+            }
+        });
+        Ok(true)
+    }
 
+    /// Asks the target to listen on `address:port` for the client. `port`
+    /// 0 lets the target pick one, which is written back.
+    pub(crate) async fn tcpip_forward<B>(
+        &mut self,
+        backend: Arc<B>,
+        address: &str,
+        port: &mut u32,
+        session: &mut ru_server::Session,
+    ) -> Result<bool, Error>
+    where
+        B: 'static + crate::server::HandlerBackend + Send + Sync,
+    {
+        if self.reason_missing() {
+            debug!(
+                "[{}] Refused tcpip-forward on {}:{}, an access reason is required",
+                self.handler_id, address, port
+            );
+            return Ok(false);
+        }
+        if self.target_handle.is_none() {
+            self.do_connect_to_target(backend.clone(), false, false)
+                .await?;
+        }
+        let (Some(handle), Some(target), Some(user)) = (
+            self.target_handle.as_ref(),
+            self.target.as_ref(),
+            self.user.as_ref(),
+        ) else {
+            return Ok(false);
+        };
+        let bound = match handle.tcpip_forward(address, *port).await {
+            Ok(0) => *port,
+            Ok(bound) => bound,
+            Err(e) => {
+                debug!(
+                    "[{}] Target '{}' refused to listen on {}:{}: {}",
+                    self.handler_id, target.name, address, port, e
+                );
+                return Ok(false);
+            }
+        };
+        // Checked once bound, a port picked by the target must fit as well
+        if !casbin::is_port_allowed(bound, self.ports) {
+            debug!(
+                "[{}] Refused tcpip-forward on {}:{}, the port is outside the policy's ports",
+                self.handler_id, address, bound
+            );
+            let _ = handle.cancel_tcpip_forward(address, bound).await;
+            return Ok(false);
+        }
+
+        let (routes, sender) = self.forward_route.get_or_insert_with(|| {
+            let (sender, recv) = mpsc::unbounded_channel();
+            tokio::spawn(serve_remote_forwards(
+                backend.clone(),
+                session.handle(),
+                recv,
+                self.handler_id,
+                user.id,
+                target.name.clone(),
+            ));
+            (backend.remote_forwards(), sender)
+        });
+        routes.register(target.id, address, bound, sender.clone());
+        self.remote_forwards.push(RemoteForward {
+            address: address.to_string(),
+            port: bound,
+            handle: handle.clone(),
+        });
+        backend
+            .insert_log(
+                self.handler_id,
+                user.id,
+                FORWARD_LOG_TYPE.into(),
+                format!("listen on {}:{} of {}", address, bound, target.name),
+            )
+            .await;
+        *port = bound;
+        Ok(true)
+    }
+
+    pub(crate) async fn cancel_tcpip_forward(
+        &mut self,
+        address: &str,
+        port: u32,
+    ) -> Result<bool, Error> {
+        let Some(i) = self
+            .remote_forwards
+            .iter()
+            .position(|f| f.address == address && f.port == port)
+        else {
+            return Ok(false);
+        };
+        let forward = self.remote_forwards.swap_remove(i);
+        if let (Some((routes, _)), Some(target)) =
+            (self.forward_route.as_ref(), self.target.as_ref())
+        {
+            routes.unregister(target.id, address, port);
+        }
+        forward.handle.cancel_tcpip_forward(address, port).await?;
+        Ok(true)
+    }
+
+    /// The client forwards its agent on `channel`. The target is asked for
+    /// agent forwarding on that channel, and connections made from now on
+    /// skip the pool so their agent channels reach only this session.
+    pub(crate) async fn agent_request<B>(
+        &mut self,
+        backend: Arc<B>,
+        channel: ChannelId,
+        session: &mut ru_server::Session,
+    ) -> Result<bool, Error>
+    where
+        B: 'static + crate::server::HandlerBackend + Send + Sync,
+    {
+        let (Some(target), Some(user)) = (self.target.as_ref(), self.user.as_ref()) else {
+            return Ok(false);
+        };
+        if self.agent.is_none() {
+            let (sender, recv) = mpsc::unbounded_channel();
+            tokio::spawn(serve_agent(
+                backend.clone(),
+                session.handle(),
+                recv,
+                self.handler_id,
+                user.id,
+                target.name.clone(),
+            ));
+            self.agent = Some(sender);
+        }
+        self.agent_channels.insert(channel);
+        backend
+            .insert_log(
+                self.handler_id,
+                user.id,
+                AGENT_LOG_TYPE.into(),
+                format!("agent forwarded to {}", target.name),
+            )
+            .await;
         Ok(true)
     }
 
     #[allow(clippy::too_many_arguments)]
-    pub(crate) async fn channel_open_direct_tcpip<B>(
+    pub(crate) async fn exec_request<B>(
         &mut self,
         backend: Arc<B>,
-        channel: Channel<ru_server::Msg>,
-        host_to_connect: &str,
-        port_to_connect: u32,
-        originator_address: &str,
-        originator_port: u32,
+        channel: ChannelId,
+        data: &[u8],
         session: &mut ru_server::Session,
-    ) -> Result<bool, Error>
+        term: Option<&String>,
+        window_size: Option<(u32, u32, u32, u32)>,
+        modes: Option<&Vec<(Pty, u32)>>,
+    ) -> Result<(), Error>
     where
         B: 'static + crate::server::HandlerBackend + Send + Sync,
     {
-        match self
-            .do_channel_open_direct_tcpip(
-                backend,
+        if self.reason_missing() {
+            session.extended_data(
                 channel,
-                host_to_connect,
-                port_to_connect,
-                originator_address,
-                originator_port,
-                session,
-            )
+                1,
+                b"rustion: an access reason is required, open an interactive shell instead\r\n"
+                    .to_vec(),
+            )?;
+            session.channel_failure(channel)?;
+            session.close(channel)?;
+            return Ok(());
+        }
+        let command = String::from_utf8_lossy(data);
+        if let Some(why) = self.command_filter.as_ref().and_then(|f| f.check(&command)) {
+            session.extended_data(
+                channel,
+                1,
+                b"rustion: command refused by policy\r\n".to_vec(),
+            )?;
+            session.channel_failure(channel)?;
+            session.close(channel)?;
+            self.log_refused(backend.as_ref(), &command, &why).await;
+            return Ok(());
+        }
+        self.log_exec(backend.as_ref(), &command).await;
+        match self
+            .do_exec_request(backend, data, term, window_size, modes, channel, session)
             .await
         {
-            Err(Error::Russh(russh::Error::ChannelOpenFailure(_))) => Ok(false),
-            res => res,
+            Ok(_) => {
+                session.channel_success(channel)?;
+                Ok(())
+            }
+            Err(e) => {
+                session.channel_failure(channel)?;
+                Err(e)
+            }
         }
     }
 
-    #[allow(clippy::too_many_arguments)]
-    pub(crate) async fn exec_request<B>(
+    /// Bridges a subsystem channel, `sftp` or one listed in `subsystems`.
+    /// Packets of `sftp` go through an `SftpAudit` that logs the files moved,
+    /// the others are passed on as they are.
+    pub(crate) async fn subsystem_request<B>(
         &mut self,
         backend: Arc<B>,
         channel: ChannelId,
-        data: &[u8],
+        name: &str,
         session: &mut ru_server::Session,
-        term: Option<&String>,
-        window_size: Option<(u32, u32, u32, u32)>,
-        modes: Option<&Vec<(Pty, u32)>>,
     ) -> Result<(), Error>
     where
         B: 'static + crate::server::HandlerBackend + Send + Sync,
     {
+        if self.reason_missing() {
+            session.extended_data(
+                channel,
+                1,
+                b"rustion: an access reason is required, open an interactive shell instead\r\n"
+                    .to_vec(),
+            )?;
+            session.channel_failure(channel)?;
+            session.close(channel)?;
+            return Ok(());
+        }
         match self
-            .do_exec_request(backend, data, term, window_size, modes, channel, session)
+            .do_subsystem_request(backend, name, channel, session)
             .await
         {
             Ok(_) => {
@@ -216,6 +1190,25 @@ impl ConnectTarget {
     where
         B: 'static + crate::server::HandlerBackend + Send + Sync,
     {
+        // Rendered before connecting, which records this session as the last
+        let motd = self.motd(backend.as_ref()).await;
+        if self.reason_missing() {
+            self.pending_shell.insert(
+                channel,
+                PendingShell {
+                    term: term.to_string(),
+                    window_size,
+                    modes: modes.to_vec(),
+                    input: Vec::new(),
+                },
+            );
+            session.channel_success(channel)?;
+            if let Some(motd) = motd {
+                session.data(channel, motd.into_bytes())?;
+            }
+            session.data(channel, REASON_PROMPT.as_bytes().to_vec())?;
+            return Ok(());
+        }
         match self
             .connect_to_target_with_shell(
                 backend.clone(),
@@ -229,6 +1222,11 @@ impl ConnectTarget {
         {
             Ok(_) => {
                 session.channel_success(channel)?;
+                if let Some(p) = self.pending_prompts.get_mut(&channel) {
+                    p.motd = motd;
+                } else if let Some(motd) = motd {
+                    session.data(channel, motd.into_bytes())?;
+                }
                 Ok(())
             }
             Err(e) => {
@@ -238,6 +1236,200 @@ impl ConnectTarget {
         }
     }
 
+    /// `motd` of a shell opened straight on the target, with the deadline of
+    /// the granting policy
+    async fn motd<B>(&self, backend: &B) -> Option<String>
+    where
+        B: crate::server::HandlerBackend,
+    {
+        let template = backend.motd().filter(|_| !self.motd_shown)?;
+        let user = self.user.as_ref()?;
+        let targets = match backend.list_targets_for_user(&user.id, true).await {
+            Ok(targets) => targets
+                .iter()
+                .map(|v| v.target_id)
+                .collect::<std::collections::HashSet<_>>()
+                .len(),
+            Err(e) => {
+                debug!("[{}] Fail to list targets for motd: {}", self.handler_id, e);
+                0
+            }
+        };
+        let motd = motd_for(
+            template,
+            backend.db_repository(),
+            user,
+            targets,
+            self.session_end().map(|(end, _)| end),
+        );
+        Some(motd.await)
+    }
+
+    /// When bridged channels are closed, by the policy running out or the
+    /// session reaching its limit, with the termination reason for either
+    fn session_end(&self) -> Option<(DateTime<Utc>, &'static str)> {
+        match (self.deadline, self.session_limit) {
+            (Some(d), Some(l)) if l < d => Some((l, POLICY_EXPIRED)),
+            (Some(d), _) => Some((d, ACCESS_EXPIRED)),
+            (None, l) => l.map(|l| (l, POLICY_EXPIRED)),
+        }
+    }
+
+    fn reason_missing(&self) -> bool {
+        self.require_reason && self.reason.is_none()
+    }
+
+    /// Line editing for the access reason prompt. The shell is opened once a
+    /// non-empty line is entered, Ctrl-C or Ctrl-D gives up.
+    async fn read_reason<B>(
+        &mut self,
+        backend: Arc<B>,
+        channel: ChannelId,
+        mut pending: PendingShell,
+        data: &[u8],
+        session: &mut ru_server::Session,
+    ) -> Result<(), Error>
+    where
+        B: 'static + crate::server::HandlerBackend + Send + Sync,
+    {
+        let (line, echo) = edit_line(&mut pending.input, data, MAX_REASON_LEN, true);
+        if matches!(line, LineInput::Aborted) {
+            debug!("[{}] Access reason prompt aborted", self.handler_id);
+            session.data(channel, b"\r\n".to_vec())?;
+            session.close(channel)?;
+            return Ok(());
+        }
+        if !echo.is_empty() {
+            session.data(channel, echo)?;
+        }
+        if matches!(line, LineInput::Partial) {
+            self.pending_shell.insert(channel, pending);
+            return Ok(());
+        }
+
+        let reason = String::from_utf8_lossy(&pending.input).trim().to_string();
+        if reason.is_empty() {
+            pending.input.clear();
+            session.data(channel, format!("\r\n{}", REASON_PROMPT).into_bytes())?;
+            self.pending_shell.insert(channel, pending);
+            return Ok(());
+        }
+        session.data(channel, b"\r\n".to_vec())?;
+        self.reason = Some(reason);
+
+        // The shell request was already answered when the prompt was shown
+        if let Err(e) = self
+            .connect_to_target_with_shell(
+                backend,
+                &pending.term,
+                pending.window_size,
+                &pending.modes,
+                channel,
+                session,
+            )
+            .await
+        {
+            session.close(channel)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Line editing for a prompt of the target's keyboard-interactive login.
+    /// The shell is opened once the target takes the answers, Ctrl-C or
+    /// Ctrl-D gives up.
+    async fn read_prompt<B>(
+        &mut self,
+        backend: Arc<B>,
+        channel: ChannelId,
+        mut pending: PendingPrompts,
+        data: &[u8],
+        session: &mut ru_server::Session,
+    ) -> Result<(), Error>
+    where
+        B: 'static + crate::server::HandlerBackend + Send + Sync,
+    {
+        let (line, echo) = edit_line(&mut pending.shell.input, data, MAX_ANSWER_LEN, pending.echo);
+        if matches!(line, LineInput::Aborted) {
+            debug!("[{}] Target prompt aborted", self.handler_id);
+            session.data(channel, b"\r\n".to_vec())?;
+            session.close(channel)?;
+            return Ok(());
+        }
+        if !echo.is_empty() {
+            session.data(channel, echo)?;
+        }
+        if matches!(line, LineInput::Partial) {
+            self.pending_prompts.insert(channel, pending);
+            return Ok(());
+        }
+        session.data(channel, b"\r\n".to_vec())?;
+
+        let answer = String::from_utf8_lossy(&pending.shell.input).into_owned();
+        pending.shell.input.clear();
+        let login = match pending.login.answer(answer).await {
+            Ok(login) => login,
+            Err(e) => {
+                session.close(channel)?;
+                return Err(e);
+            }
+        };
+        match login {
+            TargetLogin::Prompts(login) => {
+                pending.login = login;
+                self.ask(channel, pending, session)
+            }
+            TargetLogin::Ready(handle, endpoint) => {
+                if let Some(endpoint) = endpoint {
+                    self.log_endpoint(backend.as_ref(), &endpoint).await;
+                }
+                self.prompted = Some(handle);
+                let shell = pending.shell;
+                // The shell request was already answered when the first
+                // prompt was shown
+                if let Err(e) = self
+                    .connect_to_target_with_shell(
+                        backend,
+                        &shell.term,
+                        shell.window_size,
+                        &shell.modes,
+                        channel,
+                        session,
+                    )
+                    .await
+                {
+                    session.close(channel)?;
+                    return Err(e);
+                }
+                if let Some(motd) = pending.motd {
+                    session.data(channel, motd.into_bytes())?;
+                }
+                Ok(())
+            }
+            TargetLogin::Refused => {
+                debug!("[{}] Target refused the prompt answers", self.handler_id);
+                session.data(channel, TARGET_PROMPTS_REFUSED.as_bytes().to_vec())?;
+                session.close(channel)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Shows the next prompt of the target's keyboard-interactive login,
+    /// the shell waits until it's answered
+    fn ask(
+        &mut self,
+        channel: ChannelId,
+        mut pending: PendingPrompts,
+        session: &mut ru_server::Session,
+    ) -> Result<(), Error> {
+        let (text, echo) = pending.login.prompt();
+        pending.echo = echo;
+        session.data(channel, text.into_bytes())?;
+        self.pending_prompts.insert(channel, pending);
+        Ok(())
+    }
+
     async fn connect_to_target_without_pty<'a, B>(
         &mut self,
         backend: Arc<B>,
@@ -250,7 +1442,7 @@ impl ConnectTarget {
     {
         // TODO: print some info to client
         if !self
-            .request_target_channel(channel, backend, request)
+            .open_target_channel(channel, backend, session, request)
             .await?
         {
             session.close(channel)?;
@@ -306,12 +1498,14 @@ impl ConnectTarget {
     where
         B: 'static + crate::server::HandlerBackend + Send + Sync,
     {
-        // TODO: print some info to client
         if !self
-            .request_target_channel(channel, backend.clone(), request)
+            .open_target_channel(channel, backend.clone(), session, request)
             .await?
         {
-            session.close(channel)?;
+            // Kept open to put the target's prompts to the user
+            if self.prompt_login.is_none() {
+                session.close(channel)?;
+            }
             return Ok(false);
         }
 
@@ -331,6 +1525,16 @@ impl ConnectTarget {
                 modes,
             )
             .await?;
+        self.pty_size
+            .insert(channel, (window_size.0 as u16, window_size.1 as u16));
+        self.pty_requests.insert(
+            channel,
+            PtyRequest {
+                term: term.to_string(),
+                modes: modes.to_vec(),
+                size: Arc::new(std::sync::Mutex::new(window_size)),
+            },
+        );
 
         if backend.enable_record() {
             let target_sec_name = self.target_sec_name.as_ref().unwrap_or_else(|| {
@@ -341,14 +1545,38 @@ impl ConnectTarget {
                 target_sec_name.target_id,
                 target_sec_name.secret_id,
                 self.handler_id,
-            );
+            )
+            .with_ticket(self.ticket.clone());
+            let recording = if backend.record_files().per_target() {
+                recording.in_target_dir()
+            } else {
+                recording
+            };
+            let sink: Box<dyn asciinema::RecordSink> = match backend.record_s3() {
+                Some(s3) => Box::new(s3.sink(&recording.file_path)),
+                None => {
+                    let record_file = backend
+                        .record_files()
+                        .prepare(Path::new(backend.record_path()), &recording.file_path)?;
+                    Box::new(asciinema::FileSink::create(record_file).await?)
+                }
+            };
+
+            let mut env = HashMap::new();
+            if let Some(t) = self.ticket.as_ref() {
+                env.insert("RUSTION_TICKET".to_string(), t.clone());
+            }
+            if let Some(r) = self.reason.as_ref() {
+                env.insert("RUSTION_REASON".to_string(), r.clone());
+            }
 
             // Create the asciinema recorder
             let session = asciinema::new_recorder(
                 Some(term.to_string()),
-                std::path::PathBuf::from(backend.record_path()).join(&recording.file_path),
+                sink,
                 (window_size.0 as u16, window_size.1 as u16),
                 None,
+                env,
                 backend.record_input(),
             )
             .await?;
@@ -357,6 +1585,7 @@ impl ConnectTarget {
             let recording_session = RecordingSession {
                 session,
                 recording_id: recording.id,
+                file_path: recording.file_path.clone(),
             };
 
             // Save to database
@@ -370,7 +1599,10 @@ impl ConnectTarget {
                     self.handler_id,
                     e
                 );
-                return Err(Error::App(AppError::InitRecordError));
+                return Err(Error::App(AppError::InitRecord {
+                    recording_id: recording.id,
+                    source: Box::new(e),
+                }));
             }
 
             if self
@@ -380,11 +1612,37 @@ impl ConnectTarget {
             {
                 return Err(Error::App(AppError::ChannelRecordExists));
             }
+            self.notice(session, channel, request, "this session is recorded")?;
         }
 
         Ok(true)
     }
 
+    /// Tunnels never get bastion notices, exec sessions only when the
+    /// granting policy isn't quiet
+    fn wants_notice(&self, request: &Request<'_>) -> bool {
+        match request {
+            Request::OpenDirectTcpip(_) | Request::Subsystem(_) => false,
+            Request::Exec(_) => !self.quiet,
+            Request::Shell => true,
+        }
+    }
+
+    /// Writes a bastion notice to the client's stderr
+    fn notice(
+        &self,
+        session: &mut ru_server::Session,
+        channel: ChannelId,
+        request: &Request<'_>,
+        msg: &str,
+    ) -> Result<(), Error> {
+        if !self.wants_notice(request) {
+            return Ok(());
+        }
+        session.extended_data(channel, 1, format!("rustion: {}\r\n", msg).into_bytes())?;
+        Ok(())
+    }
+
     #[allow(clippy::too_many_arguments)]
     async fn do_exec_request<B>(
         &mut self,
@@ -420,6 +1678,39 @@ impl ConnectTarget {
         };
 
         if res {
+            if let Some(command) = ScpCommand::parse(data) {
+                self.transfer_audit.insert(
+                    channel,
+                    Arc::new(Mutex::new(TransferAudit::Scp(ScpAudit::new(command)))),
+                );
+            }
+            self.bridge(session.handle(), channel, request, backend)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn do_subsystem_request<B>(
+        &mut self,
+        backend: Arc<B>,
+        name: &str,
+        channel: ChannelId,
+        session: &mut ru_server::Session,
+    ) -> Result<(), Error>
+    where
+        B: 'static + crate::server::HandlerBackend + Send + Sync,
+    {
+        let request = Request::Subsystem(name);
+        if self
+            .connect_to_target_without_pty(backend.clone(), channel, session, &request)
+            .await?
+        {
+            if name == SFTP_SUBSYSTEM {
+                self.transfer_audit.insert(
+                    channel,
+                    Arc::new(Mutex::new(TransferAudit::Sftp(SftpAudit::default()))),
+                );
+            }
             self.bridge(session.handle(), channel, request, backend)
                 .await?;
         }
@@ -452,24 +1743,65 @@ impl ConnectTarget {
         {
             self.bridge(session.handle(), channel, Request::Shell, backend)
                 .await?;
+        } else if let Some(login) = self.prompt_login.take() {
+            let shell = PendingShell {
+                term: term.to_string(),
+                window_size,
+                modes: modes.to_vec(),
+                input: Vec::new(),
+            };
+            let pending = PendingPrompts {
+                shell,
+                login,
+                echo: false,
+                motd: None,
+            };
+            self.ask(channel, pending, session)?;
         }
 
         Ok(())
     }
 
-    pub(crate) async fn window_change_request(
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn window_change_request<B>(
         &mut self,
+        backend: Arc<B>,
         channel: ChannelId,
         col_width: u32,
         row_height: u32,
         pix_width: u32,
         pix_height: u32,
         session: &mut ru_server::Session,
-    ) -> Result<(), Error> {
-        if let Some(ch) = self.target_channel.get(&channel) {
-            ch.window_change(col_width, row_height, pix_width, pix_height)
-                .await?;
+    ) -> Result<(), Error>
+    where
+        B: 'static + crate::server::HandlerBackend + Send + Sync,
+    {
+        self.pty_size
+            .insert(channel, (col_width as u16, row_height as u16));
+        if let Some(p) = self.pty_requests.get(&channel) {
+            *p.size.lock().unwrap() = (col_width, row_height, pix_width, pix_height);
+        }
+        if let Some(p) = self.pending_shell.get_mut(&channel) {
+            p.window_size = (col_width, row_height, pix_width, pix_height);
+            session.channel_success(channel)?;
+            return Ok(());
+        }
+        if let Some(p) = self.pending_prompts.get_mut(&channel) {
+            p.shell.window_size = (col_width, row_height, pix_width, pix_height);
             session.channel_success(channel)?;
+            return Ok(());
+        }
+        let Some(ch) = self.target_writer(channel) else {
+            session.channel_failure(channel)?;
+            return Ok(());
+        };
+        ch.window_change(col_width, row_height, pix_width, pix_height)
+            .await?;
+        session.channel_success(channel)?;
+        if let Some(id) = self.live.get(&channel) {
+            backend
+                .live_sessions()
+                .resize(*id, col_width as u16, row_height as u16);
         }
 
         if let Some(r) = self.record_session.get(&channel) {
@@ -480,7 +1812,6 @@ impl ConnectTarget {
                 .await;
         }
 
-        session.channel_failure(channel)?;
         Ok(())
     }
 
@@ -511,13 +1842,22 @@ impl ConnectTarget {
             .unwrap_or_else(|| panic!("[{}] target should be assigned", self.handler_id));
         let move_target = target.clone();
 
-        let request_str = request.to_string();
+        let env = self.env.remove(&channel).unwrap_or_default();
+        for (name, value) in &env {
+            write_half.set_env(false, name, value).await?;
+        }
+        let observing = matches!(request, Request::Shell) && self.observe;
+        let request_str = if observing {
+            OBSERVE_REQUEST.to_string()
+        } else {
+            request.to_string()
+        };
         match request {
             Request::Shell => write_half.request_shell(false).await?,
             Request::Exec(data) => write_half.exec(false, data).await?,
+            Request::Subsystem(name) => write_half.request_subsystem(false, name).await?,
             Request::OpenDirectTcpip(_) => {}
         }
-        let log = self.log.clone();
 
         let (send, mut recv) = mpsc::channel::<()>(1);
         if self.notify.insert(channel, send).is_some() {
@@ -525,89 +1865,520 @@ impl ConnectTarget {
         };
 
         let record = self.record_session.get(&channel).cloned();
+        let transfer_audit = self.transfer_audit.get(&channel).cloned();
+        let recording_path = match &record {
+            Some(r) => Some(r.lock().await.file_path.clone()),
+            None => None,
+        };
+        let target_sec_name = self
+            .target_sec_name
+            .as_ref()
+            .unwrap_or_else(|| panic!("[{}] target_sec_name should not be none", self.handler_id));
+        let mut ssh_session = SshSession::new(
+            self.handler_id,
+            self.user.as_ref().unwrap().id,
+            target_sec_name.id,
+            request_str,
+        )
+        .with_recording_path(recording_path)
+        .with_ticket(self.ticket.clone())
+        .with_access_reason(self.reason.clone())
+        .with_login_as(self.login_as.clone());
+        if let Err(e) = backend
+            .db_repository()
+            .create_ssh_session(&ssh_session)
+            .await
+        {
+            log::error!("[{}] Failed to create session: {}", self.handler_id, e);
+            return Err(Error::App(AppError::InitSession {
+                session_id: ssh_session.id,
+                source: Box::new(e),
+            }));
+        }
+        let bytes_in = Arc::new(AtomicU64::new(0));
+        self.bytes_in.insert(channel, bytes_in.clone());
+        if matches!(request, Request::Shell) && self.command_filter.is_some() {
+            self.shell_input.insert(channel, LineTracker::default());
+        }
+
+        // Channels over a pooled handle are listed for admins, who may
+        // revoke them
+        let login_as = self.login_as.as_deref();
+        let pooled_target = format!(
+            "{}@{}",
+            login_as.unwrap_or(&target_sec_name.secret_user),
+            target.name
+        );
+        let conn_key = pool_key(&target_sec_name.id, &target.id, login_as);
+        let lease = backend.pool_leases().map(|leases| {
+            leases.acquire(
+                conn_key.clone(),
+                pooled_target.clone(),
+                self.user.as_ref().unwrap(),
+            )
+        });
+        let (lease_id, mut revoked) = lease.unzip();
+        // Listed for admins, who may watch what the target sends
+        let (live_id, live_events, mut live_control) = backend.live_sessions().register(
+            &ssh_session,
+            &self.user.as_ref().unwrap().username,
+            pooled_target.clone(),
+            self.pty_size.get(&channel).copied(),
+        );
+        self.live.insert(channel, live_id);
+        let wants_notice = self.wants_notice(&request);
+        if observing {
+            self.observers.insert(channel);
+            let _ = handle
+                .extended_data(channel, 1, OBSERVE_NOTICE.as_bytes().to_vec())
+                .await;
+        }
+        // Shells are watched for `rz`/`sz`, their client side only when
+        // transfers are blocked
+        let mut zmodem = (matches!(request, Request::Shell) && self.zmodem != ZmodemPolicy::Allow)
+            .then(|| ZmodemWatch::target(self.zmodem));
+        if let Some(w) = zmodem.as_ref().filter(|w| w.blocks()) {
+            self.zmodem_input.insert(channel, ZmodemWatch::client(w));
+        }
+
+        // The pooled handle outlives a dropped client, the token lets the
+        // user back onto it
+        let reconnect_token = match (request, lease_id, backend.reconnect_tokens()) {
+            (Request::Shell, Some(_), Some(tokens)) => {
+                let user = self.user.as_ref().unwrap();
+                let token = tokens.issue(user.id, pooled_target.clone());
+                let msg = format!(
+                    "rustion: if the connection drops, log in as {}@{} with password {} within {} to resume\r\n",
+                    user.username,
+                    pooled_target,
+                    token,
+                    humantime::format_duration(tokens.ttl())
+                );
+                let _ = handle.extended_data(channel, 1, msg.into_bytes()).await;
+                Some(token)
+            }
+            _ => None,
+        };
+
+        // Soonest last, `None` closes the channel
+        let mut expiry: Vec<(tokio::time::Instant, Option<Duration>)> = Vec::new();
+        let deadline = self.session_end();
+        if let Some((d, _)) = deadline {
+            let left = (d - Utc::now()).to_std().unwrap_or_default();
+            let start = tokio::time::Instant::now();
+            expiry.push((start + left, None));
+            if self.wants_notice(&request) {
+                for (wait, left) in expiry_warnings(left).into_iter().rev() {
+                    expiry.push((start + wait, Some(left)));
+                }
+            }
+        }
+
+        // Output resets the idle timer in the loop, input is seen through
+        // `bytes_in` when the timer fires
+        let idle = self.idle;
+        let throttle_down = self.throttle_down.clone();
+        // A target gone without a RST is only noticed by the keepalives,
+        // checked on every probe
+        let mut target_handle = self.target_handle.clone();
+        let probe = backend.target_keepalive();
+        // and a shell is then reopened on a new connection, if allowed
+        let reopen = match (request, backend.shell_reconnect_window()) {
+            (Request::Shell, Some(window)) => self.pty_requests.get(&channel).map(|pty| {
+                let reconnect = Arc::new(Reconnect::default());
+                self.reconnects.insert(channel, reconnect.clone());
+                Reopen {
+                    pty: pty.clone(),
+                    env,
+                    window,
+                    target_secret_id: target_sec_name.id,
+                    login_as: self.login_as.clone(),
+                    reconnect,
+                }
+            }),
+            _ => None,
+        };
 
         let backend_for_task = backend.clone();
         let handler_id = self.handler_id;
         tokio::spawn(async move {
-            loop {
+            let mut bytes_out = 0u64;
+            let mut exit_status = None;
+            let mut client_dropped = false;
+            let mut last_active = tokio::time::Instant::now();
+            let mut seen_in = 0;
+            let mut idle_warned = false;
+            let mut next_probe = probe.map(|p| tokio::time::Instant::now() + p);
+            let mut lost = false;
+            let reason = loop {
+                if let Some(r) = reopen.as_ref().filter(|_| lost) {
+                    lost = false;
+                    let reopened = tokio::select! {
+                        reopened = reopen_shell(
+                            backend_for_task.as_ref(),
+                            handler_id,
+                            ssh_session.user_id,
+                            &move_target,
+                            r,
+                            &handle,
+                            channel,
+                        ) => reopened,
+                        _ = recv.recv() => {
+                            client_dropped = true;
+                            break "client disconnected".to_string();
+                        }
+                    };
+                    let Some((h, read)) = reopened else {
+                        break TARGET_LOST.to_string();
+                    };
+                    target_handle = Some(h);
+                    read_half = read;
+                }
+                let next_expiry = expiry.last().map(|e| e.0);
+                let next_idle = idle.map(|i| {
+                    last_active
+                        + if idle_warned {
+                            i.after + i.grace
+                        } else {
+                            i.after
+                        }
+                });
                 tokio::select! {
                     msg = read_half.wait() => {
                         if let Some(msg) = msg {
                             match msg {
                                 ChannelMsg::Data { data } => {
+                                    bytes_out += data.len() as u64;
+                                    last_active = tokio::time::Instant::now();
+                                    idle_warned = false;
                                     if let Some(r) = &record {
                                         r.lock().await.session.handle_output(data.as_ref()).await;
                                     }
-                                    let _ = handle.data(channel, data).await;
+                                    if let Some(a) = &transfer_audit {
+                                        let mut audit = a.lock().await;
+                                        for event in audit.target_data(data.as_ref()) {
+                                            log_transfer(
+                                                backend_for_task.as_ref(),
+                                                handler_id,
+                                                ssh_session.user_id,
+                                                audit.log_type(),
+                                                &move_target.name,
+                                                &event,
+                                            )
+                                            .await;
+                                        }
+                                    }
+                                    tee(&live_events, data.as_ref());
+                                    throttle(&throttle_down, data.len()).await;
+                                    let mut pass = data.len();
+                                    if let Some(z) = zmodem.as_mut() {
+                                        let checked = z.check(data.as_ref());
+                                        pass = checked.pass;
+                                        if let Some(direction) = checked.started {
+                                            if z.blocks() {
+                                                let msg = ZMODEM_BLOCKED.as_bytes().to_vec();
+                                                let _ = handle.extended_data(channel, 1, msg).await;
+                                            }
+                                            log_transfer(
+                                                backend_for_task.as_ref(),
+                                                handler_id,
+                                                ssh_session.user_id,
+                                                ZMODEM_LOG_TYPE,
+                                                &move_target.name,
+                                                z.event(direction),
+                                            )
+                                            .await;
+                                        }
+                                    }
+                                    if pass == data.len() {
+                                        let _ = handle.data(channel, data).await;
+                                    } else if pass > 0 {
+                                        let _ = handle.data(channel, data[..pass].to_vec()).await;
+                                    }
                                 }
                                 ChannelMsg::Eof => {
                                     let _ = handle.eof(channel).await;
                                 }
                                 ChannelMsg::ExtendedData { data, ext: 1 }  => {
+                                    bytes_out += data.len() as u64;
+                                    last_active = tokio::time::Instant::now();
+                                    idle_warned = false;
                                     if let Some(r) = &record {
                                         r.lock().await.session.handle_output(data.as_ref()).await;
                                     }
+                                    tee(&live_events, data.as_ref());
+                                    throttle(&throttle_down, data.len()).await;
                                     let _ = handle.extended_data(channel, 1, data).await;
 
                                 }
-                                ChannelMsg::ExitStatus { exit_status } => {
+                                ChannelMsg::ExitStatus { exit_status: status } => {
+                                    exit_status = Some(status);
                                     if let Some(r) = &record {
-                                        r.lock().await.session.handle_exit(exit_status as i32).await;
+                                        r.lock().await.session.handle_exit(status as i32).await;
                                     }
-                                    let _ = handle.exit_status_request(channel, exit_status).await;
+                                    let _ = handle.exit_status_request(channel, status).await;
                                 }
                                 _ => {}
                             }
                         } else {
-                            break;
+                            let closed = target_handle.as_ref().is_some_and(|h| h.is_closed());
+                            if closed && exit_status.is_none() && reopen.is_some() {
+                                lost = true;
+                                continue;
+                            }
+                            break match exit_status {
+                                Some(s) => format!("exit status {}", s),
+                                None if closed => TARGET_LOST.to_string(),
+                                None => "target closed".to_string(),
+                            };
                         }
                     }
                     _ = recv.recv() => {
-                        break;
+                        client_dropped = true;
+                        break "client disconnected".to_string();
+                    }
+                    _ = recv_revoked(&mut revoked) => {
+                        if wants_notice {
+                            let msg = b"\r\nrustion: this session was revoked by an administrator\r\n";
+                            let _ = handle.extended_data(channel, 1, msg.to_vec()).await;
+                        }
+                        break "revoked by admin".to_string();
+                    }
+                    Some(control) = live_control.recv() => {
+                        match control {
+                            Control::Notice(msg) => {
+                                let msg = format!("\r\nrustion: {}\r\n", msg);
+                                let _ = handle.extended_data(channel, 1, msg.into_bytes()).await;
+                            }
+                            Control::Kill(why) => {
+                                let msg = format!(
+                                    "\r\nrustion: this session was terminated by an administrator: {}\r\n",
+                                    why
+                                );
+                                let _ = handle.extended_data(channel, 1, msg.into_bytes()).await;
+                                break format!("{}: {}", KILLED_BY_ADMIN, why);
+                            }
+                        }
+                    }
+                    _ = sleep_until(next_expiry) => {
+                        match (expiry.pop(), deadline) {
+                            (Some((_, Some(left))), Some((d, why))) => {
+                                let what = if why == POLICY_EXPIRED {
+                                    "session time limit"
+                                } else {
+                                    "access"
+                                };
+                                let msg = format!(
+                                    "\r\nrustion: {} ends in {}, this session will be closed at {}\r\n",
+                                    what,
+                                    humantime::format_duration(left),
+                                    d.format("%Y-%m-%d %H:%M:%S UTC")
+                                );
+                                let _ = handle.extended_data(channel, 1, msg.into_bytes()).await;
+                            }
+                            (_, end) => {
+                                break end.map_or(ACCESS_EXPIRED, |(_, why)| why).to_string();
+                            }
+                        }
+                    }
+                    _ = sleep_until(next_idle) => {
+                        let Some(i) = idle else {
+                            continue;
+                        };
+                        let input = bytes_in.load(Ordering::Relaxed);
+                        if input != seen_in {
+                            seen_in = input;
+                            last_active = tokio::time::Instant::now();
+                            idle_warned = false;
+                        } else if !idle_warned {
+                            idle_warned = true;
+                            if wants_notice {
+                                let msg = format!(
+                                    "\r\nrustion: this session has been idle for {}, it will be closed in {} without activity\r\n",
+                                    humantime::format_duration(i.after),
+                                    humantime::format_duration(i.grace)
+                                );
+                                let _ = handle.extended_data(channel, 1, msg.into_bytes()).await;
+                            }
+                        } else {
+                            warn!(
+                                "[{}] Session {} on {}({}) closed after {} without activity",
+                                handler_id,
+                                ssh_session.id,
+                                move_target.name,
+                                move_target.id,
+                                humantime::format_duration(i.after + i.grace)
+                            );
+                            break IDLE_TIMEOUT.to_string();
+                        }
+                    }
+                    _ = sleep_until(next_probe) => {
+                        next_probe = probe.map(|p| tokio::time::Instant::now() + p);
+                        if target_handle.as_ref().is_some_and(|h| h.is_closed()) {
+                            warn!(
+                                "[{}] Session {} on {}({}) lost its target connection",
+                                handler_id,
+                                ssh_session.id,
+                                move_target.name,
+                                move_target.id
+                            );
+                            if reopen.is_some() {
+                                lost = true;
+                                continue;
+                            }
+                            if wants_notice {
+                                let msg = b"\r\nrustion: the target stopped answering, closing this session\r\n";
+                                let _ = handle.extended_data(channel, 1, msg.to_vec()).await;
+                            }
+                            break TARGET_LOST.to_string();
+                        }
                     }
                 }
+            };
+            // Other sessions must not be handed the dead handle
+            if reason == TARGET_LOST {
+                backend_for_task.evict_dead_connection(&conn_key).await;
+            }
+            if let (Some(id), Some(leases)) = (lease_id, backend_for_task.pool_leases()) {
+                leases.release(id);
+            }
+            backend_for_task.live_sessions().release(live_id);
+            if let (Some(token), Some(tokens)) =
+                (&reconnect_token, backend_for_task.reconnect_tokens())
+            {
+                if client_dropped {
+                    tokens.arm(token);
+                } else {
+                    tokens.discard(token);
+                }
             }
             // Update session recording as completed
-            if let Some(r) = record
-                && let Ok(Some(rec)) = backend_for_task
+            if let Some(r) = record {
+                let (recording_id, stats) = {
+                    let r = r.lock().await;
+                    (r.recording_id, r.session.stats())
+                };
+                if let Ok(Some(rec)) = backend_for_task
                     .db_repository()
-                    .get_session_recording_by_id(&r.lock().await.recording_id)
-                    .await
-            {
-                let mut updated = rec;
-                updated.ended_at = Some(chrono::Utc::now().timestamp_millis());
-                updated.status = "completed".to_string();
-                if let Err(e) = backend_for_task
-                    .db_repository()
-                    .update_session_recording(&updated)
+                    .get_session_recording_by_id(&recording_id)
                     .await
                 {
-                    log::error!("[{}] Failed to update session recording: {}", handler_id, e);
+                    let mut updated = rec;
+                    updated.ended_at = Some(Timestamp::now());
+                    updated.status = "completed".to_string();
+                    updated.set_stats(stats.duration, stats.active, stats.events);
+                    if let Err(e) = backend_for_task
+                        .db_repository()
+                        .update_session_recording(&updated)
+                        .await
+                    {
+                        log::error!("[{}] Failed to update session recording: {}", handler_id, e);
+                    }
+                }
+            }
+            if let Some(a) = transfer_audit {
+                let mut audit = a.lock().await;
+                for event in audit.finish() {
+                    log_transfer(
+                        backend_for_task.as_ref(),
+                        handler_id,
+                        ssh_session.user_id,
+                        audit.log_type(),
+                        &move_target.name,
+                        &event,
+                    )
+                    .await;
+                }
+                if !audit.is_following() {
+                    warn!(
+                        "[{}] Session {} did not parse as {}, its transfers were not logged",
+                        handler_id,
+                        ssh_session.id,
+                        audit.log_type()
+                    );
                 }
             }
             let _ = handle.close(channel).await;
-            log(
-                LOG_TYPE.into(),
-                format!(
-                    "target request: {} closed on {}({})",
-                    request_str, move_target.name, move_target.id
-                ),
-            )
-            .await;
+
+            ssh_session.ended_at = Some(Timestamp::now());
+            ssh_session.bytes_in = bytes_in.load(Ordering::Relaxed) as i64;
+            ssh_session.bytes_out = bytes_out as i64;
+            ssh_session.termination_reason = Some(reason);
+            if let Err(e) = backend_for_task
+                .db_repository()
+                .update_ssh_session(&ssh_session)
+                .await
+            {
+                log::error!("[{}] Failed to update session: {}", handler_id, e);
+            }
+            debug!(
+                "[{}] Session {} on {}({}) ended: {}",
+                handler_id,
+                ssh_session.id,
+                move_target.name,
+                move_target.id,
+                ssh_session
+                    .termination_reason
+                    .as_deref()
+                    .unwrap_or_default()
+            );
         });
 
-        (self.log)(
-            LOG_TYPE.into(),
-            format!(
-                "target request: {} succeed on {}({})",
-                request, target.name, target.id
-            ),
-        )
-        .await;
+        debug!(
+            "[{}] Target request: {} succeed on {}({}) {}",
+            self.handler_id,
+            request,
+            target.name,
+            target.id,
+            target_sec_name.ssh_url()
+        );
 
         Ok(())
     }
 
+    /// Shell access to the target: `shell`, else a read-only shell under
+    /// `observe`
+    pub(crate) async fn check_shell_permission<B>(
+        &mut self,
+        backend: Arc<B>,
+        ip: Option<std::net::IpAddr>,
+    ) -> Result<bool, Error>
+    where
+        B: 'static + crate::server::HandlerBackend + Send + Sync,
+    {
+        let uuids = crate::database::common::InternalUuids::get();
+        self.observe = false;
+        if self
+            .check_permission(backend.clone(), uuids.act_shell, ip)
+            .await?
+        {
+            return Ok(true);
+        }
+        self.observe = self
+            .check_permission(backend, uuids.act_observe, ip)
+            .await?;
+        Ok(self.observe)
+    }
+
+    /// `observe` brings the pty its shell needs
+    pub(crate) async fn check_pty_permission<B>(
+        &mut self,
+        backend: Arc<B>,
+        ip: Option<std::net::IpAddr>,
+    ) -> Result<bool, Error>
+    where
+        B: 'static + crate::server::HandlerBackend + Send + Sync,
+    {
+        let uuids = crate::database::common::InternalUuids::get();
+        if self
+            .check_permission(backend.clone(), uuids.act_pty, ip)
+            .await?
+        {
+            return Ok(true);
+        }
+        self.check_permission(backend, uuids.act_observe, ip).await
+    }
+
     pub async fn check_permission<B>(
         &mut self,
         backend: Arc<B>,
@@ -635,25 +2406,103 @@ impl ConnectTarget {
             return Ok(false);
         };
 
-        if !backend
-            .enforce(
+        let Some(policy) = backend
+            .enforce_policy(
                 user.id,
                 target_sec_id,
                 action_uuid,
-                casbin::ExtendPolicyReq::new(ip),
+                casbin::ExtendPolicyReq::new(ip).with_ticket(self.ticket.clone()),
             )
             .await?
-        {
+        else {
             debug!(
                 "[{}] User: {} doesn't have permission to access target: {}, action_uuid: {}",
                 self.handler_id, &user.username, &target.name, action_uuid
             );
             return Ok(false);
+        };
+        if self.login_as.is_some() && !policy.any_user {
+            debug!(
+                "[{}] Policy granting action_uuid: {} on target: {} doesn't allow any user",
+                self.handler_id, action_uuid, &target.name
+            );
+            return Ok(false);
+        }
+        self.quiet = policy.quiet;
+        self.require_reason = policy.require_reason;
+        self.deadline = policy.deadline(Utc::now());
+        self.session_limit = policy
+            .max_session
+            .and_then(|max| TimeDelta::from_std(max).ok())
+            .and_then(|max| self.started_at.checked_add_signed(max));
+        self.ports = policy.ports;
+        self.zmodem = policy.zmodem.unwrap_or_else(|| backend.zmodem_policy());
+        self.idle = IdleLimit::new(
+            backend.idle_session_timeout(),
+            policy.idle,
+            policy.idle_grace,
+        );
+        self.set_rate(backend.rate_limit().min(policy.rate.unwrap_or_default()));
+        self.command_filter = match policy.command_filter.as_deref() {
+            Some(name) => match backend.command_filter(name) {
+                Some(f) => Some(f),
+                None => {
+                    // Refused rather than let run unchecked
+                    warn!(
+                        "[{}] Policy granting action_uuid: {} on target: {} names unknown command filter '{}'",
+                        self.handler_id, action_uuid, &target.name, name
+                    );
+                    return Ok(false);
+                }
+            },
+            None => None,
+        };
+        if let Some((window, until)) = backend.maintenance(user.id, target.id, ip).await? {
+            debug!(
+                "[{}] User: {} turned away from target: {} under maintenance until {}",
+                self.handler_id, &user.username, &target.name, until
+            );
+            self.refusal = Some(window.notice(&target.name, until));
+            return Ok(false);
+        }
+        if policy.quota.is_some()
+            && let Some(usage) = backend
+                .session_quota(
+                    user.id,
+                    target_sec_id,
+                    action_uuid,
+                    casbin::ExtendPolicyReq::new(ip).with_ticket(self.ticket.clone()),
+                )
+                .await?
+            && usage.left() == 0
+        {
+            debug!(
+                "[{}] User: {} used up the session quota on target: {}, {}",
+                self.handler_id, &user.username, &target.name, usage
+            );
+            return Ok(false);
         }
         Ok(true)
     }
 
-    async fn do_connect_to_target<B>(&mut self, backend: Arc<B>) -> Result<(), Error>
+    /// Writes the reason of the last refused request to stderr of `channel`
+    pub(crate) fn send_refusal(
+        &mut self,
+        session: &mut ru_server::Session,
+        channel: ChannelId,
+    ) -> Result<(), Error> {
+        if let Some(msg) = self.refusal.take() {
+            session.extended_data(channel, 1, format!("rustion: {}\r\n", msg).into_bytes())?;
+        }
+        Ok(())
+    }
+
+    async fn do_connect_to_target<B>(
+        &mut self,
+        backend: Arc<B>,
+        force_build: bool,
+        prompts: bool,
+    ) -> Result<(), Error>
     where
         B: 'static + crate::server::HandlerBackend + Send + Sync,
     {
@@ -669,19 +2518,87 @@ impl ConnectTarget {
             return Ok(());
         };
 
-        // NOTE: target_handle could be re-assigned.
-        self.target_handle = backend
-            .connect_to_target(target.clone(), target_sec_id, false)
+        let connect = match self.agent.as_ref() {
+            Some(agent) => target.clone().with_agent(agent.clone()),
+            None => target.clone(),
+        };
+        let login = backend
+            .connect_to_target(
+                connect,
+                target_sec_id,
+                self.login_as.as_deref(),
+                force_build,
+                prompts,
+            )
             .await?;
-
-        debug!(
-            "[{}] Connected to target '{}({})' ({}:{})",
-            self.handler_id, target.name, target.id, target.hostname, target.port
-        );
+        // NOTE: target_handle could be re-assigned.
+        self.target_handle = match login {
+            TargetLogin::Ready(handle, endpoint) => {
+                debug!(
+                    "[{}] Connected to target '{}({})' ({})",
+                    self.handler_id,
+                    target.name,
+                    target.id,
+                    endpoint.as_deref().unwrap_or("pooled")
+                );
+                if let Some(endpoint) = endpoint {
+                    self.log_endpoint(backend.as_ref(), &endpoint).await;
+                }
+                Some(handle)
+            }
+            TargetLogin::Prompts(login) => {
+                self.prompt_login = Some(login);
+                None
+            }
+            TargetLogin::Refused => None,
+        };
 
         Ok(())
     }
 
+    /// Logs the address a new target connection reached, one of the
+    /// fallbacks when the first didn't answer
+    async fn log_endpoint<B>(&self, backend: &B, endpoint: &str)
+    where
+        B: crate::server::HandlerBackend,
+    {
+        if let (Some(user), Some(target)) = (self.user.as_ref(), self.target.as_ref()) {
+            backend
+                .insert_log(
+                    self.handler_id,
+                    user.id,
+                    CONNECT_LOG_TYPE.into(),
+                    format!("{} at {}", target.name, endpoint),
+                )
+                .await;
+        }
+    }
+
+    /// `request_target_channel`, telling the user about a target that didn't
+    /// answer instead of failing the session
+    async fn open_target_channel<'a, B>(
+        &mut self,
+        channel: ChannelId,
+        backend: Arc<B>,
+        session: &mut ru_server::Session,
+        request: &Request<'a>,
+    ) -> Result<bool, Error>
+    where
+        B: 'static + crate::server::HandlerBackend + Send + Sync,
+    {
+        match self.request_target_channel(channel, backend, request).await {
+            Err(Error::Server(ServerError::TargetUnreachable { target, reason })) => {
+                warn!(
+                    "[{}] Target {} unreachable: {}",
+                    self.handler_id, target, reason
+                );
+                self.notice(session, channel, request, TARGET_UNREACHABLE)?;
+                Ok(false)
+            }
+            res => res,
+        }
+    }
+
     async fn request_target_channel<'a, B>(
         &mut self,
         channel_id: ChannelId,
@@ -691,7 +2608,14 @@ impl ConnectTarget {
     where
         B: 'static + crate::server::HandlerBackend + Send + Sync,
     {
-        self.do_connect_to_target(backend.clone()).await?;
+        match self.prompted.take() {
+            Some(handle) => self.target_handle = Some(handle),
+            None => {
+                let prompts = matches!(request, Request::Shell);
+                self.do_connect_to_target(backend.clone(), false, prompts)
+                    .await?
+            }
+        }
         let handle = if let Some(h) = self.target_handle.as_ref() {
             h
         } else {
@@ -699,7 +2623,7 @@ impl ConnectTarget {
         };
 
         let channel = match request {
-            Request::Shell | Request::Exec(_) => {
+            Request::Shell | Request::Exec(_) | Request::Subsystem(_) => {
                 match handle.channel_open_session().await {
                     Ok(ch) => ch,
                     Err(
@@ -708,8 +2632,9 @@ impl ConnectTarget {
                         )
                         | russh::Error::SendError,
                     ) => {
-                        // Try again if the cache of target connection is unavailable
-                        self.do_connect_to_target(backend).await?;
+                        // The cached connection passed its keepalive but can't open
+                        // channels anymore, build a fresh one
+                        self.do_connect_to_target(backend, true, false).await?;
                         let handle = if let Some(h) = self.target_handle.as_ref() {
                             h
                         } else {
@@ -729,8 +2654,9 @@ impl ConnectTarget {
                         )
                         | russh::Error::SendError,
                     ) => {
-                        // Try again if the cache of target connection is unavailable
-                        self.do_connect_to_target(backend).await?;
+                        // The cached connection passed its keepalive but can't open
+                        // channels anymore, build a fresh one
+                        self.do_connect_to_target(backend, true, false).await?;
                         let handle = if let Some(h) = self.target_handle.as_ref() {
                             h
                         } else {
@@ -743,17 +2669,173 @@ impl ConnectTarget {
             }
         };
 
+        if self.agent_channels.remove(&channel_id) {
+            channel.agent_forward(false).await?;
+        }
         self.target_channel
             .insert(channel_id, TargetChannel::ChannelFull(channel));
         Ok(true)
     }
 }
 
+/// Sleeps until `at`, forever without one
+async fn sleep_until(at: Option<tokio::time::Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Opens a shell whose target was lost again on a new connection, with
+/// status lines for the user. `None` once `reopen.window` passed without
+/// one, or the login wants answers only the user could give.
+async fn reopen_shell<B>(
+    backend: &B,
+    handler_id: Uuid,
+    user_id: Uuid,
+    target: &Target,
+    reopen: &Reopen,
+    client: &ru_server::Handle,
+    channel: ChannelId,
+) -> Option<(Arc<ru_client::Handle<Target>>, ChannelReadHalf)>
+where
+    B: crate::server::HandlerBackend,
+{
+    reopen.reconnect.pending.store(true, Ordering::Relaxed);
+    let msg = format!(
+        "\r\nrustion: lost the connection to {}, reconnecting for up to {}\r\n",
+        target.name,
+        humantime::format_duration(reopen.window)
+    );
+    let _ = client.extended_data(channel, 1, msg.into_bytes()).await;
+    let deadline = tokio::time::Instant::now() + reopen.window;
+    loop {
+        match tokio::time::timeout_at(deadline, open_shell(backend, target, reopen)).await {
+            Ok(Ok(Some((handle, endpoint, target_channel)))) => {
+                let (read_half, write_half) = target_channel.split();
+                *reopen.reconnect.channel.lock().unwrap() =
+                    Some(TargetChannel::ChannelWriteHalf(write_half));
+                reopen.reconnect.pending.store(false, Ordering::Relaxed);
+                let endpoint = endpoint.as_deref().unwrap_or("pooled");
+                info!(
+                    "[{}] Reconnected to target '{}({})' ({})",
+                    handler_id, target.name, target.id, endpoint
+                );
+                backend
+                    .insert_log(
+                        handler_id,
+                        user_id,
+                        CONNECT_LOG_TYPE.into(),
+                        format!("{} at {} (reconnected)", target.name, endpoint),
+                    )
+                    .await;
+                let msg = format!("rustion: reconnected to {}\r\n", target.name);
+                let _ = client.extended_data(channel, 1, msg.into_bytes()).await;
+                return Some((handle, read_half));
+            }
+            Ok(Ok(None)) | Err(_) => break,
+            Ok(Err(e)) => {
+                debug!(
+                    "[{}] Reconnecting to target '{}({})' failed: {}",
+                    handler_id, target.name, target.id, e
+                );
+            }
+        }
+        if tokio::time::Instant::now() + RECONNECT_PAUSE >= deadline {
+            break;
+        }
+        tokio::time::sleep(RECONNECT_PAUSE).await;
+    }
+    let msg = format!(
+        "rustion: could not reconnect to {}, closing this session\r\n",
+        target.name
+    );
+    let _ = client.extended_data(channel, 1, msg.into_bytes()).await;
+    None
+}
+
+/// Logs in to `target` anew and starts a shell like the lost one on it
+async fn open_shell<B>(
+    backend: &B,
+    target: &Target,
+    reopen: &Reopen,
+) -> Result<
+    Option<(
+        Arc<ru_client::Handle<Target>>,
+        Option<String>,
+        Channel<ru_client::Msg>,
+    )>,
+    Error,
+>
+where
+    B: crate::server::HandlerBackend,
+{
+    let login = backend
+        .connect_to_target(
+            target.clone(),
+            &reopen.target_secret_id,
+            reopen.login_as.as_deref(),
+            true,
+            false,
+        )
+        .await?;
+    let TargetLogin::Ready(handle, endpoint) = login else {
+        return Ok(None);
+    };
+    let channel = handle.channel_open_session().await?;
+    for (name, value) in &reopen.env {
+        channel.set_env(false, name, value).await?;
+    }
+    let (cols, rows, pix_width, pix_height) = *reopen.pty.size.lock().unwrap();
+    channel
+        .request_pty(
+            false,
+            &reopen.pty.term,
+            cols,
+            rows,
+            pix_width,
+            pix_height,
+            &reopen.pty.modes,
+        )
+        .await?;
+    channel.request_shell(false).await?;
+    Ok(Some((handle, endpoint, channel)))
+}
+
+/// Fires once an admin revokes the channel, never for unpooled ones
+async fn recv_revoked(revoked: &mut Option<mpsc::Receiver<()>>) {
+    match revoked {
+        Some(r) => {
+            r.recv().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Copies output to the admins watching the channel, if any
+fn tee(events: &broadcast::Sender<LiveEvent>, data: &[u8]) {
+    if events.receiver_count() > 0 {
+        let _ = events.send(LiveEvent::Output(data.to_vec()));
+    }
+}
+
+/// Waits until `len` more bytes fit the session's rate
+async fn throttle(throttle: &Option<SharedThrottle>, len: usize) {
+    let Some(t) = throttle else {
+        return;
+    };
+    let wait = t.lock().unwrap().take(len, std::time::Instant::now());
+    if !wait.is_zero() {
+        tokio::time::sleep(wait).await;
+    }
+}
+
 impl<'a> fmt::Display for Request<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Request::Shell => write!(f, "shell"),
             Request::Exec(d) => write!(f, "exec: {}", String::from_utf8_lossy(d)),
+            Request::Subsystem(name) => write!(f, "subsystem: {}", name),
             Request::OpenDirectTcpip(d) => {
                 write!(
                     f,
@@ -856,6 +2938,22 @@ impl TargetChannel {
         Ok(())
     }
 
+    async fn set_env(&self, want_reply: bool, name: &str, value: &str) -> Result<(), Error> {
+        match self {
+            TargetChannel::ChannelFull(ch) => ch.set_env(want_reply, name, value).await?,
+            TargetChannel::ChannelWriteHalf(ch) => ch.set_env(want_reply, name, value).await?,
+        }
+        Ok(())
+    }
+
+    async fn request_subsystem(&self, want_reply: bool, name: &str) -> Result<(), Error> {
+        match self {
+            TargetChannel::ChannelFull(ch) => ch.request_subsystem(want_reply, name).await?,
+            TargetChannel::ChannelWriteHalf(ch) => ch.request_subsystem(want_reply, name).await?,
+        }
+        Ok(())
+    }
+
     async fn close(&self) -> Result<(), Error> {
         match self {
             TargetChannel::ChannelFull(ch) => ch.close().await?,
@@ -893,6 +2991,258 @@ impl Drop for ConnectTarget {
                 let _ = ch.close().await;
             });
         }
+        // The target handle may be pooled and outlive this session, its
+        // listeners go with the session
+        if let (Some((routes, _)), Some(target)) = (self.forward_route.take(), self.target.as_ref())
+        {
+            let forwards = std::mem::take(&mut self.remote_forwards);
+            for f in &forwards {
+                routes.unregister(target.id, &f.address, f.port);
+            }
+            tokio::spawn(async move {
+                for f in forwards {
+                    let _ = f.handle.cancel_tcpip_forward(f.address, f.port).await;
+                }
+            });
+        }
         trace!("[{}] drop ConnectTarget", self.handler_id);
     }
 }
+
+/// Opens a `forwarded-tcpip` channel to the client for each connection a
+/// target listener of the session accepted, and copies between the two
+async fn serve_remote_forwards<B>(
+    backend: Arc<B>,
+    handle: ru_server::Handle,
+    mut recv: mpsc::UnboundedReceiver<ForwardedTcpip>,
+    handler_id: Uuid,
+    user_id: Uuid,
+    target: String,
+) where
+    B: 'static + crate::server::HandlerBackend + Send + Sync,
+{
+    while let Some(forwarded) = recv.recv().await {
+        let client = match handle
+            .channel_open_forwarded_tcpip(
+                forwarded.connected_address.clone(),
+                forwarded.connected_port,
+                forwarded.originator_address.clone(),
+                forwarded.originator_port,
+            )
+            .await
+        {
+            Ok(ch) => ch,
+            Err(e) => {
+                debug!(
+                    "[{}] Client refused forwarded-tcpip for {}:{}: {}",
+                    handler_id, forwarded.connected_address, forwarded.connected_port, e
+                );
+                let _ = forwarded.channel.close().await;
+                continue;
+            }
+        };
+        backend
+            .insert_log(
+                handler_id,
+                user_id,
+                FORWARD_LOG_TYPE.into(),
+                format!(
+                    "{}:{} on {} from {}:{}",
+                    forwarded.connected_address,
+                    forwarded.connected_port,
+                    target,
+                    forwarded.originator_address,
+                    forwarded.originator_port
+                ),
+            )
+            .await;
+        tokio::spawn(splice(client, forwarded.channel, handler_id));
+    }
+}
+
+/// Bridges the agent channels the target opens to the client's agent
+async fn serve_agent<B>(
+    backend: Arc<B>,
+    handle: ru_server::Handle,
+    mut recv: mpsc::UnboundedReceiver<Channel<ru_client::Msg>>,
+    handler_id: Uuid,
+    user_id: Uuid,
+    target: String,
+) where
+    B: 'static + crate::server::HandlerBackend + Send + Sync,
+{
+    while let Some(channel) = recv.recv().await {
+        let client = match handle.channel_open_agent().await {
+            Ok(ch) => ch,
+            Err(e) => {
+                debug!("[{}] Client refused an agent channel: {}", handler_id, e);
+                let _ = channel.close().await;
+                continue;
+            }
+        };
+        backend
+            .insert_log(
+                handler_id,
+                user_id,
+                AGENT_LOG_TYPE.into(),
+                format!("agent used from {}", target),
+            )
+            .await;
+        tokio::spawn(splice(client, channel, handler_id));
+    }
+}
+
+/// Copies between a client and a target channel until either side ends
+async fn splice(
+    client: Channel<ru_server::Msg>,
+    target: Channel<ru_client::Msg>,
+    handler_id: Uuid,
+) {
+    let mut client = client.into_stream();
+    let mut target = target.into_stream();
+    if let Err(e) = tokio::io::copy_bidirectional(&mut client, &mut target).await {
+        trace!("[{}] Spliced channels ended: {}", handler_id, e);
+    }
+}
+
+/// Operation log entry of one file transfer or change on `target`
+async fn log_transfer<B>(
+    backend: &B,
+    handler_id: Uuid,
+    user_id: Uuid,
+    log_type: &str,
+    target: &str,
+    event: &str,
+) where
+    B: crate::server::HandlerBackend,
+{
+    debug!("[{}] {} on '{}': {}", handler_id, log_type, target, event);
+    backend
+        .insert_log(
+            handler_id,
+            user_id,
+            log_type.to_string(),
+            format!("{} on {}", event, target),
+        )
+        .await;
+}
+
+/// First binding of `target_name` granted by a policy with the `anyuser` flag
+async fn any_user_binding<B>(
+    backend: Arc<B>,
+    targets: &[TargetSecretName],
+    target_name: &str,
+) -> Result<Option<TargetSecretName>, Error>
+where
+    B: 'static + crate::server::HandlerBackend + Send + Sync,
+{
+    let mut candidates = targets
+        .iter()
+        .filter(|t| t.target_name == target_name)
+        .peekable();
+    if candidates.peek().is_none() {
+        return Ok(None);
+    }
+    let policies = backend
+        .db_repository()
+        .list_casbin_rules_by_ptype("p")
+        .await?;
+    Ok(candidates
+        .find(|t| {
+            policies.iter().any(|p| {
+                p.id == t.pid
+                    && p.v3
+                        .parse::<casbin::ExtendPolicy>()
+                        .is_ok_and(|ext| ext.policies().any(|p| p.any_user))
+            })
+        })
+        .cloned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_account_name() {
+        for name in ["root", "lab-user", "svc_backup.2"] {
+            assert!(is_account_name(name), "{}", name);
+        }
+        for name in ["", "-oProxyCommand", "a b", "x;id", "ünï", &"a".repeat(33)] {
+            assert!(!is_account_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_expiry_warnings() {
+        let min = Duration::from_secs(60);
+        assert_eq!(
+            expiry_warnings(60 * min),
+            vec![(50 * min, 10 * min), (59 * min, min)]
+        );
+        // Logged in with 5 minutes left, told right away
+        assert_eq!(
+            expiry_warnings(5 * min),
+            vec![(Duration::ZERO, 5 * min), (4 * min, min)]
+        );
+        assert_eq!(
+            expiry_warnings(Duration::from_secs(30)),
+            vec![(Duration::ZERO, Duration::from_secs(30))]
+        );
+    }
+
+    #[test]
+    fn test_idle_warning() {
+        let min = Duration::from_secs(60);
+        assert_eq!(idle_warning(30 * min), min);
+        assert_eq!(idle_warning(2 * min), min);
+        assert_eq!(
+            idle_warning(Duration::from_secs(90)),
+            Duration::from_secs(45)
+        );
+    }
+
+    #[test]
+    fn test_edit_line() {
+        let mut input = Vec::new();
+        let (line, echo) = edit_line(&mut input, b"ab\x7fc", 10, true);
+        assert!(matches!(line, LineInput::Partial));
+        assert_eq!(echo, b"ab\x08 \x08c");
+        let (line, echo) = edit_line(&mut input, "é\r".as_bytes(), 10, false);
+        assert!(matches!(line, LineInput::Entered));
+        assert!(echo.is_empty());
+        assert_eq!(input, "acé".as_bytes());
+        // A whole character goes on backspace
+        edit_line(&mut input, b"\x7f", 10, false);
+        assert_eq!(input, b"ac");
+        edit_line(&mut input, b"defghijklm", 4, false);
+        assert_eq!(input, b"acde");
+        assert!(matches!(
+            edit_line(&mut input, b"x\x03", 10, true).0,
+            LineInput::Aborted
+        ));
+    }
+
+    #[test]
+    fn test_idle_limit() {
+        let min = Duration::from_secs(60);
+        let limit = |after, grace| Some(IdleLimit { after, grace });
+        assert_eq!(IdleLimit::new(None, None, Some(min)), None);
+        assert_eq!(
+            IdleLimit::new(Some(30 * min), None, None),
+            limit(29 * min, min)
+        );
+        assert_eq!(
+            IdleLimit::new(Some(30 * min), Some(10 * min), None),
+            limit(10 * min, min)
+        );
+        assert_eq!(
+            IdleLimit::new(Some(30 * min), None, Some(5 * min)),
+            limit(29 * min, 5 * min)
+        );
+        assert_eq!(
+            IdleLimit::new(None, Some(2 * min), None),
+            limit(2 * min, IDLE_WARNING)
+        );
+    }
+}
@@ -1,4 +1,6 @@
+use crate::error::ErrorCode;
 use thiserror::Error;
+use uuid::Uuid;
 
 #[derive(Debug, Error)]
 pub enum AppError {
@@ -8,8 +10,19 @@ pub enum AppError {
     #[error("Channel record already exists")]
     ChannelRecordExists,
 
-    #[error("Init record error")]
-    InitRecordError,
+    #[error("Failed to init recording {recording_id}: {source}")]
+    InitRecord {
+        recording_id: Uuid,
+        #[source]
+        source: Box<crate::error::Error>,
+    },
+
+    #[error("Failed to init session {session_id}: {source}")]
+    InitSession {
+        session_id: Uuid,
+        #[source]
+        source: Box<crate::error::Error>,
+    },
 
     #[error("Channel notify already exists")]
     ChannelNotifyExists,
@@ -18,3 +31,16 @@ pub enum AppError {
     #[error(transparent)]
     Admin(#[from] super::admin::error::AdminError),
 }
+
+impl AppError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::NoTargetAvailable => ErrorCode::NotFound,
+            AppError::InitRecord { source, .. } | AppError::InitSession { source, .. } => {
+                source.code()
+            }
+            AppError::ChannelRecordExists | AppError::ChannelNotifyExists => ErrorCode::Internal,
+            AppError::Admin(e) => e.code(),
+        }
+    }
+}
@@ -33,7 +33,7 @@ use crate::asciinema::{
 };
 use crate::database::Uuid;
 use crossbeam_channel::{Receiver, Sender, unbounded};
-use log::{debug, trace, warn};
+use log::{debug, log, trace, warn};
 use tokio::sync::mpsc;
 
 use russh::server as ru_server;
@@ -175,10 +175,10 @@ impl Player {
                 sender.send(win_raw).map_err(std::io::Error::other)?;
             }
             session.channel_success(channel)?;
+        } else {
+            session.channel_failure(channel)?;
         }
 
-        session.channel_failure(channel)?;
-
         Ok(())
     }
 
@@ -366,6 +366,14 @@ where
             .unwrap_or(0)
             .max(6);
 
+        let duration_len = items
+            .iter()
+            .map(|v| v.print_duration())
+            .map(|v| UnicodeWidthStr::width(v.as_str()))
+            .max()
+            .unwrap_or(0)
+            .max(8);
+
         let status_len = items
             .iter()
             .map(|v| v.status.as_str())
@@ -378,6 +386,7 @@ where
             Constraint::Length(target_len as u16),
             Constraint::Length(DATETIME_LENGTH),
             Constraint::Length(DATETIME_LENGTH),
+            Constraint::Length(duration_len as u16),
             Constraint::Length(status_len as u16),
         ]
     }
@@ -409,9 +418,16 @@ where
         terminal: &mut Terminal<NottyBackend<W>>,
     ) -> Result<(), Error> {
         let idx = self.table.state.selected().unwrap();
-        let file_path = std::path::PathBuf::from(self.backend.record_path())
-            .join(self.items.get(idx).unwrap().generate_path());
-        let recording = asciicast::open_from_path(std::path::Path::new(&file_path))?;
+        let file_path = &self.items.get(idx).unwrap().file_path;
+        let recording = match self.backend.record_s3() {
+            Some(s3) => {
+                let cast = self.t_handle.block_on(s3.get(file_path))?;
+                asciicast::open(std::io::Cursor::new(cast))?
+            }
+            None => asciicast::open_from_path(
+                std::path::PathBuf::from(self.backend.record_path()).join(file_path),
+            )?,
+        };
 
         let initial_cols = recording.header.term_cols;
         let initial_rows = recording.header.term_rows;
@@ -720,8 +736,13 @@ where
         loop {
             if self.is_playing {
                 if let Err(e) = self.do_play(&tty, terminal) {
-                    warn!("[{}] Play record cast error: {}", self.handler_id, e);
-                    self.message = Some(Message::Error(vec!["Internal error".into()]));
+                    log!(
+                        e.log_level(),
+                        "[{}] Play record cast error: {}",
+                        self.handler_id,
+                        e
+                    );
+                    self.message = Some(Message::Error(vec![e.user_message()]));
                 };
                 self.is_playing = false;
             }
@@ -1,12 +1,16 @@
 use crate::database::Uuid;
-use crate::database::models::{TargetSecretName, User};
+use crate::database::common::InternalUuids;
+use crate::database::models::{TagFilter, TargetSecretName, TargetTag, User};
 use crate::error::Error;
-use crate::server::HandlerLog;
 use crate::server::app::error::AppError;
 use crate::server::app::{Application, ConnectTarget};
+use crate::server::casbin::ExtendPolicyReq;
+use crate::server::motd::motd_for;
 use crossbeam_channel::{Sender, unbounded};
 use crossterm::event::{NoTtyEvent, SenderWriter};
-use log::{debug, trace, warn};
+use inquire::validator::{StringValidator, Validation};
+use inquire::{InquireError, Select, Text, min_length};
+use log::{debug, info, trace, warn};
 use reedline::{
     ColumnarMenu, DefaultPrompt, DefaultPromptSegment, Emacs, ExampleHighlighter,
     FileBackedHistory, MenuBuilder, Reedline, ReedlineMenu, Signal, default_emacs_keybindings,
@@ -14,9 +18,32 @@ use reedline::{
 use reedline::{KeyCode, KeyModifiers, Keybindings, ReedlineEvent};
 use russh::server as ru_server;
 use russh::{Channel, ChannelId};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::mpsc;
 
+/// `filter env=prod team=db` narrows the server list by tag, `filter` alone resets it
+const CMD_FILTER: &str = "filter";
+/// `list` prints the shown servers with their address and SSH URLs
+const CMD_LIST: &str = "list";
+/// `request <server>` asks an admin for a shell on a server the user can't
+/// reach, `request` alone lists those servers
+const CMD_REQUEST: &str = "request";
+
+const LOG_TYPE: &str = "access_request";
+
+#[derive(Clone)]
+struct DurationValidator;
+
+impl StringValidator for DurationValidator {
+    fn validate(&self, input: &str) -> Result<Validation, inquire::error::CustomUserError> {
+        Ok(match humantime::parse_duration(input.trim()) {
+            Ok(d) if !d.is_zero() => Validation::Valid,
+            _ => Validation::Invalid("Use a duration such as 30m, 4h or 2days".into()),
+        })
+    }
+}
+
 #[derive(Clone)]
 enum TerminalStatus {
     SelectTarget,
@@ -30,26 +57,37 @@ pub(crate) struct TargetSelector {
     user: Option<User>,
 
     allowed_targets: Option<Vec<TargetSecretName>>,
+    ticket: Option<String>,
+    client_ip: Option<std::net::IpAddr>,
 
     // shell
     tty: Option<NoTtyEvent>,
     send_to_tty: Option<Sender<Vec<u8>>>,
-
-    log: HandlerLog,
 }
 
 impl TargetSelector {
-    pub(crate) fn new(id: Uuid, user: Option<User>, log: HandlerLog) -> Self {
+    pub(crate) fn new(id: Uuid, user: Option<User>) -> Self {
         Self {
             handler_id: id,
             user,
             allowed_targets: None,
+            ticket: None,
+            client_ip: None,
             tty: None,
             send_to_tty: None,
-            log,
         }
     }
 
+    pub(crate) fn with_ticket(mut self, val: Option<String>) -> Self {
+        self.ticket = val;
+        self
+    }
+
+    pub(crate) fn with_client_ip(mut self, val: Option<std::net::IpAddr>) -> Self {
+        self.client_ip = val;
+        self
+    }
+
     pub(crate) async fn data(
         &mut self,
         _channel: ChannelId,
@@ -113,7 +151,9 @@ impl TargetSelector {
             self.handler_id,
             allowed_targets.iter().map(|v| v.id).collect::<Vec<Uuid>>()
         );
-        if allowed_targets.is_empty() {
+        // With access requests on, the selector is where a user without
+        // servers asks for one
+        if allowed_targets.is_empty() && !backend.access_requests() {
             return Ok(false);
         }
 
@@ -138,10 +178,10 @@ impl TargetSelector {
                 sender.send(win_raw).map_err(std::io::Error::other)?;
             }
             session.channel_success(channel)?;
+        } else {
+            session.channel_failure(channel)?;
         }
 
-        session.channel_failure(channel)?;
-
         Ok(())
     }
 
@@ -169,9 +209,39 @@ impl TargetSelector {
             .take()
             .unwrap_or_else(|| panic!("[{}] at least one target available", handler_id));
 
-        if allowed_targets.is_empty() {
+        if allowed_targets.is_empty() && !backend.access_requests() {
             return Err(Error::App(AppError::NoTargetAvailable));
         }
+        let requestable = if backend.access_requests() {
+            requestable_targets(backend.as_ref(), &allowed_targets).await?
+        } else {
+            Vec::new()
+        };
+
+        let motd = match backend.motd() {
+            Some(template) => {
+                let targets = allowed_targets
+                    .iter()
+                    .map(|v| v.target_id)
+                    .collect::<std::collections::HashSet<_>>()
+                    .len();
+                Some(motd_for(template, backend.db_repository(), &user, targets, None).await)
+            }
+            None => None,
+        };
+
+        // Shown to the right of the server prompt
+        let quota_footer = match backend.list_session_quotas(&user.id).await {
+            Ok(quotas) => quotas
+                .iter()
+                .map(|q| q.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            Err(e) => {
+                warn!("[{}] Fail to list session quotas: {}", handler_id, e);
+                String::new()
+            }
+        };
 
         let (send_status, mut recv_status) = mpsc::channel(1);
 
@@ -242,8 +312,9 @@ impl TargetSelector {
         });
 
         let tokio_handle = tokio::runtime::Handle::current();
-        let handler_log = self.log.clone();
         let handler_id = self.handler_id;
+        let ticket = self.ticket.clone();
+        let client_ip = self.client_ip;
 
         tokio::task::spawn_blocking(move || {
             // TODO: Classify different target type in future
@@ -256,12 +327,39 @@ impl TargetSelector {
 
             let mut selected_target_sec_name = None;
             let backend = backend;
+            let mut target_tags: HashMap<Uuid, Vec<TargetTag>> = HashMap::new();
+            match tokio_handle.block_on(backend.db_repository().list_target_tags()) {
+                Ok(tags) => {
+                    // Only tags of reachable targets, others would leak through completion
+                    for t in tags {
+                        if allowed_targets.iter().any(|v| v.target_id == t.target_id) {
+                            target_tags.entry(t.target_id).or_default().push(t);
+                        }
+                    }
+                }
+                Err(e) => warn!("[{}] Fail to list target tags: {}", handler_id, e),
+            }
+            let tag_labels: Vec<String> = target_tags
+                .values()
+                .flatten()
+                .map(|t| t.label())
+                .collect::<std::collections::HashSet<_>>()
+                .into_iter()
+                .collect();
+            let mut tag_filter: Vec<TagFilter> = Vec::new();
             let target_commands: Vec<String> = allowed_targets
                 .iter()
                 .map(|v| v.target_name.clone())
                 .collect::<std::collections::HashSet<_>>()
                 .into_iter()
                 .collect();
+            let mut requestable_names: Vec<String> = requestable
+                .iter()
+                .map(|v| v.target_name.clone())
+                .collect::<std::collections::HashSet<_>>()
+                .into_iter()
+                .collect();
+            requestable_names.sort();
 
             // init prompt
             let history = Box::new(
@@ -269,6 +367,7 @@ impl TargetSelector {
                     .unwrap_or_else(|_| panic!("[{}] safe capacity", handler_id)),
             );
 
+            let picker_tty = tty.clone();
             let mut line_editor = Reedline::create(tty, SenderWriter::new(send_to_session.clone()))
                 .with_quick_completions(true)
                 .with_menu(ReedlineMenu::EngineCompleter(Box::new(
@@ -287,28 +386,47 @@ impl TargetSelector {
             loop {
                 match status {
                     TerminalStatus::SelectTarget => {
-                        if target_commands.len() == 1 {
+                        if target_commands.len() == 1 && requestable.is_empty() {
                             status = TerminalStatus::SelectUser;
                             selected_target_name = target_commands.first().unwrap().clone();
                             continue;
                         }
-                        let prompt = DefaultPrompt::new(
-                            DefaultPromptSegment::Basic(server_prompt.to_string()),
-                            DefaultPromptSegment::Empty,
+                        let shown = filter_targets(
+                            &target_commands,
+                            &allowed_targets,
+                            &target_tags,
+                            &tag_filter,
                         );
+                        let prompt_text = if tag_filter.is_empty() {
+                            server_prompt.to_string()
+                        } else {
+                            let labels: Vec<String> =
+                                tag_filter.iter().map(|f| f.to_string()).collect();
+                            format!("{} [{}]", server_prompt, labels.join(" "))
+                        };
+                        let right = if quota_footer.is_empty() {
+                            DefaultPromptSegment::Empty
+                        } else {
+                            DefaultPromptSegment::Basic(quota_footer.clone())
+                        };
+                        let prompt =
+                            DefaultPrompt::new(DefaultPromptSegment::Basic(prompt_text), right);
 
                         let mut completer = Box::new(
-                            crate::terminal::BastionCompleter::with_inclusions(&['-', '_'])
+                            crate::terminal::BastionCompleter::with_inclusions(&['-', '_', '='])
                                 .set_min_word_len(0),
                         );
-                        completer.insert(target_commands.clone());
-
-                        line_editor =
-                            line_editor
-                                .with_completer(completer)
-                                .with_highlighter(Box::new(ExampleHighlighter::new(
-                                    target_commands.clone(),
-                                )));
+                        completer.insert(shown.clone());
+                        completer.insert(vec![CMD_FILTER.to_string(), CMD_LIST.to_string()]);
+                        completer.insert(tag_labels.clone());
+                        if !requestable.is_empty() {
+                            completer.insert(vec![CMD_REQUEST.to_string()]);
+                            completer.insert(requestable_names.clone());
+                        }
+
+                        line_editor = line_editor
+                            .with_completer(completer)
+                            .with_highlighter(Box::new(ExampleHighlighter::new(shown.clone())));
                         let sig = line_editor.read_line(&prompt);
 
                         match sig {
@@ -320,6 +438,77 @@ impl TargetSelector {
                                     status = TerminalStatus::Terminate;
                                     continue;
                                 }
+                                if let Some(rest) = p.strip_prefix(CMD_FILTER)
+                                    && (rest.is_empty() || rest.starts_with(' '))
+                                {
+                                    let msg = match TagFilter::parse_list(rest) {
+                                        Ok(f) => {
+                                            tag_filter = f;
+                                            let shown = filter_targets(
+                                                &target_commands,
+                                                &allowed_targets,
+                                                &target_tags,
+                                                &tag_filter,
+                                            );
+                                            format!(
+                                                "{} server(s):\r\n{}",
+                                                shown.len(),
+                                                target_rows(&shown, &allowed_targets)
+                                            )
+                                        }
+                                        Err(e) => e.to_string(),
+                                    };
+                                    if let Err(e) = send_to_session.blocking_send(msg.into()) {
+                                        warn!(
+                                            "[{}] Fail to send data to channel from prompt: {}",
+                                            handler_id, e
+                                        );
+                                        status = TerminalStatus::Terminate;
+                                    }
+                                    continue;
+                                }
+                                if p.as_str() == CMD_LIST {
+                                    let msg = target_rows(&shown, &allowed_targets);
+                                    if let Err(e) = send_to_session.blocking_send(msg.into()) {
+                                        warn!(
+                                            "[{}] Fail to send data to channel from prompt: {}",
+                                            handler_id, e
+                                        );
+                                        status = TerminalStatus::Terminate;
+                                    }
+                                    continue;
+                                }
+                                if let Some(rest) = p.strip_prefix(CMD_REQUEST)
+                                    && !requestable.is_empty()
+                                    && (rest.is_empty() || rest.starts_with(' '))
+                                {
+                                    let name = rest.trim();
+                                    let msg = if name.is_empty() {
+                                        format!(
+                                            "Servers you may request:\r\n{}",
+                                            requestable_names.join("\r\n")
+                                        )
+                                    } else {
+                                        request_access(
+                                            backend.as_ref(),
+                                            &tokio_handle,
+                                            handler_id,
+                                            &user,
+                                            &requestable,
+                                            name,
+                                            picker_tty.clone(),
+                                            &send_to_session,
+                                        )
+                                    };
+                                    if let Err(e) = send_to_session.blocking_send(msg.into()) {
+                                        warn!(
+                                            "[{}] Fail to send data to channel from prompt: {}",
+                                            handler_id, e
+                                        );
+                                        status = TerminalStatus::Terminate;
+                                    }
+                                    continue;
+                                }
                                 if !target_commands.iter().any(|v| v == &p) {
                                     status = TerminalStatus::SelectTarget;
                                     if let Err(e) = send_to_session.blocking_send(
@@ -370,48 +559,28 @@ impl TargetSelector {
                             continue;
                         }
 
-                        let prompt = DefaultPrompt::new(
-                            DefaultPromptSegment::Basic(user_prompt.to_string()),
-                            DefaultPromptSegment::Empty,
-                        );
-
-                        let mut completer = Box::new(
-                            crate::terminal::BastionCompleter::with_inclusions(&['-', '_'])
-                                .set_min_word_len(0),
+                        // Accounts are picked from a list rather than typed, so
+                        // a host with several bound secrets stays a single entry
+                        // in the server prompt.
+                        let mut user_options = user_commands;
+                        user_options.sort();
+                        let address = allowed_targets
+                            .iter()
+                            .find(|v| v.target_name == selected_target_name)
+                            .map(|v| v.address())
+                            .unwrap_or_default();
+                        let picked = Select::new(
+                            &format!("{} on {} ({})", user_prompt, selected_target_name, address),
+                            user_options,
+                        )
+                        .with_help_message("↑↓ to move, enter to connect, esc to go back")
+                        .prompt(
+                            picker_tty.clone(),
+                            SenderWriter::new(send_to_session.clone()),
                         );
-                        completer.insert(user_commands.clone());
-
-                        line_editor =
-                            line_editor
-                                .with_completer(completer)
-                                .with_highlighter(Box::new(ExampleHighlighter::new(
-                                    user_commands.clone(),
-                                )));
-
-                        let sig = line_editor.read_line(&prompt);
 
-                        match sig {
-                            Ok(Signal::Success(p)) => {
-                                if p.is_empty() {
-                                    continue;
-                                }
-                                if p.as_str() == "quit" || p.as_str() == "exit" {
-                                    status = TerminalStatus::Terminate;
-                                    continue;
-                                }
-                                if !user_commands.iter().any(|v| v == &p) {
-                                    status = TerminalStatus::SelectUser;
-                                    if let Err(e) = send_to_session.blocking_send(
-                                        format!("System user: {} doesn't exist", p).into(),
-                                    ) {
-                                        warn!(
-                                            "[{}] Fail to send data to channel from prompt: {}",
-                                            handler_id, e
-                                        );
-                                        status = TerminalStatus::Terminate;
-                                    };
-                                    continue;
-                                }
+                        match picked {
+                            Ok(p) => {
                                 let target_sec_name = allowed_targets
                                     .iter()
                                     .find(|v| {
@@ -425,10 +594,10 @@ impl TargetSelector {
                                 selected_target_sec_name = Some(target_sec_name);
                                 status = TerminalStatus::Connect;
                             }
-                            Ok(Signal::CtrlC) => {
+                            Err(InquireError::OperationInterrupted) => {
                                 continue;
                             }
-                            Ok(Signal::CtrlD) => {
+                            Err(InquireError::OperationCanceled) => {
                                 status = TerminalStatus::SelectTarget;
                                 if allowed_targets
                                     .iter()
@@ -436,13 +605,14 @@ impl TargetSelector {
                                     .collect::<std::collections::HashSet<_>>()
                                     .len()
                                     == 1
+                                    && requestable.is_empty()
                                 {
                                     status = TerminalStatus::Terminate;
                                 }
                             }
-                            Ok(_) => unreachable!(),
                             Err(e) => {
-                                warn!("[{}] Fail to get signal from prompt: {}", handler_id, e);
+                                warn!("[{}] Fail to get account from picker: {}", handler_id, e);
+                                status = TerminalStatus::Terminate;
                             }
                         }
                     }
@@ -453,7 +623,41 @@ impl TargetSelector {
                         return;
                     }
                     TerminalStatus::Connect => {
-                        break;
+                        let Some(picked) = selected_target_sec_name.as_ref() else {
+                            break;
+                        };
+                        let Some(msg) = under_maintenance(
+                            backend.as_ref(),
+                            &tokio_handle,
+                            handler_id,
+                            user.id,
+                            picked,
+                            client_ip,
+                        )
+                        .or_else(|| {
+                            quota_used_up(
+                                backend.as_ref(),
+                                &tokio_handle,
+                                handler_id,
+                                user.id,
+                                picked,
+                                ticket.clone(),
+                            )
+                        }) else {
+                            break;
+                        };
+                        status = if target_commands.len() == 1 && requestable.is_empty() {
+                            TerminalStatus::Terminate
+                        } else {
+                            TerminalStatus::SelectTarget
+                        };
+                        if let Err(e) = send_to_session.blocking_send(msg.into()) {
+                            warn!(
+                                "[{}] Fail to send data to channel from prompt: {}",
+                                handler_id, e
+                            );
+                            status = TerminalStatus::Terminate;
+                        }
                     }
                 }
             }
@@ -485,9 +689,11 @@ impl TargetSelector {
                 }
             };
 
-            let connect_target = ConnectTarget::new(handler_id, Some(user), handler_log)
+            let connect_target = ConnectTarget::new(handler_id, Some(user))
                 .with_target(target)
-                .with_target_sec_name(selected_target_sec_name);
+                .with_target_sec_name(selected_target_sec_name)
+                .with_ticket(ticket)
+                .with_motd_shown(true);
             if app_sender
                 .blocking_send((
                     channel_id,
@@ -502,6 +708,9 @@ impl TargetSelector {
             };
         });
         session.channel_success(channel)?;
+        if let Some(motd) = motd {
+            session.data(channel, motd.into_bytes())?;
+        }
         Ok(())
     }
 }
@@ -512,6 +721,203 @@ impl Drop for TargetSelector {
     }
 }
 
+/// Active bindings the user has no policy for
+async fn requestable_targets<B: crate::server::HandlerBackend>(
+    backend: &B,
+    allowed_targets: &[TargetSecretName],
+) -> Result<Vec<TargetSecretName>, Error> {
+    let repo = backend.db_repository();
+    let ids: Vec<Uuid> = repo
+        .list_target_secrets(true)
+        .await?
+        .into_iter()
+        .map(|ts| ts.id)
+        .filter(|id| !allowed_targets.iter().any(|v| v.id == *id))
+        .collect();
+    let ids: Vec<&Uuid> = ids.iter().collect();
+    repo.list_targets_by_ids(&ids, &Uuid::nil(), true).await
+}
+
+/// Asks for the account, duration and reason of an access request to `name`
+/// and files it. Returns what to tell the user.
+#[allow(clippy::too_many_arguments)]
+fn request_access<B: crate::server::HandlerBackend>(
+    backend: &B,
+    tokio_handle: &tokio::runtime::Handle,
+    handler_id: Uuid,
+    user: &User,
+    requestable: &[TargetSecretName],
+    name: &str,
+    tty: NoTtyEvent,
+    send_to_session: &mpsc::Sender<Vec<u8>>,
+) -> String {
+    let mut accounts: Vec<&TargetSecretName> = requestable
+        .iter()
+        .filter(|v| v.target_name == name)
+        .collect();
+    if accounts.is_empty() {
+        return format!("Server: {} can't be requested", name);
+    }
+    accounts.sort_by(|a, b| a.secret_user.cmp(&b.secret_user));
+    accounts.dedup_by(|a, b| a.secret_user == b.secret_user);
+
+    let writer = || SenderWriter::new(send_to_session.clone());
+    let account = if accounts.len() == 1 {
+        accounts[0]
+    } else {
+        let options: Vec<String> = accounts.iter().map(|v| v.secret_user.clone()).collect();
+        let picked = Select::new(&format!("Account on {}", name), options)
+            .with_help_message("↑↓ to move, enter to pick, esc to cancel")
+            .prompt(tty.clone(), writer());
+        match picked
+            .ok()
+            .and_then(|p| accounts.iter().find(|v| v.secret_user == p))
+        {
+            Some(v) => v,
+            None => return "Request cancelled".to_string(),
+        }
+    };
+    let duration = match Text::new("Duration: ")
+        .with_default("1h")
+        .with_validator(DurationValidator)
+        .with_help_message("How long the access lasts once approved")
+        .prompt(tty.clone(), writer())
+    {
+        Ok(d) => humantime::parse_duration(d.trim()).unwrap_or_default(),
+        Err(_) => return "Request cancelled".to_string(),
+    };
+    let reason = match Text::new("Reason: ")
+        .with_validator(min_length!(1))
+        .prompt(tty, writer())
+    {
+        Ok(r) => r.trim().to_string(),
+        Err(_) => return "Request cancelled".to_string(),
+    };
+
+    let uuids = InternalUuids::get();
+    let result = tokio_handle.block_on(backend.db_service().request_access(
+        &user.id,
+        &account.id,
+        &[uuids.act_shell, uuids.act_pty],
+        duration,
+        reason,
+    ));
+    match result {
+        Ok(r) => {
+            let detail = format!(
+                "requested {} on {} for {}: {}",
+                account.secret_user,
+                name,
+                r.print_duration(),
+                r.reason
+            );
+            info!("[{}] {} {}", handler_id, user.username, detail);
+            tokio_handle.block_on(backend.insert_log(
+                handler_id,
+                user.id,
+                LOG_TYPE.to_string(),
+                detail,
+            ));
+            format!(
+                "Access to {}@{} requested, it opens once an admin approves",
+                account.secret_user, name
+            )
+        }
+        Err(e) => {
+            warn!(
+                "[{}] Access request of {} for {} failed: {}",
+                handler_id, user.username, name, e
+            );
+            e.user_message()
+        }
+    }
+}
+
+/// Why the user can't open a shell on `target`, when the quota of the
+/// granting policy is used up. The session is checked again once connected.
+fn quota_used_up<B: crate::server::HandlerBackend>(
+    backend: &B,
+    tokio_handle: &tokio::runtime::Handle,
+    handler_id: Uuid,
+    user_id: Uuid,
+    target: &TargetSecretName,
+    ticket: Option<String>,
+) -> Option<String> {
+    let ext = ExtendPolicyReq::default().with_ticket(ticket);
+    let act = InternalUuids::get().act_shell;
+    match tokio_handle.block_on(backend.session_quota(user_id, target.id, act, ext)) {
+        Ok(Some(usage)) if usage.left() == 0 => Some(format!("Session quota used up, {}", usage)),
+        Ok(_) => None,
+        Err(e) => {
+            warn!("[{}] Fail to check session quota: {}", handler_id, e);
+            None
+        }
+    }
+}
+
+/// Why the user can't open a shell on `target`, when a maintenance window
+/// covers it and the user isn't an admin
+fn under_maintenance<B: crate::server::HandlerBackend>(
+    backend: &B,
+    tokio_handle: &tokio::runtime::Handle,
+    handler_id: Uuid,
+    user_id: Uuid,
+    target: &TargetSecretName,
+    ip: Option<std::net::IpAddr>,
+) -> Option<String> {
+    match tokio_handle.block_on(backend.maintenance(user_id, target.target_id, ip)) {
+        Ok(Some((window, until))) => Some(window.notice(&target.target_name, until)),
+        Ok(None) => None,
+        Err(e) => {
+            warn!("[{}] Fail to check maintenance windows: {}", handler_id, e);
+            None
+        }
+    }
+}
+
+/// One line per shown server: name, `host:port` and the SSH URL of each account
+fn target_rows(shown: &[String], allowed_targets: &[TargetSecretName]) -> String {
+    shown
+        .iter()
+        .filter_map(|name| {
+            let accounts: Vec<&TargetSecretName> = allowed_targets
+                .iter()
+                .filter(|v| &v.target_name == name)
+                .collect();
+            let first = accounts.first()?;
+            let mut urls: Vec<String> = accounts.iter().map(|v| v.ssh_url()).collect();
+            urls.sort();
+            urls.dedup();
+            Some(format!("{}  {}  {}", name, first.address(), urls.join(" ")))
+        })
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+/// Target names carrying every tag of `filter`, sorted
+fn filter_targets(
+    names: &[String],
+    allowed_targets: &[TargetSecretName],
+    target_tags: &HashMap<Uuid, Vec<TargetTag>>,
+    filter: &[TagFilter],
+) -> Vec<String> {
+    let mut shown: Vec<String> = names
+        .iter()
+        .filter(|name| {
+            let Some(t) = allowed_targets.iter().find(|v| &v.target_name == *name) else {
+                return false;
+            };
+            let tags = target_tags.get(&t.target_id);
+            filter
+                .iter()
+                .all(|f| tags.is_some_and(|tags| tags.iter().any(|tag| f.matches(tag))))
+        })
+        .cloned()
+        .collect();
+    shown.sort();
+    shown
+}
+
 fn add_menu_keybindings(keybindings: &mut Keybindings) {
     keybindings.add_binding(
         KeyModifiers::NONE,
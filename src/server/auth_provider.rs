@@ -0,0 +1,571 @@
+//! Credential checks behind the SSH handler. The handler looks the user up,
+//! enforces lockouts and TOTP, and leaves the question "is this credential
+//! good for this user" to the providers of `auth_providers`, asked in order.
+//! A new identity backend is one more `AuthProvider`, the handler is left
+//! alone.
+
+use crate::config::error::ConfigError;
+use crate::database::models::User;
+use crate::error::Error;
+use crate::server::error::ServerError;
+use async_trait::async_trait;
+use ipnetwork::IpNetwork;
+use log::{debug, warn};
+use russh::keys::ssh_key::certificate::CertType;
+use russh::keys::ssh_key::{Certificate, Fingerprint, PublicKey};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// One entry of `auth_providers`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AuthProviderConfig {
+    /// Password hashes, authorized keys and `trusted_user_ca_keys` of the
+    /// bastion's own database
+    Local,
+    /// Passwords checked with the password grant at an OpenID provider's
+    /// token endpoint
+    Oidc {
+        token_url: String,
+        client_id: String,
+        /// Sent with HTTP basic authentication, public clients have none
+        #[serde(default)]
+        client_secret: Option<String>,
+        #[serde(default = "default_oidc_timeout", with = "humantime_serde")]
+        timeout: Duration,
+    },
+}
+
+impl AuthProviderConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        match self {
+            AuthProviderConfig::Local => Ok(()),
+            AuthProviderConfig::Oidc {
+                token_url, timeout, ..
+            } => {
+                if !token_url.starts_with("https://") && !token_url.starts_with("http://") {
+                    return Err(ConfigError::OidcTokenUrl {
+                        url: token_url.clone(),
+                    });
+                }
+                if timeout.is_zero() {
+                    return Err(ConfigError::DurationZero {
+                        name: "auth_providers.timeout",
+                    });
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for AuthProviderConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthProviderConfig::Local => write!(f, "local"),
+            AuthProviderConfig::Oidc { token_url, .. } => write!(f, "oidc({})", token_url),
+        }
+    }
+}
+
+pub fn default_auth_providers() -> Vec<AuthProviderConfig> {
+    vec![AuthProviderConfig::Local]
+}
+
+fn default_oidc_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+/// Answers whether a credential proves the identity of a user that already
+/// exists in the bastion. Credential types a provider doesn't handle are
+/// refused by the default methods.
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn verify_password(&self, _user: &User, _password: &str) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    async fn verify_publickey(&self, _user: &User, _key: &PublicKey) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    /// `client_ip` is checked against the certificate's `source-address`
+    async fn verify_certificate(
+        &self,
+        _user: &User,
+        _certificate: &Certificate,
+        _client_ip: Option<IpAddr>,
+    ) -> Result<bool, Error> {
+        Ok(false)
+    }
+}
+
+pub struct Local {
+    user_ca_fingerprints: Vec<Fingerprint>,
+}
+
+impl Local {
+    pub fn new(user_ca_fingerprints: Vec<Fingerprint>) -> Self {
+        Local {
+            user_ca_fingerprints,
+        }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for Local {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    async fn verify_password(&self, user: &User, password: &str) -> Result<bool, Error> {
+        Ok(user.verify_password(password))
+    }
+
+    async fn verify_publickey(&self, user: &User, key: &PublicKey) -> Result<bool, Error> {
+        Ok(user.verify_authorized_keys(key))
+    }
+
+    /// A user certificate signed by a trusted CA, naming the user among its
+    /// principals, whose critical options all hold
+    async fn verify_certificate(
+        &self,
+        user: &User,
+        certificate: &Certificate,
+        client_ip: Option<IpAddr>,
+    ) -> Result<bool, Error> {
+        if self.user_ca_fingerprints.is_empty() || certificate.cert_type() != CertType::User {
+            return Ok(false);
+        }
+        if !certificate
+            .valid_principals()
+            .iter()
+            .any(|p| *p == user.username)
+        {
+            return Ok(false);
+        }
+        if let Err(e) = check_critical_options(certificate, client_ip) {
+            debug!(
+                "Certificate '{}' for {} rejected: {}",
+                certificate.key_id(),
+                user.username,
+                e
+            );
+            return Ok(false);
+        }
+        // Checks the CA signature and the validity window
+        match certificate.validate(&self.user_ca_fingerprints) {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                debug!(
+                    "Certificate '{}' for {} rejected: {}",
+                    certificate.key_id(),
+                    user.username,
+                    e
+                );
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// A client must honour every critical option of its certificate, an
+/// unknown one refuses it. Only `source-address` is understood:
+/// `force-command` would take the menu and the target choice away, so a
+/// certificate carrying it is refused rather than let run anything.
+fn check_critical_options(
+    certificate: &Certificate,
+    client_ip: Option<IpAddr>,
+) -> Result<(), String> {
+    for (name, data) in certificate.critical_options().iter() {
+        if name != "source-address" {
+            return Err(format!("unsupported critical option '{}'", name));
+        }
+        let mut allowed = false;
+        for source in data.split(',') {
+            let network = source
+                .trim()
+                .parse::<IpNetwork>()
+                .map_err(|_| format!("invalid source-address '{}'", source))?;
+            allowed |= client_ip.is_some_and(|ip| network.contains(ip));
+        }
+        if !allowed {
+            return Err(format!("{:?} not in source-address '{}'", client_ip, data));
+        }
+    }
+    Ok(())
+}
+
+/// The resource owner password grant (RFC 6749, section 4.3) at an OpenID
+/// provider: the provider handing out tokens for the user's name and
+/// password accepts it, `invalid_grant` refuses it. The tokens are dropped.
+pub struct Oidc {
+    client: reqwest::Client,
+    token_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    timeout: Duration,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    #[allow(dead_code)]
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct TokenError {
+    error: String,
+}
+
+impl Oidc {
+    pub fn new(
+        token_url: String,
+        client_id: String,
+        client_secret: Option<String>,
+        timeout: Duration,
+    ) -> Self {
+        Oidc {
+            client: reqwest::Client::new(),
+            token_url,
+            client_id,
+            client_secret,
+            timeout,
+        }
+    }
+
+    fn failed(&self, reason: impl ToString) -> Error {
+        ServerError::TokenEndpoint {
+            url: self.token_url.clone(),
+            reason: reason.to_string(),
+        }
+        .into()
+    }
+}
+
+#[async_trait]
+impl AuthProvider for Oidc {
+    fn name(&self) -> &'static str {
+        "oidc"
+    }
+
+    async fn verify_password(&self, user: &User, password: &str) -> Result<bool, Error> {
+        if password.is_empty() {
+            return Ok(false);
+        }
+        let mut form = vec![
+            ("grant_type", "password"),
+            ("username", user.username.as_str()),
+            ("password", password),
+            ("scope", "openid"),
+        ];
+        let mut request = self.client.post(&self.token_url).timeout(self.timeout);
+        match self.client_secret.as_deref() {
+            Some(secret) => request = request.basic_auth(&self.client_id, Some(secret)),
+            None => form.push(("client_id", self.client_id.as_str())),
+        }
+        let response = request
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| self.failed(e))?;
+        let status = response.status();
+        let body = response.bytes().await.map_err(|e| self.failed(e))?;
+        // A page answering 200 to anything must not let everyone in
+        if status.is_success() {
+            return match serde_json::from_slice::<TokenResponse>(&body) {
+                Ok(_) => Ok(true),
+                Err(e) => Err(self.failed(format!("{}: {}", status, e))),
+            };
+        }
+        match serde_json::from_slice::<TokenError>(&body) {
+            Ok(e) if e.error == "invalid_grant" => Ok(false),
+            Ok(e) => Err(self.failed(format!("{}: {}", status, e.error))),
+            Err(_) => Err(self.failed(status)),
+        }
+    }
+}
+
+/// What a client offered, for providers asked one after the other
+#[derive(Clone, Copy)]
+enum Credential<'c> {
+    Password(&'c str),
+    PublicKey(&'c PublicKey),
+    Certificate(&'c Certificate, Option<IpAddr>),
+}
+
+/// The providers of `auth_providers` in order, the first one accepting a
+/// credential wins. A provider failing, say an unreachable directory, is
+/// logged and the next one is asked.
+pub struct Chain {
+    providers: Vec<Box<dyn AuthProvider>>,
+}
+
+impl Chain {
+    pub fn new(providers: Vec<Box<dyn AuthProvider>>) -> Self {
+        Chain { providers }
+    }
+
+    async fn first_accepting(&self, user: &User, credential: Credential<'_>) -> bool {
+        for provider in &self.providers {
+            let verified = match credential {
+                Credential::Password(p) => provider.verify_password(user, p).await,
+                Credential::PublicKey(k) => provider.verify_publickey(user, k).await,
+                Credential::Certificate(c, ip) => provider.verify_certificate(user, c, ip).await,
+            };
+            match verified {
+                Ok(true) => {
+                    debug!("User '{}' accepted by {}", user.username, provider.name());
+                    return true;
+                }
+                Ok(false) => {}
+                Err(e) => warn!(
+                    "Authentication provider {} failed for user '{}': {}",
+                    provider.name(),
+                    user.username,
+                    e
+                ),
+            }
+        }
+        false
+    }
+}
+
+#[async_trait]
+impl AuthProvider for Chain {
+    fn name(&self) -> &'static str {
+        "chain"
+    }
+
+    async fn verify_password(&self, user: &User, password: &str) -> Result<bool, Error> {
+        Ok(self
+            .first_accepting(user, Credential::Password(password))
+            .await)
+    }
+
+    async fn verify_publickey(&self, user: &User, key: &PublicKey) -> Result<bool, Error> {
+        Ok(self.first_accepting(user, Credential::PublicKey(key)).await)
+    }
+
+    async fn verify_certificate(
+        &self,
+        user: &User,
+        certificate: &Certificate,
+        client_ip: Option<IpAddr>,
+    ) -> Result<bool, Error> {
+        Ok(self
+            .first_accepting(user, Credential::Certificate(certificate, client_ip))
+            .await)
+    }
+}
+
+/// Builds the chain configured in `auth_providers`
+pub fn create_auth_provider(
+    configs: &[AuthProviderConfig],
+    user_ca_fingerprints: Vec<Fingerprint>,
+) -> Result<Chain, Error> {
+    let mut providers: Vec<Box<dyn AuthProvider>> = Vec::with_capacity(configs.len());
+    for config in configs {
+        match config {
+            AuthProviderConfig::Local => {
+                providers.push(Box::new(Local::new(user_ca_fingerprints.clone())))
+            }
+            AuthProviderConfig::Oidc {
+                token_url,
+                client_id,
+                client_secret,
+                timeout,
+            } => {
+                if token_url.starts_with("http://") {
+                    warn!("Passwords are sent to {} in clear text", token_url);
+                }
+                providers.push(Box::new(Oidc::new(
+                    token_url.clone(),
+                    client_id.clone(),
+                    client_secret.clone(),
+                    *timeout,
+                )))
+            }
+        }
+    }
+    Ok(Chain::new(providers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rng;
+    use russh::keys::ssh_key::HashAlg;
+    use russh::keys::ssh_key::certificate::Builder;
+    use russh::keys::{Algorithm, PrivateKey};
+    use uuid::Uuid;
+
+    /// Accepts one password, fails on anything else
+    struct Flaky(&'static str);
+
+    #[async_trait]
+    impl AuthProvider for Flaky {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        async fn verify_password(&self, _user: &User, password: &str) -> Result<bool, Error> {
+            if password == self.0 {
+                return Ok(true);
+            }
+            Err(ServerError::InvalidLoginName.into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain() {
+        let mut user = User::new(Uuid::nil());
+        user.username = "alice".to_string();
+        let chain = Chain::new(vec![
+            Box::new(Flaky("directory")),
+            Box::new(Local::new(Vec::new())),
+        ]);
+        assert!(chain.verify_password(&user, "directory").await.unwrap());
+        // The failing provider is skipped, local has no password for alice
+        assert!(!chain.verify_password(&user, "other").await.unwrap());
+
+        assert!(create_auth_provider(&default_auth_providers(), Vec::new()).is_ok());
+        assert!(serde_json::from_str::<AuthProviderConfig>(r#"{"type": "ldap"}"#).is_err());
+    }
+
+    /// An HTTP server on localhost answering every request with `reply`,
+    /// which gets the lowercased request
+    async fn token_endpoint(reply: fn(&str) -> (u16, &'static str)) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).to_lowercase();
+                    let Some(end) = text.find("\r\n\r\n") else {
+                        continue;
+                    };
+                    let length = text
+                        .lines()
+                        .find_map(|l| l.strip_prefix("content-length:"))
+                        .map_or(0, |l| l.trim().parse::<usize>().unwrap());
+                    if request.len() >= end + 4 + length {
+                        break;
+                    }
+                }
+                let (status, body) = reply(&String::from_utf8_lossy(&request).to_lowercase());
+                let response = format!(
+                    "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        format!("http://{}/token", addr)
+    }
+
+    #[tokio::test]
+    async fn test_oidc() {
+        let mut user = User::new(Uuid::nil());
+        user.username = "alice".to_string();
+        let oidc =
+            |url: String| Oidc::new(url, "rustion".to_string(), None, Duration::from_secs(5));
+
+        let provider = oidc(
+            token_endpoint(|request| {
+                if !request.contains("grant_type=password")
+                    || !request.contains("username=alice")
+                    || !request.contains("client_id=rustion")
+                {
+                    return (400, r#"{"error":"invalid_request"}"#);
+                }
+                if request.contains("password=s3cret") {
+                    (200, r#"{"access_token":"t","token_type":"Bearer"}"#)
+                } else {
+                    (400, r#"{"error":"invalid_grant"}"#)
+                }
+            })
+            .await,
+        );
+        assert!(provider.verify_password(&user, "s3cret").await.unwrap());
+        assert!(!provider.verify_password(&user, "wrong").await.unwrap());
+        assert!(!provider.verify_password(&user, "").await.unwrap());
+
+        // Neither a page answering 200 nor a rejected client accepts anyone
+        let html = oidc(token_endpoint(|_| (200, "<html></html>")).await);
+        assert!(html.verify_password(&user, "s3cret").await.is_err());
+        let client = oidc(token_endpoint(|_| (401, r#"{"error":"invalid_client"}"#)).await);
+        assert!(client.verify_password(&user, "s3cret").await.is_err());
+
+        let config: AuthProviderConfig = serde_json::from_str(
+            r#"{"type": "oidc", "token_url": "ldap://idp", "client_id": "rustion"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::OidcTokenUrl { .. })
+        ));
+    }
+
+    /// A user certificate for alice signed by `ca`, with `options`
+    fn certificate(ca: &PrivateKey, options: &[(&str, &str)]) -> Certificate {
+        let key = PrivateKey::random(&mut rng(), Algorithm::Ed25519).unwrap();
+        let now = chrono::Utc::now().timestamp() as u64;
+        let mut builder = Builder::new_with_random_nonce(
+            &mut rng(),
+            key.public_key().key_data().clone(),
+            now - 60,
+            now + 300,
+        )
+        .unwrap();
+        builder.cert_type(CertType::User).unwrap();
+        builder.valid_principal("alice").unwrap();
+        for (name, data) in options {
+            builder.critical_option(*name, *data).unwrap();
+        }
+        builder.sign(ca).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_certificate_critical_options() {
+        let mut user = User::new(Uuid::nil());
+        user.username = "alice".to_string();
+        let ca = PrivateKey::random(&mut rng(), Algorithm::Ed25519).unwrap();
+        let local = Local::new(vec![ca.public_key().fingerprint(HashAlg::Sha256)]);
+        let inside: Option<IpAddr> = Some("10.1.2.3".parse().unwrap());
+        let outside: Option<IpAddr> = Some("198.51.100.7".parse().unwrap());
+        let accepts = async |cert: &Certificate, ip: Option<IpAddr>| {
+            local.verify_certificate(&user, cert, ip).await.unwrap()
+        };
+
+        let plain = certificate(&ca, &[]);
+        assert!(accepts(&plain, outside).await);
+
+        let sourced = certificate(&ca, &[("source-address", "192.0.2.1,10.0.0.0/8")]);
+        assert!(accepts(&sourced, inside).await);
+        assert!(!accepts(&sourced, outside).await);
+        assert!(!accepts(&sourced, None).await);
+        let malformed = certificate(&ca, &[("source-address", "10.0.0.0/8,nowhere")]);
+        assert!(!accepts(&malformed, inside).await);
+
+        // The bastion can't confine a session to one command
+        let forced = certificate(&ca, &[("force-command", "/usr/bin/true")]);
+        assert!(!accepts(&forced, inside).await);
+
+        let unknown = certificate(&ca, &[("verify-required", "")]);
+        assert!(!accepts(&unknown, inside).await);
+    }
+}
@@ -1,19 +1,68 @@
+//! One client connection. Authentication runs here: the user is looked up,
+//! lockouts, `allowed_source_cidrs`, TOTP and push approval are enforced,
+//! and credentials go to the `auth_provider` chain. Channels are then
+//! routed to the `Application` the login name picked, within the
+//! `[limits]` caps.
+
 use super::app::{self, Application};
 use super::error::ServerError;
+use super::motd;
+use super::push_mfa::PushRequest;
+use super::sftp_audit::SFTP_SUBSYSTEM;
+use super::trace::Trace;
 use super::HandlerBackend;
-use crate::database::models::User;
+use crate::config::LimitsConfig;
+use crate::database::models::{Timestamp, User};
 use crate::database::Uuid;
 use crate::error::Error;
 use crate::server::casbin::ExtendPolicyReq;
 use futures::future::FutureExt;
 use log::{debug, info, trace, warn};
-use russh::keys::ssh_key::PublicKey;
+use russh::keys::ssh_key::{Certificate, PublicKey};
 use russh::server as ru_server;
-use russh::{Channel, ChannelId, Pty};
+use russh::{Channel, ChannelId, MethodKind, MethodSet, Pty};
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 
 static LOG_TYPE: &str = "server";
+const TOTP_PROMPT: &str = "Verification code: ";
+const PASSWORD_PROMPT: &str = "Password: ";
+
+/// Points the client at keyboard-interactive, where it is asked for the TOTP
+/// code again or told about a lockout
+fn keyboard_interactive_reject() -> ru_server::Auth {
+    ru_server::Auth::Reject {
+        proceed_with_methods: Some(MethodSet::from(&[MethodKind::KeyboardInteractive][..])),
+        partial_success: false,
+    }
+}
+
+/// Keyboard-interactive info request with at most one hidden prompt. Without
+/// a prompt, clients print the instructions and answer with nothing.
+fn info_request(instructions: String, prompt: Option<&'static str>) -> ru_server::Auth {
+    let prompts: Vec<(std::borrow::Cow<'static, str>, bool)> =
+        prompt.map(|p| vec![(p.into(), false)]).unwrap_or_default();
+    ru_server::Auth::Partial {
+        name: "".into(),
+        instructions: instructions.into(),
+        prompts: prompts.into(),
+    }
+}
+
+/// How an authentication request was answered, for traces
+fn auth_outcome(auth: &ru_server::Auth) -> &'static str {
+    match auth {
+        ru_server::Auth::Accept => "accept",
+        ru_server::Auth::Reject {
+            partial_success: true,
+            ..
+        } => "partial success",
+        ru_server::Auth::Partial { .. } => "info request",
+        _ => "reject",
+    }
+}
 
 pub struct BastionHandler<B: HandlerBackend + Send + Clone> {
     // Unique ID for each connection.
@@ -26,6 +75,24 @@ pub struct BastionHandler<B: HandlerBackend + Send + Clone> {
     log: super::HandlerLog,
     auth_attempts_per_conn: u32,
     max_auth_attempts_per_conn: u32,
+    // first factor that succeeded while the TOTP code is still owed
+    pending_totp: Option<&'static str>,
+    // authenticated over keyboard-interactive, accepted once the client has
+    // answered the login notice
+    pending_notice: bool,
+    // set once attempts ran out, every later login is refused
+    lockout: Option<Lockout>,
+    // channels currently open on this connection, and those of them that
+    // ran an exec or carry a direct-tcpip stream
+    channels: HashSet<ChannelId>,
+    execs: HashSet<ChannelId>,
+    direct_tcpip: HashSet<ChannelId>,
+    max_channels: usize,
+    max_execs: usize,
+    max_direct_tcpip: usize,
+    // debug trace asked for by a trace rule, looked up on the first login
+    trace: Option<Trace>,
+    trace_checked: bool,
     send_app_msg: Sender<(ChannelId, Application)>,
     recv_app_msg: Receiver<(ChannelId, Application)>,
     //pty
@@ -38,11 +105,1015 @@ impl<B: 'static + HandlerBackend + Send + Sync> ru_server::Handler for BastionHa
     type Error = crate::error::Error;
     type Data = (ChannelId, Application);
 
-    async fn channel_open_session(
+    async fn channel_open_session(
+        &mut self,
+        channel: Channel<ru_server::Msg>,
+        session: &mut ru_server::Session,
+    ) -> Result<bool, Self::Error> {
+        let id = channel.id();
+        if !self.slot_free(id, "channel", self.channels.len(), self.max_channels) {
+            return Ok(false);
+        }
+        let started = Instant::now();
+        let opened = self.open_session(channel, session).await?;
+        self.trace(format_args!(
+            "channel {:?} open session: {} in {:?}",
+            id,
+            opened,
+            started.elapsed()
+        ));
+        if opened {
+            self.channels.insert(id);
+        }
+        Ok(opened)
+    }
+
+    async fn channel_close(
+        &mut self,
+        channel: ChannelId,
+        _session: &mut ru_server::Session,
+    ) -> Result<(), Self::Error> {
+        self.trace(format_args!("channel {:?} close", channel));
+        self.channels.remove(&channel);
+        self.execs.remove(&channel);
+        self.direct_tcpip.remove(&channel);
+        if let Application::ConnectTarget(ref mut app) = self.app {
+            app.channel_close(channel).await?;
+        }
+        Ok(())
+    }
+
+    async fn authentication_banner(&mut self) -> Result<Option<String>, Self::Error> {
+        let mut banner = self.backend.banner().map(motd::crlf);
+        if !self.backend.database_available() {
+            let unavailable =
+                "rustion: service temporarily unavailable, please try again later\r\n";
+            banner.get_or_insert_default().push_str(unavailable);
+        }
+        Ok(banner)
+    }
+
+    async fn auth_password(
+        &mut self,
+        login_name: &str,
+        password: &str,
+    ) -> Result<ru_server::Auth, Self::Error> {
+        let auth = self.password_auth(login_name, password).await?;
+        self.trace(format_args!(
+            "auth password for '{}': {}",
+            login_name,
+            auth_outcome(&auth)
+        ));
+        Ok(auth)
+    }
+
+    async fn auth_publickey(
+        &mut self,
+        login_name: &str,
+        public_key: &PublicKey,
+    ) -> Result<ru_server::Auth, Self::Error> {
+        let auth = self.publickey_auth(login_name, public_key).await?;
+        self.trace(format_args!(
+            "auth publickey {} for '{}': {}",
+            public_key.algorithm(),
+            login_name,
+            auth_outcome(&auth)
+        ));
+        Ok(auth)
+    }
+
+    async fn auth_openssh_certificate(
+        &mut self,
+        login_name: &str,
+        certificate: &Certificate,
+    ) -> Result<ru_server::Auth, Self::Error> {
+        let auth = self.certificate_auth(login_name, certificate).await?;
+        self.trace(format_args!(
+            "auth certificate '{}' for '{}': {}",
+            certificate.key_id(),
+            login_name,
+            auth_outcome(&auth)
+        ));
+        Ok(auth)
+    }
+
+    async fn auth_keyboard_interactive<'a>(
+        &'a mut self,
+        login_name: &str,
+        submethods: &str,
+        response: Option<ru_server::Response<'a>>,
+    ) -> Result<ru_server::Auth, Self::Error> {
+        let answered = response.is_some();
+        let auth = self.keyboard_interactive_auth(login_name, response).await?;
+        self.trace(format_args!(
+            "auth keyboard-interactive{} for '{}' (submethods '{}'): {}",
+            if answered { " response" } else { "" },
+            login_name,
+            submethods,
+            auth_outcome(&auth)
+        ));
+        Ok(auth)
+    }
+
+    async fn channel_eof(
+        &mut self,
+        channel: ChannelId,
+        session: &mut ru_server::Session,
+    ) -> Result<(), Self::Error> {
+        self.trace(format_args!("channel {:?} eof", channel));
+        match self.app {
+            Application::ConnectTarget(ref mut app) => app.channel_eof(channel, session).await,
+            _ => {
+                warn!("[{}] Unsupported eof request", self.id);
+                session.channel_failure(channel)?;
+                session.close(channel)?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn data(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut ru_server::Session,
+    ) -> Result<(), Self::Error> {
+        self.trace(format_args!(
+            "channel {:?} data {} bytes",
+            channel,
+            data.len()
+        ));
+        match self.app {
+            Application::ConnectTarget(ref mut app) => {
+                app.data(self.backend.clone(), channel, data, session).await
+            }
+            Application::ChangePassword(ref mut app) => app.data(channel, data, session).await,
+            Application::TargetSelector(ref mut app) => app.data(channel, data, session).await,
+            Application::Admin(ref mut app) => app.data(channel, data, session).await,
+            Application::Player(ref mut app) => app.data(channel, data, session).await,
+            Application::None => Ok(()),
+        }
+    }
+
+    /// The client's window size has changed.
+    async fn window_change_request(
+        &mut self,
+        channel: ChannelId,
+        col_width: u32,
+        row_height: u32,
+        pix_width: u32,
+        pix_height: u32,
+        session: &mut ru_server::Session,
+    ) -> Result<(), Self::Error> {
+        let (col_width, row_height) = self.term_size(col_width, row_height);
+        self.trace(format_args!(
+            "channel {:?} window change {}x{}",
+            channel, col_width, row_height
+        ));
+        self.window_size = Some((col_width, row_height, pix_width, pix_height));
+        match self.app {
+            Application::ConnectTarget(ref mut app) => {
+                app.window_change_request(
+                    self.backend.clone(),
+                    channel,
+                    col_width,
+                    row_height,
+                    pix_width,
+                    pix_height,
+                    session,
+                )
+                .await
+            }
+            Application::ChangePassword(ref mut app) => {
+                app.window_change_request(
+                    channel, col_width, row_height, pix_width, pix_height, session,
+                )
+                .await
+            }
+            Application::TargetSelector(ref mut app) => {
+                app.window_change_request(
+                    channel, col_width, row_height, pix_width, pix_height, session,
+                )
+                .await
+            }
+            Application::Admin(ref mut app) => {
+                app.window_change_request(
+                    channel, col_width, row_height, pix_width, pix_height, session,
+                )
+                .await
+            }
+            Application::Player(ref mut app) => {
+                app.window_change_request(
+                    channel, col_width, row_height, pix_width, pix_height, session,
+                )
+                .await
+            }
+            Application::None => Ok(()),
+        }
+    }
+
+    async fn exec_request(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut ru_server::Session,
+    ) -> Result<(), Self::Error> {
+        if !self.slot_free(channel, "exec", self.execs.len(), self.max_execs) {
+            session.channel_failure(channel)?;
+            session.close(channel)?;
+            return Ok(());
+        }
+        // Counted until the channel closes, refused execs close it at once
+        self.execs.insert(channel);
+        let started = Instant::now();
+        let res = self.start_exec(channel, data, session).await;
+        // Masked like the audit log, execs left out of it show their size only
+        let entry = self
+            .backend
+            .exec_log()
+            .entry(&String::from_utf8_lossy(data));
+        let command = match entry {
+            Some(masked) => format!("{:?}", masked),
+            None => format!("of {} bytes", data.len()),
+        };
+        self.trace(format_args!(
+            "channel {:?} exec {}: {} in {:?}",
+            channel,
+            command,
+            if res.is_ok() { "ok" } else { "error" },
+            started.elapsed()
+        ));
+        res
+    }
+
+    async fn subsystem_request(
+        &mut self,
+        channel: ChannelId,
+        name: &str,
+        session: &mut ru_server::Session,
+    ) -> Result<(), Self::Error> {
+        let started = Instant::now();
+        let res = self.start_subsystem(channel, name, session).await;
+        self.trace(format_args!(
+            "channel {:?} subsystem {}: {} in {:?}",
+            channel,
+            name,
+            if res.is_ok() { "ok" } else { "error" },
+            started.elapsed()
+        ));
+        res
+    }
+
+    async fn channel_open_direct_tcpip(
+        &mut self,
+        channel: Channel<ru_server::Msg>,
+        host_to_connect: &str,
+        port_to_connect: u32,
+        originator_address: &str,
+        originator_port: u32,
+        session: &mut ru_server::Session,
+    ) -> Result<bool, Self::Error> {
+        let id = channel.id();
+        if !self.slot_free(id, "channel", self.channels.len(), self.max_channels)
+            || !self.slot_free(
+                id,
+                "direct-tcpip",
+                self.direct_tcpip.len(),
+                self.max_direct_tcpip,
+            )
+        {
+            return Ok(false);
+        }
+        let started = Instant::now();
+        let opened = self
+            .open_direct_tcpip(
+                channel,
+                host_to_connect,
+                port_to_connect,
+                originator_address,
+                originator_port,
+                session,
+            )
+            .await?;
+        self.trace(format_args!(
+            "channel {:?} open direct-tcpip {}:{} from {}:{}: {} in {:?}",
+            id,
+            host_to_connect,
+            port_to_connect,
+            originator_address,
+            originator_port,
+            opened,
+            started.elapsed()
+        ));
+        if opened {
+            self.channels.insert(id);
+            self.direct_tcpip.insert(id);
+        }
+        Ok(opened)
+    }
+
+    async fn env_request(
+        &mut self,
+        channel: ChannelId,
+        variable_name: &str,
+        variable_value: &str,
+        session: &mut ru_server::Session,
+    ) -> Result<(), Self::Error> {
+        self.trace(format_args!("channel {:?} env {}", channel, variable_name));
+        match self.app {
+            Application::ConnectTarget(ref mut app) => {
+                if app.env_request(self.backend.clone(), channel, variable_name, variable_value) {
+                    session.channel_success(channel)?;
+                } else {
+                    session.channel_failure(channel)?;
+                }
+            }
+            _ => session.channel_failure(channel)?,
+        }
+        Ok(())
+    }
+
+    async fn agent_request(
+        &mut self,
+        channel: ChannelId,
+        session: &mut ru_server::Session,
+    ) -> Result<bool, Self::Error> {
+        let started = Instant::now();
+        let res = self.start_agent_forward(channel, session).await?;
+        self.trace(format_args!(
+            "channel {:?} auth-agent-req: {} in {:?}",
+            channel,
+            res,
+            started.elapsed()
+        ));
+        Ok(res)
+    }
+
+    async fn tcpip_forward(
+        &mut self,
+        address: &str,
+        port: &mut u32,
+        session: &mut ru_server::Session,
+    ) -> Result<bool, Self::Error> {
+        let started = Instant::now();
+        let requested = *port;
+        let res = self.start_remote_forward(address, port, session).await?;
+        self.trace(format_args!(
+            "tcpip-forward {}:{} bound {}: {} in {:?}",
+            address,
+            requested,
+            port,
+            res,
+            started.elapsed()
+        ));
+        Ok(res)
+    }
+
+    async fn cancel_tcpip_forward(
+        &mut self,
+        address: &str,
+        port: u32,
+        _session: &mut ru_server::Session,
+    ) -> Result<bool, Self::Error> {
+        let res = match self.app {
+            Application::ConnectTarget(ref mut app) => {
+                app.cancel_tcpip_forward(address, port).await?
+            }
+            _ => false,
+        };
+        self.trace(format_args!(
+            "cancel-tcpip-forward {}:{}: {}",
+            address, port, res
+        ));
+        Ok(res)
+    }
+
+    /// The client requests a pseudo-terminal with the given
+    /// specifications.
+    async fn pty_request(
+        &mut self,
+        channel: ChannelId,
+        term: &str,
+        col_width: u32,
+        row_height: u32,
+        pix_width: u32,
+        pix_height: u32,
+        modes: &[(Pty, u32)],
+        session: &mut ru_server::Session,
+    ) -> Result<(), Self::Error> {
+        let (col_width, row_height) = self.term_size(col_width, row_height);
+        self.trace(format_args!(
+            "channel {:?} pty {} {}x{} with {} modes",
+            channel,
+            term,
+            col_width,
+            row_height,
+            modes.len()
+        ));
+        match self.app {
+            Application::ConnectTarget(ref mut app) => {
+                if !app
+                    .check_pty_permission(self.backend.clone(), self.client_ip.map(|v| v.ip()))
+                    .await?
+                {
+                    app.send_refusal(session, channel)?;
+                    session.channel_failure(channel)?;
+                    session.close(channel)?;
+                    return Ok(());
+                }
+            }
+            Application::Admin(ref mut app) => {
+                app.pty_request(
+                    channel, term, col_width, row_height, pix_width, pix_height, modes, session,
+                )
+                .await?;
+            }
+            Application::ChangePassword(ref mut app) => {
+                app.pty_request(
+                    channel, term, col_width, row_height, pix_width, pix_height, modes, session,
+                )
+                .await?;
+            }
+            Application::Player(ref mut app) => {
+                app.pty_request(
+                    channel, term, col_width, row_height, pix_width, pix_height, modes, session,
+                )
+                .await?;
+            }
+            _ => {}
+        }
+        self.pty_modes = Some(Vec::from(modes));
+        self.pty_term = Some(term.to_string());
+        self.window_size = Some((col_width, row_height, pix_width, pix_height));
+        session.channel_success(channel)?;
+        Ok(())
+    }
+
+    async fn shell_request(
+        &mut self,
+        channel: ChannelId,
+        session: &mut ru_server::Session,
+    ) -> Result<(), Self::Error> {
+        let started = Instant::now();
+        let res = self.start_shell(channel, session).await;
+        self.trace(format_args!(
+            "channel {:?} shell: {} in {:?}",
+            channel,
+            if res.is_ok() { "ok" } else { "error" },
+            started.elapsed()
+        ));
+        res
+    }
+
+    async fn trigger(&mut self) -> Result<Self::Data, Self::Error> {
+        match self.recv_app_msg.recv().await {
+            Some(d) => Ok(d),
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn process(
+        &mut self,
+        data: Self::Data,
+        session: &mut ru_server::Session,
+    ) -> Result<(), Self::Error> {
+        let started = Instant::now();
+        self.app = data.1;
+        match self.app {
+            Application::ConnectTarget(ref mut app) => {
+                if app
+                    .check_pty_permission(self.backend.clone(), self.client_ip.map(|v| v.ip()))
+                    .await?
+                    && app
+                        .check_shell_permission(
+                            self.backend.clone(),
+                            self.client_ip.map(|v| v.ip()),
+                        )
+                        .await?
+                {
+                    app.shell_request(
+                        self.backend.clone(),
+                        data.0,
+                        session,
+                        self.pty_term
+                            .as_ref()
+                            .unwrap_or_else(|| panic!("[{}] pty_term should not be none", self.id)),
+                        self.window_size.unwrap_or_else(|| {
+                            panic!("[{}] window_size should not be none", self.id)
+                        }),
+                        self.pty_modes.as_ref().unwrap_or_else(|| {
+                            panic!("[{}] pty_modes should not be none", self.id)
+                        }),
+                    )
+                    .await?;
+                } else {
+                    app.send_refusal(session, data.0)?;
+                    session.close(data.0)?
+                }
+            }
+            Application::None => {}
+            Application::TargetSelector(_) => {}
+            _ => {}
+        }
+        self.trace(format_args!(
+            "channel {:?} target selected, bridged in {:?}",
+            data.0,
+            started.elapsed()
+        ));
+        Ok(())
+    }
+}
+
+impl<B: 'static + HandlerBackend + Sync> BastionHandler<B> {
+    async fn password_auth(
+        &mut self,
+        login_name: &str,
+        password: &str,
+    ) -> Result<ru_server::Auth, Error> {
+        if self.database_unavailable(login_name) {
+            return Ok(ru_server::Auth::reject());
+        }
+        if self.rate_limited().await {
+            return Ok(keyboard_interactive_reject());
+        }
+        self.init_login(login_name).await?;
+
+        if self.max_auth_attempts(login_name).await {
+            return Ok(keyboard_interactive_reject());
+        }
+
+        match self.user.as_ref() {
+            Some(u) => {
+                self.log = self.handler_log(u.id);
+                if !u.is_active || !self.source_allowed(u) {
+                    return Ok(ru_server::Auth::reject());
+                }
+                // Resuming a dropped session, the factors were checked at
+                // its login
+                let resumed = self.reconnect_token_redeemed(u, password);
+                if resumed
+                    || self
+                        .backend
+                        .auth_provider()
+                        .verify_password(u, password)
+                        .await?
+                {
+                    if !resumed && u.has_totp() {
+                        return Ok(self.require_totp("password"));
+                    }
+                    if !resumed && !self.push_approved("password").await {
+                        return Ok(ru_server::Auth::reject());
+                    }
+                    self.backend
+                        .clear_auth_attempts(
+                            self.client_ip,
+                            self.login_parse
+                                .as_ref()
+                                .unwrap_or_else(|| panic!("[{}] should not be none", self.id))
+                                .0
+                                .clone(),
+                        )
+                        .await;
+                    let by = if resumed {
+                        "reconnection token"
+                    } else {
+                        "password"
+                    };
+                    (self.log)(LOG_TYPE.into(), format!("login successfully by {}", by)).await;
+                    return Ok(ru_server::Auth::Accept);
+                }
+            }
+            None => {
+                debug!("[{}] User {} doesn't exist", self.id, login_name);
+                return Ok(ru_server::Auth::reject());
+            }
+        }
+        Ok(ru_server::Auth::reject())
+    }
+
+    async fn publickey_auth(
+        &mut self,
+        login_name: &str,
+        public_key: &PublicKey,
+    ) -> Result<ru_server::Auth, Error> {
+        if self.database_unavailable(login_name) {
+            return Ok(ru_server::Auth::reject());
+        }
+        if self.rate_limited().await {
+            return Ok(keyboard_interactive_reject());
+        }
+        self.init_login(login_name).await?;
+
+        if self.max_auth_attempts(login_name).await {
+            return Ok(keyboard_interactive_reject());
+        }
+
+        match self.user.as_ref() {
+            Some(u) => {
+                self.log = self.handler_log(u.id);
+                if !u.is_active || !self.source_allowed(u) {
+                    return Ok(ru_server::Auth::reject());
+                }
+                if self
+                    .backend
+                    .auth_provider()
+                    .verify_publickey(u, public_key)
+                    .await?
+                {
+                    if u.has_totp() {
+                        return Ok(self.require_totp("public key"));
+                    }
+                    if !self.push_approved("public key").await {
+                        return Ok(ru_server::Auth::reject());
+                    }
+                    self.backend
+                        .clear_auth_attempts(
+                            self.client_ip,
+                            self.login_parse
+                                .as_ref()
+                                .unwrap_or_else(|| panic!("[{}] should not be none", self.id))
+                                .0
+                                .clone(),
+                        )
+                        .await;
+                    (self.log)(LOG_TYPE.into(), "login successfully by public key".into()).await;
+                    return Ok(ru_server::Auth::Accept);
+                }
+            }
+            None => {
+                debug!("[{}] User {} doesn't exist", self.id, login_name);
+                return Ok(ru_server::Auth::reject());
+            }
+        }
+        Ok(ru_server::Auth::reject())
+    }
+
+    async fn certificate_auth(
+        &mut self,
+        login_name: &str,
+        certificate: &Certificate,
+    ) -> Result<ru_server::Auth, Error> {
+        if self.database_unavailable(login_name) {
+            return Ok(ru_server::Auth::reject());
+        }
+        if self.rate_limited().await {
+            return Ok(keyboard_interactive_reject());
+        }
+        self.init_login(login_name).await?;
+
+        if self.max_auth_attempts(login_name).await {
+            return Ok(keyboard_interactive_reject());
+        }
+
+        match self.user.as_ref() {
+            Some(u) => {
+                self.log = self.handler_log(u.id);
+                if !u.is_active || !self.source_allowed(u) {
+                    return Ok(ru_server::Auth::reject());
+                }
+                if self
+                    .backend
+                    .auth_provider()
+                    .verify_certificate(u, certificate, self.client_ip.map(|v| v.ip()))
+                    .await?
+                {
+                    if u.has_totp() {
+                        return Ok(self.require_totp("certificate"));
+                    }
+                    if !self.push_approved("certificate").await {
+                        return Ok(ru_server::Auth::reject());
+                    }
+                    self.backend
+                        .clear_auth_attempts(
+                            self.client_ip,
+                            self.login_parse
+                                .as_ref()
+                                .unwrap_or_else(|| panic!("[{}] should not be none", self.id))
+                                .0
+                                .clone(),
+                        )
+                        .await;
+                    (self.log)(
+                        LOG_TYPE.into(),
+                        format!(
+                            "login successfully by certificate '{}'",
+                            certificate.key_id()
+                        ),
+                    )
+                    .await;
+                    return Ok(ru_server::Auth::Accept);
+                }
+            }
+            None => {
+                debug!("[{}] User {} doesn't exist", self.id, login_name);
+                return Ok(ru_server::Auth::reject());
+            }
+        }
+        Ok(ru_server::Auth::reject())
+    }
+
+    async fn keyboard_interactive_auth(
+        &mut self,
+        login_name: &str,
+        response: Option<ru_server::Response<'_>>,
+    ) -> Result<ru_server::Auth, Error> {
+        if self.pending_notice {
+            self.pending_notice = false;
+            return Ok(ru_server::Auth::Accept);
+        }
+        if let Some(lockout) = self.lockout.as_ref() {
+            return Ok(match response {
+                None => info_request(lockout.to_string(), None),
+                Some(_) => ru_server::Auth::reject(),
+            });
+        }
+        let Some(first_factor) = self.pending_totp else {
+            return self
+                .keyboard_interactive_password(login_name, response)
+                .await;
+        };
+        let Some(code) = response.and_then(|mut r| r.next()) else {
+            return Ok(info_request(String::new(), Some(TOTP_PROMPT)));
+        };
+        let code = String::from_utf8_lossy(&code).to_string();
+
+        if self.database_unavailable(login_name) {
+            return Ok(ru_server::Auth::reject());
+        }
+        if self.rate_limited().await {
+            return Ok(keyboard_interactive_reject());
+        }
+        if self.max_auth_attempts(login_name).await {
+            return Ok(keyboard_interactive_reject());
+        }
+        let user = match self.user.as_ref() {
+            Some(u) => u,
+            None => return Ok(ru_server::Auth::reject()),
+        };
+        match self.backend.verify_totp(user, &code) {
+            Ok(true) => {}
+            Ok(false) => {
+                debug!(
+                    "[{}] Wrong TOTP code for user '{}({})'",
+                    self.id, user.username, user.id
+                );
+                return Ok(keyboard_interactive_reject());
+            }
+            Err(e) => {
+                warn!(
+                    "[{}] Failed to check TOTP code of user '{}({})': {}",
+                    self.id, user.username, user.id, e
+                );
+                return Ok(ru_server::Auth::reject());
+            }
+        }
+
+        self.pending_totp = None;
+        if !self
+            .push_approved(&format!("{} and TOTP", first_factor))
+            .await
+        {
+            return Ok(ru_server::Auth::reject());
+        }
+        self.backend
+            .clear_auth_attempts(
+                self.client_ip,
+                self.login_parse
+                    .as_ref()
+                    .unwrap_or_else(|| panic!("[{}] should not be none", self.id))
+                    .0
+                    .clone(),
+            )
+            .await;
+        (self.log)(
+            LOG_TYPE.into(),
+            format!("login successfully by {} and TOTP", first_factor),
+        )
+        .await;
+        Ok(self.keyboard_interactive_accept())
+    }
+
+    /// Password as the first factor, for clients and jump hosts that only
+    /// speak keyboard-interactive. A user with TOTP is asked for the code in
+    /// the same exchange.
+    async fn keyboard_interactive_password(
+        &mut self,
+        login_name: &str,
+        response: Option<ru_server::Response<'_>>,
+    ) -> Result<ru_server::Auth, Error> {
+        let Some(password) = response.and_then(|mut r| r.next()) else {
+            return Ok(info_request(String::new(), Some(PASSWORD_PROMPT)));
+        };
+        let password = String::from_utf8_lossy(&password).to_string();
+        let auth = self.password_auth(login_name, &password).await?;
+        if let ru_server::Auth::Accept = auth {
+            return Ok(self.keyboard_interactive_accept());
+        }
+        if self.pending_totp.is_some() {
+            return Ok(info_request(String::new(), Some(TOTP_PROMPT)));
+        }
+        if let Some(lockout) = self.lockout.as_ref() {
+            return Ok(info_request(lockout.to_string(), None));
+        }
+        Ok(auth)
+    }
+
+    /// Accepts, unless the user should first hear about something that waits
+    /// for them after login. The notice goes out as an info request and the
+    /// next answer is accepted.
+    fn keyboard_interactive_accept(&mut self) -> ru_server::Auth {
+        match self.login_notice() {
+            Some(notice) => {
+                self.pending_notice = true;
+                info_request(notice, None)
+            }
+            None => ru_server::Auth::Accept,
+        }
+    }
+
+    fn login_notice(&self) -> Option<String> {
+        let user = self.user.as_ref()?;
+        if user.force_init_pass {
+            return Some("You must set a new password before continuing".into());
+        }
+        if user.password_expired(self.backend.password_max_age(), Timestamp::now()) {
+            return Some("Your password has expired and must be changed".into());
+        }
+        None
+    }
+
+    async fn start_exec(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut ru_server::Session,
+    ) -> Result<(), Error> {
+        match self.app {
+            Application::ConnectTarget(ref mut app) => {
+                let uuids = crate::database::common::InternalUuids::get();
+                // scp runs as an exec but copies files, so it has its own action
+                let action = if super::scp::ScpCommand::parse(data).is_some() {
+                    uuids.act_scp
+                } else {
+                    uuids.act_exec
+                };
+                if app
+                    .check_permission(
+                        self.backend.clone(),
+                        action,
+                        self.client_ip.map(|v| v.ip()),
+                    )
+                    .await?
+                {
+                    return app
+                        .exec_request(
+                            self.backend.clone(),
+                            channel,
+                            data,
+                            session,
+                            self.pty_term.as_ref(),
+                            self.window_size,
+                            self.pty_modes.as_ref(),
+                        )
+                        .await;
+                }
+                app.send_refusal(session, channel)?;
+                session.channel_failure(channel)?;
+                session.close(channel)?;
+                Ok(())
+            }
+            _ => {
+                warn!("[{}] Unsupported exec request", self.id);
+                session.channel_failure(channel)?;
+                session.close(channel)?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn start_subsystem(
+        &mut self,
+        channel: ChannelId,
+        name: &str,
+        session: &mut ru_server::Session,
+    ) -> Result<(), Error> {
+        let action = if name == SFTP_SUBSYSTEM {
+            Some(crate::database::common::InternalUuids::get().act_sftp)
+        } else {
+            self.backend.subsystem_action(name)
+        };
+        match (&mut self.app, action) {
+            (Application::ConnectTarget(app), Some(action)) => {
+                if app
+                    .check_permission(self.backend.clone(), action, self.client_ip.map(|v| v.ip()))
+                    .await?
+                {
+                    return app
+                        .subsystem_request(self.backend.clone(), channel, name, session)
+                        .await;
+                }
+                app.send_refusal(session, channel)?;
+                session.channel_failure(channel)?;
+                session.close(channel)?;
+                Ok(())
+            }
+            _ => {
+                warn!("[{}] Unsupported subsystem request '{}'", self.id, name);
+                session.channel_failure(channel)?;
+                session.close(channel)?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn start_shell(
+        &mut self,
+        channel: ChannelId,
+        session: &mut ru_server::Session,
+    ) -> Result<(), Error> {
+        if self.pty_term.is_none() || self.pty_modes.is_none() || self.window_size.is_none() {
+            warn!(
+                "[{}] user doesn't request pty before request shell",
+                self.id
+            );
+            session.channel_failure(channel)?;
+            session.close(channel)?;
+            return Ok(());
+        }
+
+        match self.app {
+            Application::TargetSelector(ref mut app) => {
+                app.shell_request(
+                    self.backend.clone(),
+                    channel,
+                    session,
+                    self.send_app_msg.clone(),
+                    self.window_size
+                        .unwrap_or_else(|| panic!("[{}] window_size should not be none", self.id)),
+                )
+                .await
+            }
+            Application::ConnectTarget(ref mut app) => {
+                if app
+                    .check_shell_permission(self.backend.clone(), self.client_ip.map(|v| v.ip()))
+                    .await?
+                {
+                    return app
+                        .shell_request(
+                            self.backend.clone(),
+                            channel,
+                            session,
+                            self.pty_term.as_ref().unwrap_or_else(|| {
+                                panic!("[{}] pty_term should not be none", self.id)
+                            }),
+                            self.window_size.unwrap_or_else(|| {
+                                panic!("[{}] window_size should not be none", self.id)
+                            }),
+                            self.pty_modes.as_ref().unwrap_or_else(|| {
+                                panic!("[{}] pty_modes should not be none", self.id)
+                            }),
+                        )
+                        .await;
+                }
+                app.send_refusal(session, channel)?;
+                session.channel_failure(channel)?;
+                session.close(channel)?;
+                Ok(())
+            }
+            Application::ChangePassword(ref mut app) => {
+                app.shell_request(self.backend.clone(), channel, session)
+                    .await
+            }
+            Application::Admin(ref mut app) => {
+                app.shell_request(self.backend.clone(), channel, session)
+                    .await
+            }
+            Application::Player(ref mut app) => {
+                app.shell_request(self.backend.clone(), channel, session)
+                    .await
+            }
+            Application::None => Ok(()),
+        }
+    }
+
+    /// Refuses a `kind` of request on the channel once the connection holds
+    /// `max` of them, as set by `limits`
+    fn slot_free(&self, channel: ChannelId, kind: &str, open: usize, max: usize) -> bool {
+        if open < max {
+            return true;
+        }
+        warn!(
+            "[{}] Refused {} on channel {:?}, connection already has {} open",
+            self.id, kind, channel, open
+        );
+        false
+    }
+
+    async fn open_session(
         &mut self,
         channel: Channel<ru_server::Msg>,
         session: &mut ru_server::Session,
-    ) -> Result<bool, Self::Error> {
+    ) -> Result<bool, Error> {
         match self.app {
             Application::None => {
                 if !self.init_session().await? {
@@ -61,10 +1132,12 @@ impl<B: 'static + HandlerBackend + Send + Sync> ru_server::Handler for BastionHa
                     return Ok(false);
                 };
 
-                if user.force_init_pass {
+                let password_expired =
+                    user.password_expired(self.backend.password_max_age(), Timestamp::now());
+                if user.force_init_pass || password_expired {
                     debug!(
-                        "[{}] User '{}({})' requires password change",
-                        self.id, user.username, user.id
+                        "[{}] User '{}({})' requires password change, expired: {}",
+                        self.id, user.username, user.id, password_expired
                     );
                     let app = Box::new(app::ChangePassword::new(
                         self.id,
@@ -80,11 +1153,11 @@ impl<B: 'static + HandlerBackend + Send + Sync> ru_server::Handler for BastionHa
                             "[{}] Starting target selector for user '{}({})'",
                             self.id, user.username, user.id
                         );
-                        let mut app = Box::new(app::TargetSelector::new(
-                            self.id,
-                            self.user.take(),
-                            self.log.clone(),
-                        ));
+                        let mut app = Box::new(
+                            app::TargetSelector::new(self.id, self.user.take())
+                                .with_ticket(login_parse.ticket().cloned())
+                                .with_client_ip(self.client_ip.map(|v| v.ip())),
+                        );
                         let res = app
                             .channel_open_session(self.backend.clone(), channel, session)
                             .await?;
@@ -104,6 +1177,18 @@ impl<B: 'static + HandlerBackend + Send + Sync> ru_server::Handler for BastionHa
                         self.app = Application::ChangePassword(app);
                         Ok(true)
                     }
+                    LoginMode::Totp => {
+                        debug!(
+                            "[{}] Starting TOTP enrollment for user '{}({})'",
+                            self.id, user.username, user.id
+                        );
+                        let app = Box::new(
+                            app::ChangePassword::new(self.id, self.user.take(), self.log.clone())
+                                .with_totp(true),
+                        );
+                        self.app = Application::ChangePassword(app);
+                        Ok(true)
+                    }
                     LoginMode::Player => {
                         debug!(
                             "[{}] Starting player session for user '{}({})'",
@@ -148,11 +1233,10 @@ impl<B: 'static + HandlerBackend + Send + Sync> ru_server::Handler for BastionHa
                             "[{}] Direct connection to '{}@{}' for user '{}({})'",
                             self.id, target_user, target, user.username, user.id
                         );
-                        let mut app = Box::new(app::ConnectTarget::new(
-                            self.id,
-                            self.user.take(),
-                            self.log.clone(),
-                        ));
+                        let mut app = Box::new(
+                            app::ConnectTarget::new(self.id, self.user.take())
+                                .with_ticket(login_parse.ticket().cloned()),
+                        );
                         let res = app
                             .init_target(self.backend.clone(), &target_user, &target)
                             .await?;
@@ -160,11 +1244,11 @@ impl<B: 'static + HandlerBackend + Send + Sync> ru_server::Handler for BastionHa
                         Ok(res)
                     }
                     LoginMode::Target(name) => {
-                        let mut app = Box::new(app::TargetSelector::new(
-                            self.id,
-                            self.user.take(),
-                            self.log.clone(),
-                        ));
+                        let mut app = Box::new(
+                            app::TargetSelector::new(self.id, self.user.take())
+                                .with_ticket(login_parse.ticket().cloned())
+                                .with_client_ip(self.client_ip.map(|v| v.ip())),
+                        );
                         let res = app
                             .channel_open_with_target_name(
                                 self.backend.clone(),
@@ -185,206 +1269,8 @@ impl<B: 'static + HandlerBackend + Send + Sync> ru_server::Handler for BastionHa
         }
     }
 
-    async fn auth_password(
-        &mut self,
-        login_name: &str,
-        password: &str,
-    ) -> Result<ru_server::Auth, Self::Error> {
-        self.init_login(login_name).await?;
-
-        if self.max_auth_attempts(login_name).await {
-            return Ok(ru_server::Auth::reject());
-        }
-
-        match self.user.as_ref() {
-            Some(u) => {
-                self.log = self.handler_log(u.id);
-                if !u.is_active {
-                    return Ok(ru_server::Auth::reject());
-                }
-                if u.verify_password(password) {
-                    self.backend
-                        .clear_auth_attempts(
-                            self.client_ip,
-                            self.login_parse
-                                .as_ref()
-                                .unwrap_or_else(|| panic!("[{}] should not be none", self.id))
-                                .0
-                                .clone(),
-                        )
-                        .await;
-                    (self.log)(LOG_TYPE.into(), "login successfully by password".into()).await;
-                    return Ok(ru_server::Auth::Accept);
-                }
-            }
-            None => {
-                debug!("[{}] User {} doesn't exist", self.id, login_name);
-                return Ok(ru_server::Auth::reject());
-            }
-        }
-        Ok(ru_server::Auth::reject())
-    }
-
-    async fn auth_publickey(
-        &mut self,
-        login_name: &str,
-        public_key: &PublicKey,
-    ) -> Result<ru_server::Auth, Self::Error> {
-        self.init_login(login_name).await?;
-
-        if self.max_auth_attempts(login_name).await {
-            return Ok(ru_server::Auth::reject());
-        }
-
-        match self.user.as_ref() {
-            Some(u) => {
-                self.log = self.handler_log(u.id);
-                if !u.is_active {
-                    return Ok(ru_server::Auth::reject());
-                }
-                if u.verify_authorized_keys(public_key) {
-                    self.backend
-                        .clear_auth_attempts(
-                            self.client_ip,
-                            self.login_parse
-                                .as_ref()
-                                .unwrap_or_else(|| panic!("[{}] should not be none", self.id))
-                                .0
-                                .clone(),
-                        )
-                        .await;
-                    (self.log)(LOG_TYPE.into(), "login successfully by public key".into()).await;
-                    return Ok(ru_server::Auth::Accept);
-                }
-            }
-            None => {
-                debug!("[{}] User {} doesn't exist", self.id, login_name);
-                return Ok(ru_server::Auth::reject());
-            }
-        }
-        Ok(ru_server::Auth::reject())
-    }
-
-    async fn channel_eof(
-        &mut self,
-        channel: ChannelId,
-        session: &mut ru_server::Session,
-    ) -> Result<(), Self::Error> {
-        match self.app {
-            Application::ConnectTarget(ref mut app) => app.channel_eof(channel, session).await,
-            _ => {
-                warn!("[{}] Unsupported eof request", self.id);
-                session.channel_failure(channel)?;
-                session.close(channel)?;
-                Ok(())
-            }
-        }
-    }
-
-    async fn data(
-        &mut self,
-        channel: ChannelId,
-        data: &[u8],
-        session: &mut ru_server::Session,
-    ) -> Result<(), Self::Error> {
-        match self.app {
-            Application::ConnectTarget(ref mut app) => app.data(channel, data, session).await,
-            Application::ChangePassword(ref mut app) => app.data(channel, data, session).await,
-            Application::TargetSelector(ref mut app) => app.data(channel, data, session).await,
-            Application::Admin(ref mut app) => app.data(channel, data, session).await,
-            Application::Player(ref mut app) => app.data(channel, data, session).await,
-            Application::None => Ok(()),
-        }
-    }
-
-    /// The client's window size has changed.
-    async fn window_change_request(
-        &mut self,
-        channel: ChannelId,
-        col_width: u32,
-        row_height: u32,
-        pix_width: u32,
-        pix_height: u32,
-        session: &mut ru_server::Session,
-    ) -> Result<(), Self::Error> {
-        self.window_size = Some((col_width, row_height, pix_width, pix_height));
-        match self.app {
-            Application::ConnectTarget(ref mut app) => {
-                app.window_change_request(
-                    channel, col_width, row_height, pix_width, pix_height, session,
-                )
-                .await
-            }
-            Application::ChangePassword(ref mut app) => {
-                app.window_change_request(
-                    channel, col_width, row_height, pix_width, pix_height, session,
-                )
-                .await
-            }
-            Application::TargetSelector(ref mut app) => {
-                app.window_change_request(
-                    channel, col_width, row_height, pix_width, pix_height, session,
-                )
-                .await
-            }
-            Application::Admin(ref mut app) => {
-                app.window_change_request(
-                    channel, col_width, row_height, pix_width, pix_height, session,
-                )
-                .await
-            }
-            Application::Player(ref mut app) => {
-                app.window_change_request(
-                    channel, col_width, row_height, pix_width, pix_height, session,
-                )
-                .await
-            }
-            Application::None => Ok(()),
-        }
-    }
-
-    async fn exec_request(
-        &mut self,
-        channel: ChannelId,
-        data: &[u8],
-        session: &mut ru_server::Session,
-    ) -> Result<(), Self::Error> {
-        match self.app {
-            Application::ConnectTarget(ref mut app) => {
-                if app
-                    .check_permission(
-                        self.backend.clone(),
-                        crate::database::common::InternalUuids::get().act_exec,
-                        self.client_ip.map(|v| v.ip()),
-                    )
-                    .await?
-                {
-                    return app
-                        .exec_request(
-                            self.backend.clone(),
-                            channel,
-                            data,
-                            session,
-                            self.pty_term.as_ref(),
-                            self.window_size,
-                            self.pty_modes.as_ref(),
-                        )
-                        .await;
-                }
-                session.channel_failure(channel)?;
-                session.close(channel)?;
-                Ok(())
-            }
-            _ => {
-                warn!("[{}] Unsupported exec request", self.id);
-                session.channel_failure(channel)?;
-                session.close(channel)?;
-                Ok(())
-            }
-        }
-    }
-
-    async fn channel_open_direct_tcpip(
+    #[allow(clippy::too_many_arguments)]
+    async fn open_direct_tcpip(
         &mut self,
         channel: Channel<ru_server::Msg>,
         host_to_connect: &str,
@@ -392,7 +1278,7 @@ impl<B: 'static + HandlerBackend + Send + Sync> ru_server::Handler for BastionHa
         originator_address: &str,
         originator_port: u32,
         session: &mut ru_server::Session,
-    ) -> Result<bool, Self::Error> {
+    ) -> Result<bool, Error> {
         match self.app {
             Application::ConnectTarget(ref mut app) => {
                 if app
@@ -417,65 +1303,41 @@ impl<B: 'static + HandlerBackend + Send + Sync> ru_server::Handler for BastionHa
                 }
                 Ok(false)
             }
+            Application::None
+                if matches!(
+                    self.login_parse.as_ref().map(|l| l.parse_mode()),
+                    Some(LoginMode::TargetSelector)
+                ) =>
+            {
+                self.open_jump(channel, host_to_connect).await
+            }
             Application::None => {
-                if !self.init_session().await? {
-                    return Ok(false);
-                }
-
-                let user = if let Some(u) = self.user.as_ref() {
-                    u
-                } else {
-                    return Ok(false);
-                };
-
-                if user.force_init_pass {
-                    return Ok(false);
-                }
-
-                let login_parse = if let Some(l) = self.login_parse.as_ref() {
-                    l
-                } else {
+                let Some(mut app) = self.connect_target_app().await? else {
                     return Ok(false);
                 };
-                match login_parse.parse_mode() {
-                    LoginMode::TargetWithUser(user, target) => {
-                        let mut app = Box::new(app::ConnectTarget::new(
-                            self.id,
-                            self.user.take(),
-                            self.log.clone(),
-                        ));
-                        if !app
-                            .init_target(self.backend.clone(), &user, &target)
-                            .await?
-                        {
-                            return Ok(false);
-                        }
-                        if app
-                            .check_permission(
-                                self.backend.clone(),
-                                crate::database::common::InternalUuids::get().act_direct_tcpip,
-                                self.client_ip.map(|v| v.ip()),
-                            )
-                            .await?
-                            && app
-                                .channel_open_direct_tcpip(
-                                    self.backend.clone(),
-                                    channel,
-                                    host_to_connect,
-                                    port_to_connect,
-                                    originator_address,
-                                    originator_port,
-                                    session,
-                                )
-                                .await?
-                        {
-                            self.app = Application::ConnectTarget(app);
-                            return Ok(true);
-                        }
-                        Ok(false)
-                    }
-                    _ => Ok(false),
+                if app
+                    .check_permission(
+                        self.backend.clone(),
+                        crate::database::common::InternalUuids::get().act_direct_tcpip,
+                        self.client_ip.map(|v| v.ip()),
+                    )
+                    .await?
+                    && app
+                        .channel_open_direct_tcpip(
+                            self.backend.clone(),
+                            channel,
+                            host_to_connect,
+                            port_to_connect,
+                            originator_address,
+                            originator_port,
+                            session,
+                        )
+                        .await?
+                {
+                    self.app = Application::ConnectTarget(app);
+                    return Ok(true);
                 }
+                Ok(false)
             }
             _ => {
                 warn!("[{}] Unsupported open_direct_tcpip request", self.id);
@@ -484,195 +1346,262 @@ impl<B: 'static + HandlerBackend + Send + Sync> ru_server::Handler for BastionHa
         }
     }
 
-    /// The client requests a pseudo-terminal with the given
-    /// specifications.
-    async fn pty_request(
+    /// `ssh -J user@bastion account@target` logs in without a target and
+    /// asks for a direct-tcpip to the target's name. Any binding of that
+    /// target granting direct-tcpip to the user lets it through.
+    async fn open_jump(
         &mut self,
-        channel: ChannelId,
-        term: &str,
-        col_width: u32,
-        row_height: u32,
-        pix_width: u32,
-        pix_height: u32,
-        modes: &[(Pty, u32)],
-        session: &mut ru_server::Session,
-    ) -> Result<(), Self::Error> {
-        match self.app {
-            Application::ConnectTarget(ref mut app) => {
-                if !app
+        channel: Channel<ru_server::Msg>,
+        target_name: &str,
+    ) -> Result<bool, Error> {
+        if !self.init_session().await? {
+            return Ok(false);
+        }
+        let Some(user) = self.user.clone() else {
+            return Ok(false);
+        };
+        if user.force_init_pass
+            || user.password_expired(self.backend.password_max_age(), Timestamp::now())
+        {
+            return Ok(false);
+        }
+        let ticket = self.login_parse.as_ref().and_then(|l| l.ticket().cloned());
+        let targets = self.backend.list_targets_for_user(&user.id, true).await?;
+        for binding in targets.iter().filter(|t| t.target_name == target_name) {
+            let mut app =
+                app::ConnectTarget::new(self.id, Some(user.clone())).with_ticket(ticket.clone());
+            if app
+                .init_target(self.backend.clone(), &binding.secret_user, target_name)
+                .await?
+                && app
                     .check_permission(
                         self.backend.clone(),
-                        crate::database::common::InternalUuids::get().act_pty,
+                        crate::database::common::InternalUuids::get().act_direct_tcpip,
                         self.client_ip.map(|v| v.ip()),
                     )
                     .await?
-                {
-                    session.channel_failure(channel)?;
-                    session.close(channel)?;
-                    return Ok(());
-                }
-            }
-            Application::Admin(ref mut app) => {
-                app.pty_request(
-                    channel, term, col_width, row_height, pix_width, pix_height, modes, session,
-                )
-                .await?;
-            }
-            Application::ChangePassword(ref mut app) => {
-                app.pty_request(
-                    channel, term, col_width, row_height, pix_width, pix_height, modes, session,
-                )
-                .await?;
-            }
-            Application::Player(ref mut app) => {
-                app.pty_request(
-                    channel, term, col_width, row_height, pix_width, pix_height, modes, session,
-                )
-                .await?;
+            {
+                return app.open_jump(self.backend.clone(), channel).await;
             }
-            _ => {}
         }
-        self.pty_modes = Some(Vec::from(modes));
-        self.pty_term = Some(term.to_string());
-        self.window_size = Some((col_width, row_height, pix_width, pix_height));
-        session.channel_success(channel)?;
-        Ok(())
+        debug!(
+            "[{}] Refused jump to '{}' for user '{}({})'",
+            self.id, target_name, user.username, user.id
+        );
+        Ok(false)
     }
 
-    async fn shell_request(
+    /// `ssh -A`, only with a policy granting the agent forwarding action
+    async fn start_agent_forward(
         &mut self,
         channel: ChannelId,
         session: &mut ru_server::Session,
-    ) -> Result<(), Self::Error> {
-        if self.pty_term.is_none() || self.pty_modes.is_none() || self.window_size.is_none() {
-            warn!(
-                "[{}] user doesn't request pty before request shell",
-                self.id
-            );
-            session.channel_failure(channel)?;
-            session.close(channel)?;
-            return Ok(());
-        }
-
+    ) -> Result<bool, Error> {
         match self.app {
-            Application::TargetSelector(ref mut app) => {
-                app.shell_request(
-                    self.backend.clone(),
-                    channel,
-                    session,
-                    self.send_app_msg.clone(),
-                    self.window_size
-                        .unwrap_or_else(|| panic!("[{}] window_size should not be none", self.id)),
-                )
-                .await
-            }
             Application::ConnectTarget(ref mut app) => {
                 if app
                     .check_permission(
                         self.backend.clone(),
-                        crate::database::common::InternalUuids::get().act_shell,
+                        crate::database::common::InternalUuids::get().act_agent_forward,
                         self.client_ip.map(|v| v.ip()),
                     )
                     .await?
                 {
                     return app
-                        .shell_request(
-                            self.backend.clone(),
-                            channel,
-                            session,
-                            self.pty_term.as_ref().unwrap_or_else(|| {
-                                panic!("[{}] pty_term should not be none", self.id)
-                            }),
-                            self.window_size.unwrap_or_else(|| {
-                                panic!("[{}] window_size should not be none", self.id)
-                            }),
-                            self.pty_modes.as_ref().unwrap_or_else(|| {
-                                panic!("[{}] pty_modes should not be none", self.id)
-                            }),
-                        )
+                        .agent_request(self.backend.clone(), channel, session)
                         .await;
                 }
-                session.channel_failure(channel)?;
-                session.close(channel)?;
-                Ok(())
-            }
-            Application::ChangePassword(ref mut app) => {
-                app.shell_request(self.backend.clone(), channel, session)
-                    .await
-            }
-            Application::Admin(ref mut app) => {
-                app.shell_request(self.backend.clone(), channel, session)
-                    .await
+                Ok(false)
             }
-            Application::Player(ref mut app) => {
-                app.shell_request(self.backend.clone(), channel, session)
-                    .await
+            _ => {
+                warn!("[{}] Unsupported agent forwarding request", self.id);
+                Ok(false)
             }
-            Application::None => Ok(()),
-        }
-    }
-
-    async fn trigger(&mut self) -> Result<Self::Data, Self::Error> {
-        match self.recv_app_msg.recv().await {
-            Some(d) => Ok(d),
-            None => std::future::pending().await,
         }
     }
 
-    async fn process(
+    /// `ssh -R`, the target listens and its connections come back to the
+    /// client as `forwarded-tcpip` channels
+    async fn start_remote_forward(
         &mut self,
-        data: Self::Data,
+        address: &str,
+        port: &mut u32,
         session: &mut ru_server::Session,
-    ) -> Result<(), Self::Error> {
-        self.app = data.1;
+    ) -> Result<bool, Error> {
+        let act_remote_forward = crate::database::common::InternalUuids::get().act_remote_forward;
         match self.app {
             Application::ConnectTarget(ref mut app) => {
                 if app
                     .check_permission(
                         self.backend.clone(),
-                        crate::database::common::InternalUuids::get().act_pty,
+                        act_remote_forward,
                         self.client_ip.map(|v| v.ip()),
                     )
                     .await?
-                    && app
-                        .check_permission(
-                            self.backend.clone(),
-                            crate::database::common::InternalUuids::get().act_shell,
-                            self.client_ip.map(|v| v.ip()),
-                        )
-                        .await?
                 {
-                    app.shell_request(
+                    return app
+                        .tcpip_forward(self.backend.clone(), address, port, session)
+                        .await;
+                }
+                Ok(false)
+            }
+            // `ssh -N -R` asks before opening any channel
+            Application::None => {
+                let Some(mut app) = self.connect_target_app().await? else {
+                    return Ok(false);
+                };
+                if app
+                    .check_permission(
                         self.backend.clone(),
-                        data.0,
-                        session,
-                        self.pty_term
-                            .as_ref()
-                            .unwrap_or_else(|| panic!("[{}] pty_term should not be none", self.id)),
-                        self.window_size.unwrap_or_else(|| {
-                            panic!("[{}] window_size should not be none", self.id)
-                        }),
-                        self.pty_modes.as_ref().unwrap_or_else(|| {
-                            panic!("[{}] pty_modes should not be none", self.id)
-                        }),
+                        act_remote_forward,
+                        self.client_ip.map(|v| v.ip()),
                     )
-                    .await?;
-                } else {
-                    session.close(data.0)?
+                    .await?
+                    && app
+                        .tcpip_forward(self.backend.clone(), address, port, session)
+                        .await?
+                {
+                    self.app = Application::ConnectTarget(app);
+                    return Ok(true);
                 }
+                Ok(false)
+            }
+            _ => {
+                warn!("[{}] Unsupported tcpip-forward request", self.id);
+                Ok(false)
             }
-            Application::None => {}
-            Application::TargetSelector(_) => {}
-            _ => {}
         }
-        Ok(())
     }
-}
 
-impl<B: 'static + HandlerBackend + Sync> BastionHandler<B> {
+    /// The target session of a login naming one, for requests that come
+    /// before any session channel
+    async fn connect_target_app(&mut self) -> Result<Option<Box<app::ConnectTarget>>, Error> {
+        if !self.init_session().await? {
+            return Ok(None);
+        }
+
+        let user = if let Some(u) = self.user.as_ref() {
+            u
+        } else {
+            return Ok(None);
+        };
+
+        if user.force_init_pass
+            || user.password_expired(self.backend.password_max_age(), Timestamp::now())
+        {
+            return Ok(None);
+        }
+
+        let login_parse = if let Some(l) = self.login_parse.as_ref() {
+            l
+        } else {
+            return Ok(None);
+        };
+        match login_parse.parse_mode() {
+            LoginMode::TargetWithUser(user, target) => {
+                let mut app = Box::new(
+                    app::ConnectTarget::new(self.id, self.user.take())
+                        .with_ticket(login_parse.ticket().cloned()),
+                );
+                if !app
+                    .init_target(self.backend.clone(), &user, &target)
+                    .await?
+                {
+                    return Ok(None);
+                }
+                Ok(Some(app))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The first factor passed, the client is sent on to keyboard-interactive
+    /// for the code
+    fn require_totp(&mut self, first_factor: &'static str) -> ru_server::Auth {
+        debug!(
+            "[{}] Login by {} accepted, waiting for TOTP code",
+            self.id, first_factor
+        );
+        self.pending_totp = Some(first_factor);
+        ru_server::Auth::Reject {
+            proceed_with_methods: Some(MethodSet::from(&[MethodKind::KeyboardInteractive][..])),
+            partial_success: true,
+        }
+    }
+
+    /// `password` is a reconnection token of `user` for the `account@target`
+    /// of this login
+    fn reconnect_token_redeemed(&self, user: &User, password: &str) -> bool {
+        let (Some(tokens), Some(target)) = (
+            self.backend.reconnect_tokens(),
+            self.login_parse.as_ref().and_then(|l| l.target()),
+        ) else {
+            return false;
+        };
+        tokens.redeem(password, &user.id, &target)
+    }
+
+    /// Last step of a login when `push_mfa` is on, waits for the user to
+    /// approve it on their device. Every decision is written to the logs.
+    async fn push_approved(&mut self, method: &str) -> bool {
+        let (Some(push_mfa), Some(user)) = (self.backend.push_mfa(), self.user.as_ref()) else {
+            return true;
+        };
+        let request = PushRequest {
+            id: Uuid::new_v4(),
+            connection_id: self.id,
+            user_id: user.id,
+            username: &user.username,
+            client_ip: self.client_ip.map(|a| a.ip()),
+            method,
+            timestamp: Timestamp::now().millis(),
+        };
+        let started = Instant::now();
+        let decision = push_mfa.ask(&request).await;
+        if !decision.is_allowed() {
+            info!(
+                "[{}] Push approval of user '{}({})' {}",
+                self.id, user.username, user.id, decision
+            );
+        }
+        self.trace(format_args!(
+            "push approval {} in {:?}",
+            decision,
+            started.elapsed()
+        ));
+        (self.log)(
+            LOG_TYPE.into(),
+            format!("push approval after {} {}", method, decision),
+        )
+        .await;
+        decision.is_allowed()
+    }
+
+    fn database_unavailable(&self, login_name: &str) -> bool {
+        if self.backend.database_available() {
+            return false;
+        }
+        warn!(
+            "[{}] Database unavailable, rejecting login of {}",
+            self.id, login_name
+        );
+        true
+    }
+
+    /// Some clients report 0x0 when they can't tell their size, which would
+    /// leave the target and the recording without a usable geometry
+    fn term_size(&self, col_width: u32, row_height: u32) -> (u32, u32) {
+        let (cols, rows) = self.backend.default_term_size();
+        (
+            if col_width == 0 { cols } else { col_width },
+            if row_height == 0 { rows } else { row_height },
+        )
+    }
+
     pub(super) fn new(
         client_ip: Option<std::net::SocketAddr>,
         max_auth_attempts_per_conn: u32,
+        limits: &LimitsConfig,
         backend: Arc<B>,
     ) -> Self {
         let (send_app_msg, recv_app_msg) = channel(1);
@@ -694,6 +1623,17 @@ impl<B: 'static + HandlerBackend + Sync> BastionHandler<B> {
             log,
             auth_attempts_per_conn: 0,
             max_auth_attempts_per_conn,
+            pending_totp: None,
+            pending_notice: false,
+            lockout: None,
+            channels: HashSet::new(),
+            execs: HashSet::new(),
+            direct_tcpip: HashSet::new(),
+            max_channels: limits.max_channels,
+            max_execs: limits.max_execs,
+            max_direct_tcpip: limits.max_direct_tcpip,
+            trace: None,
+            trace_checked: false,
             send_app_msg,
             recv_app_msg,
             pty_modes: None,
@@ -715,6 +1655,20 @@ impl<B: 'static + HandlerBackend + Sync> BastionHandler<B> {
         })
     }
 
+    /// The user's `allowed_source_cidrs` admit the client address, on top of
+    /// whatever IP rules the policies carry
+    fn source_allowed(&self, user: &User) -> bool {
+        let ip = self.client_ip.map(|v| v.ip());
+        if user.allows_source(ip) {
+            return true;
+        }
+        warn!(
+            "[{}] Login of '{}' refused from {:?}, not in its allowed source CIDRs",
+            self.id, user.username, ip
+        );
+        false
+    }
+
     async fn init_login(&mut self, login_name: &str) -> Result<(), Error> {
         if self.login_parse.is_none() {
             self.login_parse = LoginParse::parse_login_name(login_name);
@@ -723,12 +1677,51 @@ impl<B: 'static + HandlerBackend + Sync> BastionHandler<B> {
         match self.login_parse.as_ref() {
             Some(l) => {
                 let user = l.0.clone();
+                self.start_trace(&user).await;
                 self.get_user(&user).await
             }
             None => Err(Error::Server(ServerError::InvalidLoginName)),
         }
     }
 
+    /// Trace rules are looked up once, on the first login attempt. Nothing
+    /// before it, key exchange included, is traced.
+    async fn start_trace(&mut self, username: &str) {
+        if self.trace_checked {
+            return;
+        }
+        self.trace_checked = true;
+        let Some(until) = self
+            .backend
+            .trace_until(username, self.client_ip.map(|v| v.ip()))
+            .await
+        else {
+            return;
+        };
+        match Trace::create(self.backend.trace_path(), self.id, until) {
+            Ok(trace) => {
+                info!(
+                    "[{}] Tracing connection of '{}' from {:?} until {}",
+                    self.id, username, self.client_ip, until
+                );
+                trace.event(format_args!(
+                    "client {:?} login '{}'",
+                    self.client_ip, username
+                ));
+                self.trace = Some(trace);
+            }
+            Err(e) => warn!("[{}] Failed to start trace: {}", self.id, e),
+        }
+    }
+
+    /// Adds a line to the trace of this connection, if it is traced
+    fn trace(&mut self, event: std::fmt::Arguments) {
+        if self.trace.as_ref().is_some_and(|t| !t.event(event)) {
+            debug!("[{}] Trace ended", self.id);
+            self.trace = None;
+        }
+    }
+
     async fn init_session(&self) -> Result<bool, Error> {
         let user = if let Some(u) = self.user.as_ref() {
             u
@@ -743,7 +1736,8 @@ impl<B: 'static + HandlerBackend + Sync> BastionHandler<B> {
                 user.id,
                 uuids.obj_login,
                 uuids.act_login,
-                ExtendPolicyReq::new(self.client_ip.map(|v| v.ip())),
+                ExtendPolicyReq::new(self.client_ip.map(|v| v.ip()))
+                    .with_ticket(self.login_parse.as_ref().and_then(|l| l.ticket().cloned())),
             )
             .await?
         {
@@ -763,8 +1757,19 @@ impl<B: 'static + HandlerBackend + Sync> BastionHandler<B> {
         Ok(())
     }
 
+    /// Checked before anything else, including the user lookup
+    async fn rate_limited(&mut self) -> bool {
+        match self.backend.rate_limit(self.client_ip).await {
+            Some(wait) => {
+                self.lock_out(Lockout::Throttled(wait)).await;
+                true
+            }
+            None => false,
+        }
+    }
+
     async fn max_auth_attempts(&mut self, login_name: &str) -> bool {
-        if self
+        if let Some(ban) = self
             .backend
             .reject_auth_attempts(
                 self.client_ip,
@@ -776,6 +1781,7 @@ impl<B: 'static + HandlerBackend + Sync> BastionHandler<B> {
             )
             .await
         {
+            self.lock_out(Lockout::Banned(ban)).await;
             return true;
         }
         self.auth_attempts_per_conn += 1;
@@ -785,6 +1791,7 @@ impl<B: 'static + HandlerBackend + Sync> BastionHandler<B> {
                 "[{}] Client {:?} exceeded max authentication attempts ({})",
                 self.id, self.client_ip, self.max_auth_attempts_per_conn
             );
+            self.lock_out(Lockout::Connection).await;
             return true;
         }
 
@@ -795,10 +1802,56 @@ impl<B: 'static + HandlerBackend + Sync> BastionHandler<B> {
 
         false
     }
+
+    async fn lock_out(&mut self, lockout: Lockout) {
+        if self.lockout.is_some() {
+            return;
+        }
+        if let Some(u) = self.user.as_ref() {
+            self.log = self.handler_log(u.id);
+            (self.log)(LOG_TYPE.into(), format!("login refused: {}", lockout)).await;
+        }
+        self.lockout = Some(lockout);
+    }
+}
+
+/// Why logins on a connection are refused, told to the client instead of a
+/// bare authentication failure
+enum Lockout {
+    /// The IP or the user is over its global limit
+    Banned(std::time::Duration),
+    /// The IP is over `rate_limit.attempts_per_minute`
+    Throttled(std::time::Duration),
+    /// The connection used up `max_auth_attempts_per_conn`
+    Connection,
+}
+
+impl std::fmt::Display for Lockout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lockout::Banned(ban) => write!(
+                f,
+                "Too many failed logins, try again in {}",
+                humantime::format_duration(*ban)
+            ),
+            Lockout::Throttled(wait) => write!(
+                f,
+                "Too many login attempts from your address, try again in {}",
+                humantime::format_duration(*wait)
+            ),
+            Lockout::Connection => write!(
+                f,
+                "Too many authentication attempts, reconnect to try again"
+            ),
+        }
+    }
 }
 
 impl<B: HandlerBackend + Send + Clone> Drop for BastionHandler<B> {
     fn drop(&mut self) {
+        if let Some(trace) = self.trace.take() {
+            trace.event(format_args!("disconnected"));
+        }
         let log = self.log.clone();
         tokio::spawn(async move {
             log(LOG_TYPE.into(), "logout".into()).await;
@@ -814,13 +1867,18 @@ impl<B: HandlerBackend + Send + Clone> Drop for BastionHandler<B> {
 ///  - ssh user@target@rustion user to connect to target but doesn't
 ///    specify system user.
 ///  - ssh user@password@rustion used to change user's password.
+///  - ssh user@totp@rustion used to enroll an authenticator app.
 ///  - ssh user@rustion used to enter default mode.
+///
+/// A change/ticket reference may be appended to the last part with
+/// `%`, e.g. ssh user@target%CHG-1234@rustion.
 #[derive(Clone)]
-pub(super) struct LoginParse(String, String, String);
+pub(super) struct LoginParse(String, String, String, Option<String>);
 
 pub enum LoginMode {
     TargetSelector,
     Password,
+    Totp,
     Player,
     Admin,
     Target(String),
@@ -829,28 +1887,46 @@ pub enum LoginMode {
 
 impl LoginParse {
     fn parse_login_name(login: &str) -> Option<LoginParse> {
+        let (login, ticket) = match login.rsplit_once('%') {
+            Some((l, t)) if !t.is_empty() && !t.contains('@') => (l, Some(t.to_string())),
+            Some(_) => return None,
+            None => (login, None),
+        };
         let mut sp: Vec<_> = login.split('@').collect();
         match sp.len() {
             1 => Some(LoginParse(
                 sp.pop().unwrap().into(),
                 String::new(),
                 String::new(),
+                ticket,
             )),
             2 => {
                 let second = sp.pop().unwrap().into();
                 let first = sp.pop().unwrap().into();
-                Some(LoginParse(first, second, String::new()))
+                Some(LoginParse(first, second, String::new(), ticket))
             }
             3 => {
                 let third = sp.pop().unwrap().into();
                 let second = sp.pop().unwrap().into();
                 let first = sp.pop().unwrap().into();
-                Some(LoginParse(first, second, third))
+                Some(LoginParse(first, second, third, ticket))
             }
             _ => None,
         }
     }
 
+    pub fn ticket(&self) -> Option<&String> {
+        self.3.as_ref()
+    }
+
+    /// `account@target` of a direct target login
+    fn target(&self) -> Option<String> {
+        match self.parse_mode() {
+            LoginMode::TargetWithUser(account, target) => Some(format!("{}@{}", account, target)),
+            _ => None,
+        }
+    }
+
     pub fn parse_mode(&self) -> LoginMode {
         if !self.1.is_empty() && !self.2.is_empty() {
             return LoginMode::TargetWithUser(self.1.clone(), self.2.clone());
@@ -858,6 +1934,7 @@ impl LoginParse {
         if !self.1.is_empty() && self.2.is_empty() {
             match self.1.as_str() {
                 "password" => return LoginMode::Password,
+                "totp" => return LoginMode::Totp,
                 "player" => return LoginMode::Player,
                 "admin" => return LoginMode::Admin,
                 _ => return LoginMode::Target(self.1.clone()),
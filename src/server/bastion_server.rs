@@ -1,42 +1,121 @@
+//! `BastionServer`, the `HandlerBackend` of every connection. Besides the
+//! config, database and role manager it owns what connections share:
+//! attempt caches and bans, pooled target handles and their leases, live
+//! sessions for the admin Live tab, reconnection tokens and remote
+//! forwards. `run` accepts connections itself, so bans and PROXY headers
+//! are dealt with before the SSH handshake. Background tasks probe the
+//! database, reload bans and policies, clean expired policies and apply
+//! record retention.
+
 use super::casbin;
 use crate::database::DatabaseRepository;
 use crate::database::Uuid;
 use crate::server::error::ServerError;
-use aes_gcm::aead::{Aead, rand_core::RngCore};
+use crate::server::session_quota::{QuotaUsage, SessionQuota};
 use argon2::{
     Argon2,
     password_hash::{PasswordHasher, SaltString},
 };
-use log::{error, info, trace, warn};
+use ipnetwork::IpNetwork;
+use log::{debug, error, info, trace, warn};
 use moka::future::Cache;
 use moka::ops::compute::{CompResult, Op};
 use petgraph::stable_graph::StableDiGraph;
-use russh::client as ru_client;
-use russh::keys::Algorithm;
 use aes_gcm::aead::OsRng;
-use rand::rng;
-use russh::server::{Config as RusshConfig, Server};
+use russh::client as ru_client;
+use russh::keys::PrivateKey;
+use russh::keys::ssh_key::Certificate;
+use russh::server::{Config as RusshConfig, Server, run_stream};
 
 use super::bastion_handler::BastionHandler;
-use crate::config::Config;
+use super::target_prompts::{PromptLogin, TargetLogin};
+use crate::config::{Config, OpaFallback};
 use crate::database::models;
+use crate::database::models::{HOST_KEY_CONFIRM, HostKeyCheck, PresentedKey};
 use crate::database::service::DatabaseService;
 use crate::error::Error;
-use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use aes_gcm::{Aes256Gcm, KeyInit};
 use base64::{Engine as _, engine::general_purpose};
+use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use tokio::sync::RwLock;
 
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+const HEALTH_CHECK_MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Picks up bans written by other servers sharing the database
+const BAN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+/// How often policies past `expired_policy_grace` are looked for
+const POLICY_CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+const POLICY_CLEANUP_LOG_TYPE: &str = "policy_cleanup";
+const RECORD_RETENTION_LOG_TYPE: &str = "record_retention";
+const HOST_KEY_LOG_TYPE: &str = "host_key";
+
+/// Builds the role graphs from the grouping rules in the database
+pub(crate) async fn load_role_manager(
+    repo: &dyn DatabaseRepository,
+) -> Result<casbin::RoleManage, Error> {
+    let mut g1 = repo.list_casbin_rule_group_by_ptype("g1").await?;
+    // User group memberships live outside casbin_rule
+    g1.extend(repo.list_user_group_rule_groups().await?);
+    let mut g2 = repo.list_casbin_rule_group_by_ptype("g2").await?;
+    // So do the bindings of tagged targets in `tag:key=value` groups
+    g2.extend(repo.list_tag_rule_groups().await?);
+    let g3 = repo.list_casbin_rule_group_by_ptype("g3").await?;
+    casbin::RoleManage::new(&g1, &g2, &g3)
+}
+
+/// What `enforce_policy` decides from for a subject, object and action.
+/// The ip, time and ticket of a request are checked against it every time.
+struct PolicyCandidates {
+    /// `p_deny` rules covering the request
+    denies: Vec<models::CasbinRule>,
+    /// `p` rules covering the request, in their order
+    grants: Vec<models::CasbinRule>,
+    object_active: bool,
+}
+
+type PolicyCache = Cache<(Uuid, Uuid, Uuid), Arc<PolicyCandidates>>;
+
 #[derive(Clone)]
 pub struct BastionServer {
     config: Config,
     secret_key: Aes256Gcm,
+    auth_provider: Arc<super::auth_provider::Chain>,
     database: DatabaseService,
     client_ip_pool: Cache<std::net::IpAddr, u32>,
     client_user_pool: Cache<String, u32>,
+    rate_limiter: super::rate_limit::RateLimiter,
+    /// Bans in force, checked before a connection gets to the handshake
+    bans: Arc<RwLock<Vec<models::BannedIp>>>,
     connection_pool: Option<super::connection_pool::ConnectionPool>,
+    /// Channels bridged over `connection_pool` handles, per user
+    pool_leases: Option<Arc<super::connection_pool::Leases>>,
+    /// Every bridged channel, for the admin Live tab
+    live_sessions: Arc<super::live_sessions::LiveSessions>,
+    reconnect_tokens: Option<Arc<super::reconnect::ReconnectTokens>>,
+    /// Sessions listening through remote forwards on targets
+    remote_forwards: Arc<super::remote_forward::RemoteForwards>,
+    breach_filter: Option<Arc<super::breach::BreachFilter>>,
+    record_files: Arc<super::record_files::RecordFiles>,
+    record_s3: Option<Arc<crate::asciinema::S3Store>>,
+    push_mfa: Option<Arc<super::push_mfa::PushMfa>>,
+    opa: Option<Arc<super::opa::Opa>>,
+    geoip: Option<Arc<super::geoip::GeoIp>>,
+    /// Signs the certificates of `ca` secrets, set with `target_ca_key`
+    target_ca: Option<Arc<super::target_ca::TargetCa>>,
+    command_filters: Arc<super::command_filter::CommandFilters>,
+    exec_log: Arc<super::exec_log::ExecLog>,
+    totp_steps: Arc<super::totp::UsedSteps>,
+    /// Action of each subsystem in `subsystems`, by subsystem name
+    subsystem_actions: Arc<HashMap<String, Uuid>>,
     role_manager: Arc<RwLock<casbin::RoleManage>>,
+    /// Set with `policy_cache_ttl`
+    policy_cache: Option<PolicyCache>,
+    database_healthy: Arc<AtomicBool>,
 }
 
 impl Server for BastionServer {
@@ -45,6 +124,7 @@ impl Server for BastionServer {
         BastionHandler::new(
             client_ip,
             self.config.max_auth_attempts_per_conn,
+            &self.config.limits,
             Arc::new(self.clone()),
         )
     }
@@ -71,6 +151,11 @@ impl BastionServer {
             })
         })?;
 
+        let auth_provider = super::auth_provider::create_auth_provider(
+            &config.auth_providers,
+            config.user_ca_fingerprints()?,
+        )?;
+
         // Initialize database service
         let database = DatabaseService::new(&config.database).await?;
 
@@ -114,23 +199,91 @@ impl BastionServer {
             }
         });
 
-        // initial casbin role
-        let role_manager = {
-            let g1 = database
-                .repository()
-                .list_casbin_rule_group_by_ptype("g1")
-                .await?;
-            let g2 = database
-                .repository()
-                .list_casbin_rule_group_by_ptype("g2")
-                .await?;
-            let g3 = database
-                .repository()
-                .list_casbin_rule_group_by_ptype("g3")
-                .await?;
+        let rate_limiter =
+            super::rate_limit::RateLimiter::new(&config.rate_limit).map_err(Error::Config)?;
+
+        let breach_filter = match config.breached_passwords.as_ref() {
+            Some(path) => {
+                let filter = super::breach::BreachFilter::load(Path::new(path)).map_err(|e| {
+                    Error::Config(crate::config::error::ConfigError::BreachFilter {
+                        path: path.clone(),
+                        source: e,
+                    })
+                })?;
+                info!(
+                    "Loaded breached password filter {} ({} bytes)",
+                    path,
+                    filter.size_bytes()
+                );
+                Some(Arc::new(filter))
+            }
+            None => None,
+        };
 
-            casbin::RoleManage::new(&g1, &g2, &g3)?
+        let record_files =
+            super::record_files::RecordFiles::new(&config.record_files).map_err(Error::Config)?;
+        let record_s3 = match config.record_sink.kind {
+            crate::config::RecordSinkKind::S3 => Some(Arc::new(
+                crate::asciinema::S3Store::new(&config.record_sink).map_err(Error::Config)?,
+            )),
+            crate::config::RecordSinkKind::File => None,
+        };
+        let push_mfa = super::push_mfa::PushMfa::new(&config.push_mfa).map(Arc::new);
+        let opa = super::opa::Opa::new(&config.opa).map(Arc::new);
+        let geoip = match config.geoip_database.as_ref() {
+            Some(path) => {
+                let geoip = super::geoip::GeoIp::open(Path::new(path)).map_err(|e| {
+                    Error::Config(crate::config::error::ConfigError::GeoIpDatabase {
+                        path: path.clone(),
+                        source: e,
+                    })
+                })?;
+                info!("Loaded GeoIP database {} ({})", path, geoip.kind());
+                Some(Arc::new(geoip))
+            }
+            None => None,
+        };
+        let target_ca = match config.target_ca_key.as_ref() {
+            Some(path) => {
+                let ca = super::target_ca::TargetCa::load(path, config.target_cert_validity)?;
+                info!(
+                    "Loaded target CA {} ({})",
+                    path,
+                    ca.public_key()
+                        .fingerprint(russh::keys::ssh_key::HashAlg::Sha256)
+                );
+                Some(Arc::new(ca))
+            }
+            None => None,
         };
+        let command_filters = super::command_filter::CommandFilters::new(&config.command_filters)
+            .map_err(Error::Config)?;
+        let exec_log = super::exec_log::ExecLog::new(&config.exec_log).map_err(Error::Config)?;
+
+        // Every tag in use gets its `tag:key=value` group before policies
+        // are checked against them
+        let author = database.internal_author().await?;
+        database.repository().sync_tag_groups(&author).await?;
+
+        // Say what the enforcer is going to skip before it starts skipping it
+        super::policy_lint::report(&database).await?;
+
+        // initial casbin role
+        let role_manager = load_role_manager(database.repository()).await?;
+
+        // Databases set up before these actions existed get them now
+        {
+            use crate::database::common::*;
+            for name in [
+                ACT_SFTP,
+                ACT_SCP,
+                ACT_REMOTE_FORWARD,
+                ACT_AGENT_FORWARD,
+                ACT_OBSERVE,
+            ] {
+                Self::internal_action_or_create(&database, name, &author).await?;
+            }
+        }
 
         // Initialize global internal UUIDs (only once)
         // TODO: Query once to get all internal uuids.
@@ -217,6 +370,11 @@ impl BastionServer {
                     })
                 })?
                 .id;
+            let act_sftp = Self::internal_action(&database, ACT_SFTP).await?;
+            let act_scp = Self::internal_action(&database, ACT_SCP).await?;
+            let act_remote_forward = Self::internal_action(&database, ACT_REMOTE_FORWARD).await?;
+            let act_agent_forward = Self::internal_action(&database, ACT_AGENT_FORWARD).await?;
+            let act_observe = Self::internal_action(&database, ACT_OBSERVE).await?;
 
             InternalUuids::init(InternalUuids {
                 obj_login,
@@ -227,73 +385,654 @@ impl BastionServer {
                 act_exec,
                 act_login,
                 act_direct_tcpip,
+                act_sftp,
+                act_scp,
+                act_remote_forward,
+                act_agent_forward,
+                act_observe,
             });
         }
 
+        let mut subsystem_actions = HashMap::new();
+        for name in &config.subsystems {
+            let action = format!("{}{}", crate::database::common::ACT_SUBSYSTEM_PREFIX, name);
+            let id = Self::internal_action_or_create(&database, &action, &author).await?;
+            subsystem_actions.insert(name.clone(), id);
+        }
+
+        let policy_cache = config.policy_cache_ttl.map(|ttl| {
+            Cache::builder()
+                .max_capacity(MAX_CAPACITY)
+                .time_to_live(ttl)
+                .build()
+        });
+
+        let reconnect_tokens = connection_pool
+            .as_ref()
+            .and(config.reconnect_token_ttl)
+            .map(|ttl| Arc::new(super::reconnect::ReconnectTokens::new(ttl)));
+
         Ok(Self {
             config,
             secret_key: token,
+            auth_provider: Arc::new(auth_provider),
             database,
             client_ip_pool,
             client_user_pool,
+            rate_limiter,
+            bans: Arc::new(RwLock::new(Vec::new())),
+            pool_leases: connection_pool.as_ref().map(|_| Arc::default()),
+            live_sessions: Arc::default(),
+            reconnect_tokens,
             connection_pool,
+            remote_forwards: Arc::default(),
+            breach_filter,
+            record_files: Arc::new(record_files),
+            record_s3,
+            push_mfa,
+            opa,
+            geoip,
+            target_ca,
+            command_filters: Arc::new(command_filters),
+            exec_log: Arc::new(exec_log),
+            totp_steps: Arc::default(),
+            subsystem_actions: Arc::new(subsystem_actions),
             role_manager: Arc::new(RwLock::new(role_manager)),
+            policy_cache,
+            database_healthy: Arc::new(AtomicBool::new(true)),
         })
     }
 
-    pub async fn do_load_role_manager(&self) -> Result<(), Error> {
-        let g1 = self
-            .database
+    /// Id of the internal action `name`
+    async fn internal_action(database: &DatabaseService, name: &str) -> Result<Uuid, Error> {
+        Ok(database
             .repository()
-            .list_casbin_rule_group_by_ptype("g1")
-            .await?;
-        let g2 = self
+            .get_casbin_name_by_name(name)
+            .await?
+            .ok_or_else(|| {
+                Error::Server(ServerError::ActionNotFound {
+                    name: name.to_string(),
+                })
+            })?
+            .id)
+    }
+
+    /// Id of the internal action `name`, created by `author` when the
+    /// database lacks it
+    async fn internal_action_or_create(
+        database: &DatabaseService,
+        name: &str,
+        author: &Uuid,
+    ) -> Result<Uuid, Error> {
+        if let Some(action) = database.repository().get_casbin_name_by_name(name).await? {
+            return Ok(action.id);
+        }
+        let action = models::CasbinName::new(
+            crate::database::common::INTERNAL_ACTION_TYPE.to_string(),
+            name.to_string(),
+            true,
+            *author,
+        );
+        info!("Creating internal action {}", name);
+        Ok(database.repository().create_casbin_name(&action).await?.id)
+    }
+
+    /// Adds the country of the client address, unless the caller gave one
+    fn locate(&self, ext: casbin::ExtendPolicyReq) -> casbin::ExtendPolicyReq {
+        match (self.geoip.as_ref(), ext.ip) {
+            (Some(geoip), Some(ip)) if ext.country.is_none() => {
+                let country = geoip.country(ip);
+                ext.with_country(country)
+            }
+            _ => ext,
+        }
+    }
+
+    pub async fn do_load_role_manager(&self) -> Result<(), Error> {
+        let role_manager = load_role_manager(self.database.repository()).await?;
+        *self.role_manager.write().await = role_manager;
+        self.do_invalidate_policy_cache();
+        Ok(())
+    }
+
+    pub fn do_invalidate_policy_cache(&self) {
+        if let Some(cache) = self.policy_cache.as_ref() {
+            cache.invalidate_all();
+        }
+    }
+
+    async fn policy_candidates(
+        &self,
+        sub: Uuid,
+        obj: Uuid,
+        act: Uuid,
+    ) -> Result<Arc<PolicyCandidates>, Error> {
+        let key = (sub, obj, act);
+        if let Some(cache) = self.policy_cache.as_ref()
+            && let Some(candidates) = cache.get(&key).await
+        {
+            return Ok(candidates);
+        }
+
+        let repo = self.database.repository();
+        let denies = repo.list_casbin_rules_by_ptype("p_deny").await?;
+        let policies = repo.list_casbin_rules_by_ptype("p").await?;
+        let (denies, grants) = {
+            let role_manager = self.role_manager.read().await;
+            (
+                role_manager.covering(denies, sub, obj, act),
+                role_manager.covering(policies, sub, obj, act),
+            )
+        };
+        let object_active = !grants.is_empty() && repo.check_object_active(&obj).await?;
+        let candidates = Arc::new(PolicyCandidates {
+            denies,
+            grants,
+            object_active,
+        });
+        if let Some(cache) = self.policy_cache.as_ref() {
+            cache.insert(key, candidates.clone()).await;
+        }
+        Ok(candidates)
+    }
+
+    /// Decision of the local rules alone, `ext` already carries the action
+    async fn local_policy(
+        &self,
+        sub: Uuid,
+        obj: Uuid,
+        act: Uuid,
+        ext: &casbin::ExtendPolicyReq,
+    ) -> Result<Option<casbin::ExtendPolicy>, Error> {
+        let candidates = self.policy_candidates(sub, obj, act).await?;
+
+        // deny rules override every grant
+        for pol in &candidates.denies {
+            if casbin::deny_applies(ext, &pol.v3)? {
+                trace!("Reject by deny rule, sub: {}, policy: {:?}", sub, pol);
+                return Ok(None);
+            }
+        }
+        if !candidates.object_active {
+            trace!(
+                "Reject due to object not active or not granted, sub: {}, obj: {}, act: {}",
+                sub, obj, act
+            );
+            return Ok(None);
+        }
+        for pol in &candidates.grants {
+            // match ext
+            if let Some(matched) = casbin::match_extend_policy(ext, &pol.v3)? {
+                trace!("Accept sub: {}, policy: {:?}", sub, pol);
+                return Ok(Some(matched));
+            }
+        }
+
+        trace!("Reject by ext, sub: {}, obj: {}, act: {}", sub, obj, act);
+        Ok(None)
+    }
+
+    /// Ext of a request OPA allowed. Local deny rules still refuse it, and
+    /// when local grants cover it one of them has to match, its IP, time and
+    /// expiry limits included. Without local grants OPA's answer stands.
+    async fn opa_allowed_policy(
+        &self,
+        sub: Uuid,
+        obj: Uuid,
+        act: Uuid,
+        ext: &casbin::ExtendPolicyReq,
+    ) -> Result<Option<casbin::ExtendPolicy>, Error> {
+        let candidates = self.policy_candidates(sub, obj, act).await?;
+
+        for pol in &candidates.denies {
+            if casbin::deny_applies(ext, &pol.v3)? {
+                trace!("Reject by deny rule, sub: {}, policy: {:?}", sub, pol);
+                return Ok(None);
+            }
+        }
+        let mut granted = false;
+        for pol in &candidates.grants {
+            if let Some(matched) = casbin::match_extend_policy(ext, &pol.v3)? {
+                trace!("Accept sub: {}, policy: {:?}", sub, pol);
+                return Ok(Some(matched));
+            }
+            let parsed: casbin::ExtendPolicy =
+                pol.v3.parse().map_err(ServerError::ExtendPolicyParse)?;
+            // a pending access request grants nothing, nor does it limit
+            granted |= !parsed.pending;
+        }
+        if granted {
+            trace!("Reject by ext, sub: {}, obj: {}, act: {}", sub, obj, act);
+            return Ok(None);
+        }
+        Ok(Some(casbin::ExtendPolicy::default()))
+    }
+
+    /// Sessions `user_id` opened in the current period on the bindings `pol`
+    /// covers, directly or through its object group
+    async fn quota_usage(
+        &self,
+        user_id: Uuid,
+        pol: &models::CasbinRule,
+        quota: SessionQuota,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<QuotaUsage, Error> {
+        let mut covered = self
+            .role_manager
+            .read()
+            .await
+            .fetch_role_from_start(pol.v1, casbin::GroupType::Object);
+        covered.push(pol.v1);
+        let ids: Vec<&Uuid> = covered.iter().collect();
+        let used = self
             .database
             .repository()
-            .list_casbin_rule_group_by_ptype("g2")
+            .count_ssh_sessions(&user_id, &ids, models::Timestamp(quota.since(now)))
             .await?;
-        let g3 = self
+        let object = self.database.rule_labels().await?.label(&pol.v1);
+        Ok(QuotaUsage {
+            rule_id: pol.id,
+            object,
+            quota,
+            used: u32::try_from(used).unwrap_or(u32::MAX),
+        })
+    }
+
+    /// Drops expired bans from the database and reloads the rest
+    pub async fn do_load_bans(&self) -> Result<(), Error> {
+        let now = models::Timestamp::now();
+        let expired = self
             .database
             .repository()
-            .list_casbin_rule_group_by_ptype("g3")
+            .delete_expired_banned_ips(now)
             .await?;
-
-        let mut m = self.role_manager.write().await;
-        *m = casbin::RoleManage::new(&g1, &g2, &g3)?;
+        if expired > 0 {
+            debug!("Removed {} expired bans", expired);
+        }
+        let bans = self.database.repository().list_banned_ips().await?;
+        *self.bans.write().await = bans;
         Ok(())
     }
 
-    pub async fn run(&mut self) -> Result<(), Error> {
-        // Load server key or generate a random one
-        let key_file = Path::new(&self.config.server_key);
-        let keys = if key_file.exists() {
-            vec![russh::keys::PrivateKey::read_openssh_file(key_file).map_err(russh::Error::from)?]
-        } else {
-            warn!("Server key file not found, generating a random key",);
-            vec![
-                russh::keys::PrivateKey::random(&mut rng(), Algorithm::Ed25519)
-                    .map_err(russh::Error::from)?,
-            ]
+    async fn banned(&self, ip: std::net::IpAddr) -> Option<models::BannedIp> {
+        let now = models::Timestamp::now();
+        self.bans
+            .read()
+            .await
+            .iter()
+            .find(|b| b.matches(ip, now))
+            .cloned()
+    }
+
+    /// Bans an IP that went over `max_ip_attempts` for `unban_duration`
+    async fn auto_ban(&self, ip: std::net::IpAddr, failures: u32) {
+        let ban = models::BannedIp::new(
+            IpNetwork::from(ip),
+            format!("{} failed logins", failures),
+            Uuid::nil(),
+        )
+        .with_expires_at(models::Timestamp::now().checked_add(self.config.unban_duration));
+        if let Err(e) = self.database.repository().create_banned_ip(&ban).await {
+            error!("Failed to ban {}: {}", ip, e);
+            return;
+        }
+        info!("Banned {} until {}", ip, ban.print_expires_at());
+        self.bans.write().await.push(ban);
+    }
+
+    fn spawn_ban_refresh(&self) {
+        let server = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(BAN_REFRESH_INTERVAL).await;
+                if let Err(e) = server.do_load_bans().await {
+                    warn!("Failed to reload bans: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Deletes the `p` and `p_deny` rules whose expire date is more than
+    /// `grace` ago, returns how many. Pending rules are left to the admin
+    /// deciding their request.
+    pub async fn do_clean_expired_policies(&self, grace: Duration) -> Result<usize, Error> {
+        let Some(cutoff) = chrono::TimeDelta::from_std(grace)
+            .ok()
+            .and_then(|g| chrono::Utc::now().checked_sub_signed(g))
+        else {
+            return Ok(0);
         };
+        let repo = self.database.repository();
+        let mut expired = Vec::new();
+        for ptype in ["p", "p_deny"] {
+            for rule in repo.list_casbin_rules_by_ptype(ptype).await? {
+                let ext: casbin::ExtendPolicy = match rule.v3.parse() {
+                    Ok(ext) => ext,
+                    Err(e) => {
+                        warn!("Skipped rule {} with a bad ext: {}", rule.id, e);
+                        continue;
+                    }
+                };
+                // Every keyed action has to be past its expire date too
+                if !ext.pending
+                    && ext.policies().all(|p| {
+                        p.expire_date
+                            .is_some_and(|e| e.with_timezone(&chrono::Utc) < cutoff)
+                    })
+                {
+                    expired.push(rule.id);
+                }
+            }
+        }
+        if expired.is_empty() {
+            return Ok(0);
+        }
+
+        let requests = self.database.delete_expired_casbin_rules(&expired).await?;
+        self.do_invalidate_policy_cache();
+        let detail = format!(
+            "deleted {} expired policies, {} access requests expired",
+            expired.len(),
+            requests.len()
+        );
+        info!("Policy cleanup {}", detail);
+        self.insert_log(
+            Uuid::nil(),
+            Uuid::nil(),
+            POLICY_CLEANUP_LOG_TYPE.to_string(),
+            detail,
+        )
+        .await;
+        Ok(expired.len())
+    }
+
+    fn spawn_policy_cleanup(&self, grace: Duration) {
+        let server = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = server.do_clean_expired_policies(grace).await {
+                    warn!("Failed to clean expired policies: {}", e);
+                }
+                tokio::time::sleep(POLICY_CLEANUP_INTERVAL).await;
+            }
+        });
+    }
+
+    /// Purges the completed recordings `retention` selects, returns how
+    /// many. A recording whose file is already gone counts as purged.
+    pub async fn do_apply_record_retention(
+        &self,
+        retention: &super::record_retention::Retention,
+    ) -> Result<usize, Error> {
+        let repo = self.database.repository();
+        let names: HashMap<Uuid, String> = repo
+            .list_targets(false, &[])
+            .await?
+            .into_iter()
+            .map(|t| (t.id, t.name))
+            .collect();
+        let recordings: Vec<_> = repo
+            .list_session_recordings(None)
+            .await?
+            .into_iter()
+            .filter(|r| r.status == "completed")
+            .collect();
+        let root = Path::new(&self.config.record_path);
+        let mut candidates = Vec::with_capacity(recordings.len());
+        for r in &recordings {
+            let target = names.get(&r.target_id).cloned().unwrap_or_default();
+            let mut size = 0;
+            if retention.sizes_target(&target) {
+                let res = match self.record_s3.as_ref() {
+                    Some(s3) => s3.size(&r.file_path).await,
+                    None => std::fs::metadata(root.join(&r.file_path)).map(|m| m.len()),
+                };
+                match res {
+                    Ok(s) => size = s,
+                    Err(e) => warn!("Failed to read the size of {}: {}", r.file_path, e),
+                }
+            }
+            candidates.push(super::record_retention::Candidate {
+                target,
+                ended_at: r.ended_at.unwrap_or(r.started_at).0,
+                size,
+            });
+        }
+
+        let archive = self.config.record_retention.archive_path.as_deref();
+        let mut purged = 0;
+        for (i, reason) in retention.select(&candidates, chrono::Utc::now()) {
+            let r = &recordings[i];
+            let res = match (self.record_s3.as_ref(), archive) {
+                (Some(s3), _) => s3.delete(&r.file_path).await.map(|_| "purged"),
+                (None, Some(dir)) => super::record_retention::archive(
+                    &root.join(&r.file_path),
+                    &Path::new(dir).join(&r.file_path),
+                )
+                .map(|_| "archived"),
+                (None, None) => std::fs::remove_file(root.join(&r.file_path)).map(|_| "purged"),
+            };
+            let status = match res {
+                Ok(status) => status,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => "purged",
+                Err(e) => {
+                    warn!("Failed to purge recording {}: {}", r.file_path, e);
+                    continue;
+                }
+            };
+            let mut updated = r.clone();
+            updated.status = status.to_string();
+            if let Err(e) = repo.update_session_recording(&updated).await {
+                warn!("Failed to mark recording {} {}: {}", r.id, status, e);
+            }
+            let detail = format!(
+                "{} recording {} of {}, {}",
+                status, r.file_path, candidates[i].target, reason
+            );
+            info!("Record retention {}", detail);
+            self.insert_log(
+                r.connection_id,
+                r.user_id,
+                RECORD_RETENTION_LOG_TYPE.to_string(),
+                detail,
+            )
+            .await;
+            purged += 1;
+        }
+        Ok(purged)
+    }
+
+    fn spawn_record_retention(&self, retention: super::record_retention::Retention) {
+        let server = self.clone();
+        let interval = self.config.record_retention.interval;
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = server.do_apply_record_retention(&retention).await {
+                    warn!("Failed to apply record retention: {}", e);
+                }
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
+
+    /// Reloads the role manager whenever the policy version moved, which
+    /// every write to rules, groups, targets and bindings does, wherever it
+    /// came from
+    fn spawn_policy_watch(&self, interval: Duration) {
+        let server = self.clone();
+        tokio::spawn(async move {
+            let repo = server.database.repository();
+            let mut seen = repo.get_policy_version().await.ok();
+            loop {
+                tokio::time::sleep(interval).await;
+                let version = match repo.get_policy_version().await {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Failed to read the policy version: {}", e);
+                        continue;
+                    }
+                };
+                if seen == Some(version) {
+                    continue;
+                }
+                match server.do_load_role_manager().await {
+                    Ok(()) => {
+                        debug!("Reloaded role manager at policy version {}", version);
+                        seen = Some(version);
+                    }
+                    Err(e) => warn!("Failed to reload role manager: {}", e),
+                }
+            }
+        });
+    }
+
+    pub async fn run(&mut self) -> Result<(), Error> {
+        let keys = super::host_keys::load(&self.config, chrono::Utc::now())?;
 
         let russh_config = RusshConfig {
             keys,
             server_id: russh::SshId::Standard(self.config.server_id.clone().into()),
             inactivity_timeout: self.config.inactivity_timeout,
+            keepalive_interval: self.config.keepalive_interval,
             auth_rejection_time: self.config.auth_rejection_time,
+            window_size: self.config.limits.window_size,
+            maximum_packet_size: self.config.limits.max_packet_size,
+            channel_buffer_size: self.config.limits.channel_buffer_size,
+            preferred: self.config.algorithms.server.preferred("server")?,
             ..Default::default()
         };
 
+        // Nothing is live before the listener is up
+        let stale = self
+            .database
+            .repository()
+            .end_active_ssh_sessions("server restart")
+            .await?;
+        if stale > 0 {
+            warn!("Closed {} sessions left open by a previous run", stale);
+        }
+
+        self.do_load_bans().await?;
+        self.spawn_ban_refresh();
+        self.spawn_health_probe();
+        if let Some(grace) = self.config.expired_policy_grace {
+            self.spawn_policy_cleanup(grace);
+        }
+        if !self.config.policy_reload_interval.is_zero() {
+            self.spawn_policy_watch(self.config.policy_reload_interval);
+        }
+        if let Some(retention) =
+            super::record_retention::Retention::new(&self.config.record_retention)
+        {
+            self.spawn_record_retention(retention);
+        }
+
         let listen_addr = self.config.parse_listen_addr()?;
         info!("Starting rustion server on {}", listen_addr);
 
+        let proxies = self.config.trusted_proxy_networks()?;
         let socket = tokio::net::TcpListener::bind(listen_addr).await?;
-        let server = self.run_on_socket(Arc::new(russh_config), &socket);
+        let russh_config = Arc::new(russh_config);
         // TODO: gracefully shutdown when catch TERM signal
-        let _handle = server.handle();
+        loop {
+            let (mut stream, mut peer) = socket.accept().await?;
+            let proxied = proxies.iter().any(|n| n.contains(peer.ip()));
+            if !proxied && let Some(ban) = self.banned(peer.ip()).await {
+                debug!(
+                    "Dropped connection from {}, banned by {}: {}",
+                    peer, ban.network, ban.reason
+                );
+                continue;
+            }
 
-        server.await?;
-        Ok(())
+            let config = russh_config.clone();
+            let mut server = self.clone();
+            tokio::spawn(async move {
+                // Read here, a slow balancer holds up no other connection
+                if proxied {
+                    let header = tokio::time::timeout(
+                        super::proxy_protocol::HEADER_TIMEOUT,
+                        super::proxy_protocol::read_header(&mut stream),
+                    )
+                    .await
+                    .unwrap_or_else(|_| Err(std::io::ErrorKind::TimedOut.into()));
+                    match header {
+                        Ok(Some(client)) => {
+                            trace!("Connection from {} proxied by {}", client, peer);
+                            peer = client;
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            warn!("Dropped connection from proxy {}: {}", peer, e);
+                            return;
+                        }
+                    }
+                    if let Some(ban) = server.banned(peer.ip()).await {
+                        debug!(
+                            "Dropped connection from {}, banned by {}: {}",
+                            peer, ban.network, ban.reason
+                        );
+                        return;
+                    }
+                }
+                let handler = server.new_client(Some(peer));
+                let session = match run_stream(config, stream, handler).await {
+                    Ok(s) => s,
+                    Err(e) => {
+                        server.handle_session_error(e);
+                        return;
+                    }
+                };
+                if let Err(e) = session.await {
+                    server.handle_session_error(e);
+                }
+            });
+        }
+    }
+
+    /// Probes the database every `HEALTH_CHECK_INTERVAL`. After a failure the
+    /// probe retries with exponential backoff and new logins are turned away
+    /// until the database answers again.
+    fn spawn_health_probe(&self) {
+        let database = self.database.clone();
+        let healthy = self.database_healthy.clone();
+        tokio::spawn(async move {
+            let mut backoff = Duration::from_secs(1);
+            loop {
+                let res = tokio::time::timeout(
+                    HEALTH_CHECK_TIMEOUT,
+                    database.repository().health_check(),
+                )
+                .await
+                .unwrap_or_else(|_| {
+                    Err(Error::Server(ServerError::DatabaseTimeout {
+                        timeout: HEALTH_CHECK_TIMEOUT,
+                    }))
+                });
+                match res {
+                    Ok(()) => {
+                        if !healthy.swap(true, Ordering::Relaxed) {
+                            info!("Database is reachable again, accepting logins");
+                        }
+                        backoff = Duration::from_secs(1);
+                        tokio::time::sleep(HEALTH_CHECK_INTERVAL).await;
+                    }
+                    Err(e) => {
+                        if healthy.swap(false, Ordering::Relaxed) {
+                            error!("Database health check failed, rejecting logins: {}", e);
+                        } else {
+                            warn!(
+                                "Database still unreachable, next probe in {}: {}",
+                                humantime::format_duration(backoff),
+                                e
+                            );
+                        }
+                        tokio::time::sleep(backoff).await;
+                        backoff = (backoff * 2).min(HEALTH_CHECK_MAX_BACKOFF);
+                    }
+                }
+            }
+        });
     }
 
     /// Hash a plain-text password and return a PHC string.
@@ -305,18 +1044,7 @@ impl BastionServer {
     }
 
     fn decrypt_with_secret_key(&self, text: &str) -> Result<String, Error> {
-        let encrypt_key = general_purpose::STANDARD
-            .decode(text)
-            .map_err(|e| Error::Server(ServerError::Base64Decode { source: e }))?;
-        let (nonce, ciphertext) = encrypt_key.split_at(12);
-        let nonce = Nonce::from_slice(nonce);
-
-        match self.secret_key.decrypt(nonce, ciphertext.as_ref()) {
-            Ok(plain) => Ok(String::from_utf8_lossy(&plain).to_string()),
-            Err(e) => Err(Error::Server(ServerError::DecryptionFailed {
-                reason: e.to_string(),
-            })),
-        }
+        crate::common::decrypt_with_key(&self.secret_key, text)
     }
 
     pub async fn generate_random_password(&self, mut user: models::User) -> Result<String, Error> {
@@ -324,23 +1052,344 @@ impl BastionServer {
         let h = self
             .hash_password(&password)
             .map_err(|_| Error::Server(ServerError::PasswordHashFailed))?;
-        user.set_password_hash(h);
+        user.set_password_hash(h, self.config.password_history);
         self.database.repository().update_user(&user).await?;
         Ok(password.to_string())
     }
-}
 
-impl super::HandlerBackend for BastionServer {
-    async fn get_user_by_username(
+    /// Connects to `target` and logs in with the secret of the binding
+    async fn login_target(
         &self,
-        name: &str,
-        active_only: bool,
-    ) -> Result<Option<models::User>, Error> {
-        self.database
+        target: models::Target,
+        target_secret_id: &Uuid,
+        login_as: Option<&str>,
+        prompts: bool,
+        pool_key: Option<String>,
+    ) -> Result<TargetLogin, Error> {
+        let mut secret = match self
+            .database
             .repository()
-            .get_user_by_username(name, active_only)
-            .await
-    }
+            .get_secret_by_target_secret_id(target_secret_id, true)
+            .await?
+        {
+            Some(s) => {
+                if !s.is_active {
+                    return Ok(TargetLogin::Refused);
+                }
+                s
+            }
+            None => return Ok(TargetLogin::Refused),
+        };
+        if let Some(account) = login_as {
+            secret.user = account.to_string();
+        }
+        // Signed before connecting, the target's name is gone after
+        let certificate = if secret.is_ca() {
+            let Some(ca) = self.target_ca.as_ref() else {
+                warn!(
+                    "Secret '{}' logs in with a certificate but target_ca_key is not set",
+                    secret.name
+                );
+                return Ok(TargetLogin::Refused);
+            };
+            let key_id = format!("rustion:{}@{}", secret.user, target.name);
+            let now = chrono::Utc::now().timestamp() as u64;
+            let (key, cert) = ca.issue(&secret.user, &key_id, now)?;
+            debug!("Signed certificate {} serial {}", key_id, cert.serial());
+            Some((key, cert))
+        } else {
+            None
+        };
+
+        let presented = PresentedKey::default();
+        let (target_id, target_name) = (target.id, target.name.clone());
+        let host_key_mode = target.host_key_mode.clone();
+        let auth_timeout = self
+            .config
+            .target_connect
+            .for_target(target.timeout())
+            .auth_timeout;
+        let connected = target
+            .with_remote_forwards(self.remote_forwards.clone())
+            .with_presented_key(presented.clone())
+            .build_connect(
+                self.config.client_id.clone(),
+                &self.config.limits,
+                &self.config.target_connect,
+                self.config.algorithms.client.preferred("client")?,
+            )
+            .await;
+        let seen = presented.lock().unwrap().take();
+        let (handle, endpoint) = match (connected, seen) {
+            (Ok(connected), Some((key, HostKeyCheck::Unknown))) => {
+                self.store_host_key(&target_id, &key, false).await?;
+                connected
+            }
+            (Ok(connected), _) => connected,
+            (Err(_), Some((key, check))) => {
+                self.host_key_refused(&target_id, &target_name, &host_key_mode, &key, check)
+                    .await?;
+                return Ok(TargetLogin::Refused);
+            }
+            (Err(e), None) => return Err(e),
+        };
+
+        let login = self.authenticate(handle, endpoint, secret, certificate, prompts, pool_key);
+        tokio::time::timeout(auth_timeout, login)
+            .await
+            .unwrap_or_else(|_| {
+                Err(ServerError::TargetUnreachable {
+                    target: target_name,
+                    reason: format!(
+                        "login timed out after {}",
+                        humantime::format_duration(auth_timeout)
+                    ),
+                }
+                .into())
+            })
+    }
+
+    /// Logs in on a connected target, with the certificate signed for the
+    /// secret or its stored credentials
+    async fn authenticate(
+        &self,
+        mut handle: ru_client::Handle<models::Target>,
+        endpoint: String,
+        mut secret: models::Secret,
+        certificate: Option<(PrivateKey, Certificate)>,
+        prompts: bool,
+        pool_key: Option<String>,
+    ) -> Result<TargetLogin, Error> {
+        if let Some((key, cert)) = certificate {
+            let auth_res = handle
+                .authenticate_openssh_cert(secret.user.clone(), Arc::new(key), cert)
+                .await?;
+            if auth_res.success() {
+                return Ok(self.ready(handle, endpoint, pool_key).await);
+            }
+            return Ok(TargetLogin::Refused);
+        }
+
+        if let Some(k) = secret.take_private_key() {
+            let key = match russh::keys::decode_secret_key(
+                self.decrypt_with_secret_key(&k)?.as_str(),
+                None,
+            ) {
+                Ok(k) => k,
+                Err(e) => {
+                    if matches!(e, russh::keys::Error::KeyIsEncrypted) {
+                        let pass = match secret.take_password() {
+                            Some(pub_key) => Some(self.decrypt_with_secret_key(&pub_key)?),
+                            None => None,
+                        };
+                        match russh::keys::decode_secret_key(
+                            self.decrypt_with_secret_key(&k)?.as_str(),
+                            pass.as_deref(),
+                        ) {
+                            Ok(key) => key,
+                            Err(e) => return Err(e.into()),
+                        }
+                    } else {
+                        return Err(e.into());
+                    }
+                }
+            };
+            let auth_res = handle
+                .authenticate_publickey(
+                    secret.user.clone(),
+                    russh::keys::PrivateKeyWithHashAlg::new(
+                        Arc::new(key),
+                        handle.best_supported_rsa_hash().await?.flatten(),
+                    ),
+                )
+                .await?;
+            if auth_res.success() {
+                return Ok(self.ready(handle, endpoint, pool_key).await);
+            }
+        };
+
+        if let Some(p) = secret.take_password() {
+            let pass = self.decrypt_with_secret_key(&p)?;
+            let auth_res = handle
+                .authenticate_password(secret.user.clone(), pass)
+                .await?;
+            if auth_res.success() {
+                return Ok(self.ready(handle, endpoint, pool_key).await);
+            }
+        }
+
+        // Relayed logins belong to the user who answered, they aren't pooled
+        if prompts && self.config.relay_target_prompts {
+            return PromptLogin::start(handle, endpoint, secret.user).await;
+        }
+        Ok(TargetLogin::Refused)
+    }
+
+    /// Logged in, the handle is pooled under `pool_key` when given
+    async fn ready(
+        &self,
+        handle: ru_client::Handle<models::Target>,
+        endpoint: String,
+        pool_key: Option<String>,
+    ) -> TargetLogin {
+        let handle = Arc::new(handle);
+        if let (Some(pool), Some(key)) = (self.connection_pool.as_ref(), pool_key) {
+            pool.insert(key, handle.clone()).await;
+        }
+        TargetLogin::Ready(handle, Some(endpoint))
+    }
+
+    /// Targets between the bastion and `target`, the one reached directly
+    /// first, each with the binding logging in to it: the one with the secret
+    /// of `target_secret_id`, else its only active one. `None` when the
+    /// chain can't be followed.
+    async fn via_chain(
+        &self,
+        target: &models::Target,
+        target_secret_id: &Uuid,
+    ) -> Result<Option<Vec<(models::Target, Uuid)>>, Error> {
+        let Some(mut via_id) = target.via_target_id else {
+            return Ok(Some(Vec::new()));
+        };
+        let repo = self.database.repository();
+        let bindings = repo.list_target_secrets(true).await?;
+        let Some(secret_id) = bindings
+            .iter()
+            .find(|b| b.id == *target_secret_id)
+            .map(|b| b.secret_id)
+        else {
+            return Ok(None);
+        };
+        let mut chain: Vec<(models::Target, Uuid)> = Vec::new();
+        loop {
+            if via_id == target.id
+                || chain.len() == models::MAX_VIA_HOPS
+                || chain.iter().any(|(t, _)| t.id == via_id)
+            {
+                warn!(
+                    "Target '{}({})' is reached through a loop or more than {} targets",
+                    target.name,
+                    target.id,
+                    models::MAX_VIA_HOPS
+                );
+                return Ok(None);
+            }
+            let Some(hop) = repo.get_target_by_id(&via_id, true).await? else {
+                warn!(
+                    "Target '{}({})' is reached through missing or inactive target {}",
+                    target.name, target.id, via_id
+                );
+                return Ok(None);
+            };
+            let own: Vec<_> = bindings.iter().filter(|b| b.target_id == hop.id).collect();
+            let binding = match own.iter().find(|b| b.secret_id == secret_id) {
+                Some(b) => b.id,
+                None if own.len() == 1 => own[0].id,
+                None => {
+                    warn!(
+                        "No binding to log in to target '{}({})' on the way to '{}({})'",
+                        hop.name, hop.id, target.name, target.id
+                    );
+                    return Ok(None);
+                }
+            };
+            let next = hop.via_target_id;
+            chain.push((hop, binding));
+            match next {
+                Some(id) => via_id = id,
+                None => break,
+            }
+        }
+        chain.reverse();
+        Ok(Some(chain))
+    }
+
+    /// Saves `key` as the key of `target_id`, or as its pending key
+    async fn store_host_key(
+        &self,
+        target_id: &Uuid,
+        key: &russh::keys::PublicKey,
+        pending: bool,
+    ) -> Result<(), Error> {
+        let repo = self.database.repository();
+        let Some(mut target) = repo.get_target_by_id(target_id, false).await? else {
+            return Ok(());
+        };
+        let key = key.to_openssh().map_err(russh::Error::from)?;
+        if pending {
+            if target.pending_public_key.as_deref() == Some(key.as_str()) {
+                return Ok(());
+            }
+            target.pending_public_key = Some(key);
+        } else {
+            info!(
+                "Trusting host key of target '{}({})' on first use",
+                target.name, target.id
+            );
+            target.server_public_key = key;
+            target.pending_public_key = None;
+        }
+        repo.update_target(&target).await?;
+        Ok(())
+    }
+
+    /// Records a handshake refused for the key `key`, logging mismatches
+    /// for the admins
+    async fn host_key_refused(
+        &self,
+        target_id: &Uuid,
+        target_name: &str,
+        mode: &str,
+        key: &russh::keys::PublicKey,
+        check: HostKeyCheck,
+    ) -> Result<(), Error> {
+        let fingerprint = key.fingerprint(russh::keys::ssh_key::HashAlg::Sha256);
+        let detail = match check {
+            HostKeyCheck::Mismatch => {
+                error!(
+                    "Host key {} of target '{}({})' doesn't match the stored key, session blocked",
+                    fingerprint, target_name, target_id
+                );
+                format!(
+                    "host key {} of target {} doesn't match the stored key",
+                    fingerprint, target_name
+                )
+            }
+            HostKeyCheck::Unknown if mode == HOST_KEY_CONFIRM => {
+                self.store_host_key(target_id, key, true).await?;
+                warn!(
+                    "Host key {} of target '{}({})' waits for an admin to confirm it",
+                    fingerprint, target_name, target_id
+                );
+                format!(
+                    "host key {} of target {} waits for confirmation",
+                    fingerprint, target_name
+                )
+            }
+            _ => return Ok(()),
+        };
+        self.insert_log(
+            Uuid::nil(),
+            Uuid::nil(),
+            HOST_KEY_LOG_TYPE.to_string(),
+            detail,
+        )
+        .await;
+        Ok(())
+    }
+}
+
+impl super::HandlerBackend for BastionServer {
+    async fn get_user_by_username(
+        &self,
+        name: &str,
+        active_only: bool,
+    ) -> Result<Option<models::User>, Error> {
+        self.database
+            .repository()
+            .get_user_by_username(name, active_only)
+            .await
+    }
 
     // async fn get_target_by_name(&self, name: &str) -> Result<Option<models::Target>, Error> {
     //     self.database.repository().get_target_by_name(name).await
@@ -372,6 +1421,14 @@ impl super::HandlerBackend for BastionServer {
 
         // NOTE: Duplicate ids of target_secrets due to different policies.
         for pol in allowed_policies {
+            // Rules of an access request awaiting approval
+            if pol
+                .v3
+                .parse::<casbin::ExtendPolicy>()
+                .is_ok_and(|ext| ext.pending)
+            {
+                continue;
+            }
             // Get all role IDs from object group
             let role_manager = self.role_manager.read().await;
             let role_ids = role_manager.fetch_role_from_start(pol.v1, casbin::GroupType::Object);
@@ -397,98 +1454,125 @@ impl super::HandlerBackend for BastionServer {
                 res.extend_from_slice(&ts);
             }
         }
+
+        // Hide the bindings a deny rule keeps the user's shell away from
+        let denies = self
+            .database
+            .repository()
+            .list_casbin_rules_by_ptype("p_deny")
+            .await?;
+        if !denies.is_empty() {
+            let act_shell = crate::database::common::InternalUuids::get().act_shell;
+            let role_manager = self.role_manager.read().await;
+            let mut kept = Vec::with_capacity(res.len());
+            for t in res {
+                let denied = role_manager.match_deny(
+                    denies.clone(),
+                    *user_id,
+                    t.id,
+                    act_shell,
+                    &casbin::ExtendPolicyReq::default().with_action(act_shell),
+                )?;
+                if denied.is_none() {
+                    kept.push(t);
+                }
+            }
+            res = kept;
+        }
         Ok(res)
     }
 
+    async fn evict_dead_connection(&self, pool_key: &str) {
+        let Some(pool) = self.connection_pool.as_ref() else {
+            return;
+        };
+        // Only the handle that died, a fresh one may already be in its place
+        if pool.get(pool_key).await.is_some_and(|h| h.is_closed()) {
+            pool.invalidate(pool_key).await;
+        }
+    }
+
+    async fn scan_host_keys(
+        &self,
+        hostname: &str,
+        port: u16,
+    ) -> Result<Vec<russh::keys::PublicKey>, Error> {
+        super::keyscan::scan(hostname, port, &self.config.client_id).await
+    }
+
     async fn connect_to_target(
         &self,
         target: models::Target,
         target_secret_id: &Uuid,
+        login_as: Option<&str>,
         force_build_cconnect: bool,
-    ) -> Result<Option<Arc<ru_client::Handle<models::Target>>>, Error> {
-        let conn_key = format!("{}-{}", target_secret_id, target.id);
-        if let Some(pool) = self.connection_pool.as_ref() {
+        prompts: bool,
+    ) -> Result<TargetLogin, Error> {
+        let conn_key = super::connection_pool::pool_key(target_secret_id, &target.id, login_as);
+        // A connection forwarding an agent is kept to the session owning it
+        let pool = self
+            .connection_pool
+            .as_ref()
+            .filter(|_| target.agent.is_none());
+        if let Some(pool) = pool {
             if force_build_cconnect {
                 pool.invalidate(&conn_key).await;
             }
             if let Some(t) = pool.get(&conn_key).await {
-                return Ok(Some(t));
-            }
-        };
-        let mut secret = match self
-            .database
-            .repository()
-            .get_secret_by_target_secret_id(target_secret_id, true)
-            .await?
-        {
-            Some(s) => {
-                if !s.is_active {
-                    return Ok(None);
+                if super::connection_pool::is_alive(&t).await {
+                    return Ok(TargetLogin::Ready(t, None));
                 }
-                s
+                warn!(
+                    "Cached connection to target '{}({})' is stale, reconnecting",
+                    target.name, target.id
+                );
+                pool.invalidate(&conn_key).await;
             }
-            None => return Ok(None),
         };
-
-        let mut handle = target
-            .build_connect(self.config.client_id.clone())
-            .await?;
-
-        if let Some(k) = secret.take_private_key() {
-            let key = match russh::keys::decode_secret_key(
-                self.decrypt_with_secret_key(&k)?.as_str(),
-                None,
-            ) {
-                Ok(k) => k,
-                Err(e) => {
-                    if matches!(e, russh::keys::Error::KeyIsEncrypted) {
-                        let pass = match secret.take_password() {
-                            Some(pub_key) => Some(self.decrypt_with_secret_key(&pub_key)?),
-                            None => None,
-                        };
-                        match russh::keys::decode_secret_key(
-                            self.decrypt_with_secret_key(&k)?.as_str(),
-                            pass.as_deref(),
-                        ) {
-                            Ok(key) => key,
-                            Err(e) => return Err(e.into()),
-                        }
-                    } else {
-                        return Err(e.into());
-                    }
-                }
-            };
-            let auth_res = handle
-                .authenticate_publickey(
-                    secret.user.clone(),
-                    russh::keys::PrivateKeyWithHashAlg::new(
-                        Arc::new(key),
-                        handle.best_supported_rsa_hash().await?.flatten(),
-                    ),
-                )
-                .await?;
-            if auth_res.success() {
-                let handle = Arc::new(handle);
-                if let Some(pool) = self.connection_pool.as_ref() {
-                    pool.insert(conn_key, handle.clone()).await;
-                };
-                return Ok(Some(handle));
-            }
+        let Some(hops) = self.via_chain(&target, target_secret_id).await? else {
+            return Ok(TargetLogin::Refused);
         };
-
-        if let Some(p) = secret.take_password() {
-            let pass = self.decrypt_with_secret_key(&p)?;
-            let auth_res = handle.authenticate_password(secret.user, pass).await?;
-            if auth_res.success() {
-                let handle = Arc::new(handle);
-                if let Some(pool) = self.connection_pool.as_ref() {
-                    pool.insert(conn_key, handle.clone()).await;
-                };
-                return Ok(Some(handle));
+        // Every hop is reached through the one before it, the first directly
+        let mut via = None;
+        for (hop, binding) in hops {
+            let hop = match via.take() {
+                Some(v) => hop.with_via(v),
+                None => hop,
+            };
+            let (hop_id, hop_name) = (hop.id, hop.name.clone());
+            match self.login_target(hop, &binding, None, false, None).await? {
+                TargetLogin::Ready(handle, endpoint) => {
+                    debug!(
+                        "Reached target '{}({})' at {} on the way to '{}({})'",
+                        hop_name,
+                        hop_id,
+                        endpoint.unwrap_or_default(),
+                        target.name,
+                        target.id
+                    );
+                    via = Some(handle);
+                }
+                _ => {
+                    warn!(
+                        "Target '{}({})' refused the login on the way to '{}({})'",
+                        hop_name, hop_id, target.name, target.id
+                    );
+                    return Ok(TargetLogin::Refused);
+                }
             }
         }
-
-        Ok(None)
+        let target = match via {
+            Some(v) => target.with_via(v),
+            None => target,
+        };
+        self.login_target(
+            target,
+            target_secret_id,
+            login_as,
+            prompts,
+            pool.map(|_| conn_key),
+        )
+        .await
     }
 
     async fn update_user_password(
@@ -499,19 +1583,70 @@ impl super::HandlerBackend for BastionServer {
         let h = self
             .hash_password(&password)
             .map_err(|_| Error::Server(ServerError::PasswordHashFailed))?;
-        user.set_password_hash(h);
-        self.database.repository().update_user(&user).await?;
-        Ok(user)
+        user.set_password_hash(h, self.config.password_history);
+        self.database.repository().update_user(&user).await
     }
 
     fn set_password(&self, user: &mut models::User, password: &str) -> Result<(), Error> {
         let h = self
             .hash_password(password)
             .map_err(|_| Error::Server(ServerError::PasswordHashFailed))?;
-        user.set_password_hash(h);
+        user.set_password_hash(h, self.config.password_history);
+        Ok(())
+    }
+
+    fn set_totp_secret(&self, user: &mut models::User, secret: Option<&str>) -> Result<(), Error> {
+        let encrypted = secret.map(|s| (self.encrypt_plain_text())(s)).transpose()?;
+        user.set_totp_secret(encrypted);
         Ok(())
     }
 
+    fn verify_totp(&self, user: &models::User, code: &str) -> Result<bool, Error> {
+        let Some(encrypted) = user.totp_secret() else {
+            return Ok(false);
+        };
+        let secret = self.decrypt_with_secret_key(encrypted)?;
+        let now = chrono::Utc::now().timestamp() as u64;
+        Ok(super::totp::verify(&secret, code, now)
+            .is_some_and(|step| self.totp_steps.accept(user.id, step)))
+    }
+
+    fn auth_provider(&self) -> &dyn super::auth_provider::AuthProvider {
+        self.auth_provider.as_ref()
+    }
+
+    fn push_mfa(&self) -> Option<&super::push_mfa::PushMfa> {
+        self.push_mfa.as_deref()
+    }
+
+    fn banner(&self) -> Option<&str> {
+        self.config.banner.as_deref()
+    }
+
+    fn motd(&self) -> Option<&str> {
+        self.config.motd.as_deref()
+    }
+
+    fn access_requests(&self) -> bool {
+        self.config.access_requests
+    }
+
+    fn command_filter(&self, name: &str) -> Option<Arc<super::command_filter::CommandFilter>> {
+        self.command_filters.get(name)
+    }
+
+    fn exec_log(&self) -> &super::exec_log::ExecLog {
+        &self.exec_log
+    }
+
+    fn subsystem_action(&self, name: &str) -> Option<Uuid> {
+        self.subsystem_actions.get(name).copied()
+    }
+
+    fn env_allowed(&self, name: &str) -> bool {
+        self.config.env_allowed(name)
+    }
+
     // async fn update_user(&self, user: models::User) -> Result<models::User, Error> {
     //     self.database.repository().update_user(&user).await?;
     //     Ok(user)
@@ -529,7 +1664,9 @@ impl super::HandlerBackend for BastionServer {
             user_id,
             log_type,
             detail,
-            created_at: chrono::Utc::now().timestamp_millis(),
+            created_at: models::Timestamp::now(),
+            prev_hash: String::new(),
+            hash: String::new(),
         };
         if let Err(e) = self.database.repository().insert_log(&l).await {
             error!("Insert log to database failed: {}", e);
@@ -549,11 +1686,24 @@ impl super::HandlerBackend for BastionServer {
         remove_counter(&self.client_user_pool, &username).await;
     }
 
+    async fn rate_limit(&self, socket_addr: Option<std::net::SocketAddr>) -> Option<Duration> {
+        let ip = socket_addr?.ip();
+        let wait = self.rate_limiter.check(ip).await;
+        if let Some(wait) = wait {
+            warn!(
+                "Rate limit exceeded by {}, refused for {}",
+                ip,
+                humantime::format_duration(wait)
+            );
+        }
+        wait
+    }
+
     async fn reject_auth_attempts(
         &self,
         socket_addr: Option<std::net::SocketAddr>,
         username: String,
-    ) -> bool {
+    ) -> Option<Duration> {
         let mut res = false;
         if let Some(sa) = socket_addr {
             let ip = sa.ip();
@@ -562,6 +1712,10 @@ impl super::HandlerBackend for BastionServer {
                 && entry.value() > &self.config.max_ip_attempts
             {
                 warn!("Brute-force login detected from {}", ip);
+                // Only the attempt crossing the limit writes the ban
+                if *entry.value() == self.config.max_ip_attempts + 1 {
+                    self.auto_ban(ip, *entry.value()).await;
+                }
                 res = true;
             }
         }
@@ -574,13 +1728,18 @@ impl super::HandlerBackend for BastionServer {
             res = true;
         }
 
-        res
+        // Counters idle out, every refused attempt restarts the full ban
+        res.then_some(self.config.unban_duration)
     }
 
     fn db_repository(&self) -> &dyn DatabaseRepository {
         self.database.repository()
     }
 
+    fn db_service(&self) -> &DatabaseService {
+        &self.database
+    }
+
     async fn enforce(
         &self,
         sub: Uuid,
@@ -588,59 +1747,138 @@ impl super::HandlerBackend for BastionServer {
         act: Uuid,
         ext: casbin::ExtendPolicyReq,
     ) -> Result<bool, Error> {
-        // match sub
+        Ok(self.enforce_policy(sub, obj, act, ext).await?.is_some())
+    }
+
+    async fn enforce_policy(
+        &self,
+        sub: Uuid,
+        obj: Uuid,
+        act: Uuid,
+        ext: casbin::ExtendPolicyReq,
+    ) -> Result<Option<casbin::ExtendPolicy>, Error> {
+        let ext = self.locate(ext).with_action(act);
+        let Some(opa) = self.opa.as_ref() else {
+            return self.local_policy(sub, obj, act, &ext).await;
+        };
+
+        let input = super::opa::OpaInput {
+            user_id: sub,
+            object_id: obj,
+            action_id: act,
+            action: ext.action,
+            ip: ext.ip,
+            country: ext.country.as_deref(),
+            time: ext.now,
+            ticket: ext.ticket.as_deref(),
+        };
+        match opa.decide(&input).await {
+            super::opa::Decision::Allow => {
+                trace!("Accept by OPA, sub: {}, obj: {}, act: {}", sub, obj, act);
+                self.opa_allowed_policy(sub, obj, act, &ext).await
+            }
+            super::opa::Decision::Deny => {
+                trace!("Reject by OPA, sub: {}, obj: {}, act: {}", sub, obj, act);
+                Ok(None)
+            }
+            super::opa::Decision::Failed(e) => {
+                warn!(
+                    "OPA decision failed, sub: {}, obj: {}, act: {}: {}, falling back to {}",
+                    sub, obj, act, e, opa.fallback
+                );
+                match opa.fallback {
+                    OpaFallback::Local => self.local_policy(sub, obj, act, &ext).await,
+                    OpaFallback::Deny => Ok(None),
+                }
+            }
+        }
+    }
+
+    async fn session_quota(
+        &self,
+        sub: Uuid,
+        obj: Uuid,
+        act: Uuid,
+        ext: casbin::ExtendPolicyReq,
+    ) -> Result<Option<QuotaUsage>, Error> {
+        let candidates = self.policy_candidates(sub, obj, act).await?;
+        let ext = self.locate(ext).with_action(act);
+        for pol in &candidates.grants {
+            if let Some(matched) = casbin::match_extend_policy(&ext, &pol.v3)? {
+                return match matched.quota {
+                    Some(quota) => Ok(Some(self.quota_usage(sub, pol, quota, ext.now).await?)),
+                    None => Ok(None),
+                };
+            }
+        }
+        Ok(None)
+    }
+
+    async fn list_session_quotas(&self, user_id: &Uuid) -> Result<Vec<QuotaUsage>, Error> {
         let policies = self
             .database
             .repository()
             .list_casbin_rules_by_ptype("p")
             .await?;
-        let allowed_policies = self.role_manager.read().await.match_sub(policies, sub);
-        trace!("sub: {} polices: {:?}", sub, allowed_policies);
-
-        for pol in allowed_policies {
-            // match obj
-            if pol.v1 == obj
-                || self
-                    .role_manager
-                    .read()
-                    .await
-                    .match_role(pol.v1, obj, casbin::GroupType::Object)
-            {
-                if !self.database.repository().check_object_active(&obj).await? {
-                    trace!(
-                        "Reject due to object not active, sub: {}, act: {}, policy: {:?}",
-                        sub, obj, pol
-                    );
-                    continue;
-                }
-                // match act
-                if pol.v2 == act
-                    || self.role_manager.read().await.match_role(
-                        pol.v2,
-                        act,
-                        casbin::GroupType::Action,
-                    )
-                {
-                    // match ext
-                    if casbin::verify_extend_policy(&ext, &pol.v3)? {
-                        trace!("Accept sub: {}, policy: {:?}", sub, pol);
-                        return Ok(true);
-                    }
-                } else {
-                    trace!(
-                        "Reject by action, sub: {}, act: {}, policy: {:?}",
-                        sub, act, pol
-                    );
-                }
-            } else {
-                trace!(
-                    "Reject by object, sub: {}, obj: {}, policy: {:?}",
-                    sub, obj, pol
-                );
+        let policies = self.role_manager.read().await.match_sub(policies, *user_id);
+        let now = chrono::Utc::now();
+        let mut res = Vec::new();
+        for pol in policies {
+            let Ok(ext) = pol.v3.parse::<casbin::ExtendPolicy>() else {
+                continue;
+            };
+            if ext.pending {
+                continue;
+            }
+            if let Some(quota) = ext.policies().find_map(|p| p.quota) {
+                res.push(self.quota_usage(*user_id, &pol, quota, now).await?);
             }
         }
+        Ok(res)
+    }
 
-        Ok(false)
+    async fn maintenance(
+        &self,
+        user_id: Uuid,
+        target_id: Uuid,
+        ip: Option<std::net::IpAddr>,
+    ) -> Result<Option<(models::MaintenanceWindow, models::Timestamp)>, Error> {
+        let repo = self.database.repository();
+        let now = models::Timestamp::now();
+        let active: Vec<_> = repo
+            .list_maintenance_windows()
+            .await?
+            .into_iter()
+            .filter_map(|w| w.active_until(now).map(|until| (w, until)))
+            .collect();
+        if active.is_empty() {
+            return Ok(None);
+        }
+        let tags = repo.list_tags_for_target(&target_id).await?;
+        let Some(window) = active
+            .into_iter()
+            .filter(|(w, _)| w.covers(target_id, &tags))
+            .max_by_key(|(_, until)| *until)
+        else {
+            return Ok(None);
+        };
+        let uuids = crate::database::common::InternalUuids::get();
+        if self
+            .enforce(
+                user_id,
+                uuids.obj_admin,
+                uuids.act_login,
+                casbin::ExtendPolicyReq::new(ip),
+            )
+            .await?
+        {
+            return Ok(None);
+        }
+        Ok(Some(window))
+    }
+
+    fn database_available(&self) -> bool {
+        self.database_healthy.load(Ordering::Relaxed)
     }
 
     fn enable_record(&self) -> bool {
@@ -651,33 +1889,108 @@ impl super::HandlerBackend for BastionServer {
         self.config.record_input
     }
 
+    fn default_term_size(&self) -> (u32, u32) {
+        (self.config.default_term_cols, self.config.default_term_rows)
+    }
+
     fn record_path(&self) -> &str {
         &self.config.record_path
     }
 
+    fn record_files(&self) -> &super::record_files::RecordFiles {
+        &self.record_files
+    }
+
+    fn record_s3(&self) -> Option<Arc<crate::asciinema::S3Store>> {
+        self.record_s3.clone()
+    }
+
+    fn pool_leases(&self) -> Option<&super::connection_pool::Leases> {
+        self.pool_leases.as_deref()
+    }
+
+    fn live_sessions(&self) -> &super::live_sessions::LiveSessions {
+        &self.live_sessions
+    }
+
+    fn reconnect_tokens(&self) -> Option<&super::reconnect::ReconnectTokens> {
+        self.reconnect_tokens.as_deref()
+    }
+
+    fn remote_forwards(&self) -> Arc<super::remote_forward::RemoteForwards> {
+        self.remote_forwards.clone()
+    }
+
+    fn password_max_age(&self) -> Option<Duration> {
+        self.config.password_max_age
+    }
+
+    fn idle_session_timeout(&self) -> Option<Duration> {
+        self.config.idle_session_timeout
+    }
+
+    fn target_keepalive(&self) -> Option<Duration> {
+        self.config.target_connect.keepalive()
+    }
+
+    fn shell_reconnect_window(&self) -> Option<Duration> {
+        self.config.target_connect.reconnect()
+    }
+
+    fn zmodem_policy(&self) -> super::zmodem::ZmodemPolicy {
+        self.config.zmodem
+    }
+
+    fn rate_limit(&self) -> super::throttle::Rate {
+        super::throttle::Rate {
+            up: self.config.limits.upload_rate,
+            down: self.config.limits.download_rate,
+        }
+    }
+
+    fn breach_filter(&self) -> Option<Arc<super::breach::BreachFilter>> {
+        self.breach_filter.clone()
+    }
+
+    fn trace_path(&self) -> &str {
+        &self.config.trace_path
+    }
+
+    async fn trace_until(
+        &self,
+        username: &str,
+        ip: Option<std::net::IpAddr>,
+    ) -> Option<models::Timestamp> {
+        let rules = match self.database.repository().list_trace_rules().await {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("Failed to load trace rules: {}", e);
+                return None;
+            }
+        };
+        let now = models::Timestamp::now();
+        rules
+            .into_iter()
+            .filter(|r| r.matches(username, ip, now))
+            .map(|r| r.expires_at)
+            .max()
+    }
+
     async fn load_role_manager(&self) -> Result<(), Error> {
         self.do_load_role_manager().await
     }
 
-    fn encrypt_plain_text(&self) -> crate::common::EncryptPlainText {
-        let secret_key = self.secret_key.clone();
-        Box::new(move |text: &str| -> Result<String, Error> {
-            let mut nonce_bytes = [0u8; 12];
-            OsRng.fill_bytes(&mut nonce_bytes);
-            let nonce = Nonce::from_slice(&nonce_bytes);
-
-            let ciphertext = secret_key.encrypt(nonce, text.as_bytes()).map_err(|e| {
-                Error::Server(ServerError::EncryptionFailed {
-                    reason: e.to_string(),
-                })
-            })?;
+    fn invalidate_policy_cache(&self) {
+        self.do_invalidate_policy_cache()
+    }
 
-            let mut blob = Vec::with_capacity(nonce_bytes.len() + ciphertext.len());
-            blob.extend_from_slice(&nonce_bytes);
-            blob.extend_from_slice(&ciphertext);
+    async fn load_bans(&self) -> Result<(), Error> {
+        self.do_load_bans().await
+    }
 
-            Ok(general_purpose::STANDARD.encode(blob))
-        })
+    fn encrypt_plain_text(&self) -> crate::common::EncryptPlainText {
+        let secret_key = self.secret_key.clone();
+        Box::new(move |text: &str| crate::common::encrypt_with_key(&secret_key, text))
     }
 
     async fn get_graph(&self, rt: casbin::GroupType) -> StableDiGraph<casbin::RuleGroup, ()> {
@@ -0,0 +1,153 @@
+//! Bloom filter of breached password hashes, built offline from the Have I
+//! Been Pwned SHA-1 dump with `rustion breach-filter`. New passwords found in
+//! it are refused. A false positive only costs the user another pick, and
+//! the filter never holds anything that would reveal a password.
+
+use sha1::{Digest, Sha1};
+use std::io::{BufRead, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"RBF1";
+
+pub struct BreachFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    hashes: u32,
+}
+
+impl BreachFilter {
+    /// Sized for `items` hashes at the false positive rate `fp_rate`
+    pub fn new(items: u64, fp_rate: f64) -> Self {
+        let items = items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-(items * fp_rate.ln()) / (ln2 * ln2)).ceil().max(1024.0) as u64;
+        let hashes = ((num_bits as f64 / items) * ln2).round().clamp(1.0, 32.0) as u32;
+        BreachFilter {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            hashes,
+        }
+    }
+
+    /// SHA-1 is already uniform, two halves of it make every probe
+    fn probes(&self, digest: &[u8; 20]) -> impl Iterator<Item = u64> + '_ {
+        let h1 = u64::from_le_bytes(digest[0..8].try_into().unwrap_or_default());
+        let h2 = u64::from_le_bytes(digest[8..16].try_into().unwrap_or_default()) | 1;
+        (0..self.hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+    }
+
+    pub fn insert(&mut self, digest: &[u8; 20]) {
+        let probes: Vec<u64> = self.probes(digest).collect();
+        for bit in probes {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn contains(&self, digest: &[u8; 20]) -> bool {
+        self.probes(digest)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    pub fn contains_password(&self, password: &str) -> bool {
+        self.contains(&Sha1::digest(password.as_bytes()).into())
+    }
+
+    /// Reads the HIBP dump, one `HASH:count` line per password, or bare
+    /// hashes. Lines that are not a SHA-1 are skipped and counted.
+    pub fn from_hibp<R: BufRead>(
+        reader: R,
+        items: u64,
+        fp_rate: f64,
+    ) -> std::io::Result<(Self, u64)> {
+        let mut filter = BreachFilter::new(items, fp_rate);
+        let mut skipped = 0;
+        for line in reader.lines() {
+            let line = line?;
+            let hash = line.split(':').next().unwrap_or_default().trim();
+            match data_encoding::HEXUPPER_PERMISSIVE.decode(hash.as_bytes()) {
+                Ok(d) if d.len() == 20 => filter.insert(&d.try_into().unwrap_or([0; 20])),
+                _ => skipped += 1,
+            }
+        }
+        Ok((filter, skipped))
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut header = [0u8; 16];
+        file.read_exact(&mut header)?;
+        if &header[0..4] != MAGIC {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "not a breached password filter, build one with `rustion breach-filter`",
+            ));
+        }
+        let hashes = u32::from_le_bytes(header[4..8].try_into().unwrap_or_default());
+        let num_bits = u64::from_le_bytes(header[8..16].try_into().unwrap_or_default());
+        if hashes == 0 || num_bits == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "breached password filter is empty",
+            ));
+        }
+        let mut raw = Vec::new();
+        file.read_to_end(&mut raw)?;
+        if raw.len() as u64 != num_bits.div_ceil(64) * 8 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "breached password filter is truncated",
+            ));
+        }
+        let bits = raw
+            .chunks_exact(8)
+            .map(|c| u64::from_le_bytes(c.try_into().unwrap_or_default()))
+            .collect();
+        Ok(BreachFilter {
+            bits,
+            num_bits,
+            hashes,
+        })
+    }
+
+    pub fn save<W: Write>(&self, mut out: W) -> std::io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&self.hashes.to_le_bytes())?;
+        out.write_all(&self.num_bits.to_le_bytes())?;
+        for word in &self.bits {
+            out.write_all(&word.to_le_bytes())?;
+        }
+        out.flush()
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        self.bits.len() * 8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breach_filter() {
+        // SHA-1 of "password" and "P@ssw0rd", as listed in the HIBP dump
+        let dump = "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\n\
+                    21BD12DC183F740EE76F27B78EB39C8AD972A757:52579\n\
+                    not a hash\n";
+        let (filter, skipped) = BreachFilter::from_hibp(dump.as_bytes(), 2, 0.001).unwrap();
+        assert_eq!(skipped, 1);
+        assert!(filter.contains_password("password"));
+        assert!(filter.contains_password("P@ssw0rd"));
+        assert!(!filter.contains_password("Str0ng&P@ssw0rd"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("breached.bloom");
+        filter.save(std::fs::File::create(&path).unwrap()).unwrap();
+        let loaded = BreachFilter::load(&path).unwrap();
+        assert!(loaded.contains_password("password"));
+        assert!(!loaded.contains_password("Str0ng&P@ssw0rd"));
+
+        std::fs::write(&path, b"5BAA61E4C9B93F3F").unwrap();
+        assert!(BreachFilter::load(&path).is_err());
+    }
+}
@@ -1,6 +1,24 @@
+//! RBAC engine after Casbin's model. `p` rules grant a subject (user, role
+//! or user group) an action on an object (binding or group of bindings);
+//! `p_deny` rules are checked first and override them. `g1` (user→role),
+//! `g2` (binding→group, `tag:key=value` groups resolved from target tags)
+//! and `g3` (action→group) are `petgraph` graphs walked by `match_role`.
+//! Members of `user_groups` join g1 when the role manager is built, they
+//! are never stored as rules.
+//!
+//! A rule's ext is an `ExtendPolicy`: address, time window and expiry
+//! limits plus the flags documented on its fields. `;<action>=<ext>`
+//! segments give a built-in action its own ext, and
+//! `ExtendPolicy::deadline` bounds the channels a rule grants.
+//! `RoleManage::explain` backs `rustion enforce`.
+
 use crate::database::Uuid;
+use crate::database::common::{INTERNAL_ACTIONS, InternalUuids};
 use crate::error::Error;
 use crate::server::error::{ExtendPolicyParseError, ServerError};
+use crate::server::session_quota::SessionQuota;
+use crate::server::throttle::Rate;
+use crate::server::zmodem::ZmodemPolicy;
 use ipnetwork::IpNetwork;
 use log::trace;
 use std::fmt;
@@ -13,9 +31,10 @@ use {
     std::collections::HashMap,
 };
 
-use chrono::{DateTime, FixedOffset, NaiveTime, Utc};
+use chrono::{DateTime, FixedOffset, NaiveTime, TimeDelta, Utc};
 use serde::{Deserialize, Deserializer, Serialize, Serializer, ser};
 use std::str::FromStr;
+use std::time::Duration;
 
 pub struct RoleManage {
     h1: HashMap<Uuid, NodeIndex>,
@@ -97,6 +116,33 @@ impl RuleGroup {
     }
 }
 
+/// Outcome of one policy in `RoleManage::explain`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyCheck {
+    Granted,
+    /// A `p_deny` rule covering the request, it overrides every grant
+    Denied,
+    /// The object is neither the request's nor a group holding it
+    Object,
+    /// The action is neither the request's nor a group holding it
+    Action,
+    /// The ip, country, time, expiry, ticket or pending condition of `ext`
+    /// failed
+    Extend,
+}
+
+impl fmt::Display for PolicyCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Granted => write!(f, "granted"),
+            Self::Denied => write!(f, "denied"),
+            Self::Object => write!(f, "object mismatch"),
+            Self::Action => write!(f, "action mismatch"),
+            Self::Extend => write!(f, "conditions not met"),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Type {
     Object,
@@ -187,6 +233,81 @@ impl RoleManage {
             .collect()
     }
 
+    /// The rules of `sub` about `obj` and `act`, in their order. Only their
+    /// ext is left to check against a request.
+    pub fn covering(
+        &self,
+        policies: Vec<CasbinRule>,
+        sub: Uuid,
+        obj: Uuid,
+        act: Uuid,
+    ) -> Vec<CasbinRule> {
+        self.match_sub(policies, sub)
+            .into_iter()
+            .filter(|pol| self.match_object_action(pol, obj, act))
+            .collect()
+    }
+
+    /// First `p_deny` rule of `sub` covering the request. Deny rules win
+    /// over any grant; the ip, time window and expire date of their ext
+    /// limit where and when they apply, other flags are ignored.
+    pub fn match_deny(
+        &self,
+        denies: Vec<CasbinRule>,
+        sub: Uuid,
+        obj: Uuid,
+        act: Uuid,
+        ext: &ExtendPolicyReq,
+    ) -> Result<Option<CasbinRule>, Error> {
+        for pol in self.covering(denies, sub, obj, act) {
+            if deny_applies(ext, &pol.v3)? {
+                return Ok(Some(pol));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Every `p` and `p_deny` rule of `sub` checked against a request. The
+    /// request is refused when one is `Denied`, otherwise the first
+    /// `Granted` one is the policy the server uses. Whether the object is
+    /// active is left to the caller.
+    pub fn explain(
+        &self,
+        policies: Vec<CasbinRule>,
+        sub: Uuid,
+        obj: Uuid,
+        act: Uuid,
+        ext: &ExtendPolicyReq,
+    ) -> Result<Vec<(CasbinRule, PolicyCheck)>, Error> {
+        self.match_sub(policies, sub)
+            .into_iter()
+            .map(|pol| {
+                let check = if pol.v1 != obj && !self.match_role(pol.v1, obj, GroupType::Object) {
+                    PolicyCheck::Object
+                } else if pol.v2 != act && !self.match_role(pol.v2, act, GroupType::Action) {
+                    PolicyCheck::Action
+                } else if pol.ptype == "p_deny" {
+                    if deny_applies(ext, &pol.v3)? {
+                        PolicyCheck::Denied
+                    } else {
+                        PolicyCheck::Extend
+                    }
+                } else if match_extend_policy(ext, &pol.v3)?.is_none() {
+                    PolicyCheck::Extend
+                } else {
+                    PolicyCheck::Granted
+                };
+                Ok((pol, check))
+            })
+            .collect()
+    }
+
+    /// Whether `pol` is about `obj` and `act`, directly or through groups
+    fn match_object_action(&self, pol: &CasbinRule, obj: Uuid, act: Uuid) -> bool {
+        (pol.v1 == obj || self.match_role(pol.v1, obj, GroupType::Object))
+            && (pol.v2 == act || self.match_role(pol.v2, act, GroupType::Action))
+    }
+
     pub fn fetch_role_from_start(&self, start: Uuid, rt: GroupType) -> Vec<Uuid> {
         match rt {
             GroupType::Subject => {
@@ -290,13 +411,57 @@ impl RoleManage {
         }
     }
 }
-/// This is used for p.ext
-#[derive(Debug, PartialEq)]
+/// Internal action names without this prefix key the per-action ext
+const ACTION_KEY_PREFIX: &str = "__internal_action_";
+
+/// This is used for p.ext. `;<action>=<ext>` segments after the ext give
+/// an action its own policy in place of this one, e.g. `;shell=,09:00
+/// +0000,17:00 +0000` keeps shells to office hours and leaves exec free.
+#[derive(Debug, Default, PartialEq)]
 pub struct ExtendPolicy {
     pub ip_policy: Option<IpPolicy>,
     pub start_time: Option<DateTime<FixedOffset>>,
     pub end_time: Option<DateTime<FixedOffset>>,
     pub expire_date: Option<DateTime<FixedOffset>>,
+    /// Require the session to carry a change/ticket reference
+    pub require_ticket: bool,
+    /// Ask for a free-text access reason before an interactive shell, and
+    /// refuse non-interactive requests that can't answer it
+    pub require_reason: bool,
+    /// Suppress bastion notices on exec and direct-tcpip sessions
+    pub quiet: bool,
+    /// Let `user@account@target` log into any account on the target with the
+    /// bound secret, not just the secret's own user
+    pub any_user: bool,
+    /// `cmd=<name>` picks the `command_filters` entry checking exec commands
+    /// and shell input
+    pub command_filter: Option<String>,
+    /// `max_session=<duration>` closes a target session this long after it
+    /// started, whatever the time left on the policy
+    pub max_session: Option<Duration>,
+    /// `idle=<duration>` warns a target session that long without input or
+    /// output, in place of `idle_session_timeout`
+    pub idle: Option<Duration>,
+    /// `idle_grace=<duration>` is how long a warned idle session has left
+    pub idle_grace: Option<Duration>,
+    /// `quota=<n>/<day|week>` caps the sessions opened under this policy
+    pub quota: Option<SessionQuota>,
+    /// `geo=DE,NL` limits the policy to clients located in these countries,
+    /// empty for anywhere
+    pub geo: Vec<String>,
+    /// `ports=8000-8099` limits the ports reached by direct-tcpip and those
+    /// the target listens on for remote forwards, a single port works too
+    pub ports: Option<(u16, u16)>,
+    /// `rate=1M/256K` caps each session's bytes per second to and from the
+    /// target, one value sets both
+    pub rate: Option<Rate>,
+    /// `zmodem=allow|log|block` decides on `rz`/`sz` transfers in shells, in
+    /// place of the `zmodem` setting
+    pub zmodem: Option<ZmodemPolicy>,
+    /// Written by an access request, grants nothing until an admin approves
+    pub pending: bool,
+    /// Policies of the keyed actions, by internal action name
+    pub actions: Vec<(&'static str, ExtendPolicy)>,
 }
 
 /// This is used for r.ext
@@ -304,6 +469,12 @@ pub struct ExtendPolicy {
 pub struct ExtendPolicyReq {
     pub ip: Option<IpAddr>,
     pub now: DateTime<Utc>,
+    pub ticket: Option<String>,
+    /// Internal name of the requested action, picks its keyed ext
+    pub action: Option<&'static str>,
+    /// ISO country code of `ip`, filled in by the server from
+    /// `geoip_database`
+    pub country: Option<String>,
 }
 
 impl Default for ExtendPolicyReq {
@@ -311,6 +482,9 @@ impl Default for ExtendPolicyReq {
         ExtendPolicyReq {
             ip: None,
             now: Utc::now(),
+            ticket: None,
+            action: None,
+            country: None,
         }
     }
 }
@@ -320,8 +494,30 @@ impl ExtendPolicyReq {
         ExtendPolicyReq {
             ip,
             now: Utc::now(),
+            ticket: None,
+            action: None,
+            country: None,
         }
     }
+
+    pub fn with_ticket(mut self, val: Option<String>) -> Self {
+        self.ticket = val;
+        self
+    }
+
+    pub fn with_country(mut self, val: Option<String>) -> Self {
+        self.country = val;
+        self
+    }
+
+    /// Custom actions have no key, their rules are checked against the ext
+    /// as a whole
+    pub fn with_action(mut self, act: Uuid) -> Self {
+        self.action = InternalUuids::is_initialized()
+            .then(|| InternalUuids::get().action_name(act))
+            .flatten();
+        self
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -331,20 +527,84 @@ pub enum IpPolicy {
 }
 
 pub fn verify_extend_policy(ext_req: &ExtendPolicyReq, ext_str: &str) -> Result<bool, Error> {
+    Ok(match_extend_policy(ext_req, ext_str)?.is_some())
+}
+
+/// Like `verify_extend_policy`, but hands back the parsed policy on a match
+/// so callers can honour its flags.
+pub fn match_extend_policy(
+    ext_req: &ExtendPolicyReq,
+    ext_str: &str,
+) -> Result<Option<ExtendPolicy>, Error> {
     trace!("ext_req: {:?} ext_str: \"{}\"", ext_req, ext_str);
     let ext: ExtendPolicy = ext_str.parse().map_err(ServerError::ExtendPolicyParse)?;
-    if !is_ip_in_cidr(ext_req.ip, ext.ip_policy) {
-        return Ok(false);
+    let ext = ext.into_action(ext_req.action);
+    if ext.pending || !in_scope(ext_req, &ext) {
+        return Ok(None);
     }
-    if !is_in_period(ext_req.now, ext.start_time, ext.end_time) {
-        return Ok(false);
+    if ext.require_ticket && ext_req.ticket.as_ref().is_none_or(|t| t.is_empty()) {
+        return Ok(None);
     }
-    if let Some(ep) = ext.expire_date
-        && ext_req.now >= ep
-    {
-        return Ok(false);
+    Ok(Some(ext))
+}
+
+/// Whether the request comes from the ip range and countries and falls in
+/// the time window and validity of `ext`
+fn in_scope(ext_req: &ExtendPolicyReq, ext: &ExtendPolicy) -> bool {
+    is_in_countries(ext_req.country.as_deref(), &ext.geo) && in_window(ext_req, ext)
+}
+
+/// `in_scope` but for the countries
+fn in_window(ext_req: &ExtendPolicyReq, ext: &ExtendPolicy) -> bool {
+    is_ip_in_cidr(ext_req.ip, ext.ip_policy)
+        && is_in_period(ext_req.now, ext.start_time, ext.end_time)
+        && ext.expire_date.is_none_or(|ep| ext_req.now < ep)
+}
+
+/// Whether a `p_deny` rule with `ext_str` applies to the request. A client
+/// of unknown country falls under a `geo=` deny, as it would be refused by
+/// a `geo=` grant.
+pub fn deny_applies(ext_req: &ExtendPolicyReq, ext_str: &str) -> Result<bool, Error> {
+    let ext: ExtendPolicy = ext_str.parse().map_err(ServerError::ExtendPolicyParse)?;
+    let ext = ext.into_action(ext_req.action);
+    let country =
+        ext_req.country.is_none() || is_in_countries(ext_req.country.as_deref(), &ext.geo);
+    Ok(country && in_window(ext_req, &ext))
+}
+
+impl ExtendPolicy {
+    /// The policy keyed for `action`, or this one
+    pub fn into_action(mut self, action: Option<&str>) -> ExtendPolicy {
+        match self.actions.iter().position(|(a, _)| Some(*a) == action) {
+            Some(i) => self.actions.swap_remove(i).1,
+            None => self,
+        }
+    }
+
+    /// This policy and those keyed for an action
+    pub fn policies(&self) -> impl Iterator<Item = &ExtendPolicy> {
+        std::iter::once(self).chain(self.actions.iter().map(|(_, p)| p))
+    }
+
+    /// When access granted at `now` runs out: the expire date or the end of
+    /// the current time window, whichever comes first. `None` for policies
+    /// without either.
+    pub fn deadline(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let window_end = match (self.start_time, self.end_time) {
+            (Some(_), Some(e)) => {
+                let e = e.with_timezone(&Utc);
+                // `e` carries the date it was parsed on, the window may close
+                // tomorrow when it wraps around midnight
+                Some(if e > now { e } else { e + TimeDelta::days(1) })
+            }
+            _ => None,
+        };
+        let expire = self.expire_date.map(|e| e.with_timezone(&Utc));
+        match (window_end, expire) {
+            (Some(w), Some(e)) => Some(w.min(e)),
+            (w, e) => w.or(e),
+        }
     }
-    Ok(true)
 }
 
 impl fmt::Display for ExtendPolicy {
@@ -375,8 +635,57 @@ impl fmt::Display for ExtendPolicy {
         } else {
             parts.push("".to_string());
         }
+        if self.require_ticket {
+            parts.push("ticket".to_string());
+        }
+        if self.require_reason {
+            parts.push("reason".to_string());
+        }
+        if self.quiet {
+            parts.push("quiet".to_string());
+        }
+        if self.any_user {
+            parts.push("anyuser".to_string());
+        }
+        if let Some(name) = &self.command_filter {
+            parts.push(format!("cmd={}", name));
+        }
+        if let Some(max) = self.max_session {
+            parts.push(format!("max_session={}", humantime::format_duration(max)));
+        }
+        if let Some(idle) = self.idle {
+            parts.push(format!("idle={}", humantime::format_duration(idle)));
+        }
+        if let Some(grace) = self.idle_grace {
+            parts.push(format!("idle_grace={}", humantime::format_duration(grace)));
+        }
+        if let Some(quota) = &self.quota {
+            parts.push(format!("quota={}", quota));
+        }
+        if !self.geo.is_empty() {
+            parts.push(format!("geo={}", self.geo.join(",")));
+        }
+        match self.ports {
+            Some((from, to)) if from == to => parts.push(format!("ports={}", from)),
+            Some((from, to)) => parts.push(format!("ports={}-{}", from, to)),
+            None => {}
+        }
+        if let Some(rate) = self.rate {
+            parts.push(format!("rate={}", rate));
+        }
+        if let Some(zmodem) = self.zmodem {
+            parts.push(format!("zmodem={}", zmodem));
+        }
+        if self.pending {
+            parts.push("pending".to_string());
+        }
 
-        write!(f, "{}", parts.join(","))
+        write!(f, "{}", parts.join(","))?;
+        for (action, policy) in &self.actions {
+            let key = action.strip_prefix(ACTION_KEY_PREFIX).unwrap_or(action);
+            write!(f, ";{}={}", key, policy)?;
+        }
+        Ok(())
     }
 }
 
@@ -384,77 +693,218 @@ impl FromStr for ExtendPolicy {
     type Err = ExtendPolicyParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let parts: Vec<&str> = s.split(',').collect();
-
-        let ip_policy = if !parts.is_empty() && !parts[0].is_empty() {
-            if parts[0].starts_with('!') {
-                Some(IpPolicy::Deny(parts[0][1..].parse().map_err(
-                    |e: ipnetwork::IpNetworkError| {
-                        ExtendPolicyParseError::InvalidIpPolicy(e.to_string())
-                    },
-                )?))
-            } else {
-                Some(IpPolicy::Allow(parts[0].parse().map_err(
-                    |e: ipnetwork::IpNetworkError| {
-                        ExtendPolicyParseError::InvalidIpPolicy(e.to_string())
-                    },
-                )?))
-            }
-        } else {
-            None
-        };
+        let mut segments = s.split(';');
+        let mut policy = parse_policy(segments.next().unwrap_or_default())?;
+        for segment in segments {
+            let invalid = || ExtendPolicyParseError::InvalidActionKey {
+                key: segment.to_string(),
+            };
+            let (key, ext) = segment.split_once('=').ok_or_else(invalid)?;
+            let action = INTERNAL_ACTIONS
+                .into_iter()
+                .find(|a| a.strip_prefix(ACTION_KEY_PREFIX) == Some(key.trim()))
+                .filter(|a| policy.actions.iter().all(|(b, _)| b != a))
+                .ok_or_else(invalid)?;
+            policy.actions.push((action, parse_policy(ext)?));
+        }
+        Ok(policy)
+    }
+}
 
-        let start_time = if parts.len() > 1 && !parts[1].is_empty() {
-            Some(
-                parse_time(parts[1])
-                    .map_err(|e| ExtendPolicyParseError::InvalidTimeFormat(e.to_string()))?,
-            )
-        } else {
-            None
-        };
+/// One `;` separated segment of an ext
+fn parse_policy(s: &str) -> Result<ExtendPolicy, ExtendPolicyParseError> {
+    let parts: Vec<&str> = s.split(',').collect();
 
-        let end_time = if parts.len() > 2 && !parts[2].is_empty() {
-            Some(
-                parse_time(parts[2])
-                    .map_err(|e| ExtendPolicyParseError::InvalidTimeFormat(e.to_string()))?,
-            )
+    let ip_policy = if !parts.is_empty() && !parts[0].is_empty() {
+        if parts[0].starts_with('!') {
+            Some(IpPolicy::Deny(parts[0][1..].parse().map_err(|e| {
+                ExtendPolicyParseError::InvalidIpPolicy {
+                    input: parts[0].to_string(),
+                    source: e,
+                }
+            })?))
         } else {
-            None
-        };
-
-        // ensure start_time and end_time are consistent
-        match (&start_time, &end_time) {
-            (Some(_), None) | (None, Some(_)) => {
-                return Err(ExtendPolicyParseError::TimeConsistencyError(
-                    "start_time and end_time must both be present or both absent".into(),
-                ));
+            Some(IpPolicy::Allow(parts[0].parse().map_err(|e| {
+                ExtendPolicyParseError::InvalidIpPolicy {
+                    input: parts[0].to_string(),
+                    source: e,
+                }
+            })?))
+        }
+    } else {
+        None
+    };
+
+    let start_time = if parts.len() > 1 && !parts[1].is_empty() {
+        Some(
+            parse_time(parts[1]).map_err(|e| ExtendPolicyParseError::InvalidTimeFormat {
+                input: parts[1].to_string(),
+                source: e,
+            })?,
+        )
+    } else {
+        None
+    };
+
+    let end_time = if parts.len() > 2 && !parts[2].is_empty() {
+        Some(
+            parse_time(parts[2]).map_err(|e| ExtendPolicyParseError::InvalidTimeFormat {
+                input: parts[2].to_string(),
+                source: e,
+            })?,
+        )
+    } else {
+        None
+    };
+
+    // ensure start_time and end_time are consistent
+    match (&start_time, &end_time) {
+        (Some(_), None) | (None, Some(_)) => {
+            return Err(ExtendPolicyParseError::IncompleteTimeRange);
+        }
+        (Some(s), Some(e)) => {
+            if s.timezone() != e.timezone() {
+                return Err(ExtendPolicyParseError::TimezoneMismatch);
             }
-            (Some(s), Some(e)) => {
-                if s.timezone() != e.timezone() {
-                    return Err(ExtendPolicyParseError::TimeConsistencyError(
-                        "timezone of start_time and end_time must be equal".into(),
-                    ));
+        }
+        _ => {}
+    }
+
+    let expire_date = if parts.len() > 3 && !parts[3].is_empty() {
+        Some(
+            DateTime::parse_from_str(parts[3], "%Y-%m-%d %H:%M:%S %z").map_err(|e| {
+                ExtendPolicyParseError::InvalidExpireDateFormat {
+                    input: parts[3].to_string(),
+                    source: e,
+                }
+            })?,
+        )
+    } else {
+        None
+    };
+
+    // trailing flags, in any order
+    let mut require_ticket = false;
+    let mut require_reason = false;
+    let mut quiet = false;
+    let mut any_user = false;
+    let mut command_filter = None;
+    let mut max_session = None;
+    let mut idle = None;
+    let mut idle_grace = None;
+    let mut quota = None;
+    let mut geo = Vec::new();
+    let mut in_geo = false;
+    let mut ports = None;
+    let mut rate = None;
+    let mut zmodem = None;
+    for flag in parts.iter().skip(4).map(|p| p.trim()) {
+        // `geo=DE,NL` goes on over the parts following it
+        if in_geo && is_country_code(flag) {
+            geo.push(flag.to_string());
+            continue;
+        }
+        in_geo = false;
+        match flag {
+            "" => {}
+            f if f.starts_with("geo=") => {
+                let code = &f["geo=".len()..];
+                if !is_country_code(code) || !geo.is_empty() {
+                    return Err(ExtendPolicyParseError::InvalidCountry {
+                        input: f.to_string(),
+                    });
                 }
+                geo.push(code.to_string());
+                in_geo = true;
+            }
+            f if f.starts_with("cmd=") && f.len() > 4 => {
+                command_filter = Some(f[4..].to_string());
+            }
+            f if f.starts_with("max_session=") => {
+                let input = &f["max_session=".len()..];
+                max_session = Some(humantime::parse_duration(input).map_err(|e| {
+                    ExtendPolicyParseError::InvalidMaxSession {
+                        input: input.to_string(),
+                        source: e,
+                    }
+                })?);
+            }
+            f if f.starts_with("idle=") => {
+                idle = Some(parse_idle(&f["idle=".len()..])?);
+            }
+            f if f.starts_with("idle_grace=") => {
+                idle_grace = Some(parse_idle(&f["idle_grace=".len()..])?);
+            }
+            f if f.starts_with("ports=") => {
+                let input = &f["ports=".len()..];
+                ports = Some(parse_ports(input).ok_or_else(|| {
+                    ExtendPolicyParseError::InvalidPorts {
+                        input: input.to_string(),
+                    }
+                })?);
+            }
+            f if f.starts_with("quota=") => {
+                quota = Some(f["quota=".len()..].parse()?);
+            }
+            f if f.starts_with("rate=") => {
+                rate = Some(f["rate=".len()..].parse()?);
+            }
+            f if f.starts_with("zmodem=") => {
+                zmodem = Some(f["zmodem=".len()..].parse()?);
+            }
+            "ticket" => require_ticket = true,
+            "reason" => require_reason = true,
+            "quiet" => quiet = true,
+            "anyuser" => any_user = true,
+            "pending" => pending = true,
+            p => {
+                return Err(ExtendPolicyParseError::InvalidFlag {
+                    flag: p.to_string(),
+                });
             }
-            _ => {}
         }
+    }
 
-        let expire_date = if parts.len() > 3 && !parts[3].is_empty() {
-            Some(
-                DateTime::parse_from_str(parts[3], "%Y-%m-%d %H:%M:%S %z")
-                    .map_err(|e| ExtendPolicyParseError::InvalidExpireDateFormat(e.to_string()))?,
-            )
-        } else {
-            None
-        };
+    Ok(ExtendPolicy {
+        ip_policy,
+        start_time,
+        end_time,
+        expire_date,
+        require_ticket,
+        require_reason,
+        quiet,
+        any_user,
+        command_filter,
+        max_session,
+        idle,
+        idle_grace,
+        quota,
+        geo,
+        ports,
+        rate,
+        zmodem,
+        pending,
+        actions: Vec::new(),
+    })
+}
 
-        Ok(ExtendPolicy {
-            ip_policy,
-            start_time,
-            end_time,
-            expire_date,
-        })
-    }
+fn parse_idle(input: &str) -> Result<Duration, ExtendPolicyParseError> {
+    humantime::parse_duration(input).map_err(|e| ExtendPolicyParseError::InvalidIdle {
+        input: input.to_string(),
+        source: e,
+    })
+}
+
+/// `8080` or `8000-8099`, the range not running backwards
+fn parse_ports(s: &str) -> Option<(u16, u16)> {
+    let (from, to) = s.split_once('-').unwrap_or((s, s));
+    let (from, to) = (from.parse().ok()?, to.parse().ok()?);
+    (from <= to).then_some((from, to))
+}
+
+/// Two upper case letters, as in `DE`
+fn is_country_code(s: &str) -> bool {
+    s.len() == 2 && s.bytes().all(|b| b.is_ascii_uppercase())
 }
 
 fn parse_time(time_str: &str) -> Result<DateTime<FixedOffset>, chrono::ParseError> {
@@ -521,6 +971,18 @@ pub fn is_in_period(
     }
 }
 
+/// Whether a client located in `country` may use a policy limited to
+/// `countries`. A client of unknown location only gets unlimited policies.
+pub fn is_in_countries(country: Option<&str>, countries: &[String]) -> bool {
+    countries.is_empty() || country.is_some_and(|c| countries.iter().any(|v| v == c))
+}
+
+/// Whether `port` falls in the `ports=` range of a policy, any port
+/// without one
+pub fn is_port_allowed(port: u32, ports: Option<(u16, u16)>) -> bool {
+    ports.is_none_or(|(from, to)| (u32::from(from)..=u32::from(to)).contains(&port))
+}
+
 /// Check if an IP address is within a CIDR range
 ///
 /// # Arguments
@@ -689,6 +1151,356 @@ mod tests {
         assert_eq!(policy.start_time, None);
         assert_eq!(policy.end_time, None);
         assert_eq!(policy.expire_date, None);
+        assert!(!policy.require_ticket);
+    }
+
+    #[test]
+    fn test_extend_policy_require_ticket() {
+        let input = "10.0.0.0/8,,,,ticket";
+        let policy: ExtendPolicy = input.parse().unwrap();
+        assert!(policy.require_ticket);
+        assert_eq!(policy.to_string(), input);
+        assert!(",,,,approval".parse::<ExtendPolicy>().is_err());
+
+        let ext = ",,,,ticket";
+        assert!(!verify_extend_policy(&ExtendPolicyReq::default(), ext).unwrap());
+        assert!(
+            !verify_extend_policy(
+                &ExtendPolicyReq::default().with_ticket(Some("".to_string())),
+                ext
+            )
+            .unwrap()
+        );
+        assert!(
+            verify_extend_policy(
+                &ExtendPolicyReq::default().with_ticket(Some("CHG-1234".to_string())),
+                ext
+            )
+            .unwrap()
+        );
+        assert!(
+            verify_extend_policy(
+                &ExtendPolicyReq::default().with_ticket(Some("CHG-1234".to_string())),
+                ",,,"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_extend_policy_quiet() {
+        let policy: ExtendPolicy = ",,,,quiet".parse().unwrap();
+        assert!(policy.quiet);
+        assert!(!policy.require_ticket);
+        assert_eq!(policy.to_string(), ",,,,quiet");
+
+        let policy: ExtendPolicy = ",,,,quiet,ticket".parse().unwrap();
+        assert!(policy.quiet && policy.require_ticket);
+        assert_eq!(policy.to_string(), ",,,,ticket,quiet");
+
+        let ext = match_extend_policy(&ExtendPolicyReq::default(), ",,,,quiet")
+            .unwrap()
+            .unwrap();
+        assert!(ext.quiet);
+        assert!(
+            match_extend_policy(&ExtendPolicyReq::default(), ",,,,ticket,quiet")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_extend_policy_require_reason() {
+        let policy: ExtendPolicy = ",,,,quiet,reason,ticket".parse().unwrap();
+        assert!(policy.require_reason && policy.require_ticket && policy.quiet);
+        assert_eq!(policy.to_string(), ",,,,ticket,reason,quiet");
+
+        // The reason is asked for after the policy matched
+        let ext = match_extend_policy(&ExtendPolicyReq::default(), ",,,,reason")
+            .unwrap()
+            .unwrap();
+        assert!(ext.require_reason);
+    }
+
+    #[test]
+    fn test_extend_policy_any_user() {
+        let policy: ExtendPolicy = ",,,,anyuser,quiet".parse().unwrap();
+        assert!(policy.any_user && policy.quiet);
+        assert_eq!(policy.to_string(), ",,,,quiet,anyuser");
+        assert!(!",,,,quiet".parse::<ExtendPolicy>().unwrap().any_user);
+    }
+
+    #[test]
+    fn test_extend_policy_command_filter() {
+        let policy: ExtendPolicy = ",,,,cmd=no-root,quiet".parse().unwrap();
+        assert_eq!(policy.command_filter.as_deref(), Some("no-root"));
+        assert_eq!(policy.to_string(), ",,,,quiet,cmd=no-root");
+        let policy: ExtendPolicy = ",,,,quiet".parse().unwrap();
+        assert!(policy.command_filter.is_none());
+        assert!(",,,,cmd=".parse::<ExtendPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_extend_policy_actions() {
+        use crate::database::common::{ACT_EXEC, ACT_SHELL};
+
+        let input = ",,,,quiet;shell=,09:00 +0000,17:00 +0000,";
+        let policy: ExtendPolicy = input.parse().unwrap();
+        assert_eq!(policy.actions.len(), 1);
+        assert_eq!(policy.actions[0].0, ACT_SHELL);
+        assert_eq!(policy.to_string(), input);
+
+        let evening = Utc::now()
+            .with_time(NaiveTime::from_hms_opt(20, 0, 0).unwrap())
+            .unwrap();
+        let at = |action| ExtendPolicyReq {
+            now: evening,
+            action,
+            ..Default::default()
+        };
+        let shell = match_extend_policy(&at(Some(ACT_SHELL)), input).unwrap();
+        assert!(shell.is_none());
+        let exec = match_extend_policy(&at(Some(ACT_EXEC)), input).unwrap();
+        assert!(exec.is_some_and(|p| p.quiet));
+        let custom = match_extend_policy(&at(None), input).unwrap();
+        assert!(custom.is_some());
+
+        for bad in [",,,;login", ",,,;ssh=", ",,,;exec=;exec="] {
+            assert!(bad.parse::<ExtendPolicy>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_extend_policy_session_limits() {
+        let policy: ExtendPolicy = ",,,,max_session=4h,quiet".parse().unwrap();
+        assert_eq!(policy.max_session, Some(Duration::from_secs(4 * 3600)));
+        assert_eq!(policy.to_string(), ",,,,quiet,max_session=4h");
+        assert!(",,,,max_session=4x".parse::<ExtendPolicy>().is_err());
+        assert!(",,,,max_session=".parse::<ExtendPolicy>().is_err());
+
+        let policy: ExtendPolicy = ",,,,quota=20/day".parse().unwrap();
+        assert_eq!(policy.quota.map(|q| q.max), Some(20));
+        assert_eq!(policy.to_string(), ",,,,quota=20/day");
+        assert!(",,,,quota=20".parse::<ExtendPolicy>().is_err());
+
+        let policy: ExtendPolicy = ",,,,rate=1M/256K".parse().unwrap();
+        assert_eq!(policy.rate.map(|r| r.down), Some(256 << 10));
+        assert_eq!(policy.to_string(), ",,,,rate=1M/256K");
+        assert!(",,,,rate=fast".parse::<ExtendPolicy>().is_err());
+
+        let policy: ExtendPolicy = ",,,,zmodem=block,quiet".parse().unwrap();
+        assert_eq!(policy.zmodem, Some(ZmodemPolicy::Block));
+        assert_eq!(policy.to_string(), ",,,,quiet,zmodem=block");
+        assert!(",,,,zmodem=deny".parse::<ExtendPolicy>().is_err());
+
+        let policy: ExtendPolicy = ",,,,idle_grace=2m,idle=30m".parse().unwrap();
+        assert_eq!(policy.idle, Some(Duration::from_secs(30 * 60)));
+        assert_eq!(policy.idle_grace, Some(Duration::from_secs(120)));
+        assert_eq!(policy.to_string(), ",,,,idle=30m,idle_grace=2m");
+        assert!(",,,,idle=soon".parse::<ExtendPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_extend_policy_geo() {
+        let policy: ExtendPolicy = ",,,,geo=DE,NL,quiet".parse().unwrap();
+        assert_eq!(policy.geo, ["DE", "NL"]);
+        assert!(policy.quiet);
+        assert_eq!(policy.to_string(), ",,,,quiet,geo=DE,NL");
+        for bad in [",,,,geo=", ",,,,geo=de", ",,,,geo=DE,geo=NL", ",,,,NL"] {
+            assert!(bad.parse::<ExtendPolicy>().is_err(), "{}", bad);
+        }
+
+        let from = |c: Option<&str>| ExtendPolicyReq::default().with_country(c.map(String::from));
+        let ext = ",,,,geo=DE,NL";
+        assert!(verify_extend_policy(&from(Some("NL")), ext).unwrap());
+        assert!(!verify_extend_policy(&from(Some("US")), ext).unwrap());
+        assert!(!verify_extend_policy(&from(None), ext).unwrap());
+        assert!(verify_extend_policy(&from(None), ",,,").unwrap());
+        assert!(deny_applies(&from(Some("US")), ",,,,geo=US").unwrap());
+        assert!(!deny_applies(&from(Some("NL")), ",,,,geo=US").unwrap());
+        // Without a country, as without `geoip_database`, a deny fails closed
+        assert!(deny_applies(&from(None), ",,,,geo=US").unwrap());
+        assert!(!deny_applies(&from(None), "10.0.0.0/8,,,,geo=US").unwrap());
+    }
+
+    #[test]
+    fn test_extend_policy_ports() {
+        let policy: ExtendPolicy = ",,,,ports=8000-8099,quiet".parse().unwrap();
+        assert_eq!(policy.ports, Some((8000, 8099)));
+        assert_eq!(policy.to_string(), ",,,,quiet,ports=8000-8099");
+        assert!(is_port_allowed(8000, policy.ports) && is_port_allowed(8099, policy.ports));
+        assert!(!is_port_allowed(8100, policy.ports) && !is_port_allowed(0, policy.ports));
+        let policy: ExtendPolicy = ",,,,ports=443".parse().unwrap();
+        assert_eq!(policy.to_string(), ",,,,ports=443");
+        assert!(is_port_allowed(22, None));
+        for bad in [",,,,ports=", ",,,,ports=9-1", ",,,,ports=70000"] {
+            assert!(bad.parse::<ExtendPolicy>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_extend_policy_pending() {
+        let approved = ",,,2099-01-01 00:00:00 +0000";
+        let ext = format!("{},pending", approved);
+        let policy: ExtendPolicy = ext.parse().unwrap();
+        assert!(policy.pending);
+        assert_eq!(policy.to_string(), ext);
+        assert!(!verify_extend_policy(&ExtendPolicyReq::default(), &ext).unwrap());
+        assert!(verify_extend_policy(&ExtendPolicyReq::default(), approved).unwrap());
+    }
+
+    #[test]
+    fn test_explain() {
+        let (alice, dev, web, servers) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        let (shell, exec) = (Uuid::new_v4(), Uuid::new_v4());
+        let member = |v0, v0_label: &str, v1, v1_label: &str| CasbinRuleGroup {
+            id: Uuid::new_v4(),
+            v0,
+            v0_object_label: None,
+            v0_group_label: Some(v0_label.to_string()),
+            v1,
+            v1_object_label: Some(v1_label.to_string()),
+            v1_group_label: None,
+        };
+        // alice is in dev, web is in servers
+        let rm = RoleManage::new(
+            &[member(dev, "dev", alice, "alice")],
+            &[member(servers, "servers", web, "web")],
+            &[],
+        )
+        .unwrap();
+        let rule = |v0, v1, v2, v3: &str| {
+            CasbinRule::new(
+                "p".to_string(),
+                v0,
+                v1,
+                v2,
+                v3.to_string(),
+                String::new(),
+                String::new(),
+                Uuid::nil(),
+            )
+        };
+        let policies = vec![
+            rule(Uuid::new_v4(), web, shell, ""),
+            rule(dev, Uuid::new_v4(), shell, ""),
+            rule(alice, web, exec, ""),
+            rule(dev, servers, shell, "10.0.0.0/8"),
+            rule(alice, servers, shell, ""),
+        ];
+
+        let req = ExtendPolicyReq::new(Some("192.168.1.1".parse().unwrap()));
+        let checks: Vec<_> = rm
+            .explain(policies.clone(), alice, web, shell, &req)
+            .unwrap()
+            .into_iter()
+            .map(|(_, c)| c)
+            .collect();
+        assert_eq!(
+            checks,
+            [
+                PolicyCheck::Object,
+                PolicyCheck::Action,
+                PolicyCheck::Extend,
+                PolicyCheck::Granted
+            ]
+        );
+
+        let req = ExtendPolicyReq::new(Some("10.1.2.3".parse().unwrap()));
+        let checks = rm
+            .explain(policies.clone(), alice, web, shell, &req)
+            .unwrap();
+        assert_eq!(checks[2].1, PolicyCheck::Granted);
+
+        // dev may not reach web from outside 10.0.0.0/8, whatever alice holds
+        let mut deny = rule(dev, web, shell, "!10.0.0.0/8");
+        deny.ptype = "p_deny".to_string();
+        let outside = ExtendPolicyReq::new(Some("192.168.1.1".parse().unwrap()));
+        let denied = rm
+            .match_deny(vec![deny.clone()], alice, web, shell, &outside)
+            .unwrap();
+        assert_eq!(denied.map(|p| p.id), Some(deny.id));
+        assert!(
+            rm.match_deny(vec![deny.clone()], alice, web, shell, &req)
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            rm.match_deny(vec![deny.clone()], alice, web, exec, &outside)
+                .unwrap()
+                .is_none()
+        );
+
+        let mut with_deny = vec![deny];
+        with_deny.extend(policies);
+        let checks = rm
+            .explain(with_deny.clone(), alice, web, shell, &outside)
+            .unwrap();
+        assert_eq!(checks[0].1, PolicyCheck::Denied);
+        assert_eq!(checks[4].1, PolicyCheck::Granted);
+        let checks = rm.explain(with_deny, alice, web, shell, &req).unwrap();
+        assert_eq!(checks[0].1, PolicyCheck::Extend);
+    }
+
+    #[test]
+    fn test_extend_policy_deadline() {
+        let offset = FixedOffset::east_opt(3 * 3600).unwrap();
+        let now = NaiveDate::from_ymd_opt(2000, 1, 1)
+            .unwrap()
+            .and_hms_opt(11, 30, 0)
+            .unwrap()
+            .and_utc();
+        let at = |h, m| {
+            now.with_timezone(&offset)
+                .with_time(NaiveTime::from_hms_opt(h, m, 0).unwrap())
+                .unwrap()
+        };
+        let mut policy: ExtendPolicy = ",,,".parse().unwrap();
+        assert_eq!(policy.deadline(now), None);
+
+        // 14:30 +0300 is 11:30 UTC, the window closes at 17:30 +0300 today
+        policy.start_time = Some(at(10, 30));
+        policy.end_time = Some(at(17, 30));
+        assert_eq!(policy.deadline(now), Some(at(17, 30).with_timezone(&Utc)));
+
+        // Overnight window, already past its end of today
+        policy.start_time = Some(at(14, 0));
+        policy.end_time = Some(at(2, 0));
+        assert_eq!(
+            policy.deadline(now),
+            Some(at(2, 0).with_timezone(&Utc) + TimeDelta::days(1))
+        );
+
+        // The expire date wins when it comes first
+        policy.expire_date = Some(at(16, 0));
+        assert_eq!(policy.deadline(now), Some(at(16, 0).with_timezone(&Utc)));
+        policy.start_time = None;
+        policy.end_time = None;
+        assert_eq!(policy.deadline(now), Some(at(16, 0).with_timezone(&Utc)));
+    }
+
+    #[test]
+    fn test_extend_policy_parse_error() {
+        let err = "10.0.0.0/33,,,".parse::<ExtendPolicy>().unwrap_err();
+        assert!(
+            matches!(err, ExtendPolicyParseError::InvalidIpPolicy { ref input, .. } if input == "10.0.0.0/33")
+        );
+        assert!(std::error::Error::source(&err).is_some());
+
+        let err = ",11:30 +0800,,".parse::<ExtendPolicy>().unwrap_err();
+        assert!(matches!(err, ExtendPolicyParseError::IncompleteTimeRange));
+
+        let err = ",,,,loud".parse::<ExtendPolicy>().unwrap_err();
+        assert!(matches!(err, ExtendPolicyParseError::InvalidFlag { ref flag } if flag == "loud"));
+
+        let err = crate::error::Error::from(ServerError::from(err));
+        assert_eq!(err.code(), crate::error::ErrorCode::InvalidInput);
+        assert_eq!(err.user_message(), "Invalid policy flag: loud");
     }
 
     #[test]
@@ -696,6 +1508,21 @@ mod tests {
         let offset = FixedOffset::east_opt(3 * 3600).unwrap();
         let ext = ExtendPolicy {
             ip_policy: Some(IpPolicy::Allow(IpNetwork::from_str("10.0.0.0/8").unwrap())),
+            require_ticket: false,
+            require_reason: false,
+            quiet: false,
+            any_user: false,
+            command_filter: None,
+            max_session: None,
+            idle: None,
+            idle_grace: None,
+            quota: None,
+            geo: Vec::new(),
+            ports: None,
+            rate: None,
+            zmodem: None,
+            actions: Vec::new(),
+            pending: false,
             start_time: Some(
                 Utc::now()
                     .with_timezone(&offset)
@@ -727,6 +1554,21 @@ mod tests {
 
         let ext = ExtendPolicy {
             ip_policy: Some(IpPolicy::Deny(IpNetwork::from_str("10.0.0.0/8").unwrap())),
+            require_ticket: false,
+            require_reason: false,
+            quiet: false,
+            any_user: false,
+            command_filter: None,
+            max_session: None,
+            idle: None,
+            idle_grace: None,
+            quota: None,
+            geo: Vec::new(),
+            ports: None,
+            rate: None,
+            zmodem: None,
+            actions: Vec::new(),
+            pending: false,
             start_time: None,
             end_time: None,
             expire_date: Some(
@@ -745,6 +1587,21 @@ mod tests {
 
         let ext = ExtendPolicy {
             ip_policy: None,
+            require_ticket: false,
+            require_reason: false,
+            quiet: false,
+            any_user: false,
+            command_filter: None,
+            max_session: None,
+            idle: None,
+            idle_grace: None,
+            quota: None,
+            geo: Vec::new(),
+            ports: None,
+            rate: None,
+            zmodem: None,
+            actions: Vec::new(),
+            pending: false,
             start_time: None,
             end_time: None,
             expire_date: Some(
@@ -763,6 +1620,21 @@ mod tests {
 
         let ext = ExtendPolicy {
             ip_policy: None,
+            require_ticket: false,
+            require_reason: false,
+            quiet: false,
+            any_user: false,
+            command_filter: None,
+            max_session: None,
+            idle: None,
+            idle_grace: None,
+            quota: None,
+            geo: Vec::new(),
+            ports: None,
+            rate: None,
+            zmodem: None,
+            actions: Vec::new(),
+            pending: false,
             start_time: Some(
                 Utc::now()
                     .with_timezone(&offset)
@@ -782,6 +1654,21 @@ mod tests {
 
         let ext = ExtendPolicy {
             ip_policy: None,
+            require_ticket: false,
+            require_reason: false,
+            quiet: false,
+            any_user: false,
+            command_filter: None,
+            max_session: None,
+            idle: None,
+            idle_grace: None,
+            quota: None,
+            geo: Vec::new(),
+            ports: None,
+            rate: None,
+            zmodem: None,
+            actions: Vec::new(),
+            pending: false,
             start_time: None,
             end_time: Some(
                 Utc::now()
@@ -0,0 +1,279 @@
+//! Commands a policy lets run. A policy with the `cmd=<name>` flag has every
+//! exec command, and every line typed at a shell, checked against the
+//! `command_filters` entry of that name.
+//!
+//! Exec commands are checked exactly. Shell lines are rebuilt from the
+//! keystrokes, so a line recalled from history or finished by completion in
+//! the remote shell is checked as far as it was typed.
+
+use crate::config::error::ConfigError;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Marks a pattern as a shell glob matching a whole command
+const GLOB_PREFIX: &str = "glob:";
+
+lazy_static! {
+    /// Where a command line holds further commands
+    static ref SEPARATORS: Regex = Regex::new(r"[;&|\n`]|\$\(").unwrap();
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandFilterConfig {
+    /// Referenced by the `cmd=<name>` policy flag
+    pub name: String,
+    /// When set, only commands matching one of these run
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Commands matching one of these never run
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+impl std::fmt::Display for CommandFilterConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}(allow={} deny={})",
+            self.name,
+            self.allow.len(),
+            self.deny.len()
+        )
+    }
+}
+
+struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    fn new(filter: &str, source: &str) -> Result<Self, ConfigError> {
+        let regex = match source.strip_prefix(GLOB_PREFIX) {
+            Some(glob) => Regex::new(&glob_to_regex(glob)),
+            None => Regex::new(source),
+        }
+        .map_err(|e| ConfigError::CommandFilterPattern {
+            name: filter.to_string(),
+            pattern: source.to_string(),
+            source: e,
+        })?;
+        Ok(Pattern {
+            source: source.to_string(),
+            regex,
+        })
+    }
+}
+
+/// `*` any run of characters, `?` any one, the rest literally
+fn glob_to_regex(glob: &str) -> String {
+    let mut out = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => out.push_str(".*"),
+            '?' => out.push('.'),
+            c => out.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    out.push('$');
+    out
+}
+
+pub struct CommandFilter {
+    allow: Vec<Pattern>,
+    deny: Vec<Pattern>,
+}
+
+impl CommandFilter {
+    /// Why `command` may not run, `None` when it may. Commands chained with
+    /// `;`, `&&`, `|` or substituted are checked one by one, each has to be
+    /// allowed.
+    pub fn check(&self, command: &str) -> Option<String> {
+        let command = command.trim();
+        let parts: Vec<&str> = SEPARATORS
+            .split(command)
+            .map(|p| p.trim().trim_end_matches(')').trim())
+            .filter(|p| !p.is_empty())
+            .collect();
+        for p in &self.deny {
+            if p.regex.is_match(command) || parts.iter().any(|c| p.regex.is_match(c)) {
+                return Some(format!("denied by '{}'", p.source));
+            }
+        }
+        if self.allow.is_empty() {
+            return None;
+        }
+        parts
+            .iter()
+            .find(|c| !self.allow.iter().any(|p| p.regex.is_match(c)))
+            .map(|c| format!("'{}' not allowed", c))
+    }
+}
+
+/// Every configured filter by name
+#[derive(Default)]
+pub struct CommandFilters {
+    filters: HashMap<String, Arc<CommandFilter>>,
+}
+
+impl CommandFilters {
+    pub fn new(configs: &[CommandFilterConfig]) -> Result<Self, ConfigError> {
+        let mut filters = HashMap::new();
+        for config in configs {
+            let name = config.name.as_str();
+            if name.is_empty() || name.contains(',') || filters.contains_key(name) {
+                return Err(ConfigError::CommandFilterName {
+                    name: name.to_string(),
+                });
+            }
+            let compile = |patterns: &[String]| {
+                patterns
+                    .iter()
+                    .map(|p| Pattern::new(name, p))
+                    .collect::<Result<Vec<_>, _>>()
+            };
+            let filter = CommandFilter {
+                allow: compile(&config.allow)?,
+                deny: compile(&config.deny)?,
+            };
+            filters.insert(name.to_string(), Arc::new(filter));
+        }
+        Ok(CommandFilters { filters })
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<CommandFilter>> {
+        self.filters.get(name).cloned()
+    }
+}
+
+#[derive(Default)]
+enum Escape {
+    #[default]
+    None,
+    Started,
+    /// `ESC [`, ends with a byte in `@..=~`
+    Csi,
+    /// `ESC O`, one more byte
+    Ss3,
+}
+
+/// Rebuilds the line typed at a shell prompt from the client's keystrokes
+#[derive(Default)]
+pub struct LineTracker {
+    line: Vec<u8>,
+    escape: Escape,
+}
+
+impl LineTracker {
+    /// Takes one byte from the client, returns the line it ends
+    pub fn push(&mut self, b: u8) -> Option<String> {
+        match std::mem::take(&mut self.escape) {
+            Escape::None => {}
+            Escape::Started => {
+                self.escape = match b {
+                    b'[' => Escape::Csi,
+                    b'O' => Escape::Ss3,
+                    _ => Escape::None,
+                };
+                return None;
+            }
+            Escape::Csi => {
+                if !(0x40..=0x7e).contains(&b) {
+                    self.escape = Escape::Csi;
+                }
+                return None;
+            }
+            Escape::Ss3 => return None,
+        }
+        match b {
+            b'\r' | b'\n' => {
+                let line = String::from_utf8_lossy(&self.line).into_owned();
+                self.line.clear();
+                return Some(line);
+            }
+            0x1b => self.escape = Escape::Started,
+            // Ctrl-C and Ctrl-U drop the line
+            0x03 | 0x15 => self.line.clear(),
+            // Ctrl-W, the word before the cursor
+            0x17 => {
+                while self.line.last() == Some(&b' ') {
+                    self.line.pop();
+                }
+                while self.line.last().is_some_and(|c| *c != b' ') {
+                    self.line.pop();
+                }
+            }
+            0x7f | 0x08 => {
+                // A whole UTF-8 character, not just its last byte
+                while let Some(c) = self.line.pop() {
+                    if c & 0xc0 != 0x80 {
+                        break;
+                    }
+                }
+            }
+            b if b < 0x20 => {}
+            b => self.line.push(b),
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(allow: &[&str], deny: &[&str]) -> Arc<CommandFilter> {
+        let config = CommandFilterConfig {
+            name: "test".to_string(),
+            allow: allow.iter().map(|s| s.to_string()).collect(),
+            deny: deny.iter().map(|s| s.to_string()).collect(),
+        };
+        CommandFilters::new(&[config]).unwrap().get("test").unwrap()
+    }
+
+    #[test]
+    fn test_command_filter() {
+        let deny = filter(&[], &[r"^sudo\s+su\b", "glob:rm -rf *"]);
+        assert!(deny.check("ls -l").is_none());
+        assert!(deny.check("sudo su -").is_some());
+        assert!(deny.check("rm -rf /").is_some());
+        assert!(deny.check("cd /tmp && rm -rf /").is_some());
+        assert!(deny.check("echo $(sudo su)").is_some());
+        assert!(deny.check("rm -r /tmp/x").is_none());
+
+        let allow = filter(&[r"^(ls|cat|tail)\b"], &[]);
+        assert!(allow.check("ls /var/log").is_none());
+        assert!(allow.check("cat a | tail -n 5").is_none());
+        assert!(allow.check("ls; reboot").is_some());
+        assert!(allow.check("").is_none());
+
+        let bad = CommandFilterConfig {
+            name: "bad".to_string(),
+            allow: vec!["(".to_string()],
+            deny: Vec::new(),
+        };
+        assert!(matches!(
+            CommandFilters::new(&[bad]),
+            Err(ConfigError::CommandFilterPattern { .. })
+        ));
+    }
+
+    #[test]
+    fn test_line_tracker() {
+        let mut tracker = LineTracker::default();
+        let mut lines = Vec::new();
+        // Typo fixed with backspace, an arrow key, then Ctrl-U on a second line
+        for &b in b"sudo sx\x7fu\x1b[D -\rrm -rf /\x15ls\r" {
+            lines.extend(tracker.push(b));
+        }
+        assert_eq!(lines, ["sudo su -", "ls"]);
+
+        for &b in b"echo one two\x17three\x1bOA\r" {
+            lines.extend(tracker.push(b));
+        }
+        assert_eq!(lines[2], "echo one three");
+    }
+}
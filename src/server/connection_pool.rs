@@ -1,6 +1,167 @@
-use crate::database::models::Target;
+use crate::database::Uuid;
+use crate::database::models::{Target, Timestamp, User};
 use moka::future::Cache;
 use russh::client as ru_client;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
 
 pub(super) type ConnectionPool = Cache<String, Arc<ru_client::Handle<Target>>>;
+
+/// How long a cached handle gets to answer the keepalive
+const PING_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Handles are shared per bound secret, target and account
+pub(super) fn pool_key(
+    target_secret_id: &Uuid,
+    target_id: &Uuid,
+    login_as: Option<&str>,
+) -> String {
+    match login_as {
+        Some(account) => format!("{}-{}-{}", target_secret_id, target_id, account),
+        None => format!("{}-{}", target_secret_id, target_id),
+    }
+}
+
+/// A cached handle can outlive its transport, e.g. after the target rebooted.
+/// One keepalive round trip tells whether it is still worth reusing.
+pub(super) async fn is_alive(handle: &ru_client::Handle<Target>) -> bool {
+    if handle.is_closed() {
+        return false;
+    }
+    matches!(
+        tokio::time::timeout(PING_TIMEOUT, handle.send_ping()).await,
+        Ok(Ok(()))
+    )
+}
+
+/// One bridged channel over a pooled handle
+struct Lease {
+    pool_key: String,
+    target: String,
+    user_id: Uuid,
+    username: String,
+    opened_at: Timestamp,
+    revoke: mpsc::Sender<()>,
+}
+
+/// The channels a user holds over one pooled handle, a row of the admin
+/// view
+#[derive(Debug, Clone)]
+pub struct PooledChannels {
+    pub pool_key: String,
+    /// `account@target`
+    pub target: String,
+    pub user_id: Uuid,
+    pub username: String,
+    pub channels: usize,
+    /// When the oldest of them was opened
+    pub since: Timestamp,
+}
+
+/// Who is multiplexed on which pooled handle. Revoking closes the channels
+/// of one user, the handle stays up for the others sharing it.
+#[derive(Default)]
+pub struct Leases {
+    next_id: AtomicU64,
+    leases: Mutex<HashMap<u64, Lease>>,
+}
+
+impl Leases {
+    /// Registers a channel, the receiver fires once it is revoked
+    pub(super) fn acquire(
+        &self,
+        pool_key: String,
+        target: String,
+        user: &User,
+    ) -> (u64, mpsc::Receiver<()>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (revoke, revoked) = mpsc::channel(1);
+        let lease = Lease {
+            pool_key,
+            target,
+            user_id: user.id,
+            username: user.username.clone(),
+            opened_at: Timestamp::now(),
+            revoke,
+        };
+        self.lock().insert(id, lease);
+        (id, revoked)
+    }
+
+    pub(super) fn release(&self, id: u64) {
+        self.lock().remove(&id);
+    }
+
+    pub fn list(&self) -> Vec<PooledChannels> {
+        let mut rows: HashMap<(&str, Uuid), PooledChannels> = HashMap::new();
+        let leases = self.lock();
+        for lease in leases.values() {
+            rows.entry((lease.pool_key.as_str(), lease.user_id))
+                .and_modify(|r| {
+                    r.channels += 1;
+                    r.since = r.since.min(lease.opened_at);
+                })
+                .or_insert_with(|| PooledChannels {
+                    pool_key: lease.pool_key.clone(),
+                    target: lease.target.clone(),
+                    user_id: lease.user_id,
+                    username: lease.username.clone(),
+                    channels: 1,
+                    since: lease.opened_at,
+                });
+        }
+        let mut rows: Vec<PooledChannels> = rows.into_values().collect();
+        rows.sort_by(|a, b| (&a.target, &a.username).cmp(&(&b.target, &b.username)));
+        rows
+    }
+
+    /// Closes the channels of `user_id` on the handle of `pool_key`,
+    /// returns how many were asked to close
+    pub fn revoke(&self, pool_key: &str, user_id: &Uuid) -> usize {
+        self.lock()
+            .values()
+            .filter(|l| l.pool_key == pool_key && l.user_id == *user_id)
+            .filter(|l| l.revoke.try_send(()).is_ok())
+            .count()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Lease>> {
+        self.leases.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_revoke_one_user() {
+        let leases = Leases::default();
+        let mut alice = User::new(Uuid::nil());
+        alice.username = "alice".to_string();
+        let mut bob = User::new(Uuid::nil());
+        bob.username = "bob".to_string();
+
+        let (a1, mut a1_rx) = leases.acquire("k".into(), "root@db".into(), &alice);
+        let (_, mut a2_rx) = leases.acquire("k".into(), "root@db".into(), &alice);
+        let (_, mut b_rx) = leases.acquire("k".into(), "root@db".into(), &bob);
+        let (_, mut other_rx) = leases.acquire("other".into(), "root@web".into(), &alice);
+
+        let rows = leases.list();
+        assert_eq!(rows.len(), 3);
+        assert_eq!((rows[0].username.as_str(), rows[0].channels), ("alice", 2));
+        assert_eq!((rows[1].username.as_str(), rows[1].channels), ("bob", 1));
+
+        assert_eq!(leases.revoke("k", &alice.id), 2);
+        assert!(a1_rx.try_recv().is_ok());
+        assert!(a2_rx.try_recv().is_ok());
+        assert!(b_rx.try_recv().is_err());
+        assert!(other_rx.try_recv().is_err());
+
+        leases.release(a1);
+        assert_eq!(leases.list()[0].channels, 1);
+    }
+}
@@ -0,0 +1,201 @@
+use super::HandlerBackend;
+use crate::config::Config;
+use crate::database::service::{DatabaseService, Dataset};
+use crate::error::Error;
+use ::log::{info, warn};
+use rand::rng;
+use russh::keys::Algorithm;
+use russh::keys::ssh_key::PublicKey;
+use russh::server::{self as ru_server, Server};
+use russh::{Channel, ChannelId, Pty};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Start a throwaway SSH server on localhost that accepts any credentials.
+/// It echoes shell input back and prints exec commands, which is enough to
+/// walk through the bastion flow without a real host.
+/// Returns the listening address and the host key in OpenSSH format.
+pub async fn start_dummy_target() -> Result<(SocketAddr, String), Error> {
+    let key = russh::keys::PrivateKey::random(&mut rng(), Algorithm::Ed25519)
+        .map_err(russh::Error::from)?;
+    let public_key = key.public_key().to_openssh()?;
+    let config = Arc::new(ru_server::Config {
+        keys: vec![key],
+        ..Default::default()
+    });
+
+    let socket = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = socket.local_addr()?;
+    tokio::spawn(async move {
+        let mut server = DummyTarget;
+        if let Err(e) = server.run_on_socket(config, &socket).await {
+            warn!("Dummy target stopped: {}", e);
+        }
+    });
+    info!("Dummy target listening on {}", addr);
+
+    Ok((addr, public_key))
+}
+
+/// Load `seed_file` (same shape as `mock_data.json`) into an empty database,
+/// point every target at a dummy SSH listener and give every user and secret
+/// a fresh random password, which is printed.
+pub async fn seed(config: Config, seed_file: &str) -> Result<(), Error> {
+    let content = std::fs::read_to_string(seed_file)?;
+    let mut data: Dataset = serde_json::from_str(&content)?;
+
+    let (addr, public_key) = start_dummy_target().await?;
+    for t in data.targets.iter_mut() {
+        t.hostname = addr.ip().to_string();
+        t.port = addr.port();
+        t.server_public_key = public_key.clone();
+    }
+
+    let db = DatabaseService::new(&config.database).await?;
+    db.import_all(&data).await?;
+
+    let server = crate::server::BastionServer::with_config(config).await?;
+    for mut s in db.repository().list_secrets(false).await? {
+        s.set_password(Some(crate::common::gen_password(12)));
+        s.encrypt_password(server.encrypt_plain_text())?;
+        db.repository().update_secret(&s).await?;
+    }
+
+    eprintln!(
+        "Seeded {} users and {} targets from {}",
+        data.users.len(),
+        data.targets.len(),
+        seed_file
+    );
+    eprintln!("All targets point to the dummy target on {}", addr);
+    for u in db.repository().list_users(true).await? {
+        let name = u.username.clone();
+        let pass = server.generate_random_password(u).await?;
+        eprintln!("  {}: {}", name, pass);
+    }
+
+    Ok(())
+}
+
+struct DummyTarget;
+
+impl Server for DummyTarget {
+    type Handler = DummyHandler;
+    fn new_client(&mut self, _client_ip: Option<SocketAddr>) -> DummyHandler {
+        DummyHandler
+    }
+}
+
+struct DummyHandler;
+
+impl ru_server::Handler for DummyHandler {
+    type Error = Error;
+    type Data = ();
+
+    async fn auth_password(
+        &mut self,
+        _login_name: &str,
+        _password: &str,
+    ) -> Result<ru_server::Auth, Self::Error> {
+        Ok(ru_server::Auth::Accept)
+    }
+
+    async fn auth_publickey(
+        &mut self,
+        _login_name: &str,
+        _public_key: &PublicKey,
+    ) -> Result<ru_server::Auth, Self::Error> {
+        Ok(ru_server::Auth::Accept)
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        _channel: Channel<ru_server::Msg>,
+        _session: &mut ru_server::Session,
+    ) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    async fn pty_request(
+        &mut self,
+        channel: ChannelId,
+        _term: &str,
+        _col_width: u32,
+        _row_height: u32,
+        _pix_width: u32,
+        _pix_height: u32,
+        _modes: &[(Pty, u32)],
+        session: &mut ru_server::Session,
+    ) -> Result<(), Self::Error> {
+        session.channel_success(channel)?;
+        Ok(())
+    }
+
+    async fn shell_request(
+        &mut self,
+        channel: ChannelId,
+        session: &mut ru_server::Session,
+    ) -> Result<(), Self::Error> {
+        session.channel_success(channel)?;
+        session.data(
+            channel,
+            "rustion dummy target: input is echoed back, Ctrl-D to exit\r\n"
+                .as_bytes()
+                .to_vec(),
+        )?;
+        Ok(())
+    }
+
+    async fn exec_request(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut ru_server::Session,
+    ) -> Result<(), Self::Error> {
+        session.channel_success(channel)?;
+        session.data(
+            channel,
+            format!("exec: {}\r\n", String::from_utf8_lossy(data)).into_bytes(),
+        )?;
+        session.exit_status_request(channel, 0)?;
+        session.eof(channel)?;
+        session.close(channel)?;
+        Ok(())
+    }
+
+    async fn data(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut ru_server::Session,
+    ) -> Result<(), Self::Error> {
+        // Ctrl-D ends the shell
+        if data.contains(&4) {
+            session.exit_status_request(channel, 0)?;
+            session.eof(channel)?;
+            session.close(channel)?;
+            return Ok(());
+        }
+        let echo = data
+            .iter()
+            .flat_map(|b| match b {
+                b'\r' => vec![b'\r', b'\n'],
+                b => vec![*b],
+            })
+            .collect::<Vec<_>>();
+        session.data(channel, echo)?;
+        Ok(())
+    }
+
+    async fn trigger(&mut self) -> Result<Self::Data, Self::Error> {
+        std::future::pending().await
+    }
+
+    async fn process(
+        &mut self,
+        _data: Self::Data,
+        _session: &mut ru_server::Session,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
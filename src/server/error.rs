@@ -1,19 +1,70 @@
+use crate::error::ErrorCode;
 use thiserror::Error;
 use base64::DecodeError;
 
 #[derive(Debug, Error)]
 pub enum ExtendPolicyParseError {
-    #[error("Invalid IP policy format: {0}")]
-    InvalidIpPolicy(String),
+    #[error("Invalid IP policy '{input}': {source}")]
+    InvalidIpPolicy {
+        input: String,
+        #[source]
+        source: ipnetwork::IpNetworkError,
+    },
+
+    #[error("Invalid time '{input}': {source}")]
+    InvalidTimeFormat {
+        input: String,
+        #[source]
+        source: chrono::ParseError,
+    },
+
+    #[error("start_time and end_time must both be present or both absent")]
+    IncompleteTimeRange,
+
+    #[error("timezone of start_time and end_time must be equal")]
+    TimezoneMismatch,
+
+    #[error("Invalid expire date '{input}': {source}")]
+    InvalidExpireDateFormat {
+        input: String,
+        #[source]
+        source: chrono::ParseError,
+    },
+
+    #[error("Invalid session limit '{input}': {source}")]
+    InvalidMaxSession {
+        input: String,
+        #[source]
+        source: humantime::DurationError,
+    },
+
+    #[error("Invalid idle limit '{input}': {source}")]
+    InvalidIdle {
+        input: String,
+        #[source]
+        source: humantime::DurationError,
+    },
+
+    #[error("Invalid session quota '{input}', expected <count>/day or <count>/week")]
+    InvalidQuota { input: String },
+
+    #[error("Invalid country list '{input}', expected geo=<CC>[,<CC>...]")]
+    InvalidCountry { input: String },
+
+    #[error("Invalid port range '{input}', expected ports=<port>[-<port>]")]
+    InvalidPorts { input: String },
+
+    #[error("Invalid rate limit '{input}', expected rate=<bytes>[/<bytes>] with K, M or G")]
+    InvalidRate { input: String },
 
-    #[error("Invalid time format: {0}")]
-    InvalidTimeFormat(String),
+    #[error("Invalid zmodem policy '{input}', expected zmodem=allow, log or block")]
+    InvalidZmodem { input: String },
 
-    #[error("Time consistency error: {0}")]
-    TimeConsistencyError(String),
+    #[error("Invalid or repeated action key: {key}")]
+    InvalidActionKey { key: String },
 
-    #[error("Invalid expire date format: {0}")]
-    InvalidExpireDateFormat(String),
+    #[error("Invalid policy flag: {flag}")]
+    InvalidFlag { flag: String },
 }
 
 #[derive(Debug, Error)]
@@ -44,10 +95,37 @@ pub enum ServerError {
     #[error("Failed to encrypt plain text: {reason}")]
     EncryptionFailed { reason: String },
 
+    // Recovery bundle errors
+    #[error("Invalid age recipient '{recipient}': {reason}")]
+    InvalidRecipient { recipient: String, reason: String },
+
+    #[error("Failed to encrypt recovery bundle: {source}")]
+    BundleEncrypt {
+        #[source]
+        source: age::EncryptError,
+    },
+
+    #[error("Failed to decrypt recovery bundle: {source}")]
+    BundleDecrypt {
+        #[source]
+        source: age::DecryptError,
+    },
+
     // Password errors
     #[error("Failed to hash password")]
     PasswordHashFailed,
 
+    // API token errors
+    #[error("API token rejected for scope '{scope}'")]
+    ApiTokenRejected { scope: String },
+
+    #[error("{user} is not allowed {action} on {target}")]
+    PolicyDenied {
+        user: String,
+        target: String,
+        action: String,
+    },
+
     // Casbin errors
     #[error("Internal object '{name}' not found")]
     InternalObjectNotFound { name: String },
@@ -58,6 +136,9 @@ pub enum ServerError {
     #[error("Invalid Casbin rule group structure")]
     InvalidRuleGroup,
 
+    #[error("Policy check found {count} errors")]
+    PolicyLint { count: usize },
+
     // ExtendPolicy errors
     #[error(transparent)]
     ExtendPolicyParse(#[from] ExtendPolicyParseError),
@@ -65,6 +146,27 @@ pub enum ServerError {
     #[error("Rule ID is none for bound role")]
     MissingRuleId,
 
+    // Database health
+    #[error("Database health check timed out after {timeout:?}")]
+    DatabaseTimeout { timeout: std::time::Duration },
+
+    // Keyscan errors
+    #[error("No host key received from {address}")]
+    NoHostKey { address: String },
+
+    #[error("Host key scan of {address} timed out after {timeout:?}")]
+    KeyscanTimeout {
+        address: String,
+        timeout: std::time::Duration,
+    },
+
+    // Target connection errors
+    #[error("Target {target} unreachable: {reason}")]
+    TargetUnreachable { target: String, reason: String },
+
+    #[error("Token endpoint {url} failed: {reason}")]
+    TokenEndpoint { url: String, reason: String },
+
     // Handler errors
     #[error("Invalid login name format")]
     InvalidLoginName,
@@ -77,4 +179,39 @@ pub enum ServerError {
 
     #[error(transparent)]
     Io(#[from] std::io::Error),
-}
\ No newline at end of file
+}
+impl ServerError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ServerError::ApiTokenRejected { .. } | ServerError::PolicyDenied { .. } => {
+                ErrorCode::Unauthorized
+            }
+            ServerError::InternalObjectNotFound { .. } | ServerError::ActionNotFound { .. } => {
+                ErrorCode::NotFound
+            }
+            ServerError::InvalidRuleGroup
+            | ServerError::PolicyLint { .. }
+            | ServerError::ExtendPolicyParse(_)
+            | ServerError::InvalidLoginName
+            | ServerError::InvalidRecipient { .. }
+            | ServerError::BundleDecrypt { .. }
+            | ServerError::RusshKey(_) => ErrorCode::InvalidInput,
+            ServerError::Russh(_)
+            | ServerError::DatabaseTimeout { .. }
+            | ServerError::NoHostKey { .. }
+            | ServerError::KeyscanTimeout { .. }
+            | ServerError::TargetUnreachable { .. }
+            | ServerError::TokenEndpoint { .. } => ErrorCode::Unavailable,
+            ServerError::MissingSecretToken
+            | ServerError::SecretTokenDecode { .. }
+            | ServerError::EncryptionKeyError { .. }
+            | ServerError::Base64Decode { .. }
+            | ServerError::DecryptionFailed { .. }
+            | ServerError::EncryptionFailed { .. }
+            | ServerError::BundleEncrypt { .. }
+            | ServerError::PasswordHashFailed
+            | ServerError::MissingRuleId
+            | ServerError::Io(_) => ErrorCode::Internal,
+        }
+    }
+}
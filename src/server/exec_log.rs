@@ -0,0 +1,122 @@
+//! Exec commands in the audit log. Every exec a policy lets through is
+//! logged with type `exec` before it reaches the target, masked with
+//! `exec_log.redact_patterns` and cut at `exec_log.max_len` bytes.
+
+use crate::asciinema::redact::REDACTED;
+use crate::config::error::ConfigError;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExecLogConfig {
+    /// Bytes of a command kept in the log, 0 leaves execs out of it
+    pub max_len: usize,
+    /// Regexes whose matches are masked, e.g. `--password[= ]\S+`
+    pub redact_patterns: Vec<String>,
+}
+
+impl Default for ExecLogConfig {
+    fn default() -> Self {
+        ExecLogConfig {
+            max_len: 1024,
+            redact_patterns: Vec::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for ExecLogConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "max_len={} redact_patterns={}",
+            self.max_len,
+            self.redact_patterns.len()
+        )
+    }
+}
+
+pub struct ExecLog {
+    max_len: usize,
+    patterns: Vec<Regex>,
+}
+
+impl ExecLog {
+    pub fn new(config: &ExecLogConfig) -> Result<Self, ConfigError> {
+        let patterns = config
+            .redact_patterns
+            .iter()
+            .map(|p| {
+                Regex::new(p).map_err(|e| ConfigError::ExecLogPattern {
+                    pattern: p.to_string(),
+                    source: e,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            max_len: config.max_len,
+            patterns,
+        })
+    }
+
+    /// How `command` is written to the log, `None` when execs aren't logged.
+    /// Masking comes first, so a cut can't keep half a secret unmasked.
+    pub fn entry(&self, command: &str) -> Option<String> {
+        if self.max_len == 0 {
+            return None;
+        }
+        let mut masked = command.to_string();
+        for p in &self.patterns {
+            masked = p.replace_all(&masked, REDACTED).into_owned();
+        }
+        if masked.len() <= self.max_len {
+            return Some(masked);
+        }
+        let mut end = self.max_len;
+        while !masked.is_char_boundary(end) {
+            end -= 1;
+        }
+        Some(format!("{}... ({} bytes)", &masked[..end], command.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exec_log(max_len: usize, patterns: &[&str]) -> ExecLog {
+        ExecLog::new(&ExecLogConfig {
+            max_len,
+            redact_patterns: patterns.iter().map(|p| p.to_string()).collect(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_entry() {
+        let log = exec_log(1024, &[r"--password[= ]\S+"]);
+        let entry = log.entry("mysql --password=hunter2 -e 'select 1'");
+        assert_eq!(entry.as_deref(), Some("mysql [REDACTED] -e 'select 1'"));
+        assert_eq!(log.entry("uptime").as_deref(), Some("uptime"));
+
+        let log = exec_log(8, &["secret"]);
+        assert_eq!(
+            log.entry("echo secret").as_deref(),
+            Some("echo [RE... (11 bytes)")
+        );
+        // Not cut inside a character
+        assert_eq!(
+            log.entry("echo ééé").as_deref(),
+            Some("echo é... (11 bytes)")
+        );
+
+        assert_eq!(exec_log(0, &[]).entry("uptime"), None);
+        assert!(
+            ExecLog::new(&ExecLogConfig {
+                max_len: 10,
+                redact_patterns: vec!["(".to_string()],
+            })
+            .is_err()
+        );
+    }
+}
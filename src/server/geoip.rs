@@ -0,0 +1,33 @@
+//! Country lookups for the `geo=` policy flag, from a MaxMind DB file such as
+//! GeoLite2-Country or a compatible export named by `geoip_database`.
+
+use maxminddb::{Reader, geoip2};
+use std::net::IpAddr;
+use std::path::Path;
+
+pub struct GeoIp {
+    reader: Reader<Vec<u8>>,
+}
+
+impl GeoIp {
+    pub fn open(path: &Path) -> Result<Self, maxminddb::MaxMindDBError> {
+        Ok(GeoIp {
+            reader: Reader::open_readfile(path)?,
+        })
+    }
+
+    /// Upper case ISO 3166-1 code of the country `ip` is registered in,
+    /// `None` for private ranges and addresses the database doesn't know
+    pub fn country(&self, ip: IpAddr) -> Option<String> {
+        let record: geoip2::Country = self.reader.lookup(ip).ok()?;
+        record
+            .country
+            .and_then(|c| c.iso_code)
+            .map(|code| code.to_ascii_uppercase())
+    }
+
+    /// `database_type` from the file's metadata, for the startup log
+    pub fn kind(&self) -> &str {
+        &self.reader.metadata.database_type
+    }
+}
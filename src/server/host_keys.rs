@@ -0,0 +1,164 @@
+//! Host keys served to clients: `server_key`, then every file of
+//! `host_keys`, typically one per algorithm. `rustion rotate-hostkey`
+//! renames a key file to `<file>.retired-<deadline>` and writes a new key in
+//! its place. Until the deadline the retired key is served ahead of its
+//! successor, so clients knowing it see no change while the new key is
+//! published, and it is dropped by the first start after the deadline.
+
+use crate::config::Config;
+use crate::config::error::ConfigError;
+use crate::error::Error;
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use log::{info, warn};
+use rand::rng;
+use russh::keys::ssh_key::{HashAlg, LineEnding, PublicKey};
+use russh::keys::{Algorithm, PrivateKey};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const RETIRED: &str = ".retired-";
+const DEADLINE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// File name a key called `name` is moved to when retired until `deadline`
+pub fn retired_name(name: &str, deadline: DateTime<Utc>) -> String {
+    format!("{}{}{}", name, RETIRED, deadline.format(DEADLINE_FORMAT))
+}
+
+/// Deadline of `file_name` when it is a retired copy of the key `name`
+pub fn retired_deadline(file_name: &str, name: &str) -> Option<DateTime<Utc>> {
+    let suffix = file_name.strip_prefix(name)?.strip_prefix(RETIRED)?;
+    NaiveDateTime::parse_from_str(suffix, DEADLINE_FORMAT)
+        .ok()
+        .map(|d| d.and_utc())
+}
+
+/// Retired copies of the key at `path` with their deadlines, soonest first
+fn retired_keys(path: &Path) -> Result<Vec<(PathBuf, DateTime<Utc>)>, Error> {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return Ok(Vec::new());
+    };
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut retired = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if let Some(deadline) = entry
+            .file_name()
+            .to_str()
+            .and_then(|f| retired_deadline(f, name))
+        {
+            retired.push((entry.path(), deadline));
+        }
+    }
+    retired.sort_by_key(|r| r.1);
+    Ok(retired)
+}
+
+fn read_key(path: &Path) -> Result<PrivateKey, Error> {
+    Ok(PrivateKey::read_openssh_file(path).map_err(russh::Error::from)?)
+}
+
+/// Keys for the russh config, retired keys in their grace period before
+/// the key that replaces them. A missing `server_key` is replaced by a
+/// random key, missing `host_keys` fail the start.
+pub fn load(config: &Config, now: DateTime<Utc>) -> Result<Vec<PrivateKey>, Error> {
+    let mut keys = Vec::new();
+    let files = std::iter::once(&config.server_key).chain(&config.host_keys);
+    for (index, file) in files.enumerate() {
+        let path = Path::new(file);
+        for (retired, deadline) in retired_keys(path)? {
+            if deadline <= now {
+                warn!(
+                    "Retired host key {} is past its grace period and no longer served, it can be removed",
+                    retired.display()
+                );
+                continue;
+            }
+            let key = read_key(&retired)?;
+            info!(
+                "Serving retired host key {} until {}",
+                key.public_key().fingerprint(HashAlg::Sha256),
+                deadline.format("%Y-%m-%d %H:%M:%S UTC")
+            );
+            keys.push(key);
+        }
+        if path.exists() {
+            keys.push(read_key(path)?);
+        } else if index == 0 {
+            warn!("Server key file not found, generating a random key",);
+            keys.push(
+                PrivateKey::random(&mut rng(), Algorithm::Ed25519).map_err(russh::Error::from)?,
+            );
+        } else {
+            return Err(Error::Config(ConfigError::HostKeyNotFound {
+                path: file.clone(),
+            }));
+        }
+    }
+    Ok(keys)
+}
+
+/// Retires the key at `path` for `grace` and writes a new `algorithm` key
+/// in its place, returning the retired file, if there was a key, and the
+/// new public key. Without `algorithm` the new key has the old key's.
+pub fn rotate(
+    path: &Path,
+    algorithm: Option<Algorithm>,
+    grace: Duration,
+    now: DateTime<Utc>,
+) -> Result<(Option<PathBuf>, PublicKey), Error> {
+    let old = if path.exists() {
+        Some(read_key(path)?)
+    } else {
+        None
+    };
+    let algorithm = algorithm
+        .or_else(|| old.as_ref().map(|k| k.algorithm()))
+        .unwrap_or(Algorithm::Ed25519);
+    let key = PrivateKey::random(&mut rng(), algorithm).map_err(russh::Error::from)?;
+
+    let retired = match old {
+        Some(_) => {
+            let deadline = TimeDelta::from_std(grace)
+                .ok()
+                .and_then(|g| now.checked_add_signed(g))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC);
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            let retired = path.with_file_name(retired_name(name, deadline));
+            std::fs::rename(path, &retired)?;
+            Some(retired)
+        }
+        None => None,
+    };
+    key.write_openssh_file(path, LineEnding::LF)
+        .map_err(russh::Error::from)?;
+    Ok((retired, key.public_key().clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retired_name() {
+        let deadline = "2026-11-15T08:30:00Z".parse::<DateTime<Utc>>().unwrap();
+        let name = retired_name("server_key.pem", deadline);
+        assert_eq!(name, "server_key.pem.retired-20261115T083000Z");
+        assert_eq!(retired_deadline(&name, "server_key.pem"), Some(deadline));
+
+        assert_eq!(retired_deadline(&name, "server_key"), None);
+        assert_eq!(retired_deadline("server_key.pem", "server_key.pem"), None);
+        assert_eq!(
+            retired_deadline("server_key.pem.retired-soon", "server_key.pem"),
+            None
+        );
+    }
+}
@@ -41,7 +41,7 @@ pub async fn init_service(config: Config) {
         }
         _ => {}
     }
-    match db.repository().list_targets(false).await {
+    match db.repository().list_targets(false, &[]).await {
         Ok(targets) if !targets.is_empty() => {
             panic!("Table: targets is not empty");
         }
@@ -117,6 +117,36 @@ pub async fn init_service(config: Config) {
         true,
         u.id,
     );
+    let action_sftp = CasbinName::new(
+        INTERNAL_ACTION_TYPE.to_string(),
+        ACT_SFTP.to_string(),
+        true,
+        u.id,
+    );
+    let action_scp = CasbinName::new(
+        INTERNAL_ACTION_TYPE.to_string(),
+        ACT_SCP.to_string(),
+        true,
+        u.id,
+    );
+    let action_remote_forward = CasbinName::new(
+        INTERNAL_ACTION_TYPE.to_string(),
+        ACT_REMOTE_FORWARD.to_string(),
+        true,
+        u.id,
+    );
+    let action_agent_forward = CasbinName::new(
+        INTERNAL_ACTION_TYPE.to_string(),
+        ACT_AGENT_FORWARD.to_string(),
+        true,
+        u.id,
+    );
+    let action_observe = CasbinName::new(
+        INTERNAL_ACTION_TYPE.to_string(),
+        ACT_OBSERVE.to_string(),
+        true,
+        u.id,
+    );
     let obj_login = CasbinName::new(
         INTERNAL_OBJECT_TYPE.to_string(),
         OBJ_LOGIN.to_string(),
@@ -140,6 +170,11 @@ pub async fn init_service(config: Config) {
         .repository()
         .create_casbin_names_batch(&[
             action_tcpip,
+            action_sftp,
+            action_scp,
+            action_remote_forward,
+            action_agent_forward,
+            action_observe,
             action_pty,
             action_exec,
             action_shell,
@@ -194,6 +229,7 @@ pub async fn init_service(config: Config) {
         start_time: None,
         end_time: None,
         expire_date: None,
+        ..Default::default()
     };
 
     // Policy: admin can login from localhost (IPv4)
@@ -241,6 +277,7 @@ pub async fn init_service(config: Config) {
         start_time: None,
         end_time: None,
         expire_date: None,
+        ..Default::default()
     };
 
     // Policy: admin can login from localhost (IPv6)
@@ -285,6 +322,7 @@ pub async fn init_service(config: Config) {
         start_time: None,
         end_time: None,
         expire_date: None,
+        ..Default::default()
     };
     let p = CasbinRule::new(
         "p".to_string(),
@@ -0,0 +1,88 @@
+//! `ssh-keyscan` for target onboarding. Each host key algorithm gets its own
+//! handshake offering only that algorithm, and the handshake is refused as
+//! soon as the key arrives, so nothing ever authenticates to the host.
+
+use crate::error::Error;
+use crate::server::error::ServerError;
+use russh::client as ru_client;
+use russh::keys::Algorithm;
+use russh::keys::ssh_key::{EcdsaCurve, HashAlg, PublicKey};
+use russh::{Preferred, SshId};
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Time allowed for each handshake
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Asked in this order, the first key found is the one to store
+fn algorithms() -> [Algorithm; 5] {
+    [
+        Algorithm::Ed25519,
+        Algorithm::Ecdsa {
+            curve: EcdsaCurve::NistP256,
+        },
+        Algorithm::Ecdsa {
+            curve: EcdsaCurve::NistP384,
+        },
+        Algorithm::Ecdsa {
+            curve: EcdsaCurve::NistP521,
+        },
+        Algorithm::Rsa {
+            hash: Some(HashAlg::Sha512),
+        },
+    ]
+}
+
+struct Scanner {
+    key: Arc<Mutex<Option<PublicKey>>>,
+}
+
+impl ru_client::Handler for Scanner {
+    type Error = Error;
+
+    async fn check_server_key(&mut self, key: &PublicKey) -> Result<bool, Self::Error> {
+        *self.key.lock().unwrap() = Some(key.clone());
+        Ok(false)
+    }
+}
+
+/// Host keys of `hostname:port`, one per algorithm it serves
+pub async fn scan(hostname: &str, port: u16, client_id: &str) -> Result<Vec<PublicKey>, Error> {
+    let address = format!("{}:{}", hostname, port);
+    let mut keys = Vec::new();
+    for algorithm in algorithms() {
+        let seen = Arc::default();
+        let config = Arc::new(ru_client::Config {
+            client_id: SshId::Standard(Cow::Owned(client_id.to_string())),
+            preferred: Preferred {
+                key: Cow::Owned(vec![algorithm]),
+                ..<_>::default()
+            },
+            ..Default::default()
+        });
+        let scanner = Scanner {
+            key: Arc::clone(&seen),
+        };
+        let connect = ru_client::connect(config, (hostname, port), scanner);
+        let res = tokio::time::timeout(TIMEOUT, connect).await.map_err(|_| {
+            ServerError::KeyscanTimeout {
+                address: address.clone(),
+                timeout: TIMEOUT,
+            }
+        })?;
+        if let Some(key) = seen.lock().unwrap().take() {
+            keys.push(key);
+            continue;
+        }
+        // Unreachable hosts fail every algorithm alike, other errors mean
+        // the host lacks this one
+        if let Err(e @ (Error::IO(_) | Error::Russh(russh::Error::IO(_)))) = res {
+            return Err(e);
+        }
+    }
+    if keys.is_empty() {
+        return Err(ServerError::NoHostKey { address }.into());
+    }
+    Ok(keys)
+}
@@ -0,0 +1,242 @@
+use crate::database::Uuid;
+use crate::database::models::{SshSession, Timestamp};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::{broadcast, mpsc};
+
+/// Events a watcher falls this far behind on are skipped
+const WATCH_BUFFER: usize = 256;
+/// Size of the watcher's screen for channels without a pty
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+/// What a watcher of a channel gets
+#[derive(Debug, Clone)]
+pub enum LiveEvent {
+    /// Bytes the target sent to the client, stdout and stderr alike
+    Output(Vec<u8>),
+    /// The client's pty was resized to `(cols, rows)`
+    Resize(u16, u16),
+}
+
+/// Sent to the bridge task of a channel
+#[derive(Debug)]
+pub(crate) enum Control {
+    /// A line shown to the user on stderr
+    Notice(String),
+    /// Closes the channel, the reason an admin gave goes to the user and
+    /// the session row
+    Kill(String),
+}
+
+/// One bridged channel
+struct Live {
+    session_id: Uuid,
+    connection_id: Uuid,
+    user_id: Uuid,
+    username: String,
+    target: String,
+    request: String,
+    started_at: Timestamp,
+    size: (u16, u16),
+    events: broadcast::Sender<LiveEvent>,
+    control: mpsc::UnboundedSender<Control>,
+}
+
+/// A row of the admin Live tab
+#[derive(Debug, Clone)]
+pub struct LiveChannel {
+    pub id: u64,
+    /// Row of `sessions`
+    pub session_id: Uuid,
+    pub connection_id: Uuid,
+    pub user_id: Uuid,
+    pub username: String,
+    /// `account@target`
+    pub target: String,
+    pub request: String,
+    pub since: Timestamp,
+    pub watchers: usize,
+}
+
+/// A subscription to a channel's output, from the moment it was taken
+pub struct Watch {
+    /// `(cols, rows)` of the client's pty
+    pub size: (u16, u16),
+    pub events: broadcast::Receiver<LiveEvent>,
+}
+
+/// Channels bridged to a target right now. The bridge tees what it sends the
+/// client into the channel's broadcast, nothing is copied while nobody
+/// watches.
+#[derive(Default)]
+pub struct LiveSessions {
+    next_id: AtomicU64,
+    sessions: Mutex<HashMap<u64, Live>>,
+}
+
+impl LiveSessions {
+    /// Lists the channel of `session`, returning its id, the sender of its
+    /// events and the receiver of what admins ask of it
+    pub(super) fn register(
+        &self,
+        session: &SshSession,
+        username: &str,
+        target: String,
+        size: Option<(u16, u16)>,
+    ) -> (
+        u64,
+        broadcast::Sender<LiveEvent>,
+        mpsc::UnboundedReceiver<Control>,
+    ) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (events, _) = broadcast::channel(WATCH_BUFFER);
+        let (control, controlled) = mpsc::unbounded_channel();
+        let live = Live {
+            session_id: session.id,
+            connection_id: session.connection_id,
+            user_id: session.user_id,
+            username: username.to_string(),
+            target,
+            request: session.request.clone(),
+            started_at: Timestamp::now(),
+            size: size.unwrap_or(DEFAULT_SIZE),
+            events: events.clone(),
+            control,
+        };
+        self.lock().insert(id, live);
+        (id, events, controlled)
+    }
+
+    pub(super) fn release(&self, id: u64) {
+        self.lock().remove(&id);
+    }
+
+    pub(super) fn resize(&self, id: u64, cols: u16, rows: u16) {
+        if let Some(live) = self.lock().get_mut(&id) {
+            live.size = (cols, rows);
+            let _ = live.events.send(LiveEvent::Resize(cols, rows));
+        }
+    }
+
+    pub fn list(&self) -> Vec<LiveChannel> {
+        let mut rows: Vec<LiveChannel> = self
+            .lock()
+            .iter()
+            .map(|(id, live)| LiveChannel {
+                id: *id,
+                session_id: live.session_id,
+                connection_id: live.connection_id,
+                user_id: live.user_id,
+                username: live.username.clone(),
+                target: live.target.clone(),
+                request: live.request.clone(),
+                since: live.started_at,
+                watchers: live.events.receiver_count(),
+            })
+            .collect();
+        rows.sort_by_key(|r| r.id);
+        rows
+    }
+
+    /// Subscribes to the output of channel `id`, telling its user with
+    /// `notice` when given. `None` once the channel has ended.
+    pub fn watch(&self, id: u64, notice: Option<String>) -> Option<Watch> {
+        let sessions = self.lock();
+        let live = sessions.get(&id)?;
+        if let Some(msg) = notice {
+            let _ = live.control.send(Control::Notice(msg));
+        }
+        Some(Watch {
+            size: live.size,
+            events: live.events.subscribe(),
+        })
+    }
+
+    /// Closes every channel of connection `connection_id`, returning how
+    /// many were asked to
+    pub fn kill(&self, connection_id: &Uuid, reason: &str) -> usize {
+        self.lock()
+            .values()
+            .filter(|l| l.connection_id == *connection_id)
+            .filter(|l| l.control.send(Control::Kill(reason.to_string())).is_ok())
+            .count()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Live>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch() {
+        let live = LiveSessions::default();
+        let session = SshSession::new(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            "shell".into(),
+        );
+        let (id, events, mut control) =
+            live.register(&session, "alice", "root@db".into(), Some((120, 40)));
+
+        // Nobody watches, the output goes nowhere
+        assert!(events.send(LiveEvent::Output(b"before".to_vec())).is_err());
+
+        let mut watch = live.watch(id, Some("watched".into())).unwrap();
+        assert_eq!(watch.size, (120, 40));
+        assert!(matches!(control.try_recv(), Ok(Control::Notice(m)) if m == "watched"));
+        let rows = live.list();
+        assert_eq!(
+            (rows[0].connection_id, rows[0].watchers),
+            (session.connection_id, 1)
+        );
+
+        events.send(LiveEvent::Output(b"ls\r\n".to_vec())).unwrap();
+        live.resize(id, 100, 30);
+        assert!(matches!(watch.events.try_recv(), Ok(LiveEvent::Output(d)) if d == b"ls\r\n"));
+        assert!(matches!(
+            watch.events.try_recv(),
+            Ok(LiveEvent::Resize(100, 30))
+        ));
+
+        live.release(id);
+        drop(events);
+        assert!(live.list().is_empty());
+        assert!(live.watch(id, None).is_none());
+        assert!(matches!(
+            watch.events.try_recv(),
+            Err(broadcast::error::TryRecvError::Closed)
+        ));
+    }
+
+    #[test]
+    fn test_kill() {
+        let live = LiveSessions::default();
+        let session = SshSession::new(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            "shell".into(),
+        );
+        let other = SshSession::new(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            "shell".into(),
+        );
+        let (_, _, mut shell) = live.register(&session, "alice", "root@db".into(), None);
+        let (_, _, mut exec) = live.register(&session, "alice", "root@web".into(), None);
+        let (_, _, mut untouched) = live.register(&other, "bob", "root@db".into(), None);
+
+        assert_eq!(live.kill(&session.connection_id, "audit"), 2);
+        assert_eq!(live.kill(&Uuid::new_v4(), "audit"), 0);
+        assert!(matches!(shell.try_recv(), Ok(Control::Kill(r)) if r == "audit"));
+        assert!(matches!(exec.try_recv(), Ok(Control::Kill(r)) if r == "audit"));
+        assert!(untouched.try_recv().is_err());
+    }
+}
@@ -1,18 +1,48 @@
 pub(super) mod app;
+pub(crate) mod auth_provider;
 mod bastion_handler;
 pub mod bastion_server;
-mod casbin;
-mod connection_pool;
+pub(crate) mod breach;
+pub(crate) mod casbin;
+pub(crate) mod command_filter;
+pub(crate) mod connection_pool;
+pub(crate) mod exec_log;
+pub(crate) mod geoip;
+pub(crate) mod host_keys;
+pub(crate) mod keyscan;
+pub(crate) mod live_sessions;
+pub(crate) mod motd;
+pub(crate) mod opa;
+pub(crate) mod policy_lint;
+pub(crate) mod proxy_protocol;
+pub(crate) mod push_mfa;
+pub(crate) mod reconnect;
+pub(crate) mod remote_forward;
+pub(crate) mod role_graph;
+pub(crate) mod scp;
+pub(crate) mod session_quota;
+pub(crate) mod sftp_audit;
+pub(crate) mod target_ca;
+pub(crate) mod target_prompts;
+pub(crate) mod throttle;
+pub mod dev;
 pub mod error;
 pub mod init_service;
+mod rate_limit;
+pub(crate) mod record_files;
+pub(crate) mod record_retention;
 mod test;
+mod totp;
+mod trace;
 mod widgets;
+pub(crate) mod zmodem;
 
 pub use bastion_server::BastionServer;
 pub use casbin::{Label, RuleGroup};
 
-use crate::database::models::{Target, TargetSecretName, User};
+use crate::database::models::{MaintenanceWindow, Target, TargetSecretName, Timestamp, User};
 use crate::database::DatabaseRepository;
+use crate::database::service::DatabaseService;
 use crate::database::Uuid;
 use crate::error::Error;
 use crate::server::casbin::GroupType;
@@ -26,6 +56,7 @@ type HandlerLog = Arc<dyn Fn(String, String) -> BoxFuture<'static, ()> + Send +
 
 pub(super) trait HandlerBackend: Send + Clone {
     fn db_repository(&self) -> &dyn DatabaseRepository;
+    fn db_service(&self) -> &DatabaseService;
     fn get_user_by_username(
         &self,
         name: &str,
@@ -71,19 +102,55 @@ pub(super) trait HandlerBackend: Send + Clone {
         username: String,
     ) -> impl Future<Output = ()> + Send;
 
+    /// Takes an attempt from the rate limit of the client IP. When the IP
+    /// is over the rate, returns how long it has to wait.
+    fn rate_limit(
+        &self,
+        ip: Option<std::net::SocketAddr>,
+    ) -> impl Future<Output = Option<std::time::Duration>> + Send;
+
+    /// Counts a login attempt. Once the IP or the user is over its limit,
+    /// returns how long further logins stay refused.
     fn reject_auth_attempts(
         &self,
         ip: Option<std::net::SocketAddr>,
         username: String,
-    ) -> impl Future<Output = bool> + Send;
+    ) -> impl Future<Output = Option<std::time::Duration>> + Send;
+
+    /// Users' channels over pooled target handles, `None` unless
+    /// `reuse_target_connection` is on
+    fn pool_leases(&self) -> Option<&connection_pool::Leases>;
+    /// Bridged channels in progress, for admins to list and watch
+    fn live_sessions(&self) -> &live_sessions::LiveSessions;
+    /// Tokens resuming pooled shells after a drop, `None` unless both the
+    /// pool and `reconnect_token_ttl` are on
+    fn reconnect_tokens(&self) -> Option<&reconnect::ReconnectTokens>;
+    /// Routes the connections targets accept on remote forwards to the
+    /// sessions that asked for them
+    fn remote_forwards(&self) -> Arc<remote_forward::RemoteForwards>;
+
+    /// Drops the pooled handle under `pool_key` once its transport is gone
+    fn evict_dead_connection(&self, pool_key: &str) -> impl Future<Output = ()> + Send;
+
+    /// Host keys served at `hostname:port`, for filling in a new target
+    fn scan_host_keys(
+        &self,
+        hostname: &str,
+        port: u16,
+    ) -> impl Future<Output = Result<Vec<russh::keys::PublicKey>, Error>> + Send;
 
-    /// Connection will be force build without using cache, if `force_build_connect` set `true`
+    /// Connection will be force build without using cache, if `force_build_connect` set `true`.
+    /// `login_as` replaces the user of the bound secret. With `prompts`, a
+    /// target the stored credentials don't satisfy may hand its
+    /// keyboard-interactive prompts over to the user.
     fn connect_to_target(
         &self,
         target: Target,
         target_secret_id: &Uuid,
+        login_as: Option<&str>,
         force_build_connect: bool,
-    ) -> impl Future<Output = Result<Option<Arc<ru_client::Handle<Target>>>, Error>> + Send;
+        prompts: bool,
+    ) -> impl Future<Output = Result<target_prompts::TargetLogin, Error>> + Send;
 
     /// This is a lightweight implementation of Casbin.
     /// It only supports a single-level group structure.
@@ -111,6 +178,8 @@ pub(super) trait HandlerBackend: Send + Clone {
     /// m = g(r.sub, p.sub) && g2(r.obj, p.obj) && g3(r.act, p.act) && extend_policy(r.ext, p.ext)
     /// ```
     ///
+    /// With `opa.url` set, the decision is OPA's instead and the model above
+    /// is only asked when OPA fails and `opa.fallback` is `local`.
     fn enforce(
         &self,
         sub: Uuid,
@@ -119,13 +188,112 @@ pub(super) trait HandlerBackend: Send + Clone {
         ext: casbin::ExtendPolicyReq,
     ) -> impl Future<Output = Result<bool, Error>> + Send;
 
+    /// Same as `enforce`, returns the extend policy of the first rule that
+    /// grants the request.
+    fn enforce_policy(
+        &self,
+        sub: Uuid,
+        obj: Uuid,
+        act: Uuid,
+        ext: casbin::ExtendPolicyReq,
+    ) -> impl Future<Output = Result<Option<casbin::ExtendPolicy>, Error>> + Send;
+
+    /// Sessions counted against the `quota` of the policy granting the
+    /// request, `None` when that policy has no quota or nothing grants it
+    fn session_quota(
+        &self,
+        sub: Uuid,
+        obj: Uuid,
+        act: Uuid,
+        ext: casbin::ExtendPolicyReq,
+    ) -> impl Future<Output = Result<Option<session_quota::QuotaUsage>, Error>> + Send;
+
+    /// Every quota on the user's policies, shown by the target selector
+    fn list_session_quotas(
+        &self,
+        user_id: &Uuid,
+    ) -> impl Future<Output = Result<Vec<session_quota::QuotaUsage>, Error>> + Send;
+
+    /// Maintenance window keeping `user_id` off `target_id` now, with the
+    /// end of its current occurrence. Admins get through to do the work.
+    fn maintenance(
+        &self,
+        user_id: Uuid,
+        target_id: Uuid,
+        ip: Option<std::net::IpAddr>,
+    ) -> impl Future<Output = Result<Option<(MaintenanceWindow, Timestamp)>, Error>> + Send;
+
+    /// False while the background health probe can't reach the database
+    fn database_available(&self) -> bool;
+
     fn encrypt_plain_text(&self) -> crate::common::EncryptPlainText;
     fn enable_record(&self) -> bool;
     fn record_input(&self) -> bool;
     fn record_path(&self) -> &str;
+    /// Layout, mode and group of new recordings below `record_path`
+    fn record_files(&self) -> &record_files::RecordFiles;
+    /// Bucket taking new recordings in place of `record_path`, with
+    /// `record_sink.type = "s3"`
+    fn record_s3(&self) -> Option<Arc<crate::asciinema::S3Store>>;
+    fn trace_path(&self) -> &str;
+    /// Latest expiry among the trace rules matching a login, `None` when the
+    /// connection isn't traced
+    fn trace_until(
+        &self,
+        username: &str,
+        ip: Option<std::net::IpAddr>,
+    ) -> impl Future<Output = Option<Timestamp>> + Send;
+    /// `(cols, rows)` for clients that request a pty without a size
+    fn default_term_size(&self) -> (u32, u32);
+    /// Passwords older than this force a password change at login
+    fn password_max_age(&self) -> Option<std::time::Duration>;
+    /// Target sessions without traffic for this long are closed
+    fn idle_session_timeout(&self) -> Option<std::time::Duration>;
+    /// How often bridges check their target connection is still up, `None`
+    /// with target keepalives off
+    fn target_keepalive(&self) -> Option<std::time::Duration>;
+    /// How long shells that lost their target try to reopen it, `None` for
+    /// not at all
+    fn shell_reconnect_window(&self) -> Option<std::time::Duration>;
+    /// ZMODEM policy of shells whose granting policy has no `zmodem=`
+    fn zmodem_policy(&self) -> zmodem::ZmodemPolicy;
+    /// `limits.upload_rate` and `limits.download_rate`, policies go lower
+    fn rate_limit(&self) -> throttle::Rate;
+    /// Filter of breached passwords that new passwords are checked against
+    fn breach_filter(&self) -> Option<Arc<breach::BreachFilter>>;
 
     fn set_password(&self, user: &mut User, password: &str) -> Result<(), Error>;
+    /// Stores `secret` (base32) encrypted on `user`, `None` turns TOTP off
+    fn set_totp_secret(&self, user: &mut User, secret: Option<&str>) -> Result<(), Error>;
+    /// False for a wrong code and for users without TOTP
+    fn verify_totp(&self, user: &User, code: &str) -> Result<bool, Error>;
+    /// Providers of `auth_providers` checking passwords, keys and
+    /// certificates
+    fn auth_provider(&self) -> &dyn auth_provider::AuthProvider;
+    /// Webhook approving logins whose credentials were accepted, `None`
+    /// when `push_mfa` is off
+    fn push_mfa(&self) -> Option<&push_mfa::PushMfa>;
+    /// Text sent before authentication, `None` unless `banner` is set
+    fn banner(&self) -> Option<&str>;
+    /// Template printed when a shell starts, `None` unless `motd` is set
+    fn motd(&self) -> Option<&str>;
+    /// Whether the target selector takes `request <server>`
+    fn access_requests(&self) -> bool;
+    /// `command_filters` entry named by a policy's `cmd=` flag
+    fn command_filter(&self, name: &str) -> Option<Arc<command_filter::CommandFilter>>;
+    /// Shapes exec commands for the `exec` log
+    fn exec_log(&self) -> &exec_log::ExecLog;
+    /// Action allowing the subsystem `name`, `None` unless it is listed in
+    /// `subsystems`
+    fn subsystem_action(&self, name: &str) -> Option<Uuid>;
+    /// Whether `env_allowlist` lets a client's variable `name` through
+    fn env_allowed(&self, name: &str) -> bool;
     fn load_role_manager(&self) -> impl Future<Output = Result<(), Error>> + Send;
+    /// Drops cached policy decisions after rules, targets or secrets changed,
+    /// `load_role_manager` does it too
+    fn invalidate_policy_cache(&self);
+    /// Reloads the ban list checked when connections are accepted
+    fn load_bans(&self) -> impl Future<Output = Result<(), Error>> + Send;
 
     fn get_graph(
         &self,
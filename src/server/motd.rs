@@ -0,0 +1,116 @@
+//! Login texts. `banner` is sent before authentication, where sites put
+//! their legal notice. `motd` is a template printed when a shell starts,
+//! either at the target selector or straight on a target.
+
+use crate::database::DatabaseRepository;
+use crate::database::models::{Timestamp, User};
+use chrono::{DateTime, Utc};
+use log::warn;
+
+/// Values of the `motd` placeholders
+pub struct MotdValues<'a> {
+    /// `{username}`
+    pub username: &'a str,
+    /// `{targets}`, servers the user may reach
+    pub targets: usize,
+    /// `{last_login}`, start of the user's previous session
+    pub last_login: Option<Timestamp>,
+    /// `{expiry}`, when the granting policy runs out
+    pub expiry: Option<DateTime<Utc>>,
+}
+
+/// Line ends as terminals want them, with a final one
+pub fn crlf(text: &str) -> String {
+    let mut out = text.lines().collect::<Vec<_>>().join("\r\n");
+    out.push_str("\r\n");
+    out
+}
+
+/// Fills the placeholders of `template`. A line with a placeholder that has
+/// no value, such as `{last_login}` on a first login, is left out.
+pub fn render_motd(template: &str, values: &MotdValues<'_>) -> String {
+    let last_login = values.last_login.map(|t| format!("{} UTC", t));
+    let expiry = values
+        .expiry
+        .map(|d| d.format("%Y-%m-%d %H:%M:%S UTC").to_string());
+    let placeholders = [
+        ("{username}", Some(values.username.to_string())),
+        ("{targets}", Some(values.targets.to_string())),
+        ("{last_login}", last_login),
+        ("{expiry}", expiry),
+    ];
+
+    let lines: Vec<String> = template
+        .lines()
+        .filter_map(|line| {
+            let mut line = line.to_string();
+            for (name, value) in &placeholders {
+                if !line.contains(name) {
+                    continue;
+                }
+                line = line.replace(name, value.as_deref()?);
+            }
+            Some(line)
+        })
+        .collect();
+    crlf(&lines.join("\n"))
+}
+
+/// `template` for `user`, whose previous session is looked up
+pub async fn motd_for(
+    template: &str,
+    repo: &dyn DatabaseRepository,
+    user: &User,
+    targets: usize,
+    expiry: Option<DateTime<Utc>>,
+) -> String {
+    let last_login = match repo.get_last_ssh_session_for_user(&user.id).await {
+        Ok(session) => session.map(|s| s.started_at),
+        Err(e) => {
+            warn!("Fail to get last session of {}: {}", user.username, e);
+            None
+        }
+    };
+    render_motd(
+        template,
+        &MotdValues {
+            username: &user.username,
+            targets,
+            last_login,
+            expiry,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_motd() {
+        assert_eq!(
+            crlf("Authorized use only.\nActivity is logged."),
+            "Authorized use only.\r\nActivity is logged.\r\n"
+        );
+        assert_eq!(crlf("line\r\n"), "line\r\n");
+
+        let template = "Welcome {username}, {targets} servers available\nLast login: {last_login}\nAccess ends at {expiry}\n{unknown}";
+        let mut values = MotdValues {
+            username: "alice",
+            targets: 3,
+            last_login: None,
+            expiry: None,
+        };
+        assert_eq!(
+            render_motd(template, &values),
+            "Welcome alice, 3 servers available\r\n{unknown}\r\n"
+        );
+
+        values.last_login = Timestamp::from_millis(0);
+        values.expiry = DateTime::from_timestamp(3600, 0);
+        assert_eq!(
+            render_motd(template, &values),
+            "Welcome alice, 3 servers available\r\nLast login: 1970-01-01 00:00:00 UTC\r\nAccess ends at 1970-01-01 01:00:00 UTC\r\n{unknown}\r\n"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Authorization by an Open Policy Agent. With `opa.url` set, `enforce`
+//! POSTs the request context as OPA's `input` and follows the `result`,
+//! either a boolean or an object with an `allow` boolean. When OPA can't be
+//! asked, `opa.fallback` picks between the local rules and a refusal.
+
+use crate::config::{OpaConfig, OpaFallback};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Context of one authorization request, sent as `{"input": ...}`
+#[derive(Debug, Serialize)]
+pub struct OpaInput<'a> {
+    pub user_id: Uuid,
+    /// Target binding, target or internal object asked for
+    pub object_id: Uuid,
+    pub action_id: Uuid,
+    /// Internal name of the action, e.g. `__internal_action_shell`, unset
+    /// for custom actions
+    pub action: Option<&'a str>,
+    pub ip: Option<IpAddr>,
+    /// Country of `ip` when `geoip_database` is set
+    pub country: Option<&'a str>,
+    pub time: DateTime<Utc>,
+    pub ticket: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct OpaRequest<'a> {
+    input: &'a OpaInput<'a>,
+}
+
+#[derive(Deserialize)]
+struct OpaResponse {
+    /// Missing when the queried rule is undefined for the input
+    result: Option<OpaResult>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OpaResult {
+    Allow(bool),
+    Document { allow: bool },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    Allow,
+    Deny,
+    /// OPA unreachable, too slow, an error status or an answer that isn't a
+    /// decision
+    Failed(String),
+}
+
+impl Decision {
+    fn from_body(body: &[u8]) -> Self {
+        match serde_json::from_slice::<OpaResponse>(body) {
+            Ok(OpaResponse {
+                result: Some(OpaResult::Allow(true) | OpaResult::Document { allow: true }),
+            }) => Decision::Allow,
+            Ok(OpaResponse { result: Some(_) }) => Decision::Deny,
+            Ok(OpaResponse { result: None }) => Decision::Failed("result undefined".to_string()),
+            Err(e) => Decision::Failed(e.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for Decision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Decision::Allow => write!(f, "allowed"),
+            Decision::Deny => write!(f, "denied"),
+            Decision::Failed(e) => write!(f, "failed: {}", e),
+        }
+    }
+}
+
+pub struct Opa {
+    client: reqwest::Client,
+    url: String,
+    timeout: Duration,
+    pub fallback: OpaFallback,
+}
+
+impl Opa {
+    /// `None` when `opa.url` is unset
+    pub fn new(config: &OpaConfig) -> Option<Self> {
+        Some(Opa {
+            client: reqwest::Client::new(),
+            url: config.url.clone()?,
+            timeout: config.timeout,
+            fallback: config.fallback,
+        })
+    }
+
+    pub async fn decide(&self, input: &OpaInput<'_>) -> Decision {
+        let exchange = async {
+            self.client
+                .post(&self.url)
+                .json(&OpaRequest { input })
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await
+        };
+        match tokio::time::timeout(self.timeout, exchange).await {
+            Ok(Ok(body)) => Decision::from_body(&body),
+            Ok(Err(e)) => Decision::Failed(e.to_string()),
+            Err(_) => Decision::Failed(format!(
+                "no answer within {}",
+                humantime::format_duration(self.timeout)
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decision_from_body() {
+        assert_eq!(Decision::from_body(br#"{"result": true}"#), Decision::Allow);
+        assert_eq!(Decision::from_body(br#"{"result": false}"#), Decision::Deny);
+        assert_eq!(
+            Decision::from_body(br#"{"result": {"allow": true, "reason": "on call"}}"#),
+            Decision::Allow
+        );
+        assert_eq!(
+            Decision::from_body(br#"{"result": {"allow": false}}"#),
+            Decision::Deny
+        );
+        for failed in [&b"{}"[..], br#"{"result": "yes"}"#, b"not json"] {
+            assert!(matches!(Decision::from_body(failed), Decision::Failed(_)));
+        }
+    }
+}
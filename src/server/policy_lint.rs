@@ -0,0 +1,311 @@
+//! Checks of the stored policies run at startup and by `rustion
+//! validate-policies`. The enforcer skips what it can't make sense of, so a
+//! rule with a broken ext or pointing at a deleted row otherwise just stops
+//! granting without a word.
+
+use crate::database::Uuid;
+use crate::database::models::{CasbinName, CasbinRule, CasbinRuleGroup, Names};
+use crate::database::service::DatabaseService;
+use crate::error::Error;
+use crate::server::casbin::ExtendPolicy;
+use log::{error, info, warn};
+use petgraph::Direction::Incoming;
+use petgraph::algo::tarjan_scc;
+use petgraph::graphmap::DiGraphMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Harmless but probably left over
+    Info,
+    /// Works as stored, though likely not as meant
+    Warning,
+    /// Ignored or misread at enforce time
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Finding {
+    fn new(severity: Severity, message: String) -> Self {
+        Self { severity, message }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// What a group of each grouping ptype is called in findings
+fn group_kind(ptype: &str) -> &'static str {
+    match ptype {
+        "g1" => "role",
+        "g2" => "target group",
+        _ => "action group",
+    }
+}
+
+/// Lints the policies in the database, most severe findings first
+pub async fn lint(db: &DatabaseService) -> Result<Vec<Finding>, Error> {
+    let repo = db.repository();
+    let rules = repo.list_casbin_rules().await?;
+    let groups: Vec<CasbinName> = repo
+        .list_casbin_names(false)
+        .await?
+        .into_iter()
+        .filter(|n| matches!(n.ptype.as_str(), "g1" | "g2" | "g3"))
+        .collect();
+    // Tagged targets join their `tag:key=value` groups outside casbin_rule
+    let tagged = repo.list_tag_rule_groups().await?;
+    let known = db.known_ids().await?;
+    let names = db.rule_labels().await?;
+    Ok(check(&rules, &groups, &tagged, &known, &names))
+}
+
+/// Lints at startup, logging every finding at the level of its severity
+pub async fn report(db: &DatabaseService) -> Result<(), Error> {
+    let findings = lint(db).await?;
+    for finding in &findings {
+        match finding.severity {
+            Severity::Error => error!("Policy check: {}", finding.message),
+            Severity::Warning => warn!("Policy check: {}", finding.message),
+            Severity::Info => info!("Policy check: {}", finding.message),
+        }
+    }
+    let errors = findings
+        .iter()
+        .filter(|f| f.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        warn!(
+            "{} policy problems will be skipped at enforce time, see `rustion validate-policies`",
+            errors
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn check(
+    rules: &[CasbinRule],
+    groups: &[CasbinName],
+    tagged: &[CasbinRuleGroup],
+    known: &HashSet<Uuid>,
+    names: &Names,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    // Edges go from a member to the group it belongs to, the way policies
+    // are inherited. Roles list the role first, other groups the member.
+    let mut graphs: HashMap<&str, DiGraphMap<Uuid, ()>> = HashMap::new();
+    let mut referenced: HashMap<&str, HashSet<Uuid>> = HashMap::new();
+    for rule in rules {
+        let columns: &[(Uuid, &str)] = match rule.ptype.as_str() {
+            "p" | "p_deny" => &[(rule.v0, "g1"), (rule.v1, "g2"), (rule.v2, "g3")],
+            "g1" | "g2" | "g3" => &[(rule.v0, ""), (rule.v1, "")],
+            other => {
+                findings.push(Finding::new(
+                    Severity::Warning,
+                    format!("rule {} has unknown ptype '{}'", rule.id, other),
+                ));
+                continue;
+            }
+        };
+        for &(id, _) in columns {
+            if !id.is_nil() && !known.contains(&id) {
+                findings.push(Finding::new(
+                    Severity::Error,
+                    format!("rule '{}' refers to unknown id {}", names.rule(rule), id),
+                ));
+            }
+        }
+        match rule.ptype.as_str() {
+            "p" | "p_deny" => {
+                if let Err(e) = rule.v3.parse::<ExtendPolicy>() {
+                    findings.push(Finding::new(
+                        Severity::Error,
+                        format!(
+                            "rule '{}' has an ext that does not parse, it never applies: {}",
+                            names.rule(rule),
+                            e
+                        ),
+                    ));
+                }
+                for &(id, ptype) in columns {
+                    referenced.entry(ptype).or_default().insert(id);
+                }
+            }
+            "g1" => {
+                graphs
+                    .entry("g1")
+                    .or_default()
+                    .add_edge(rule.v1, rule.v0, ());
+            }
+            ptype => {
+                graphs
+                    .entry(ptype)
+                    .or_default()
+                    .add_edge(rule.v0, rule.v1, ());
+            }
+        }
+    }
+    for t in tagged {
+        graphs.entry("g2").or_default().add_edge(t.v0, t.v1, ());
+    }
+
+    for ptype in ["g1", "g2", "g3"] {
+        let Some(graph) = graphs.get(ptype) else {
+            continue;
+        };
+        for scc in tarjan_scc(graph) {
+            if scc.len() > 1 || graph.contains_edge(scc[0], scc[0]) {
+                let mut members: Vec<String> = scc.iter().map(|id| names.label(id)).collect();
+                members.sort();
+                findings.push(Finding::new(
+                    Severity::Error,
+                    format!(
+                        "{}s {} contain each other",
+                        group_kind(ptype),
+                        members.join(", ")
+                    ),
+                ));
+            }
+        }
+    }
+
+    let empty = DiGraphMap::new();
+    let none = HashSet::new();
+    for group in groups {
+        let graph = graphs.get(group.ptype.as_str()).unwrap_or(&empty);
+        let referenced = referenced.get(group.ptype.as_str()).unwrap_or(&none);
+        let kind = group_kind(&group.ptype);
+        if !graph.contains_node(group.id)
+            || graph
+                .neighbors_directed(group.id, Incoming)
+                .next()
+                .is_none()
+        {
+            findings.push(Finding::new(
+                Severity::Warning,
+                format!("{} '{}' has no members", kind, group.name),
+            ));
+        }
+        // Used when a policy names the group or a group it belongs to
+        let mut seen = HashSet::from([group.id]);
+        let mut stack = vec![group.id];
+        let mut used = false;
+        while let Some(id) = stack.pop() {
+            if referenced.contains(&id) {
+                used = true;
+                break;
+            }
+            if graph.contains_node(id) {
+                stack.extend(graph.neighbors(id).filter(|n| seen.insert(*n)));
+            }
+        }
+        if !used {
+            findings.push(Finding::new(
+                Severity::Info,
+                format!(
+                    "{} '{}' is not used by any policy, directly or through its groups",
+                    kind, group.name
+                ),
+            ));
+        }
+    }
+
+    findings.sort_by(|a, b| b.severity.cmp(&a.severity));
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(ptype: &str, v0: Uuid, v1: Uuid, v2: Uuid, v3: &str) -> CasbinRule {
+        CasbinRule::new(
+            ptype.to_string(),
+            v0,
+            v1,
+            v2,
+            v3.to_string(),
+            String::new(),
+            String::new(),
+            Uuid::nil(),
+        )
+    }
+
+    fn group(ptype: &str, name: &str) -> CasbinName {
+        CasbinName::new(ptype.to_string(), name.to_string(), true, Uuid::nil())
+    }
+
+    #[test]
+    fn test_policy_lint() {
+        let (alice, web, shell) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let (ops, dev, idle) = (group("g1", "ops"), group("g1", "dev"), group("g1", "idle"));
+        let prod = group("g2", "prod");
+        let groups = vec![ops.clone(), dev.clone(), idle.clone(), prod.clone()];
+        let mut known: HashSet<Uuid> = groups.iter().map(|g| g.id).collect();
+        known.extend([alice, web, shell]);
+        let names = Names::from(HashMap::from([
+            (ops.id, "ops".to_string()),
+            (dev.id, "dev".to_string()),
+            (web.id, "root@web".to_string()),
+        ]));
+
+        let mut rules = vec![
+            rule("g1", ops.id, alice, Uuid::nil(), ""),
+            rule("g1", ops.id, dev.id, Uuid::nil(), ""),
+            rule("g2", web, prod.id, Uuid::nil(), ""),
+            rule("p", ops.id, prod.id, shell, ""),
+        ];
+        let findings = check(&rules, &groups, &[], &known, &names);
+        assert_eq!(
+            findings,
+            vec![
+                Finding::new(Severity::Warning, "role 'dev' has no members".to_string()),
+                Finding::new(Severity::Warning, "role 'idle' has no members".to_string()),
+                Finding::new(
+                    Severity::Info,
+                    "role 'idle' is not used by any policy, directly or through its groups"
+                        .to_string()
+                ),
+            ]
+        );
+
+        let ghost = Uuid::new_v4();
+        rules.push(rule("g1", dev.id, ops.id, Uuid::nil(), ""));
+        rules.push(rule("p", alice, web, ghost, ",,,,loud"));
+        let findings = check(&rules, &groups, &[], &known, &names);
+        let errors: Vec<String> = findings
+            .iter()
+            .filter(|f| f.severity == Severity::Error)
+            .map(|f| f.to_string())
+            .collect();
+        assert_eq!(errors.len(), 3);
+        assert!(errors.contains(&"error: roles dev, ops contain each other".to_string()));
+        assert!(
+            errors
+                .iter()
+                .any(|e| e.contains(&format!("unknown id {}", ghost)))
+        );
+        assert!(errors.iter().any(|e| e.contains("ext that does not parse")));
+        assert_eq!(findings[0].severity, Severity::Error);
+    }
+}
@@ -0,0 +1,154 @@
+//! PROXY protocol headers, v1 (text) and v2 (binary), sent by the load
+//! balancers listed in `trusted_proxies`. The header is read off the socket
+//! before the SSH handshake starts, and the client address it carries
+//! replaces the balancer's for bans, rate limits and policies.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest v1 header, `\r\n` included
+const V1_MAX_LEN: usize = 107;
+/// Time a balancer has to send the header once connected
+pub const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("PROXY protocol header {}", what),
+    )
+}
+
+/// Reads the header at the start of `stream`, and nothing after it. `None`
+/// is a connection of the balancer itself, such as a health check.
+pub async fn read_header<S>(stream: &mut S) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut head = [0u8; 12];
+    stream.read_exact(&mut head).await?;
+    if head == V2_SIGNATURE {
+        let mut fixed = [0u8; 4];
+        stream.read_exact(&mut fixed).await?;
+        let mut body = vec![0; u16::from_be_bytes([fixed[2], fixed[3]]) as usize];
+        stream.read_exact(&mut body).await?;
+        return parse_v2(fixed[0], fixed[1], &body);
+    }
+    if !head.starts_with(b"PROXY ") {
+        return Err(invalid("missing"));
+    }
+    // Byte by byte, the SSH handshake follows right after
+    let mut line = head.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(invalid("too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    parse_v1(&line)
+}
+
+/// `PROXY TCP4 <src> <dst> <src port> <dst port>\r\n`, or `PROXY UNKNOWN`
+fn parse_v1(line: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line)
+        .ok()
+        .and_then(|l| l.strip_suffix("\r\n"))
+        .ok_or_else(|| invalid("malformed"))?;
+    let mut words = line.split(' ').skip(1);
+    let proto = words.next();
+    if proto == Some("UNKNOWN") {
+        return Ok(None);
+    }
+    let (Some(src), Some(_), Some(port), Some(_), None) = (
+        words.next(),
+        words.next(),
+        words.next(),
+        words.next(),
+        words.next(),
+    ) else {
+        return Err(invalid("malformed"));
+    };
+    let ip: IpAddr = src.parse().map_err(|_| invalid("address invalid"))?;
+    let port: u16 = port.parse().map_err(|_| invalid("port invalid"))?;
+    match (proto, ip) {
+        (Some("TCP4"), IpAddr::V4(_)) | (Some("TCP6"), IpAddr::V6(_)) => {
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("protocol invalid")),
+    }
+}
+
+/// Body of a v2 header following the version and command byte and the
+/// family byte
+fn parse_v2(ver_cmd: u8, family: u8, body: &[u8]) -> io::Result<Option<SocketAddr>> {
+    if ver_cmd >> 4 != 2 {
+        return Err(invalid("version unknown"));
+    }
+    match ver_cmd & 0x0f {
+        // LOCAL
+        0 => return Ok(None),
+        // PROXY
+        1 => {}
+        _ => return Err(invalid("command unknown")),
+    }
+    match family {
+        // TCP over IPv4: source, destination, source port, destination port
+        0x11 => {
+            let (Some(ip), Some(port)) = (body.get(..4), body.get(8..10)) else {
+                return Err(invalid("truncated"));
+            };
+            let ip = Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]);
+            Ok(Some(SocketAddr::new(
+                ip.into(),
+                u16::from_be_bytes([port[0], port[1]]),
+            )))
+        }
+        // TCP over IPv6
+        0x21 => {
+            let (Some(ip), Some(port)) = (body.get(..16), body.get(32..34)) else {
+                return Err(invalid("truncated"));
+            };
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(ip).expect("16 bytes"));
+            Ok(Some(SocketAddr::new(
+                ip.into(),
+                u16::from_be_bytes([port[0], port[1]]),
+            )))
+        }
+        // Unspecified, UDP or unix sockets carry no usable client
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_header() {
+        let mut stream: &[u8] = b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 2222\r\nSSH-2.0-x\r\n";
+        let addr = read_header(&mut stream).await.unwrap();
+        assert_eq!(addr, Some("203.0.113.7:51234".parse().unwrap()));
+        assert_eq!(stream, b"SSH-2.0-x\r\n");
+
+        let mut v2 = V2_SIGNATURE.to_vec();
+        v2.extend_from_slice(&[
+            0x21, 0x11, 0, 12, 198, 51, 100, 9, 10, 0, 0, 1, 0x1f, 0x90, 0x08, 0xae,
+        ]);
+        v2.extend_from_slice(b"SSH-2.0-x\r\n");
+        let mut stream = v2.as_slice();
+        let addr = read_header(&mut stream).await.unwrap();
+        assert_eq!(addr, Some("198.51.100.9:8080".parse().unwrap()));
+        assert_eq!(stream, b"SSH-2.0-x\r\n");
+
+        let mut stream: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(read_header(&mut stream).await.unwrap(), None);
+        let mut stream: &[u8] = b"SSH-2.0-OpenSSH_9.6\r\n";
+        assert!(read_header(&mut stream).await.is_err());
+        let mut stream: &[u8] = b"PROXY TCP4 ::1 ::1 1 2\r\n";
+        assert!(read_header(&mut stream).await.is_err());
+        assert_eq!(parse_v2(0x20, 0x11, &[]).unwrap(), None);
+        assert!(parse_v2(0x21, 0x11, &[127, 0, 0, 1]).is_err());
+    }
+}
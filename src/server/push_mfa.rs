@@ -0,0 +1,157 @@
+//! Push approval of logins, in the way of Duo Push. Once the credentials of
+//! a login are accepted, `push_mfa.url` gets a signed request and answers
+//! when the user has approved or denied it on their device.
+
+use crate::common::hmac_sha256;
+use crate::config::PushMfaConfig;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Header carrying `sha256=<hex HMAC-SHA256 of the body>`
+pub const SIGNATURE_HEADER: &str = "X-Rustion-Signature";
+
+/// Body POSTed to the webhook
+#[derive(Debug, Serialize)]
+pub struct PushRequest<'a> {
+    /// Unique per request, lets the webhook drop replays
+    pub id: Uuid,
+    pub connection_id: Uuid,
+    pub user_id: Uuid,
+    pub username: &'a str,
+    pub client_ip: Option<IpAddr>,
+    /// Credentials already accepted, such as `public key and TOTP`
+    pub method: &'a str,
+    /// Milliseconds since the epoch
+    pub timestamp: i64,
+}
+
+#[derive(Deserialize)]
+struct PushResponse {
+    result: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    Allow,
+    Deny,
+    Timeout,
+    /// Webhook unreachable, an error status or an answer not understood
+    Failed(String),
+}
+
+impl Decision {
+    pub fn is_allowed(&self) -> bool {
+        *self == Decision::Allow
+    }
+
+    fn from_result(result: &str) -> Self {
+        match result {
+            "allow" => Decision::Allow,
+            "deny" => Decision::Deny,
+            other => Decision::Failed(format!("unknown result '{}'", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for Decision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Decision::Allow => write!(f, "approved"),
+            Decision::Deny => write!(f, "denied"),
+            Decision::Timeout => write!(f, "not answered in time"),
+            Decision::Failed(e) => write!(f, "failed: {}", e),
+        }
+    }
+}
+
+pub struct PushMfa {
+    client: reqwest::Client,
+    url: String,
+    secret: Vec<u8>,
+    timeout: Duration,
+}
+
+impl PushMfa {
+    /// `None` when `push_mfa.url` is unset
+    pub fn new(config: &PushMfaConfig) -> Option<Self> {
+        Some(PushMfa {
+            client: reqwest::Client::new(),
+            url: config.url.clone()?,
+            secret: config.secret.clone().unwrap_or_default().into_bytes(),
+            timeout: config.timeout,
+        })
+    }
+
+    /// Waits for the user's answer, up to `push_mfa.timeout`
+    pub async fn ask(&self, request: &PushRequest<'_>) -> Decision {
+        let body = match serde_json::to_vec(request) {
+            Ok(body) => body,
+            Err(e) => return Decision::Failed(e.to_string()),
+        };
+        let signature = sign(&self.secret, &body);
+        let exchange = async {
+            self.client
+                .post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, signature)
+                .body(body)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<PushResponse>()
+                .await
+        };
+        match tokio::time::timeout(self.timeout, exchange).await {
+            Ok(Ok(response)) => Decision::from_result(&response.result),
+            Ok(Err(e)) => Decision::Failed(e.to_string()),
+            Err(_) => Decision::Timeout,
+        }
+    }
+}
+
+/// `sha256=` and the hex HMAC-SHA256 of `body`
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    format!(
+        "sha256={}",
+        data_encoding::HEXLOWER.encode(&hmac_sha256(secret, body))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hmac::{Hmac, KeyInit, Mac};
+    use sha2::Sha256;
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231 test cases 2 and 6, the second with a key longer than a block
+        assert_eq!(
+            sign(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            sign(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            ),
+            "sha256=60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+
+        // What a webhook does with the header: a constant-time comparison
+        let body = br#"{"id":"x"}"#;
+        let signature = sign(b"secret", body);
+        let tag = data_encoding::HEXLOWER
+            .decode(signature.strip_prefix("sha256=").unwrap().as_bytes())
+            .unwrap();
+        let mac = || Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        assert!(mac().chain_update(body).verify_slice(&tag).is_ok());
+        assert!(mac().chain_update(b"{}").verify_slice(&tag).is_err());
+
+        assert_eq!(Decision::from_result("allow"), Decision::Allow);
+        assert_eq!(Decision::from_result("deny"), Decision::Deny);
+        assert!(!Decision::from_result("maybe").is_allowed());
+    }
+}
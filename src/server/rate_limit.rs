@@ -0,0 +1,201 @@
+//! Token bucket per client IP, checked before any authentication attempt
+//! touches the database. Scanners hammering the port are refused early, with
+//! bans that double while they keep coming back.
+
+use crate::config::RateLimitConfig;
+use crate::config::error::ConfigError;
+use ipnetwork::IpNetwork;
+use moka::future::Cache;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Clone)]
+pub(super) struct RateLimiter {
+    rate: f64,
+    ban: Duration,
+    max_ban: Duration,
+    whitelist: Vec<IpNetwork>,
+    buckets: Cache<IpAddr, Arc<Mutex<Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Result<Self, ConfigError> {
+        // Idle buckets are full again, dropping them loses nothing
+        let buckets = Cache::builder()
+            .time_to_idle(config.max_ban_duration.max(Duration::from_secs(60)))
+            .build();
+        let cache = buckets.clone();
+        tokio::spawn(async move {
+            loop {
+                // Expired cache will be removed every 1 minute
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                cache.run_pending_tasks().await;
+            }
+        });
+        Ok(RateLimiter {
+            rate: config.attempts_per_minute as f64,
+            ban: config.ban_duration,
+            max_ban: config.max_ban_duration,
+            whitelist: config.whitelist_networks()?,
+            buckets,
+        })
+    }
+
+    /// Takes one attempt from the bucket of `ip`. Gives how long the IP
+    /// still has to wait when it is over the rate.
+    pub async fn check(&self, ip: IpAddr) -> Option<Duration> {
+        if self.rate == 0.0 || self.whitelist.iter().any(|n| n.contains(ip)) {
+            return None;
+        }
+        let rate = self.rate;
+        let bucket = self
+            .buckets
+            .get_with(ip, async move { Arc::new(Mutex::new(Bucket::new(rate))) })
+            .await;
+        let mut bucket = bucket.lock().unwrap_or_else(|e| e.into_inner());
+        bucket.take(Instant::now(), self.rate, self.ban, self.max_ban)
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+    banned_until: Option<Instant>,
+    /// Bans in a row, each one twice as long as the previous
+    strikes: u32,
+}
+
+impl Bucket {
+    fn new(capacity: f64) -> Self {
+        Bucket {
+            tokens: capacity,
+            refilled_at: Instant::now(),
+            banned_until: None,
+            strikes: 0,
+        }
+    }
+
+    fn take(
+        &mut self,
+        now: Instant,
+        per_minute: f64,
+        ban: Duration,
+        max_ban: Duration,
+    ) -> Option<Duration> {
+        if let Some(until) = self.banned_until {
+            if now < until {
+                return Some(until - now);
+            }
+            self.banned_until = None;
+        }
+
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        if elapsed >= max_ban {
+            // Quiet for a full max ban, the streak is over
+            self.strikes = 0;
+        }
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * per_minute / 60.0).min(per_minute);
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return None;
+        }
+
+        let wait = ban
+            .saturating_mul(2u32.saturating_pow(self.strikes))
+            .min(max_ban);
+        self.strikes = self.strikes.saturating_add(1);
+        let until = now + wait;
+        self.banned_until = Some(until);
+        // Leaves the ban with a full bucket
+        self.tokens = per_minute;
+        self.refilled_at = until;
+        Some(wait)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BAN: Duration = Duration::from_secs(60);
+    const MAX_BAN: Duration = Duration::from_secs(300);
+
+    #[test]
+    fn test_bucket_backoff() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(3.0);
+        for _ in 0..3 {
+            assert_eq!(bucket.take(start, 3.0, BAN, MAX_BAN), None);
+        }
+        assert_eq!(bucket.take(start, 3.0, BAN, MAX_BAN), Some(BAN));
+        let later = start + Duration::from_secs(20);
+        assert_eq!(
+            bucket.take(later, 3.0, BAN, MAX_BAN),
+            Some(Duration::from_secs(40))
+        );
+
+        // Out of the ban with a full bucket, emptying it again doubles the ban
+        let after = start + BAN;
+        for _ in 0..3 {
+            assert_eq!(bucket.take(after, 3.0, BAN, MAX_BAN), None);
+        }
+        assert_eq!(bucket.take(after, 3.0, BAN, MAX_BAN), Some(2 * BAN));
+        let after = after + 2 * BAN;
+        for _ in 0..3 {
+            assert_eq!(bucket.take(after, 3.0, BAN, MAX_BAN), None);
+        }
+        assert_eq!(bucket.take(after, 3.0, BAN, MAX_BAN), Some(4 * BAN));
+        let after = after + 4 * BAN;
+        for _ in 0..3 {
+            assert_eq!(bucket.take(after, 3.0, BAN, MAX_BAN), None);
+        }
+        assert_eq!(bucket.take(after, 3.0, BAN, MAX_BAN), Some(MAX_BAN));
+
+        // Staying away for a full max ban starts over
+        let quiet = after + 2 * MAX_BAN;
+        for _ in 0..3 {
+            assert_eq!(bucket.take(quiet, 3.0, BAN, MAX_BAN), None);
+        }
+        assert_eq!(bucket.take(quiet, 3.0, BAN, MAX_BAN), Some(BAN));
+    }
+
+    #[test]
+    fn test_bucket_refill() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(2.0);
+        assert_eq!(bucket.take(start, 2.0, BAN, MAX_BAN), None);
+        assert_eq!(bucket.take(start, 2.0, BAN, MAX_BAN), None);
+        // One attempt comes back every 30 seconds
+        let later = start + Duration::from_secs(30);
+        assert_eq!(bucket.take(later, 2.0, BAN, MAX_BAN), None);
+        assert_eq!(bucket.take(later, 2.0, BAN, MAX_BAN), Some(BAN));
+    }
+
+    #[tokio::test]
+    async fn test_whitelist() {
+        let config = RateLimitConfig {
+            attempts_per_minute: 1,
+            whitelist: vec!["10.0.0.0/8".to_string()],
+            ..RateLimitConfig::default()
+        };
+        let limiter = RateLimiter::new(&config).unwrap();
+        let trusted: IpAddr = "10.1.2.3".parse().unwrap();
+        let scanner: IpAddr = "192.0.2.1".parse().unwrap();
+        for _ in 0..5 {
+            assert_eq!(limiter.check(trusted).await, None);
+        }
+        assert_eq!(limiter.check(scanner).await, None);
+        assert_eq!(limiter.check(scanner).await, Some(config.ban_duration));
+
+        let off = RateLimiter::new(&RateLimitConfig {
+            attempts_per_minute: 0,
+            ..RateLimitConfig::default()
+        })
+        .unwrap();
+        for _ in 0..100 {
+            assert_eq!(off.check(scanner).await, None);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Reconnection tokens. A shell bridged over a pooled target handle gets a
+//! token on its stderr. Should the client drop, the token logs the same user
+//! into the same `account@target` once, within `reconnect_token_ttl`, in
+//! place of the credentials and second factors, and the login picks the
+//! still-pooled handle up again.
+
+use crate::database::Uuid;
+use crate::database::models::ApiToken;
+use rand::distr::{Alphanumeric, SampleString};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const TOKEN_PREFIX: &str = "rc-";
+const TOKEN_LEN: usize = 24;
+
+struct Pending {
+    user_id: Uuid,
+    /// `account@target` the token logs into
+    target: String,
+    /// `None` while the session is still bridged
+    expires_at: Option<Instant>,
+}
+
+/// Tokens are kept as SHA-256 hashes, like API tokens
+pub struct ReconnectTokens {
+    ttl: Duration,
+    tokens: Mutex<HashMap<String, Pending>>,
+}
+
+impl ReconnectTokens {
+    pub fn new(ttl: Duration) -> Self {
+        ReconnectTokens {
+            ttl,
+            tokens: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Token of a session that just started, unusable until `arm`
+    pub fn issue(&self, user_id: Uuid, target: String) -> String {
+        let token = format!(
+            "{}{}",
+            TOKEN_PREFIX,
+            Alphanumeric.sample_string(&mut rand::rng(), TOKEN_LEN)
+        );
+        let now = Instant::now();
+        let mut tokens = self.lock();
+        tokens.retain(|_, p| p.expires_at.is_none_or(|e| e > now));
+        tokens.insert(
+            ApiToken::hash_token(&token),
+            Pending {
+                user_id,
+                target,
+                expires_at: None,
+            },
+        );
+        token
+    }
+
+    /// The client went away, the token is good for `ttl` from now
+    pub fn arm(&self, token: &str) {
+        if let Some(p) = self.lock().get_mut(&ApiToken::hash_token(token)) {
+            p.expires_at = Some(Instant::now() + self.ttl);
+        }
+    }
+
+    /// The session ended on its own, nothing to come back to
+    pub fn discard(&self, token: &str) {
+        self.lock().remove(&ApiToken::hash_token(token));
+    }
+
+    /// Uses up an armed, unexpired token of `user_id` for `target`
+    pub fn redeem(&self, token: &str, user_id: &Uuid, target: &str) -> bool {
+        if !token.starts_with(TOKEN_PREFIX) {
+            return false;
+        }
+        let hash = ApiToken::hash_token(token);
+        let mut tokens = self.lock();
+        let usable = tokens.get(&hash).is_some_and(|p| {
+            p.user_id == *user_id
+                && p.target == target
+                && p.expires_at.is_some_and(|e| e > Instant::now())
+        });
+        if usable {
+            tokens.remove(&hash);
+        }
+        usable
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Pending>> {
+        self.tokens.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_tokens() {
+        let tokens = ReconnectTokens::new(Duration::from_secs(300));
+        let user = Uuid::new_v4();
+        let token = tokens.issue(user, "root@web1".to_string());
+
+        // Not before the client dropped
+        assert!(!tokens.redeem(&token, &user, "root@web1"));
+        tokens.arm(&token);
+        assert!(!tokens.redeem(&token, &Uuid::new_v4(), "root@web1"));
+        assert!(!tokens.redeem(&token, &user, "root@web2"));
+        assert!(tokens.redeem(&token, &user, "root@web1"));
+        // Single use
+        assert!(!tokens.redeem(&token, &user, "root@web1"));
+
+        let token = tokens.issue(user, "root@web1".to_string());
+        tokens.discard(&token);
+        tokens.arm(&token);
+        assert!(!tokens.redeem(&token, &user, "root@web1"));
+
+        let expired = ReconnectTokens::new(Duration::ZERO);
+        let token = expired.issue(user, "root@web1".to_string());
+        expired.arm(&token);
+        assert!(!expired.redeem(&token, &user, "root@web1"));
+    }
+}
@@ -0,0 +1,99 @@
+//! Directories and rights of recording files. The recorder only appends to
+//! the file, it is created here first so it never exists, even empty, with
+//! wider rights than `record_files` asks for.
+
+use crate::config::RecordFilesConfig;
+use crate::config::error::ConfigError;
+use std::fs::Permissions;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+pub struct RecordFiles {
+    per_target: bool,
+    file_mode: Option<u32>,
+    gid: Option<u32>,
+}
+
+impl RecordFiles {
+    pub fn new(config: &RecordFilesConfig) -> Result<Self, ConfigError> {
+        Ok(RecordFiles {
+            per_target: config.per_target,
+            file_mode: config.file_mode,
+            gid: config.gid()?,
+        })
+    }
+
+    pub fn per_target(&self) -> bool {
+        self.per_target
+    }
+
+    /// Creates `file_path` below `root` along with its directories, returns
+    /// the full path for the recorder
+    pub fn prepare(&self, root: &Path, file_path: &str) -> io::Result<PathBuf> {
+        std::fs::create_dir_all(root)?;
+        let mut dir = root.to_path_buf();
+        let parents = Path::new(file_path).parent().into_iter();
+        for part in parents.flat_map(|p| p.components()) {
+            dir.push(part);
+            match std::fs::create_dir(&dir) {
+                Ok(()) => self.apply(&dir, true)?,
+                // Another session of the same target got there first
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let path = root.join(file_path);
+        std::fs::File::create(&path)?;
+        self.apply(&path, false)?;
+        Ok(path)
+    }
+
+    fn apply(&self, path: &Path, is_dir: bool) -> io::Result<()> {
+        if let Some(gid) = self.gid {
+            std::os::unix::fs::chown(path, None, Some(gid))?;
+        }
+        if let Some(mode) = self.file_mode {
+            let mode = if is_dir { dir_mode(mode) } else { mode };
+            std::fs::set_permissions(path, Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    }
+}
+
+/// Whoever may read the files may also enter their directory
+fn dir_mode(file_mode: u32) -> u32 {
+    file_mode | ((file_mode & 0o444) >> 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prepare() {
+        assert_eq!(dir_mode(0o640), 0o750);
+        assert_eq!(dir_mode(0o600), 0o700);
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("record");
+        let config = RecordFilesConfig {
+            per_target: true,
+            file_mode: Some(0o640),
+            group: None,
+        };
+        let files = RecordFiles::new(&config).unwrap();
+        let path = files.prepare(&root, "target/secret/id.cast").unwrap();
+        assert_eq!(path, root.join("target/secret/id.cast"));
+
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o640);
+        assert_eq!(mode(&root.join("target")), 0o750);
+        assert_eq!(mode(&root.join("target/secret")), 0o750);
+
+        // A second recording of the same target reuses the directories
+        let other = files.prepare(&root, "target/secret/other.cast").unwrap();
+        assert_eq!(mode(&other), 0o640);
+    }
+}
@@ -0,0 +1,213 @@
+//! Retention of recordings. Every `record_retention.interval` the completed
+//! recordings older than `max_age`, then the oldest ones beyond `max_size`
+//! bytes, are purged: deleted, or moved below `archive_path`. A target named
+//! in `targets` follows its own limits, and its recordings count against its
+//! own `max_size` only. Each purged recording is logged with type
+//! `record_retention` and its row kept with status `purged` or `archived`.
+
+use crate::config::RecordRetentionConfig;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Limits {
+    max_age: Option<Duration>,
+    max_size: Option<u64>,
+}
+
+impl Limits {
+    fn is_off(&self) -> bool {
+        self.max_age.is_none() && self.max_size.is_none()
+    }
+}
+
+/// A completed recording, as far as retention looks at it
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    /// Name of its target
+    pub target: String,
+    pub ended_at: DateTime<Utc>,
+    /// Bytes, 0 when no limit needs them
+    pub size: u64,
+}
+
+/// Why a recording is purged
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    Age(Duration),
+    Size(u64),
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Age(max) => write!(f, "older than {}", humantime::format_duration(*max)),
+            Reason::Size(max) => write!(f, "beyond {} bytes of recordings", max),
+        }
+    }
+}
+
+pub struct Retention {
+    default: Limits,
+    targets: HashMap<String, Limits>,
+}
+
+impl Retention {
+    /// `None` when no limit is set anywhere
+    pub fn new(config: &RecordRetentionConfig) -> Option<Self> {
+        let retention = Retention {
+            default: Limits {
+                max_age: config.max_age,
+                max_size: config.max_size,
+            },
+            targets: config
+                .targets
+                .iter()
+                .map(|t| {
+                    let limits = Limits {
+                        max_age: t.max_age,
+                        max_size: t.max_size,
+                    };
+                    (t.target.clone(), limits)
+                })
+                .collect(),
+        };
+        let off = retention.default.is_off() && retention.targets.values().all(Limits::is_off);
+        (!off).then_some(retention)
+    }
+
+    /// Whether the recordings of `target` need their size
+    pub fn sizes_target(&self, target: &str) -> bool {
+        self.limits(target).max_size.is_some()
+    }
+
+    fn limits(&self, target: &str) -> &Limits {
+        self.targets.get(target).unwrap_or(&self.default)
+    }
+
+    /// Indexes of the recordings to purge, oldest first within each target
+    /// group
+    pub fn select(&self, candidates: &[Candidate], now: DateTime<Utc>) -> Vec<(usize, Reason)> {
+        // Listed targets are a group each, the others share the default one
+        let mut groups: HashMap<Option<&str>, Vec<usize>> = HashMap::new();
+        for (i, c) in candidates.iter().enumerate() {
+            let group = self
+                .targets
+                .contains_key(&c.target)
+                .then_some(c.target.as_str());
+            groups.entry(group).or_default().push(i);
+        }
+
+        let mut purged = Vec::new();
+        for (group, mut members) in groups {
+            let limits = group.map_or(&self.default, |t| self.limits(t));
+            members.sort_by_key(|i| candidates[*i].ended_at);
+            let mut kept = Vec::new();
+            for i in members {
+                let age = (now - candidates[i].ended_at).to_std().unwrap_or_default();
+                match limits.max_age {
+                    Some(max) if age > max => purged.push((i, Reason::Age(max))),
+                    _ => kept.push(i),
+                }
+            }
+            if let Some(max) = limits.max_size {
+                let mut total: u64 = kept.iter().map(|i| candidates[*i].size).sum();
+                for i in kept {
+                    if total <= max {
+                        break;
+                    }
+                    total -= candidates[i].size;
+                    purged.push((i, Reason::Size(max)));
+                }
+            }
+        }
+        purged
+    }
+}
+
+/// Moves `from` to `to`, copying across filesystems
+pub fn archive(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(dir) = to.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    match std::fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            std::fs::copy(from, to)?;
+            std::fs::remove_file(from)
+        }
+        res => res,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TargetRetentionConfig;
+
+    const DAY: Duration = Duration::from_secs(86400);
+
+    fn candidate(target: &str, days_ago: i64, size: u64, now: DateTime<Utc>) -> Candidate {
+        Candidate {
+            target: target.to_string(),
+            ended_at: now - chrono::TimeDelta::days(days_ago),
+            size,
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        assert!(Retention::new(&RecordRetentionConfig::default()).is_none());
+
+        let config = RecordRetentionConfig {
+            max_age: Some(30 * DAY),
+            max_size: Some(100),
+            targets: vec![TargetRetentionConfig {
+                target: "prod-db".to_string(),
+                max_age: Some(365 * DAY),
+                max_size: None,
+            }],
+            ..Default::default()
+        };
+        let retention = Retention::new(&config).unwrap();
+        assert!(retention.sizes_target("web"));
+        assert!(!retention.sizes_target("prod-db"));
+
+        let now = Utc::now();
+        let candidates = [
+            candidate("web", 40, 10, now),
+            candidate("web", 3, 60, now),
+            candidate("web", 2, 30, now),
+            candidate("web", 1, 20, now),
+            candidate("prod-db", 40, 1000, now),
+            candidate("prod-db", 400, 1000, now),
+        ];
+        let mut purged = retention.select(&candidates, now);
+        purged.sort_by_key(|(i, _)| *i);
+        assert_eq!(
+            purged,
+            vec![
+                (0, Reason::Age(30 * DAY)),
+                // 110 bytes left after the age limit, the oldest goes
+                (1, Reason::Size(100)),
+                (5, Reason::Age(365 * DAY)),
+            ]
+        );
+        assert_eq!(Reason::Age(30 * DAY).to_string(), "older than 30days");
+    }
+
+    #[test]
+    fn test_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("record/t/s/id.cast");
+        std::fs::create_dir_all(from.parent().unwrap()).unwrap();
+        std::fs::write(&from, "{}").unwrap();
+        let to = dir.path().join("archive/t/s/id.cast");
+        archive(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert_eq!(std::fs::read_to_string(&to).unwrap(), "{}");
+    }
+}
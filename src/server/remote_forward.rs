@@ -0,0 +1,66 @@
+//! Remote forwards (`ssh -R`) requested by clients are bound on the target,
+//! and the `forwarded-tcpip` channels it opens for them arrive at the
+//! target's `ru_client::Handler`. `RemoteForwards` hands each of them to the
+//! session that asked for the listener. A target can bind an address and
+//! port once, so that is enough to find the session, pooled handle or not.
+
+use crate::database::Uuid;
+use russh::Channel;
+use russh::client as ru_client;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+
+/// A connection the target accepted on a forwarded listener
+pub struct ForwardedTcpip {
+    pub channel: Channel<ru_client::Msg>,
+    pub connected_address: String,
+    pub connected_port: u32,
+    pub originator_address: String,
+    pub originator_port: u32,
+}
+
+type Route = (Uuid, String, u32);
+
+#[derive(Debug, Default)]
+pub struct RemoteForwards {
+    routes: Mutex<HashMap<Route, mpsc::UnboundedSender<ForwardedTcpip>>>,
+}
+
+impl RemoteForwards {
+    /// Sends the connections to `address:port` of `target_id` to `sender`
+    pub fn register(
+        &self,
+        target_id: Uuid,
+        address: &str,
+        port: u32,
+        sender: mpsc::UnboundedSender<ForwardedTcpip>,
+    ) {
+        self.routes
+            .lock()
+            .unwrap()
+            .insert((target_id, address.to_string(), port), sender);
+    }
+
+    pub fn unregister(&self, target_id: Uuid, address: &str, port: u32) {
+        self.routes
+            .lock()
+            .unwrap()
+            .remove(&(target_id, address.to_string(), port));
+    }
+
+    /// Passes `forwarded` on to its session, handing it back when no
+    /// session listens there anymore
+    pub fn route(&self, target_id: Uuid, forwarded: ForwardedTcpip) -> Result<(), ForwardedTcpip> {
+        let key = (
+            target_id,
+            forwarded.connected_address.clone(),
+            forwarded.connected_port,
+        );
+        let routes = self.routes.lock().unwrap();
+        match routes.get(&key) {
+            Some(sender) => sender.send(forwarded).map_err(|e| e.0),
+            None => Err(forwarded),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Text renderings of the role graphs of `RoleManage`, for `rustion
+//! role-graph` and the admin `role_graph` command. Edges go the way the
+//! enforcer walks them: from a user or role to the roles it inherits, from
+//! a target or action group to its members.
+
+use crate::server::casbin::{GroupType, RuleGroup, Type};
+use petgraph::Direction::{Incoming, Outgoing};
+use petgraph::stable_graph::{NodeIndex, StableDiGraph};
+use std::fmt::Write;
+
+/// Names the graphs go by on the command line
+pub const KINDS: [(&str, GroupType); 3] = [
+    ("subject", GroupType::Subject),
+    ("object", GroupType::Object),
+    ("action", GroupType::Action),
+];
+
+fn graph_name(rt: GroupType) -> &'static str {
+    match rt {
+        GroupType::Subject => "subjects",
+        GroupType::Object => "objects",
+        GroupType::Action => "actions",
+    }
+}
+
+fn quote(label: &str) -> String {
+    format!("\"{}\"", label.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Graphviz digraph, groups drawn as bold boxes
+pub fn to_dot(graph: &StableDiGraph<RuleGroup, ()>, rt: GroupType) -> String {
+    let mut out = format!("digraph {} {{\n    rankdir=LR;\n", graph_name(rt));
+    for n in graph.node_indices() {
+        let shape = match graph[n].get_type() {
+            Type::Group => " shape=box style=bold",
+            Type::Object => "",
+        };
+        let _ = writeln!(
+            out,
+            "    n{} [label={}{}];",
+            n.index(),
+            quote(&graph[n].label()),
+            shape
+        );
+    }
+    for e in graph.edge_indices() {
+        if let Some((from, to)) = graph.edge_endpoints(e) {
+            let _ = writeln!(out, "    n{} -> n{};", from.index(), to.index());
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Indented tree from every node with edges out and none in, laid out like
+/// the admin tree. A node reached again below itself is marked `(cycle)`
+/// and not followed.
+pub fn to_tree(graph: &StableDiGraph<RuleGroup, ()>) -> String {
+    let mut roots: Vec<_> = graph
+        .node_indices()
+        .filter(|&n| {
+            graph.neighbors_directed(n, Outgoing).next().is_some()
+                && graph.neighbors_directed(n, Incoming).next().is_none()
+        })
+        .collect();
+    roots.sort_by_key(|&n| graph[n].label());
+
+    let mut out = String::new();
+    for root in roots {
+        let _ = writeln!(out, "{}", graph[root].label());
+        tree_children(graph, root, "", &mut vec![root], &mut out);
+    }
+    out
+}
+
+fn tree_children(
+    graph: &StableDiGraph<RuleGroup, ()>,
+    node: NodeIndex,
+    prefix: &str,
+    path: &mut Vec<NodeIndex>,
+    out: &mut String,
+) {
+    let mut children: Vec<_> = graph.neighbors_directed(node, Outgoing).collect();
+    children.sort_by_key(|&n| graph[n].label());
+    let count = children.len();
+    for (i, child) in children.into_iter().enumerate() {
+        let last = i + 1 == count;
+        let (branch, indent) = if last {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+        if path.contains(&child) {
+            let _ = writeln!(out, "{}{}{} (cycle)", prefix, branch, graph[child].label());
+            continue;
+        }
+        let _ = writeln!(out, "{}{}{}", prefix, branch, graph[child].label());
+        path.push(child);
+        tree_children(graph, child, &format!("{}{}", prefix, indent), path, out);
+        path.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Uuid;
+    use crate::server::casbin::{Group, Label};
+
+    fn node(label: Label) -> RuleGroup {
+        RuleGroup::V1(Group {
+            id: Uuid::new_v4(),
+            v: Uuid::new_v4(),
+            label,
+        })
+    }
+
+    #[test]
+    fn test_role_graph() {
+        let mut g = StableDiGraph::new();
+        let prod = g.add_node(node(Label::Group("prod".to_string())));
+        let db = g.add_node(node(Label::Group("db \"main\"".to_string())));
+        let web = g.add_node(node(Label::Object("root@web".to_string())));
+        let pg = g.add_node(node(Label::Object("postgres@pg".to_string())));
+        g.add_edge(prod, web, ());
+        g.add_edge(prod, db, ());
+        g.add_edge(db, pg, ());
+
+        assert_eq!(
+            to_tree(&g),
+            "prod\n├── db \"main\"\n│   └── postgres@pg\n└── root@web\n"
+        );
+
+        let dot = to_dot(&g, GroupType::Object);
+        assert!(dot.starts_with("digraph objects {\n"));
+        assert!(dot.contains("n1 [label=\"db \\\"main\\\"\" shape=box style=bold];"));
+        assert!(dot.contains("n2 [label=\"root@web\"];"));
+        assert!(dot.contains("n0 -> n1;"));
+        assert!(dot.ends_with("}\n"));
+
+        // a group holding itself through another one
+        g.add_edge(pg, db, ());
+        assert!(to_tree(&g).contains("│       └── db \"main\" (cycle)\n"));
+    }
+}
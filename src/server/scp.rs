@@ -0,0 +1,268 @@
+//! Legacy scp, run by the client as an exec of `scp -t <path>` (upload) or
+//! `scp -f <path>` (download) on the target. Such execs are granted by the
+//! `scp` action rather than `exec`, and `ScpAudit` reads the file headers
+//! of the sending side to log what was copied.
+
+use std::fmt;
+
+/// Longest control line followed, longer ones mean the stream isn't scp
+const MAX_LINE: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScpCommand {
+    /// `-t`, files go from the client to the target
+    pub upload: bool,
+    pub recursive: bool,
+    /// Path given on the target
+    pub path: String,
+}
+
+impl ScpCommand {
+    /// The scp run behind an exec request, `None` for any other command
+    pub fn parse(command: &[u8]) -> Option<Self> {
+        let command = std::str::from_utf8(command).ok()?;
+        let mut words = command.split_whitespace();
+        let program = words.next()?;
+        if program != "scp" && !program.ends_with("/scp") {
+            return None;
+        }
+        let (mut to, mut from, mut recursive) = (false, false, false);
+        let mut path = Vec::new();
+        for word in words.by_ref() {
+            if word == "--" {
+                break;
+            }
+            match word.strip_prefix('-') {
+                Some(flags) if path.is_empty() && !flags.is_empty() => {
+                    for flag in flags.chars() {
+                        match flag {
+                            't' => to = true,
+                            'f' => from = true,
+                            'r' => recursive = true,
+                            _ => {}
+                        }
+                    }
+                }
+                _ => path.push(word),
+            }
+        }
+        path.extend(words);
+        if to == from || path.is_empty() {
+            return None;
+        }
+        Some(Self {
+            upload: to,
+            recursive,
+            path: path.join(" "),
+        })
+    }
+}
+
+/// One file copied, with its path under the directories sent before it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScpFile {
+    pub upload: bool,
+    pub name: String,
+    pub bytes: u64,
+    /// Path of the command on the target
+    pub target_path: String,
+}
+
+impl fmt::Display for ScpFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.upload {
+            write!(
+                f,
+                "upload {} ({} bytes) to {}",
+                self.name, self.bytes, self.target_path
+            )
+        } else {
+            write!(
+                f,
+                "download {} ({} bytes) from {}",
+                self.name, self.bytes, self.target_path
+            )
+        }
+    }
+}
+
+enum State {
+    /// Reading a control line
+    Line(Vec<u8>),
+    /// File contents with `left` bytes to go
+    Data { name: String, size: u64, left: u64 },
+    /// The status byte after the contents
+    Status,
+    /// The stream didn't parse
+    Lost,
+}
+
+pub struct ScpAudit {
+    command: ScpCommand,
+    state: State,
+    dirs: Vec<String>,
+}
+
+impl ScpAudit {
+    pub fn new(command: ScpCommand) -> Self {
+        Self {
+            command,
+            state: State::Line(Vec::new()),
+            dirs: Vec::new(),
+        }
+    }
+
+    pub fn is_following(&self) -> bool {
+        !matches!(self.state, State::Lost)
+    }
+
+    /// Takes bytes sent by the client, files come from it on uploads
+    pub fn client_data(&mut self, data: &[u8]) -> Vec<ScpFile> {
+        if self.command.upload {
+            self.sender_data(data)
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Takes bytes sent by the target, files come from it on downloads
+    pub fn target_data(&mut self, data: &[u8]) -> Vec<ScpFile> {
+        if self.command.upload {
+            Vec::new()
+        } else {
+            self.sender_data(data)
+        }
+    }
+
+    fn sender_data(&mut self, mut data: &[u8]) -> Vec<ScpFile> {
+        let mut files = Vec::new();
+        while !data.is_empty() {
+            match &mut self.state {
+                State::Lost => break,
+                State::Line(line) => {
+                    let end = data.iter().position(|&b| b == b'\n');
+                    let (head, rest) = data.split_at(end.map_or(data.len(), |i| i + 1));
+                    line.extend_from_slice(head);
+                    data = rest;
+                    if end.is_some() {
+                        let line = std::mem::take(line);
+                        self.state = self.control(&line);
+                    } else if line.len() > MAX_LINE {
+                        self.state = State::Lost;
+                    }
+                }
+                State::Data { name, size, left } => {
+                    let n = (*left).min(data.len() as u64);
+                    *left -= n;
+                    data = &data[n as usize..];
+                    if *left == 0 {
+                        files.push(ScpFile {
+                            upload: self.command.upload,
+                            name: std::mem::take(name),
+                            bytes: *size,
+                            target_path: self.command.path.clone(),
+                        });
+                        self.state = State::Status;
+                    }
+                }
+                State::Status => {
+                    data = &data[1..];
+                    self.state = State::Line(Vec::new());
+                }
+            }
+        }
+        files
+    }
+
+    /// What follows the control line `line`, `\n` included
+    fn control(&mut self, line: &[u8]) -> State {
+        let line = String::from_utf8_lossy(&line[..line.len() - 1]);
+        let Some(kind) = line.chars().next() else {
+            return State::Lost;
+        };
+        match kind {
+            // `C<mode> <size> <name>` for a file, `D<mode> 0 <name>` to
+            // enter a directory
+            'C' | 'D' => {
+                let mut fields = line[1..].splitn(3, ' ');
+                let (Some(_), Some(size), Some(name)) =
+                    (fields.next(), fields.next(), fields.next())
+                else {
+                    return State::Lost;
+                };
+                let Ok(size) = size.parse::<u64>() else {
+                    return State::Lost;
+                };
+                if kind == 'D' {
+                    self.dirs.push(name.to_string());
+                    return State::Line(Vec::new());
+                }
+                let mut path = self.dirs.join("/");
+                if !path.is_empty() {
+                    path.push('/');
+                }
+                path.push_str(name);
+                State::Data {
+                    name: path,
+                    size,
+                    left: size,
+                }
+            }
+            'E' => {
+                self.dirs.pop();
+                State::Line(Vec::new())
+            }
+            // Times of the next file or directory, and errors the sender
+            // passes on
+            'T' | '\x01' | '\x02' => State::Line(Vec::new()),
+            _ => State::Lost,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scp_command() {
+        let cmd = ScpCommand::parse(b"scp -v -r -t -- /srv/my dir").unwrap();
+        assert!(cmd.upload && cmd.recursive);
+        assert_eq!(cmd.path, "/srv/my dir");
+        let cmd = ScpCommand::parse(b"/usr/bin/scp -pf /etc/hosts").unwrap();
+        assert!(!cmd.upload && !cmd.recursive);
+        assert_eq!(cmd.path, "/etc/hosts");
+
+        assert_eq!(ScpCommand::parse(b"scp /etc/hosts"), None);
+        assert_eq!(ScpCommand::parse(b"scp -t -f /x"), None);
+        assert_eq!(ScpCommand::parse(b"scpx -t /x"), None);
+        assert_eq!(ScpCommand::parse(b"ls -t /x"), None);
+    }
+
+    #[test]
+    fn test_scp_audit() {
+        let cmd = ScpCommand::parse(b"scp -r -t /srv").unwrap();
+        let mut audit = ScpAudit::new(cmd);
+        assert!(audit.target_data(b"\0").is_empty());
+
+        let stream =
+            b"D0755 0 conf\nT1 0 1 0\nC0644 5 a.txt\nhello\0C0600 0 empty\n\0E\nC0644 3 b\nxyz\0";
+        // split inside a control line and inside the contents
+        let mut files = audit.client_data(&stream[..20]);
+        files.extend(audit.client_data(&stream[20..40]));
+        files.extend(audit.client_data(&stream[40..]));
+        let logged: Vec<String> = files.iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            logged,
+            [
+                "upload conf/a.txt (5 bytes) to /srv",
+                "upload conf/empty (0 bytes) to /srv",
+                "upload b (3 bytes) to /srv",
+            ]
+        );
+        assert!(audit.is_following());
+
+        audit.client_data(b"garbage\n");
+        assert!(!audit.is_following());
+    }
+}
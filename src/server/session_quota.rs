@@ -0,0 +1,141 @@
+//! Session quotas. A policy with the `quota=<n>/<day|week>` flag lets a user
+//! open `n` sessions per UTC day, or per week starting Monday, on the
+//! bindings the policy covers. Sessions are counted from the `sessions`
+//! table, one per bridged channel.
+
+use crate::database::Uuid;
+use crate::server::error::ExtendPolicyParseError;
+use chrono::{DateTime, Datelike, NaiveTime, TimeDelta, Utc};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuotaPeriod {
+    Day,
+    Week,
+}
+
+impl QuotaPeriod {
+    fn as_str(&self) -> &'static str {
+        match self {
+            QuotaPeriod::Day => "day",
+            QuotaPeriod::Week => "week",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SessionQuota {
+    pub max: u32,
+    pub period: QuotaPeriod,
+}
+
+impl SessionQuota {
+    /// Start of the period `now` falls in
+    pub fn since(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let midnight = now.date_naive().and_time(NaiveTime::MIN).and_utc();
+        match self.period {
+            QuotaPeriod::Day => midnight,
+            QuotaPeriod::Week => {
+                midnight - TimeDelta::days(now.weekday().num_days_from_monday().into())
+            }
+        }
+    }
+}
+
+impl fmt::Display for SessionQuota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.max, self.period.as_str())
+    }
+}
+
+impl FromStr for SessionQuota {
+    type Err = ExtendPolicyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ExtendPolicyParseError::InvalidQuota {
+            input: s.to_string(),
+        };
+        let (max, period) = s.split_once('/').ok_or_else(invalid)?;
+        let period = match period {
+            "day" => QuotaPeriod::Day,
+            "week" => QuotaPeriod::Week,
+            _ => return Err(invalid()),
+        };
+        Ok(SessionQuota {
+            max: max.parse().map_err(|_| invalid())?,
+            period,
+        })
+    }
+}
+
+/// Sessions counted against the quota of one policy
+#[derive(Debug, Clone)]
+pub struct QuotaUsage {
+    pub rule_id: Uuid,
+    /// Name of the target, binding or group the policy covers
+    pub object: String,
+    pub quota: SessionQuota,
+    pub used: u32,
+}
+
+impl QuotaUsage {
+    pub fn left(&self) -> u32 {
+        self.quota.max.saturating_sub(self.used)
+    }
+}
+
+impl fmt::Display for QuotaUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let period = match self.quota.period {
+            QuotaPeriod::Day => "today",
+            QuotaPeriod::Week => "this week",
+        };
+        write!(
+            f,
+            "{}: {}/{} sessions left {}",
+            self.object,
+            self.left(),
+            self.quota.max,
+            period
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_session_quota() {
+        let quota: SessionQuota = "20/day".parse().unwrap();
+        assert_eq!(quota.max, 20);
+        assert_eq!(quota.period, QuotaPeriod::Day);
+        assert_eq!(quota.to_string(), "20/day");
+        for bad in ["20", "20/month", "x/week", "-1/day"] {
+            assert!(bad.parse::<SessionQuota>().is_err(), "{}", bad);
+        }
+
+        // A Thursday afternoon
+        let now = Utc.with_ymd_and_hms(2025, 9, 11, 15, 30, 0).unwrap();
+        assert_eq!(
+            quota.since(now),
+            Utc.with_ymd_and_hms(2025, 9, 11, 0, 0, 0).unwrap()
+        );
+        let weekly: SessionQuota = "100/week".parse().unwrap();
+        assert_eq!(
+            weekly.since(now),
+            Utc.with_ymd_and_hms(2025, 9, 8, 0, 0, 0).unwrap()
+        );
+
+        let usage = QuotaUsage {
+            rule_id: Uuid::nil(),
+            object: "prod".to_string(),
+            quota,
+            used: 25,
+        };
+        assert_eq!(usage.left(), 0);
+        assert_eq!(usage.to_string(), "prod: 0/20 sessions left today");
+    }
+}
@@ -0,0 +1,424 @@
+//! Follows an SFTP (version 3) conversation passing through a bridged
+//! `sftp` subsystem channel, just far enough to tell which files were
+//! downloaded, uploaded, removed or renamed and how many bytes moved.
+//! Packets are passed on untouched; the audit only reads copies of them.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+/// Subsystem name of SFTP, the one subsystem bridged without a `subsystems`
+/// entry
+pub const SFTP_SUBSYSTEM: &str = "sftp";
+
+/// Largest packet followed, as in OpenSSH. A longer one means the stream
+/// isn't SFTP and the audit stops.
+const MAX_PACKET: usize = 256 * 1024;
+/// Pending requests of each kind, and open files, followed at once. Beyond
+/// it new ones go unaudited, a client can't grow the audit without bound.
+const MAX_TRACKED: usize = 1024;
+/// Longest handle a server may give (draft-ietf-secsh-filexfer-02), longer
+/// ones aren't followed
+const MAX_HANDLE: usize = 256;
+
+const FXP_OPEN: u8 = 3;
+const FXP_CLOSE: u8 = 4;
+const FXP_READ: u8 = 5;
+const FXP_WRITE: u8 = 6;
+const FXP_REMOVE: u8 = 13;
+const FXP_MKDIR: u8 = 14;
+const FXP_RMDIR: u8 = 15;
+const FXP_RENAME: u8 = 18;
+const FXP_STATUS: u8 = 101;
+const FXP_HANDLE: u8 = 102;
+const FXP_DATA: u8 = 103;
+
+const FXF_WRITE: u32 = 0x02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Target to client
+    Download,
+    /// Client to target
+    Upload,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SftpEvent {
+    Transfer {
+        path: String,
+        direction: Direction,
+        bytes: u64,
+    },
+    Remove(String),
+    Rename(String, String),
+    Mkdir(String),
+    Rmdir(String),
+}
+
+impl fmt::Display for SftpEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SftpEvent::Transfer {
+                path,
+                direction,
+                bytes,
+            } => {
+                let what = match direction {
+                    Direction::Download => "download",
+                    Direction::Upload => "upload",
+                };
+                write!(f, "{} {} ({} bytes)", what, path, bytes)
+            }
+            SftpEvent::Remove(path) => write!(f, "remove {}", path),
+            SftpEvent::Rename(from, to) => write!(f, "rename {} -> {}", from, to),
+            SftpEvent::Mkdir(path) => write!(f, "mkdir {}", path),
+            SftpEvent::Rmdir(path) => write!(f, "rmdir {}", path),
+        }
+    }
+}
+
+/// A file opened by the client, from the handle the target gave it
+struct OpenFile {
+    path: String,
+    write: bool,
+    read: u64,
+    written: u64,
+}
+
+impl OpenFile {
+    fn into_event(self) -> SftpEvent {
+        let (direction, bytes) = if self.write || self.written > 0 {
+            (Direction::Upload, self.written)
+        } else {
+            (Direction::Download, self.read)
+        };
+        SftpEvent::Transfer {
+            path: self.path,
+            direction,
+            bytes,
+        }
+    }
+}
+
+/// Reads the fields of one packet in order
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn u32(&mut self) -> Option<u32> {
+        let (head, rest) = self.0.split_first_chunk::<4>()?;
+        self.0 = rest;
+        Some(u32::from_be_bytes(*head))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let (head, rest) = self.0.split_first_chunk::<8>()?;
+        self.0 = rest;
+        Some(u64::from_be_bytes(*head))
+    }
+
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.bytes()
+            .map(|b| String::from_utf8_lossy(b).into_owned())
+    }
+}
+
+/// Inserts unless `map` already follows `MAX_TRACKED` other entries
+fn insert_capped<K: Eq + Hash, V>(map: &mut HashMap<K, V>, key: K, value: V) {
+    if map.len() < MAX_TRACKED || map.contains_key(&key) {
+        map.insert(key, value);
+    }
+}
+
+/// Splits complete packets off a byte stream
+#[derive(Default)]
+struct Framer {
+    buf: Vec<u8>,
+    broken: bool,
+}
+
+impl Framer {
+    fn feed(&mut self, data: &[u8], mut packet: impl FnMut(u8, Fields<'_>)) {
+        if self.broken {
+            return;
+        }
+        self.buf.extend_from_slice(data);
+        let mut start = 0;
+        while let Some(head) = self.buf[start..].first_chunk::<4>() {
+            let len = u32::from_be_bytes(*head) as usize;
+            if len == 0 || len > MAX_PACKET {
+                self.broken = true;
+                self.buf = Vec::new();
+                return;
+            }
+            let end = start + 4 + len;
+            if self.buf.len() < end {
+                break;
+            }
+            packet(self.buf[start + 4], Fields(&self.buf[start + 5..end]));
+            start = end;
+        }
+        self.buf.drain(..start);
+    }
+}
+
+#[derive(Default)]
+pub struct SftpAudit {
+    client: Framer,
+    target: Framer,
+    /// Paths of `OPEN` requests awaiting a handle, by request id
+    opening: HashMap<u32, (String, bool)>,
+    /// Handles of `READ` requests awaiting data, by request id
+    reading: HashMap<u32, Vec<u8>>,
+    files: HashMap<Vec<u8>, OpenFile>,
+}
+
+impl SftpAudit {
+    /// Whether the stream still parses as SFTP
+    pub fn is_following(&self) -> bool {
+        !self.client.broken && !self.target.broken
+    }
+
+    /// Takes bytes sent by the client, returning what they completed
+    pub fn client_data(&mut self, data: &[u8]) -> Vec<SftpEvent> {
+        let mut events = Vec::new();
+        let Self {
+            client,
+            opening,
+            reading,
+            files,
+            ..
+        } = self;
+        client.feed(data, |kind, mut f| {
+            let Some(id) = f.u32() else {
+                return;
+            };
+            match kind {
+                FXP_OPEN => {
+                    if let (Some(path), Some(flags)) = (f.string(), f.u32()) {
+                        insert_capped(opening, id, (path, flags & FXF_WRITE != 0));
+                    }
+                }
+                FXP_READ => {
+                    if let Some(handle) = f.bytes().filter(|h| files.contains_key(*h)) {
+                        insert_capped(reading, id, handle.to_vec());
+                    }
+                }
+                FXP_WRITE => {
+                    if let (Some(handle), Some(_), Some(data)) = (f.bytes(), f.u64(), f.bytes())
+                        && let Some(file) = files.get_mut(handle)
+                    {
+                        file.written += data.len() as u64;
+                    }
+                }
+                FXP_CLOSE => {
+                    let Some(handle) = f.bytes() else {
+                        return;
+                    };
+                    reading.retain(|_, h| h != handle);
+                    if let Some(file) = files.remove(handle) {
+                        events.push(file.into_event());
+                    }
+                }
+                FXP_REMOVE => events.extend(f.string().map(SftpEvent::Remove)),
+                FXP_MKDIR => events.extend(f.string().map(SftpEvent::Mkdir)),
+                FXP_RMDIR => events.extend(f.string().map(SftpEvent::Rmdir)),
+                FXP_RENAME => {
+                    if let (Some(from), Some(to)) = (f.string(), f.string()) {
+                        events.push(SftpEvent::Rename(from, to));
+                    }
+                }
+                _ => {}
+            }
+        });
+        events
+    }
+
+    /// Takes bytes sent by the target
+    pub fn target_data(&mut self, data: &[u8]) {
+        let Self {
+            target,
+            opening,
+            reading,
+            files,
+            ..
+        } = self;
+        target.feed(data, |kind, mut f| {
+            let Some(id) = f.u32() else {
+                return;
+            };
+            match kind {
+                FXP_HANDLE => {
+                    if let (Some((path, write)), Some(handle)) = (opening.remove(&id), f.bytes())
+                        && handle.len() <= MAX_HANDLE
+                    {
+                        insert_capped(
+                            files,
+                            handle.to_vec(),
+                            OpenFile {
+                                path,
+                                write,
+                                read: 0,
+                                written: 0,
+                            },
+                        );
+                    }
+                }
+                FXP_DATA => {
+                    if let (Some(handle), Some(data)) = (reading.remove(&id), f.bytes())
+                        && let Some(file) = files.get_mut(&handle)
+                    {
+                        file.read += data.len() as u64;
+                    }
+                }
+                FXP_STATUS => {
+                    opening.remove(&id);
+                    reading.remove(&id);
+                }
+                _ => {}
+            }
+        });
+    }
+
+    /// Files still open when the channel ends, pending requests are dropped
+    pub fn finish(&mut self) -> Vec<SftpEvent> {
+        self.opening.clear();
+        self.reading.clear();
+        let mut events: Vec<_> = self.files.drain().map(|(_, f)| f.into_event()).collect();
+        events.sort_by_key(|e| e.to_string());
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(kind: u8, fields: &[&[u8]]) -> Vec<u8> {
+        let body: Vec<u8> = fields.concat();
+        let mut out = ((body.len() + 1) as u32).to_be_bytes().to_vec();
+        out.push(kind);
+        out.extend(body);
+        out
+    }
+
+    fn string(s: &[u8]) -> Vec<u8> {
+        let mut out = (s.len() as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(s);
+        out
+    }
+
+    #[test]
+    fn test_sftp_audit() {
+        let mut audit = SftpAudit::default();
+        let id = |n: u32| n.to_be_bytes();
+
+        // get /etc/hosts, the READ split over two data messages
+        let open = packet(
+            FXP_OPEN,
+            &[&id(1), &string(b"/etc/hosts"), &1u32.to_be_bytes(), &[0; 4]],
+        );
+        assert!(audit.client_data(&open).is_empty());
+        audit.target_data(&packet(FXP_HANDLE, &[&id(1), &string(b"h1")]));
+        let read = packet(
+            FXP_READ,
+            &[
+                &id(2),
+                &string(b"h1"),
+                &0u64.to_be_bytes(),
+                &32768u32.to_be_bytes(),
+            ],
+        );
+        assert!(audit.client_data(&read[..7]).is_empty());
+        assert!(audit.client_data(&read[7..]).is_empty());
+        audit.target_data(&packet(FXP_DATA, &[&id(2), &string(&[b'x'; 120])]));
+        let events = audit.client_data(&packet(FXP_CLOSE, &[&id(3), &string(b"h1")]));
+        assert_eq!(
+            events,
+            vec![SftpEvent::Transfer {
+                path: "/etc/hosts".to_string(),
+                direction: Direction::Download,
+                bytes: 120,
+            }]
+        );
+
+        // put /tmp/a, left open when the channel goes
+        let open = packet(
+            FXP_OPEN,
+            &[&id(4), &string(b"/tmp/a"), &0x1au32.to_be_bytes(), &[0; 4]],
+        );
+        audit.client_data(&open);
+        audit.target_data(&packet(FXP_HANDLE, &[&id(4), &string(b"h2")]));
+        let write = packet(
+            FXP_WRITE,
+            &[
+                &id(5),
+                &string(b"h2"),
+                &0u64.to_be_bytes(),
+                &string(&[0; 50]),
+            ],
+        );
+        let rename = packet(FXP_RENAME, &[&id(6), &string(b"/a"), &string(b"/b")]);
+        let events = audit.client_data(&[write, rename].concat());
+        assert_eq!(events[0].to_string(), "rename /a -> /b");
+        assert_eq!(audit.finish()[0].to_string(), "upload /tmp/a (50 bytes)");
+
+        // anything but SFTP
+        audit.client_data(b"\xff\xff\xff\xffgarbage");
+        assert!(!audit.is_following());
+    }
+
+    #[test]
+    fn test_sftp_audit_caps() {
+        let mut audit = SftpAudit::default();
+        let id = |n: u32| n.to_be_bytes();
+        let read = |n: u32, handle: &[u8]| {
+            packet(
+                FXP_READ,
+                &[
+                    &id(n),
+                    &string(handle),
+                    &0u64.to_be_bytes(),
+                    &32768u32.to_be_bytes(),
+                ],
+            )
+        };
+
+        let open = packet(FXP_OPEN, &[&id(0), &string(b"/f"), &[0; 4], &[0; 4]]);
+        audit.client_data(&open);
+        audit.target_data(&packet(FXP_HANDLE, &[&id(0), &string(b"h1")]));
+        // Reads of unknown handles aren't kept, the others only up to the cap
+        audit.client_data(&read(1, &[b'x'; MAX_HANDLE + 1]));
+        let flood: Vec<u8> = (2..MAX_TRACKED as u32 + 100)
+            .flat_map(|n| read(n, b"h1"))
+            .collect();
+        audit.client_data(&flood);
+        assert_eq!(audit.reading.len(), MAX_TRACKED);
+
+        // Closing the handle drops its reads
+        let events = audit.client_data(&packet(FXP_CLOSE, &[&id(0), &string(b"h1")]));
+        assert_eq!(events.len(), 1);
+        assert!(audit.reading.is_empty());
+
+        // A handle longer than the protocol allows isn't followed
+        audit.client_data(&open);
+        let long = [b'h'; MAX_HANDLE + 1];
+        audit.target_data(&packet(FXP_HANDLE, &[&id(0), &string(&long)]));
+        assert!(audit.files.is_empty());
+
+        audit.client_data(&open);
+        assert!(audit.finish().is_empty());
+        assert!(audit.opening.is_empty());
+        assert!(audit.is_following());
+    }
+}
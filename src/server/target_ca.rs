@@ -0,0 +1,114 @@
+//! Certificates for secrets of kind `ca`. Targets list the public half of
+//! `target_ca_key` in sshd's `TrustedUserCAKeys`, and every connection made
+//! for such a secret logs in with a fresh key and a user certificate for
+//! the secret's user, valid for `target_cert_validity`. No credential of
+//! the target is stored.
+
+use crate::config::error::ConfigError;
+use crate::error::Error;
+use rand::rng;
+use russh::keys::ssh_key::certificate::{Builder, CertType};
+use russh::keys::ssh_key::{Certificate, LineEnding, PublicKey};
+use russh::keys::{Algorithm, PrivateKey};
+use std::path::Path;
+use std::time::Duration;
+
+/// Certificates start this far in the past, targets whose clocks are a
+/// little behind still take them
+const CLOCK_SKEW: u64 = 60;
+/// What `ssh-keygen -s` grants by default, the bastion's policies decide
+/// what the channels may do
+const EXTENSIONS: [&str; 5] = [
+    "permit-X11-forwarding",
+    "permit-agent-forwarding",
+    "permit-port-forwarding",
+    "permit-pty",
+    "permit-user-rc",
+];
+
+pub struct TargetCa {
+    key: PrivateKey,
+    validity: Duration,
+}
+
+impl TargetCa {
+    pub fn load(path: &str, validity: Duration) -> Result<Self, Error> {
+        let key = PrivateKey::read_openssh_file(Path::new(path)).map_err(|source| {
+            ConfigError::TargetCaKey {
+                path: path.to_string(),
+                source,
+            }
+        })?;
+        Ok(Self { key, validity })
+    }
+
+    /// Writes a new ed25519 CA key to `path`
+    pub fn generate(path: &str) -> Result<PublicKey, Error> {
+        let key = PrivateKey::random(&mut rng(), Algorithm::Ed25519).map_err(russh::Error::from)?;
+        key.write_openssh_file(Path::new(path), LineEnding::LF)
+            .map_err(russh::Error::from)?;
+        Ok(key.public_key().clone())
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        self.key.public_key()
+    }
+
+    /// A new key with a user certificate for `principal`, valid from `now`
+    /// (unix seconds) for the configured time
+    pub fn issue(
+        &self,
+        principal: &str,
+        key_id: &str,
+        now: u64,
+    ) -> Result<(PrivateKey, Certificate), Error> {
+        let key = PrivateKey::random(&mut rng(), Algorithm::Ed25519).map_err(russh::Error::from)?;
+        let mut builder = Builder::new_with_random_nonce(
+            &mut rng(),
+            key.public_key().key_data().clone(),
+            now.saturating_sub(CLOCK_SKEW),
+            now.saturating_add(self.validity.as_secs()),
+        )
+        .map_err(russh::Error::from)?;
+        builder
+            .serial(rand::random())
+            .and_then(|b| b.key_id(key_id))
+            .and_then(|b| b.cert_type(CertType::User))
+            .and_then(|b| b.valid_principal(principal))
+            .map_err(russh::Error::from)?;
+        for extension in EXTENSIONS {
+            builder
+                .extension(extension, "")
+                .map_err(russh::Error::from)?;
+        }
+        let cert = builder.sign(&self.key).map_err(russh::Error::from)?;
+        Ok((key, cert))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh::keys::ssh_key::HashAlg;
+
+    #[test]
+    fn test_issue() {
+        let ca = TargetCa {
+            key: PrivateKey::random(&mut rng(), Algorithm::Ed25519).unwrap(),
+            validity: Duration::from_secs(300),
+        };
+        let now = 1_760_000_000;
+        let (key, cert) = ca.issue("deploy", "rustion:deploy@web1", now).unwrap();
+
+        assert_eq!(cert.public_key(), key.public_key().key_data());
+        assert_eq!(cert.cert_type(), CertType::User);
+        assert_eq!(cert.valid_principals(), ["deploy".to_string()]);
+        assert_eq!(cert.key_id(), "rustion:deploy@web1");
+        assert_eq!(cert.valid_before(), now + 300);
+        assert!(cert.extensions().contains_key("permit-pty"));
+
+        let trusted = [ca.public_key().fingerprint(HashAlg::Sha256)];
+        assert!(cert.validate_at(now, &trusted).is_ok());
+        assert!(cert.validate_at(now + 301, &trusted).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+//! Keyboard-interactive logins to targets. When the stored credentials of a
+//! secret aren't enough, e.g. the target wants its own one-time code after
+//! the password, and `relay_target_prompts` is on, the target's prompts are
+//! put to the user of an interactive shell and the answers sent back. Other
+//! requests have no terminal to ask on and are refused as before.
+
+use crate::database::models::Target;
+use crate::error::Error;
+use russh::client::{self as ru_client, KeyboardInteractiveAuthResponse, Prompt};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Where logging in to a target got
+pub enum TargetLogin {
+    /// With the `host:port` connected to, `None` for a pooled handle
+    Ready(Arc<ru_client::Handle<Target>>, Option<String>),
+    /// The target asks questions only the user can answer
+    Prompts(PromptLogin),
+    Refused,
+}
+
+/// Keyboard-interactive login waiting on the user
+pub struct PromptLogin {
+    handle: ru_client::Handle<Target>,
+    endpoint: String,
+    /// Name and instructions of the round, shown before its first prompt
+    banner: String,
+    prompts: VecDeque<Prompt>,
+    answers: Vec<String>,
+}
+
+impl PromptLogin {
+    /// Asks the target for keyboard-interactive as `user`
+    pub async fn start(
+        mut handle: ru_client::Handle<Target>,
+        endpoint: String,
+        user: String,
+    ) -> Result<TargetLogin, Error> {
+        let res = handle
+            .authenticate_keyboard_interactive_start(user, None)
+            .await?;
+        Self::next_round(handle, endpoint, res).await
+    }
+
+    async fn next_round(
+        mut handle: ru_client::Handle<Target>,
+        endpoint: String,
+        mut res: KeyboardInteractiveAuthResponse,
+    ) -> Result<TargetLogin, Error> {
+        loop {
+            match res {
+                KeyboardInteractiveAuthResponse::Success => {
+                    return Ok(TargetLogin::Ready(Arc::new(handle), Some(endpoint)));
+                }
+                KeyboardInteractiveAuthResponse::Failure { .. } => {
+                    return Ok(TargetLogin::Refused);
+                }
+                // Rounds without prompts only want an empty reply
+                KeyboardInteractiveAuthResponse::InfoRequest { prompts, .. }
+                    if prompts.is_empty() =>
+                {
+                    res = handle
+                        .authenticate_keyboard_interactive_respond(Vec::new())
+                        .await?;
+                }
+                KeyboardInteractiveAuthResponse::InfoRequest {
+                    name,
+                    instructions,
+                    prompts,
+                } => {
+                    let banner = [name, instructions]
+                        .iter()
+                        .filter(|s| !s.trim().is_empty())
+                        .map(|s| format!("{}\r\n", terminal_text(s.trim_end())))
+                        .collect();
+                    return Ok(TargetLogin::Prompts(Self {
+                        handle,
+                        endpoint,
+                        banner,
+                        prompts: prompts.into(),
+                        answers: Vec::new(),
+                    }));
+                }
+            }
+        }
+    }
+
+    /// Text showing the next prompt, and whether its answer is echoed
+    pub fn prompt(&mut self) -> (String, bool) {
+        let mut text = std::mem::take(&mut self.banner);
+        let echo = match self.prompts.front() {
+            Some(p) => {
+                text.push_str(&terminal_text(&p.prompt));
+                p.echo
+            }
+            None => false,
+        };
+        (text, echo)
+    }
+
+    /// Takes the answer to the prompt shown last, the round is sent once
+    /// every prompt of it has one
+    pub async fn answer(mut self, answer: String) -> Result<TargetLogin, Error> {
+        self.prompts.pop_front();
+        self.answers.push(answer);
+        if !self.prompts.is_empty() {
+            return Ok(TargetLogin::Prompts(self));
+        }
+        let res = self
+            .handle
+            .authenticate_keyboard_interactive_respond(self.answers)
+            .await?;
+        Self::next_round(self.handle, self.endpoint, res).await
+    }
+}
+
+/// `s` without the control characters a target could use to take over the
+/// user's terminal, with line breaks a raw terminal understands
+fn terminal_text(s: &str) -> String {
+    s.chars()
+        .filter(|c| *c == '\n' || !c.is_control())
+        .collect::<String>()
+        .replace('\n', "\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_terminal_text() {
+        assert_eq!(terminal_text("Verification code: "), "Verification code: ");
+        assert_eq!(terminal_text("line\nnext"), "line\r\nnext");
+        assert_eq!(terminal_text("\x1b]0;owned\x07OTP: "), "]0;ownedOTP: ");
+    }
+}
@@ -3,9 +3,13 @@ mod tests {
     use crate::database::common::OBJ_LOGIN;
     use crate::database::models::{
         casbin_rule::CasbinName, target_secret::TargetSecret, CasbinRule, Secret, Target,
-        TargetSecretName, User,
+        TargetSecretName, TargetTag, User,
+    };
+    use crate::database::{
+        common,
+        service::{DatabaseService, Dataset},
+        DatabaseConfig,
     };
-    use crate::database::{common, service::DatabaseService, DatabaseConfig};
     use crate::server::casbin::{ExtendPolicy, ExtendPolicyReq, IpPolicy};
     use crate::server::{self, HandlerBackend};
     use chrono::{Datelike, FixedOffset, NaiveDate, NaiveTime, TimeZone, Utc};
@@ -92,9 +96,11 @@ mod tests {
 
         let rules = db.repository().list_casbin_rules().await.unwrap();
         let secrets = db.repository().list_secrets(false).await.unwrap();
-        let targets = db.repository().list_targets(false).await.unwrap();
+        let targets = db.repository().list_targets(false, &[]).await.unwrap();
         let target_secrets = db.repository().list_target_secrets(false).await.unwrap();
-        let server = server::BastionServer::with_config(config).await.unwrap();
+        let server = server::BastionServer::with_config(config.clone())
+            .await
+            .unwrap();
 
         // Get UUIDs from global cache (initialized by BastionServer::with_config)
         let uuids = common::InternalUuids::get();
@@ -140,6 +146,25 @@ mod tests {
             .unwrap();
         assert!(alice.verify_password("12345678"));
 
+        // The replaced password stays in the history and the change date is stored
+        alice = server
+            .update_user_password("An0ther!pass".into(), alice)
+            .await
+            .unwrap();
+        let stored = server
+            .get_user_by_username("alice", true)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(stored.password_reused("12345678"));
+        assert!(stored.password_reused("An0ther!pass"));
+        assert!(!stored.password_reused("Unused1!pass"));
+        let changed = stored.password_changed_at.unwrap();
+        let day = std::time::Duration::from_secs(86400);
+        assert!(!stored.password_expired(None, changed.checked_add(day).unwrap()));
+        assert!(!stored.password_expired(Some(day), changed));
+        assert!(stored.password_expired(Some(day), changed.checked_add(day).unwrap()));
+
         let alice_lt = server.list_targets_for_user(&alice.id, true).await.unwrap();
         assert_eq!(
             alice_lt
@@ -327,6 +352,68 @@ mod tests {
             .await
             .unwrap());
 
+        // A deny rule overrides the grant
+        let mut deny = r.clone();
+        deny.id = Uuid::new_v4();
+        deny.ptype = "p_deny".to_string();
+        deny.v0 = alice.id;
+        deny = db.repository().create_casbin_rule(&deny).await.unwrap();
+        assert!(!server
+            .enforce(
+                alice.id,
+                Uuid::from_str("9888ece7-a675-41d9-97e3-81c6d4964b0c").unwrap(),
+                exec_uuid,
+                ExtendPolicyReq::default(),
+            )
+            .await
+            .unwrap());
+        db.repository().delete_casbin_rule(&deny.id).await.unwrap();
+
+        // With a policy cache, rule changes show once the cache is dropped
+        let mut cached_config = config.clone();
+        cached_config.policy_cache_ttl = Some(std::time::Duration::from_secs(60));
+        let cached = server::BastionServer::with_config(cached_config)
+            .await
+            .unwrap();
+        let obj = Uuid::from_str("9888ece7-a675-41d9-97e3-81c6d4964b0c").unwrap();
+        assert!(cached
+            .enforce(alice.id, obj, exec_uuid, ExtendPolicyReq::default())
+            .await
+            .unwrap());
+        deny = db.repository().create_casbin_rule(&deny).await.unwrap();
+        assert!(cached
+            .enforce(alice.id, obj, exec_uuid, ExtendPolicyReq::default())
+            .await
+            .unwrap());
+        cached.invalidate_policy_cache();
+        assert!(!cached
+            .enforce(alice.id, obj, exec_uuid, ExtendPolicyReq::default())
+            .await
+            .unwrap());
+        db.repository().delete_casbin_rule(&deny.id).await.unwrap();
+
+        // Only policies past the grace period are cleaned up
+        let mut expired = r.clone();
+        expired.id = Uuid::new_v4();
+        expired.v3 = ",,,2000-01-01 00:00:00 +0000".to_string();
+        expired = db.repository().create_casbin_rule(&expired).await.unwrap();
+        let mut recent = expired.clone();
+        recent.id = Uuid::new_v4();
+        recent.v3 = format!(
+            ",,,{}",
+            (chrono::Utc::now() - chrono::TimeDelta::minutes(1)).format("%Y-%m-%d %H:%M:%S %z")
+        );
+        recent = db.repository().create_casbin_rule(&recent).await.unwrap();
+        let grace = std::time::Duration::from_secs(3600);
+        assert_eq!(server.do_clean_expired_policies(grace).await.unwrap(), 1);
+        let repo = db.repository();
+        let gone = repo.get_casbin_rule_by_id(&expired.id).await.unwrap();
+        assert!(gone.is_none());
+        let kept = repo.get_casbin_rule_by_id(&recent.id).await.unwrap();
+        assert!(kept.is_some());
+        assert!(repo.get_casbin_rule_by_id(&r.id).await.unwrap().is_some());
+        repo.delete_casbin_rule(&recent.id).await.unwrap();
+
         // tokio::time::sleep(std::time::Duration::from_secs(300)).await;
         assert!(server
             .enforce(bob.id, obj_login, login_uuid, ExtendPolicyReq::default(),)
@@ -372,6 +459,7 @@ mod tests {
                     )
                     .unwrap(),
             ),
+            ..Default::default()
         };
         r.v3 = ep.to_string();
         r = db.repository().update_casbin_rule(&r).await.unwrap();
@@ -396,6 +484,7 @@ mod tests {
                         .and_hms_opt(0, 0, 0)
                         .unwrap()
                         .and_utc(),
+                    ..Default::default()
                 },
             )
             .await
@@ -413,6 +502,7 @@ mod tests {
                         .and_hms_opt(21, 0, 1)
                         .unwrap()
                         .and_utc(),
+                    ..Default::default()
                 },
             )
             .await
@@ -433,6 +523,7 @@ mod tests {
                     .unwrap(),
             ),
             expire_date: Some(Utc::now().with_timezone(&offset).with_year(3000).unwrap()),
+            ..Default::default()
         };
         r.v3 = ep.to_string();
         r = db.repository().update_casbin_rule(&r).await.unwrap();
@@ -445,7 +536,8 @@ mod tests {
                     ip: None,
                     now: Utc::now()
                         .with_time(NaiveTime::from_hms_opt(5, 34, 59).unwrap())
-                        .unwrap()
+                        .unwrap(),
+                    ..Default::default()
                 },
             )
             .await
@@ -459,7 +551,8 @@ mod tests {
                     ip: None,
                     now: Utc::now()
                         .with_time(NaiveTime::from_hms_opt(14, 35, 0).unwrap())
-                        .unwrap()
+                        .unwrap(),
+                    ..Default::default()
                 },
             )
             .await
@@ -473,7 +566,8 @@ mod tests {
                     ip: None,
                     now: Utc::now()
                         .with_time(NaiveTime::from_hms_opt(10, 0, 0).unwrap())
-                        .unwrap()
+                        .unwrap(),
+                    ..Default::default()
                 },
             )
             .await
@@ -494,6 +588,7 @@ mod tests {
                     .unwrap(),
             ),
             expire_date: Some(Utc::now().with_timezone(&offset).with_year(3000).unwrap()),
+            ..Default::default()
         };
         r.v3 = ep.to_string();
         db.repository().update_casbin_rule(&r).await.unwrap();
@@ -506,7 +601,8 @@ mod tests {
                     ip: None,
                     now: Utc::now()
                         .with_time(NaiveTime::from_hms_opt(10, 0, 0).unwrap())
-                        .unwrap()
+                        .unwrap(),
+                    ..Default::default()
                 },
             )
             .await
@@ -520,7 +616,8 @@ mod tests {
                     ip: Some("192.168.1.1".parse().unwrap()),
                     now: Utc::now()
                         .with_time(NaiveTime::from_hms_opt(10, 0, 0).unwrap())
-                        .unwrap()
+                        .unwrap(),
+                    ..Default::default()
                 },
             )
             .await
@@ -651,6 +748,46 @@ mod tests {
             )
             .await
             .unwrap());
+
+        // A policy on a tag group covers the bindings of the tagged targets,
+        // read from the tags whenever the role manager is built
+        let venus21 = Uuid::from_str("ca0d0cb5-2a51-43d7-beaa-96317b0dd91e").unwrap();
+        let venus21_root = Uuid::from_str("ee267744-b110-469e-917d-8754d8aafa3c").unwrap();
+        let staging = CasbinName::new("g2".into(), "tag:env=staging".into(), true, admin.id);
+        db.repository().create_casbin_name(&staging).await.unwrap();
+        let r = CasbinRule::new(
+            "p".to_string(),
+            jack.id,
+            staging.id,
+            exec_uuid,
+            String::new(),
+            String::new(),
+            String::new(),
+            admin.id,
+        );
+        db.repository().create_casbin_rule(&r).await.unwrap();
+        let tag = TargetTag::new(venus21, "env=staging".parse().unwrap(), admin.id);
+        db.repository()
+            .set_target_tags(&venus21, &[tag])
+            .await
+            .unwrap();
+        server.load_role_manager().await.unwrap();
+        assert!(server
+            .enforce(jack.id, venus21_root, exec_uuid, ExtendPolicyReq::default())
+            .await
+            .unwrap());
+        let jack_lt = server.list_targets_for_user(&jack.id, true).await.unwrap();
+        assert_eq!(jack_lt.iter().filter(|v| v.target_id == venus21).count(), 3);
+
+        db.repository()
+            .set_target_tags(&venus21, &[])
+            .await
+            .unwrap();
+        server.load_role_manager().await.unwrap();
+        assert!(!server
+            .enforce(jack.id, venus21_root, exec_uuid, ExtendPolicyReq::default())
+            .await
+            .unwrap());
     }
 
     #[tokio::test]
@@ -854,4 +991,175 @@ mod tests {
             .await
             .unwrap());
     }
+
+    /// Config of a SQLite database loaded from `mock_data.json`, which was
+    /// set up before the sftp, scp, forward and observe actions existed
+    async fn mock_config() -> (tempfile::TempDir, crate::config::Config) {
+        let temp_dir = tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let _ = File::create(&db_path).unwrap();
+        let mut config = crate::config::Config::default().gen_secret_token();
+        config.database = DatabaseConfig::Sqlite {
+            path: db_path.to_string_lossy().into(),
+        };
+        let mut buffer = String::new();
+        File::open("mock_data.json")
+            .unwrap()
+            .read_to_string(&mut buffer)
+            .unwrap();
+        let data: Dataset = serde_json::from_str(&buffer).unwrap();
+        let db = DatabaseService::new(&config.database).await.unwrap();
+        db.import_all(&data).await.unwrap();
+        (temp_dir, config)
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_internal_actions() {
+        let (_temp_dir, config) = mock_config().await;
+        let db = DatabaseService::new(&config.database).await.unwrap();
+        let repo = db.repository();
+        let admin = repo
+            .get_user_by_username("admin", true)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(repo
+            .get_casbin_name_by_name(common::ACT_SFTP)
+            .await
+            .unwrap()
+            .is_none());
+
+        // SQLite checks the author of the actions startup adds
+        server::BastionServer::with_config(config).await.unwrap();
+        for name in [
+            common::ACT_SFTP,
+            common::ACT_SCP,
+            common::ACT_REMOTE_FORWARD,
+            common::ACT_AGENT_FORWARD,
+            common::ACT_OBSERVE,
+        ] {
+            let action = repo.get_casbin_name_by_name(name).await.unwrap().unwrap();
+            assert_eq!(action.updated_by, admin.id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subsystem_actions() {
+        let (_temp_dir, mut config) = mock_config().await;
+        config.subsystems = vec!["netconf".to_string()];
+        let db = DatabaseService::new(&config.database).await.unwrap();
+        let admin = db.internal_author().await.unwrap();
+
+        // SQLite checks the author of the action each subsystem gets
+        let server = server::BastionServer::with_config(config).await.unwrap();
+        let name = format!("{}netconf", common::ACT_SUBSYSTEM_PREFIX);
+        let action = db
+            .repository()
+            .get_casbin_name_by_name(&name)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(action.updated_by, admin);
+        assert_eq!(server.subsystem_action("netconf"), Some(action.id));
+    }
+
+    /// An OPA on localhost allowing every request
+    async fn allow_all_opa() -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).to_lowercase();
+                    let Some(end) = text.find("\r\n\r\n") else {
+                        continue;
+                    };
+                    let length = text
+                        .lines()
+                        .find_map(|l| l.strip_prefix("content-length:"))
+                        .map_or(0, |l| l.trim().parse::<usize>().unwrap());
+                    if request.len() >= end + 4 + length {
+                        break;
+                    }
+                }
+                let body = r#"{"result":true}"#;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        format!("http://{}/v1/data/rustion/allow", addr)
+    }
+
+    #[tokio::test]
+    async fn test_opa_local_limits() {
+        let (_temp_dir, mut config) = mock_config().await;
+        config.opa.url = Some(allow_all_opa().await);
+        let db = DatabaseService::new(&config.database).await.unwrap();
+        let admin = db.internal_author().await.unwrap();
+        let server = server::BastionServer::with_config(config).await.unwrap();
+        let login_uuid = common::InternalUuids::get().act_login;
+
+        // No local rule covers it, OPA alone decides
+        let obj = Uuid::new_v4();
+        assert!(server
+            .enforce(admin, obj, login_uuid, ExtendPolicyReq::default())
+            .await
+            .unwrap());
+
+        // An expired local grant still limits what OPA allows
+        let expired = ExtendPolicy {
+            expire_date: Some(
+                FixedOffset::east_opt(0)
+                    .unwrap()
+                    .with_ymd_and_hms(2020, 1, 1, 0, 0, 0)
+                    .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let grant = CasbinRule::new(
+            "p".to_string(),
+            admin,
+            obj,
+            login_uuid,
+            expired.to_string(),
+            String::new(),
+            String::new(),
+            admin,
+        );
+        let grant = db.repository().create_casbin_rule(&grant).await.unwrap();
+        assert!(!server
+            .enforce(admin, obj, login_uuid, ExtendPolicyReq::default())
+            .await
+            .unwrap());
+        db.repository().delete_casbin_rule(&grant.id).await.unwrap();
+
+        // A local deny rule refuses what OPA allows
+        let deny = CasbinRule::new(
+            "p_deny".to_string(),
+            admin,
+            obj,
+            login_uuid,
+            String::new(),
+            String::new(),
+            String::new(),
+            admin,
+        );
+        db.repository().create_casbin_rule(&deny).await.unwrap();
+        assert!(!server
+            .enforce(admin, obj, login_uuid, ExtendPolicyReq::default())
+            .await
+            .unwrap());
+    }
 }
@@ -0,0 +1,166 @@
+//! Bandwidth limits of target sessions, from `limits.upload_rate`,
+//! `limits.download_rate` and the `rate=` flag of the granting policy. Each
+//! direction of a session has a token bucket holding up to a second of
+//! traffic. Bytes beyond it wait, and while they wait nothing more is read
+//! from that side, so SSH flow control slows the sender down.
+
+use crate::server::error::ExtendPolicyParseError;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Bytes per second in each direction, 0 for no limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rate {
+    /// Client to target
+    pub up: u64,
+    /// Target to client
+    pub down: u64,
+}
+
+impl Rate {
+    /// The lower limit of each direction
+    pub fn min(self, other: Rate) -> Rate {
+        let lower = |a: u64, b: u64| match (a, b) {
+            (0, b) => b,
+            (a, 0) => a,
+            (a, b) => a.min(b),
+        };
+        Rate {
+            up: lower(self.up, other.up),
+            down: lower(self.down, other.down),
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.up == 0 && self.down == 0
+    }
+}
+
+/// `<bytes>` with an optional `K`, `M` or `G` suffix, powers of 1024
+fn parse_bytes(s: &str) -> Option<u64> {
+    let (digits, unit) = match s.char_indices().last()? {
+        (i, 'K' | 'k') => (&s[..i], 1 << 10),
+        (i, 'M' | 'm') => (&s[..i], 1 << 20),
+        (i, 'G' | 'g') => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+fn fmt_bytes(n: u64, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match n {
+        n if n != 0 && n % (1 << 30) == 0 => write!(f, "{}G", n >> 30),
+        n if n != 0 && n % (1 << 20) == 0 => write!(f, "{}M", n >> 20),
+        n if n != 0 && n % (1 << 10) == 0 => write!(f, "{}K", n >> 10),
+        n => write!(f, "{}", n),
+    }
+}
+
+impl FromStr for Rate {
+    type Err = ExtendPolicyParseError;
+
+    /// `<up>/<down>`, or one value for both directions
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ExtendPolicyParseError::InvalidRate {
+            input: s.to_string(),
+        };
+        let (up, down) = s.split_once('/').unwrap_or((s, s));
+        let rate = Rate {
+            up: parse_bytes(up).ok_or_else(invalid)?,
+            down: parse_bytes(down).ok_or_else(invalid)?,
+        };
+        if rate.is_unlimited() {
+            return Err(invalid());
+        }
+        Ok(rate)
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_bytes(self.up, f)?;
+        if self.down != self.up {
+            f.write_str("/")?;
+            fmt_bytes(self.down, f)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Throttle {
+    rate: u64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Throttle {
+    /// `None` for a rate of 0
+    pub fn new(rate: u64, now: Instant) -> Option<Self> {
+        (rate > 0).then_some(Self {
+            rate,
+            tokens: rate as f64,
+            last: now,
+        })
+    }
+
+    /// Takes `n` bytes from the bucket, returning how long they have to wait
+    pub fn take(&mut self, n: usize, now: Instant) -> Duration {
+        let rate = self.rate as f64;
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * rate).min(rate) - n as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate() {
+        let rate: Rate = "1M/256K".parse().unwrap();
+        assert_eq!(
+            rate,
+            Rate {
+                up: 1 << 20,
+                down: 256 << 10
+            }
+        );
+        assert_eq!(rate.to_string(), "1M/256K");
+        let rate: Rate = "1500".parse().unwrap();
+        assert_eq!(rate.to_string(), "1500");
+        assert_eq!("0/2K".parse::<Rate>().unwrap().to_string(), "0/2K");
+        for bad in ["", "0", "1X", "1M/", "/1M", "99999999999G"] {
+            assert!(bad.parse::<Rate>().is_err(), "{}", bad);
+        }
+
+        let global = Rate { up: 0, down: 1000 };
+        assert_eq!(
+            global.min(rate),
+            Rate {
+                up: 1500,
+                down: 1000
+            }
+        );
+        assert!(Rate::default().min(Rate::default()).is_unlimited());
+    }
+
+    #[test]
+    fn test_throttle() {
+        let start = Instant::now();
+        assert!(Throttle::new(0, start).is_none());
+        let mut throttle = Throttle::new(1000, start).unwrap();
+        // a second's worth goes at once
+        assert_eq!(throttle.take(1000, start), Duration::ZERO);
+        assert_eq!(throttle.take(500, start), Duration::from_millis(500));
+        let later = start + Duration::from_secs(2);
+        assert_eq!(throttle.take(200, later), Duration::ZERO);
+    }
+}
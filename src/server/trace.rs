@@ -0,0 +1,104 @@
+//! Debug traces of single connections, turned on with `rustion trace start`
+//! to see what an odd client sends and how long the bridge to the target
+//! takes. Lines are handed to a writer task so handler callbacks never wait
+//! on the disk.
+
+use crate::database::Uuid;
+use crate::database::models::Timestamp;
+use log::warn;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+
+pub(super) struct Trace {
+    lines: UnboundedSender<String>,
+    started: Instant,
+    until: Timestamp,
+}
+
+impl Trace {
+    /// Starts `<dir>/<connection id>.trace`, written until `until`
+    pub fn create(dir: &str, connection_id: Uuid, until: Timestamp) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let path = Path::new(dir).join(format!("{}.trace", connection_id));
+        let file = std::fs::File::options()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let (lines, rx) = unbounded_channel();
+        tokio::spawn(write_lines(tokio::fs::File::from_std(file), path, rx));
+        let trace = Trace {
+            lines,
+            started: Instant::now(),
+            until,
+        };
+        let _ = trace.lines.send(format!(
+            "# connection {} traced from {} until {}\n",
+            connection_id,
+            Timestamp::now(),
+            until
+        ));
+        Ok(trace)
+    }
+
+    /// Appends `event` with the time since the trace started. False once the
+    /// window is over, the trace should then be dropped.
+    pub fn event(&self, event: fmt::Arguments) -> bool {
+        if Timestamp::now() >= self.until {
+            let _ = self.lines.send("# trace window ended\n".to_string());
+            return false;
+        }
+        let elapsed = self.started.elapsed().as_secs_f64();
+        self.lines
+            .send(format!("{:>10.3} {}\n", elapsed, event))
+            .is_ok()
+    }
+}
+
+async fn write_lines(
+    mut file: tokio::fs::File,
+    path: PathBuf,
+    mut rx: tokio::sync::mpsc::UnboundedReceiver<String>,
+) {
+    while let Some(line) = rx.recv().await {
+        if let Err(e) = file.write_all(line.as_bytes()).await {
+            warn!("Failed to write trace {}: {}", path.display(), e);
+            return;
+        }
+    }
+    let _ = file.flush().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_trace_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path().to_str().unwrap();
+        let id = Uuid::new_v4();
+
+        let until = Timestamp::now()
+            .checked_add(Duration::from_secs(60))
+            .unwrap();
+        let trace = Trace::create(dir_path, id, until).unwrap();
+        assert!(trace.event(format_args!("auth password for '{}': {}", "bob", "accept")));
+        drop(trace);
+
+        let expired = Trace::create(dir_path, Uuid::new_v4(), Timestamp::now()).unwrap();
+        assert!(!expired.event(format_args!("never written")));
+        drop(expired);
+
+        // Let the writer tasks drain
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let content = std::fs::read_to_string(dir.path().join(format!("{}.trace", id))).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(&format!("# connection {}", id)));
+        assert!(lines[1].ends_with("auth password for 'bob': accept"));
+    }
+}
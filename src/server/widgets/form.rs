@@ -14,6 +14,8 @@ pub enum FormFieldWidget {
     MultiLine(MultiLineText),
     Checkbox(bool),
     Radio(RadioButtons),
+    /// Read-only lines, set by the caller with `set_info`
+    Info(Vec<String>),
 }
 
 /// A single field in a form: label, layout height, and widget.
@@ -71,6 +73,14 @@ impl FormField {
             widget: FormFieldWidget::Radio(RadioButtons::new(options, initial)),
         }
     }
+
+    pub fn info(label: &'static str, lines: Vec<String>, height: u16) -> Self {
+        Self {
+            label,
+            height,
+            widget: FormFieldWidget::Info(lines),
+        }
+    }
 }
 
 /// Result of a key event processed by `FormEditor`.
@@ -108,6 +118,7 @@ impl FormEditor {
             Some(FormFieldWidget::MultiLine(_)) => MULTILINES_HELP,
             Some(FormFieldWidget::Checkbox(_)) => CHECKBOX_HELP,
             Some(FormFieldWidget::Radio(_)) => RADIO_HELP,
+            Some(FormFieldWidget::Info(_)) => INFO_HELP,
             None => COMMON_HELP,
         };
         Self {
@@ -159,6 +170,14 @@ impl FormEditor {
         }
     }
 
+    /// Replace the lines of an `Info` field.
+    pub fn set_info(&mut self, index: usize, lines: Vec<String>) {
+        match &mut self.fields[index].widget {
+            FormFieldWidget::Info(l) => *l = lines,
+            _ => panic!("field {} is not Info", index),
+        }
+    }
+
     /// Get a mutable reference to the `MultiLineText` at `index`.
     pub fn get_multiline_mut(&mut self, index: usize) -> &mut MultiLineText {
         match &mut self.fields[index].widget {
@@ -174,7 +193,9 @@ impl FormEditor {
         match &mut self.fields[self.focused].widget {
             FormFieldWidget::Text(t) => t.handle_paste(paste),
             FormFieldWidget::MultiLine(t) => t.handle_paste(paste),
-            FormFieldWidget::Checkbox(_) | FormFieldWidget::Radio(_) => false,
+            FormFieldWidget::Checkbox(_) | FormFieldWidget::Radio(_) | FormFieldWidget::Info(_) => {
+                false
+            }
         }
     }
 
@@ -290,7 +311,7 @@ impl FormEditor {
                     self.help_text = RADIO_HELP;
                 }
             }
-            FormFieldWidget::Checkbox(_) => unreachable!(),
+            FormFieldWidget::Checkbox(_) | FormFieldWidget::Info(_) => unreachable!(),
         }
     }
 
@@ -314,6 +335,7 @@ impl FormEditor {
                 self.editing_mode = true;
                 self.help_text = RADIO_EDIT_HELP;
             }
+            FormFieldWidget::Info(_) => {}
         }
     }
 
@@ -347,6 +369,7 @@ impl FormEditor {
             FormFieldWidget::MultiLine(_) => MULTILINES_HELP,
             FormFieldWidget::Checkbox(_) => CHECKBOX_HELP,
             FormFieldWidget::Radio(_) => RADIO_HELP,
+            FormFieldWidget::Info(_) => INFO_HELP,
         };
     }
 
@@ -432,6 +455,16 @@ impl FormEditor {
                         is_focused,
                     );
                 }
+                FormFieldWidget::Info(lines) => {
+                    render_info(
+                        chunks[i],
+                        &mut editor_buf,
+                        field.label,
+                        lines,
+                        &self.colors,
+                        is_focused,
+                    );
+                }
             }
         }
 
@@ -181,6 +181,10 @@ pub const CHECKBOX_HELP: [&str; 2] = [
     "(Space) toggle",
     "(Ctrl+s) save | (Esc) cancel | (Tab) next | (Shift Tab) previous",
 ];
+pub const INFO_HELP: [&str; 2] = [
+    "(read only)",
+    "(Ctrl+s) save | (Esc) cancel | (Tab) next | (Shift Tab) previous",
+];
 pub const MULTILINES_HELP: [&str; 2] = [
     "(Enter) activate | (d) clear all",
     "(Ctrl+s) save | (Esc) cancel | (Tab) next | (Shift Tab) previous",
@@ -415,9 +419,7 @@ impl Widget for &SingleLineText {
 }
 
 pub enum Message {
-    #[allow(dead_code)]
     Info(Vec<String>),
-    #[allow(dead_code)]
     Warning(Vec<String>),
     Error(Vec<String>),
     Success(Vec<String>),
@@ -649,6 +651,30 @@ pub fn render_checkbox(
     paragraph.render(area, buf);
 }
 
+/// Read-only lines under a titled border, derived from other fields
+pub fn render_info(
+    area: Rect,
+    buf: &mut Buffer,
+    label: &str,
+    lines: &[String],
+    colors: &EditorColors,
+    is_focused: bool,
+) {
+    let border_style = if is_focused {
+        Style::default().fg(colors.focus)
+    } else {
+        Style::default()
+    };
+    let text: Vec<Line> = lines.iter().map(|l| Line::from(l.as_str())).collect();
+    let paragraph = Paragraph::new(text).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(label)
+            .border_style(border_style),
+    );
+    paragraph.render(area, buf);
+}
+
 pub fn render_message_popup(area: Rect, buf: &mut Buffer, message: &Message) {
     let popup_area = if area.width <= common::MAX_POPUP_WINDOW_COL {
         area
@@ -7,6 +7,8 @@ use ratatui::widgets::{
     Cell, HighlightSpacing, Row, Scrollbar, ScrollbarOrientation, ScrollbarState, StatefulWidget,
     Table, TableState,
 };
+use std::ops::Deref;
+use std::sync::Arc;
 use style::palette::tailwind;
 use unicode_width::UnicodeWidthStr;
 
@@ -336,16 +338,42 @@ impl FieldsToArray for Target {
     }
 }
 
-impl FieldsToArray for TargetSecret {
+/// Row whose references to users, targets, secrets, bindings and casbin
+/// names are shown by name, or as the raw id when nothing is called so
+pub struct Named<T> {
+    row: T,
+    names: Arc<Names>,
+}
+
+impl<T> Named<T> {
+    pub fn wrap(rows: Vec<T>, names: &Arc<Names>) -> Vec<Self> {
+        rows.into_iter()
+            .map(|row| Named {
+                row,
+                names: names.clone(),
+            })
+            .collect()
+    }
+}
+
+impl<T> Deref for Named<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.row
+    }
+}
+
+impl FieldsToArray for Named<TargetSecret> {
     fn to_array(&self, mode: DisplayMode) -> Vec<String> {
         match mode {
             DisplayMode::Full => {
                 vec![
                     self.id.to_string(),
-                    self.target_id.to_string(),
-                    self.secret_id.to_string(),
+                    self.names.label(&self.target_id),
+                    self.names.label(&self.secret_id),
                     self.is_active.to_string(),
-                    self.updated_by.to_string(),
+                    self.names.label(&self.updated_by),
                     self.updated_at.to_string(),
                 ]
             }
@@ -417,6 +445,84 @@ impl FieldsToArray for CasbinName {
     }
 }
 
+impl FieldsToArray for crate::server::connection_pool::PooledChannels {
+    fn to_array(&self, _mode: DisplayMode) -> Vec<String> {
+        vec![
+            self.target.clone(),
+            self.username.clone(),
+            self.channels.to_string(),
+            self.since.to_string(),
+        ]
+    }
+}
+
+impl FieldsToArray for crate::server::live_sessions::LiveChannel {
+    fn to_array(&self, _mode: DisplayMode) -> Vec<String> {
+        vec![
+            self.username.clone(),
+            self.target.clone(),
+            self.request.clone(),
+            self.connection_id.to_string(),
+            self.watchers.to_string(),
+            self.since.to_string(),
+        ]
+    }
+}
+
+impl FieldsToArray for AccessRequestView {
+    fn to_array(&self, _mode: DisplayMode) -> Vec<String> {
+        vec![
+            self.username.clone(),
+            self.target_secret.clone(),
+            self.print_duration(),
+            self.reason.clone(),
+            self.status.clone(),
+            self.created_at.to_string(),
+        ]
+    }
+}
+
+impl FieldsToArray for PolicyChangeView {
+    fn to_array(&self, _mode: DisplayMode) -> Vec<String> {
+        vec![
+            self.changed_at.to_string(),
+            self.changed_by.clone(),
+            self.action.clone(),
+            self.rule_id.to_string(),
+            self.diff.clone(),
+        ]
+    }
+}
+
+impl FieldsToArray for BannedIp {
+    fn to_array(&self, mode: DisplayMode) -> Vec<String> {
+        match mode {
+            DisplayMode::Full => {
+                vec![
+                    self.id.to_string(),
+                    self.network.clone(),
+                    self.reason.clone(),
+                    self.print_expires_at(),
+                    self.updated_by.to_string(),
+                    self.updated_at.to_string(),
+                ]
+            }
+            DisplayMode::Manage => {
+                vec![
+                    self.network.clone(),
+                    self.reason.clone(),
+                    self.print_expires_at(),
+                    if self.is_automatic() {
+                        "server".to_string()
+                    } else {
+                        "admin".to_string()
+                    },
+                ]
+            }
+        }
+    }
+}
+
 impl FieldsToArray for PermissionPolicy {
     fn to_array(&self, mode: DisplayMode) -> Vec<String> {
         match mode {
@@ -428,6 +534,7 @@ impl FieldsToArray for PermissionPolicy {
                     self.user_role.clone(),
                     self.target_group.clone(),
                     self.action_group.clone(),
+                    self.effect().to_string(),
                     self.rule.v3.clone(),
                 ]
             }
@@ -435,20 +542,20 @@ impl FieldsToArray for PermissionPolicy {
     }
 }
 
-impl FieldsToArray for CasbinRule {
+impl FieldsToArray for Named<CasbinRule> {
     fn to_array(&self, mode: DisplayMode) -> Vec<String> {
         match mode {
             DisplayMode::Full => {
                 vec![
                     self.id.to_string(),
                     self.ptype.clone(),
-                    self.v0.to_string(),
-                    self.v1.to_string(),
-                    self.v2.to_string(),
+                    self.names.label(&self.v0),
+                    self.names.label(&self.v1),
+                    self.names.label(&self.v2),
                     self.v3.clone(),
                     self.v4.clone(),
                     self.v5.clone(),
-                    self.updated_by.to_string(),
+                    self.names.label(&self.updated_by),
                     self.updated_at.to_string(),
                 ]
             }
@@ -459,14 +566,14 @@ impl FieldsToArray for CasbinRule {
     }
 }
 
-impl FieldsToArray for Log {
+impl FieldsToArray for Named<Log> {
     fn to_array(&self, mode: DisplayMode) -> Vec<String> {
         match mode {
             DisplayMode::Full => {
                 vec![
                     self.connection_id.to_string(),
                     self.log_type.clone(),
-                    self.user_id.to_string(),
+                    self.names.label(&self.user_id),
                     self.detail.clone(),
                     self.created_at.to_string(),
                 ]
@@ -478,20 +585,21 @@ impl FieldsToArray for Log {
     }
 }
 
-impl FieldsToArray for SessionRecording {
+impl FieldsToArray for Named<SessionRecording> {
     fn to_array(&self, mode: DisplayMode) -> Vec<String> {
         match mode {
             DisplayMode::Full => {
                 vec![
                     self.id.to_string(),
-                    self.user_id.to_string(),
-                    self.target_id.to_string(),
-                    self.secret_id.to_string(),
+                    self.names.label(&self.user_id),
+                    self.names.label(&self.target_id),
+                    self.names.label(&self.secret_id),
                     self.file_path.clone(),
                     self.started_at.to_string(),
                     self.ended_at.map(|t| t.to_string()).unwrap_or_default(),
                     self.connection_id.to_string(),
                     self.status.clone(),
+                    self.ticket.clone().unwrap_or_default(),
                 ]
             }
             DisplayMode::Manage => {
@@ -553,10 +661,9 @@ impl FieldsToArray for RecordingView {
     fn to_array(&self, _mode: DisplayMode) -> Vec<String> {
         vec![
             self.target_secret.clone(),
-            super::common::format_timestamp(self.started_at),
-            self.ended_at
-                .map(super::common::format_timestamp)
-                .unwrap_or_default(),
+            self.started_at.to_string(),
+            self.ended_at.map(|t| t.to_string()).unwrap_or_default(),
+            self.print_duration(),
             self.status.clone(),
         ]
     }
@@ -564,7 +671,7 @@ impl FieldsToArray for RecordingView {
 
 impl TableData for Vec<RecordingView> {
     fn header(&self) -> Vec<&str> {
-        vec!["Target", "Started At", "Ended At", "Status"]
+        vec!["Target", "Started At", "Ended At", "Duration", "Status"]
     }
 
     fn as_vec(&self) -> Vec<&dyn FieldsToArray> {
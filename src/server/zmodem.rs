@@ -0,0 +1,323 @@
+//! ZMODEM transfers inside shells. `rz` and `sz` on a target move files
+//! over the terminal itself, past the sftp and scp controls. Either side of
+//! a transfer announces itself with a hex header, ZRQINIT from the sender
+//! and ZRINIT from the receiver, which `ZmodemDetector` picks out of the
+//! bytes of a shell channel. The granting policy's `zmodem=` flag, else the
+//! `zmodem` setting, says what happens then.
+
+use crate::server::error::ExtendPolicyParseError;
+use crate::server::sftp_audit::Direction;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Makes `rz` and `sz` give up at once, as their own Ctrl-X abort does: a
+/// run of CANs ends a ZMODEM session, the backspaces rub them out of a
+/// shell that wasn't in one
+pub const CANCEL: &[u8] = b"\x18\x18\x18\x18\x18\x18\x18\x18\x08\x08\x08\x08\x08\x08\x08\x08";
+
+/// ZPAD ZDLE, starting every header; `*` may come twice before ZDLE
+const ZPAD: u8 = b'*';
+const ZDLE: u8 = 0x18;
+/// Header encodings following ZDLE: binary, hex, binary with 32-bit CRC
+const ZBIN: u8 = b'A';
+const ZHEX: u8 = b'B';
+const ZBIN32: u8 = b'C';
+
+/// Frame types of a hex header, two hex digits
+const ZRQINIT: &[u8; 2] = b"00";
+const ZRINIT: &[u8; 2] = b"01";
+
+/// Headers of one kind this close to the last are retries of the same
+/// transfer, `rz` and `sz` repeat theirs every ten seconds until answered
+const SAME_TRANSFER: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ZmodemPolicy {
+    /// Let transfers through unnoticed
+    Allow,
+    /// Let them through and write each one to the log
+    #[default]
+    Log,
+    /// Keep the headers from the other side and cancel the transfer
+    Block,
+}
+
+impl fmt::Display for ZmodemPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZmodemPolicy::Allow => write!(f, "allow"),
+            ZmodemPolicy::Log => write!(f, "log"),
+            ZmodemPolicy::Block => write!(f, "block"),
+        }
+    }
+}
+
+impl FromStr for ZmodemPolicy {
+    type Err = ExtendPolicyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(ZmodemPolicy::Allow),
+            "log" => Ok(ZmodemPolicy::Log),
+            "block" => Ok(ZmodemPolicy::Block),
+            _ => Err(ExtendPolicyParseError::InvalidZmodem {
+                input: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// A header found by `ZmodemDetector`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// Offset of its first byte in the data pushed, 0 when it began in an
+    /// earlier push
+    pub at: usize,
+    /// `Some(true)` for ZRQINIT, the side sending it has files to send,
+    /// `Some(false)` for ZRINIT, it waits for files. `None` for the others.
+    pub sender: Option<bool>,
+}
+
+/// Looks for ZMODEM headers in one direction of a channel, across the
+/// packets it arrives in
+#[derive(Debug, Default)]
+pub struct ZmodemDetector {
+    /// Bytes of a header seen at the end of the last push
+    partial: Vec<u8>,
+}
+
+impl ZmodemDetector {
+    /// First header completed in `data`, the bytes after it aren't looked
+    /// at
+    pub fn push(&mut self, data: &[u8]) -> Option<Header> {
+        for (i, &b) in data.iter().enumerate() {
+            self.partial.push(b);
+            match self.partial.as_slice() {
+                [ZPAD] | [ZPAD, ZPAD] | [ZPAD, ZDLE] | [ZPAD, ZPAD, ZDLE] => {}
+                [.., ZDLE, ZBIN | ZBIN32] => {
+                    let start = (i + 1).saturating_sub(self.partial.len());
+                    self.partial.clear();
+                    return Some(Header {
+                        at: start,
+                        sender: None,
+                    });
+                }
+                [.., ZDLE, ZHEX] | [.., ZDLE, ZHEX, _] => {}
+                [.., ZDLE, ZHEX, hi, lo] => {
+                    let start = (i + 1).saturating_sub(self.partial.len());
+                    let sender = match &[*hi, *lo] {
+                        ZRQINIT => Some(true),
+                        ZRINIT => Some(false),
+                        _ => None,
+                    };
+                    self.partial.clear();
+                    return Some(Header { at: start, sender });
+                }
+                // Not a header after all, though its last byte may start one
+                _ => {
+                    self.partial.clear();
+                    if b == ZPAD {
+                        self.partial.push(b);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Tells the transfers started on a shell apart from the retries of their
+/// headers, so each is logged once
+#[derive(Debug, Default)]
+pub struct TransferLog {
+    last: Option<(Direction, Instant)>,
+}
+
+impl TransferLog {
+    /// Whether a start header in `direction` seen at `now` opens a new
+    /// transfer
+    pub fn is_new(&mut self, direction: Direction, now: Instant) -> bool {
+        let new = !matches!(
+            self.last,
+            Some((d, at)) if d == direction && now.duration_since(at) < SAME_TRANSFER
+        );
+        self.last = Some((direction, now));
+        new
+    }
+}
+
+/// What a watch made of a packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checked {
+    /// Bytes of it to pass on, under `block` those before a header
+    pub pass: usize,
+    /// Transfer it starts, unless it only repeats the last start
+    pub started: Option<Direction>,
+}
+
+/// One side of a shell watched under `log` or `block`
+#[derive(Debug)]
+pub struct ZmodemWatch {
+    policy: ZmodemPolicy,
+    /// Bytes come from the target, else from the client
+    from_target: bool,
+    detector: ZmodemDetector,
+    transfers: TransferLog,
+    /// Shared by both sides: a transfer was blocked and `rz` or `sz` on the
+    /// target still waits for `CANCEL`
+    cancel: Arc<AtomicBool>,
+}
+
+impl ZmodemWatch {
+    /// Watch of what the target sends
+    pub fn target(policy: ZmodemPolicy) -> Self {
+        ZmodemWatch {
+            policy,
+            from_target: true,
+            detector: ZmodemDetector::default(),
+            transfers: TransferLog::default(),
+            cancel: Arc::default(),
+        }
+    }
+
+    /// Watch of what the client sends on the same channel as `target`
+    pub fn client(target: &ZmodemWatch) -> Self {
+        ZmodemWatch {
+            policy: target.policy,
+            from_target: false,
+            detector: ZmodemDetector::default(),
+            transfers: TransferLog::default(),
+            cancel: target.cancel.clone(),
+        }
+    }
+
+    pub fn blocks(&self) -> bool {
+        self.policy == ZmodemPolicy::Block
+    }
+
+    pub fn check(&mut self, data: &[u8]) -> Checked {
+        let Some(header) = self.detector.push(data) else {
+            return Checked {
+                pass: data.len(),
+                started: None,
+            };
+        };
+        // ZRQINIT comes from the side with files to send
+        let started = header
+            .sender
+            .map(|sender| {
+                if sender == self.from_target {
+                    Direction::Download
+                } else {
+                    Direction::Upload
+                }
+            })
+            .filter(|d| self.transfers.is_new(*d, Instant::now()));
+        if !self.blocks() {
+            return Checked {
+                pass: data.len(),
+                started,
+            };
+        }
+        self.cancel.store(true, Ordering::Relaxed);
+        Checked {
+            pass: header.at,
+            started,
+        }
+    }
+
+    /// Log entry of a transfer started in `direction`
+    pub fn event(&self, direction: Direction) -> &'static str {
+        match (direction, self.blocks()) {
+            (Direction::Download, false) => "download",
+            (Direction::Upload, false) => "upload",
+            (Direction::Download, true) => "blocked download",
+            (Direction::Upload, true) => "blocked upload",
+        }
+    }
+
+    /// Whether the target waits on a blocked transfer, once per block
+    pub fn take_cancel(&self) -> bool {
+        self.cancel.swap(false, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_headers() {
+        let mut d = ZmodemDetector::default();
+        assert_eq!(d.push(b"ls\r\n"), None);
+        // `sz` prints `rz` for the terminal, then ZRQINIT
+        let h = d.push(b"rz\r**\x18B00000000000000\r\n\x11").unwrap();
+        assert_eq!(h.at, 3);
+        assert_eq!(h.sender, Some(true));
+        // ZRINIT of `rz`, split over packets
+        assert_eq!(d.push(b"**\x18"), None);
+        assert_eq!(
+            d.push(b"B0100000023be50\r\n"),
+            Some(Header {
+                at: 0,
+                sender: Some(false),
+            })
+        );
+        // Binary ZFILE from a client uploading blindly
+        let h = d.push(b"x*\x18C\x04").unwrap();
+        assert_eq!((h.at, h.sender), (1, None));
+        // Markdown and glob stars stay text
+        assert_eq!(d.push(b"**bold** *.rs"), None);
+    }
+
+    #[test]
+    fn test_watch() {
+        let mut target = ZmodemWatch::target(ZmodemPolicy::Log);
+        let checked = target.check(b"rz\r**\x18B00000000000000\r\n");
+        assert_eq!(checked.pass, 23);
+        assert_eq!(checked.started, Some(Direction::Download));
+        // `sz` asking again
+        let checked = target.check(b"**\x18B00000000000000\r\n");
+        assert_eq!(checked.started, None);
+        assert!(!target.take_cancel());
+
+        let mut target = ZmodemWatch::target(ZmodemPolicy::Block);
+        let mut client = ZmodemWatch::client(&target);
+        let checked = target.check(b"\r\n**\x18B0100000023be50\r\n");
+        assert_eq!(checked.pass, 2);
+        assert_eq!(checked.started, Some(Direction::Upload));
+        assert!(client.take_cancel());
+        assert!(!client.take_cancel());
+        // A client sending to `rz` without being asked
+        let checked = client.check(b"**\x18B00000000000000\r\n");
+        assert_eq!(checked.pass, 0);
+        assert_eq!(checked.started, Some(Direction::Upload));
+        assert_eq!(client.check(b"ls\r").pass, 3);
+    }
+
+    #[test]
+    fn test_policy() {
+        assert_eq!(
+            "block".parse::<ZmodemPolicy>().unwrap(),
+            ZmodemPolicy::Block
+        );
+        assert_eq!(ZmodemPolicy::Log.to_string(), "log");
+        assert!("deny".parse::<ZmodemPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_transfer_log() {
+        let mut log = TransferLog::default();
+        let t = Instant::now();
+        assert!(log.is_new(Direction::Download, t));
+        assert!(!log.is_new(Direction::Download, t + Duration::from_secs(10)));
+        assert!(!log.is_new(Direction::Download, t + Duration::from_secs(20)));
+        assert!(log.is_new(Direction::Upload, t + Duration::from_secs(21)));
+        assert!(log.is_new(Direction::Upload, t + Duration::from_secs(60)));
+    }
+}